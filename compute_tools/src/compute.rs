@@ -235,6 +235,44 @@ impl ComputeNode {
         Ok(())
     }
 
+    /// The versions of TimescaleDB that this compute ships a library of, `timescaledb-<version>.so`
+    /// in the package library directory. `None` if they cannot be listed.
+    fn timescaledb_versions(&self) -> Option<Vec<String>> {
+        let pg_config = Path::new(&self.pgbin).with_file_name("pg_config");
+        let pkglibdir = match Command::new(&pg_config).arg("--pkglibdir").output() {
+            Ok(output) if output.status.success() => {
+                String::from_utf8_lossy(&output.stdout).trim().to_owned()
+            }
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                warn!("pg_config --pkglibdir failed: {}", stderr.trim());
+                return None;
+            }
+            Err(e) => {
+                warn!("could not run {}: {e}", pg_config.display());
+                return None;
+            }
+        };
+        let entries = match fs::read_dir(&pkglibdir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("could not list {pkglibdir}: {e}");
+                return None;
+            }
+        };
+
+        let mut versions = entries
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                let version = name.strip_prefix("timescaledb-")?.strip_suffix(".so")?;
+                // timescaledb-tsl-<version>.so has the features under the Timescale License.
+                (!version.starts_with("tsl-")).then(|| version.to_owned())
+            })
+            .collect::<Vec<_>>();
+        versions.sort();
+        Some(versions)
+    }
+
     // Get basebackup from the libpq connection to pageserver using `connstr` and
     // unarchive it to `pgdata` directory overriding all its previous content.
     #[instrument(skip_all, fields(%lsn))]
@@ -254,7 +292,7 @@ impl ComputeNode {
         }
 
         let mut client = config.connect(NoTls)?;
-        let basebackup_cmd = match lsn {
+        let mut basebackup_cmd = match lsn {
             // HACK We don't use compression on first start (Lsn(0)) because there's no API for it
             Lsn(0) => format!("basebackup {} {}", spec.tenant_id, spec.timeline_id),
             _ => format!(
@@ -262,6 +300,13 @@ impl ComputeNode {
                 spec.tenant_id, spec.timeline_id, lsn
             ),
         };
+        // Let the pageserver check that we can run the installed TimescaleDB versions, if it
+        // knows the argument: an older one would fail the request.
+        if spec.spec.check_timescaledb_versions {
+            if let Some(versions) = self.timescaledb_versions() {
+                basebackup_cmd += &format!(" --timescaledb-versions={}", versions.join(","));
+            }
+        }
 
         let copyreader = client.copy_out(basebackup_cmd.as_str())?;
         let mut measured_reader = MeasuredReader::new(copyreader);
@@ -8,7 +8,7 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufReader, Write};
-use std::num::NonZeroU64;
+use std::num::{NonZeroU64, NonZeroUsize};
 use std::path::PathBuf;
 use std::process::{Child, Command};
 use std::{io, result};
@@ -380,6 +380,44 @@ impl PageServerNode {
                 .map(|x| x.parse::<bool>())
                 .transpose()
                 .context("Failed to parse 'gc_feedback' as bool")?,
+            prefetch_distance: settings
+                .remove("prefetch_distance")
+                .map(|x| x.parse::<u32>())
+                .transpose()
+                .context("Failed to parse 'prefetch_distance' as an integer")?,
+            prefetch_max_inflight: settings
+                .remove("prefetch_max_inflight")
+                .map(|x| x.parse::<usize>())
+                .transpose()
+                .context("Failed to parse 'prefetch_max_inflight' as an integer")?,
+            tiering_policy: settings
+                .remove("tiering_policy")
+                .map(serde_json::from_str)
+                .transpose()
+                .context("Failed to parse 'tiering_policy' json")?,
+            deletion_dry_run: settings
+                .remove("deletion_dry_run")
+                .map(|x| x.parse::<bool>())
+                .transpose()
+                .context("Failed to parse 'deletion_dry_run' as bool")?,
+            max_inprogress_uploads: settings
+                .remove("max_inprogress_uploads")
+                .map(|x| x.parse::<NonZeroUsize>())
+                .transpose()
+                .context("Failed to parse 'max_inprogress_uploads' as non zero integer")?,
+            walredo_memory_limit: settings
+                .remove("walredo_memory_limit")
+                .map(|x| x.parse::<u64>())
+                .transpose()
+                .context("Failed to parse 'walredo_memory_limit' as integer")?,
+            walredo_cpu_time_limit: settings
+                .remove("walredo_cpu_time_limit")
+                .map(|x| x.to_string()),
+            remote_storage: settings
+                .remove("remote_storage")
+                .map(serde_json::from_str)
+                .transpose()
+                .context("Failed to parse 'remote_storage' json")?,
         };
 
         // If tenant ID was not specified, generate one
@@ -479,6 +517,41 @@ impl PageServerNode {
                     .map(|x| x.parse::<bool>())
                     .transpose()
                     .context("Failed to parse 'gc_feedback' as bool")?,
+                prefetch_distance: settings
+                    .remove("prefetch_distance")
+                    .map(|x| x.parse::<u32>())
+                    .transpose()
+                    .context("Failed to parse 'prefetch_distance' as an integer")?,
+                prefetch_max_inflight: settings
+                    .remove("prefetch_max_inflight")
+                    .map(|x| x.parse::<usize>())
+                    .transpose()
+                    .context("Failed to parse 'prefetch_max_inflight' as an integer")?,
+                tiering_policy: settings
+                    .remove("tiering_policy")
+                    .map(serde_json::from_str)
+                    .transpose()
+                    .context("Failed to parse 'tiering_policy' json")?,
+                deletion_dry_run: settings
+                    .remove("deletion_dry_run")
+                    .map(|x| x.parse::<bool>())
+                    .transpose()
+                    .context("Failed to parse 'deletion_dry_run' as bool")?,
+                max_inprogress_uploads: settings
+                    .remove("max_inprogress_uploads")
+                    .map(|x| x.parse::<NonZeroUsize>())
+                    .transpose()
+                    .context("Failed to parse 'max_inprogress_uploads' as non zero integer")?,
+                walredo_memory_limit: settings
+                    .remove("walredo_memory_limit")
+                    .map(|x| x.parse::<u64>())
+                    .transpose()
+                    .context("Failed to parse 'walredo_memory_limit' as an integer")?,
+                walredo_cpu_time_limit: settings
+                    .remove("walredo_cpu_time_limit")
+                    .map(|x| x.to_string()),
+                // Can't be changed after the creation
+                remote_storage: None,
             }
         };
 
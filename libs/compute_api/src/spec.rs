@@ -60,6 +60,13 @@ pub struct ComputeSpec {
     /// If set, 'storage_auth_token' is used as the password to authenticate to
     /// the pageserver and safekeepers.
     pub storage_auth_token: Option<String>,
+
+    /// Whether to send the TimescaleDB versions that the compute ships with the basebackup
+    /// request, for the pageserver to check them. Only the pageservers that know the
+    /// `--timescaledb-versions` argument accept it: the control plane sets this once they
+    /// all do.
+    #[serde(default)] // Default false
+    pub check_timescaledb_versions: bool,
 }
 
 #[serde_as]
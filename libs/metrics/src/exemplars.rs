@@ -0,0 +1,255 @@
+//! Exemplars of the histogram buckets: the trace of an observation in the bucket, for a dashboard
+//! to go from a latency to a trace of it.
+//!
+//! The `prometheus` crate has no exemplars, so the last one of each bucket of each series is kept
+//! here, by [`observe_with_exemplar`]. Only the OpenMetrics text format has exemplars:
+//! [`encode_openmetrics`] writes it, for the scrapers that ask for it, the Prometheus text format
+//! of [`crate::TextEncoder`] stays the default.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use prometheus::core::{Collector, Metric};
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+use prometheus::Histogram;
+
+/// The content type of [`encode_openmetrics`].
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+struct Exemplar {
+    trace_id: String,
+    value: f64,
+    /// Seconds since the epoch.
+    timestamp: f64,
+}
+
+/// A series, by its metric name and its label pairs, sorted by name as in the gathered metrics.
+type SeriesKey = (String, Vec<(String, String)>);
+
+/// The exemplars of the buckets of each series, the `+Inf` bucket last.
+static EXEMPLARS: Lazy<Mutex<HashMap<SeriesKey, Vec<Option<Exemplar>>>>> =
+    Lazy::new(Default::default);
+
+fn series_key(name: &str, labels: &[LabelPair]) -> SeriesKey {
+    let labels = labels
+        .iter()
+        .map(|pair| (pair.get_name().to_string(), pair.get_value().to_string()))
+        .collect();
+    (name.to_string(), labels)
+}
+
+/// Observe `value` in `histogram`, and make the trace `trace_id` the exemplar of its bucket, if
+/// there is one.
+pub fn observe_with_exemplar(histogram: &Histogram, value: f64, trace_id: Option<String>) {
+    histogram.observe(value);
+    let Some(trace_id) = trace_id else {
+        return;
+    };
+    let descs = histogram.desc();
+    let Some(desc) = descs.first() else {
+        return;
+    };
+    let metric = histogram.metric();
+    let buckets = metric.get_histogram().get_bucket();
+    let bucket = buckets
+        .iter()
+        .position(|bucket| value <= bucket.get_upper_bound())
+        .unwrap_or(buckets.len());
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+
+    let mut exemplars = EXEMPLARS.lock().unwrap();
+    let series = exemplars
+        .entry(series_key(&desc.fq_name, metric.get_label()))
+        .or_default();
+    series.resize_with(buckets.len() + 1, || None);
+    series[bucket] = Some(Exemplar {
+        trace_id,
+        value,
+        timestamp,
+    });
+}
+
+/// Encode `families` in the OpenMetrics text format, with the exemplars of the histogram
+/// buckets. The exemplars of the series that are gone are dropped.
+///
+/// OpenMetrics names the samples of a counter `<family>_total`: the counters whose names don't
+/// end with `_total` are typed `unknown` instead, so that their series keep their names.
+pub fn encode_openmetrics(families: &[MetricFamily], w: &mut dyn Write) -> io::Result<()> {
+    let mut exemplars = EXEMPLARS.lock().unwrap();
+    let mut histograms = HashSet::new();
+    for family in families {
+        let name = family.get_name();
+        let (family_name, type_name) = match family.get_field_type() {
+            MetricType::COUNTER => match name.strip_suffix("_total") {
+                Some(family_name) => (family_name, "counter"),
+                None => (name, "unknown"),
+            },
+            MetricType::GAUGE => (name, "gauge"),
+            MetricType::SUMMARY => (name, "summary"),
+            MetricType::UNTYPED => (name, "unknown"),
+            MetricType::HISTOGRAM => (name, "histogram"),
+        };
+        writeln!(w, "# HELP {family_name} {}", escape(family.get_help()))?;
+        writeln!(w, "# TYPE {family_name} {type_name}")?;
+
+        for metric in family.get_metric() {
+            let labels = metric.get_label();
+            match family.get_field_type() {
+                MetricType::COUNTER => {
+                    let value = metric.get_counter().get_value();
+                    write_sample(w, name, labels, None, value, None)?;
+                }
+                MetricType::GAUGE => {
+                    let value = metric.get_gauge().get_value();
+                    write_sample(w, name, labels, None, value, None)?;
+                }
+                MetricType::UNTYPED => {
+                    let value = metric.get_untyped().get_value();
+                    write_sample(w, name, labels, None, value, None)?;
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        let label = ("quantile", fmt_float(quantile.get_quantile()));
+                        write_sample(w, name, labels, Some(label), quantile.get_value(), None)?;
+                    }
+                    let (sum, count) = (summary.get_sample_sum(), summary.get_sample_count());
+                    write_sample(w, &format!("{name}_sum"), labels, None, sum, None)?;
+                    write_sample(w, &format!("{name}_count"), labels, None, count as f64, None)?;
+                }
+                MetricType::HISTOGRAM => {
+                    let key = series_key(name, labels);
+                    let series = exemplars.get(&key);
+                    let exemplar =
+                        |i: usize| series.and_then(|s| s.get(i)).and_then(Option::as_ref);
+
+                    let histogram = metric.get_histogram();
+                    let buckets = histogram.get_bucket();
+                    let bucket_name = format!("{name}_bucket");
+                    for (i, bucket) in buckets.iter().enumerate() {
+                        let label = ("le", fmt_float(bucket.get_upper_bound()));
+                        let count = bucket.get_cumulative_count() as f64;
+                        write_sample(w, &bucket_name, labels, Some(label), count, exemplar(i))?;
+                    }
+                    let (sum, count) = (histogram.get_sample_sum(), histogram.get_sample_count());
+                    let label = ("le", fmt_float(f64::INFINITY));
+                    let inf_exemplar = exemplar(buckets.len());
+                    write_sample(w, &bucket_name, labels, Some(label), count as f64, inf_exemplar)?;
+                    write_sample(w, &format!("{name}_sum"), labels, None, sum, None)?;
+                    write_sample(w, &format!("{name}_count"), labels, None, count as f64, None)?;
+                    histograms.insert(key);
+                }
+            }
+        }
+    }
+    exemplars.retain(|key, _| histograms.contains(key));
+    writeln!(w, "# EOF")
+}
+
+fn write_sample(
+    w: &mut dyn Write,
+    name: &str,
+    labels: &[LabelPair],
+    extra_label: Option<(&str, String)>,
+    value: f64,
+    exemplar: Option<&Exemplar>,
+) -> io::Result<()> {
+    let mut labels = labels
+        .iter()
+        .map(|pair| format!("{}=\"{}\"", pair.get_name(), escape(pair.get_value())))
+        .collect::<Vec<_>>();
+    if let Some((label_name, label_value)) = extra_label {
+        labels.push(format!("{label_name}=\"{}\"", escape(&label_value)));
+    }
+    if labels.is_empty() {
+        write!(w, "{name} {}", fmt_float(value))?;
+    } else {
+        write!(w, "{name}{{{}}} {}", labels.join(","), fmt_float(value))?;
+    }
+    if let Some(exemplar) = exemplar {
+        write!(
+            w,
+            " # {{trace_id=\"{}\"}} {} {}",
+            escape(&exemplar.trace_id),
+            fmt_float(exemplar.value),
+            exemplar.timestamp
+        )?;
+    }
+    writeln!(w)
+}
+
+fn fmt_float(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else if value.is_nan() {
+        "NaN".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Escape a label value or a help text.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::{HistogramOpts, HistogramVec, IntCounter};
+
+    use super::*;
+
+    #[test]
+    fn exemplars_in_openmetrics() {
+        let opts = HistogramOpts::new("test_exemplar_seconds", "A \"test\" histogram")
+            .buckets(vec![0.1, 1.0]);
+        let histograms = HistogramVec::new(opts, &["op"]).unwrap();
+        let read = histograms.with_label_values(&["read"]);
+        observe_with_exemplar(&read, 0.5, Some("0af7651916cd43dd8448eb211c80319c".to_string()));
+        observe_with_exemplar(&read, 0.0625, None);
+        observe_with_exemplar(&read, 4.0, Some("4bf92f3577b34da6a3ce929d0e0e4736".to_string()));
+        let counter = IntCounter::new("test_exemplar_requests", "Requests").unwrap();
+        counter.inc();
+
+        let mut families = histograms.collect();
+        families.extend(counter.collect());
+        let mut buf = Vec::new();
+        encode_openmetrics(&families, &mut buf).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "# HELP test_exemplar_seconds A \\\"test\\\" histogram");
+        assert_eq!(lines[1], "# TYPE test_exemplar_seconds histogram");
+        assert_eq!(lines[2], "test_exemplar_seconds_bucket{op=\"read\",le=\"0.1\"} 1");
+        assert!(lines[3].starts_with(
+            "test_exemplar_seconds_bucket{op=\"read\",le=\"1\"} 2 \
+             # {trace_id=\"0af7651916cd43dd8448eb211c80319c\"} 0.5 "
+        ));
+        assert!(lines[4].starts_with(
+            "test_exemplar_seconds_bucket{op=\"read\",le=\"+Inf\"} 3 \
+             # {trace_id=\"4bf92f3577b34da6a3ce929d0e0e4736\"} 4 "
+        ));
+        assert_eq!(lines[5], "test_exemplar_seconds_sum{op=\"read\"} 4.5625");
+        assert_eq!(lines[6], "test_exemplar_seconds_count{op=\"read\"} 3");
+        // Not named like an OpenMetrics counter
+        assert_eq!(lines[8], "# TYPE test_exemplar_requests unknown");
+        assert_eq!(lines[9], "test_exemplar_requests 1");
+        assert_eq!(lines[10], "# EOF");
+
+        // The exemplars of a removed series are dropped
+        histograms.remove_label_values(&["read"]).unwrap();
+        encode_openmetrics(&histograms.collect(), &mut io::sink()).unwrap();
+        let key = series_key("test_exemplar_seconds", read.metric().get_label());
+        assert!(!EXEMPLARS.lock().unwrap().contains_key(&key));
+    }
+}
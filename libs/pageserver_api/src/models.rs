@@ -7,9 +7,13 @@ use std::{
 use byteorder::{BigEndian, ReadBytesExt};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
+use serde_json::json;
+use strum::VariantNames;
 use strum_macros;
 use utils::{
+    api_schema,
     history_buffer::HistoryBufferWithDropCounter,
+    http::openapi::{object_schema, string_enum_schema, ApiSchema, Components, Value},
     id::{NodeId, TenantId, TimelineId},
     lsn::Lsn,
 };
@@ -131,6 +135,19 @@ impl TenantState {
     }
 }
 
+// NB: the variants are intentionally not part of the OpenAPI spec, we don't want to commit
+// to a specific set of TenantState's.
+impl ApiSchema for TenantState {
+    fn schema(components: &mut Components) -> Value {
+        components.named("TenantState", |_| {
+            object_schema(vec![
+                ("slug", false, json!({ "type": "string" })),
+                ("data", true, json!({})),
+            ])
+        })
+    }
+}
+
 impl std::fmt::Debug for TenantState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -152,7 +169,16 @@ pub enum ActivatingFrom {
 }
 
 /// A state of a timeline in pageserver's memory.
-#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    strum_macros::EnumVariantNames,
+    strum_macros::IntoStaticStr,
+)]
 pub enum TimelineState {
     /// The timeline is recognized by the pageserver but is not yet operational.
     /// In particular, the walreceiver connection loop is not running for this timeline.
@@ -168,6 +194,23 @@ pub enum TimelineState {
     Broken { reason: String, backtrace: String },
 }
 
+impl ApiSchema for TimelineState {
+    fn schema(components: &mut Components) -> Value {
+        components.named("TimelineState", |_| {
+            let broken = object_schema(vec![
+                ("reason", false, json!({ "type": "string" })),
+                ("backtrace", false, json!({ "type": "string" })),
+            ]);
+            json!({
+                "oneOf": [
+                    string_enum_schema(&["Loading", "Active", "Stopping"]),
+                    object_schema(vec![("Broken", false, broken)]),
+                ]
+            })
+        })
+    }
+}
+
 #[serde_as]
 #[derive(Serialize, Deserialize)]
 pub struct TimelineCreateRequest {
@@ -182,6 +225,13 @@ pub struct TimelineCreateRequest {
     pub pg_version: Option<u32>,
 }
 
+api_schema!(TimelineCreateRequest {
+    new_timeline_id: TimelineId,
+    ancestor_timeline_id: Option<TimelineId>,
+    ancestor_start_lsn: Option<Lsn>,
+    pg_version: Option<u32>,
+});
+
 #[serde_as]
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
@@ -192,6 +242,11 @@ pub struct TenantCreateRequest {
     pub config: TenantConfig, // as we have a flattened field, we should reject all unknown fields in it
 }
 
+api_schema!(TenantCreateRequest {
+    new_tenant_id: TenantId,
+    ..TenantConfig
+});
+
 impl std::ops::Deref for TenantCreateRequest {
     type Target = TenantConfig;
 
@@ -218,23 +273,133 @@ pub struct TenantConfig {
     // We defer the parsing of the eviction_policy field to the request handler.
     // Otherwise we'd have to move the types for eviction policy into this package.
     // We might do that once the eviction feature has stabilizied.
-    // For now, this field is not even documented in the openapi_spec.yml.
+    // For now, this field is only described as an arbitrary JSON value.
     pub eviction_policy: Option<serde_json::Value>,
     pub min_resident_size_override: Option<u64>,
     pub evictions_low_residence_duration_metric_threshold: Option<String>,
     pub gc_feedback: Option<bool>,
+    pub prefetch_distance: Option<u32>,
+    pub prefetch_max_inflight: Option<usize>,
+    // Deferred to the request handler, like eviction_policy.
+    pub tiering_policy: Option<serde_json::Value>,
+    pub deletion_dry_run: Option<bool>,
+    pub max_inprogress_uploads: Option<NonZeroUsize>,
+    pub walredo_memory_limit: Option<u64>,
+    pub walredo_cpu_time_limit: Option<String>,
+    /// The remote storage of the tenant, in place of the one of the pageserver, as in the
+    /// `remote_storage` pageserver setting. Only taken when the tenant is created or attached.
+    pub remote_storage: Option<serde_json::Value>,
 }
 
+api_schema!(TenantConfig {
+    checkpoint_distance: Option<u64>,
+    checkpoint_timeout: Option<String>,
+    compaction_target_size: Option<u64>,
+    compaction_period: Option<String>,
+    compaction_threshold: Option<usize>,
+    gc_horizon: Option<u64>,
+    gc_period: Option<String>,
+    image_creation_threshold: Option<usize>,
+    pitr_interval: Option<String>,
+    walreceiver_connect_timeout: Option<String>,
+    lagging_wal_timeout: Option<String>,
+    max_lsn_wal_lag: Option<NonZeroU64>,
+    trace_read_requests: Option<bool>,
+    eviction_policy: Option<Value>,
+    min_resident_size_override: Option<u64>,
+    evictions_low_residence_duration_metric_threshold: Option<String>,
+    gc_feedback: Option<bool>,
+    prefetch_distance: Option<u32>,
+    prefetch_max_inflight: Option<usize>,
+    tiering_policy: Option<Value>,
+    deletion_dry_run: Option<bool>,
+    max_inprogress_uploads: Option<NonZeroUsize>,
+    walredo_memory_limit: Option<u64>,
+    walredo_cpu_time_limit: Option<String>,
+    remote_storage: Option<Value>,
+});
+
 #[serde_as]
 #[derive(Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct TenantCreateResponse(#[serde_as(as = "DisplayFromStr")] pub TenantId);
 
+impl ApiSchema for TenantCreateResponse {
+    fn schema(components: &mut Components) -> Value {
+        TenantId::schema(components)
+    }
+}
+
 #[derive(Serialize)]
 pub struct StatusResponse {
     pub id: NodeId,
+    /// The remote storage of the pageserver first, then those of the tenants with their own.
+    pub remote_storage_health: Vec<RemoteStorageHealthInfo>,
+}
+
+api_schema!(StatusResponse {
+    id: NodeId,
+    remote_storage_health: Vec<RemoteStorageHealthInfo>,
+});
+
+/// The state of the circuit breaker of a remote storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum_macros::EnumVariantNames)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum CircuitBreakerState {
+    /// The remote tasks are launched.
+    Closed,
+    /// The remote tasks are paused, after too many failures in a row.
+    Open,
+    /// The cooldown has passed, the remote tasks are launched again. The next failure opens
+    /// the circuit breaker again, the next success closes it.
+    HalfOpen,
+}
+
+api_schema!(CircuitBreakerState = CircuitBreakerState::VARIANTS);
+
+/// The health of a remote storage, as seen from the remote tasks of the upload queues.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteStorageHealthInfo {
+    /// `pageserver`, or the id of the tenant with a remote storage of its own.
+    pub storage: String,
+    pub circuit_breaker: CircuitBreakerState,
+    /// The number of remote task attempts that failed since the last success.
+    pub consecutive_failures: u64,
+    /// How long the remote tasks stay paused, while the circuit breaker is open.
+    pub paused_for_secs: Option<u64>,
+}
+
+api_schema!(RemoteStorageHealthInfo {
+    storage: String,
+    circuit_breaker: CircuitBreakerState,
+    consecutive_failures: u64,
+    paused_for_secs: Option<u64>,
+});
+
+/// What exactly a pageserver binary is, see `GET /v1/status/build`.
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BuildInfoResponse {
+    /// Same as in the `--version` output, e.g. `git:<full sha>`.
+    pub git_version: String,
+    #[serde_as(as = "serde_with::TimestampSeconds")]
+    pub build_time: SystemTime,
+    pub rustc_version: String,
+    pub features: Vec<String>,
+    /// Postgres versions the pageserver has a distribution of, i.e. can run WAL redo for.
+    pub pg_versions: Vec<u32>,
 }
 
+api_schema!(BuildInfoResponse {
+    git_version: String,
+    // seconds since the epoch
+    build_time: u64,
+    rustc_version: String,
+    features: Vec<String>,
+    pg_versions: Vec<u32>,
+});
+
 impl TenantCreateRequest {
     pub fn new(new_tenant_id: TenantId) -> TenantCreateRequest {
         TenantCreateRequest {
@@ -254,6 +419,11 @@ pub struct TenantConfigRequest {
     pub config: TenantConfig, // as we have a flattened field, we should reject all unknown fields in it
 }
 
+api_schema!(TenantConfigRequest {
+    tenant_id: TenantId,
+    ..TenantConfig
+});
+
 impl std::ops::Deref for TenantConfigRequest {
     type Target = TenantConfig;
 
@@ -282,6 +452,14 @@ impl TenantConfigRequest {
             min_resident_size_override: None,
             evictions_low_residence_duration_metric_threshold: None,
             gc_feedback: None,
+            prefetch_distance: None,
+            prefetch_max_inflight: None,
+            tiering_policy: None,
+            deletion_dry_run: None,
+            max_inprogress_uploads: None,
+            walredo_memory_limit: None,
+            walredo_cpu_time_limit: None,
+            remote_storage: None,
         };
         TenantConfigRequest { tenant_id, config }
     }
@@ -290,8 +468,40 @@ impl TenantConfigRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TenantAttachRequest {
     pub config: TenantAttachConfig,
+    /// Generation of this attachment of the tenant, increased by the control plane with every
+    /// attachment.
+    #[serde(default)]
+    pub generation: Option<u32>,
+    /// Import the timelines of an external prefix before attaching, for the tenants migrated
+    /// from another system with an offline import tool.
+    #[serde(default)]
+    pub import: Option<TenantImportSource>,
+}
+
+api_schema!(TenantAttachRequest {
+    config: TenantAttachConfig,
+    generation: Option<u32>,
+    import: Option<TenantImportSource>,
+});
+
+/// Where the timelines of a tenant attached in import mode come from: a directory per timeline
+/// under the prefix, named by the timeline id, with the layer files of the timeline and its
+/// `metadata` file, but no index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenantImportSource {
+    /// The remote storage of the prefix, with the parameters of the `remote_storage` pageserver
+    /// setting. By default, the remote storage of the tenant.
+    #[serde(default)]
+    pub remote_storage: Option<serde_json::Value>,
+    /// A relative path.
+    pub prefix: String,
 }
 
+api_schema!(TenantImportSource {
+    remote_storage: Option<Value>,
+    prefix: String,
+});
+
 /// Newtype to enforce deny_unknown_fields on TenantConfig for
 /// its usage inside `TenantAttachRequest`.
 #[derive(Debug, Serialize, Deserialize)]
@@ -301,6 +511,8 @@ pub struct TenantAttachConfig {
     allowing_unknown_fields: TenantConfig,
 }
 
+api_schema!(TenantAttachConfig { ..TenantConfig });
+
 impl std::ops::Deref for TenantAttachConfig {
     type Target = TenantConfig;
 
@@ -318,6 +530,22 @@ pub enum TenantAttachmentStatus {
     Failed { reason: String },
 }
 
+impl ApiSchema for TenantAttachmentStatus {
+    fn schema(components: &mut Components) -> Value {
+        components.named("TenantAttachmentStatus", |_| {
+            let failed = object_schema(vec![("reason", false, json!({ "type": "string" }))]);
+            object_schema(vec![
+                (
+                    "slug",
+                    false,
+                    string_enum_schema(&["maybe", "attached", "failed"]),
+                ),
+                ("data", true, failed),
+            ])
+        })
+    }
+}
+
 #[serde_as]
 #[derive(Serialize, Deserialize, Clone)]
 pub struct TenantInfo {
@@ -331,6 +559,97 @@ pub struct TenantInfo {
     pub attachment_status: TenantAttachmentStatus,
 }
 
+api_schema!(TenantInfo {
+    id: TenantId,
+    state: TenantState,
+    current_physical_size: Option<u64>,
+    attachment_status: TenantAttachmentStatus,
+});
+
+/// The limits on the layer uploads of a tenant to the remote storage, shared by all of its
+/// timelines. A missing limit means no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UploadThrottleConfig {
+    pub bytes_per_second: Option<NonZeroU64>,
+    pub ops_per_second: Option<NonZeroU64>,
+}
+
+api_schema!(UploadThrottleConfig {
+    bytes_per_second: Option<NonZeroU64>,
+    ops_per_second: Option<NonZeroU64>,
+});
+
+/// How much of each item the list endpoints return, the `detail` query parameter.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    strum_macros::EnumString,
+    strum_macros::Display,
+    strum_macros::EnumVariantNames,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum ListDetail {
+    /// What's in memory already.
+    #[default]
+    Basic,
+    /// Also the sizes that need to be summed up or calculated: the physical size of the
+    /// tenants, and the non-incremental logical size of the timelines.
+    Full,
+}
+
+api_schema!(ListDetail = ListDetail::VARIANTS);
+
+/// Pagination and filtering of the list endpoints, `GET /v1/tenant` and
+/// `GET /v1/tenant/:tenant_id/timeline`. The items are sorted by id, so that the pages are
+/// stable while items are added and removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListQuery<Id> {
+    /// Return at most this many items.
+    pub limit: Option<NonZeroUsize>,
+    /// Return the items after this id, i.e. the id of the last item of the previous page.
+    pub cursor: Option<Id>,
+    /// Only return the items in one of these states, e.g. `Active`.
+    pub states: Option<Vec<String>>,
+    pub detail: ListDetail,
+}
+
+impl<Id> Default for ListQuery<Id> {
+    fn default() -> Self {
+        ListQuery {
+            limit: None,
+            cursor: None,
+            states: None,
+            detail: ListDetail::default(),
+        }
+    }
+}
+
+impl<Id: Ord + Copy> ListQuery<Id> {
+    /// The page of `items` selected by this query.
+    pub fn apply<T>(
+        &self,
+        mut items: Vec<T>,
+        id: impl Fn(&T) -> Id,
+        state: impl Fn(&T) -> &'static str,
+    ) -> Vec<T> {
+        items.sort_by_key(&id);
+        items
+            .into_iter()
+            .filter(|item| self.cursor.map_or(true, |cursor| id(item) > cursor))
+            .filter(|item| match &self.states {
+                Some(states) => states.iter().any(|s| s == state(item)),
+                None => true,
+            })
+            .take(self.limit.map_or(usize::MAX, NonZeroUsize::get))
+            .collect()
+    }
+}
+
 /// This represents the output of the "timeline_detail" and "timeline_list" API calls.
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -370,8 +689,107 @@ pub struct TimelineInfo {
     pub pg_version: u32,
 
     pub state: TimelineState,
+
+    /// With the `check-remote-layer` query parameter of the timeline detail API: whether that
+    /// layer file is in the remote storage.
+    #[serde(default)]
+    pub remote_layer_exists: Option<bool>,
 }
 
+api_schema!(TimelineInfo {
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+    ancestor_timeline_id: Option<TimelineId>,
+    ancestor_lsn: Option<Lsn>,
+    last_record_lsn: Lsn,
+    prev_record_lsn: Option<Lsn>,
+    latest_gc_cutoff_lsn: Lsn,
+    disk_consistent_lsn: Lsn,
+    remote_consistent_lsn: Lsn,
+    current_logical_size: Option<u64>,
+    current_physical_size: Option<u64>,
+    current_logical_size_non_incremental: Option<u64>,
+    timeline_dir_layer_file_size_sum: Option<u64>,
+    wal_source_connstr: Option<String>,
+    last_received_msg_lsn: Option<Lsn>,
+    last_received_msg_ts: Option<u128>,
+    pg_version: u32,
+    state: TimelineState,
+    remote_layer_exists: Option<bool>,
+});
+
+/// The output of the "relation_sizes" API call: the size of the relations of each database.
+///
+/// The chunks of TimescaleDB hypertables, with their indexes and TOAST relations, are not
+/// listed among the relations, but summed up per hypertable.
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RelationSizesResponse {
+    #[serde_as(as = "DisplayFromStr")]
+    pub lsn: Lsn,
+    pub databases: Vec<DatabaseSizes>,
+}
+
+api_schema!(RelationSizesResponse {
+    lsn: Lsn,
+    databases: Vec<DatabaseSizes>,
+});
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DatabaseSizes {
+    pub spcnode: u32,
+    pub dbnode: u32,
+    pub relations: Vec<RelationSize>,
+    pub hypertables: Vec<HypertableSize>,
+}
+
+api_schema!(DatabaseSizes {
+    spcnode: u32,
+    dbnode: u32,
+    relations: Vec<RelationSize>,
+    hypertables: Vec<HypertableSize>,
+});
+
+/// The size of all the forks of a relation. The schema and the name are missing if the
+/// relation is not in the catalog of the database, e.g. for the shared catalogs.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RelationSize {
+    pub relnode: u32,
+    pub schema: Option<String>,
+    pub name: Option<String>,
+    pub size_bytes: u64,
+}
+
+api_schema!(RelationSize {
+    relnode: u32,
+    schema: Option<String>,
+    name: Option<String>,
+    size_bytes: u64,
+});
+
+/// The size of the chunks of a TimescaleDB hypertable.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct HypertableSize {
+    pub id: i32,
+    /// Missing if the hypertable is not in the `_timescaledb_catalog.hypertable` table.
+    pub schema: Option<String>,
+    pub name: Option<String>,
+    pub chunks: u64,
+    /// Size of the chunks, including the compressed ones.
+    pub size_bytes: u64,
+    /// Size of the compressed chunks.
+    pub compressed_size_bytes: u64,
+}
+
+api_schema!(HypertableSize {
+    id: i32,
+    schema: Option<String>,
+    name: Option<String>,
+    chunks: u64,
+    size_bytes: u64,
+    compressed_size_bytes: u64,
+});
+
 #[derive(Debug, Clone, Serialize)]
 pub struct LayerMapInfo {
     pub in_memory_layers: Vec<InMemoryLayerInfo>,
@@ -501,6 +919,10 @@ pub struct DownloadRemoteLayersTaskSpawnRequest {
     pub max_concurrent_downloads: NonZeroUsize,
 }
 
+api_schema!(DownloadRemoteLayersTaskSpawnRequest {
+    max_concurrent_downloads: NonZeroUsize,
+});
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DownloadRemoteLayersTaskInfo {
     pub task_id: String,
@@ -510,13 +932,66 @@ pub struct DownloadRemoteLayersTaskInfo {
     pub failed_download_count: u64,     // stable once `completed`
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+api_schema!(DownloadRemoteLayersTaskInfo {
+    task_id: String,
+    state: DownloadRemoteLayersTaskState,
+    total_layer_count: u64,
+    successful_download_count: u64,
+    failed_download_count: u64,
+});
+
+#[derive(Debug, Serialize, Deserialize, Clone, strum_macros::EnumVariantNames)]
 pub enum DownloadRemoteLayersTaskState {
     Running,
     Completed,
     ShutDown,
 }
 
+api_schema!(DownloadRemoteLayersTaskState = DownloadRemoteLayersTaskState::VARIANTS);
+
+/// The layers to download ahead of the reads, e.g. after an attach. Without an LSN range, the
+/// layers that the reads at the latest LSN need: the latest image layers of each key range, and
+/// the delta layers above them.
+#[serde_as]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PrefetchLayersRequest {
+    /// Only the layers overlapping the keys from `key_start` to `key_end`, excluded, in hex.
+    pub key_start: Option<String>,
+    pub key_end: Option<String>,
+    /// Only the layers overlapping the LSNs from `lsn_start` to `lsn_end`, excluded.
+    #[serde(default)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub lsn_start: Option<Lsn>,
+    #[serde(default)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub lsn_end: Option<Lsn>,
+    pub max_concurrent_downloads: Option<NonZeroUsize>,
+}
+
+api_schema!(PrefetchLayersRequest {
+    key_start: Option<String>,
+    key_end: Option<String>,
+    lsn_start: Option<Lsn>,
+    lsn_end: Option<Lsn>,
+    max_concurrent_downloads: Option<NonZeroUsize>,
+});
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefetchLayersResponse {
+    pub downloaded_layers: u64,
+    pub downloaded_bytes: u64,
+    /// The selected layers that were on the local disk already.
+    pub resident_layers: u64,
+    pub failed_layers: u64,
+}
+
+api_schema!(PrefetchLayersResponse {
+    downloaded_layers: u64,
+    downloaded_bytes: u64,
+    resident_layers: u64,
+    failed_layers: u64,
+});
+
 pub type ConfigureFailpointsRequest = Vec<FailpointConfig>;
 
 /// Information for configuring a single fail point
@@ -530,11 +1005,441 @@ pub struct FailpointConfig {
     pub actions: String,
 }
 
+api_schema!(FailpointConfig {
+    name: String,
+    actions: String,
+});
+
+/// The kinds of remote storage operations, see [`RemoteStorageFault`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum_macros::EnumVariantNames)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum RemoteStorageOpKind {
+    List,
+    Head,
+    Upload,
+    Download,
+    Delete,
+    Copy,
+}
+
+api_schema!(RemoteStorageOpKind = RemoteStorageOpKind::VARIANTS);
+
+pub type ConfigureRemoteStorageFaultsRequest = Vec<RemoteStorageFault>;
+
+/// A fault injected into the remote storage operations of a kind, with the `testing` feature.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteStorageFault {
+    pub op: RemoteStorageOpKind,
+    /// Only the operations on the paths that contain this, e.g. `index_part.json`.
+    #[serde(default)]
+    pub path_contains: Option<String>,
+    /// Delay the operations by this many milliseconds.
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Fail this many of the operations, counting down, all of them if missing.
+    #[serde(default)]
+    pub errors: Option<u64>,
+    /// Have the failed uploads write the first half of the object, and the failed batch
+    /// deletions delete the first half of the objects.
+    #[serde(default)]
+    pub partial: bool,
+}
+
+api_schema!(RemoteStorageFault {
+    op: RemoteStorageOpKind,
+    path_contains: Option<String>,
+    latency_ms: u64,
+    errors: Option<u64>,
+    partial: bool,
+});
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TimelineGcRequest {
     pub gc_horizon: Option<u64>,
 }
 
+api_schema!(TimelineGcRequest {
+    gc_horizon: Option<u64>,
+});
+
+/// The state of the upload queue of a timeline, which performs the uploads and deletions of
+/// its files in the remote storage.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadQueueInfo {
+    pub state: UploadQueueState,
+    /// The `disk_consistent_lsn` of the last index uploaded, once the queue is initialized.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub last_uploaded_consistent_lsn: Option<Lsn>,
+    /// The next index upload may go back in LSN, see
+    /// `PUT /v1/tenant/:tenant_id/timeline/:timeline_id/upload_queue/allow_lsn_regression`.
+    pub allow_lsn_regression: bool,
+    /// The size of the layer files queued or in progress for upload.
+    pub queued_layer_bytes: u64,
+    /// The operations launched, by task id.
+    pub inprogress_tasks: Vec<UploadTaskInfo>,
+    /// The operations waiting for those before them, in order.
+    pub queued_operations: Vec<UploadOpInfo>,
+    /// The progress of the deletion of the timeline, once the index marks it deleted.
+    pub deletion_progress: Option<TimelineDeletionProgress>,
+}
+
+api_schema!(UploadQueueInfo {
+    state: UploadQueueState,
+    last_uploaded_consistent_lsn: Option<Lsn>,
+    allow_lsn_regression: bool,
+    queued_layer_bytes: u64,
+    inprogress_tasks: Vec<UploadTaskInfo>,
+    queued_operations: Vec<UploadOpInfo>,
+    deletion_progress: Option<TimelineDeletionProgress>,
+});
+
+/// How far the deletion of the layer files of a timeline from the remote storage got.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimelineDeletionProgress {
+    /// The layer files deleted since the deletion started or resumed on this pageserver.
+    pub layers_deleted: u64,
+    /// The layer files left to delete.
+    pub layers_remaining: u64,
+    /// The deleted layer files that the remote index no longer references, so that a
+    /// deletion resumed after a restart doesn't delete them again.
+    pub layers_checkpointed: u64,
+}
+
+api_schema!(TimelineDeletionProgress {
+    layers_deleted: u64,
+    layers_remaining: u64,
+    layers_checkpointed: u64,
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum_macros::EnumVariantNames)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum UploadQueueState {
+    Uninitialized,
+    Initialized,
+    /// Stopped by a shutdown, a detach or a timeline deletion, or because the tenant has been
+    /// attached elsewhere since. The tasks in progress still run to completion.
+    Stopped,
+}
+
+api_schema!(UploadQueueState = UploadQueueState::VARIANTS);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum_macros::EnumVariantNames)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum UploadOpKind {
+    UploadLayer,
+    UploadIndex,
+    Delete,
+    Barrier,
+}
+
+api_schema!(UploadOpKind = UploadOpKind::VARIANTS);
+
+/// An operation of an upload queue.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadOpInfo {
+    pub kind: UploadOpKind,
+    /// The layer file uploaded or deleted, the first one of a batch of deletions.
+    pub layer_file_name: Option<String>,
+    /// The size of the layer file uploaded.
+    pub file_size: Option<u64>,
+    /// The `disk_consistent_lsn` of the index uploaded.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub disk_consistent_lsn: Option<Lsn>,
+    /// The number of layer files deleted.
+    pub layer_count: Option<u64>,
+}
+
+api_schema!(UploadOpInfo {
+    kind: UploadOpKind,
+    layer_file_name: Option<String>,
+    file_size: Option<u64>,
+    disk_consistent_lsn: Option<Lsn>,
+    layer_count: Option<u64>,
+});
+
+/// An operation of an upload queue that has been launched, and retries until it succeeds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadTaskInfo {
+    pub task_id: u64,
+    #[serde(flatten)]
+    pub op: UploadOpInfo,
+    /// How many attempts failed so far.
+    pub retries: u32,
+    /// The error of the last failed attempt.
+    pub last_error: Option<String>,
+}
+
+api_schema!(UploadTaskInfo {
+    task_id: u64,
+    retries: u32,
+    last_error: Option<String>,
+    ..UploadOpInfo
+});
+
+/// Outcome of a scrub of the remote storage of a timeline, which deletes the objects not
+/// referenced from its index.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteScrubReport {
+    pub deleted_objects: u64,
+    pub deleted_bytes: u64,
+    /// Objects not referenced from the index, but written too recently to be deleted.
+    pub skipped_recent_objects: u64,
+}
+
+api_schema!(RemoteScrubReport {
+    deleted_objects: u64,
+    deleted_bytes: u64,
+    skipped_recent_objects: u64,
+});
+
+/// Outcome of a check of the remote storage of a timeline against its index.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteConsistencyReport {
+    /// The generation of the index checked, if the tenant is attached with generations.
+    pub index_generation: Option<u32>,
+    /// The number of layer files referenced from the index.
+    pub checked_layers: u64,
+    /// The layer files referenced from the index that the remote storage doesn't have.
+    pub missing_layers: Vec<String>,
+    /// The layer files whose size or checksum differ from those in the index.
+    pub mismatched_layers: Vec<RemoteLayerMismatch>,
+}
+
+api_schema!(RemoteConsistencyReport {
+    index_generation: Option<u32>,
+    checked_layers: u64,
+    missing_layers: Vec<String>,
+    mismatched_layers: Vec<RemoteLayerMismatch>,
+});
+
+impl RemoteConsistencyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.missing_layers.is_empty() && self.mismatched_layers.is_empty()
+    }
+}
+
+/// A layer file in the remote storage that doesn't match the index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteLayerMismatch {
+    pub layer_file_name: String,
+    /// The size of the object in the remote storage according to the index, once compressed
+    /// and encrypted.
+    pub expected_size: u64,
+    pub remote_size: u64,
+    /// The checksums are only compared when the remote storage has one, and the layer file is
+    /// stored neither compressed nor encrypted.
+    pub expected_crc32c: Option<u32>,
+    pub remote_crc32c: Option<u32>,
+}
+
+api_schema!(RemoteLayerMismatch {
+    layer_file_name: String,
+    expected_size: u64,
+    remote_size: u64,
+    expected_crc32c: Option<u32>,
+    remote_crc32c: Option<u32>,
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimelineCopyRemoteRequest {
+    /// The remote storage to copy the timeline to, with the parameters of the
+    /// `remote_storage` pageserver setting.
+    pub remote_storage: serde_json::Value,
+}
+
+api_schema!(TimelineCopyRemoteRequest {
+    remote_storage: Value,
+});
+
+/// Outcome of a copy of a timeline to another remote storage.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteCopyReport {
+    /// The generation of the index copied, if the tenant is attached with generations.
+    pub index_generation: Option<u32>,
+    pub copied_layers: u64,
+    pub copied_bytes: u64,
+    /// Layer files already at the destination with the expected size, from an earlier copy.
+    pub skipped_layers: u64,
+}
+
+api_schema!(RemoteCopyReport {
+    index_generation: Option<u32>,
+    copied_layers: u64,
+    copied_bytes: u64,
+    skipped_layers: u64,
+});
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimelineRestoreIndexRequest {
+    /// The `disk_consistent_lsn` of the index snapshot to restore.
+    #[serde_as(as = "DisplayFromStr")]
+    pub lsn: Lsn,
+    /// The remote storage of the tenant, if it has its own, with the parameters of the
+    /// `remote_storage` pageserver setting.
+    #[serde(default)]
+    pub remote_storage: Option<serde_json::Value>,
+}
+
+api_schema!(TimelineRestoreIndexRequest {
+    lsn: Lsn,
+    remote_storage: Option<Value>,
+});
+
+/// Outcome of a restore of the index of a timeline from one of its snapshots.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteRestoreReport {
+    /// The generation of the index file replaced, if the tenant was attached with generations.
+    pub index_generation: Option<u32>,
+    /// The layer files the restored index references, all present in the remote storage.
+    pub restored_layers: u64,
+}
+
+api_schema!(RemoteRestoreReport {
+    index_generation: Option<u32>,
+    restored_layers: u64,
+});
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimelineExportRequest {
+    /// The LSN to export the timeline at, the last record LSN of the timeline by default.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub lsn: Option<Lsn>,
+    /// The remote storage to upload the tarball to, with the parameters of the `remote_storage`
+    /// pageserver setting. By default, the remote storage of the tenant, under its `exports`
+    /// prefix.
+    #[serde(default)]
+    pub remote_storage: Option<serde_json::Value>,
+    /// A relative path under which to upload the tarball.
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// Compress the tarball with gzip.
+    #[serde(default)]
+    pub gzip: bool,
+}
+
+api_schema!(TimelineExportRequest {
+    lsn: Option<Lsn>,
+    remote_storage: Option<Value>,
+    prefix: Option<String>,
+    gzip: bool,
+});
+
+/// A basebackup tarball of a timeline uploaded to a remote storage.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimelineExportResponse {
+    #[serde_as(as = "DisplayFromStr")]
+    pub lsn: Lsn,
+    /// The path of the tarball, relative to the root of the remote storage.
+    pub remote_path: String,
+    pub size: u64,
+}
+
+api_schema!(TimelineExportResponse {
+    lsn: Lsn,
+    remote_path: String,
+    size: u64,
+});
+
+/// The requests to the remote storage made by the timelines of a tenant since it was attached
+/// to this pageserver, and their estimated cost with the `remote_storage_prices` setting.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TenantRemoteCost {
+    #[serde_as(as = "DisplayFromStr")]
+    pub tenant_id: TenantId,
+    pub put_requests: u64,
+    /// Including the head requests.
+    pub get_requests: u64,
+    pub delete_requests: u64,
+    pub list_requests: u64,
+    pub uploaded_bytes: u64,
+    pub downloaded_bytes: u64,
+    pub estimated_cost: f64,
+}
+
+api_schema!(TenantRemoteCost {
+    tenant_id: TenantId,
+    put_requests: u64,
+    get_requests: u64,
+    delete_requests: u64,
+    list_requests: u64,
+    uploaded_bytes: u64,
+    downloaded_bytes: u64,
+    estimated_cost: f64,
+});
+
+/// An object that an upload queue would have deleted from the remote storage, had its tenant
+/// not been in the deletion dry-run mode.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeletionDryRunRecord {
+    #[serde_as(as = "DisplayFromStr")]
+    pub timeline_id: TimelineId,
+    /// Relative to the prefix of the remote storage.
+    pub remote_path: String,
+}
+
+api_schema!(DeletionDryRunRecord {
+    timeline_id: TimelineId,
+    remote_path: String,
+});
+
+/// The objects recorded by the deletion dry-run mode of a tenant since the last flush.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeletionDryRunFlushResponse {
+    pub records: Vec<DeletionDryRunRecord>,
+    /// The records dropped because too many were kept.
+    pub dropped_records: u64,
+}
+
+api_schema!(DeletionDryRunFlushResponse {
+    records: Vec<DeletionDryRunRecord>,
+    dropped_records: u64,
+});
+
+/// The WAL redo process of a tenant for a Postgres version, see
+/// `GET /v1/tenant/:tenant_id/wal_redo`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalRedoProcessInfo {
+    pub pg_version: u32,
+    /// The running process, if any.
+    pub pid: Option<u32>,
+    pub uptime_secs: Option<u64>,
+    /// The requests sent to the running process. Missing while a request is writing to it.
+    pub n_requests: Option<u64>,
+    /// The responses read from the running process, not yet taken by their requests. Missing
+    /// while a request is waiting for its response.
+    pub pending_responses: Option<u64>,
+    /// The processes killed after a failed request since the tenant was loaded, each of them
+    /// replaced on the next request.
+    pub restarts: u64,
+    /// The failure of the last of them.
+    pub last_restart_reason: Option<String>,
+    /// The last lines written to stderr by the processes, including the killed ones.
+    pub stderr_tail: Vec<String>,
+}
+
+api_schema!(WalRedoProcessInfo {
+    pg_version: u32,
+    pid: Option<u32>,
+    uptime_secs: Option<u64>,
+    n_requests: Option<u64>,
+    pending_responses: Option<u64>,
+    restarts: u64,
+    last_restart_reason: Option<String>,
+    stderr_tail: Vec<String>,
+});
+
 // Wrapped in libpq CopyData
 #[derive(PartialEq, Eq, Debug)]
 pub enum PagestreamFeMessage {
@@ -746,6 +1651,68 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn list_query() {
+        let items = vec![
+            (5, "Active"),
+            (1, "Active"),
+            (3, "Broken"),
+            (4, "Active"),
+            (2, "Loading"),
+        ];
+        let apply = |query: ListQuery<u32>| {
+            query
+                .apply(items.clone(), |(id, _)| *id, |(_, state)| *state)
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(apply(ListQuery::default()), [1, 2, 3, 4, 5]);
+
+        let limit = NonZeroUsize::new(2);
+        let page = |cursor| ListQuery {
+            limit,
+            cursor,
+            ..ListQuery::default()
+        };
+        assert_eq!(apply(page(None)), [1, 2]);
+        assert_eq!(apply(page(Some(2))), [3, 4]);
+        assert_eq!(apply(page(Some(4))), [5]);
+        assert_eq!(apply(page(Some(5))), Vec::<u32>::new());
+
+        let active = |cursor| ListQuery {
+            limit,
+            cursor,
+            states: Some(vec!["Active".to_string()]),
+            ..ListQuery::default()
+        };
+        assert_eq!(apply(active(None)), [1, 4]);
+        assert_eq!(apply(active(Some(4))), [5]);
+
+        let broken_or_loading = ListQuery {
+            states: Some(vec!["Broken".to_string(), "Loading".to_string()]),
+            ..ListQuery::default()
+        };
+        assert_eq!(apply(broken_or_loading), [2, 3]);
+    }
+
+    #[test]
+    fn tenant_config_schema() {
+        let mut components = Components::default();
+        TenantConfig::schema(&mut components);
+        let schema = components.get("TenantConfig").unwrap();
+
+        // All the fields are described, and nothing else.
+        let serialized = serde_json::to_value(TenantConfig::default()).unwrap();
+        let mut fields = serialized.as_object().unwrap().keys().collect::<Vec<_>>();
+        let mut described = schema["properties"].as_object().unwrap().keys().collect::<Vec<_>>();
+        fields.sort();
+        described.sort();
+        assert_eq!(fields, described);
+        assert_eq!(schema["required"], json!([]));
+    }
+
     #[test]
     fn test_pagestream() {
         // Test serialization/deserialization of PagestreamFeMessage
@@ -34,6 +34,9 @@ pub const SMGR_TRUNCATE_FSM: u32 = 0x0004;
 const SIZEOF_PAGE_HEADER_DATA: usize = std::mem::size_of::<PageHeaderData>();
 pub const MAXALIGN_SIZE_OF_PAGE_HEADER_DATA: usize = (SIZEOF_PAGE_HEADER_DATA + 7) & !7;
 
+pub const PD_ALL_VISIBLE: u16 = 0x0004;
+pub const PG_PAGE_LAYOUT_VERSION: u16 = 4;
+
 //
 // constants from clog.h
 //
@@ -73,6 +76,49 @@ pub const VISIBILITYMAP_ALL_VISIBLE: u8 = 0x01;
 pub const VISIBILITYMAP_ALL_FROZEN: u8 = 0x02;
 pub const VISIBILITYMAP_VALID_BITS: u8 = 0x03;
 
+// From itemid.h
+pub const LP_UNUSED: u32 = 0;
+pub const LP_NORMAL: u32 = 1;
+
+// From htup_details.h
+pub const HEAP_HASNULL: u16 = 0x0001;
+pub const HEAP_XMAX_KEYSHR_LOCK: u16 = 0x0010;
+pub const HEAP_COMBOCID: u16 = 0x0020;
+pub const HEAP_XMAX_EXCL_LOCK: u16 = 0x0040;
+pub const HEAP_XMAX_LOCK_ONLY: u16 = 0x0080;
+pub const HEAP_XMAX_SHR_LOCK: u16 = HEAP_XMAX_EXCL_LOCK | HEAP_XMAX_KEYSHR_LOCK;
+pub const HEAP_LOCK_MASK: u16 = HEAP_XMAX_SHR_LOCK | HEAP_XMAX_EXCL_LOCK | HEAP_XMAX_KEYSHR_LOCK;
+pub const HEAP_XMIN_COMMITTED: u16 = 0x0100;
+pub const HEAP_XMIN_INVALID: u16 = 0x0200;
+pub const HEAP_XMAX_COMMITTED: u16 = 0x0400;
+pub const HEAP_XMAX_INVALID: u16 = 0x0800;
+pub const HEAP_XMAX_IS_MULTI: u16 = 0x1000;
+pub const HEAP_MOVED_OFF: u16 = 0x4000;
+pub const HEAP_MOVED_IN: u16 = 0x8000;
+pub const HEAP_MOVED: u16 = HEAP_MOVED_OFF | HEAP_MOVED_IN;
+pub const HEAP_XMAX_BITS: u16 = HEAP_XMAX_COMMITTED
+    | HEAP_XMAX_INVALID
+    | HEAP_XMAX_IS_MULTI
+    | HEAP_LOCK_MASK
+    | HEAP_XMAX_LOCK_ONLY;
+pub const HEAP_NATTS_MASK: u16 = 0x07FF;
+pub const HEAP_KEYS_UPDATED: u16 = 0x2000;
+pub const HEAP_HOT_UPDATED: u16 = 0x4000;
+pub const SIZEOF_HEAP_TUPLE_HEADER: usize = 23;
+pub const MAX_HEAP_TUPLES_PER_PAGE: u16 =
+    (BLCKSZ - SIZE_OF_PAGE_HEADER) / (((SIZEOF_HEAP_TUPLE_HEADER as u16 + 7) & !7) + 4);
+
+// From pg_config_manual.h
+pub const NAMEDATALEN: usize = 64;
+
+// From relmapper.c
+pub const RELMAPPER_FILEMAGIC: u32 = 0x592717;
+
+// From pg_class_d.h, pg_namespace_d.h and pg_extension_d.h
+pub const RELATION_RELATION_ID: u32 = 1259;
+pub const NAMESPACE_RELATION_ID: u32 = 2615;
+pub const EXTENSION_RELATION_ID: u32 = 3079;
+
 // From xact.h
 pub const XLOG_XACT_COMMIT: u8 = 0x00;
 pub const XLOG_XACT_PREPARE: u8 = 0x10;
@@ -144,7 +190,19 @@ pub const XLH_INSERT_ALL_FROZEN_SET: u8 = (1 << 5) as u8;
 pub const XLH_INSERT_ALL_VISIBLE_CLEARED: u8 = (1 << 0) as u8;
 pub const XLH_UPDATE_OLD_ALL_VISIBLE_CLEARED: u8 = (1 << 0) as u8;
 pub const XLH_UPDATE_NEW_ALL_VISIBLE_CLEARED: u8 = (1 << 1) as u8;
+pub const XLH_UPDATE_PREFIX_FROM_OLD: u8 = (1 << 5) as u8;
+pub const XLH_UPDATE_SUFFIX_FROM_OLD: u8 = (1 << 6) as u8;
 pub const XLH_DELETE_ALL_VISIBLE_CLEARED: u8 = (1 << 0) as u8;
+pub const XLH_DELETE_IS_SUPER: u8 = (1 << 3) as u8;
+pub const XLH_DELETE_IS_PARTITION_MOVE: u8 = (1 << 4) as u8;
+pub const XLHL_XMAX_IS_MULTI: u8 = 0x01;
+pub const XLHL_XMAX_LOCK_ONLY: u8 = 0x02;
+pub const XLHL_XMAX_EXCL_LOCK: u8 = 0x04;
+pub const XLHL_XMAX_KEYSHR_LOCK: u8 = 0x08;
+pub const XLHL_KEYS_UPDATED: u8 = 0x10;
+
+// From nbtxlog.h
+pub const XLOG_BTREE_INSERT_LEAF: u8 = 0x00;
 
 // From replication/message.h
 pub const XLOG_LOGICAL_MESSAGE: u8 = 0x00;
@@ -161,6 +219,7 @@ pub const RM_RELMAP_ID: u8 = 7;
 pub const RM_STANDBY_ID: u8 = 8;
 pub const RM_HEAP2_ID: u8 = 9;
 pub const RM_HEAP_ID: u8 = 10;
+pub const RM_BTREE_ID: u8 = 11;
 pub const RM_LOGICALMSG_ID: u8 = 21;
 
 // from xlogreader.h
@@ -0,0 +1,324 @@
+//! This module provides a wrapper around a real RemoteStorage implementation that injects
+//! the faults a [`FaultInjector`] decides on into the operations made through it: latency,
+//! errors, and partial writes. Unlike [`crate::UnreliableWrapper`], the faults can change at
+//! runtime, e.g. from the management API of the pageserver. For testing purposes.
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{
+    Download, DownloadError, ObjectHead, RemoteObject, RemotePath, RemoteStorage, StorageMetadata,
+    UploadError, UploadPrecondition, UploadedPart,
+};
+
+/// The kinds of operations that faults are injected into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultOpKind {
+    List,
+    Head,
+    Upload,
+    Download,
+    Delete,
+    Copy,
+}
+
+/// The fault to inject into an operation, no fault by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Fault {
+    /// Wait this long before running, or failing, the operation.
+    pub latency: Duration,
+    /// Fail the operation.
+    pub error: bool,
+    /// With `error`, a failed upload writes the first half of the object, and a failed batch
+    /// deletion deletes the first half of the objects.
+    pub partial: bool,
+}
+
+/// Decides on the faults of the operations made through a [`FaultInjectingStorage`].
+pub trait FaultInjector: Send + Sync {
+    /// The fault to inject into an operation of `kind` on `path`, the prefix for listings.
+    /// A batch deletion asks for each of its objects, and fails if any of them does.
+    fn fault(&self, kind: FaultOpKind, path: Option<&RemotePath>) -> Fault;
+}
+
+pub struct FaultInjectingStorage {
+    inner: crate::GenericRemoteStorage,
+    injector: Arc<dyn FaultInjector>,
+}
+
+impl FaultInjectingStorage {
+    pub fn new(inner: crate::GenericRemoteStorage, injector: Arc<dyn FaultInjector>) -> Self {
+        FaultInjectingStorage { inner, injector }
+    }
+
+    /// Wait for the latency of the fault of the operation, and return the fault.
+    async fn inject(&self, kind: FaultOpKind, path: Option<&RemotePath>) -> Fault {
+        let fault = self.injector.fault(kind, path);
+        if !fault.latency.is_zero() {
+            tokio::time::sleep(fault.latency).await;
+        }
+        fault
+    }
+
+    /// Upload the first half of the object, as an upload interrupted midway would.
+    async fn upload_partial(
+        &self,
+        mut data: impl AsyncRead + Unpin,
+        data_size_bytes: usize,
+        to: &RemotePath,
+    ) -> anyhow::Result<()> {
+        let mut buf = Vec::with_capacity(data_size_bytes);
+        data.read_to_end(&mut buf).await?;
+        buf.truncate(buf.len() / 2);
+        let len = buf.len();
+        self.inner
+            .upload(std::io::Cursor::new(buf), len, to, None)
+            .await
+    }
+}
+
+fn injected_error(kind: FaultOpKind, path: Option<&RemotePath>) -> anyhow::Error {
+    anyhow::anyhow!("injected failure of remote {kind:?} operation on {path:?}")
+}
+
+#[async_trait::async_trait]
+impl RemoteStorage for FaultInjectingStorage {
+    async fn list_prefixes(
+        &self,
+        prefix: Option<&RemotePath>,
+    ) -> Result<Vec<RemotePath>, DownloadError> {
+        if self.inject(FaultOpKind::List, prefix).await.error {
+            let error = injected_error(FaultOpKind::List, prefix);
+            return Err(DownloadError::Other(error));
+        }
+        self.inner.list_prefixes(prefix).await
+    }
+
+    async fn list_files(&self, folder: Option<&RemotePath>) -> anyhow::Result<Vec<RemotePath>> {
+        if self.inject(FaultOpKind::List, folder).await.error {
+            return Err(injected_error(FaultOpKind::List, folder));
+        }
+        self.inner.list_files(folder).await
+    }
+
+    async fn list_objects(
+        &self,
+        folder: Option<&RemotePath>,
+    ) -> anyhow::Result<Vec<RemoteObject>> {
+        if self.inject(FaultOpKind::List, folder).await.error {
+            return Err(injected_error(FaultOpKind::List, folder));
+        }
+        self.inner.list_objects(folder).await
+    }
+
+    async fn head_object(&self, path: &RemotePath) -> Result<ObjectHead, DownloadError> {
+        if self.inject(FaultOpKind::Head, Some(path)).await.error {
+            let error = injected_error(FaultOpKind::Head, Some(path));
+            return Err(DownloadError::Other(error));
+        }
+        self.inner.head_object(path).await
+    }
+
+    async fn upload(
+        &self,
+        data: impl tokio::io::AsyncRead + Unpin + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        metadata: Option<StorageMetadata>,
+    ) -> anyhow::Result<()> {
+        let fault = self.inject(FaultOpKind::Upload, Some(to)).await;
+        if fault.error {
+            if fault.partial {
+                self.upload_partial(data, data_size_bytes, to).await?;
+            }
+            return Err(injected_error(FaultOpKind::Upload, Some(to)));
+        }
+        self.inner.upload(data, data_size_bytes, to, metadata).await
+    }
+
+    async fn upload_conditional(
+        &self,
+        data: impl tokio::io::AsyncRead + Unpin + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        precondition: Option<&UploadPrecondition>,
+    ) -> Result<Option<String>, UploadError> {
+        let fault = self.inject(FaultOpKind::Upload, Some(to)).await;
+        if fault.error {
+            if fault.partial {
+                self.upload_partial(data, data_size_bytes, to).await?;
+            }
+            let error = injected_error(FaultOpKind::Upload, Some(to));
+            return Err(UploadError::Other(error));
+        }
+        self.inner
+            .upload_conditional(data, data_size_bytes, to, precondition)
+            .await
+    }
+
+    async fn download(&self, from: &RemotePath) -> Result<Download, DownloadError> {
+        if self.inject(FaultOpKind::Download, Some(from)).await.error {
+            let error = injected_error(FaultOpKind::Download, Some(from));
+            return Err(DownloadError::Other(error));
+        }
+        self.inner.download(from).await
+    }
+
+    async fn download_byte_range(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+    ) -> Result<Download, DownloadError> {
+        if self.inject(FaultOpKind::Download, Some(from)).await.error {
+            let error = injected_error(FaultOpKind::Download, Some(from));
+            return Err(DownloadError::Other(error));
+        }
+        self.inner
+            .download_byte_range(from, start_inclusive, end_exclusive)
+            .await
+    }
+
+    async fn delete(&self, path: &RemotePath) -> anyhow::Result<()> {
+        if self.inject(FaultOpKind::Delete, Some(path)).await.error {
+            return Err(injected_error(FaultOpKind::Delete, Some(path)));
+        }
+        self.inner.delete(path).await
+    }
+
+    async fn delete_objects<'a>(&self, paths: &'a [RemotePath]) -> anyhow::Result<()> {
+        // One request, with the longest of the latencies and the first of the errors
+        let mut latency = Duration::ZERO;
+        let mut failed = None;
+        for path in paths {
+            let fault = self.injector.fault(FaultOpKind::Delete, Some(path));
+            latency = latency.max(fault.latency);
+            if fault.error && failed.is_none() {
+                failed = Some((path, fault.partial));
+            }
+        }
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        if let Some((path, partial)) = failed {
+            if partial {
+                self.inner.delete_objects(&paths[..paths.len() / 2]).await?;
+            }
+            return Err(injected_error(FaultOpKind::Delete, Some(path)));
+        }
+        self.inner.delete_objects(paths).await
+    }
+
+    async fn copy(&self, from: &RemotePath, to: &RemotePath) -> anyhow::Result<()> {
+        if self.inject(FaultOpKind::Copy, Some(to)).await.error {
+            return Err(injected_error(FaultOpKind::Copy, Some(to)));
+        }
+        self.inner.copy(from, to).await
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        to: &RemotePath,
+        metadata: Option<StorageMetadata>,
+    ) -> anyhow::Result<String> {
+        if self.inject(FaultOpKind::Upload, Some(to)).await.error {
+            return Err(injected_error(FaultOpKind::Upload, Some(to)));
+        }
+        self.inner.create_multipart_upload(to, metadata).await
+    }
+
+    async fn upload_part(
+        &self,
+        mut data: impl tokio::io::AsyncRead + Unpin + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        upload_id: &str,
+        part_number: u32,
+    ) -> anyhow::Result<String> {
+        let fault = self.inject(FaultOpKind::Upload, Some(to)).await;
+        if fault.error {
+            if fault.partial {
+                let mut buf = Vec::with_capacity(data_size_bytes);
+                data.read_to_end(&mut buf).await?;
+                buf.truncate(buf.len() / 2);
+                let len = buf.len();
+                self.inner
+                    .upload_part(std::io::Cursor::new(buf), len, to, upload_id, part_number)
+                    .await?;
+            }
+            return Err(injected_error(FaultOpKind::Upload, Some(to)));
+        }
+        self.inner
+            .upload_part(data, data_size_bytes, to, upload_id, part_number)
+            .await
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        to: &RemotePath,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> anyhow::Result<()> {
+        if self.inject(FaultOpKind::Upload, Some(to)).await.error {
+            return Err(injected_error(FaultOpKind::Upload, Some(to)));
+        }
+        self.inner
+            .complete_multipart_upload(to, upload_id, parts)
+            .await
+    }
+
+    async fn abort_multipart_upload(&self, to: &RemotePath, upload_id: &str) -> anyhow::Result<()> {
+        if self.inject(FaultOpKind::Delete, Some(to)).await.error {
+            return Err(injected_error(FaultOpKind::Delete, Some(to)));
+        }
+        self.inner.abort_multipart_upload(to, upload_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{path::Path, sync::Mutex};
+
+    /// Fails the uploads of the given paths, partially.
+    #[derive(Default)]
+    struct FailUploads(Mutex<Vec<RemotePath>>);
+
+    impl FaultInjector for FailUploads {
+        fn fault(&self, kind: FaultOpKind, path: Option<&RemotePath>) -> Fault {
+            let failing = self.0.lock().unwrap();
+            let error = kind == FaultOpKind::Upload && path.map_or(false, |p| failing.contains(p));
+            Fault {
+                latency: Duration::ZERO,
+                error,
+                partial: error,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn partial_upload() -> anyhow::Result<()> {
+        let root = tempfile::tempdir()?;
+        let injector = Arc::new(FailUploads::default());
+        let storage = crate::GenericRemoteStorage::fault_injecting(
+            crate::GenericRemoteStorage::LocalFs(crate::LocalFs::new(root.path().to_path_buf())?),
+            injector.clone(),
+        );
+        let path = RemotePath::new(Path::new("file"))?;
+        let contents = "0123456789";
+
+        injector.0.lock().unwrap().push(path.clone());
+        let from = tokio::io::BufReader::new(contents.as_bytes());
+        assert!(storage.upload(from, contents.len(), &path, None).await.is_err());
+        assert_eq!(std::fs::read_to_string(root.path().join("file"))?, "01234");
+
+        injector.0.lock().unwrap().clear();
+        let from = tokio::io::BufReader::new(contents.as_bytes());
+        storage.upload(from, contents.len(), &path, None).await?;
+        assert_eq!(std::fs::read_to_string(root.path().join("file"))?, contents);
+
+        Ok(())
+    }
+}
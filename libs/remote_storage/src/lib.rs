@@ -5,7 +5,9 @@
 //!   * [`local_fs`] allows to use local file system as an external storage
 //!   * [`s3_bucket`] uses AWS S3 bucket as an external storage
 //!
+mod fault_injection;
 mod local_fs;
+mod metered;
 mod s3_bucket;
 mod simulate_failures;
 
@@ -16,6 +18,7 @@ use std::{
     path::{Path, PathBuf},
     pin::Pin,
     sync::Arc,
+    time::SystemTime,
 };
 
 use anyhow::{bail, Context};
@@ -24,7 +27,13 @@ use tokio::io;
 use toml_edit::Item;
 use tracing::info;
 
-pub use self::{local_fs::LocalFs, s3_bucket::S3Bucket, simulate_failures::UnreliableWrapper};
+pub use self::{
+    fault_injection::{Fault, FaultInjectingStorage, FaultInjector, FaultOpKind},
+    local_fs::LocalFs,
+    metered::{MeteredStorage, RequestKind, RequestObserver, REQUEST_PAGE_SIZE},
+    s3_bucket::S3Bucket,
+    simulate_failures::UnreliableWrapper,
+};
 
 /// How many different timelines can be processed simultaneously when synchronizing layers with the remote storage.
 /// During regular work, pageserver produces one layer file per timeline checkpoint, with bursts of concurrency
@@ -104,9 +113,21 @@ pub trait RemoteStorage: Send + Sync + 'static {
     /// "foo/bar/cat567.txt", "foo/bar/dog123.txt", "foo/bar/dog456.txt"]
     /// whereas,
     /// list_prefixes("foo/bar/") = ["cat", "dog"]
+    /// The folder is a raw prefix, so list_files("foo/bar/cat") = ["foo/bar/cat123.txt",
+    /// "foo/bar/cat567.txt"].
     /// See `test_real_s3.rs` for more details.
     async fn list_files(&self, folder: Option<&RemotePath>) -> anyhow::Result<Vec<RemotePath>>;
 
+    /// Lists the files like `list_files`, with their size and modification time.
+    /// Unlike `list_files` for the local file system, it doesn't list directories.
+    async fn list_objects(
+        &self,
+        folder: Option<&RemotePath>,
+    ) -> anyhow::Result<Vec<RemoteObject>>;
+
+    /// Returns the size and other attributes of a file, without its contents.
+    async fn head_object(&self, path: &RemotePath) -> Result<ObjectHead, DownloadError>;
+
     /// Streams the local file contents into remote into the remote storage entry.
     async fn upload(
         &self,
@@ -118,6 +139,17 @@ pub trait RemoteStorage: Send + Sync + 'static {
         metadata: Option<StorageMetadata>,
     ) -> anyhow::Result<()>;
 
+    /// Uploads like [`RemoteStorage::upload`], if the file matches the precondition, atomically,
+    /// unlike a check before the upload. Without a precondition, the upload is unconditional.
+    /// Returns the entity tag of the uploaded file, if the storage has one.
+    async fn upload_conditional(
+        &self,
+        from: impl io::AsyncRead + Unpin + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        precondition: Option<&UploadPrecondition>,
+    ) -> Result<Option<String>, UploadError>;
+
     /// Streams the remote storage entry contents into the buffered writer given, returns the filled writer.
     /// Returns the metadata, if any was stored with the file previously.
     async fn download(&self, from: &RemotePath) -> Result<Download, DownloadError>;
@@ -134,18 +166,86 @@ pub trait RemoteStorage: Send + Sync + 'static {
     async fn delete(&self, path: &RemotePath) -> anyhow::Result<()>;
 
     async fn delete_objects<'a>(&self, paths: &'a [RemotePath]) -> anyhow::Result<()>;
+
+    /// Copies an object within the storage, with its metadata, without downloading it where the
+    /// storage can copy it itself. Overwrites the destination.
+    async fn copy(&self, from: &RemotePath, to: &RemotePath) -> anyhow::Result<()>;
+
+    /// Starts a multipart upload to `to`, returns its id. The file is only written, with the
+    /// parts uploaded until then, by [`RemoteStorage::complete_multipart_upload`].
+    async fn create_multipart_upload(
+        &self,
+        to: &RemotePath,
+        metadata: Option<StorageMetadata>,
+    ) -> anyhow::Result<String>;
+
+    /// Uploads a part of a multipart upload, numbered from 1, replacing an earlier upload of
+    /// the same part. Returns the entity tag of the part, to complete the upload with.
+    async fn upload_part(
+        &self,
+        from: impl io::AsyncRead + Unpin + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        upload_id: &str,
+        part_number: u32,
+    ) -> anyhow::Result<String>;
+
+    /// Writes the file of a multipart upload from the given parts, in ascending order of
+    /// their numbers, and ends the upload.
+    async fn complete_multipart_upload(
+        &self,
+        to: &RemotePath,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> anyhow::Result<()>;
+
+    /// Ends a multipart upload without writing the file, removing its parts.
+    async fn abort_multipart_upload(&self, to: &RemotePath, upload_id: &str) -> anyhow::Result<()>;
+}
+
+/// A part of a multipart upload, as uploaded by [`RemoteStorage::upload_part`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadedPart {
+    pub part_number: u32,
+    pub etag: String,
+}
+
+/// A file in the remote storage, as listed by [`RemoteStorage::list_objects`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteObject {
+    pub path: RemotePath,
+    pub size: u64,
+    /// When the file was last written, if the storage reports it.
+    pub last_modified: Option<SystemTime>,
+}
+
+/// The attributes of a file in the remote storage, as returned by [`RemoteStorage::head_object`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectHead {
+    pub size: u64,
+    /// When the file was last written, if the storage reports it.
+    pub last_modified: Option<SystemTime>,
+    /// CRC32C of the file contents, if the storage has one. S3 only has one for the objects
+    /// uploaded with that checksum.
+    pub crc32c: Option<u32>,
+    /// The entity tag of the file, if the storage has one.
+    pub etag: Option<String>,
 }
 
 pub struct Download {
     pub download_stream: Pin<Box<dyn io::AsyncRead + Unpin + Send + Sync>>,
     /// Extra key-value data, associated with the current remote file.
     pub metadata: Option<StorageMetadata>,
+    /// The entity tag of the downloaded version of the file, if the storage has one, for an
+    /// [`UploadPrecondition::IfMatch`] to overwrite that version only.
+    pub etag: Option<String>,
 }
 
 impl Debug for Download {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Download")
             .field("metadata", &self.metadata)
+            .field("etag", &self.etag)
             .finish()
     }
 }
@@ -158,6 +258,8 @@ pub enum DownloadError {
     NotFound,
     /// The file was found in the remote storage, but the download failed.
     Other(anyhow::Error),
+    /// The caller cancelled the download.
+    Cancelled,
 }
 
 impl std::fmt::Display for DownloadError {
@@ -168,12 +270,49 @@ impl std::fmt::Display for DownloadError {
             }
             DownloadError::NotFound => write!(f, "No file found for the remote object id given"),
             DownloadError::Other(e) => write!(f, "Failed to download a remote file: {e:?}"),
+            DownloadError::Cancelled => write!(f, "The download of a remote file was cancelled"),
         }
     }
 }
 
 impl std::error::Error for DownloadError {}
 
+/// The condition for [`RemoteStorage::upload_conditional`] to write the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UploadPrecondition {
+    /// The file doesn't exist.
+    IfNoneMatch,
+    /// The file has the given entity tag: it hasn't been written since it was read with it.
+    IfMatch(String),
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    /// The file didn't match the precondition of the upload, and was left as it is.
+    PreconditionFailed,
+    /// The upload failed.
+    Other(anyhow::Error),
+}
+
+impl std::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UploadError::PreconditionFailed => {
+                write!(f, "The remote file doesn't match the precondition of the upload")
+            }
+            UploadError::Other(e) => write!(f, "Failed to upload a remote file: {e:?}"),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+impl From<anyhow::Error> for UploadError {
+    fn from(e: anyhow::Error) -> Self {
+        UploadError::Other(e)
+    }
+}
+
 /// Every storage, currently supported.
 /// Serves as a simple way to pass around the [`RemoteStorage`] without dealing with generics.
 #[derive(Clone)]
@@ -181,6 +320,8 @@ pub enum GenericRemoteStorage {
     LocalFs(LocalFs),
     AwsS3(Arc<S3Bucket>),
     Unreliable(Arc<UnreliableWrapper>),
+    Metered(Arc<MeteredStorage>),
+    FaultInjecting(Arc<FaultInjectingStorage>),
 }
 
 impl GenericRemoteStorage {
@@ -192,6 +333,8 @@ impl GenericRemoteStorage {
             Self::LocalFs(s) => s.list_prefixes(prefix).await,
             Self::AwsS3(s) => s.list_prefixes(prefix).await,
             Self::Unreliable(s) => s.list_prefixes(prefix).await,
+            Self::Metered(s) => s.list_prefixes(prefix).await,
+            Self::FaultInjecting(s) => s.list_prefixes(prefix).await,
         }
     }
 
@@ -200,6 +343,31 @@ impl GenericRemoteStorage {
             Self::LocalFs(s) => s.list_files(folder).await,
             Self::AwsS3(s) => s.list_files(folder).await,
             Self::Unreliable(s) => s.list_files(folder).await,
+            Self::Metered(s) => s.list_files(folder).await,
+            Self::FaultInjecting(s) => s.list_files(folder).await,
+        }
+    }
+
+    pub async fn list_objects(
+        &self,
+        folder: Option<&RemotePath>,
+    ) -> anyhow::Result<Vec<RemoteObject>> {
+        match self {
+            Self::LocalFs(s) => s.list_objects(folder).await,
+            Self::AwsS3(s) => s.list_objects(folder).await,
+            Self::Unreliable(s) => s.list_objects(folder).await,
+            Self::Metered(s) => s.list_objects(folder).await,
+            Self::FaultInjecting(s) => s.list_objects(folder).await,
+        }
+    }
+
+    pub async fn head_object(&self, path: &RemotePath) -> Result<ObjectHead, DownloadError> {
+        match self {
+            Self::LocalFs(s) => s.head_object(path).await,
+            Self::AwsS3(s) => s.head_object(path).await,
+            Self::Unreliable(s) => s.head_object(path).await,
+            Self::Metered(s) => s.head_object(path).await,
+            Self::FaultInjecting(s) => s.head_object(path).await,
         }
     }
 
@@ -214,6 +382,39 @@ impl GenericRemoteStorage {
             Self::LocalFs(s) => s.upload(from, data_size_bytes, to, metadata).await,
             Self::AwsS3(s) => s.upload(from, data_size_bytes, to, metadata).await,
             Self::Unreliable(s) => s.upload(from, data_size_bytes, to, metadata).await,
+            Self::Metered(s) => s.upload(from, data_size_bytes, to, metadata).await,
+            Self::FaultInjecting(s) => s.upload(from, data_size_bytes, to, metadata).await,
+        }
+    }
+
+    pub async fn upload_conditional(
+        &self,
+        from: impl io::AsyncRead + Unpin + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        precondition: Option<&UploadPrecondition>,
+    ) -> Result<Option<String>, UploadError> {
+        match self {
+            Self::LocalFs(s) => {
+                s.upload_conditional(from, data_size_bytes, to, precondition)
+                    .await
+            }
+            Self::AwsS3(s) => {
+                s.upload_conditional(from, data_size_bytes, to, precondition)
+                    .await
+            }
+            Self::Unreliable(s) => {
+                s.upload_conditional(from, data_size_bytes, to, precondition)
+                    .await
+            }
+            Self::Metered(s) => {
+                s.upload_conditional(from, data_size_bytes, to, precondition)
+                    .await
+            }
+            Self::FaultInjecting(s) => {
+                s.upload_conditional(from, data_size_bytes, to, precondition)
+                    .await
+            }
         }
     }
 
@@ -222,6 +423,8 @@ impl GenericRemoteStorage {
             Self::LocalFs(s) => s.download(from).await,
             Self::AwsS3(s) => s.download(from).await,
             Self::Unreliable(s) => s.download(from).await,
+            Self::Metered(s) => s.download(from).await,
+            Self::FaultInjecting(s) => s.download(from).await,
         }
     }
 
@@ -244,6 +447,14 @@ impl GenericRemoteStorage {
                 s.download_byte_range(from, start_inclusive, end_exclusive)
                     .await
             }
+            Self::Metered(s) => {
+                s.download_byte_range(from, start_inclusive, end_exclusive)
+                    .await
+            }
+            Self::FaultInjecting(s) => {
+                s.download_byte_range(from, start_inclusive, end_exclusive)
+                    .await
+            }
         }
     }
 
@@ -252,6 +463,8 @@ impl GenericRemoteStorage {
             Self::LocalFs(s) => s.delete(path).await,
             Self::AwsS3(s) => s.delete(path).await,
             Self::Unreliable(s) => s.delete(path).await,
+            Self::Metered(s) => s.delete(path).await,
+            Self::FaultInjecting(s) => s.delete(path).await,
         }
     }
 
@@ -260,6 +473,93 @@ impl GenericRemoteStorage {
             Self::LocalFs(s) => s.delete_objects(paths).await,
             Self::AwsS3(s) => s.delete_objects(paths).await,
             Self::Unreliable(s) => s.delete_objects(paths).await,
+            Self::Metered(s) => s.delete_objects(paths).await,
+            Self::FaultInjecting(s) => s.delete_objects(paths).await,
+        }
+    }
+
+    pub async fn copy(&self, from: &RemotePath, to: &RemotePath) -> anyhow::Result<()> {
+        match self {
+            Self::LocalFs(s) => s.copy(from, to).await,
+            Self::AwsS3(s) => s.copy(from, to).await,
+            Self::Unreliable(s) => s.copy(from, to).await,
+            Self::Metered(s) => s.copy(from, to).await,
+            Self::FaultInjecting(s) => s.copy(from, to).await,
+        }
+    }
+
+    pub async fn create_multipart_upload(
+        &self,
+        to: &RemotePath,
+        metadata: Option<StorageMetadata>,
+    ) -> anyhow::Result<String> {
+        match self {
+            Self::LocalFs(s) => s.create_multipart_upload(to, metadata).await,
+            Self::AwsS3(s) => s.create_multipart_upload(to, metadata).await,
+            Self::Unreliable(s) => s.create_multipart_upload(to, metadata).await,
+            Self::Metered(s) => s.create_multipart_upload(to, metadata).await,
+            Self::FaultInjecting(s) => s.create_multipart_upload(to, metadata).await,
+        }
+    }
+
+    pub async fn upload_part(
+        &self,
+        from: impl io::AsyncRead + Unpin + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        upload_id: &str,
+        part_number: u32,
+    ) -> anyhow::Result<String> {
+        match self {
+            Self::LocalFs(s) => {
+                s.upload_part(from, data_size_bytes, to, upload_id, part_number)
+                    .await
+            }
+            Self::AwsS3(s) => {
+                s.upload_part(from, data_size_bytes, to, upload_id, part_number)
+                    .await
+            }
+            Self::Unreliable(s) => {
+                s.upload_part(from, data_size_bytes, to, upload_id, part_number)
+                    .await
+            }
+            Self::Metered(s) => {
+                s.upload_part(from, data_size_bytes, to, upload_id, part_number)
+                    .await
+            }
+            Self::FaultInjecting(s) => {
+                s.upload_part(from, data_size_bytes, to, upload_id, part_number)
+                    .await
+            }
+        }
+    }
+
+    pub async fn complete_multipart_upload(
+        &self,
+        to: &RemotePath,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::LocalFs(s) => s.complete_multipart_upload(to, upload_id, parts).await,
+            Self::AwsS3(s) => s.complete_multipart_upload(to, upload_id, parts).await,
+            Self::Unreliable(s) => s.complete_multipart_upload(to, upload_id, parts).await,
+            Self::Metered(s) => s.complete_multipart_upload(to, upload_id, parts).await,
+            Self::FaultInjecting(s) => s.complete_multipart_upload(to, upload_id, parts).await,
+        }
+    }
+
+    pub async fn abort_multipart_upload(
+        &self,
+        to: &RemotePath,
+        upload_id: &str,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::LocalFs(s) => s.abort_multipart_upload(to, upload_id).await,
+            Self::AwsS3(s) => s.abort_multipart_upload(to, upload_id).await,
+            Self::Unreliable(s) => s.abort_multipart_upload(to, upload_id).await,
+            Self::Metered(s) => s.abort_multipart_upload(to, upload_id).await,
+            Self::FaultInjecting(s) => s.abort_multipart_upload(to, upload_id).await,
         }
     }
 }
@@ -283,6 +583,17 @@ impl GenericRemoteStorage {
         Self::Unreliable(Arc::new(UnreliableWrapper::new(s, fail_first)))
     }
 
+    /// Reports the requests made through the returned storage to `observer`.
+    pub fn metered(s: Self, observer: Arc<dyn RequestObserver>) -> Self {
+        Self::Metered(Arc::new(MeteredStorage::new(s, observer)))
+    }
+
+    /// Injects the faults that `injector` decides on into the requests made through the
+    /// returned storage.
+    pub fn fault_injecting(s: Self, injector: Arc<dyn FaultInjector>) -> Self {
+        Self::FaultInjecting(Arc::new(FaultInjectingStorage::new(s, injector)))
+    }
+
     /// Takes storage object contents and its size and uploads to remote storage,
     /// mapping `from_path` to the corresponding remote object id in the storage.
     ///
@@ -362,6 +673,27 @@ pub struct S3Config {
     /// See [`DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT`] for more details.
     pub concurrency_limit: NonZeroUsize,
     pub max_keys_per_list_response: Option<i32>,
+    /// Have S3 encrypt the uploaded objects.
+    pub server_side_encryption: Option<S3ServerSideEncryption>,
+    /// Static credentials to access the bucket with. By default, the credentials are taken
+    /// from the environment variables, or else from the instance metadata.
+    pub credentials: Option<S3Credentials>,
+}
+
+/// An AWS access key.
+#[derive(Clone, PartialEq, Eq)]
+pub struct S3Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+/// A kind of S3 server-side encryption.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum S3ServerSideEncryption {
+    /// With keys managed by S3 (SSE-S3).
+    S3Managed,
+    /// With a KMS key (SSE-KMS). The AWS managed key of the account, if no key id is given.
+    Kms { key_id: Option<String> },
 }
 
 impl Debug for S3Config {
@@ -375,6 +707,12 @@ impl Debug for S3Config {
                 "max_keys_per_list_response",
                 &self.max_keys_per_list_response,
             )
+            .field("server_side_encryption", &self.server_side_encryption)
+            // The secret key is never logged
+            .field(
+                "access_key_id",
+                &self.credentials.as_ref().map(|c| &c.access_key_id),
+            )
             .finish()
     }
 }
@@ -430,6 +768,8 @@ impl RemoteStorageConfig {
                     .transpose()?,
                 concurrency_limit,
                 max_keys_per_list_response,
+                server_side_encryption: None,
+                credentials: parse_s3_credentials(toml)?,
             }),
             (Some(local_path), None, None) => RemoteStorageKind::LocalFs(PathBuf::from(
                 parse_toml_string("local_path", local_path)?,
@@ -463,6 +803,17 @@ where
         .with_context(|| format!("configure option {name} is too large"))
 }
 
+fn parse_s3_credentials(toml: &toml_edit::Item) -> anyhow::Result<Option<S3Credentials>> {
+    match (toml.get("access_key_id"), toml.get("secret_access_key")) {
+        (None, None) => Ok(None),
+        (Some(access_key_id), Some(secret_access_key)) => Ok(Some(S3Credentials {
+            access_key_id: parse_toml_string("access_key_id", access_key_id)?,
+            secret_access_key: parse_toml_string("secret_access_key", secret_access_key)?,
+        })),
+        _ => bail!("'access_key_id' and 'secret_access_key' options must be given together"),
+    }
+}
+
 fn parse_toml_string(name: &str, item: &Item) -> anyhow::Result<String> {
     let s = item
         .as_str()
@@ -8,24 +8,39 @@ use std::{
     borrow::Cow,
     future::Future,
     io::ErrorKind,
+    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
     pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
 };
 
 use anyhow::{bail, ensure, Context};
+use once_cell::sync::Lazy;
 use tokio::{
     fs,
     io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::Mutex,
 };
 use tracing::*;
 use utils::{crashsafe::path_with_suffix_extension, fs_ext::is_directory_empty};
 
-use crate::{Download, DownloadError, RemotePath};
+use crate::{
+    Download, DownloadError, ObjectHead, RemoteObject, RemotePath, UploadError,
+    UploadPrecondition, UploadedPart,
+};
 
 use super::{RemoteStorage, StorageMetadata};
 
 const LOCAL_FS_TEMP_FILE_SUFFIX: &str = "___temp";
 
+/// Makes the check and the upload of [`LocalFs::upload_conditional`] atomic. Only within the
+/// process: the storage isn't shared by several processes, but in the tests.
+static CONDITIONAL_UPLOAD_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// Makes the ids of the multipart uploads started within the same nanosecond unique.
+static MULTIPART_UPLOAD_COUNTER: AtomicU64 = AtomicU64::new(0);
+
 #[derive(Debug, Clone)]
 pub struct LocalFs {
     storage_root: PathBuf,
@@ -83,6 +98,20 @@ impl LocalFs {
         }
     }
 
+    /// The temporary directory of the parts of a multipart upload.
+    fn multipart_upload_dir(&self, to: &RemotePath, upload_id: &str) -> anyhow::Result<PathBuf> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-';
+        ensure!(
+            !upload_id.is_empty() && upload_id.chars().all(valid),
+            "Invalid multipart upload id {upload_id:?}"
+        );
+        let target_file_path = to.with_base(&self.storage_root);
+        Ok(path_with_suffix_extension(
+            target_file_path,
+            &format!("{upload_id}.{LOCAL_FS_TEMP_FILE_SUFFIX}"),
+        ))
+    }
+
     #[cfg(test)]
     async fn list(&self) -> anyhow::Result<Vec<RemotePath>> {
         Ok(get_all_files(&self.storage_root, true)
@@ -148,8 +177,21 @@ impl RemoteStorage for LocalFs {
             Some(folder) => folder.with_base(&self.storage_root),
             None => self.storage_root.clone(),
         };
+        // Like S3, treat a folder that is not a directory as a prefix of the names of the
+        // files in its parent directory.
+        let (root, name_prefix) = if full_path.is_dir() {
+            (full_path, None)
+        } else {
+            match (full_path.parent(), full_path.file_name()) {
+                (Some(parent), Some(prefix)) if parent.is_dir() => (
+                    parent.to_path_buf(),
+                    Some(prefix.to_string_lossy().into_owned()),
+                ),
+                _ => return Ok(Vec::new()),
+            }
+        };
         let mut files = vec![];
-        let mut directory_queue = vec![full_path.clone()];
+        let mut directory_queue = vec![root.clone()];
 
         while !directory_queue.is_empty() {
             let cur_folder = directory_queue
@@ -158,6 +200,13 @@ impl RemoteStorage for LocalFs {
             let mut entries = fs::read_dir(cur_folder.clone()).await?;
             while let Some(entry) = entries.next_entry().await? {
                 let file_name: PathBuf = entry.file_name().into();
+                if let Some(prefix) = &name_prefix {
+                    if cur_folder == root
+                        && !file_name.to_string_lossy().starts_with(prefix.as_str())
+                    {
+                        continue;
+                    }
+                }
                 let full_file_name = cur_folder.clone().join(&file_name);
                 let file_remote_path = self.local_file_to_relative_path(full_file_name.clone());
                 files.push(file_remote_path.clone());
@@ -169,6 +218,58 @@ impl RemoteStorage for LocalFs {
         Ok(files)
     }
 
+    async fn list_objects(
+        &self,
+        folder: Option<&RemotePath>,
+    ) -> anyhow::Result<Vec<RemoteObject>> {
+        let mut objects = vec![];
+        for path in self.list_files(folder).await? {
+            let file_path = path.with_base(&self.storage_root);
+            // Skip the uploads in progress and the storage metadata of the files, which S3
+            // doesn't list either.
+            if matches!(
+                file_path.extension().and_then(|e| e.to_str()),
+                Some(LOCAL_FS_TEMP_FILE_SUFFIX | "metadata")
+            ) {
+                continue;
+            }
+            let metadata = match fs::metadata(&file_path).await {
+                Ok(metadata) => metadata,
+                // Deleted since it was listed
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to stat {file_path:?}"))
+                }
+            };
+            if metadata.is_dir() {
+                continue;
+            }
+            objects.push(RemoteObject {
+                path,
+                size: metadata.len(),
+                last_modified: metadata.modified().ok(),
+            });
+        }
+        Ok(objects)
+    }
+
+    async fn head_object(&self, path: &RemotePath) -> Result<ObjectHead, DownloadError> {
+        let file_path = path.with_base(&self.storage_root);
+        if !file_exists(&file_path).map_err(DownloadError::BadInput)? {
+            return Err(DownloadError::NotFound);
+        }
+        let metadata = fs::metadata(&file_path)
+            .await
+            .with_context(|| format!("Failed to stat {file_path:?}"))
+            .map_err(DownloadError::Other)?;
+        Ok(ObjectHead {
+            size: metadata.len(),
+            last_modified: metadata.modified().ok(),
+            crc32c: None,
+            etag: Some(file_etag(&metadata)),
+        })
+    }
+
     async fn upload(
         &self,
         data: impl io::AsyncRead + Unpin + Send + Sync + 'static,
@@ -264,19 +365,48 @@ impl RemoteStorage for LocalFs {
         Ok(())
     }
 
+    async fn upload_conditional(
+        &self,
+        data: impl io::AsyncRead + Unpin + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        precondition: Option<&UploadPrecondition>,
+    ) -> Result<Option<String>, UploadError> {
+        let _guard = CONDITIONAL_UPLOAD_LOCK.lock().await;
+        let target_file_path = to.with_base(&self.storage_root);
+        if let Some(precondition) = precondition {
+            let current_etag = match fs::metadata(&target_file_path).await {
+                Ok(metadata) => Some(file_etag(&metadata)),
+                Err(e) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => {
+                    let e = anyhow::Error::new(e).context(format!(
+                        "Failed to stat {target_file_path:?} for a conditional upload"
+                    ));
+                    return Err(UploadError::Other(e));
+                }
+            };
+            let matches = match precondition {
+                UploadPrecondition::IfNoneMatch => current_etag.is_none(),
+                UploadPrecondition::IfMatch(etag) => current_etag.as_ref() == Some(etag),
+            };
+            if !matches {
+                return Err(UploadError::PreconditionFailed);
+            }
+        }
+
+        self.upload(data, data_size_bytes, to, None).await?;
+        let metadata = fs::metadata(&target_file_path)
+            .await
+            .with_context(|| format!("Failed to stat uploaded file {target_file_path:?}"))?;
+        Ok(Some(file_etag(&metadata)))
+    }
+
     async fn download(&self, from: &RemotePath) -> Result<Download, DownloadError> {
         let target_path = from.with_base(&self.storage_root);
         if file_exists(&target_path).map_err(DownloadError::BadInput)? {
-            let source = io::BufReader::new(
-                fs::OpenOptions::new()
-                    .read(true)
-                    .open(&target_path)
-                    .await
-                    .with_context(|| {
-                        format!("Failed to open source file {target_path:?} to use in the download")
-                    })
-                    .map_err(DownloadError::Other)?,
-            );
+            let (source, etag) = open_for_download(&target_path)
+                .await
+                .map_err(DownloadError::Other)?;
 
             let metadata = self
                 .read_storage_metadata(&target_path)
@@ -285,6 +415,7 @@ impl RemoteStorage for LocalFs {
             Ok(Download {
                 metadata,
                 download_stream: Box::pin(source),
+                etag: Some(etag),
             })
         } else {
             Err(DownloadError::NotFound)
@@ -307,16 +438,9 @@ impl RemoteStorage for LocalFs {
         }
         let target_path = from.with_base(&self.storage_root);
         if file_exists(&target_path).map_err(DownloadError::BadInput)? {
-            let mut source = io::BufReader::new(
-                fs::OpenOptions::new()
-                    .read(true)
-                    .open(&target_path)
-                    .await
-                    .with_context(|| {
-                        format!("Failed to open source file {target_path:?} to use in the download")
-                    })
-                    .map_err(DownloadError::Other)?,
-            );
+            let (mut source, etag) = open_for_download(&target_path)
+                .await
+                .map_err(DownloadError::Other)?;
             source
                 .seek(io::SeekFrom::Start(start_inclusive))
                 .await
@@ -331,10 +455,12 @@ impl RemoteStorage for LocalFs {
                 Some(end_exclusive) => Download {
                     metadata,
                     download_stream: Box::pin(source.take(end_exclusive - start_inclusive)),
+                    etag: Some(etag),
                 },
                 None => Download {
                     metadata,
                     download_stream: Box::pin(source),
+                    etag: Some(etag),
                 },
             })
         } else {
@@ -360,6 +486,219 @@ impl RemoteStorage for LocalFs {
         }
         Ok(())
     }
+
+    async fn copy(&self, from: &RemotePath, to: &RemotePath) -> anyhow::Result<()> {
+        let source_path = from.with_base(&self.storage_root);
+        let target_path = to.with_base(&self.storage_root);
+        create_target_directory(&target_path).await?;
+        // Through a temp file, as the uploads, not to leave a partial copy behind
+        let temp_path = path_with_suffix_extension(&target_path, LOCAL_FS_TEMP_FILE_SUFFIX);
+        fs::copy(&source_path, &temp_path).await.with_context(|| {
+            format!(
+                "Failed to copy '{}' to '{}'",
+                source_path.display(),
+                temp_path.display()
+            )
+        })?;
+        fs::rename(&temp_path, &target_path)
+            .await
+            .with_context(|| format!("Failed to rename to '{}'", target_path.display()))?;
+
+        let source_metadata_path = storage_metadata_path(&source_path);
+        if source_metadata_path.exists() {
+            fs::copy(&source_metadata_path, storage_metadata_path(&target_path))
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to copy storage metadata '{}'",
+                        source_metadata_path.display()
+                    )
+                })?;
+        }
+        Ok(())
+    }
+
+    // The parts are kept in a temporary directory next to the file until the upload completes,
+    // which neither the listings of objects nor the downloads see, as S3 does.
+    async fn create_multipart_upload(
+        &self,
+        to: &RemotePath,
+        metadata: Option<StorageMetadata>,
+    ) -> anyhow::Result<String> {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let counter = MULTIPART_UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let upload_id = format!("{nanos:x}-{counter:x}");
+
+        let parts_dir = self.multipart_upload_dir(to, &upload_id)?;
+        fs::create_dir_all(&parts_dir)
+            .await
+            .with_context(|| format!("Failed to create multipart upload dir {parts_dir:?}"))?;
+        if let Some(storage_metadata) = metadata {
+            let storage_metadata_path = storage_metadata_path(&parts_dir.join("upload"));
+            fs::write(
+                &storage_metadata_path,
+                serde_json::to_string(&storage_metadata.0)
+                    .context("Failed to serialize storage metadata as json")?,
+            )
+            .await
+            .with_context(|| format!("Failed to write metadata to {storage_metadata_path:?}"))?;
+        }
+        Ok(upload_id)
+    }
+
+    async fn upload_part(
+        &self,
+        data: impl io::AsyncRead + Unpin + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        upload_id: &str,
+        part_number: u32,
+    ) -> anyhow::Result<String> {
+        ensure!(part_number > 0, "Part numbers start at 1");
+        let parts_dir = self.multipart_upload_dir(to, upload_id)?;
+        ensure!(
+            parts_dir.is_dir(),
+            "No multipart upload {upload_id} to '{}'",
+            to.0.display()
+        );
+        let part_path = parts_dir.join(format!("{part_number}.{LOCAL_FS_TEMP_FILE_SUFFIX}"));
+        // Written next to the part it replaces, then renamed, for the etag to change
+        let temp_part_path =
+            parts_dir.join(format!("{part_number}.upload.{LOCAL_FS_TEMP_FILE_SUFFIX}"));
+        let mut destination = io::BufWriter::new(
+            fs::File::create(&temp_part_path)
+                .await
+                .with_context(|| format!("Failed to create part file {temp_part_path:?}"))?,
+        );
+
+        let from_size_bytes = data_size_bytes as u64;
+        let mut buffer_to_read = data.take(from_size_bytes);
+        let bytes_read = io::copy(&mut buffer_to_read, &mut destination)
+            .await
+            .with_context(|| format!("Failed to write part file {temp_part_path:?}"))?;
+        if bytes_read < from_size_bytes {
+            bail!("Provided stream was shorter than expected: {bytes_read} vs {from_size_bytes} bytes");
+        }
+        let extra_read = buffer_to_read.into_inner().read(&mut [1]).await?;
+        ensure!(
+            extra_read == 0,
+            "Provided stream was larger than expected: expected {from_size_bytes} bytes",
+        );
+        destination
+            .flush()
+            .await
+            .with_context(|| format!("Failed to flush part file {temp_part_path:?}"))?;
+        fs::rename(&temp_part_path, &part_path)
+            .await
+            .with_context(|| format!("Failed to rename part file to {part_path:?}"))?;
+
+        let metadata = fs::metadata(&part_path)
+            .await
+            .with_context(|| format!("Failed to stat part file {part_path:?}"))?;
+        Ok(file_etag(&metadata))
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        to: &RemotePath,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> anyhow::Result<()> {
+        let parts_dir = self.multipart_upload_dir(to, upload_id)?;
+        ensure!(
+            parts_dir.is_dir(),
+            "No multipart upload {upload_id} to '{}'",
+            to.0.display()
+        );
+        ensure!(
+            parts
+                .windows(2)
+                .all(|pair| pair[0].part_number < pair[1].part_number),
+            "The parts must be in ascending order of their numbers"
+        );
+
+        let target_file_path = to.with_base(&self.storage_root);
+        let temp_file_path =
+            path_with_suffix_extension(&target_file_path, LOCAL_FS_TEMP_FILE_SUFFIX);
+        let mut destination = io::BufWriter::new(
+            fs::File::create(&temp_file_path)
+                .await
+                .with_context(|| format!("Failed to create {temp_file_path:?}"))?,
+        );
+        for part in parts {
+            let part_path =
+                parts_dir.join(format!("{}.{LOCAL_FS_TEMP_FILE_SUFFIX}", part.part_number));
+            let (mut source, etag) = open_for_download(&part_path).await?;
+            ensure!(
+                etag == part.etag,
+                "Part {} of the multipart upload {upload_id} has been uploaded again since",
+                part.part_number
+            );
+            io::copy(&mut source, &mut destination)
+                .await
+                .with_context(|| format!("Failed to copy part file {part_path:?}"))?;
+        }
+        destination
+            .flush()
+            .await
+            .with_context(|| format!("Failed to flush {temp_file_path:?}"))?;
+        fs::rename(&temp_file_path, &target_file_path)
+            .await
+            .with_context(|| format!("Failed to rename to '{}'", target_file_path.display()))?;
+
+        let upload_metadata_path = storage_metadata_path(&parts_dir.join("upload"));
+        if upload_metadata_path.exists() {
+            fs::rename(&upload_metadata_path, storage_metadata_path(&target_file_path))
+                .await
+                .with_context(|| format!("Failed to move metadata {upload_metadata_path:?}"))?;
+        }
+        fs::remove_dir_all(&parts_dir)
+            .await
+            .with_context(|| format!("Failed to remove multipart upload dir {parts_dir:?}"))
+    }
+
+    async fn abort_multipart_upload(&self, to: &RemotePath, upload_id: &str) -> anyhow::Result<()> {
+        let parts_dir = self.multipart_upload_dir(to, upload_id)?;
+        match fs::remove_dir_all(&parts_dir).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(anyhow::Error::new(e)
+                .context(format!("Failed to remove multipart upload dir {parts_dir:?}"))),
+        }
+    }
+}
+
+/// Opens a file to download, with the entity tag of the version opened.
+async fn open_for_download(
+    target_path: &Path,
+) -> anyhow::Result<(io::BufReader<fs::File>, String)> {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .open(target_path)
+        .await
+        .with_context(|| {
+            format!("Failed to open source file {target_path:?} to use in the download")
+        })?;
+    let metadata = file
+        .metadata()
+        .await
+        .with_context(|| format!("Failed to stat source file {target_path:?}"))?;
+    Ok((io::BufReader::new(file), file_etag(&metadata)))
+}
+
+/// The entity tag of a file. Every upload renames a new file in its place, with another inode
+/// number than the file it replaces.
+fn file_etag(metadata: &std::fs::Metadata) -> String {
+    format!(
+        "\"{:x}-{}.{:09}-{:x}\"",
+        metadata.ino(),
+        metadata.mtime(),
+        metadata.mtime_nsec(),
+        metadata.len()
+    )
 }
 
 fn storage_metadata_path(original_path: &Path) -> PathBuf {
@@ -484,6 +823,115 @@ mod fs_tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn list_files_with_name_prefix() -> anyhow::Result<()> {
+        let storage = create_storage()?;
+        let index = upload_dummy_file(&storage, "index", None).await?;
+        let index_1 = upload_dummy_file(&storage, "index-1", None).await?;
+        upload_dummy_file(&storage, "layer", None).await?;
+
+        let prefix = RemotePath::new(Path::new("timelines/some_timeline/index"))?;
+        let mut files = storage.list_files(Some(&prefix)).await?;
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(files, vec![index, index_1]);
+
+        let missing = RemotePath::new(Path::new("timelines/other_timeline/index"))?;
+        assert!(storage.list_files(Some(&missing)).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_objects_with_sizes() -> anyhow::Result<()> {
+        let storage = create_storage()?;
+        let upload_1 = upload_dummy_file(&storage, "upload_1", None).await?;
+        let metadata = StorageMetadata(HashMap::from([("one".to_string(), "1".to_string())]));
+        let upload_2 = upload_dummy_file(&storage, "upload_2", Some(metadata)).await?;
+
+        let mut objects = storage.list_objects(None).await?;
+        objects.sort_by(|a, b| a.path.cmp(&b.path));
+        let paths: Vec<RemotePath> = objects.iter().map(|o| o.path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![upload_1, upload_2],
+            "Should list neither the directories nor the metadata files"
+        );
+        for object in objects {
+            let name = object.path.object_name().unwrap();
+            assert_eq!(object.size, dummy_contents(name).len() as u64);
+            assert!(object.last_modified.is_some());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn head_object() -> anyhow::Result<()> {
+        let storage = create_storage()?;
+        let upload = upload_dummy_file(&storage, "upload_1", None).await?;
+
+        let head = storage.head_object(&upload).await?;
+        assert_eq!(head.size, dummy_contents("upload_1").len() as u64);
+        assert!(head.last_modified.is_some());
+        assert_eq!(head.crc32c, None);
+
+        let missing = RemotePath::new(Path::new("timelines/some_timeline/missing"))?;
+        assert!(matches!(
+            storage.head_object(&missing).await,
+            Err(DownloadError::NotFound)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn conditional_upload() -> anyhow::Result<()> {
+        let storage = create_storage()?;
+        let path = RemotePath::new(Path::new("timelines/some_timeline/index"))?;
+        let upload = |contents: &'static str, precondition: Option<UploadPrecondition>| {
+            let storage = storage.clone();
+            let path = path.clone();
+            async move {
+                let from = io::BufReader::new(contents.as_bytes());
+                storage
+                    .upload_conditional(from, contents.len(), &path, precondition.as_ref())
+                    .await
+            }
+        };
+
+        let etag_1 = upload("first", Some(UploadPrecondition::IfNoneMatch))
+            .await?
+            .expect("local fs files have an etag");
+        assert!(matches!(
+            upload("second", Some(UploadPrecondition::IfNoneMatch)).await,
+            Err(UploadError::PreconditionFailed)
+        ));
+
+        let etag_2 = upload("second", Some(UploadPrecondition::IfMatch(etag_1.clone())))
+            .await?
+            .expect("local fs files have an etag");
+        assert_ne!(etag_1, etag_2);
+        assert_eq!(storage.download(&path).await?.etag, Some(etag_2.clone()));
+
+        // Written by someone else since
+        assert!(matches!(
+            upload("third", Some(UploadPrecondition::IfMatch(etag_1))).await,
+            Err(UploadError::PreconditionFailed)
+        ));
+        assert_eq!(
+            read_and_assert_remote_file_contents(&storage, &path, None).await?,
+            "second"
+        );
+
+        upload("third", None).await?;
+        assert_eq!(
+            read_and_assert_remote_file_contents(&storage, &path, None).await?,
+            "third"
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn upload_file_negatives() -> anyhow::Result<()> {
         let storage = create_storage()?;
@@ -714,6 +1162,90 @@ mod fs_tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn copy_file_with_metadata() -> anyhow::Result<()> {
+        let storage = create_storage()?;
+        let upload_name = "upload_1";
+        let metadata = StorageMetadata(HashMap::from([("one".to_string(), "1".to_string())]));
+        let upload_target =
+            upload_dummy_file(&storage, upload_name, Some(metadata.clone())).await?;
+
+        let copy_target = RemotePath::new(Path::new("cold/timelines/some_timeline/upload_1"))?;
+        storage.copy(&upload_target, &copy_target).await?;
+        let contents =
+            read_and_assert_remote_file_contents(&storage, &copy_target, Some(&metadata)).await?;
+        assert_eq!(dummy_contents(upload_name), contents);
+        // The source stays
+        read_and_assert_remote_file_contents(&storage, &upload_target, Some(&metadata)).await?;
+
+        let missing = RemotePath::new(Path::new("timelines/some_timeline/missing"))?;
+        assert!(storage.copy(&missing, &copy_target).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn multipart_upload() -> anyhow::Result<()> {
+        let storage = create_storage()?;
+        let target = RemotePath::new(Path::new("timelines/some_timeline/layer"))?;
+        let upload_part = |upload_id: String, part_number: u32, contents: &'static str| {
+            let (storage, target) = (storage.clone(), target.clone());
+            async move {
+                let size = contents.len();
+                let etag = storage
+                    .upload_part(contents.as_bytes(), size, &target, &upload_id, part_number)
+                    .await?;
+                anyhow::Ok(UploadedPart { part_number, etag })
+            }
+        };
+        let metadata = StorageMetadata(HashMap::from([("one".to_string(), "1".to_string())]));
+        let upload_id = storage
+            .create_multipart_upload(&target, Some(metadata.clone()))
+            .await?;
+        let second = upload_part(upload_id.clone(), 2, "second").await?;
+        let first = upload_part(upload_id.clone(), 1, "firts ").await?;
+
+        // The parts are neither listed nor downloadable until the upload completes
+        assert!(storage.list_objects(None).await?.is_empty());
+        assert!(matches!(
+            storage.download(&target).await,
+            Err(DownloadError::NotFound)
+        ));
+
+        // The parts go in ascending order
+        let parts = [second.clone(), first.clone()];
+        let completion = storage.complete_multipart_upload(&target, &upload_id, &parts);
+        assert!(completion.await.is_err());
+
+        // A part uploaded again replaces the earlier upload of it
+        let first_again = upload_part(upload_id.clone(), 1, "first ").await?;
+        let parts = [first, second.clone()];
+        let completion = storage.complete_multipart_upload(&target, &upload_id, &parts);
+        assert!(completion.await.is_err());
+        let parts = [first_again, second];
+        storage
+            .complete_multipart_upload(&target, &upload_id, &parts)
+            .await?;
+        let contents =
+            read_and_assert_remote_file_contents(&storage, &target, Some(&metadata)).await?;
+        assert_eq!(contents, "first second");
+        let files = list_files_sorted(&storage).await?;
+
+        // An aborted upload leaves nothing behind, and the file as it was
+        let upload_id = storage.create_multipart_upload(&target, None).await?;
+        upload_part(upload_id.clone(), 1, "aborted").await?;
+        storage.abort_multipart_upload(&target, &upload_id).await?;
+        assert!(upload_part(upload_id.clone(), 2, "aborted").await.is_err());
+        let completion = storage.complete_multipart_upload(&target, &upload_id, &[]);
+        assert!(completion.await.is_err());
+        let contents =
+            read_and_assert_remote_file_contents(&storage, &target, Some(&metadata)).await?;
+        assert_eq!(contents, "first second");
+        assert_eq!(list_files_sorted(&storage).await?, files);
+
+        Ok(())
+    }
+
     async fn upload_dummy_file(
         storage: &LocalFs,
         name: &str,
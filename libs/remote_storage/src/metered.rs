@@ -0,0 +1,293 @@
+//! This module provides a wrapper around a real RemoteStorage implementation that reports
+//! the requests made through it to a [`RequestObserver`], e.g. for the pageserver to account
+//! the requests of each tenant.
+//!
+//! The requests are counted the way the storage providers bill them: a listing or a batch
+//! deletion counts one request per started page of [`REQUEST_PAGE_SIZE`] keys, and the bytes
+//! of a download count as they are read from its stream.
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::io::{self, AsyncRead, ReadBuf};
+
+use crate::{
+    Download, DownloadError, ObjectHead, RemoteObject, RemotePath, RemoteStorage, StorageMetadata,
+    UploadError, UploadPrecondition, UploadedPart,
+};
+
+/// The keys of a page of a listing, and of a batch deletion, on S3.
+pub const REQUEST_PAGE_SIZE: u64 = 1000;
+
+/// The request classes of the storage providers, which price them differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+    Put,
+    /// Also the head requests, billed the same on S3.
+    Get,
+    Delete,
+    List,
+}
+
+/// Receiver of the requests made through a [`MeteredStorage`], successful or not.
+pub trait RequestObserver: Send + Sync {
+    /// `requests` of the given kind were made, or `bytes` of object data transferred by
+    /// earlier ones.
+    fn observe_requests(&self, kind: RequestKind, requests: u64, bytes: u64);
+}
+
+pub struct MeteredStorage {
+    inner: crate::GenericRemoteStorage,
+    observer: Arc<dyn RequestObserver>,
+}
+
+impl MeteredStorage {
+    pub fn new(inner: crate::GenericRemoteStorage, observer: Arc<dyn RequestObserver>) -> Self {
+        MeteredStorage { inner, observer }
+    }
+
+    fn observe(&self, kind: RequestKind, requests: u64, bytes: u64) {
+        self.observer.observe_requests(kind, requests, bytes);
+    }
+
+    fn count_download_bytes(&self, download: Download) -> Download {
+        Download {
+            download_stream: Box::pin(CountingRead {
+                inner: download.download_stream,
+                bytes: 0,
+                observer: Arc::clone(&self.observer),
+            }),
+            ..download
+        }
+    }
+}
+
+/// Pages of [`REQUEST_PAGE_SIZE`] keys that `keys` take, at least one.
+fn pages(keys: usize) -> u64 {
+    ((keys as u64).max(1) + REQUEST_PAGE_SIZE - 1) / REQUEST_PAGE_SIZE
+}
+
+/// An `AsyncRead` adapter which reports the bytes read through it when dropped.
+struct CountingRead {
+    inner: Pin<Box<dyn AsyncRead + Unpin + Send + Sync>>,
+    bytes: u64,
+    observer: Arc<dyn RequestObserver>,
+}
+
+impl AsyncRead for CountingRead {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled_before = buf.filled().len();
+        let poll = this.inner.as_mut().poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            this.bytes += (buf.filled().len() - filled_before) as u64;
+        }
+        poll
+    }
+}
+
+impl Drop for CountingRead {
+    fn drop(&mut self) {
+        if self.bytes > 0 {
+            self.observer
+                .observe_requests(RequestKind::Get, 0, self.bytes);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RemoteStorage for MeteredStorage {
+    async fn list_prefixes(
+        &self,
+        prefix: Option<&RemotePath>,
+    ) -> Result<Vec<RemotePath>, DownloadError> {
+        let prefixes = self.inner.list_prefixes(prefix).await;
+        let keys = prefixes.as_ref().map_or(0, |prefixes| prefixes.len());
+        self.observe(RequestKind::List, pages(keys), 0);
+        prefixes
+    }
+
+    async fn list_files(&self, folder: Option<&RemotePath>) -> anyhow::Result<Vec<RemotePath>> {
+        let files = self.inner.list_files(folder).await;
+        let keys = files.as_ref().map_or(0, |files| files.len());
+        self.observe(RequestKind::List, pages(keys), 0);
+        files
+    }
+
+    async fn list_objects(
+        &self,
+        folder: Option<&RemotePath>,
+    ) -> anyhow::Result<Vec<RemoteObject>> {
+        let objects = self.inner.list_objects(folder).await;
+        let keys = objects.as_ref().map_or(0, |objects| objects.len());
+        self.observe(RequestKind::List, pages(keys), 0);
+        objects
+    }
+
+    async fn head_object(&self, path: &RemotePath) -> Result<ObjectHead, DownloadError> {
+        self.observe(RequestKind::Get, 1, 0);
+        self.inner.head_object(path).await
+    }
+
+    async fn upload(
+        &self,
+        data: impl tokio::io::AsyncRead + Unpin + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        metadata: Option<StorageMetadata>,
+    ) -> anyhow::Result<()> {
+        self.observe(RequestKind::Put, 1, data_size_bytes as u64);
+        self.inner.upload(data, data_size_bytes, to, metadata).await
+    }
+
+    async fn upload_conditional(
+        &self,
+        data: impl tokio::io::AsyncRead + Unpin + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        precondition: Option<&UploadPrecondition>,
+    ) -> Result<Option<String>, UploadError> {
+        self.observe(RequestKind::Put, 1, data_size_bytes as u64);
+        self.inner
+            .upload_conditional(data, data_size_bytes, to, precondition)
+            .await
+    }
+
+    async fn download(&self, from: &RemotePath) -> Result<Download, DownloadError> {
+        self.observe(RequestKind::Get, 1, 0);
+        let download = self.inner.download(from).await;
+        download.map(|download| self.count_download_bytes(download))
+    }
+
+    async fn download_byte_range(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+    ) -> Result<Download, DownloadError> {
+        self.observe(RequestKind::Get, 1, 0);
+        let download = self
+            .inner
+            .download_byte_range(from, start_inclusive, end_exclusive)
+            .await;
+        download.map(|download| self.count_download_bytes(download))
+    }
+
+    async fn delete(&self, path: &RemotePath) -> anyhow::Result<()> {
+        self.observe(RequestKind::Delete, 1, 0);
+        self.inner.delete(path).await
+    }
+
+    async fn delete_objects<'a>(&self, paths: &'a [RemotePath]) -> anyhow::Result<()> {
+        self.observe(RequestKind::Delete, pages(paths.len()), 0);
+        self.inner.delete_objects(paths).await
+    }
+
+    async fn copy(&self, from: &RemotePath, to: &RemotePath) -> anyhow::Result<()> {
+        // Billed as a PUT, the bytes don't leave the storage
+        self.observe(RequestKind::Put, 1, 0);
+        self.inner.copy(from, to).await
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        to: &RemotePath,
+        metadata: Option<StorageMetadata>,
+    ) -> anyhow::Result<String> {
+        self.observe(RequestKind::Put, 1, 0);
+        self.inner.create_multipart_upload(to, metadata).await
+    }
+
+    async fn upload_part(
+        &self,
+        data: impl tokio::io::AsyncRead + Unpin + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        upload_id: &str,
+        part_number: u32,
+    ) -> anyhow::Result<String> {
+        self.observe(RequestKind::Put, 1, data_size_bytes as u64);
+        self.inner
+            .upload_part(data, data_size_bytes, to, upload_id, part_number)
+            .await
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        to: &RemotePath,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> anyhow::Result<()> {
+        self.observe(RequestKind::Put, 1, 0);
+        self.inner
+            .complete_multipart_upload(to, upload_id, parts)
+            .await
+    }
+
+    async fn abort_multipart_upload(&self, to: &RemotePath, upload_id: &str) -> anyhow::Result<()> {
+        // Free on S3, as the deletions
+        self.observe(RequestKind::Delete, 1, 0);
+        self.inner.abort_multipart_upload(to, upload_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{collections::HashMap, path::Path, sync::Mutex};
+    use tokio::io::AsyncReadExt;
+
+    /// Requests and bytes by kind.
+    #[derive(Default)]
+    struct Counts(Mutex<HashMap<RequestKind, (u64, u64)>>);
+
+    impl RequestObserver for Counts {
+        fn observe_requests(&self, kind: RequestKind, requests: u64, bytes: u64) {
+            let mut counts = self.0.lock().unwrap();
+            let counts = counts.entry(kind).or_default();
+            counts.0 += requests;
+            counts.1 += bytes;
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_requests() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let counts = Arc::new(Counts::default());
+        let storage = crate::GenericRemoteStorage::metered(
+            crate::GenericRemoteStorage::LocalFs(crate::LocalFs::new(dir.path().to_path_buf())?),
+            counts.clone(),
+        );
+        let path = RemotePath::new(Path::new("timelines/some_timeline/layer"))?;
+        let contents = "contents";
+
+        let from = io::BufReader::new(contents.as_bytes());
+        storage.upload(from, contents.len(), &path, None).await?;
+        let mut download = storage.download(&path).await?;
+        let mut downloaded = String::new();
+        download
+            .download_stream
+            .read_to_string(&mut downloaded)
+            .await?;
+        drop(download);
+        // Failed requests count too
+        let missing = RemotePath::new(Path::new("timelines/some_timeline/missing"))?;
+        assert!(storage.download(&missing).await.is_err());
+        storage.list_files(None).await?;
+        storage.delete_objects(&[path]).await?;
+
+        let counts = counts.0.lock().unwrap();
+        let len = contents.len() as u64;
+        assert_eq!(counts.get(&RequestKind::Put), Some(&(1, len)));
+        assert_eq!(counts.get(&RequestKind::Get), Some(&(2, len)));
+        assert_eq!(counts.get(&RequestKind::List), Some(&(1, 0)));
+        assert_eq!(counts.get(&RequestKind::Delete), Some(&(1, 0)));
+
+        Ok(())
+    }
+}
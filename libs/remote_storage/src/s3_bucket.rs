@@ -5,23 +5,30 @@
 //! their bucket prefixes are both specified and different.
 
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use anyhow::Context;
 use aws_config::{
     environment::credentials::EnvironmentVariableCredentialsProvider,
     imds::credentials::ImdsCredentialsProvider, meta::credentials::CredentialsProviderChain,
 };
-use aws_credential_types::cache::CredentialsCache;
+use aws_credential_types::{cache::CredentialsCache, Credentials};
 use aws_sdk_s3::{
     config::{Config, Region},
     error::SdkError,
-    operation::get_object::GetObjectError,
-    primitives::ByteStream,
-    types::{Delete, ObjectIdentifier},
+    operation::{
+        get_object::GetObjectError, head_object::HeadObjectError,
+        put_object::builders::PutObjectFluentBuilder,
+    },
+    primitives::{ByteStream, DateTime},
+    types::{
+        ChecksumMode, CompletedMultipartUpload, CompletedPart, Delete, ObjectIdentifier,
+        ServerSideEncryption,
+    },
     Client,
 };
 use aws_smithy_http::body::SdkBody;
-use hyper::Body;
+use hyper::{header::HeaderValue, Body, StatusCode};
 use tokio::{
     io::{self, AsyncRead},
     sync::Semaphore,
@@ -31,7 +38,9 @@ use tracing::debug;
 
 use super::StorageMetadata;
 use crate::{
-    Download, DownloadError, RemotePath, RemoteStorage, S3Config, REMOTE_STORAGE_PREFIX_SEPARATOR,
+    Download, DownloadError, ObjectHead, RemoteObject, RemotePath, RemoteStorage, S3Config,
+    S3Credentials, S3ServerSideEncryption, UploadError, UploadPrecondition, UploadedPart,
+    REMOTE_STORAGE_PREFIX_SEPARATOR,
 };
 
 const MAX_DELETE_OBJECTS_REQUEST_SIZE: usize = 1000;
@@ -68,6 +77,16 @@ pub(super) mod metrics {
             .inc();
     }
 
+    pub fn inc_head_object() {
+        S3_REQUESTS_COUNT.with_label_values(&["head_object"]).inc();
+    }
+
+    pub fn inc_head_object_fail() {
+        S3_REQUESTS_FAIL_COUNT
+            .with_label_values(&["head_object"])
+            .inc();
+    }
+
     pub fn inc_put_object() {
         S3_REQUESTS_COUNT.with_label_values(&["put_object"]).inc();
     }
@@ -78,6 +97,27 @@ pub(super) mod metrics {
             .inc();
     }
 
+    pub fn inc_copy_object() {
+        S3_REQUESTS_COUNT.with_label_values(&["copy_object"]).inc();
+    }
+
+    pub fn inc_copy_object_fail() {
+        S3_REQUESTS_FAIL_COUNT
+            .with_label_values(&["copy_object"])
+            .inc();
+    }
+
+    /// A request of a multipart upload, `request_type` being the name of its S3 operation.
+    pub fn inc_multipart_upload(request_type: &str) {
+        S3_REQUESTS_COUNT.with_label_values(&[request_type]).inc();
+    }
+
+    pub fn inc_multipart_upload_fail(request_type: &str) {
+        S3_REQUESTS_FAIL_COUNT
+            .with_label_values(&[request_type])
+            .inc();
+    }
+
     pub fn inc_delete_object() {
         S3_REQUESTS_COUNT
             .with_label_values(&["delete_object"])
@@ -119,6 +159,7 @@ pub struct S3Bucket {
     bucket_name: String,
     prefix_in_bucket: Option<String>,
     max_keys_per_list_response: Option<i32>,
+    server_side_encryption: Option<S3ServerSideEncryption>,
     // Every request to S3 can be throttled or cancelled, if a certain number of requests per second is exceeded.
     // Same goes to IAM, which is queried before every S3 request, if enabled. IAM has even lower RPS threshold.
     // The helps to ensure we don't exceed the thresholds.
@@ -139,20 +180,31 @@ impl S3Bucket {
             aws_config.bucket_name
         );
 
-        let credentials_provider = {
-            // uses "AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY"
-            CredentialsProviderChain::first_try(
-                "env",
-                EnvironmentVariableCredentialsProvider::new(),
-            )
-            // uses imds v2
-            .or_else("imds", ImdsCredentialsProvider::builder().build())
-        };
-
         let mut config_builder = Config::builder()
             .region(Region::new(aws_config.bucket_region.clone()))
-            .credentials_cache(CredentialsCache::lazy())
-            .credentials_provider(credentials_provider);
+            .credentials_cache(CredentialsCache::lazy());
+
+        config_builder = match &aws_config.credentials {
+            Some(S3Credentials {
+                access_key_id,
+                secret_access_key,
+            }) => config_builder.credentials_provider(Credentials::new(
+                access_key_id,
+                secret_access_key,
+                None,
+                None,
+                "remote storage config",
+            )),
+            None => config_builder.credentials_provider(
+                // uses "AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY"
+                CredentialsProviderChain::first_try(
+                    "env",
+                    EnvironmentVariableCredentialsProvider::new(),
+                )
+                // uses imds v2
+                .or_else("imds", ImdsCredentialsProvider::builder().build()),
+            ),
+        };
 
         if let Some(custom_endpoint) = aws_config.endpoint.clone() {
             config_builder = config_builder
@@ -177,6 +229,7 @@ impl S3Bucket {
             client,
             bucket_name: aws_config.bucket_name.clone(),
             max_keys_per_list_response: aws_config.max_keys_per_list_response,
+            server_side_encryption: aws_config.server_side_encryption.clone(),
             prefix_in_bucket,
             concurrency_limiter: Arc::new(Semaphore::new(aws_config.concurrency_limit.get())),
         })
@@ -209,6 +262,34 @@ impl S3Bucket {
         full_path
     }
 
+    /// A PutObject request of the given contents, encrypted after the config.
+    fn put_object_request(
+        &self,
+        from: impl io::AsyncRead + Unpin + Send + Sync + 'static,
+        from_size_bytes: usize,
+        to: &RemotePath,
+    ) -> anyhow::Result<PutObjectFluentBuilder> {
+        let body = Body::wrap_stream(ReaderStream::new(from));
+        let bytes_stream = ByteStream::new(SdkBody::from(body));
+
+        let request = self
+            .client
+            .put_object()
+            .bucket(self.bucket_name.clone())
+            .key(self.relative_path_to_s3_object(to))
+            .content_length(from_size_bytes.try_into()?)
+            .body(bytes_stream);
+        Ok(match &self.server_side_encryption {
+            None => request,
+            Some(S3ServerSideEncryption::S3Managed) => {
+                request.server_side_encryption(ServerSideEncryption::Aes256)
+            }
+            Some(S3ServerSideEncryption::Kms { key_id }) => request
+                .server_side_encryption(ServerSideEncryption::AwsKms)
+                .set_ssekms_key_id(key_id.clone()),
+        })
+    }
+
     async fn download_object(&self, request: GetObjectRequest) -> Result<Download, DownloadError> {
         let permit = self
             .concurrency_limiter
@@ -232,8 +313,10 @@ impl S3Bucket {
         match get_object {
             Ok(object_output) => {
                 let metadata = object_output.metadata().cloned().map(StorageMetadata);
+                let etag = object_output.e_tag().map(str::to_owned);
                 Ok(Download {
                     metadata,
+                    etag,
                     download_stream: Box::pin(io::BufReader::new(RatelimitedAsyncRead::new(
                         permit,
                         object_output.body.into_async_read(),
@@ -253,6 +336,11 @@ impl S3Bucket {
     }
 }
 
+fn to_system_time(t: &DateTime) -> Option<SystemTime> {
+    let secs = u64::try_from(t.secs()).ok()?;
+    Some(SystemTime::UNIX_EPOCH + Duration::new(secs, t.subsec_nanos()))
+}
+
 pin_project_lite::pin_project! {
     /// An `AsyncRead` adapter which carries a permit for the lifetime of the value.
     struct RatelimitedAsyncRead<S> {
@@ -349,13 +437,22 @@ impl RemoteStorage for S3Bucket {
 
     /// See the doc for `RemoteStorage::list_files`
     async fn list_files(&self, folder: Option<&RemotePath>) -> anyhow::Result<Vec<RemotePath>> {
+        let objects = self.list_objects(folder).await?;
+        Ok(objects.into_iter().map(|object| object.path).collect())
+    }
+
+    /// See the doc for `RemoteStorage::list_objects`
+    async fn list_objects(
+        &self,
+        folder: Option<&RemotePath>,
+    ) -> anyhow::Result<Vec<RemoteObject>> {
         let folder_name = folder
             .map(|p| self.relative_path_to_s3_object(p))
             .or_else(|| self.prefix_in_bucket.clone());
 
         // AWS may need to break the response into several parts
         let mut continuation_token = None;
-        let mut all_files = vec![];
+        let mut all_objects = vec![];
         loop {
             let _guard = self
                 .concurrency_limiter
@@ -381,15 +478,64 @@ impl RemoteStorage for S3Bucket {
 
             for object in response.contents().unwrap_or_default() {
                 let object_path = object.key().expect("response does not contain a key");
-                let remote_path = self.s3_object_to_relative_path(object_path);
-                all_files.push(remote_path);
+                all_objects.push(RemoteObject {
+                    path: self.s3_object_to_relative_path(object_path),
+                    size: u64::try_from(object.size()).unwrap_or_default(),
+                    last_modified: object.last_modified().and_then(to_system_time),
+                });
             }
             match response.next_continuation_token {
                 Some(new_token) => continuation_token = Some(new_token),
                 None => break,
             }
         }
-        Ok(all_files)
+        Ok(all_objects)
+    }
+
+    async fn head_object(&self, path: &RemotePath) -> Result<ObjectHead, DownloadError> {
+        let _guard = self
+            .concurrency_limiter
+            .acquire()
+            .await
+            .context("Concurrency limiter semaphore got closed during S3 head")
+            .map_err(DownloadError::Other)?;
+
+        metrics::inc_head_object();
+
+        let head_object = self
+            .client
+            .head_object()
+            .bucket(self.bucket_name.clone())
+            .key(self.relative_path_to_s3_object(path))
+            .checksum_mode(ChecksumMode::Enabled)
+            .send()
+            .await;
+
+        match head_object {
+            Ok(output) => {
+                // Base64 of the big-endian checksum
+                let crc32c = output
+                    .checksum_crc32_c()
+                    .and_then(|checksum| base64::decode(checksum).ok())
+                    .and_then(|bytes| <[u8; 4]>::try_from(bytes.as_slice()).ok())
+                    .map(u32::from_be_bytes);
+                Ok(ObjectHead {
+                    size: u64::try_from(output.content_length()).unwrap_or_default(),
+                    last_modified: output.last_modified().and_then(to_system_time),
+                    crc32c,
+                    etag: output.e_tag().map(str::to_owned),
+                })
+            }
+            Err(SdkError::ServiceError(e)) if matches!(e.err(), HeadObjectError::NotFound(_)) => {
+                Err(DownloadError::NotFound)
+            }
+            Err(e) => {
+                metrics::inc_head_object_fail();
+                Err(DownloadError::Other(anyhow::anyhow!(
+                    "Failed to head S3 object: {e}"
+                )))
+            }
+        }
     }
 
     async fn upload(
@@ -407,16 +553,8 @@ impl RemoteStorage for S3Bucket {
 
         metrics::inc_put_object();
 
-        let body = Body::wrap_stream(ReaderStream::new(from));
-        let bytes_stream = ByteStream::new(SdkBody::from(body));
-
-        self.client
-            .put_object()
-            .bucket(self.bucket_name.clone())
-            .key(self.relative_path_to_s3_object(to))
+        self.put_object_request(from, from_size_bytes, to)?
             .set_metadata(metadata.map(|m| m.0))
-            .content_length(from_size_bytes.try_into()?)
-            .body(bytes_stream)
             .send()
             .await
             .map_err(|e| {
@@ -426,6 +564,56 @@ impl RemoteStorage for S3Bucket {
         Ok(())
     }
 
+    async fn upload_conditional(
+        &self,
+        from: impl io::AsyncRead + Unpin + Send + Sync + 'static,
+        from_size_bytes: usize,
+        to: &RemotePath,
+        precondition: Option<&UploadPrecondition>,
+    ) -> Result<Option<String>, UploadError> {
+        let _guard = self
+            .concurrency_limiter
+            .acquire()
+            .await
+            .context("Concurrency limiter semaphore got closed during S3 upload")?;
+
+        metrics::inc_put_object();
+
+        // The SDK doesn't have the conditional write headers of PutObject yet
+        let header = match precondition {
+            None => None,
+            Some(UploadPrecondition::IfNoneMatch) => Some(("If-None-Match", "*")),
+            Some(UploadPrecondition::IfMatch(etag)) => Some(("If-Match", etag.as_str())),
+        };
+        let mut request = self
+            .put_object_request(from, from_size_bytes, to)?
+            .customize()
+            .await
+            .context("Failed to customize an S3 upload")?;
+        if let Some((name, value)) = header {
+            let value = HeaderValue::from_str(value).context("Invalid S3 upload precondition")?;
+            request.request_mut().headers_mut().insert(name, value);
+        }
+        let put_object = request.send().await;
+
+        match put_object {
+            Ok(output) => Ok(output.e_tag().map(str::to_owned)),
+            // 409 for a concurrent conditional write to the same key
+            Err(SdkError::ServiceError(e))
+                if e.raw().http().status() == StatusCode::PRECONDITION_FAILED
+                    || e.raw().http().status() == StatusCode::CONFLICT =>
+            {
+                Err(UploadError::PreconditionFailed)
+            }
+            Err(e) => {
+                metrics::inc_put_object_fail();
+                Err(UploadError::Other(anyhow::anyhow!(
+                    "Failed to upload S3 object: {e}"
+                )))
+            }
+        }
+    }
+
     async fn download(&self, from: &RemotePath) -> Result<Download, DownloadError> {
         self.download_object(GetObjectRequest {
             bucket: self.bucket_name.clone(),
@@ -522,4 +710,183 @@ impl RemoteStorage for S3Bucket {
             })?;
         Ok(())
     }
+
+    async fn copy(&self, from: &RemotePath, to: &RemotePath) -> anyhow::Result<()> {
+        let _guard = self
+            .concurrency_limiter
+            .acquire()
+            .await
+            .context("Concurrency limiter semaphore got closed during S3 copy")?;
+
+        metrics::inc_copy_object();
+
+        // The keys are made of URL-safe characters, the copy source needs no encoding
+        let copy_source = format!(
+            "{}/{}",
+            self.bucket_name,
+            self.relative_path_to_s3_object(from)
+        );
+        let request = self
+            .client
+            .copy_object()
+            .bucket(self.bucket_name.clone())
+            .key(self.relative_path_to_s3_object(to))
+            .copy_source(copy_source);
+        // The copy doesn't inherit the encryption of the source
+        let request = match &self.server_side_encryption {
+            None => request,
+            Some(S3ServerSideEncryption::S3Managed) => {
+                request.server_side_encryption(ServerSideEncryption::Aes256)
+            }
+            Some(S3ServerSideEncryption::Kms { key_id }) => request
+                .server_side_encryption(ServerSideEncryption::AwsKms)
+                .set_ssekms_key_id(key_id.clone()),
+        };
+        request.send().await.map_err(|e| {
+            metrics::inc_copy_object_fail();
+            e
+        })?;
+        Ok(())
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        to: &RemotePath,
+        metadata: Option<StorageMetadata>,
+    ) -> anyhow::Result<String> {
+        let _guard = self
+            .concurrency_limiter
+            .acquire()
+            .await
+            .context("Concurrency limiter semaphore got closed during S3 upload")?;
+
+        metrics::inc_multipart_upload("create_multipart_upload");
+
+        let request = self
+            .client
+            .create_multipart_upload()
+            .bucket(self.bucket_name.clone())
+            .key(self.relative_path_to_s3_object(to))
+            .set_metadata(metadata.map(|m| m.0));
+        // The parts are encrypted as the upload says
+        let request = match &self.server_side_encryption {
+            None => request,
+            Some(S3ServerSideEncryption::S3Managed) => {
+                request.server_side_encryption(ServerSideEncryption::Aes256)
+            }
+            Some(S3ServerSideEncryption::Kms { key_id }) => request
+                .server_side_encryption(ServerSideEncryption::AwsKms)
+                .set_ssekms_key_id(key_id.clone()),
+        };
+        let output = request.send().await.map_err(|e| {
+            metrics::inc_multipart_upload_fail("create_multipart_upload");
+            e
+        })?;
+        output
+            .upload_id()
+            .map(str::to_owned)
+            .context("S3 returned no multipart upload id")
+    }
+
+    async fn upload_part(
+        &self,
+        from: impl io::AsyncRead + Unpin + Send + Sync + 'static,
+        from_size_bytes: usize,
+        to: &RemotePath,
+        upload_id: &str,
+        part_number: u32,
+    ) -> anyhow::Result<String> {
+        let _guard = self
+            .concurrency_limiter
+            .acquire()
+            .await
+            .context("Concurrency limiter semaphore got closed during S3 upload")?;
+
+        metrics::inc_multipart_upload("upload_part");
+
+        let body = Body::wrap_stream(ReaderStream::new(from));
+        let output = self
+            .client
+            .upload_part()
+            .bucket(self.bucket_name.clone())
+            .key(self.relative_path_to_s3_object(to))
+            .upload_id(upload_id)
+            .part_number(part_number.try_into()?)
+            .content_length(from_size_bytes.try_into()?)
+            .body(ByteStream::new(SdkBody::from(body)))
+            .send()
+            .await
+            .map_err(|e| {
+                metrics::inc_multipart_upload_fail("upload_part");
+                e
+            })?;
+        output
+            .e_tag()
+            .map(str::to_owned)
+            .context("S3 returned no entity tag for the uploaded part")
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        to: &RemotePath,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> anyhow::Result<()> {
+        let _guard = self
+            .concurrency_limiter
+            .acquire()
+            .await
+            .context("Concurrency limiter semaphore got closed during S3 upload")?;
+
+        metrics::inc_multipart_upload("complete_multipart_upload");
+
+        let mut completed_parts = Vec::with_capacity(parts.len());
+        for part in parts {
+            completed_parts.push(
+                CompletedPart::builder()
+                    .part_number(part.part_number.try_into()?)
+                    .e_tag(part.etag.clone())
+                    .build(),
+            );
+        }
+        let upload = CompletedMultipartUpload::builder()
+            .set_parts(Some(completed_parts))
+            .build();
+        self.client
+            .complete_multipart_upload()
+            .bucket(self.bucket_name.clone())
+            .key(self.relative_path_to_s3_object(to))
+            .upload_id(upload_id)
+            .multipart_upload(upload)
+            .send()
+            .await
+            .map_err(|e| {
+                metrics::inc_multipart_upload_fail("complete_multipart_upload");
+                e
+            })?;
+        Ok(())
+    }
+
+    async fn abort_multipart_upload(&self, to: &RemotePath, upload_id: &str) -> anyhow::Result<()> {
+        let _guard = self
+            .concurrency_limiter
+            .acquire()
+            .await
+            .context("Concurrency limiter semaphore got closed during S3 upload")?;
+
+        metrics::inc_multipart_upload("abort_multipart_upload");
+
+        self.client
+            .abort_multipart_upload()
+            .bucket(self.bucket_name.clone())
+            .key(self.relative_path_to_s3_object(to))
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(|e| {
+                metrics::inc_multipart_upload_fail("abort_multipart_upload");
+                e
+            })?;
+        Ok(())
+    }
 }
@@ -5,7 +5,10 @@ use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::sync::Mutex;
 
-use crate::{Download, DownloadError, RemotePath, RemoteStorage, StorageMetadata};
+use crate::{
+    Download, DownloadError, ObjectHead, RemoteObject, RemotePath, RemoteStorage, StorageMetadata,
+    UploadError, UploadPrecondition, UploadedPart,
+};
 
 pub struct UnreliableWrapper {
     inner: crate::GenericRemoteStorage,
@@ -22,9 +25,11 @@ pub struct UnreliableWrapper {
 enum RemoteOp {
     ListPrefixes(Option<RemotePath>),
     Upload(RemotePath),
+    UploadPart(RemotePath, u32),
     Download(RemotePath),
     Delete(RemotePath),
     DeleteObjects(Vec<RemotePath>),
+    Copy(RemotePath, RemotePath),
 }
 
 impl UnreliableWrapper {
@@ -88,6 +93,19 @@ impl RemoteStorage for UnreliableWrapper {
         self.inner.list_files(folder).await
     }
 
+    async fn list_objects(
+        &self,
+        folder: Option<&RemotePath>,
+    ) -> anyhow::Result<Vec<RemoteObject>> {
+        self.attempt(RemoteOp::ListPrefixes(folder.cloned()))?;
+        self.inner.list_objects(folder).await
+    }
+
+    async fn head_object(&self, path: &RemotePath) -> Result<ObjectHead, DownloadError> {
+        self.attempt(RemoteOp::Download(path.clone()))?;
+        self.inner.head_object(path).await
+    }
+
     async fn upload(
         &self,
         data: impl tokio::io::AsyncRead + Unpin + Send + Sync + 'static,
@@ -101,6 +119,20 @@ impl RemoteStorage for UnreliableWrapper {
         self.inner.upload(data, data_size_bytes, to, metadata).await
     }
 
+    async fn upload_conditional(
+        &self,
+        data: impl tokio::io::AsyncRead + Unpin + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        precondition: Option<&UploadPrecondition>,
+    ) -> Result<Option<String>, UploadError> {
+        self.attempt(RemoteOp::Upload(to.clone()))
+            .map_err(|e| UploadError::Other(anyhow::anyhow!(e)))?;
+        self.inner
+            .upload_conditional(data, data_size_bytes, to, precondition)
+            .await
+    }
+
     async fn download(&self, from: &RemotePath) -> Result<Download, DownloadError> {
         self.attempt(RemoteOp::Download(from.clone()))?;
         self.inner.download(from).await
@@ -142,4 +174,49 @@ impl RemoteStorage for UnreliableWrapper {
         }
         Ok(())
     }
+
+    async fn copy(&self, from: &RemotePath, to: &RemotePath) -> anyhow::Result<()> {
+        self.attempt(RemoteOp::Copy(from.clone(), to.clone()))?;
+        self.inner.copy(from, to).await
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        to: &RemotePath,
+        metadata: Option<StorageMetadata>,
+    ) -> anyhow::Result<String> {
+        self.attempt(RemoteOp::Upload(to.clone()))?;
+        self.inner.create_multipart_upload(to, metadata).await
+    }
+
+    async fn upload_part(
+        &self,
+        data: impl tokio::io::AsyncRead + Unpin + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        upload_id: &str,
+        part_number: u32,
+    ) -> anyhow::Result<String> {
+        self.attempt(RemoteOp::UploadPart(to.clone(), part_number))?;
+        self.inner
+            .upload_part(data, data_size_bytes, to, upload_id, part_number)
+            .await
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        to: &RemotePath,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> anyhow::Result<()> {
+        self.attempt(RemoteOp::Upload(to.clone()))?;
+        self.inner
+            .complete_multipart_upload(to, upload_id, parts)
+            .await
+    }
+
+    async fn abort_multipart_upload(&self, to: &RemotePath, upload_id: &str) -> anyhow::Result<()> {
+        self.attempt(RemoteOp::Delete(to.clone()))?;
+        self.inner.abort_multipart_upload(to, upload_id).await
+    }
 }
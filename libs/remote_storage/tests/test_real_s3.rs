@@ -210,6 +210,8 @@ fn ensure_logging_ready() {
         utils::logging::init(
             utils::logging::LogFormat::Test,
             utils::logging::TracingErrorLayerEnablement::Disabled,
+            Vec::new(),
+            None,
         )
         .expect("logging init failed");
     });
@@ -396,6 +398,8 @@ fn create_s3_client(
             endpoint: None,
             concurrency_limit: NonZeroUsize::new(100).unwrap(),
             max_keys_per_list_response,
+            server_side_encryption: None,
+            credentials: None,
         }),
     };
     Ok(Arc::new(
@@ -34,11 +34,13 @@
 //! ```
 
 use opentelemetry::sdk::Resource;
+use opentelemetry::trace::TraceContextExt;
 use opentelemetry::KeyValue;
 use opentelemetry_otlp::WithExportConfig;
 use opentelemetry_otlp::{OTEL_EXPORTER_OTLP_ENDPOINT, OTEL_EXPORTER_OTLP_TRACES_ENDPOINT};
 
 pub use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 pub mod http;
 
@@ -161,6 +163,26 @@ fn init_tracing_internal(service_name: String) -> opentelemetry::sdk::trace::Tra
         .expect("could not initialize opentelemetry exporter")
 }
 
+/// Whether an OTLP endpoint is set in the environment. The exporter falls back to one on
+/// localhost without it, for the services that export traces only when asked to.
+pub fn otlp_endpoint_configured() -> bool {
+    std::env::var_os(OTEL_EXPORTER_OTLP_ENDPOINT).is_some()
+        || std::env::var_os(OTEL_EXPORTER_OTLP_TRACES_ENDPOINT).is_some()
+}
+
+/// The id of the OpenTelemetry trace of the current span, in hex, if it is exported: `None`
+/// without the [`OpenTelemetryLayer`], or if the trace isn't sampled.
+pub fn current_trace_id() -> Option<String> {
+    let context = tracing::Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    if span_context.is_valid() && span_context.is_sampled() {
+        Some(span_context.trace_id().to_string())
+    } else {
+        None
+    }
+}
+
 // Shutdown trace pipeline gracefully, so that it has a chance to send any
 // pending traces before we exit.
 pub fn shutdown_tracing() {
@@ -1,10 +1,11 @@
 use crate::auth::{Claims, JwtAuth};
 use crate::http::error::{api_error_handler, route_error_handler, ApiError};
 use anyhow::Context;
-use hyper::header::{HeaderName, AUTHORIZATION};
+use hyper::header::{HeaderName, ACCEPT, AUTHORIZATION};
 use hyper::http::HeaderValue;
 use hyper::Method;
 use hyper::{header::CONTENT_TYPE, Body, Request, Response};
+use metrics::exemplars::{encode_openmetrics, OPENMETRICS_CONTENT_TYPE};
 use metrics::{register_int_counter, Encoder, IntCounter, TextEncoder};
 use once_cell::sync::Lazy;
 use routerify::ext::RequestExt;
@@ -37,7 +38,8 @@ struct RequestId(String);
 /// Use this to distinguish between logs of different HTTP requests: every request handler wrapped
 /// with this will get request info logged in the wrapping span, including the unique request ID.
 ///
-/// This also handles errors, logging them and converting them to an HTTP error response.
+/// This also handles errors, logging them and converting them to an HTTP error response,
+/// which includes the request ID.
 ///
 /// NB: If the client disconnects, Hyper will drop the Future, without polling it to
 /// completion. In other words, the handler must be async cancellation safe! request_span
@@ -106,7 +108,7 @@ where
                 }
                 Ok(response)
             }
-            Err(err) => Ok(api_error_handler(err)),
+            Err(err) => Ok(api_error_handler(err, Some(&request_id))),
         }
     }
     .instrument(request_span)
@@ -147,9 +149,17 @@ impl Drop for RequestCancelled {
     }
 }
 
-async fn prometheus_metrics_handler(_req: Request<Body>) -> Result<Response<Body>, ApiError> {
+async fn prometheus_metrics_handler(req: Request<Body>) -> Result<Response<Body>, ApiError> {
     SERVE_METRICS_COUNT.inc();
 
+    // The exemplars of the histograms are only in the OpenMetrics format, for the scrapers that
+    // ask for it, see `metrics::exemplars`
+    let openmetrics = req
+        .headers()
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map_or(false, |accept| accept.contains("application/openmetrics-text"));
+
     let mut buffer = vec![];
     let encoder = TextEncoder::new();
 
@@ -160,11 +170,17 @@ async fn prometheus_metrics_handler(_req: Request<Body>) -> Result<Response<Body
     })
     .await
     .map_err(|e: JoinError| ApiError::InternalServerError(e.into()))?;
-    encoder.encode(&metrics, &mut buffer).unwrap();
+    let content_type = if openmetrics {
+        encode_openmetrics(&metrics, &mut buffer).unwrap();
+        OPENMETRICS_CONTENT_TYPE
+    } else {
+        encoder.encode(&metrics, &mut buffer).unwrap();
+        encoder.format_type()
+    };
 
     let response = Response::builder()
         .status(200)
-        .header(CONTENT_TYPE, encoder.format_type())
+        .header(CONTENT_TYPE, content_type)
         .body(Body::from(buffer))
         .unwrap();
 
@@ -219,13 +235,23 @@ pub fn attach_openapi_ui(
     spec: &'static [u8],
     spec_mount_path: &'static str,
     ui_mount_path: &'static str,
+) -> RouterBuilder<hyper::Body, ApiError> {
+    let router_builder = router_builder.get(spec_mount_path, move |r| {
+        request_span(r, move |_| async move {
+            Ok(Response::builder().body(Body::from(spec)).unwrap())
+        })
+    });
+    attach_swagger_ui(router_builder, spec_mount_path, ui_mount_path)
+}
+
+/// Serves a Swagger UI page at `ui_mount_path` for the API description served at
+/// `spec_mount_path`, e.g. a generated one.
+pub fn attach_swagger_ui(
+    router_builder: RouterBuilder<hyper::Body, ApiError>,
+    spec_mount_path: &'static str,
+    ui_mount_path: &'static str,
 ) -> RouterBuilder<hyper::Body, ApiError> {
     router_builder
-        .get(spec_mount_path,
-            move |r| request_span(r, move |_| async move {
-                Ok(Response::builder().body(Body::from(spec)).unwrap())
-            })
-        )
         .get(ui_mount_path,
              move |r| request_span(r, move |_| async move {
                  Ok(Response::builder().body(Body::from(format!(r#"
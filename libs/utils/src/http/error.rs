@@ -1,9 +1,61 @@
 use hyper::{header, Body, Response, StatusCode};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::error::Error as StdError;
 use thiserror::Error;
 use tracing::error;
 
+/// Machine-readable error code in the body of the error responses of the management APIs,
+/// so that clients can branch on it rather than on the error message.
+///
+/// The codes are part of the API: don't rename or reuse them, add new ones instead.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum_macros::EnumVariantNames,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    TenantNotFound,
+    TimelineNotFound,
+    TimelineNotActive,
+    Conflict,
+    TenantAlreadyExists,
+    TimelineAlreadyExists,
+    DeletionInProgress,
+    PreconditionFailed,
+    TimelineHasChildren,
+    AncestorLsnNotAcceptable,
+    ServiceUnavailable,
+    TenantNotActive,
+    ShuttingDown,
+    DiskSpaceCritical,
+    Cancelled,
+    InternalError,
+    /// A code this version doesn't know, sent by a newer server.
+    #[serde(other)]
+    Unknown,
+}
+
+impl ErrorCode {
+    /// Whether the same request may succeed if retried later.
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::DeletionInProgress
+                | ErrorCode::ServiceUnavailable
+                | ErrorCode::TenantNotActive
+                | ErrorCode::TimelineNotActive
+                | ErrorCode::ShuttingDown
+                | ErrorCode::DiskSpaceCritical
+                | ErrorCode::Cancelled
+        )
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ApiError {
     #[error("Bad request: {0:#?}")]
@@ -24,54 +76,116 @@ pub enum ApiError {
     #[error("Precondition failed: {0}")]
     PreconditionFailed(Box<str>),
 
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(Cow<'static, str>),
+
     #[error(transparent)]
     InternalServerError(anyhow::Error),
+
+    /// The wrapped error with a more specific code than the default one of its variant,
+    /// see [`ApiError::with_code`].
+    #[error("{1}")]
+    WithCode(ErrorCode, Box<ApiError>),
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> ApiError {
+        ApiError::InternalServerError(e)
+    }
 }
 
 impl ApiError {
-    pub fn into_response(self) -> Response<Body> {
+    /// Report the error with the given code. The status code stays the one of the variant.
+    pub fn with_code(self, code: ErrorCode) -> ApiError {
+        ApiError::WithCode(code, Box::new(self.without_code()))
+    }
+
+    pub fn code(&self) -> ErrorCode {
         match self {
-            ApiError::BadRequest(err) => HttpErrorBody::response_from_msg_and_status(
-                format!("{err:#?}"), // use debug printing so that we give the cause
-                StatusCode::BAD_REQUEST,
-            ),
-            ApiError::Forbidden(_) => {
-                HttpErrorBody::response_from_msg_and_status(self.to_string(), StatusCode::FORBIDDEN)
-            }
-            ApiError::Unauthorized(_) => HttpErrorBody::response_from_msg_and_status(
-                self.to_string(),
-                StatusCode::UNAUTHORIZED,
-            ),
-            ApiError::NotFound(_) => {
-                HttpErrorBody::response_from_msg_and_status(self.to_string(), StatusCode::NOT_FOUND)
-            }
-            ApiError::Conflict(_) => {
-                HttpErrorBody::response_from_msg_and_status(self.to_string(), StatusCode::CONFLICT)
-            }
-            ApiError::PreconditionFailed(_) => HttpErrorBody::response_from_msg_and_status(
-                self.to_string(),
-                StatusCode::PRECONDITION_FAILED,
-            ),
-            ApiError::InternalServerError(err) => HttpErrorBody::response_from_msg_and_status(
-                err.to_string(),
-                StatusCode::INTERNAL_SERVER_ERROR,
-            ),
+            ApiError::BadRequest(_) => ErrorCode::BadRequest,
+            ApiError::Forbidden(_) => ErrorCode::Forbidden,
+            ApiError::Unauthorized(_) => ErrorCode::Unauthorized,
+            ApiError::NotFound(_) => ErrorCode::NotFound,
+            ApiError::Conflict(_) => ErrorCode::Conflict,
+            ApiError::PreconditionFailed(_) => ErrorCode::PreconditionFailed,
+            ApiError::ServiceUnavailable(_) => ErrorCode::ServiceUnavailable,
+            ApiError::InternalServerError(_) => ErrorCode::InternalError,
+            ApiError::WithCode(code, _) => *code,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::WithCode(_, inner) => inner.status(),
+        }
+    }
+
+    fn without_code(self) -> ApiError {
+        match self {
+            ApiError::WithCode(_, inner) => *inner,
+            other => other,
         }
     }
+
+    pub fn into_body(self) -> HttpErrorBody {
+        let code = self.code();
+        let msg = match self.without_code() {
+            // use debug printing so that we give the cause
+            ApiError::BadRequest(err) => format!("{err:#?}"),
+            ApiError::InternalServerError(err) => err.to_string(),
+            other => other.to_string(),
+        };
+        HttpErrorBody::from_code(code, msg)
+    }
+
+    pub fn into_response(self) -> Response<Body> {
+        let status = self.status();
+        self.into_body().to_response(status)
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct HttpErrorBody {
     pub msg: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+    /// The `x-request-id` of the failed request, to find its logs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Whether the same request may succeed if retried later, see [`ErrorCode::is_retryable`].
+    #[serde(default)]
+    pub retryable: bool,
 }
 
 impl HttpErrorBody {
     pub fn from_msg(msg: String) -> Self {
-        HttpErrorBody { msg }
+        HttpErrorBody {
+            msg,
+            code: None,
+            request_id: None,
+            retryable: false,
+        }
+    }
+
+    pub fn from_code(code: ErrorCode, msg: String) -> Self {
+        HttpErrorBody {
+            msg,
+            code: Some(code),
+            request_id: None,
+            retryable: code.is_retryable(),
+        }
     }
 
     pub fn response_from_msg_and_status(msg: String, status: StatusCode) -> Response<Body> {
-        HttpErrorBody { msg }.to_response(status)
+        HttpErrorBody::from_msg(msg).to_response(status)
     }
 
     pub fn to_response(&self, status: StatusCode) -> Response<Body> {
@@ -86,7 +200,7 @@ impl HttpErrorBody {
 
 pub async fn route_error_handler(err: routerify::RouteError) -> Response<Body> {
     match err.downcast::<ApiError>() {
-        Ok(api_error) => api_error_handler(*api_error),
+        Ok(api_error) => api_error_handler(*api_error, None),
         Err(other_error) => {
             // We expect all the request handlers to return an ApiError, so this should
             // not be reached. But just in case.
@@ -99,13 +213,60 @@ pub async fn route_error_handler(err: routerify::RouteError) -> Response<Body> {
     }
 }
 
-pub fn api_error_handler(api_error: ApiError) -> Response<Body> {
+pub fn api_error_handler(api_error: ApiError, request_id: Option<&str>) -> Response<Body> {
     // Print a stack trace for Internal Server errors
-    if let ApiError::InternalServerError(_) = api_error {
+    if api_error.status() == StatusCode::INTERNAL_SERVER_ERROR {
         error!("Error processing HTTP request: {api_error:?}");
     } else {
         error!("Error processing HTTP request: {api_error:#}");
     }
 
-    api_error.into_response()
+    let status = api_error.status();
+    let mut body = api_error.into_body();
+    body.request_id = request_id.map(str::to_owned);
+    body.to_response(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_body() {
+        let err = ApiError::NotFound(anyhow::anyhow!("tenant 1234").into())
+            .with_code(ErrorCode::TenantNotFound);
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+        assert_eq!(err.to_string(), "NotFound: tenant 1234");
+
+        let body = serde_json::to_value(err.into_body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "msg": "NotFound: tenant 1234",
+                "code": "tenant_not_found",
+                "retryable": false,
+            })
+        );
+
+        let err = ApiError::ServiceUnavailable("shutting down".into())
+            .with_code(ErrorCode::Cancelled)
+            .with_code(ErrorCode::ShuttingDown);
+        assert!(matches!(
+            &err,
+            ApiError::WithCode(ErrorCode::ShuttingDown, inner)
+                if matches!(**inner, ApiError::ServiceUnavailable(_))
+        ));
+        assert!(err.into_body().retryable);
+    }
+
+    #[test]
+    fn unknown_code() {
+        let body: HttpErrorBody =
+            serde_json::from_str(r#"{"msg": "oops", "code": "from_the_future"}"#).unwrap();
+        assert_eq!(body.code, Some(ErrorCode::Unknown));
+
+        let body: HttpErrorBody = serde_json::from_str(r#"{"msg": "oops"}"#).unwrap();
+        assert_eq!(body.code, None);
+        assert!(!body.retryable);
+    }
 }
@@ -1,6 +1,7 @@
 pub mod endpoint;
 pub mod error;
 pub mod json;
+pub mod openapi;
 pub mod request;
 
 /// Current fast way to apply simple http routing in various Neon binaries.
@@ -0,0 +1,527 @@
+//! OpenAPI description of an HTTP API, generated from the request and response types of
+//! its handlers.
+//!
+//! The types implement [`ApiSchema`], usually with the [`api_schema!`](crate::api_schema)
+//! annotation next to the type definition, and the operations are described with
+//! [`Operation`] next to the router. The document is served by the API itself, so that
+//! clients can generate their bindings from what the running server actually implements,
+//! and the annotations live next to the types, where renaming or removing a field without
+//! updating the annotation fails to compile.
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::{json, Map};
+
+#[doc(hidden)]
+pub use serde_json::Value;
+
+use super::error::{ErrorCode, HttpErrorBody};
+use crate::id::{NodeId, TenantId, TimelineId};
+use crate::lsn::Lsn;
+
+/// A type that appears in the requests or responses of the API.
+pub trait ApiSchema {
+    /// JSON schema of the type. Named types add their schema to `components` and return
+    /// a reference to it.
+    fn schema(components: &mut Components) -> Value;
+
+    /// Whether a field of this type may be missing from an object, i.e. `Option`.
+    fn optional() -> bool {
+        false
+    }
+}
+
+/// The named schemas of the document, `components/schemas`.
+#[derive(Debug, Default)]
+pub struct Components {
+    schemas: BTreeMap<&'static str, Value>,
+}
+
+impl Components {
+    /// Add a named schema, if it's not there yet, and return a reference to it.
+    pub fn named(&mut self, name: &'static str, schema: impl FnOnce(&mut Self) -> Value) -> Value {
+        if !self.schemas.contains_key(name) {
+            // Insert a placeholder first, so that recursive types terminate.
+            self.schemas.insert(name, Value::Null);
+            let schema = schema(self);
+            self.schemas.insert(name, schema);
+        }
+        json!({ "$ref": format!("#/components/schemas/{name}") })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.schemas.get(name)
+    }
+}
+
+/// Schema of a struct with the given `(name, optional, schema)` fields.
+pub fn object_schema(fields: Vec<(&'static str, bool, Value)>) -> Value {
+    let required = fields
+        .iter()
+        .filter(|(_, optional, _)| !optional)
+        .map(|(name, _, _)| *name)
+        .collect::<Vec<_>>();
+    let properties = fields
+        .into_iter()
+        .map(|(name, _, schema)| (name.to_string(), schema))
+        .collect::<Map<_, _>>();
+    json!({
+        "type": "object",
+        "required": required,
+        "properties": properties,
+    })
+}
+
+/// Schema of a value that matches all the given schemas.
+pub fn all_of(schemas: Vec<Value>) -> Value {
+    json!({ "allOf": schemas })
+}
+
+/// Schema of an enum serialized as one of the given strings.
+pub fn string_enum_schema(variants: &[&str]) -> Value {
+    json!({ "type": "string", "enum": variants })
+}
+
+/// Implement [`ApiSchema`] for a struct, listing its fields as they appear in JSON:
+///
+/// ```ignore
+/// api_schema!(TimelineCreateRequest {
+///     new_timeline_id: TimelineId,
+///     ancestor_timeline_id: Option<TimelineId>,
+/// });
+/// ```
+///
+/// The fields of a `#[serde(flatten)]` field are included with `..TenantConfig` at the end
+/// of the list. A listed field that the struct doesn't have fails to compile.
+///
+/// An enum with only unit variants is annotated with the names of its variants instead,
+/// e.g. `api_schema!(ErrorCode = ErrorCode::VARIANTS)`.
+#[macro_export]
+macro_rules! api_schema {
+    (@object $name:ident { $($field:ident : $ty:ty),* } flatten: [$($flatten:ty)?]) => {
+        impl $crate::http::openapi::ApiSchema for $name {
+            fn schema(
+                components: &mut $crate::http::openapi::Components,
+            ) -> $crate::http::openapi::Value {
+                components.named(stringify!($name), |components| {
+                    #[allow(unused_mut)]
+                    let mut schema = $crate::http::openapi::object_schema(vec![$((
+                        stringify!($field),
+                        <$ty as $crate::http::openapi::ApiSchema>::optional(),
+                        <$ty as $crate::http::openapi::ApiSchema>::schema(components),
+                    )),*]);
+                    $(
+                        let flattened =
+                            <$flatten as $crate::http::openapi::ApiSchema>::schema(components);
+                        schema = $crate::http::openapi::all_of(vec![flattened, schema]);
+                    )?
+                    schema
+                })
+            }
+        }
+
+        const _: () = {
+            /// Fails to compile if the struct doesn't have one of the listed fields.
+            #[allow(dead_code)]
+            fn check_fields(value: &$name) {
+                let $name { $($field: _,)* .. } = value;
+            }
+        };
+    };
+    ($name:ident { $($field:ident : $ty:ty),* $(,)? }) => {
+        $crate::api_schema!(@object $name { $($field: $ty),* } flatten: []);
+    };
+    ($name:ident { $($field:ident : $ty:ty,)* ..$flatten:ty $(,)? }) => {
+        $crate::api_schema!(@object $name { $($field: $ty),* } flatten: [$flatten]);
+    };
+    ($name:ident = $variants:expr) => {
+        impl $crate::http::openapi::ApiSchema for $name {
+            fn schema(
+                components: &mut $crate::http::openapi::Components,
+            ) -> $crate::http::openapi::Value {
+                components.named(stringify!($name), |_| {
+                    $crate::http::openapi::string_enum_schema($variants)
+                })
+            }
+        }
+    };
+}
+
+macro_rules! primitive_schema {
+    ($($ty:ty => $schema:tt),* $(,)?) => {
+        $(
+            impl ApiSchema for $ty {
+                fn schema(_: &mut Components) -> Value {
+                    json!($schema)
+                }
+            }
+        )*
+    };
+}
+
+primitive_schema! {
+    bool => { "type": "boolean" },
+    String => { "type": "string" },
+    u8 => { "type": "integer", "minimum": 0 },
+    u16 => { "type": "integer", "minimum": 0 },
+    u32 => { "type": "integer", "minimum": 0 },
+    u64 => { "type": "integer", "minimum": 0 },
+    u128 => { "type": "integer", "minimum": 0 },
+    usize => { "type": "integer", "minimum": 0 },
+    std::num::NonZeroU64 => { "type": "integer", "minimum": 1 },
+    std::num::NonZeroUsize => { "type": "integer", "minimum": 1 },
+    i32 => { "type": "integer" },
+    i64 => { "type": "integer" },
+    f64 => { "type": "number" },
+    // Any JSON value
+    Value => {},
+    TenantId => { "type": "string", "format": "hex" },
+    TimelineId => { "type": "string", "format": "hex" },
+    NodeId => { "type": "integer", "minimum": 0 },
+    // In the `X/Y` format of postgres, as all the API types serialize it with `DisplayFromStr`.
+    Lsn => { "type": "string", "format": "hex" },
+}
+
+impl<T: ApiSchema> ApiSchema for Option<T> {
+    fn schema(components: &mut Components) -> Value {
+        T::schema(components)
+    }
+
+    fn optional() -> bool {
+        true
+    }
+}
+
+impl<T: ApiSchema> ApiSchema for Vec<T> {
+    fn schema(components: &mut Components) -> Value {
+        json!({ "type": "array", "items": T::schema(components) })
+    }
+}
+
+impl<T: ApiSchema> ApiSchema for BTreeMap<String, T> {
+    fn schema(components: &mut Components) -> Value {
+        json!({ "type": "object", "additionalProperties": T::schema(components) })
+    }
+}
+
+impl<T: ApiSchema> ApiSchema for HashMap<String, T> {
+    fn schema(components: &mut Components) -> Value {
+        json!({ "type": "object", "additionalProperties": T::schema(components) })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Method {
+    Get,
+    Post,
+    Put,
+    Delete,
+}
+
+impl Method {
+    fn as_str(self) -> &'static str {
+        match self {
+            Method::Get => "get",
+            Method::Post => "post",
+            Method::Put => "put",
+            Method::Delete => "delete",
+        }
+    }
+}
+
+type SchemaFn = fn(&mut Components) -> Value;
+
+/// An operation of the API, described with the same path as in the router, e.g.
+/// `Operation::get("/v1/tenant/:tenant_id").response::<TenantInfo>()`.
+#[derive(Clone)]
+pub struct Operation {
+    method: Method,
+    path: &'static str,
+    summary: Option<&'static str>,
+    query: Vec<(&'static str, bool, SchemaFn)>,
+    request: Option<SchemaFn>,
+    status: u16,
+    response: Option<SchemaFn>,
+    testing: bool,
+}
+
+impl Operation {
+    fn new(method: Method, path: &'static str) -> Self {
+        Operation {
+            method,
+            path,
+            summary: None,
+            query: Vec::new(),
+            request: None,
+            status: 200,
+            response: None,
+            testing: false,
+        }
+    }
+
+    pub fn get(path: &'static str) -> Self {
+        Self::new(Method::Get, path)
+    }
+
+    pub fn post(path: &'static str) -> Self {
+        Self::new(Method::Post, path)
+    }
+
+    pub fn put(path: &'static str) -> Self {
+        Self::new(Method::Put, path)
+    }
+
+    pub fn delete(path: &'static str) -> Self {
+        Self::new(Method::Delete, path)
+    }
+
+    pub fn summary(mut self, summary: &'static str) -> Self {
+        self.summary = Some(summary);
+        self
+    }
+
+    /// A query parameter, optional if `T` is an `Option`.
+    pub fn query<T: ApiSchema>(mut self, name: &'static str) -> Self {
+        self.query.push((name, !T::optional(), T::schema));
+        self
+    }
+
+    /// The JSON request body.
+    pub fn request<T: ApiSchema>(mut self) -> Self {
+        self.request = Some(T::schema);
+        self
+    }
+
+    /// The status code of the successful response, if not 200.
+    pub fn status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// The JSON body of the successful response.
+    pub fn response<T: ApiSchema>(mut self) -> Self {
+        self.response = Some(T::schema);
+        self
+    }
+
+    /// Only available in testing builds, or with the testing API enabled.
+    pub fn testing(mut self) -> Self {
+        self.testing = true;
+        self
+    }
+
+    /// The path in the OpenAPI format, `/v1/tenant/{tenant_id}`, and its parameters.
+    fn openapi_path(&self) -> (String, Vec<&'static str>) {
+        let mut params = Vec::new();
+        let segments = self
+            .path
+            .split('/')
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(param) => {
+                    params.push(param);
+                    format!("{{{param}}}")
+                }
+                None => segment.to_string(),
+            })
+            .collect::<Vec<_>>();
+        (segments.join("/"), params)
+    }
+
+    fn to_json(&self, components: &mut Components) -> Value {
+        let (_, path_params) = self.openapi_path();
+        let mut parameters = path_params
+            .into_iter()
+            .map(|name| {
+                json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                })
+            })
+            .collect::<Vec<_>>();
+        for (name, required, schema) in &self.query {
+            parameters.push(json!({
+                "name": name,
+                "in": "query",
+                "required": required,
+                "schema": schema(components),
+            }));
+        }
+
+        let mut operation = Map::new();
+        if let Some(summary) = self.summary {
+            operation.insert("summary".to_string(), json!(summary));
+        }
+        if !parameters.is_empty() {
+            operation.insert("parameters".to_string(), json!(parameters));
+        }
+        if let Some(request) = self.request {
+            operation.insert(
+                "requestBody".to_string(),
+                json!({
+                    "required": true,
+                    "content": { "application/json": { "schema": request(components) } },
+                }),
+            );
+        }
+        let success = match self.response {
+            Some(response) => json!({
+                "description": "OK",
+                "content": { "application/json": { "schema": response(components) } },
+            }),
+            None => json!({ "description": "OK" }),
+        };
+        let error = json!({
+            "description": "Error",
+            "content": {
+                "application/json": { "schema": HttpErrorBody::schema(components) }
+            },
+        });
+        let mut responses = Map::new();
+        responses.insert(self.status.to_string(), success);
+        responses.insert("default".to_string(), error);
+        operation.insert("responses".to_string(), Value::Object(responses));
+        if self.testing {
+            operation.insert("x-testing".to_string(), json!(true));
+        }
+        Value::Object(operation)
+    }
+}
+
+api_schema!(ErrorCode = <ErrorCode as strum::VariantNames>::VARIANTS);
+
+api_schema!(HttpErrorBody {
+    msg: String,
+    code: Option<ErrorCode>,
+    request_id: Option<String>,
+    retryable: bool,
+});
+
+/// An OpenAPI 3.0 document.
+pub struct OpenApi {
+    title: &'static str,
+    version: &'static str,
+    operations: Vec<Operation>,
+}
+
+impl OpenApi {
+    pub fn new(title: &'static str, version: &'static str) -> Self {
+        OpenApi {
+            title,
+            version,
+            operations: Vec::new(),
+        }
+    }
+
+    pub fn operation(mut self, operation: Operation) -> Self {
+        self.operations.push(operation);
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut components = Components::default();
+        let mut paths = BTreeMap::<String, Map<String, Value>>::new();
+        for operation in &self.operations {
+            let (path, _) = operation.openapi_path();
+            let json = operation.to_json(&mut components);
+            let previous = paths
+                .entry(path)
+                .or_default()
+                .insert(operation.method.as_str().to_string(), json);
+            assert!(
+                previous.is_none(),
+                "{} {} described twice",
+                operation.method.as_str(),
+                operation.path
+            );
+        }
+
+        json!({
+            "openapi": "3.0.2",
+            "info": { "title": self.title, "version": self.version },
+            "paths": paths,
+            "components": { "schemas": components.schemas },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(dead_code)]
+    struct Inner {
+        lsn: Lsn,
+    }
+    api_schema!(Inner { lsn: Lsn });
+
+    #[allow(dead_code)]
+    struct Outer {
+        id: TenantId,
+        inner: Option<Inner>,
+        sizes: Vec<u64>,
+        flattened: Inner,
+    }
+    api_schema!(Outer {
+        id: TenantId,
+        inner: Option<Inner>,
+        sizes: Vec<u64>,
+        ..Inner
+    });
+
+    #[test]
+    fn document() {
+        let doc = OpenApi::new("Test API", "1.0")
+            .operation(
+                Operation::get("/v1/tenant/:tenant_id/outer")
+                    .summary("Get the outer")
+                    .query::<Option<bool>>("verbose")
+                    .response::<Outer>(),
+            )
+            .operation(
+                Operation::put("/v1/tenant/:tenant_id/outer")
+                    .request::<Outer>()
+                    .status(202),
+            )
+            .to_json();
+
+        let path = &doc["paths"]["/v1/tenant/{tenant_id}/outer"];
+        let get = &path["get"];
+        assert_eq!(get["summary"], "Get the outer");
+        assert_eq!(get["parameters"][0]["name"], "tenant_id");
+        assert_eq!(get["parameters"][0]["in"], "path");
+        assert_eq!(get["parameters"][1]["name"], "verbose");
+        assert_eq!(get["parameters"][1]["in"], "query");
+        assert_eq!(get["parameters"][1]["required"], false);
+        assert_eq!(
+            get["responses"]["200"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/Outer"
+        );
+        assert_eq!(
+            get["responses"]["default"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/HttpErrorBody"
+        );
+        assert_eq!(
+            path["put"]["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/Outer"
+        );
+        assert_eq!(path["put"]["responses"]["202"]["description"], "OK");
+
+        let schemas = &doc["components"]["schemas"];
+        let outer = &schemas["Outer"]["allOf"];
+        assert_eq!(outer[0]["$ref"], "#/components/schemas/Inner");
+        assert_eq!(outer[1]["required"], json!(["id", "sizes"]));
+        assert_eq!(outer[1]["properties"]["sizes"]["type"], "array");
+        assert_eq!(schemas["Inner"]["properties"]["lsn"]["type"], "string");
+        assert_eq!(schemas["ErrorCode"]["enum"][0], "bad_request");
+    }
+
+    #[test]
+    #[should_panic(expected = "get /v1/status described twice")]
+    fn duplicate_operation() {
+        OpenApi::new("Test API", "1.0")
+            .operation(Operation::get("/v1/status"))
+            .operation(Operation::get("/v1/status"))
+            .to_json();
+    }
+}
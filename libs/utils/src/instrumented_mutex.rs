@@ -0,0 +1,296 @@
+//! A drop-in replacement for [`std::sync::Mutex`] for data that is locked from async code.
+//!
+//! Taking a std mutex on a runtime worker thread is fine as long as it is only held
+//! briefly. Holding it across an `.await`, or holding it (or waiting for it) for long,
+//! stalls the worker and every task queued on it. The compiler doesn't catch these
+//! mistakes in all cases, e.g. in `!Send` futures or when the slow part is synchronous.
+//!
+//! With the `mutex-debug` feature enabled in a debug build, [`Mutex`] keeps track of the
+//! locks held by each thread and logs a warning with a backtrace when, inside an async poll
+//! (see [`enter_async_poll`]):
+//!
+//! - acquiring the lock took longer than [`CONTENTION_THRESHOLD`],
+//! - the lock was held for longer than [`HOLD_THRESHOLD`],
+//! - the lock was still held when the poll returned, i.e. it is held across an `.await`.
+//!
+//! Without the feature, or in a release build, [`Mutex`] is a thin wrapper with no overhead.
+
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::{LockResult, PoisonError};
+use std::time::Duration;
+
+/// Waiting longer than this for a lock inside an async poll is reported.
+pub const CONTENTION_THRESHOLD: Duration = Duration::from_millis(10);
+
+/// Holding a lock longer than this inside an async poll is reported.
+pub const HOLD_THRESHOLD: Duration = Duration::from_millis(10);
+
+#[derive(Default)]
+pub struct Mutex<T: ?Sized> {
+    inner: std::sync::Mutex<T>,
+}
+
+pub struct MutexGuard<'a, T: ?Sized + 'a> {
+    inner: std::sync::MutexGuard<'a, T>,
+    #[cfg(all(feature = "mutex-debug", debug_assertions))]
+    _held: debug::HeldLock,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Mutex {
+            inner: std::sync::Mutex::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Same as [`std::sync::Mutex::lock`].
+    #[track_caller]
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        #[cfg(all(feature = "mutex-debug", debug_assertions))]
+        let wait = debug::LockWait::start();
+
+        let (inner, poisoned) = match self.inner.lock() {
+            Ok(inner) => (inner, false),
+            Err(e) => (e.into_inner(), true),
+        };
+        let guard = MutexGuard {
+            inner,
+            #[cfg(all(feature = "mutex-debug", debug_assertions))]
+            _held: wait.acquired(std::panic::Location::caller()),
+        };
+
+        if poisoned {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.inner.get_mut()
+    }
+}
+
+impl<T: ?Sized + std::fmt::Debug> std::fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+/// Marks the current thread as polling a future until the returned guard is dropped.
+/// Call it around the poll of a task's top-level future.
+///
+/// When the guard is dropped, any [`Mutex`] acquired during the poll that is still held
+/// is reported as held across an `.await`.
+#[must_use]
+pub fn enter_async_poll() -> AsyncPollGuard {
+    AsyncPollGuard {
+        #[cfg(all(feature = "mutex-debug", debug_assertions))]
+        _inner: debug::AsyncPoll::enter(),
+        _not_send: PhantomData,
+    }
+}
+
+pub struct AsyncPollGuard {
+    #[cfg(all(feature = "mutex-debug", debug_assertions))]
+    _inner: debug::AsyncPoll,
+    // Refers to thread-local state, must be dropped on the thread that created it.
+    _not_send: PhantomData<*const ()>,
+}
+
+#[cfg(all(feature = "mutex-debug", debug_assertions))]
+mod debug {
+    use std::backtrace::Backtrace;
+    use std::cell::{Cell, RefCell};
+    use std::marker::PhantomData;
+    use std::panic::Location;
+    use std::time::Instant;
+
+    use tracing::warn;
+
+    use super::{CONTENTION_THRESHOLD, HOLD_THRESHOLD};
+
+    struct HeldEntry {
+        id: u64,
+        location: &'static Location<'static>,
+    }
+
+    thread_local! {
+        static IN_ASYNC_POLL: Cell<bool> = Cell::new(false);
+        static NEXT_ID: Cell<u64> = Cell::new(0);
+        static HELD: RefCell<Vec<HeldEntry>> = RefCell::new(Vec::new());
+    }
+
+    fn in_async_poll() -> bool {
+        IN_ASYNC_POLL.with(|f| f.get())
+    }
+
+    #[cfg(test)]
+    pub(super) fn held_count() -> usize {
+        HELD.with(|held| held.borrow().len())
+    }
+
+    pub(super) struct LockWait {
+        started: Instant,
+    }
+
+    impl LockWait {
+        pub(super) fn start() -> Self {
+            LockWait {
+                started: Instant::now(),
+            }
+        }
+
+        pub(super) fn acquired(self, location: &'static Location<'static>) -> HeldLock {
+            let now = Instant::now();
+            let waited = now.duration_since(self.started);
+            if waited > CONTENTION_THRESHOLD && in_async_poll() {
+                warn!(
+                    ?waited,
+                    %location,
+                    "waited for a std mutex for too long in async context:\n{}",
+                    Backtrace::force_capture()
+                );
+            }
+
+            let id = NEXT_ID.with(|next| {
+                let id = next.get();
+                next.set(id + 1);
+                id
+            });
+            HELD.with(|held| held.borrow_mut().push(HeldEntry { id, location }));
+
+            HeldLock {
+                id,
+                location,
+                acquired_at: now,
+                _not_send: PhantomData,
+            }
+        }
+    }
+
+    /// Registered in the thread's held-locks stack for as long as the guard lives.
+    pub(super) struct HeldLock {
+        id: u64,
+        location: &'static Location<'static>,
+        acquired_at: Instant,
+        _not_send: PhantomData<*const ()>,
+    }
+
+    impl Drop for HeldLock {
+        fn drop(&mut self) {
+            // Guards are not necessarily dropped in the order they were acquired.
+            HELD.with(|held| held.borrow_mut().retain(|e| e.id != self.id));
+
+            let held_for = self.acquired_at.elapsed();
+            if held_for > HOLD_THRESHOLD && in_async_poll() && !std::thread::panicking() {
+                warn!(
+                    ?held_for,
+                    location = %self.location,
+                    "held a std mutex for too long in async context:\n{}",
+                    Backtrace::force_capture()
+                );
+            }
+        }
+    }
+
+    pub(super) struct AsyncPoll {
+        was_in_async_poll: bool,
+        /// Locks held when entering the poll, e.g. when polling a future from within a
+        /// synchronous critical section with `block_on`. Those are not ours to report.
+        held_before: usize,
+    }
+
+    impl AsyncPoll {
+        pub(super) fn enter() -> Self {
+            AsyncPoll {
+                was_in_async_poll: IN_ASYNC_POLL.with(|f| f.replace(true)),
+                held_before: HELD.with(|held| held.borrow().len()),
+            }
+        }
+    }
+
+    impl Drop for AsyncPoll {
+        fn drop(&mut self) {
+            IN_ASYNC_POLL.with(|f| f.set(self.was_in_async_poll));
+            if std::thread::panicking() {
+                return;
+            }
+
+            HELD.with(|held| {
+                let held = held.borrow();
+                if held.len() > self.held_before {
+                    let locations = held[self.held_before..]
+                        .iter()
+                        .map(|e| e.location.to_string())
+                        .collect::<Vec<_>>();
+                    warn!(
+                        ?locations,
+                        "std mutex held across an .await, acquired at the given locations:\n{}",
+                        Backtrace::force_capture()
+                    );
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_and_poison() {
+        let m = std::sync::Arc::new(Mutex::new(1));
+        *m.lock().unwrap() += 1;
+        assert_eq!(*m.lock().unwrap(), 2);
+
+        let m2 = m.clone();
+        std::thread::spawn(move || {
+            let _guard = m2.lock().unwrap();
+            panic!("poison the mutex");
+        })
+        .join()
+        .unwrap_err();
+
+        let guard = m.lock().unwrap_err().into_inner();
+        assert_eq!(*guard, 2);
+    }
+
+    #[cfg(all(feature = "mutex-debug", debug_assertions))]
+    #[test]
+    fn held_locks_are_tracked() {
+        let a = Mutex::new(());
+        let b = Mutex::new(());
+        let _poll = enter_async_poll();
+        let guard_a = a.lock().unwrap();
+        let guard_b = b.lock().unwrap();
+        assert_eq!(debug::held_count(), 2);
+        // Out of order release.
+        drop(guard_a);
+        assert_eq!(debug::held_count(), 1);
+        drop(guard_b);
+        assert_eq!(debug::held_count(), 0);
+    }
+}
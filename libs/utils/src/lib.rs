@@ -60,6 +60,9 @@ pub mod tracing_span_assert;
 
 pub mod rate_limit;
 
+/// std Mutex wrapper that can detect blocking misuse in async code, see the `mutex-debug` feature.
+pub mod instrumented_mutex;
+
 /// Simple once-barrier and a guard which keeps barrier awaiting.
 pub mod completion;
 
@@ -1,7 +1,10 @@
 use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 use strum_macros::{EnumString, EnumVariantNames};
 
 #[derive(EnumString, EnumVariantNames, Eq, PartialEq, Debug, Clone, Copy)]
@@ -56,6 +59,134 @@ where
     }
 }
 
+static TRACING_ERROR_BUDGET_EXCEEDED: Lazy<metrics::IntCounterVec> = Lazy::new(|| {
+    metrics::register_int_counter_vec!(
+        "libmetrics_tracing_error_budget_exceeded_total",
+        "Number of windows in which the error events of a target exceeded its error budget",
+        &["target"]
+    )
+    .expect("failed to define metric")
+});
+
+/// Target of the "error budget exceeded" events, which do not count against any budget.
+const ERROR_BUDGET_TARGET: &str = "error_budget";
+
+/// A limit on the number of error events of a target within a window of time.
+///
+/// Alerting on the raw volume of error logs is noisy. Instead, once the error events of the
+/// target exceed the budget within a window, a single "error budget exceeded" event is logged
+/// and the `libmetrics_tracing_error_budget_exceeded_total` metric is incremented, which gives
+/// on-call a stable signal to alert on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ErrorBudget {
+    /// The budget covers the events of this target and of its submodules, e.g.
+    /// `pageserver::tenant` covers `pageserver::tenant::timeline` too.
+    pub target: String,
+    /// Number of error events allowed within a window.
+    pub max_errors: u64,
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+impl ErrorBudget {
+    fn covers(&self, target: &str) -> bool {
+        match target.strip_prefix(self.target.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with("::"),
+            None => false,
+        }
+    }
+}
+
+/// The error events of a budget in the current window.
+struct BudgetWindow {
+    start: Instant,
+    errors: u64,
+}
+
+struct BudgetState {
+    budget: ErrorBudget,
+    window: Mutex<BudgetWindow>,
+}
+
+impl BudgetState {
+    fn new(budget: ErrorBudget) -> Self {
+        BudgetState {
+            budget,
+            window: Mutex::new(BudgetWindow {
+                start: Instant::now(),
+                errors: 0,
+            }),
+        }
+    }
+
+    /// Count an error event. Returns true for the first event over the budget in the window.
+    fn record(&self, now: Instant) -> bool {
+        let mut window = self.window.lock().unwrap();
+        if now.duration_since(window.start) >= self.budget.window {
+            *window = BudgetWindow {
+                start: now,
+                errors: 0,
+            };
+        }
+        window.errors += 1;
+        window.errors == self.budget.max_errors + 1
+    }
+}
+
+/// Counts the error events against the [`ErrorBudget`]s, like [`TracingEventCountLayer`]
+/// counts all of them by level.
+///
+/// It logs from within `on_event`, so it has to be the outermost layer of the subscriber,
+/// without a per-layer filter: the other layers must be done with an event before the nested
+/// event starts.
+struct ErrorBudgetLayer {
+    budgets: Vec<BudgetState>,
+    exceeded: &'static metrics::IntCounterVec,
+}
+
+impl ErrorBudgetLayer {
+    fn new(exceeded: &'static metrics::IntCounterVec, budgets: Vec<ErrorBudget>) -> Self {
+        ErrorBudgetLayer {
+            budgets: budgets.into_iter().map(BudgetState::new).collect(),
+            exceeded,
+        }
+    }
+}
+
+impl<S> tracing_subscriber::layer::Layer<S> for ErrorBudgetLayer
+where
+    S: tracing::Subscriber,
+{
+    fn on_event(
+        &self,
+        event: &tracing::Event<'_>,
+        _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let metadata = event.metadata();
+        let target = metadata.target();
+        if *metadata.level() != tracing::Level::ERROR || target == ERROR_BUDGET_TARGET {
+            return;
+        }
+
+        let now = Instant::now();
+        for state in &self.budgets {
+            if !state.budget.covers(target) || !state.record(now) {
+                continue;
+            }
+            let budget = &state.budget;
+            self.exceeded.with_label_values(&[&budget.target]).inc();
+            tracing::error!(
+                target: ERROR_BUDGET_TARGET,
+                budget_target = %budget.target,
+                max_errors = budget.max_errors,
+                window = ?budget.window,
+                "error budget exceeded"
+            );
+        }
+    }
+}
+
 /// Whether to add the `tracing_error` crate's `ErrorLayer`
 /// to the global tracing subscriber.
 ///
@@ -66,9 +197,16 @@ pub enum TracingErrorLayerEnablement {
     EnableWithRustLogFilter,
 }
 
+/// A layer exporting the spans as OpenTelemetry traces, made by `tracing_utils`.
+pub type OtlpLayer = Box<dyn tracing_subscriber::Layer<tracing_subscriber::Registry> + Send + Sync>;
+
+/// Set up the logging, and the export of the spans with `otlp_layer` if given, both filtered by
+/// `RUST_LOG`.
 pub fn init(
     log_format: LogFormat,
     tracing_error_layer_enablement: TracingErrorLayerEnablement,
+    error_budgets: Vec<ErrorBudget>,
+    otlp_layer: Option<OtlpLayer>,
 ) -> anyhow::Result<()> {
     // We fall back to printing all spans at info-level or above if
     // the RUST_LOG environment variable is not set.
@@ -81,6 +219,7 @@ pub fn init(
     // See https://docs.rs/tracing-subscriber/0.3.16/tracing_subscriber/layer/index.html#per-layer-filtering
     use tracing_subscriber::prelude::*;
     let r = tracing_subscriber::registry();
+    let r = r.with(otlp_layer.map(|layer| layer.with_filter(rust_log_env_filter())));
     let r = r.with({
         let log_layer = tracing_subscriber::fmt::layer()
             .with_target(false)
@@ -94,11 +233,14 @@ pub fn init(
         log_layer.with_filter(rust_log_env_filter())
     });
     let r = r.with(TracingEventCountLayer(&TRACING_EVENT_COUNT).with_filter(rust_log_env_filter()));
+    // This one has to be the outermost layer, see its comment.
+    let error_budget_layer = ErrorBudgetLayer::new(&TRACING_ERROR_BUDGET_EXCEEDED, error_budgets);
     match tracing_error_layer_enablement {
         TracingErrorLayerEnablement::EnableWithRustLogFilter => r
             .with(tracing_error::ErrorLayer::default().with_filter(rust_log_env_filter()))
+            .with(error_budget_layer)
             .init(),
-        TracingErrorLayerEnablement::Disabled => r.init(),
+        TracingErrorLayerEnablement::Disabled => r.with(error_budget_layer).init(),
     }
 
     Ok(())
@@ -218,9 +360,11 @@ impl std::fmt::Debug for PrettyLocation<'_, '_> {
 
 #[cfg(test)]
 mod tests {
+    use std::time::{Duration, Instant};
+
     use metrics::{core::Opts, IntCounterVec};
 
-    use super::TracingEventCountLayer;
+    use super::{BudgetState, ErrorBudget, ErrorBudgetLayer, TracingEventCountLayer};
 
     #[test]
     fn tracing_event_count_metric() {
@@ -244,4 +388,52 @@ mod tests {
         assert_eq!(counter_vec.with_label_values(&["warn"]).get(), 1);
         assert_eq!(counter_vec.with_label_values(&["error"]).get(), 1);
     }
+
+    fn budget(target: &str, max_errors: u64) -> ErrorBudget {
+        ErrorBudget {
+            target: target.to_owned(),
+            max_errors,
+            window: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn error_budget_windows() {
+        let budget = budget("pageserver::tenant", 2);
+        assert!(budget.covers("pageserver::tenant"));
+        assert!(budget.covers("pageserver::tenant::timeline"));
+        assert!(!budget.covers("pageserver::tenant_mgr"));
+        assert!(!budget.covers("pageserver"));
+
+        let state = BudgetState::new(budget);
+        let start = Instant::now();
+        let exceeded = (0..5).map(|_| state.record(start)).collect::<Vec<_>>();
+        assert_eq!(exceeded, [false, false, true, false, false]);
+
+        // The next window starts from scratch.
+        let next_window = start + Duration::from_secs(61);
+        assert!(!state.record(next_window));
+        assert!(!state.record(next_window));
+        assert!(state.record(next_window));
+    }
+
+    #[test]
+    fn error_budget_exceeded_metric() {
+        let counter_vec =
+            IntCounterVec::new(Opts::new("testmetric", "testhelp"), &["target"]).unwrap();
+        let counter_vec = Box::leak(Box::new(counter_vec)); // make it 'static
+        let layer = ErrorBudgetLayer::new(counter_vec, vec![budget("budgeted", 1)]);
+        use tracing_subscriber::prelude::*;
+
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            for _ in 0..3 {
+                tracing::warn!(target: "budgeted", "foo");
+                tracing::error!(target: "budgeted::submodule", "foo");
+                tracing::error!(target: "other", "foo");
+            }
+        });
+
+        assert_eq!(counter_vec.with_label_values(&["budgeted"]).get(), 1);
+        assert_eq!(counter_vec.with_label_values(&["other"]).get(), 0);
+    }
 }
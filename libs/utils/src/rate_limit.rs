@@ -31,6 +31,109 @@ impl RateLimit {
     }
 }
 
+/// Allows at most `max_per_window` events per `window`, and counts the
+/// events that were not allowed. Used by [`warn_rate_limited`] and friends,
+/// one per call-site.
+///
+/// [`warn_rate_limited`]: crate::warn_rate_limited
+pub struct RateLimitedLog {
+    max_per_window: u32,
+    window: Duration,
+    window_start: Option<Instant>,
+    allowed_in_window: u32,
+    suppressed: u64,
+}
+
+impl RateLimitedLog {
+    pub const fn new(max_per_window: u32, window: Duration) -> Self {
+        Self {
+            max_per_window,
+            window,
+            window_start: None,
+            allowed_in_window: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Returns `Some` if the event is allowed, with the number of events that
+    /// were suppressed since the previous allowed one.
+    pub fn check(&mut self) -> Option<u64> {
+        let now = Instant::now();
+        match self.window_start {
+            Some(start) if now - start < self.window => {}
+            _ => {
+                self.window_start = Some(now);
+                self.allowed_in_window = 0;
+            }
+        }
+        if self.allowed_in_window < self.max_per_window {
+            self.allowed_in_window += 1;
+            Some(std::mem::take(&mut self.suppressed))
+        } else {
+            self.suppressed += 1;
+            None
+        }
+    }
+}
+
+pub const DEFAULT_LOG_MAX_PER_WINDOW: u32 = 10;
+pub const DEFAULT_LOG_WINDOW: Duration = Duration::from_secs(60);
+
+#[doc(hidden)]
+pub use tracing as __tracing;
+
+/// Log an event at the given level, at most `max_per_window` times per `window`
+/// for this call-site, while always incrementing `counter`. Logged events carry
+/// a `suppressed` field with the number of events dropped since the previous one,
+/// so the counter (typically a prometheus metric) gives the real rate.
+///
+/// ```ignore
+/// log_rate_limited!(Level::WARN, 5, Duration::from_secs(10), MY_COUNTER, attempt, "failed: {e:#}");
+/// ```
+#[macro_export]
+macro_rules! log_rate_limited {
+    ($level:expr, $max_per_window:expr, $window:expr, $counter:expr, $($arg:tt)+) => {{
+        static RATE_LIMIT: ::std::sync::Mutex<$crate::rate_limit::RateLimitedLog> = ::std::sync::Mutex::new(
+            $crate::rate_limit::RateLimitedLog::new($max_per_window, $window),
+        );
+        $counter.inc();
+        let allowed = RATE_LIMIT.lock().unwrap().check();
+        if let Some(suppressed) = allowed {
+            $crate::rate_limit::__tracing::event!($level, suppressed, $($arg)+);
+        }
+    }};
+}
+
+/// [`log_rate_limited`] at WARN level, with the default limit of
+/// [`DEFAULT_LOG_MAX_PER_WINDOW`] events per [`DEFAULT_LOG_WINDOW`].
+#[macro_export]
+macro_rules! warn_rate_limited {
+    ($counter:expr, $($arg:tt)+) => {
+        $crate::log_rate_limited!(
+            $crate::rate_limit::__tracing::Level::WARN,
+            $crate::rate_limit::DEFAULT_LOG_MAX_PER_WINDOW,
+            $crate::rate_limit::DEFAULT_LOG_WINDOW,
+            $counter,
+            $($arg)+
+        )
+    };
+}
+
+/// [`log_rate_limited`] at ERROR level, with the default limit of
+/// [`DEFAULT_LOG_MAX_PER_WINDOW`] events per [`DEFAULT_LOG_WINDOW`].
+#[macro_export]
+macro_rules! error_rate_limited {
+    ($counter:expr, $($arg:tt)+) => {
+        $crate::log_rate_limited!(
+            $crate::rate_limit::__tracing::Level::ERROR,
+            $crate::rate_limit::DEFAULT_LOG_MAX_PER_WINDOW,
+            $crate::rate_limit::DEFAULT_LOG_WINDOW,
+            $counter,
+            $($arg)+
+        )
+    };
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::atomic::AtomicUsize;
@@ -63,4 +166,38 @@ mod tests {
         f.call(cl);
         assert_eq!(called.load(Relaxed), 3);
     }
+
+    #[test]
+    fn rate_limited_log() {
+        use super::RateLimitedLog;
+        use std::time::Duration;
+
+        let mut log = RateLimitedLog::new(2, Duration::from_millis(100));
+        assert_eq!(log.check(), Some(0));
+        assert_eq!(log.check(), Some(0));
+        assert_eq!(log.check(), None);
+        assert_eq!(log.check(), None);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(log.check(), Some(2));
+        assert_eq!(log.check(), Some(0));
+        assert_eq!(log.check(), None);
+    }
+
+    #[test]
+    fn rate_limited_macro_counts_everything() {
+        use std::sync::atomic::Ordering::Relaxed;
+
+        struct Counter(AtomicUsize);
+        impl Counter {
+            fn inc(&self) {
+                self.0.fetch_add(1, Relaxed);
+            }
+        }
+
+        let counter = Counter(AtomicUsize::new(0));
+        for i in 0..100 {
+            crate::warn_rate_limited!(counter, i, "rate limited test event");
+        }
+        assert_eq!(counter.0.load(Relaxed), 100);
+    }
 }
@@ -474,7 +474,7 @@ struct Request {
 
 impl Request {
     fn execute(self, manager: &PostgresRedoManager) -> Result<Bytes, WalRedoError> {
-        use pageserver::walredo::WalRedoManager;
+        use pageserver::walredo::{RedoTimings, WalRedoManager};
 
         let Request {
             key,
@@ -484,6 +484,14 @@ impl Request {
             pg_version,
         } = self;
 
-        manager.request_redo(key, lsn, base_img, records, pg_version)
+        // Each requester thread awaits its own requests
+        futures::executor::block_on(manager.request_redo(
+            key,
+            lsn,
+            base_img,
+            records,
+            pg_version,
+            &mut RedoTimings::default(),
+        ))
     }
 }
@@ -0,0 +1,32 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Record what the pageserver was built with, for `build_info.rs`.
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=PAGESERVER_RUSTC_VERSION={rustc_version}");
+
+    // Honor SOURCE_DATE_EPOCH for reproducible builds. Otherwise this is the
+    // time the build script last ran, which is close enough: it reruns whenever
+    // the pageserver package is rebuilt from scratch, e.g. in CI.
+    let build_timestamp = std::env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time before unix epoch")
+            .as_secs()
+            .to_string()
+    });
+    println!("cargo:rustc-env=PAGESERVER_BUILD_TIMESTAMP={build_timestamp}");
+
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-env-changed=RUSTC");
+    println!("cargo:rerun-if-changed=build.rs");
+}
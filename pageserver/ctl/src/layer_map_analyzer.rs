@@ -135,7 +135,7 @@ pub(crate) fn main(cmd: &AnalyzeLayerMapCmd) -> Result<()> {
 
     // Initialize virtual_file (file desriptor cache) and page cache which are needed to access layer persistent B-Tree.
     pageserver::virtual_file::init(10);
-    pageserver::page_cache::init(100);
+    pageserver::page_cache::init(100, None);
 
     let mut total_delta_layers = 0usize;
     let mut total_image_layers = 0usize;
@@ -49,7 +49,7 @@ fn read_delta_file(path: impl AsRef<Path>) -> Result<()> {
 
     let path = path.as_ref();
     virtual_file::init(10);
-    page_cache::init(100);
+    page_cache::init(100, None);
     let file = FileBlockReader::new(VirtualFile::open(path)?);
     let summary_blk = file.read_blk(0)?;
     let actual_summary = Summary::des_prefix(summary_blk.as_ref())?;
@@ -116,7 +116,7 @@ fn read_pg_control_file(control_file_path: &Path) -> anyhow::Result<()> {
 fn print_layerfile(path: &Path) -> anyhow::Result<()> {
     // Basic initialization of things that don't change after startup
     virtual_file::init(10);
-    page_cache::init(100);
+    page_cache::init(100, None);
     let ctx = RequestContext::new(TaskKind::DebugTool, DownloadBehavior::Error);
     dump_layerfile_from_path(path, true, &ctx)
 }
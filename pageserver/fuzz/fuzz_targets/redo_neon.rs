@@ -0,0 +1,78 @@
+//! Redo arbitrary records in neon, over an arbitrary page, see
+//! `pageserver::walredo::fuzzing::redo_neon`.
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use pageserver::walredo::fuzzing::{self, FuzzPage};
+use pageserver_api::reltag::{RelTag, SlruKind};
+use pageserver_fuzz::Record;
+
+#[derive(Debug, Arbitrary)]
+enum Page {
+    Rel {
+        spcnode: u32,
+        dbnode: u32,
+        relnode: u32,
+        forknum: u8,
+        blkno: u32,
+    },
+    Clog(u32),
+    MultiXactMembers(u32),
+    MultiXactOffsets(u32),
+}
+
+impl From<&Page> for FuzzPage {
+    fn from(page: &Page) -> Self {
+        match *page {
+            Page::Rel {
+                spcnode,
+                dbnode,
+                relnode,
+                forknum,
+                blkno,
+            } => FuzzPage::Rel {
+                rel: RelTag {
+                    // The main, FSM, visibility map and init forks
+                    forknum: forknum % 4,
+                    spcnode,
+                    dbnode,
+                    relnode,
+                },
+                blkno,
+            },
+            Page::Clog(pageno) => FuzzPage::Slru {
+                kind: SlruKind::Clog,
+                pageno,
+            },
+            Page::MultiXactMembers(pageno) => FuzzPage::Slru {
+                kind: SlruKind::MultiXactMembers,
+                pageno,
+            },
+            Page::MultiXactOffsets(pageno) => FuzzPage::Slru {
+                kind: SlruKind::MultiXactOffsets,
+                pageno,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    page: Page,
+    base_img: Option<Vec<u8>>,
+    records: Vec<(u16, Record)>,
+    v15: bool,
+}
+
+fuzz_target!(|input: Input| {
+    let records = pageserver_fuzz::records(&input.records);
+    let pg_version = pageserver_fuzz::pg_version(input.v15);
+    // Failing to redo the records is fine, panicking isn't
+    let _ = fuzzing::redo_neon(
+        FuzzPage::from(&input.page),
+        input.base_img.as_deref(),
+        records,
+        pg_version,
+    );
+});
@@ -0,0 +1,46 @@
+//! Encode arbitrary requests to the WAL redo process, see
+//! `pageserver::walredo::fuzzing::encode_request`.
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use pageserver::walredo::{fuzzing, BufferTag};
+use pageserver_api::reltag::RelTag;
+use pageserver_fuzz::Record;
+
+#[derive(Debug, Arbitrary)]
+struct Page {
+    spcnode: u32,
+    dbnode: u32,
+    relnode: u32,
+    forknum: u8,
+    blknum: u32,
+    base_img: Option<Vec<u8>>,
+    records: Vec<(u16, Record)>,
+}
+
+impl Page {
+    fn tag(&self) -> BufferTag {
+        BufferTag {
+            rel: RelTag {
+                forknum: self.forknum,
+                spcnode: self.spcnode,
+                dbnode: self.dbnode,
+                relnode: self.relnode,
+            },
+            blknum: self.blknum,
+        }
+    }
+}
+
+fuzz_target!(|pages: Vec<Page>| {
+    let pages = pages
+        .iter()
+        .map(|page| {
+            let records = pageserver_fuzz::records(&page.records);
+            (page.tag(), page.base_img.as_deref(), records)
+        })
+        .collect::<Vec<_>>();
+    // The requests with neon records fail, which encode_request checks
+    let _ = fuzzing::encode_request(&pages);
+});
@@ -0,0 +1,93 @@
+//! The inputs of the fuzz targets, and the records they stand for.
+
+use arbitrary::Arbitrary;
+use bytes::Bytes;
+use pageserver::walrecord::{MultiXactMember, NeonWalRecord};
+use utils::lsn::Lsn;
+
+/// A [`NeonWalRecord`]. The Postgres records are raw bytes, which the fuzzer turns into
+/// records whose header decodes more often than not.
+#[derive(Debug, Clone, Arbitrary)]
+pub enum Record {
+    Postgres {
+        will_init: bool,
+        rec: Vec<u8>,
+    },
+    ClearVisibilityMapFlags {
+        new_heap_blkno: Option<u32>,
+        old_heap_blkno: Option<u32>,
+        flags: u8,
+    },
+    ClogSetCommitted {
+        xids: Vec<u32>,
+        timestamp: i64,
+    },
+    ClogSetAborted {
+        xids: Vec<u32>,
+    },
+    MultixactOffsetCreate {
+        mid: u32,
+        moff: u32,
+    },
+    MultixactMembersCreate {
+        moff: u32,
+        members: Vec<(u32, u32)>,
+    },
+}
+
+impl From<Record> for NeonWalRecord {
+    fn from(rec: Record) -> Self {
+        match rec {
+            Record::Postgres { will_init, rec } => NeonWalRecord::Postgres {
+                will_init,
+                rec: Bytes::from(rec),
+            },
+            Record::ClearVisibilityMapFlags {
+                new_heap_blkno,
+                old_heap_blkno,
+                flags,
+            } => NeonWalRecord::ClearVisibilityMapFlags {
+                new_heap_blkno,
+                old_heap_blkno,
+                flags,
+            },
+            Record::ClogSetCommitted { xids, timestamp } => {
+                NeonWalRecord::ClogSetCommitted { xids, timestamp }
+            }
+            Record::ClogSetAborted { xids } => NeonWalRecord::ClogSetAborted { xids },
+            Record::MultixactOffsetCreate { mid, moff } => {
+                NeonWalRecord::MultixactOffsetCreate { mid, moff }
+            }
+            Record::MultixactMembersCreate { moff, members } => {
+                NeonWalRecord::MultixactMembersCreate {
+                    moff,
+                    members: members
+                        .into_iter()
+                        .map(|(xid, status)| MultiXactMember { xid, status })
+                        .collect(),
+                }
+            }
+        }
+    }
+}
+
+/// The records, each at the given distance from the LSN of the one before.
+pub fn records(records: &[(u16, Record)]) -> Vec<(Lsn, NeonWalRecord)> {
+    let mut lsn = Lsn(0);
+    records
+        .iter()
+        .map(|(distance, rec)| {
+            lsn += *distance as u64;
+            (lsn, rec.clone().into())
+        })
+        .collect()
+}
+
+/// The Postgres version of the records.
+pub fn pg_version(v15: bool) -> u32 {
+    if v15 {
+        15
+    } else {
+        14
+    }
+}
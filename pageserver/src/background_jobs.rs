@@ -0,0 +1,465 @@
+//!
+//! Common driver of the periodic background jobs: compaction, GC, layer eviction,
+//! disk space monitoring, remote storage scrub, WAL redo ping, consumption metrics and
+//! metrics push.
+//!
+//! A job implements [`BackgroundJob`] and is run by [`run_job`], which takes care of
+//! the cadence, the jitter, the concurrency limits, the period overrun warnings and
+//! the per-job metrics. By default, a job runs with the period of its own setting,
+//! e.g. `compaction_period` in the tenant config, without jitter and without a
+//! concurrency limit, except for the jobs in [`DEFAULT_CONCURRENCY_CLASSES`]. This can be
+//! tuned in the `background_jobs` section of the pageserver config:
+//!
+//! ```toml
+//! [background_jobs]
+//! concurrency_limits = { heavy = 4 }
+//!
+//! [background_jobs.jobs.compaction]
+//! jitter = '10s'
+//! concurrency_class = 'heavy'
+//!
+//! [background_jobs.jobs.gc]
+//! concurrency_class = 'heavy'
+//! ```
+//!
+//! Compaction and GC wait their period after the end of each iteration, the other jobs
+//! start an iteration every period, as their own loops did before.
+//!
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, ensure};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Deserialize;
+use strum_macros::{EnumVariantNames, IntoStaticStr};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::*;
+
+use crate::metrics::{BACKGROUND_JOB_FAILURES, BACKGROUND_JOB_LAST_RUN, BACKGROUND_JOB_SECONDS};
+use crate::tenant::tasks::{random_init_delay, warn_when_period_overrun};
+
+/// How often a disabled job checks whether it got enabled again.
+const DISABLED_JOB_RECHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The concurrency class and limit of the jobs that are limited even if the config doesn't
+/// set a class for them.
+///
+/// The WAL redo ping of every tenant runs at its activation, so at startup all of them would
+/// launch a WAL redo process at once.
+const DEFAULT_CONCURRENCY_CLASSES: &[(BackgroundJobKind, &str, usize)] =
+    &[(BackgroundJobKind::WalRedoPing, "wal_redo_ping", 4)];
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, IntoStaticStr, EnumVariantNames,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum BackgroundJobKind {
+    Compaction,
+    Gc,
+    /// The per-timeline layer eviction, see [`crate::tenant::timeline::eviction_task`].
+    Eviction,
+    DiskUsageEviction,
+    DiskSpaceMonitor,
+    /// The pageserver-wide scrub of the remote storage, see [`crate::remote_scrubber`].
+    RemoteScrub,
+    /// The per-tenant deletion of remote layer files after their grace period, see
+    /// [`crate::tenant::deferred_deletion`].
+    DeferredDeletion,
+    /// The per-tenant move of the old layer files to the cold tier of the remote storage.
+    Tiering,
+    /// The per-tenant check of the WAL redo, see [`crate::walredo::PostgresRedoManager::ping`].
+    WalRedoPing,
+    ConsumptionMetrics,
+    SyntheticSize,
+    MetricsPush,
+}
+
+impl BackgroundJobKind {
+    /// Per-tenant jobs take their period from the tenant config.
+    fn is_per_tenant(self) -> bool {
+        matches!(
+            self,
+            Self::Compaction | Self::Gc | Self::Eviction | Self::Tiering
+        )
+    }
+
+    /// Delay the first iteration by a random fraction of the period, so that the jobs
+    /// of all tenants, which are started together, don't run in lockstep.
+    fn spread_first_run(self) -> bool {
+        matches!(
+            self,
+            Self::Compaction
+                | Self::Gc
+                | Self::Eviction
+                | Self::DiskUsageEviction
+                | Self::DeferredDeletion
+                | Self::Tiering
+        )
+    }
+
+    /// Wait the period after the end of an iteration rather than from its start, so that a
+    /// long iteration isn't followed right away by the next one.
+    fn period_from_end(self) -> bool {
+        matches!(self, Self::Compaction | Self::Gc)
+    }
+
+    /// Log at info level, rather than debug, each time the job finds itself disabled. The
+    /// other jobs are disabled by default, e.g. cold tiering, and would log for every tenant.
+    fn log_disabled_at_info(self) -> bool {
+        matches!(self, Self::Compaction | Self::Gc)
+    }
+
+    /// Retry failed iterations of these sooner than the period.
+    fn retry_after_failure(self) -> Option<Duration> {
+        match self {
+            Self::Compaction | Self::Gc => Some(Duration::from_secs(2)),
+            _ => None,
+        }
+    }
+
+    /// Name in the error log of failed iterations.
+    fn log_name(self) -> &'static str {
+        match self {
+            Self::Compaction => "Compaction",
+            Self::Gc => "Gc",
+            Self::Eviction => "Layer eviction",
+            Self::DiskUsageEviction => "Disk usage based eviction",
+            Self::DiskSpaceMonitor => "Disk space monitoring",
+            Self::RemoteScrub => "Remote storage scrub",
+            Self::DeferredDeletion => "Deferred deletion",
+            Self::Tiering => "Cold tiering",
+            Self::WalRedoPing => "WAL redo ping",
+            Self::ConsumptionMetrics => "Consumption metrics collection",
+            Self::SyntheticSize => "Synthetic size calculation",
+            Self::MetricsPush => "Metrics push",
+        }
+    }
+}
+
+impl std::fmt::Display for BackgroundJobKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name: &'static str = (*self).into();
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackgroundJobsConfig {
+    /// Maximum number of concurrently running iterations, by concurrency class.
+    #[serde(default)]
+    pub concurrency_limits: HashMap<String, NonZeroUsize>,
+    #[serde(default)]
+    pub jobs: HashMap<BackgroundJobKind, BackgroundJobConfig>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackgroundJobConfig {
+    /// Overrides the job's own period setting. Only for pageserver-wide jobs, the period
+    /// of per-tenant jobs is part of the tenant config.
+    #[serde(default, with = "humantime_serde")]
+    pub period: Option<Duration>,
+    /// Delay each iteration by a random duration up to this.
+    #[serde(default, with = "humantime_serde")]
+    pub jitter: Option<Duration>,
+    /// Concurrency class in `concurrency_limits`, shared with other jobs or not.
+    #[serde(default)]
+    pub concurrency_class: Option<String>,
+}
+
+impl BackgroundJobsConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (kind, job) in &self.jobs {
+            if job.period.is_some() && kind.is_per_tenant() {
+                bail!("the period of {kind} can only be set in the tenant config");
+            }
+            if let Some(class) = &job.concurrency_class {
+                ensure!(
+                    self.concurrency_limits.contains_key(class),
+                    "concurrency class '{class}' of {kind} is not in concurrency_limits"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Scheduler {
+    config: BackgroundJobsConfig,
+    concurrency_limits: HashMap<String, Semaphore>,
+}
+
+impl Scheduler {
+    fn new(mut config: BackgroundJobsConfig) -> Self {
+        for &(kind, class, limit) in DEFAULT_CONCURRENCY_CLASSES {
+            let job = config.jobs.entry(kind).or_default();
+            if job.concurrency_class.is_none() {
+                job.concurrency_class = Some(class.to_string());
+                config
+                    .concurrency_limits
+                    .entry(class.to_string())
+                    .or_insert(NonZeroUsize::new(limit).expect("limits are not zero"));
+            }
+        }
+        let concurrency_limits = config
+            .concurrency_limits
+            .iter()
+            .map(|(class, limit)| (class.clone(), Semaphore::new(limit.get())))
+            .collect();
+        Scheduler {
+            config,
+            concurrency_limits,
+        }
+    }
+
+    fn job_config(&self, kind: BackgroundJobKind) -> &BackgroundJobConfig {
+        static DEFAULT: Lazy<BackgroundJobConfig> = Lazy::new(BackgroundJobConfig::default);
+        self.config.jobs.get(&kind).unwrap_or(&DEFAULT)
+    }
+
+    async fn acquire(&self, job_config: &BackgroundJobConfig) -> Option<SemaphorePermit<'_>> {
+        let semaphore = self
+            .concurrency_limits
+            .get(job_config.concurrency_class.as_ref()?)?;
+        Some(semaphore.acquire().await.expect("never closed"))
+    }
+}
+
+static SCHEDULER: OnceCell<Scheduler> = OnceCell::new();
+
+/// Must be called at startup, before any job is started, for the config to apply.
+pub fn set_config(config: &BackgroundJobsConfig) -> anyhow::Result<()> {
+    config.validate()?;
+    SCHEDULER
+        .set(Scheduler::new(config.clone()))
+        .map_err(|_| anyhow::anyhow!("background jobs were already configured or started"))
+}
+
+fn scheduler() -> &'static Scheduler {
+    // Initializing here makes a later set_config() call fail, rather than silently
+    // not apply to the jobs already running.
+    SCHEDULER.get_or_init(|| Scheduler::new(BackgroundJobsConfig::default()))
+}
+
+/// A periodic background job, run by [`run_job`].
+#[async_trait::async_trait]
+pub(crate) trait BackgroundJob: Send {
+    /// The period from the job's own setting, read before each iteration so that
+    /// changes apply without a restart. `Duration::ZERO` disables the job.
+    fn period(&self) -> Duration;
+
+    /// Wait until the job is able to run, e.g. until the tenant is active.
+    /// `Break` stops the job.
+    async fn wait_until_ready(&mut self) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    async fn iteration(&mut self, cancel: &CancellationToken) -> anyhow::Result<()>;
+}
+
+/// Run `job` every period until `cancel` is cancelled.
+pub(crate) async fn run_job(
+    kind: BackgroundJobKind,
+    mut job: impl BackgroundJob,
+    cancel: &CancellationToken,
+) {
+    let scheduler = scheduler();
+    let job_config = scheduler.job_config(kind);
+    let kind_str: &'static str = kind.into();
+    let iteration_seconds = BACKGROUND_JOB_SECONDS.with_label_values(&[kind_str]);
+    let failures = BACKGROUND_JOB_FAILURES.with_label_values(&[kind_str]);
+    let last_run = BACKGROUND_JOB_LAST_RUN.with_label_values(&[kind_str]);
+
+    let mut first = true;
+    loop {
+        trace!("waking up");
+
+        tokio::select! {
+            _ = cancel.cancelled() => {
+                info!("received cancellation request");
+                return;
+            },
+            ready = job.wait_until_ready() => match ready {
+                ControlFlow::Break(()) => return,
+                ControlFlow::Continue(()) => (),
+            },
+        }
+
+        let period = job_config.period.unwrap_or_else(|| job.period());
+
+        if first {
+            first = false;
+            if kind.spread_first_run() && random_init_delay(period, cancel).await.is_err() {
+                return;
+            }
+        }
+
+        let started_at = Instant::now();
+
+        let sleep_until = if period == Duration::ZERO {
+            if kind.log_disabled_at_info() {
+                info!("automatic {kind} is disabled");
+            } else {
+                debug!("automatic {kind} is disabled");
+            }
+            // check again later, in case it's been enabled again.
+            started_at + DISABLED_JOB_RECHECK_INTERVAL
+        } else {
+            let res = {
+                let _permit = tokio::select! {
+                    _ = cancel.cancelled() => {
+                        info!("received cancellation request while waiting for a concurrency permit");
+                        return;
+                    },
+                    permit = scheduler.acquire(job_config) => permit,
+                };
+                let _timer = iteration_seconds.start_timer();
+                job.iteration(cancel).await
+            };
+            last_run.set(
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.as_secs() as i64)
+                    .unwrap_or_default(),
+            );
+
+            let next = match res {
+                Ok(()) if kind.period_from_end() => Instant::now() + period,
+                Ok(()) => started_at + period,
+                Err(e) => {
+                    failures.inc();
+                    let retry = kind.retry_after_failure().unwrap_or(period);
+                    error!("{} failed, retrying in {:?}: {e:?}", kind.log_name(), retry);
+                    Instant::now() + retry
+                }
+            };
+            warn_when_period_overrun(started_at.elapsed(), period, kind_str);
+            next
+        };
+
+        let jitter = match job_config.jitter {
+            Some(jitter) if jitter > Duration::ZERO => {
+                use rand::Rng;
+                rand::thread_rng().gen_range(Duration::ZERO..=jitter)
+            }
+            _ => Duration::ZERO,
+        };
+
+        tokio::select! {
+            _ = cancel.cancelled() => {
+                info!("received cancellation request during idling");
+                return;
+            },
+            _ = tokio::time::sleep_until(sleep_until + jitter) => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_validate_config() {
+        let config: BackgroundJobsConfig = toml_edit::de::from_str(
+            r#"
+            concurrency_limits = { heavy = 2 }
+
+            [jobs.compaction]
+            jitter = '10s'
+            concurrency_class = 'heavy'
+
+            [jobs.metrics_push]
+            period = '1m'
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(
+            config.jobs[&BackgroundJobKind::Compaction],
+            BackgroundJobConfig {
+                period: None,
+                jitter: Some(Duration::from_secs(10)),
+                concurrency_class: Some("heavy".to_string()),
+            }
+        );
+        assert_eq!(
+            config.jobs[&BackgroundJobKind::MetricsPush].period,
+            Some(Duration::from_secs(60))
+        );
+
+        let per_tenant_period: BackgroundJobsConfig =
+            toml_edit::de::from_str("[jobs.gc]\nperiod = '1h'").unwrap();
+        assert!(per_tenant_period.validate().is_err());
+
+        let unknown_class: BackgroundJobsConfig =
+            toml_edit::de::from_str("[jobs.gc]\nconcurrency_class = 'heavy'").unwrap();
+        assert!(unknown_class.validate().is_err());
+
+        toml_edit::de::from_str::<BackgroundJobsConfig>("[jobs.scrubber]\njitter = '1s'")
+            .expect_err("unknown job");
+    }
+
+    #[test]
+    fn default_concurrency_classes() {
+        let scheduler = Scheduler::new(BackgroundJobsConfig::default());
+        let ping = scheduler.job_config(BackgroundJobKind::WalRedoPing);
+        assert_eq!(ping.concurrency_class.as_deref(), Some("wal_redo_ping"));
+        assert_eq!(scheduler.concurrency_limits["wal_redo_ping"].available_permits(), 4);
+
+        // A class from the config takes the place of the default one
+        let config: BackgroundJobsConfig = toml_edit::de::from_str(
+            r#"
+            concurrency_limits = { light = 16 }
+
+            [jobs.wal_redo_ping]
+            concurrency_class = 'light'
+            "#,
+        )
+        .unwrap();
+        let scheduler = Scheduler::new(config);
+        let ping = scheduler.job_config(BackgroundJobKind::WalRedoPing);
+        assert_eq!(ping.concurrency_class.as_deref(), Some("light"));
+        assert!(!scheduler.concurrency_limits.contains_key("wal_redo_ping"));
+    }
+
+    struct FlakyJob {
+        iterations: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl BackgroundJob for FlakyJob {
+        fn period(&self) -> Duration {
+            Duration::from_millis(1)
+        }
+
+        async fn iteration(&mut self, cancel: &CancellationToken) -> anyhow::Result<()> {
+            self.iterations += 1;
+            if self.iterations == 3 {
+                cancel.cancel();
+            }
+            anyhow::ensure!(self.iterations != 1, "first iteration fails");
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn failures_are_counted() {
+        let kind = BackgroundJobKind::SyntheticSize;
+        let kind_str: &'static str = kind.into();
+        let failures = BACKGROUND_JOB_FAILURES.with_label_values(&[kind_str]);
+        let failures_before = failures.get();
+
+        let cancel = CancellationToken::new();
+        run_job(kind, FlakyJob { iterations: 0 }, &cancel).await;
+
+        assert_eq!(failures.get() - failures_before, 1);
+        assert!(BACKGROUND_JOB_LAST_RUN.with_label_values(&[kind_str]).get() > 0);
+    }
+}
@@ -27,19 +27,25 @@ use tracing::*;
 ///
 use tokio_tar::{Builder, EntryType, Header};
 
+use crate::catalog::PgExtension;
 use crate::context::RequestContext;
 use crate::tenant::Timeline;
+use crate::timescaledb;
 use pageserver_api::reltag::{RelTag, SlruKind};
 
 use postgres_ffi::pg_constants::{DEFAULTTABLESPACE_OID, GLOBALTABLESPACE_OID};
 use postgres_ffi::pg_constants::{PGDATA_SPECIAL_FILES, PGDATA_SUBDIRS, PG_HBA};
 use postgres_ffi::relfile_utils::{INIT_FORKNUM, MAIN_FORKNUM};
+use postgres_ffi::Oid;
 use postgres_ffi::TransactionId;
 use postgres_ffi::XLogFileName;
 use postgres_ffi::PG_TLI;
 use postgres_ffi::{BLCKSZ, RELSEG_SIZE, WAL_SEGMENT_SIZE};
 use utils::lsn::Lsn;
 
+/// Number of blocks of a relation read together, see [`Timeline::get_rel_pages_at_lsn`].
+const READ_BATCH_BLOCKS: u32 = 32;
+
 /// Create basebackup with non-rel data in it.
 /// Only include relational data if 'full_backup' is true.
 ///
@@ -121,6 +127,68 @@ where
         .await
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum BasebackupError {
+    #[error(
+        "database {dbnode} has {extname} {extversion} installed, but the compute ships {}",
+        fmt_versions(.available)
+    )]
+    UnavailableExtensionVersion {
+        dbnode: Oid,
+        extname: String,
+        extversion: String,
+        available: Vec<String>,
+    },
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+fn fmt_versions(versions: &[String]) -> String {
+    if versions.is_empty() {
+        "no versions of it".to_owned()
+    } else {
+        format!("versions {}", versions.join(", "))
+    }
+}
+
+/// Check that the compute ships the versions of TimescaleDB installed in the databases.
+///
+/// A compute without the library of the installed version fails only when a backend first
+/// loads it, with an error that doesn't point at the compute image, so the check is done
+/// before sending the basebackup.
+pub async fn check_extension_versions(
+    timeline: &Timeline,
+    lsn: Lsn,
+    timescaledb_versions: &[String],
+    ctx: &RequestContext,
+) -> Result<(), BasebackupError> {
+    let dbdirs = timeline
+        .list_dbdirs(lsn, ctx)
+        .await
+        .context("list databases")?;
+    for ((spcnode, dbnode), has_relmap_file) in dbdirs {
+        if !has_relmap_file {
+            continue;
+        }
+        let extensions = PgExtension::load_all(timeline, spcnode, dbnode, lsn, ctx)
+            .await
+            .with_context(|| format!("read extensions of database {dbnode}"))?;
+        for extension in extensions.into_iter().flatten() {
+            if extension.extname == timescaledb::EXTENSION_NAME
+                && !timescaledb_versions.contains(&extension.extversion)
+            {
+                return Err(BasebackupError::UnavailableExtensionVersion {
+                    dbnode,
+                    extname: extension.extname,
+                    extversion: extension.extversion,
+                    available: timescaledb_versions.to_vec(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
 /// This is short-living object only for the time of tarball creation,
 /// created mostly to avoid passing a lot of parameters between various functions
 /// used for constructing tarball.
@@ -260,12 +328,17 @@ where
             let endblk = std::cmp::min(startblk + RELSEG_SIZE, nblocks);
 
             let mut segment_data: Vec<u8> = vec![];
-            for blknum in startblk..endblk {
-                let img = self
+            let mut blknum = startblk;
+            while blknum < endblk {
+                let batch_end = std::cmp::min(blknum + READ_BATCH_BLOCKS, endblk);
+                let imgs = self
                     .timeline
-                    .get_rel_page_at_lsn(src, blknum, self.lsn, false, self.ctx)
+                    .get_rel_pages_at_lsn(src, blknum..batch_end, self.lsn, false, self.ctx)
                     .await?;
-                segment_data.extend_from_slice(&img[..]);
+                for img in imgs {
+                    segment_data.extend_from_slice(&img[..]);
+                }
+                blknum = batch_end;
             }
 
             let file_name = dst.to_segfile_name(seg as u32);
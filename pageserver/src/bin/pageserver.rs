@@ -2,45 +2,64 @@
 
 use std::env::{var, VarError};
 use std::sync::Arc;
+use std::path::PathBuf;
 use std::{env, ops::ControlFlow, path::Path, str::FromStr};
 
 use anyhow::{anyhow, Context};
 use clap::{Arg, ArgAction, Command};
 use fail::FailScenario;
 use metrics::launch_timestamp::{set_launch_timestamp_metric, LaunchTimestamp};
+use pageserver::config_check::{self, Report};
+use pageserver::disk_space_monitor;
 use pageserver::disk_usage_eviction_task::{self, launch_disk_usage_global_eviction_task};
+use pageserver::remote_scrubber;
 use pageserver::task_mgr::WALRECEIVER_RUNTIME;
 use remote_storage::GenericRemoteStorage;
 use tracing::*;
+use tracing_utils::OpenTelemetryLayer;
 
 use metrics::set_build_info_metric;
 use pageserver::{
+    build_info::{FEATURES, GIT_VERSION},
     config::{defaults::*, PageServerConf},
     context::{DownloadBehavior, RequestContext},
-    http, page_cache, page_service, task_mgr,
+    http, page_cache, page_service, redo_fixture,
+    repository::Key,
+    task_mgr,
     task_mgr::TaskKind,
     task_mgr::{BACKGROUND_RUNTIME, COMPUTE_REQUEST_RUNTIME, MGMT_REQUEST_RUNTIME},
-    tenant::mgr,
+    tenant::{self, mgr},
     virtual_file,
 };
 use postgres_backend::AuthType;
 use utils::logging::TracingErrorLayerEnablement;
 use utils::signals::ShutdownSignals;
-use utils::{
-    auth::JwtAuth, logging, project_git_version, sentry_init::init_sentry, signals::Signal,
-    tcp_listener,
-};
-
-project_git_version!(GIT_VERSION);
+use utils::{auth::JwtAuth, logging, sentry_init::init_sentry, signals::Signal, tcp_listener};
+use utils::id::{TenantId, TimelineId};
+use utils::lsn::Lsn;
 
 const PID_FILE_NAME: &str = "pageserver.pid";
 
-const FEATURES: &[&str] = &[
-    #[cfg(feature = "testing")]
-    "testing",
-    #[cfg(feature = "fail/failpoints")]
-    "fail/failpoints",
-];
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+/// Pointer to a static, NUL-terminated C string.
+#[cfg(feature = "jemalloc")]
+#[repr(transparent)]
+pub struct MallocConf(*const std::ffi::c_char);
+
+#[cfg(feature = "jemalloc")]
+// SAFETY: points to immutable static data.
+unsafe impl Sync for MallocConf {}
+
+/// Read by jemalloc at startup. Sample an allocation every 2 MiB on average for
+/// the heap profiles served by the `/v1/debug/heap_profile` endpoint, which is
+/// cheap enough to keep on all the time.
+#[cfg(feature = "jemalloc")]
+#[export_name = "_rjem_malloc_conf"]
+pub static MALLOC_CONF: MallocConf =
+    MallocConf(b"prof:true,prof_active:true,lg_prof_sample:21\0".as_ptr().cast());
 
 fn version() -> String {
     format!(
@@ -78,6 +97,13 @@ fn main() -> anyhow::Result<()> {
         )
     })?;
 
+    if arg_matches.subcommand_matches("check-config").is_some() {
+        return check_config(&cfg_file_path, &arg_matches, &workdir);
+    }
+    if let Some(sub_matches) = arg_matches.subcommand_matches("capture-redo-fixture") {
+        return capture_redo_fixture(&cfg_file_path, &arg_matches, sub_matches, &workdir);
+    }
+
     let conf = match initialize_config(&cfg_file_path, arg_matches, &workdir)? {
         ControlFlow::Continue(conf) => conf,
         ControlFlow::Break(()) => {
@@ -98,7 +124,20 @@ fn main() -> anyhow::Result<()> {
     } else {
         TracingErrorLayerEnablement::Disabled
     };
-    logging::init(conf.log_format, tracing_error_layer_enablement)?;
+    // Export the spans as OpenTelemetry traces if an OTLP endpoint is set, see `tracing_utils`.
+    // Their trace ids are the exemplars of some histograms, see `metrics::exemplars`.
+    let otlp_layer = if tracing_utils::otlp_endpoint_configured() {
+        tracing_utils::init_tracing_without_runtime("pageserver")
+            .map(|tracer| Box::new(OpenTelemetryLayer::new(tracer)) as logging::OtlpLayer)
+    } else {
+        None
+    };
+    logging::init(
+        conf.log_format,
+        tracing_error_layer_enablement,
+        conf.error_budgets.clone(),
+        otlp_layer,
+    )?;
 
     // mind the order required here: 1. logging, 2. panic_hook, 3. sentry.
     // disarming this hook on pageserver, because we never tear down tracing.
@@ -125,7 +164,7 @@ fn main() -> anyhow::Result<()> {
 
     // Basic initialization of things that don't change after startup
     virtual_file::init(conf.max_file_descriptors);
-    page_cache::init(conf.page_cache_size);
+    page_cache::init(conf.page_cache_size, conf.materialized_page_cache_size);
 
     start_pageserver(launch_ts, conf).context("Failed to start pageserver")?;
 
@@ -141,6 +180,42 @@ fn initialize_config(
     let init = arg_matches.get_flag("init");
     let update_config = init || arg_matches.get_flag("update-config");
 
+    let toml = read_config(cfg_file_path, &arg_matches, init, update_config)?;
+
+    debug!("Resulting toml: {toml}");
+    let conf = PageServerConf::parse_and_validate(&toml, workdir)
+        .context("Failed to parse pageserver configuration")?;
+
+    if update_config {
+        info!("Writing pageserver config to '{}'", cfg_file_path.display());
+
+        std::fs::write(cfg_file_path, toml.to_string()).with_context(|| {
+            format!(
+                "Failed to write pageserver config to '{}'",
+                cfg_file_path.display()
+            )
+        })?;
+        info!(
+            "Config successfully written to '{}'",
+            cfg_file_path.display()
+        )
+    }
+
+    Ok(if init {
+        ControlFlow::Break(())
+    } else {
+        ControlFlow::Continue(Box::leak(Box::new(conf)))
+    })
+}
+
+/// Read the config file, or the built-in default config when initializing, and apply
+/// the config overrides from the command line.
+fn read_config(
+    cfg_file_path: &Path,
+    arg_matches: &clap::ArgMatches,
+    init: bool,
+    update_config: bool,
+) -> anyhow::Result<toml_edit::Document> {
     let (mut toml, config_file_exists) = if cfg_file_path.is_file() {
         if init {
             anyhow::bail!(
@@ -196,30 +271,74 @@ fn initialize_config(
         }
     }
 
-    debug!("Resulting toml: {toml}");
-    let conf = PageServerConf::parse_and_validate(&toml, workdir)
-        .context("Failed to parse pageserver configuration")?;
+    Ok(toml)
+}
 
-    if update_config {
-        info!("Writing pageserver config to '{}'", cfg_file_path.display());
+/// `pageserver check-config`: parse the config without starting the pageserver, run the
+/// semantic checks of [`config_check`], print the diagnostics as JSON, and exit with
+/// a non-zero exit code if there are errors.
+fn check_config(
+    cfg_file_path: &Path,
+    arg_matches: &clap::ArgMatches,
+    workdir: &Path,
+) -> anyhow::Result<()> {
+    let conf = if cfg_file_path.is_file() {
+        read_config(cfg_file_path, arg_matches, false, false)
+            .and_then(|toml| PageServerConf::parse_and_validate(&toml, workdir))
+    } else {
+        Err(anyhow!("Config file '{}' not found", cfg_file_path.display()))
+    };
 
-        std::fs::write(cfg_file_path, toml.to_string()).with_context(|| {
-            format!(
-                "Failed to write pageserver config to '{}'",
-                cfg_file_path.display()
-            )
-        })?;
-        info!(
-            "Config successfully written to '{}'",
-            cfg_file_path.display()
-        )
+    let report = match conf {
+        Ok(conf) => BACKGROUND_RUNTIME.block_on(config_check::check_config(&conf)),
+        Err(e) => Report::parse_error(&e),
+    };
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !report.ok {
+        std::process::exit(1);
     }
+    Ok(())
+}
 
-    Ok(if init {
-        ControlFlow::Break(())
-    } else {
-        ControlFlow::Continue(Box::leak(Box::new(conf)))
-    })
+/// `pageserver capture-redo-fixture`: capture the WAL redo request of a key at an LSN from
+/// the local layer files of a timeline, for the walredo tests, see [`redo_fixture`].
+fn capture_redo_fixture(
+    cfg_file_path: &Path,
+    arg_matches: &clap::ArgMatches,
+    sub_matches: &clap::ArgMatches,
+    workdir: &Path,
+) -> anyhow::Result<()> {
+    let toml = read_config(cfg_file_path, arg_matches, false, false)?;
+    let conf = PageServerConf::parse_and_validate(&toml, workdir)
+        .context("Failed to parse pageserver configuration")?;
+    let conf: &'static PageServerConf = Box::leak(Box::new(conf));
+
+    let arg = |name: &str| {
+        sub_matches
+            .get_one::<String>(name)
+            .map(String::as_str)
+            .expect("required argument")
+    };
+    let tenant_id = TenantId::from_str(arg("tenant-id")).context("parse tenant id")?;
+    let timeline_id = TimelineId::from_str(arg("timeline-id")).context("parse timeline id")?;
+    let key = Key::from_hex(arg("key")).context("parse key")?;
+    let lsn = Lsn::from_str(arg("lsn")).context("parse lsn")?;
+    let output = sub_matches
+        .get_one::<PathBuf>("output")
+        .expect("required argument");
+
+    virtual_file::init(conf.max_file_descriptors);
+    page_cache::init(conf.page_cache_size, conf.materialized_page_cache_size);
+
+    let (fixture_path, page_path) =
+        redo_fixture::capture_to_files(conf, tenant_id, timeline_id, key, lsn, output)?;
+    println!(
+        "Wrote the redo request to '{}' and the page to '{}'",
+        fixture_path.display(),
+        page_path.display()
+    );
+    Ok(())
 }
 
 fn start_pageserver(
@@ -236,8 +355,12 @@ fn start_pageserver(
         launch_ts.to_string()
     );
     set_build_info_metric(GIT_VERSION);
+    pageserver::build_info::set_build_info_metric(conf);
     set_launch_timestamp_metric(launch_ts);
+    pageserver::set_histogram_buckets(&conf.histogram_buckets)?;
     pageserver::preinitialize_metrics();
+    pageserver::background_jobs::set_config(&conf.background_jobs)?;
+    pageserver::shutdown::set_deadline(conf.shutdown_deadline);
 
     // If any failpoints were set from FAILPOINTS environment variable,
     // print them to the log for debugging purposes
@@ -334,6 +457,7 @@ fn start_pageserver(
 
     // Set up remote storage client
     let remote_storage = create_remote_storage_client(conf)?;
+    tenant::init_upload_event_sink(conf).context("set up the upload events sink")?;
 
     // Startup staging or optimizing:
     //
@@ -463,6 +587,17 @@ fn start_pageserver(
         )?;
     }
 
+    disk_space_monitor::launch_disk_space_monitor(
+        conf,
+        remote_storage.clone(),
+        disk_usage_eviction_state.clone(),
+        background_jobs_barrier.clone(),
+    );
+
+    if remote_storage.is_some() {
+        remote_scrubber::launch_remote_scrubber(conf, background_jobs_barrier.clone());
+    }
+
     // Start up the service to handle HTTP mgmt API request. We created the
     // listener earlier already.
     {
@@ -541,6 +676,22 @@ fn start_pageserver(
         );
     }
 
+    if let Some(metrics_push) = &conf.metrics_push {
+        task_mgr::spawn(
+            crate::BACKGROUND_RUNTIME.handle(),
+            TaskKind::MetricsPush,
+            None,
+            None,
+            "metrics push",
+            false,
+            async move {
+                pageserver::metrics_push::push_metrics_loop(metrics_push, conf.id)
+                    .instrument(info_span!("metrics_push"))
+                    .await
+            },
+        );
+    }
+
     // Spawn a task to listen for libpq connections. It will spawn further tasks
     // for each connection. We created the listener earlier already.
     {
@@ -611,23 +762,12 @@ fn create_remote_storage_client(
         return Ok(None);
     };
 
-    // Create the client
-    let mut remote_storage = GenericRemoteStorage::from_config(config)?;
-
-    // If `test_remote_failures` is non-zero, wrap the client with a
-    // wrapper that simulates failures.
-    if conf.test_remote_failures > 0 {
-        if !cfg!(feature = "testing") {
-            anyhow::bail!("test_remote_failures option is not available because pageserver was compiled without the 'testing' feature");
-        }
-        info!(
-            "Simulating remote failures for first {} attempts of each op",
-            conf.test_remote_failures
-        );
-        remote_storage =
-            GenericRemoteStorage::unreliable_wrapper(remote_storage, conf.test_remote_failures);
+    let remote_storage = tenant::create_remote_storage(conf, config)?;
+    if let Some(mirror_config) = &conf.remote_storage_mirror_config {
+        let mirror = tenant::create_remote_storage(conf, mirror_config)
+            .context("create the remote storage mirror client")?;
+        tenant::init_remote_storage_mirror(mirror);
     }
-
     Ok(Some(remote_storage))
 }
 
@@ -645,6 +785,7 @@ fn cli() -> Command {
             Arg::new("workdir")
                 .short('D')
                 .long("workdir")
+                .global(true)
                 .help("Working directory for the pageserver"),
         )
         // See `settings.md` for more details on the extra configuration patameters pageserver can process
@@ -653,6 +794,7 @@ fn cli() -> Command {
                 .short('c')
                 .num_args(1)
                 .action(ArgAction::Append)
+                .global(true)
                 .help("Additional configuration overrides of the ones from the toml config file (or new ones to add there). \
                 Any option has to be a valid toml document, example: `-c=\"foo='hey'\"` `-c=\"foo={value=1}\"`"),
         )
@@ -668,6 +810,30 @@ fn cli() -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Show enabled compile time features"),
         )
+        .subcommand(
+            Command::new("check-config")
+                .about("Check the config for errors, without starting the pageserver"),
+        )
+        .subcommand(
+            Command::new("capture-redo-fixture")
+                .about("Capture the WAL redo request of a key at an LSN from the local layer files of a timeline, as a fixture for the walredo tests")
+                .arg(Arg::new("tenant-id").long("tenant-id").required(true))
+                .arg(Arg::new("timeline-id").long("timeline-id").required(true))
+                .arg(
+                    Arg::new("key")
+                        .long("key")
+                        .required(true)
+                        .help("The key, in hex, as it appears in the logs"),
+                )
+                .arg(Arg::new("lsn").long("lsn").required(true))
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .required(true)
+                        .value_parser(|path: &str| env::current_dir().map(|dir| dir.join(path)))
+                        .help("Path of the fixture without extension, `<output>.redo` and `<output>.page` are written"),
+                ),
+        )
 }
 
 #[test]
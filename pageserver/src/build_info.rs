@@ -0,0 +1,75 @@
+//!
+//! What exactly this pageserver binary is, so that fleet tooling can verify what
+//! runs where. Served on `GET /v1/status/build` and as the `pageserver_build_info`
+//! metric.
+//!
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use pageserver_api::models::BuildInfoResponse;
+use utils::project_git_version;
+
+use crate::config::{PageServerConf, SUPPORTED_PG_VERSIONS};
+use crate::metrics::BUILD_INFO;
+
+project_git_version!(GIT_VERSION);
+
+pub const FEATURES: &[&str] = &[
+    #[cfg(feature = "testing")]
+    "testing",
+    #[cfg(feature = "fail/failpoints")]
+    "fail/failpoints",
+];
+
+/// Set by `build.rs`.
+const RUSTC_VERSION: &str = env!("PAGESERVER_RUSTC_VERSION");
+const BUILD_TIMESTAMP: &str = env!("PAGESERVER_BUILD_TIMESTAMP");
+
+fn build_time() -> SystemTime {
+    BUILD_TIMESTAMP
+        .parse()
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        .unwrap_or(UNIX_EPOCH)
+}
+
+pub fn build_info(conf: &PageServerConf) -> BuildInfoResponse {
+    // The binary supports more versions than a given installation may ship.
+    let pg_versions = SUPPORTED_PG_VERSIONS
+        .iter()
+        .copied()
+        .filter(|&pg_version| {
+            conf.pg_bin_dir(pg_version)
+                .map(|dir| dir.join("postgres").exists())
+                .unwrap_or(false)
+        })
+        .collect();
+
+    BuildInfoResponse {
+        git_version: GIT_VERSION.to_string(),
+        build_time: build_time(),
+        rustc_version: RUSTC_VERSION.to_string(),
+        features: FEATURES.iter().map(|f| f.to_string()).collect(),
+        pg_versions,
+    }
+}
+
+/// Expose [`build_info`] as an info-style metric with a constant value of 1.
+pub fn set_build_info_metric(conf: &PageServerConf) {
+    let info = build_info(conf);
+    let build_time = humantime::format_rfc3339_seconds(info.build_time).to_string();
+    let features = info.features.join(",");
+    let pg_versions = info
+        .pg_versions
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    BUILD_INFO
+        .with_label_values(&[
+            &info.git_version,
+            &build_time,
+            &info.rustc_version,
+            &features,
+            &pg_versions,
+        ])
+        .set(1);
+}
@@ -0,0 +1,518 @@
+//! Reading the system catalogs of a database from its pages.
+//!
+//! The pageserver doesn't look into the contents of relations otherwise, but to attribute the
+//! storage of a database to its relations by name, it needs `pg_class` and `pg_namespace`, and
+//! to check that a compute can run a database, `pg_extension`.
+//! The tuples are read without checking whether the transactions that inserted or deleted
+//! them committed, so this is a best-effort view: of the versions of a row, a live one is
+//! preferred, but a deleted one is used if there is nothing else, e.g. for a relation that
+//! was dropped recently.
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use anyhow::{ensure, Context};
+use pageserver_api::reltag::RelTag;
+use postgres_ffi::pg_constants::{
+    EXTENSION_RELATION_ID, HEAP_HASNULL, HEAP_NATTS_MASK, HEAP_XMAX_INVALID, HEAP_XMAX_LOCK_ONLY,
+    HEAP_XMIN_COMMITTED, HEAP_XMIN_INVALID, LP_NORMAL, NAMEDATALEN, NAMESPACE_RELATION_ID,
+    RELATION_RELATION_ID, RELMAPPER_FILEMAGIC, SIZE_OF_PAGE_HEADER,
+};
+use postgres_ffi::relfile_utils::MAIN_FORKNUM;
+use postgres_ffi::Oid;
+use utils::lsn::Lsn;
+
+use crate::context::RequestContext;
+use crate::tenant::Timeline;
+
+// Offsets in PageHeaderData and HeapTupleHeaderData
+const PD_LOWER_OFFSET: usize = 12;
+const ITEM_ID_SIZE: usize = 4;
+const T_XMAX_OFFSET: usize = 4;
+const T_INFOMASK2_OFFSET: usize = 18;
+const T_INFOMASK_OFFSET: usize = 20;
+const T_HOFF_OFFSET: usize = 22;
+const T_BITS_OFFSET: usize = 23;
+
+/// A tuple on a heap page.
+pub struct HeapTuple<'a> {
+    natts: usize,
+    /// The null bitmap, if some attributes are null.
+    nulls: Option<&'a [u8]>,
+    /// Whether a transaction deleted the tuple, maybe one that didn't commit.
+    deleted: bool,
+    /// The attributes, starting at `t_hoff`.
+    data: &'a [u8],
+}
+
+impl<'a> HeapTuple<'a> {
+    fn parse(tuple: &'a [u8]) -> Option<Self> {
+        let xmax = read_u32(tuple, T_XMAX_OFFSET)?;
+        let infomask2 = read_u16(tuple, T_INFOMASK2_OFFSET)?;
+        let infomask = read_u16(tuple, T_INFOMASK_OFFSET)?;
+        let hoff = *tuple.get(T_HOFF_OFFSET)? as usize;
+
+        // Only xmin invalid, not frozen: the inserting transaction aborted.
+        if infomask & (HEAP_XMIN_COMMITTED | HEAP_XMIN_INVALID) == HEAP_XMIN_INVALID {
+            return None;
+        }
+
+        let natts = (infomask2 & HEAP_NATTS_MASK) as usize;
+        let nulls = if infomask & HEAP_HASNULL != 0 {
+            Some(tuple.get(T_BITS_OFFSET..T_BITS_OFFSET + (natts + 7) / 8)?)
+        } else {
+            None
+        };
+        let deleted = xmax != 0 && infomask & (HEAP_XMAX_INVALID | HEAP_XMAX_LOCK_ONLY) == 0;
+
+        Some(HeapTuple {
+            natts,
+            nulls,
+            deleted,
+            data: tuple.get(hoff..)?,
+        })
+    }
+
+    /// Whether the attribute is null, counting from 0.
+    pub fn is_null(&self, attnum: usize) -> bool {
+        if attnum >= self.natts {
+            // Added to the table after the tuple was written, without a default.
+            return true;
+        }
+        match self.nulls {
+            Some(nulls) => nulls[attnum / 8] & (1 << (attnum % 8)) == 0,
+            None => false,
+        }
+    }
+
+    pub fn u8_at(&self, offset: usize) -> Option<u8> {
+        self.data.get(offset).copied()
+    }
+
+    pub fn u32_at(&self, offset: usize) -> Option<u32> {
+        read_u32(self.data, offset)
+    }
+
+    pub fn i32_at(&self, offset: usize) -> Option<i32> {
+        self.u32_at(offset).map(|value| value as i32)
+    }
+
+    /// A `name` attribute, a NUL-padded string of `NAMEDATALEN` bytes.
+    pub fn name_at(&self, offset: usize) -> Option<String> {
+        let name = self.data.get(offset..offset + NAMEDATALEN)?;
+        let len = name.iter().position(|b| *b == 0).unwrap_or(NAMEDATALEN);
+        Some(String::from_utf8_lossy(&name[..len]).into_owned())
+    }
+
+    /// A `text` attribute that follows the given offset, at the alignment of its header. `None`
+    /// if the value is compressed or TOASTed.
+    pub fn text_at(&self, offset: usize) -> Option<String> {
+        let header = self.u8_at(offset)?;
+        let text = if header & 0x01 == 0x01 {
+            // A 1-byte header, which is not aligned. 0x01 is the header of a TOAST pointer.
+            if header == 0x01 {
+                return None;
+            }
+            let len = (header >> 1) as usize;
+            self.data.get(offset + 1..offset + len)?
+        } else {
+            // A 4-byte header, aligned to int. The padding bytes are zeros.
+            let offset = (offset + 3) & !3;
+            let header = self.u32_at(offset)?;
+            if header & 0x03 != 0 {
+                return None;
+            }
+            let len = (header >> 2) as usize;
+            self.data.get(offset + 4..offset + len)?
+        };
+        Some(String::from_utf8_lossy(text).into_owned())
+    }
+}
+
+/// The tuples on a heap page, except the ones whose inserting transaction aborted.
+pub fn heap_tuples(page: &[u8]) -> impl Iterator<Item = HeapTuple<'_>> {
+    // A new, all-zeros page has no line pointers.
+    let lower = read_u16(page, PD_LOWER_OFFSET).unwrap_or(0) as usize;
+    let nitems = lower.saturating_sub(SIZE_OF_PAGE_HEADER as usize) / ITEM_ID_SIZE;
+    (0..nitems).filter_map(move |i| {
+        let item_id = read_u32(page, SIZE_OF_PAGE_HEADER as usize + i * ITEM_ID_SIZE)?;
+        let offset = (item_id & 0x7fff) as usize;
+        let flags = (item_id >> 15) & 0x3;
+        let len = (item_id >> 17) as usize;
+        if flags != LP_NORMAL {
+            return None;
+        }
+        HeapTuple::parse(page.get(offset..offset + len)?)
+    })
+}
+
+/// Read the rows of a heap relation, decoded and keyed with `decode`. Of the versions of a
+/// row, a live one is preferred.
+pub async fn scan_heap<K, T>(
+    timeline: &Timeline,
+    rel: RelTag,
+    lsn: Lsn,
+    ctx: &RequestContext,
+    decode: impl Fn(&HeapTuple) -> Option<(K, T)>,
+) -> anyhow::Result<HashMap<K, T>>
+where
+    K: Hash + Eq,
+{
+    let mut rows: HashMap<K, (T, bool)> = HashMap::new();
+    let nblocks = timeline.get_rel_size(rel, lsn, false, ctx).await?;
+    for blknum in 0..nblocks {
+        let page = timeline
+            .get_rel_page_at_lsn(rel, blknum, lsn, false, ctx)
+            .await?;
+        for tuple in heap_tuples(&page) {
+            let Some((key, row)) = decode(&tuple) else {
+                continue;
+            };
+            let replace = match rows.get(&key) {
+                Some((_, deleted)) => *deleted && !tuple.deleted,
+                None => true,
+            };
+            if replace {
+                rows.insert(key, (row, tuple.deleted));
+            }
+        }
+    }
+    Ok(rows.into_iter().map(|(key, (row, _))| (key, row)).collect())
+}
+
+/// A row of `pg_class`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgClass {
+    pub oid: Oid,
+    pub relname: String,
+    pub relnamespace: Oid,
+    /// Zero for the catalogs whose relfilenode is in the relation mapper file.
+    pub relfilenode: Oid,
+    pub relkind: u8,
+}
+
+// Offsets of the attributes of pg_class, the same in all the supported versions
+const RELNAME_OFFSET: usize = 4;
+const RELNAMESPACE_OFFSET: usize = RELNAME_OFFSET + NAMEDATALEN;
+const RELFILENODE_OFFSET: usize = 88;
+const RELKIND_OFFSET: usize = 115;
+
+impl PgClass {
+    fn decode(tuple: &HeapTuple) -> Option<(Oid, Self)> {
+        let oid = tuple.u32_at(0)?;
+        let class = PgClass {
+            oid,
+            relname: tuple.name_at(RELNAME_OFFSET)?,
+            relnamespace: tuple.u32_at(RELNAMESPACE_OFFSET)?,
+            relfilenode: tuple.u32_at(RELFILENODE_OFFSET)?,
+            relkind: tuple.u8_at(RELKIND_OFFSET)?,
+        };
+        Some((oid, class))
+    }
+}
+
+/// A row of `pg_extension`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgExtension {
+    pub oid: Oid,
+    pub extname: String,
+    pub extversion: String,
+}
+
+// Offsets of the attributes of pg_extension, the same in all the supported versions. The
+// attributes before extversion are fixed-size and NOT NULL.
+const EXTNAME_OFFSET: usize = 4;
+const EXTVERSION_OFFSET: usize = 77;
+
+impl PgExtension {
+    fn decode(tuple: &HeapTuple) -> Option<(Oid, Self)> {
+        let oid = tuple.u32_at(0)?;
+        let extension = PgExtension {
+            oid,
+            extname: tuple.name_at(EXTNAME_OFFSET)?,
+            extversion: tuple.text_at(EXTVERSION_OFFSET)?,
+        };
+        Some((oid, extension))
+    }
+
+    /// Read the extensions installed in a database. Returns `None` for the directories without
+    /// `pg_class`, like [`Catalog::load`].
+    pub async fn load_all(
+        timeline: &Timeline,
+        spcnode: Oid,
+        dbnode: Oid,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<Option<Vec<PgExtension>>> {
+        let rel = |relnode| RelTag {
+            spcnode,
+            dbnode,
+            relnode,
+            forknum: MAIN_FORKNUM,
+        };
+
+        // The relfilenode of pg_extension is its OID, unless the table was rewritten, which
+        // saves reading all of pg_class.
+        let pg_extension = if timeline
+            .get_rel_exists(rel(EXTENSION_RELATION_ID), lsn, false, ctx)
+            .await?
+        {
+            EXTENSION_RELATION_ID
+        } else {
+            let Some(catalog) = Catalog::load(timeline, spcnode, dbnode, lsn, ctx).await? else {
+                return Ok(None);
+            };
+            catalog
+                .find("pg_catalog", "pg_extension")
+                .and_then(|class| catalog.relfilenode(class))
+                .context("pg_extension is missing from pg_class")?
+        };
+
+        let extensions = scan_heap(timeline, rel(pg_extension), lsn, ctx, PgExtension::decode)
+            .await
+            .context("read pg_extension")?;
+        Ok(Some(extensions.into_values().collect()))
+    }
+}
+
+/// The relations and the schemas of a database.
+pub struct Catalog {
+    classes: HashMap<Oid, PgClass>,
+    namespaces: HashMap<Oid, String>,
+    /// The relfilenodes of the mapped catalogs, by OID.
+    mapped: HashMap<Oid, Oid>,
+}
+
+impl Catalog {
+    /// Read the catalog of a database. Returns `None` for the directories without `pg_class`,
+    /// i.e. the one of the shared catalogs.
+    pub async fn load(
+        timeline: &Timeline,
+        spcnode: Oid,
+        dbnode: Oid,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<Option<Self>> {
+        let relmap = timeline
+            .get_relmap_file(spcnode, dbnode, lsn, ctx)
+            .await
+            .context("read relation mapper file")?;
+        let mapped = parse_relmap_file(&relmap)?;
+        let Some(&pg_class) = mapped.get(&RELATION_RELATION_ID) else {
+            return Ok(None);
+        };
+        let rel = |relnode| RelTag {
+            spcnode,
+            dbnode,
+            relnode,
+            forknum: MAIN_FORKNUM,
+        };
+
+        let classes = scan_heap(timeline, rel(pg_class), lsn, ctx, PgClass::decode)
+            .await
+            .context("read pg_class")?;
+        let mut catalog = Catalog {
+            classes,
+            namespaces: HashMap::new(),
+            mapped,
+        };
+
+        let pg_namespace = catalog
+            .classes
+            .get(&NAMESPACE_RELATION_ID)
+            .and_then(|class| catalog.relfilenode(class))
+            .context("pg_namespace is missing from pg_class")?;
+        catalog.namespaces = scan_heap(timeline, rel(pg_namespace), lsn, ctx, |tuple| {
+            Some((tuple.u32_at(0)?, tuple.name_at(4)?))
+        })
+        .await
+        .context("read pg_namespace")?;
+
+        Ok(Some(catalog))
+    }
+
+    pub fn class(&self, oid: Oid) -> Option<&PgClass> {
+        self.classes.get(&oid)
+    }
+
+    /// The relation with the given name, in the given schema.
+    pub fn find(&self, schema: &str, relname: &str) -> Option<&PgClass> {
+        self.classes
+            .values()
+            .find(|class| class.relname == relname && self.namespace(class) == Some(schema))
+    }
+
+    pub fn namespace(&self, class: &PgClass) -> Option<&str> {
+        self.namespaces.get(&class.relnamespace).map(String::as_str)
+    }
+
+    /// The relfilenode of the relation, `None` for the ones without storage, e.g. views.
+    pub fn relfilenode(&self, class: &PgClass) -> Option<Oid> {
+        match class.relfilenode {
+            0 => self.mapped.get(&class.oid).copied(),
+            relfilenode => Some(relfilenode),
+        }
+    }
+
+    pub fn by_relfilenode(&self) -> HashMap<Oid, &PgClass> {
+        self.classes
+            .values()
+            .filter_map(|class| Some((self.relfilenode(class)?, class)))
+            .collect()
+    }
+}
+
+/// The mappings of the relation mapper file, `pg_filenode.map`, from OID to relfilenode.
+fn parse_relmap_file(buf: &[u8]) -> anyhow::Result<HashMap<Oid, Oid>> {
+    let magic = read_u32(buf, 0).context("relation mapper file is too short")?;
+    ensure!(
+        magic == RELMAPPER_FILEMAGIC,
+        "invalid relation mapper file magic {magic:#x}"
+    );
+    let num_mappings = read_u32(buf, 4).context("relation mapper file is too short")?;
+    (0..num_mappings as usize)
+        .map(|i| {
+            let offset = 8 + i * 8;
+            let oid = read_u32(buf, offset);
+            let relfilenode = read_u32(buf, offset + 4);
+            oid.zip(relfilenode)
+                .context("relation mapper file is too short")
+        })
+        .collect()
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    let bytes = buf.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use postgres_ffi::BLCKSZ;
+
+    use super::*;
+
+    /// A heap page with the given tuples, as (infomask, xmax, attributes).
+    fn heap_page(tuples: &[(u16, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut page = vec![0u8; BLCKSZ as usize];
+        let mut upper = BLCKSZ as usize;
+        for (i, (infomask, xmax, data)) in tuples.iter().enumerate() {
+            let mut tuple = vec![0u8; 24];
+            tuple[T_XMAX_OFFSET..T_XMAX_OFFSET + 4].copy_from_slice(&xmax.to_le_bytes());
+            tuple[T_INFOMASK2_OFFSET..T_INFOMASK2_OFFSET + 2].copy_from_slice(&2u16.to_le_bytes());
+            tuple[T_INFOMASK_OFFSET..T_INFOMASK_OFFSET + 2]
+                .copy_from_slice(&infomask.to_le_bytes());
+            tuple[T_HOFF_OFFSET] = 24;
+            tuple.extend_from_slice(data);
+
+            upper -= tuple.len();
+            page[upper..upper + tuple.len()].copy_from_slice(&tuple);
+            let item_id = upper as u32 | (LP_NORMAL << 15) | ((tuple.len() as u32) << 17);
+            let item_offset = SIZE_OF_PAGE_HEADER as usize + i * ITEM_ID_SIZE;
+            page[item_offset..item_offset + 4].copy_from_slice(&item_id.to_le_bytes());
+        }
+        let lower = SIZE_OF_PAGE_HEADER as usize + tuples.len() * ITEM_ID_SIZE;
+        page[PD_LOWER_OFFSET..PD_LOWER_OFFSET + 2].copy_from_slice(&(lower as u16).to_le_bytes());
+        page
+    }
+
+    /// The attributes of a pg_namespace row.
+    fn namespace_row(oid: Oid, name: &str) -> Vec<u8> {
+        let mut data = oid.to_le_bytes().to_vec();
+        let mut nspname = [0u8; NAMEDATALEN];
+        nspname[..name.len()].copy_from_slice(name.as_bytes());
+        data.extend_from_slice(&nspname);
+        data
+    }
+
+    #[test]
+    fn heap_page_tuples() {
+        let page = heap_page(&[
+            (HEAP_XMAX_INVALID, 0, namespace_row(11, "pg_catalog")),
+            // deleted
+            (0, 1000, namespace_row(2200, "public")),
+            // aborted insert
+            (HEAP_XMIN_INVALID, 0, namespace_row(3000, "aborted")),
+            // frozen
+            (HEAP_XMIN_COMMITTED | HEAP_XMIN_INVALID, 0, namespace_row(99, "pg_toast")),
+        ]);
+
+        let rows = heap_tuples(&page)
+            .map(|tuple| {
+                let row = (tuple.u32_at(0).unwrap(), tuple.name_at(4).unwrap());
+                (row, tuple.deleted)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            [
+                ((11, "pg_catalog".to_owned()), false),
+                ((2200, "public".to_owned()), true),
+                ((99, "pg_toast".to_owned()), false),
+            ]
+        );
+
+        assert_eq!(heap_tuples(&vec![0u8; BLCKSZ as usize]).count(), 0);
+    }
+
+    #[test]
+    fn null_bitmap() {
+        let mut tuple = vec![0u8; 24];
+        tuple[T_INFOMASK2_OFFSET] = 3;
+        tuple[T_INFOMASK_OFFSET] = HEAP_HASNULL as u8;
+        // the second attribute is null
+        tuple[T_BITS_OFFSET] = 0b101;
+        tuple[T_HOFF_OFFSET] = 24;
+        let tuple = HeapTuple::parse(&tuple).unwrap();
+        assert!(!tuple.is_null(0));
+        assert!(tuple.is_null(1));
+        assert!(!tuple.is_null(2));
+        assert!(tuple.is_null(3));
+    }
+
+    #[test]
+    fn text_attributes() {
+        let tuple = |data: &[u8]| {
+            let mut tuple = vec![0u8; 24];
+            tuple[T_INFOMASK2_OFFSET] = 1;
+            tuple[T_HOFF_OFFSET] = 24;
+            tuple.extend_from_slice(data);
+            tuple
+        };
+
+        // A 1-byte header right after a bool, at offset 1
+        let short = tuple(&[1, (7 << 1) | 1, b'2', b'.', b'1', b'0', b'.', b'1']);
+        assert_eq!(HeapTuple::parse(&short).unwrap().text_at(1).as_deref(), Some("2.10.1"));
+
+        // A 4-byte header, after padding to offset 4
+        let mut data = vec![1, 0, 0, 0];
+        data.extend_from_slice(&((4u32 + 6) << 2).to_le_bytes());
+        data.extend_from_slice(b"2.11.0");
+        let long = tuple(&data);
+        assert_eq!(HeapTuple::parse(&long).unwrap().text_at(1).as_deref(), Some("2.11.0"));
+
+        // compressed
+        data[4] |= 0x02;
+        assert_eq!(HeapTuple::parse(&tuple(&data)).unwrap().text_at(1), None);
+        // TOAST pointer
+        assert_eq!(HeapTuple::parse(&tuple(&[1, 0x01])).unwrap().text_at(1), None);
+    }
+
+    #[test]
+    fn relmap_file() {
+        let mut buf = Vec::new();
+        for value in [RELMAPPER_FILEMAGIC, 2, 1259, 16000, 1249, 1249] {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        let mapped = parse_relmap_file(&buf).unwrap();
+        assert_eq!(mapped, HashMap::from([(1259, 16000), (1249, 1249)]));
+
+        assert!(parse_relmap_file(&buf[..20]).is_err());
+        buf[0] = 0;
+        assert!(parse_relmap_file(&buf).is_err());
+    }
+}
@@ -13,6 +13,7 @@ use utils::crashsafe::path_with_suffix_extension;
 use utils::id::ConnectionId;
 
 use once_cell::sync::OnceCell;
+use pageserver_api::models::UploadThrottleConfig;
 use reqwest::Url;
 use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
@@ -25,15 +26,31 @@ use toml_edit::{Document, Item};
 use postgres_backend::AuthType;
 use utils::{
     id::{NodeId, TenantId, TimelineId},
-    logging::LogFormat,
+    logging::{ErrorBudget, LogFormat},
 };
 
+use crate::background_jobs::BackgroundJobsConfig;
+use crate::disk_space_monitor::DiskSpaceMonitorConfig;
 use crate::disk_usage_eviction_task::DiskUsageEvictionTaskConfig;
+use crate::metrics::{HistogramBucketsConfig, RemoteStoragePrices};
+use crate::metrics_push::MetricsPushConfig;
+use crate::page_service::GetPageTimingConfig;
+use crate::remote_scrubber::RemoteScrubConfig;
 use crate::tenant::config::TenantConf;
 use crate::tenant::config::TenantConfOpt;
-use crate::tenant::{TENANT_ATTACHING_MARKER_FILENAME, TIMELINES_SEGMENT_NAME};
+use crate::tenant::download_limiter::LayerDownloadLimiter;
+use crate::tenant::upload_queue::{IndexLsnCheck, UploadQueueLimitsConfig};
+use crate::tenant::{
+    IngestCoalescingConfig, LayerPathScheme, LayerWatermarksConfig, ParallelDownloadConfig,
+    RemoteCircuitBreakerConfig, RemoteCompressionConfig, RemoteEncryptionConfig,
+    RemoteListingConfig, RemoteMultipartUploadConfig, RemoteRetryConfig, UploadEventsConfig,
+    TENANT_ATTACHING_MARKER_FILENAME, TIMELINES_SEGMENT_NAME,
+};
+use crate::walredo::{WalRedoPolicy, WalRedoStderrConfig};
 use crate::{
-    IGNORED_TENANT_FILE_NAME, METADATA_FILE_NAME, TENANT_CONFIG_NAME, TIMELINE_UNINIT_MARK_SUFFIX,
+    IGNORED_TENANT_FILE_NAME, METADATA_FILE_NAME, TENANT_CONFIG_NAME,
+    TENANT_DEFERRED_DELETIONS_FILE_NAME, TENANT_GENERATION_FILE_NAME, TENANT_IMPORT_FILE_NAME,
+    TENANT_REMOTE_STORAGE_FILE_NAME, TIMELINE_UNINIT_MARK_SUFFIX,
 };
 
 pub mod defaults {
@@ -48,6 +65,9 @@ pub mod defaults {
 
     pub const DEFAULT_WAIT_LSN_TIMEOUT: &str = "60 s";
     pub const DEFAULT_WAL_REDO_TIMEOUT: &str = "60 s";
+    pub const DEFAULT_WAL_REDO_SLOW_THRESHOLD: &str = "1 s";
+    pub const DEFAULT_WAL_REDO_PING_PERIOD: &str = "1 h";
+    pub const DEFAULT_WAL_REDO_THREADS: usize = 8;
 
     pub const DEFAULT_SUPERUSER: &str = "cloud_admin";
 
@@ -64,6 +84,11 @@ pub mod defaults {
     pub const DEFAULT_METRIC_COLLECTION_ENDPOINT: Option<reqwest::Url> = None;
     pub const DEFAULT_SYNTHETIC_SIZE_CALCULATION_INTERVAL: &str = "10 min";
     pub const DEFAULT_BACKGROUND_TASK_MAXIMUM_DELAY: &str = "10s";
+    pub const DEFAULT_REMOTE_DELETION_GRACE_PERIOD: &str = "0s";
+    pub const DEFAULT_REMOTE_INDEX_SNAPSHOT_PERIOD: &str = "0s";
+    pub const DEFAULT_CONCURRENT_LAYER_DOWNLOADS: usize =
+        super::LayerDownloadLimiter::DEFAULT_PERMITS.get();
+    pub const DEFAULT_SHUTDOWN_DEADLINE: &str = "60s";
 
     ///
     /// Default built-in configuration file.
@@ -76,6 +101,15 @@ pub mod defaults {
 
 #wait_lsn_timeout = '{DEFAULT_WAIT_LSN_TIMEOUT}'
 #wal_redo_timeout = '{DEFAULT_WAL_REDO_TIMEOUT}'
+#wal_redo_native_heap = false
+#wal_redo_native_btree = false
+#wal_redo_native_fpi = false
+#wal_redo_policy = {{ timeout_per_record = '10ms', max_attempts = 3, quarantine_threshold = 10 }}
+#wal_redo_stderr = {{ max_lines_per_minute = 60, tail_lines = 20, max_line_length = 4096 }}
+#wal_redo_slow_threshold = '{DEFAULT_WAL_REDO_SLOW_THRESHOLD}'
+#wal_redo_metrics_per_timeline = false
+#wal_redo_ping_period = '{DEFAULT_WAL_REDO_PING_PERIOD}'
+#wal_redo_threads = {DEFAULT_WAL_REDO_THREADS}
 
 #max_file_descriptors = {DEFAULT_MAX_FILE_DESCRIPTORS}
 
@@ -85,6 +119,7 @@ pub mod defaults {
 #broker_endpoint = '{BROKER_DEFAULT_ENDPOINT}'
 
 #log_format = '{DEFAULT_LOG_FORMAT}'
+#error_budgets = [{{ target = 'pageserver::tenant', max_errors = 100, window = '10m' }}]
 
 #concurrent_tenant_size_logical_size_queries = '{DEFAULT_CONCURRENT_TENANT_SIZE_LOGICAL_SIZE_QUERIES}'
 
@@ -94,8 +129,60 @@ pub mod defaults {
 
 #disk_usage_based_eviction = {{ max_usage_pct = .., min_avail_bytes = .., period = "10s"}}
 
+#disk_space_monitor = {{ period = '10s', critical_usage_pct = 95, critical_inode_usage_pct = 95, actions = ['evict', 'pause_wal_ingest', 'refuse_timeline_creation'] }}
+
+#ingest_coalescing = {{ max_rel_blocks = 16, max_delay = '1h' }}
+
+#layer_watermarks = {{ layer_count = {{ warning = 5000, critical = 20000 }}, local_size_bytes = {{ warning = 107374182400, critical = 429496729600 }} }}
+
+#getpage_timing = {{ sample_one_in = 1000, trace = false }}
+
+#metrics_push = {{ endpoint = 'http://pushgateway:9091/', interval = '15s', basic_auth = {{ username = .., password = .. }} }}
+
+#histogram_buckets = {{ pageserver_smgr_query_seconds = [0.0001, 0.0005, 0.001, 0.01, 0.1] }}
+
+#upload_throttle = {{ bytes_per_second = 104857600, ops_per_second = 100 }}
+
+#upload_queue_limits = {{ max_queued_ops = 1000, max_queued_bytes = 10737418240 }}
+
+#remote_listing = {{ max_concurrent_lists = 16, max_concurrent_index_downloads = 64, manifest_max_age = '1d' }}
+
+#remote_encryption = {{ mode = 'client_side', key_file = 'remote_encryption.key' }}
+
+#remote_compression = {{ algorithm = 'zstd', level = 3 }}
+
+#remote_multipart_upload = {{ threshold = 4294967296, part_size = 67108864, max_part_attempts = 3 }}
+
+#remote_layer_path_scheme = 'flat'
+
+#remote_scrub = {{ period = '1d', min_age = '1d' }}
+
+#remote_circuit_breaker = {{ failure_threshold = 100, cooldown = '30s' }}
+
+#remote_index_lsn_check = 'warn'
+
+#remote_retry = {{ upload = {{ max_attempts = 100, give_up = 'cancel' }}, download = {{ jitter = '1s' }} }}
+
+#remote_deletion_grace_period = '{DEFAULT_REMOTE_DELETION_GRACE_PERIOD}'
+
+#remote_index_segment_layers = 10000
+
+#remote_index_snapshot_period = '{DEFAULT_REMOTE_INDEX_SNAPSHOT_PERIOD}'
+
+#remote_storage_prices = {{ put_per_1000 = 0.005, get_per_1000 = 0.0004, list_per_1000 = 0.005, delete_per_1000 = 0.0, upload_per_gib = 0.0, download_per_gib = 0.0 }}
+
+#upload_events = {{ sink = 'webhook', endpoint = 'http://control-plane:1234/upload_events' }}
+
+#parallel_download = {{ min_size = 268435456, chunks = 8 }}
+
+#concurrent_layer_downloads = {DEFAULT_CONCURRENT_LAYER_DOWNLOADS}
+
+#background_jobs = {{ concurrency_limits = {{ heavy = 4 }}, jobs = {{ compaction = {{ jitter = '10s', concurrency_class = 'heavy' }} }} }}
+
 #background_task_maximum_delay = '{DEFAULT_BACKGROUND_TASK_MAXIMUM_DELAY}'
 
+#shutdown_deadline = '{DEFAULT_SHUTDOWN_DEADLINE}'
+
 [tenant_config]
 #checkpoint_distance = {DEFAULT_CHECKPOINT_DISTANCE} # in bytes
 #checkpoint_timeout = {DEFAULT_CHECKPOINT_TIMEOUT}
@@ -111,6 +198,12 @@ pub mod defaults {
 #min_resident_size_override = .. # in bytes
 #evictions_low_residence_duration_metric_threshold = '{DEFAULT_EVICTIONS_LOW_RESIDENCE_DURATION_METRIC_THRESHOLD}'
 #gc_feedback = false
+#prefetch_distance = 0
+#prefetch_max_inflight = {DEFAULT_PREFETCH_MAX_INFLIGHT}
+#deletion_dry_run = false
+#max_inprogress_uploads = .. # unlimited by default
+#walredo_memory_limit = .. # in bytes, unlimited by default
+#walredo_cpu_time_limit = .. # unlimited by default
 
 [remote_storage]
 
@@ -136,10 +229,36 @@ pub struct PageServerConf {
     pub wait_lsn_timeout: Duration,
     // How long to wait for WAL redo to complete.
     pub wal_redo_timeout: Duration,
+    // Redo the heap inserts, deletes and HOT updates without the WAL redo process, see
+    // walredo/heap.rs.
+    pub wal_redo_native_heap: bool,
+    // Redo the btree leaf inserts without the WAL redo process, see walredo/btree.rs.
+    pub wal_redo_native_btree: bool,
+    // Restore the full-page images without the WAL redo process, see walredo/fpi.rs.
+    pub wal_redo_native_fpi: bool,
+    /// Timeouts, retries and quarantine of the WAL redo process, see
+    /// [`crate::walredo::WalRedoPolicy`].
+    pub wal_redo_policy: WalRedoPolicy,
+    /// Logging of the stderr of the WAL redo process, see
+    /// [`crate::walredo::WalRedoStderrConfig`].
+    pub wal_redo_stderr: WalRedoStderrConfig,
+    /// WAL redo requests that take longer are logged, a sample of them.
+    pub wal_redo_slow_threshold: Duration,
+    /// Label the per-tenant WAL redo metrics with the timeline too.
+    pub wal_redo_metrics_per_timeline: bool,
+    /// How often the WAL redo of each tenant is checked, on top of the check at its activation,
+    /// see [`crate::walredo::PostgresRedoManager::ping`]. 0 disables the checks.
+    pub wal_redo_ping_period: Duration,
+    /// The most threads that talk to each WAL redo process, and so the most requests that are
+    /// pipelined to it at once, see `walredo::pool`.
+    pub wal_redo_threads: usize,
 
     pub superuser: String,
 
     pub page_cache_size: usize,
+    /// How many of the page cache slots the page images reconstructed with WAL redo may take,
+    /// see [`crate::page_cache`]. All of them by default.
+    pub materialized_page_cache_size: Option<NonZeroUsize>,
     pub max_file_descriptors: usize,
 
     // Repository directory, relative to current working directory.
@@ -162,6 +281,9 @@ pub struct PageServerConf {
     pub auth_validation_public_key_path: Option<PathBuf>,
 
     pub remote_storage_config: Option<RemoteStorageConfig>,
+    /// A secondary remote storage that the uploads to the remote storage are mirrored to, see
+    /// [`crate::tenant::remote_timeline_client::mirror`].
+    pub remote_storage_mirror_config: Option<RemoteStorageConfig>,
 
     pub default_tenant_conf: TenantConf,
 
@@ -170,6 +292,8 @@ pub struct PageServerConf {
     pub broker_keepalive_interval: Duration,
 
     pub log_format: LogFormat,
+    /// Limits on the error events of targets, see [`ErrorBudget`].
+    pub error_budgets: Vec<ErrorBudget>,
 
     /// Number of concurrent [`Tenant::gather_size_inputs`] allowed.
     pub concurrent_tenant_size_logical_size_queries: ConfigurableSemaphore,
@@ -187,6 +311,91 @@ pub struct PageServerConf {
 
     pub disk_usage_based_eviction: Option<DiskUsageEvictionTaskConfig>,
 
+    /// Watch the free space of the workdir volume, and protect the pageserver when it runs low.
+    pub disk_space_monitor: Option<DiskSpaceMonitorConfig>,
+
+    /// Warning and critical thresholds on the layer count and the local size of each timeline.
+    pub layer_watermarks: Option<LayerWatermarksConfig>,
+
+    /// Defer the freeze of open layers holding only writes to small relations.
+    pub ingest_coalescing: Option<IngestCoalescingConfig>,
+
+    /// Break down the time of a sample of the getpage requests by stage.
+    pub getpage_timing: Option<GetPageTimingConfig>,
+
+    /// Push the metrics to a Prometheus Pushgateway, for deployments that can't scrape us.
+    pub metrics_push: Option<MetricsPushConfig>,
+
+    /// Overrides of the compiled-in histogram buckets, by metric name.
+    pub histogram_buckets: HistogramBucketsConfig,
+
+    /// Cadence, jitter and concurrency limits of the background jobs.
+    pub background_jobs: BackgroundJobsConfig,
+
+    /// The initial limits on the layer uploads of each tenant, adjustable per tenant at runtime.
+    pub upload_throttle: UploadThrottleConfig,
+
+    /// How much work the upload queue of a timeline may have pending before its flushes and
+    /// compactions wait.
+    pub upload_queue_limits: UploadQueueLimitsConfig,
+
+    /// Limits on the listings of the timelines of the tenants being attached, see
+    /// [`crate::tenant::remote_timeline_client::listing`].
+    pub remote_listing: RemoteListingConfig,
+
+    /// Whether and how the layer files are encrypted in the remote storage.
+    pub remote_encryption: RemoteEncryptionConfig,
+
+    /// Whether and how the layer files are compressed in the remote storage.
+    pub remote_compression: RemoteCompressionConfig,
+
+    /// Upload the large layer files in parts, see
+    /// [`crate::tenant::remote_timeline_client::multipart`].
+    pub remote_multipart_upload: RemoteMultipartUploadConfig,
+
+    /// Where the layer files are uploaded in the remote storage, see
+    /// [`crate::tenant::remote_timeline_client::sharding`].
+    pub remote_layer_path_scheme: LayerPathScheme,
+
+    /// Delete the layer files in the remote storage that the index doesn't reference.
+    pub remote_scrub: Option<RemoteScrubConfig>,
+
+    /// Pause the remote tasks while the remote storage is failing, see
+    /// [`crate::tenant::remote_timeline_client::health`].
+    pub remote_circuit_breaker: Option<RemoteCircuitBreakerConfig>,
+
+    /// What to do with the index uploads that go back in `disk_consistent_lsn`.
+    pub remote_index_lsn_check: IndexLsnCheck,
+
+    /// How the failed uploads, downloads and deletions of the remote storage are retried.
+    pub remote_retry: RemoteRetryConfig,
+
+    /// Delete the layer files dereferenced from the index from the remote storage only after
+    /// this long, see [`crate::tenant::deferred_deletion`]. Zero deletes them right away.
+    pub remote_deletion_grace_period: Duration,
+
+    /// Write the index of the timelines with more layers than this in the chunked form, see
+    /// [`crate::tenant::remote_timeline_client::index::chunked`].
+    pub remote_index_segment_layers: Option<NonZeroUsize>,
+
+    /// Upload a snapshot of the index at most this often, for the index of a timeline to be
+    /// restored from, see [`crate::tenant::remote_timeline_client`]. Zero uploads none.
+    pub remote_index_snapshot_period: Duration,
+
+    /// Prices of the remote storage requests, to estimate the remote storage cost of each tenant.
+    pub remote_storage_prices: RemoteStoragePrices,
+
+    /// Where to send the events of the upload queues, see
+    /// [`crate::tenant::remote_timeline_client::events`].
+    pub upload_events: Option<UploadEventsConfig>,
+
+    /// Download the large layer files with concurrent range requests.
+    pub parallel_download: Option<ParallelDownloadConfig>,
+
+    /// Limit on the concurrent layer downloads, which the on-demand downloads of the getpage
+    /// requests get ahead of the background ones for, see [`crate::tenant::download_limiter`].
+    pub concurrent_layer_downloads: LayerDownloadLimiter,
+
     pub test_remote_failures: u64,
 
     pub ondemand_download_behavior_treat_error_as_warn: bool,
@@ -199,6 +408,10 @@ pub struct PageServerConf {
     /// has it's initial logical size calculated. Not running background tasks for some seconds is
     /// not terrible.
     pub background_task_maximum_delay: Duration,
+
+    /// How long the graceful shutdown may take at most. The process exits when the deadline
+    /// is reached, even if not all in-memory layers were flushed or uploaded yet.
+    pub shutdown_deadline: Duration,
 }
 
 /// We do not want to store this in a PageServerConf because the latter may be logged
@@ -235,10 +448,20 @@ struct PageServerConfigBuilder {
 
     wait_lsn_timeout: BuilderValue<Duration>,
     wal_redo_timeout: BuilderValue<Duration>,
+    wal_redo_native_heap: BuilderValue<bool>,
+    wal_redo_native_btree: BuilderValue<bool>,
+    wal_redo_native_fpi: BuilderValue<bool>,
+    wal_redo_policy: BuilderValue<WalRedoPolicy>,
+    wal_redo_stderr: BuilderValue<WalRedoStderrConfig>,
+    wal_redo_slow_threshold: BuilderValue<Duration>,
+    wal_redo_metrics_per_timeline: BuilderValue<bool>,
+    wal_redo_ping_period: BuilderValue<Duration>,
+    wal_redo_threads: BuilderValue<usize>,
 
     superuser: BuilderValue<String>,
 
     page_cache_size: BuilderValue<usize>,
+    materialized_page_cache_size: BuilderValue<Option<NonZeroUsize>>,
     max_file_descriptors: BuilderValue<usize>,
 
     workdir: BuilderValue<PathBuf>,
@@ -251,6 +474,7 @@ struct PageServerConfigBuilder {
     //
     auth_validation_public_key_path: BuilderValue<Option<PathBuf>>,
     remote_storage_config: BuilderValue<Option<RemoteStorageConfig>>,
+    remote_storage_mirror_config: BuilderValue<Option<RemoteStorageConfig>>,
 
     id: BuilderValue<NodeId>,
 
@@ -258,6 +482,7 @@ struct PageServerConfigBuilder {
     broker_keepalive_interval: BuilderValue<Duration>,
 
     log_format: BuilderValue<LogFormat>,
+    error_budgets: BuilderValue<Vec<ErrorBudget>>,
 
     concurrent_tenant_size_logical_size_queries: BuilderValue<NonZeroUsize>,
 
@@ -268,11 +493,58 @@ struct PageServerConfigBuilder {
 
     disk_usage_based_eviction: BuilderValue<Option<DiskUsageEvictionTaskConfig>>,
 
+    disk_space_monitor: BuilderValue<Option<DiskSpaceMonitorConfig>>,
+
+    layer_watermarks: BuilderValue<Option<LayerWatermarksConfig>>,
+    ingest_coalescing: BuilderValue<Option<IngestCoalescingConfig>>,
+
+    getpage_timing: BuilderValue<Option<GetPageTimingConfig>>,
+
+    metrics_push: BuilderValue<Option<MetricsPushConfig>>,
+
+    histogram_buckets: BuilderValue<HistogramBucketsConfig>,
+
+    background_jobs: BuilderValue<BackgroundJobsConfig>,
+
+    upload_throttle: BuilderValue<UploadThrottleConfig>,
+
+    upload_queue_limits: BuilderValue<UploadQueueLimitsConfig>,
+
+    remote_listing: BuilderValue<RemoteListingConfig>,
+
+    remote_encryption: BuilderValue<RemoteEncryptionConfig>,
+
+    remote_compression: BuilderValue<RemoteCompressionConfig>,
+    remote_multipart_upload: BuilderValue<RemoteMultipartUploadConfig>,
+    remote_layer_path_scheme: BuilderValue<LayerPathScheme>,
+
+    remote_scrub: BuilderValue<Option<RemoteScrubConfig>>,
+    remote_circuit_breaker: BuilderValue<Option<RemoteCircuitBreakerConfig>>,
+    remote_index_lsn_check: BuilderValue<IndexLsnCheck>,
+
+    remote_retry: BuilderValue<RemoteRetryConfig>,
+
+    remote_deletion_grace_period: BuilderValue<Duration>,
+
+    remote_index_segment_layers: BuilderValue<Option<NonZeroUsize>>,
+
+    remote_index_snapshot_period: BuilderValue<Duration>,
+
+    remote_storage_prices: BuilderValue<RemoteStoragePrices>,
+
+    upload_events: BuilderValue<Option<UploadEventsConfig>>,
+
+    parallel_download: BuilderValue<Option<ParallelDownloadConfig>>,
+
+    concurrent_layer_downloads: BuilderValue<NonZeroUsize>,
+
     test_remote_failures: BuilderValue<u64>,
 
     ondemand_download_behavior_treat_error_as_warn: BuilderValue<bool>,
 
     background_task_maximum_delay: BuilderValue<Duration>,
+
+    shutdown_deadline: BuilderValue<Duration>,
 }
 
 impl Default for PageServerConfigBuilder {
@@ -287,8 +559,20 @@ impl Default for PageServerConfigBuilder {
                 .expect("cannot parse default wait lsn timeout")),
             wal_redo_timeout: Set(humantime::parse_duration(DEFAULT_WAL_REDO_TIMEOUT)
                 .expect("cannot parse default wal redo timeout")),
+            wal_redo_native_heap: Set(false),
+            wal_redo_native_btree: Set(false),
+            wal_redo_native_fpi: Set(false),
+            wal_redo_policy: Set(WalRedoPolicy::default()),
+            wal_redo_stderr: Set(WalRedoStderrConfig::default()),
+            wal_redo_slow_threshold: Set(humantime::parse_duration(DEFAULT_WAL_REDO_SLOW_THRESHOLD)
+                .expect("cannot parse default wal redo slow threshold")),
+            wal_redo_metrics_per_timeline: Set(false),
+            wal_redo_ping_period: Set(humantime::parse_duration(DEFAULT_WAL_REDO_PING_PERIOD)
+                .expect("cannot parse default wal redo ping period")),
+            wal_redo_threads: Set(DEFAULT_WAL_REDO_THREADS),
             superuser: Set(DEFAULT_SUPERUSER.to_string()),
             page_cache_size: Set(DEFAULT_PAGE_CACHE_SIZE),
+            materialized_page_cache_size: Set(None),
             max_file_descriptors: Set(DEFAULT_MAX_FILE_DESCRIPTORS),
             workdir: Set(PathBuf::new()),
             pg_distrib_dir: Set(env::current_dir()
@@ -298,6 +582,7 @@ impl Default for PageServerConfigBuilder {
             pg_auth_type: Set(AuthType::Trust),
             auth_validation_public_key_path: Set(None),
             remote_storage_config: Set(None),
+            remote_storage_mirror_config: Set(None),
             id: NotSet,
             broker_endpoint: Set(storage_broker::DEFAULT_ENDPOINT
                 .parse()
@@ -307,6 +592,7 @@ impl Default for PageServerConfigBuilder {
             )
             .expect("cannot parse default keepalive interval")),
             log_format: Set(LogFormat::from_str(DEFAULT_LOG_FORMAT).unwrap()),
+            error_budgets: Set(Vec::new()),
 
             concurrent_tenant_size_logical_size_queries: Set(
                 ConfigurableSemaphore::DEFAULT_INITIAL,
@@ -327,6 +613,57 @@ impl Default for PageServerConfigBuilder {
 
             disk_usage_based_eviction: Set(None),
 
+            disk_space_monitor: Set(None),
+
+            layer_watermarks: Set(None),
+            ingest_coalescing: Set(None),
+
+            getpage_timing: Set(None),
+
+            metrics_push: Set(None),
+
+            histogram_buckets: Set(HistogramBucketsConfig::default()),
+
+            background_jobs: Set(BackgroundJobsConfig::default()),
+
+            upload_throttle: Set(UploadThrottleConfig::default()),
+
+            upload_queue_limits: Set(UploadQueueLimitsConfig::default()),
+
+            remote_listing: Set(RemoteListingConfig::default()),
+
+            remote_encryption: Set(RemoteEncryptionConfig::default()),
+
+            remote_compression: Set(RemoteCompressionConfig::default()),
+            remote_multipart_upload: Set(RemoteMultipartUploadConfig::default()),
+            remote_layer_path_scheme: Set(LayerPathScheme::default()),
+
+            remote_scrub: Set(None),
+            remote_circuit_breaker: Set(None),
+            remote_index_lsn_check: Set(IndexLsnCheck::default()),
+
+            remote_retry: Set(RemoteRetryConfig::default()),
+
+            remote_deletion_grace_period: Set(humantime::parse_duration(
+                DEFAULT_REMOTE_DELETION_GRACE_PERIOD,
+            )
+            .unwrap()),
+
+            remote_index_segment_layers: Set(None),
+
+            remote_index_snapshot_period: Set(humantime::parse_duration(
+                DEFAULT_REMOTE_INDEX_SNAPSHOT_PERIOD,
+            )
+            .unwrap()),
+
+            remote_storage_prices: Set(RemoteStoragePrices::default()),
+
+            upload_events: Set(None),
+
+            parallel_download: Set(None),
+
+            concurrent_layer_downloads: Set(LayerDownloadLimiter::DEFAULT_PERMITS),
+
             test_remote_failures: Set(0),
 
             ondemand_download_behavior_treat_error_as_warn: Set(false),
@@ -335,6 +672,8 @@ impl Default for PageServerConfigBuilder {
                 DEFAULT_BACKGROUND_TASK_MAXIMUM_DELAY,
             )
             .unwrap()),
+
+            shutdown_deadline: Set(humantime::parse_duration(DEFAULT_SHUTDOWN_DEADLINE).unwrap()),
         }
     }
 }
@@ -360,6 +699,42 @@ impl PageServerConfigBuilder {
         self.wal_redo_timeout = BuilderValue::Set(wal_redo_timeout)
     }
 
+    pub fn wal_redo_native_heap(&mut self, wal_redo_native_heap: bool) {
+        self.wal_redo_native_heap = BuilderValue::Set(wal_redo_native_heap)
+    }
+
+    pub fn wal_redo_native_btree(&mut self, wal_redo_native_btree: bool) {
+        self.wal_redo_native_btree = BuilderValue::Set(wal_redo_native_btree)
+    }
+
+    pub fn wal_redo_native_fpi(&mut self, wal_redo_native_fpi: bool) {
+        self.wal_redo_native_fpi = BuilderValue::Set(wal_redo_native_fpi)
+    }
+
+    pub fn wal_redo_policy(&mut self, value: WalRedoPolicy) {
+        self.wal_redo_policy = BuilderValue::Set(value);
+    }
+
+    pub fn wal_redo_stderr(&mut self, value: WalRedoStderrConfig) {
+        self.wal_redo_stderr = BuilderValue::Set(value);
+    }
+
+    pub fn wal_redo_slow_threshold(&mut self, wal_redo_slow_threshold: Duration) {
+        self.wal_redo_slow_threshold = BuilderValue::Set(wal_redo_slow_threshold)
+    }
+
+    pub fn wal_redo_metrics_per_timeline(&mut self, wal_redo_metrics_per_timeline: bool) {
+        self.wal_redo_metrics_per_timeline = BuilderValue::Set(wal_redo_metrics_per_timeline)
+    }
+
+    pub fn wal_redo_ping_period(&mut self, wal_redo_ping_period: Duration) {
+        self.wal_redo_ping_period = BuilderValue::Set(wal_redo_ping_period)
+    }
+
+    pub fn wal_redo_threads(&mut self, wal_redo_threads: usize) {
+        self.wal_redo_threads = BuilderValue::Set(wal_redo_threads)
+    }
+
     pub fn superuser(&mut self, superuser: String) {
         self.superuser = BuilderValue::Set(superuser)
     }
@@ -368,6 +743,10 @@ impl PageServerConfigBuilder {
         self.page_cache_size = BuilderValue::Set(page_cache_size)
     }
 
+    pub fn materialized_page_cache_size(&mut self, value: Option<NonZeroUsize>) {
+        self.materialized_page_cache_size = BuilderValue::Set(value)
+    }
+
     pub fn max_file_descriptors(&mut self, max_file_descriptors: usize) {
         self.max_file_descriptors = BuilderValue::Set(max_file_descriptors)
     }
@@ -399,6 +778,13 @@ impl PageServerConfigBuilder {
         self.remote_storage_config = BuilderValue::Set(remote_storage_config)
     }
 
+    pub fn remote_storage_mirror_config(
+        &mut self,
+        remote_storage_mirror_config: Option<RemoteStorageConfig>,
+    ) {
+        self.remote_storage_mirror_config = BuilderValue::Set(remote_storage_mirror_config)
+    }
+
     pub fn broker_endpoint(&mut self, broker_endpoint: Uri) {
         self.broker_endpoint = BuilderValue::Set(broker_endpoint)
     }
@@ -415,6 +801,10 @@ impl PageServerConfigBuilder {
         self.log_format = BuilderValue::Set(log_format)
     }
 
+    pub fn error_budgets(&mut self, error_budgets: Vec<ErrorBudget>) {
+        self.error_budgets = BuilderValue::Set(error_budgets)
+    }
+
     pub fn concurrent_tenant_size_logical_size_queries(&mut self, u: NonZeroUsize) {
         self.concurrent_tenant_size_logical_size_queries = BuilderValue::Set(u);
     }
@@ -451,6 +841,106 @@ impl PageServerConfigBuilder {
         self.disk_usage_based_eviction = BuilderValue::Set(value);
     }
 
+    pub fn disk_space_monitor(&mut self, value: Option<DiskSpaceMonitorConfig>) {
+        self.disk_space_monitor = BuilderValue::Set(value);
+    }
+
+    pub fn layer_watermarks(&mut self, value: Option<LayerWatermarksConfig>) {
+        self.layer_watermarks = BuilderValue::Set(value);
+    }
+
+    pub fn ingest_coalescing(&mut self, value: Option<IngestCoalescingConfig>) {
+        self.ingest_coalescing = BuilderValue::Set(value);
+    }
+
+    pub fn getpage_timing(&mut self, value: Option<GetPageTimingConfig>) {
+        self.getpage_timing = BuilderValue::Set(value);
+    }
+
+    pub fn metrics_push(&mut self, value: Option<MetricsPushConfig>) {
+        self.metrics_push = BuilderValue::Set(value);
+    }
+
+    pub fn histogram_buckets(&mut self, value: HistogramBucketsConfig) {
+        self.histogram_buckets = BuilderValue::Set(value);
+    }
+
+    pub fn background_jobs(&mut self, value: BackgroundJobsConfig) {
+        self.background_jobs = BuilderValue::Set(value);
+    }
+
+    pub fn upload_throttle(&mut self, value: UploadThrottleConfig) {
+        self.upload_throttle = BuilderValue::Set(value);
+    }
+
+    pub fn upload_queue_limits(&mut self, value: UploadQueueLimitsConfig) {
+        self.upload_queue_limits = BuilderValue::Set(value);
+    }
+
+    pub fn remote_listing(&mut self, value: RemoteListingConfig) {
+        self.remote_listing = BuilderValue::Set(value);
+    }
+
+    pub fn remote_encryption(&mut self, value: RemoteEncryptionConfig) {
+        self.remote_encryption = BuilderValue::Set(value);
+    }
+
+    pub fn remote_compression(&mut self, value: RemoteCompressionConfig) {
+        self.remote_compression = BuilderValue::Set(value);
+    }
+
+    pub fn remote_multipart_upload(&mut self, value: RemoteMultipartUploadConfig) {
+        self.remote_multipart_upload = BuilderValue::Set(value);
+    }
+
+    pub fn remote_layer_path_scheme(&mut self, value: LayerPathScheme) {
+        self.remote_layer_path_scheme = BuilderValue::Set(value);
+    }
+
+    pub fn remote_scrub(&mut self, value: Option<RemoteScrubConfig>) {
+        self.remote_scrub = BuilderValue::Set(value);
+    }
+
+    pub fn remote_circuit_breaker(&mut self, value: Option<RemoteCircuitBreakerConfig>) {
+        self.remote_circuit_breaker = BuilderValue::Set(value);
+    }
+
+    pub fn remote_index_lsn_check(&mut self, value: IndexLsnCheck) {
+        self.remote_index_lsn_check = BuilderValue::Set(value);
+    }
+
+    pub fn remote_retry(&mut self, value: RemoteRetryConfig) {
+        self.remote_retry = BuilderValue::Set(value);
+    }
+
+    pub fn remote_deletion_grace_period(&mut self, value: Duration) {
+        self.remote_deletion_grace_period = BuilderValue::Set(value);
+    }
+
+    pub fn remote_index_segment_layers(&mut self, value: Option<NonZeroUsize>) {
+        self.remote_index_segment_layers = BuilderValue::Set(value);
+    }
+
+    pub fn remote_index_snapshot_period(&mut self, value: Duration) {
+        self.remote_index_snapshot_period = BuilderValue::Set(value);
+    }
+
+    pub fn remote_storage_prices(&mut self, value: RemoteStoragePrices) {
+        self.remote_storage_prices = BuilderValue::Set(value);
+    }
+
+    pub fn upload_events(&mut self, value: Option<UploadEventsConfig>) {
+        self.upload_events = BuilderValue::Set(value);
+    }
+
+    pub fn parallel_download(&mut self, value: Option<ParallelDownloadConfig>) {
+        self.parallel_download = BuilderValue::Set(value);
+    }
+
+    pub fn concurrent_layer_downloads(&mut self, value: NonZeroUsize) {
+        self.concurrent_layer_downloads = BuilderValue::Set(value);
+    }
+
     pub fn ondemand_download_behavior_treat_error_as_warn(
         &mut self,
         ondemand_download_behavior_treat_error_as_warn: bool,
@@ -463,6 +953,10 @@ impl PageServerConfigBuilder {
         self.background_task_maximum_delay = BuilderValue::Set(delay);
     }
 
+    pub fn shutdown_deadline(&mut self, deadline: Duration) {
+        self.shutdown_deadline = BuilderValue::Set(deadline);
+    }
+
     pub fn build(self) -> anyhow::Result<PageServerConf> {
         let concurrent_tenant_size_logical_size_queries = self
             .concurrent_tenant_size_logical_size_queries
@@ -485,10 +979,36 @@ impl PageServerConfigBuilder {
             wal_redo_timeout: self
                 .wal_redo_timeout
                 .ok_or(anyhow!("missing wal_redo_timeout"))?,
+            wal_redo_native_heap: self
+                .wal_redo_native_heap
+                .ok_or(anyhow!("missing wal_redo_native_heap"))?,
+            wal_redo_native_btree: self
+                .wal_redo_native_btree
+                .ok_or(anyhow!("missing wal_redo_native_btree"))?,
+            wal_redo_native_fpi: self
+                .wal_redo_native_fpi
+                .ok_or(anyhow!("missing wal_redo_native_fpi"))?,
+            wal_redo_policy: self.wal_redo_policy.ok_or(anyhow!("missing wal_redo_policy"))?,
+            wal_redo_stderr: self.wal_redo_stderr.ok_or(anyhow!("missing wal_redo_stderr"))?,
+            wal_redo_slow_threshold: self
+                .wal_redo_slow_threshold
+                .ok_or(anyhow!("missing wal_redo_slow_threshold"))?,
+            wal_redo_metrics_per_timeline: self
+                .wal_redo_metrics_per_timeline
+                .ok_or(anyhow!("missing wal_redo_metrics_per_timeline"))?,
+            wal_redo_ping_period: self
+                .wal_redo_ping_period
+                .ok_or(anyhow!("missing wal_redo_ping_period"))?,
+            wal_redo_threads: self
+                .wal_redo_threads
+                .ok_or(anyhow!("missing wal_redo_threads"))?,
             superuser: self.superuser.ok_or(anyhow!("missing superuser"))?,
             page_cache_size: self
                 .page_cache_size
                 .ok_or(anyhow!("missing page_cache_size"))?,
+            materialized_page_cache_size: self
+                .materialized_page_cache_size
+                .ok_or(anyhow!("missing materialized_page_cache_size"))?,
             max_file_descriptors: self
                 .max_file_descriptors
                 .ok_or(anyhow!("missing max_file_descriptors"))?,
@@ -506,6 +1026,9 @@ impl PageServerConfigBuilder {
             remote_storage_config: self
                 .remote_storage_config
                 .ok_or(anyhow!("missing remote_storage_config"))?,
+            remote_storage_mirror_config: self
+                .remote_storage_mirror_config
+                .ok_or(anyhow!("missing remote_storage_mirror_config"))?,
             id: self.id.ok_or(anyhow!("missing id"))?,
             // TenantConf is handled separately
             default_tenant_conf: TenantConf::default(),
@@ -516,6 +1039,7 @@ impl PageServerConfigBuilder {
                 .broker_keepalive_interval
                 .ok_or(anyhow!("No broker keepalive interval provided"))?,
             log_format: self.log_format.ok_or(anyhow!("missing log_format"))?,
+            error_budgets: self.error_budgets.ok_or(anyhow!("missing error_budgets"))?,
             concurrent_tenant_size_logical_size_queries: ConfigurableSemaphore::new(
                 concurrent_tenant_size_logical_size_queries,
             ),
@@ -537,6 +1061,78 @@ impl PageServerConfigBuilder {
             disk_usage_based_eviction: self
                 .disk_usage_based_eviction
                 .ok_or(anyhow!("missing disk_usage_based_eviction"))?,
+            disk_space_monitor: self
+                .disk_space_monitor
+                .ok_or(anyhow!("missing disk_space_monitor"))?,
+            layer_watermarks: self
+                .layer_watermarks
+                .ok_or(anyhow!("missing layer_watermarks"))?,
+            ingest_coalescing: self
+                .ingest_coalescing
+                .ok_or(anyhow!("missing ingest_coalescing"))?,
+            getpage_timing: self
+                .getpage_timing
+                .ok_or(anyhow!("missing getpage_timing"))?,
+            metrics_push: self
+                .metrics_push
+                .ok_or(anyhow!("missing metrics_push"))?,
+            histogram_buckets: self
+                .histogram_buckets
+                .ok_or(anyhow!("missing histogram_buckets"))?,
+            background_jobs: self
+                .background_jobs
+                .ok_or(anyhow!("missing background_jobs"))?,
+            upload_throttle: self
+                .upload_throttle
+                .ok_or(anyhow!("missing upload_throttle"))?,
+            upload_queue_limits: self
+                .upload_queue_limits
+                .ok_or(anyhow!("missing upload_queue_limits"))?,
+            remote_listing: self
+                .remote_listing
+                .ok_or(anyhow!("missing remote_listing"))?,
+            remote_encryption: self
+                .remote_encryption
+                .ok_or(anyhow!("missing remote_encryption"))?,
+            remote_compression: self
+                .remote_compression
+                .ok_or(anyhow!("missing remote_compression"))?,
+            remote_multipart_upload: self
+                .remote_multipart_upload
+                .ok_or(anyhow!("missing remote_multipart_upload"))?,
+            remote_layer_path_scheme: self
+                .remote_layer_path_scheme
+                .ok_or(anyhow!("missing remote_layer_path_scheme"))?,
+            remote_scrub: self.remote_scrub.ok_or(anyhow!("missing remote_scrub"))?,
+            remote_circuit_breaker: self
+                .remote_circuit_breaker
+                .ok_or(anyhow!("missing remote_circuit_breaker"))?,
+            remote_index_lsn_check: self
+                .remote_index_lsn_check
+                .ok_or(anyhow!("missing remote_index_lsn_check"))?,
+            remote_retry: self.remote_retry.ok_or(anyhow!("missing remote_retry"))?,
+            remote_deletion_grace_period: self
+                .remote_deletion_grace_period
+                .ok_or(anyhow!("missing remote_deletion_grace_period"))?,
+            remote_index_segment_layers: self
+                .remote_index_segment_layers
+                .ok_or(anyhow!("missing remote_index_segment_layers"))?,
+            remote_index_snapshot_period: self
+                .remote_index_snapshot_period
+                .ok_or(anyhow!("missing remote_index_snapshot_period"))?,
+            remote_storage_prices: self
+                .remote_storage_prices
+                .ok_or(anyhow!("missing remote_storage_prices"))?,
+            upload_events: self
+                .upload_events
+                .ok_or(anyhow!("missing upload_events"))?,
+            parallel_download: self
+                .parallel_download
+                .ok_or(anyhow!("missing parallel_download"))?,
+            concurrent_layer_downloads: LayerDownloadLimiter::new(
+                self.concurrent_layer_downloads
+                    .ok_or(anyhow!("missing concurrent_layer_downloads"))?,
+            ),
             test_remote_failures: self
                 .test_remote_failures
                 .ok_or(anyhow!("missing test_remote_failuers"))?,
@@ -548,10 +1144,17 @@ impl PageServerConfigBuilder {
             background_task_maximum_delay: self
                 .background_task_maximum_delay
                 .ok_or(anyhow!("missing background_task_maximum_delay"))?,
+            shutdown_deadline: self
+                .shutdown_deadline
+                .ok_or(anyhow!("missing shutdown_deadline"))?,
         })
     }
 }
 
+/// Postgres versions that the pageserver supports, see [`PageServerConf::pg_distrib_dir`]. An
+/// installation may ship the binaries of only some of them.
+pub const SUPPORTED_PG_VERSIONS: &[u32] = &[14, 15];
+
 impl PageServerConf {
     //
     // Repository paths, relative to workdir.
@@ -580,6 +1183,24 @@ impl PageServerConf {
         self.tenant_path(tenant_id).join(TENANT_CONFIG_NAME)
     }
 
+    pub fn tenant_generation_path(&self, tenant_id: &TenantId) -> PathBuf {
+        self.tenant_path(tenant_id).join(TENANT_GENERATION_FILE_NAME)
+    }
+
+    pub fn tenant_deferred_deletions_path(&self, tenant_id: &TenantId) -> PathBuf {
+        self.tenant_path(tenant_id)
+            .join(TENANT_DEFERRED_DELETIONS_FILE_NAME)
+    }
+
+    pub fn tenant_remote_storage_path(&self, tenant_id: &TenantId) -> PathBuf {
+        self.tenant_path(tenant_id)
+            .join(TENANT_REMOTE_STORAGE_FILE_NAME)
+    }
+
+    pub fn tenant_import_path(&self, tenant_id: &TenantId) -> PathBuf {
+        self.tenant_path(tenant_id).join(TENANT_IMPORT_FILE_NAME)
+    }
+
     pub fn timelines_path(&self, tenant_id: &TenantId) -> PathBuf {
         self.tenant_path(tenant_id).join(TIMELINES_SEGMENT_NAME)
     }
@@ -689,8 +1310,31 @@ impl PageServerConf {
                 "availability_zone" => builder.availability_zone(Some(parse_toml_string(key, item)?)),
                 "wait_lsn_timeout" => builder.wait_lsn_timeout(parse_toml_duration(key, item)?),
                 "wal_redo_timeout" => builder.wal_redo_timeout(parse_toml_duration(key, item)?),
+                "wal_redo_native_heap" => builder.wal_redo_native_heap(parse_toml_bool(key, item)?),
+                "wal_redo_native_btree" => builder.wal_redo_native_btree(parse_toml_bool(key, item)?),
+                "wal_redo_native_fpi" => builder.wal_redo_native_fpi(parse_toml_bool(key, item)?),
+                "wal_redo_policy" => {
+                    builder.wal_redo_policy(
+                        deserialize_from_item("wal_redo_policy", item)
+                            .context("parse wal_redo_policy")?
+                    )
+                },
+                "wal_redo_stderr" => {
+                    builder.wal_redo_stderr(
+                        deserialize_from_item("wal_redo_stderr", item)
+                            .context("parse wal_redo_stderr")?
+                    )
+                },
+                "wal_redo_slow_threshold" => builder.wal_redo_slow_threshold(parse_toml_duration(key, item)?),
+                "wal_redo_metrics_per_timeline" => builder.wal_redo_metrics_per_timeline(parse_toml_bool(key, item)?),
+                "wal_redo_ping_period" => builder.wal_redo_ping_period(parse_toml_duration(key, item)?),
+                "wal_redo_threads" => builder.wal_redo_threads(parse_toml_u64(key, item)? as usize),
                 "initial_superuser_name" => builder.superuser(parse_toml_string(key, item)?),
                 "page_cache_size" => builder.page_cache_size(parse_toml_u64(key, item)? as usize),
+                "materialized_page_cache_size" => builder.materialized_page_cache_size(Some(
+                    NonZeroUsize::new(parse_toml_u64(key, item)? as usize)
+                        .context("materialized_page_cache_size must be positive")?
+                )),
                 "max_file_descriptors" => {
                     builder.max_file_descriptors(parse_toml_u64(key, item)? as usize)
                 }
@@ -705,6 +1349,9 @@ impl PageServerConf {
                 "remote_storage" => {
                     builder.remote_storage_config(RemoteStorageConfig::from_toml(item)?)
                 }
+                "remote_storage_mirror" => {
+                    builder.remote_storage_mirror_config(RemoteStorageConfig::from_toml(item)?)
+                }
                 "tenant_config" => {
                     t_conf = Self::parse_toml_tenant_conf(item)?;
                 }
@@ -714,6 +1361,10 @@ impl PageServerConf {
                 "log_format" => builder.log_format(
                     LogFormat::from_config(&parse_toml_string(key, item)?)?
                 ),
+                "error_budgets" => builder.error_budgets(
+                    deserialize_from_item("error_budgets", item)
+                        .context("parse error_budgets")?
+                ),
                 "concurrent_tenant_size_logical_size_queries" => builder.concurrent_tenant_size_logical_size_queries({
                     let input = parse_toml_string(key, item)?;
                     let permits = input.parse::<usize>().context("expected a number of initial permits, not {s:?}")?;
@@ -735,8 +1386,145 @@ impl PageServerConf {
                             .context("parse disk_usage_based_eviction")?
                     )
                 },
+                "disk_space_monitor" => {
+                    builder.disk_space_monitor(
+                        deserialize_from_item("disk_space_monitor", item)
+                            .context("parse disk_space_monitor")?
+                    )
+                },
+                "layer_watermarks" => {
+                    let layer_watermarks: LayerWatermarksConfig = deserialize_from_item("layer_watermarks", item)
+                        .context("parse layer_watermarks")?;
+                    layer_watermarks.validate().context("invalid layer_watermarks")?;
+                    builder.layer_watermarks(Some(layer_watermarks))
+                },
+                "ingest_coalescing" => {
+                    let ingest_coalescing: IngestCoalescingConfig = deserialize_from_item("ingest_coalescing", item)
+                        .context("parse ingest_coalescing")?;
+                    ingest_coalescing.validate().context("invalid ingest_coalescing")?;
+                    builder.ingest_coalescing(Some(ingest_coalescing))
+                },
+                "getpage_timing" => {
+                    builder.getpage_timing(
+                        deserialize_from_item("getpage_timing", item)
+                            .context("parse getpage_timing")?
+                    )
+                },
+                "metrics_push" => {
+                    builder.metrics_push(
+                        deserialize_from_item("metrics_push", item)
+                            .context("parse metrics_push")?
+                    )
+                },
+                "histogram_buckets" => {
+                    let buckets: HistogramBucketsConfig = deserialize_from_item("histogram_buckets", item)
+                        .context("parse histogram_buckets")?;
+                    buckets.validate().context("invalid histogram_buckets")?;
+                    builder.histogram_buckets(buckets)
+                },
+                "background_jobs" => {
+                    let background_jobs: BackgroundJobsConfig = deserialize_from_item("background_jobs", item)
+                        .context("parse background_jobs")?;
+                    background_jobs.validate().context("invalid background_jobs")?;
+                    builder.background_jobs(background_jobs)
+                },
+                "upload_throttle" => {
+                    builder.upload_throttle(
+                        deserialize_from_item("upload_throttle", item)
+                            .context("parse upload_throttle")?
+                    )
+                },
+                "upload_queue_limits" => {
+                    builder.upload_queue_limits(
+                        deserialize_from_item("upload_queue_limits", item)
+                            .context("parse upload_queue_limits")?
+                    )
+                },
+                "remote_listing" => {
+                    builder.remote_listing(
+                        deserialize_from_item("remote_listing", item)
+                            .context("parse remote_listing")?
+                    )
+                },
+                "remote_encryption" => {
+                    builder.remote_encryption(
+                        deserialize_from_item("remote_encryption", item)
+                            .context("parse remote_encryption")?
+                    )
+                },
+                "remote_compression" => {
+                    builder.remote_compression(
+                        deserialize_from_item("remote_compression", item)
+                            .context("parse remote_compression")?
+                    )
+                },
+                "remote_multipart_upload" => {
+                    builder.remote_multipart_upload(
+                        deserialize_from_item("remote_multipart_upload", item)
+                            .context("parse remote_multipart_upload")?
+                    )
+                },
+                "remote_layer_path_scheme" => {
+                    builder.remote_layer_path_scheme(
+                        deserialize_from_item("remote_layer_path_scheme", item)
+                            .context("parse remote_layer_path_scheme")?
+                    )
+                },
+                "remote_scrub" => {
+                    builder.remote_scrub(
+                        deserialize_from_item("remote_scrub", item)
+                            .context("parse remote_scrub")?
+                    )
+                },
+                "remote_circuit_breaker" => {
+                    builder.remote_circuit_breaker(
+                        deserialize_from_item("remote_circuit_breaker", item)
+                            .context("parse remote_circuit_breaker")?
+                    )
+                },
+                "remote_index_lsn_check" => {
+                    builder.remote_index_lsn_check(
+                        deserialize_from_item("remote_index_lsn_check", item)
+                            .context("parse remote_index_lsn_check")?
+                    )
+                },
+                "remote_retry" => {
+                    builder.remote_retry(
+                        deserialize_from_item("remote_retry", item)
+                            .context("parse remote_retry")?
+                    )
+                },
+                "remote_deletion_grace_period" => builder.remote_deletion_grace_period(parse_toml_duration(key, item)?),
+                "remote_index_segment_layers" => builder.remote_index_segment_layers(Some(
+                    NonZeroUsize::new(parse_toml_u64(key, item)? as usize)
+                        .context("remote_index_segment_layers must be positive")?
+                )),
+                "remote_index_snapshot_period" => builder.remote_index_snapshot_period(parse_toml_duration(key, item)?),
+                "remote_storage_prices" => {
+                    let prices: RemoteStoragePrices = deserialize_from_item("remote_storage_prices", item)
+                        .context("parse remote_storage_prices")?;
+                    prices.validate().context("invalid remote_storage_prices")?;
+                    builder.remote_storage_prices(prices)
+                },
+                "upload_events" => {
+                    builder.upload_events(
+                        deserialize_from_item("upload_events", item)
+                            .context("parse upload_events")?
+                    )
+                },
+                "parallel_download" => {
+                    builder.parallel_download(
+                        deserialize_from_item("parallel_download", item)
+                            .context("parse parallel_download")?
+                    )
+                },
+                "concurrent_layer_downloads" => builder.concurrent_layer_downloads(
+                    NonZeroUsize::new(parse_toml_u64(key, item)? as usize)
+                        .context("concurrent_layer_downloads must be positive")?
+                ),
                 "ondemand_download_behavior_treat_error_as_warn" => builder.ondemand_download_behavior_treat_error_as_warn(parse_toml_bool(key, item)?),
                 "background_task_maximum_delay" => builder.background_task_maximum_delay(parse_toml_duration(key, item)?),
+                "shutdown_deadline" => builder.shutdown_deadline(parse_toml_duration(key, item)?),
                 _ => bail!("unrecognized pageserver option '{key}'"),
             }
         }
@@ -863,6 +1651,53 @@ impl PageServerConf {
             );
         }
 
+        if let Some(item) = item.get("prefetch_distance") {
+            t_conf.prefetch_distance = Some(
+                deserialize_from_item("prefetch_distance", item)
+                    .context("parse prefetch_distance")?,
+            );
+        }
+
+        if let Some(item) = item.get("prefetch_max_inflight") {
+            t_conf.prefetch_max_inflight = Some(
+                deserialize_from_item("prefetch_max_inflight", item)
+                    .context("parse prefetch_max_inflight")?,
+            );
+        }
+
+        if let Some(tiering_policy) = item.get("tiering_policy") {
+            t_conf.tiering_policy = Some(
+                deserialize_from_item("tiering_policy", tiering_policy)
+                    .context("parse tiering_policy")?,
+            );
+        }
+
+        if let Some(item) = item.get("deletion_dry_run") {
+            t_conf.deletion_dry_run = Some(
+                deserialize_from_item("deletion_dry_run", item)
+                    .context("parse deletion_dry_run")?,
+            );
+        }
+
+        if let Some(item) = item.get("max_inprogress_uploads") {
+            t_conf.max_inprogress_uploads = Some(
+                deserialize_from_item("max_inprogress_uploads", item)
+                    .context("parse max_inprogress_uploads")?,
+            );
+        }
+
+        if let Some(item) = item.get("walredo_memory_limit") {
+            t_conf.walredo_memory_limit = Some(
+                deserialize_from_item("walredo_memory_limit", item)
+                    .context("parse walredo_memory_limit")?,
+            );
+        }
+
+        if let Some(item) = item.get("walredo_cpu_time_limit") {
+            t_conf.walredo_cpu_time_limit =
+                Some(parse_toml_duration("walredo_cpu_time_limit", item)?);
+        }
+
         Ok(t_conf)
     }
 
@@ -878,7 +1713,17 @@ impl PageServerConf {
             id: NodeId(0),
             wait_lsn_timeout: Duration::from_secs(60),
             wal_redo_timeout: Duration::from_secs(60),
+            wal_redo_native_heap: false,
+            wal_redo_native_btree: false,
+            wal_redo_native_fpi: false,
+            wal_redo_policy: WalRedoPolicy::default(),
+            wal_redo_stderr: WalRedoStderrConfig::default(),
+            wal_redo_slow_threshold: Duration::from_secs(1),
+            wal_redo_metrics_per_timeline: false,
+            wal_redo_ping_period: Duration::ZERO,
+            wal_redo_threads: defaults::DEFAULT_WAL_REDO_THREADS,
             page_cache_size: defaults::DEFAULT_PAGE_CACHE_SIZE,
+            materialized_page_cache_size: None,
             max_file_descriptors: defaults::DEFAULT_MAX_FILE_DESCRIPTORS,
             listen_pg_addr: defaults::DEFAULT_PG_LISTEN_ADDR.to_string(),
             listen_http_addr: defaults::DEFAULT_HTTP_LISTEN_ADDR.to_string(),
@@ -890,10 +1735,12 @@ impl PageServerConf {
             pg_auth_type: AuthType::Trust,
             auth_validation_public_key_path: None,
             remote_storage_config: None,
+            remote_storage_mirror_config: None,
             default_tenant_conf: TenantConf::default(),
             broker_endpoint: storage_broker::DEFAULT_ENDPOINT.parse().unwrap(),
             broker_keepalive_interval: Duration::from_secs(5000),
             log_format: LogFormat::from_str(defaults::DEFAULT_LOG_FORMAT).unwrap(),
+            error_budgets: Vec::new(),
             concurrent_tenant_size_logical_size_queries: ConfigurableSemaphore::default(),
             eviction_task_immitated_concurrent_logical_size_queries: ConfigurableSemaphore::default(
             ),
@@ -902,9 +1749,35 @@ impl PageServerConf {
             metric_collection_endpoint: defaults::DEFAULT_METRIC_COLLECTION_ENDPOINT,
             synthetic_size_calculation_interval: Duration::from_secs(60),
             disk_usage_based_eviction: None,
+            disk_space_monitor: None,
+            layer_watermarks: None,
+            ingest_coalescing: None,
+            getpage_timing: None,
+            metrics_push: None,
+            histogram_buckets: HistogramBucketsConfig::default(),
+            background_jobs: BackgroundJobsConfig::default(),
+            upload_throttle: UploadThrottleConfig::default(),
+            upload_queue_limits: UploadQueueLimitsConfig::default(),
+            remote_listing: RemoteListingConfig::default(),
+            remote_encryption: RemoteEncryptionConfig::default(),
+            remote_compression: RemoteCompressionConfig::default(),
+            remote_multipart_upload: RemoteMultipartUploadConfig::default(),
+            remote_layer_path_scheme: LayerPathScheme::default(),
+            remote_scrub: None,
+            remote_circuit_breaker: None,
+            remote_index_lsn_check: IndexLsnCheck::default(),
+            remote_retry: RemoteRetryConfig::default(),
+            remote_deletion_grace_period: Duration::ZERO,
+            remote_index_segment_layers: None,
+            remote_index_snapshot_period: Duration::ZERO,
+            remote_storage_prices: RemoteStoragePrices::default(),
+            upload_events: None,
+            parallel_download: None,
+            concurrent_layer_downloads: LayerDownloadLimiter::default(),
             test_remote_failures: 0,
             ondemand_download_behavior_treat_error_as_warn: false,
             background_task_maximum_delay: Duration::ZERO,
+            shutdown_deadline: Duration::from_secs(60),
         }
     }
 }
@@ -1032,7 +1905,7 @@ impl ConfigurableSemaphore {
 mod tests {
     use std::{
         fs,
-        num::{NonZeroU32, NonZeroUsize},
+        num::{NonZeroU32, NonZeroU64, NonZeroUsize},
     };
 
     use remote_storage::{RemoteStorageKind, S3Config};
@@ -1040,7 +1913,11 @@ mod tests {
     use utils::serde_percent::Percent;
 
     use super::*;
-    use crate::{tenant::config::EvictionPolicy, DEFAULT_PG_VERSION};
+    use crate::{
+        background_jobs::BackgroundJobKind,
+        tenant::{config::EvictionPolicy, GiveUp, RetryPolicy},
+        DEFAULT_PG_VERSION,
+    };
 
     const ALL_BASE_VALUES_TOML: &str = r#"
 # Initial configuration file created by 'pageserver --init'
@@ -1050,8 +1927,16 @@ listen_http_addr = '127.0.0.1:9898'
 
 wait_lsn_timeout = '111 s'
 wal_redo_timeout = '111 s'
+wal_redo_native_heap = true
+wal_redo_native_btree = true
+wal_redo_native_fpi = true
+wal_redo_slow_threshold = '222 ms'
+wal_redo_metrics_per_timeline = true
+wal_redo_ping_period = '333 s'
+wal_redo_threads = 3
 
 page_cache_size = 444
+materialized_page_cache_size = 222
 max_file_descriptors = 333
 
 # initial superuser role name to use when creating a new tenant
@@ -1065,6 +1950,10 @@ synthetic_size_calculation_interval = '333 s'
 
 log_format = 'json'
 background_task_maximum_delay = '334 s'
+shutdown_deadline = '335 s'
+remote_deletion_grace_period = '336 s'
+remote_index_segment_layers = 337
+remote_index_snapshot_period = '338 s'
 
 "#;
 
@@ -1092,8 +1981,22 @@ background_task_maximum_delay = '334 s'
                 availability_zone: None,
                 wait_lsn_timeout: humantime::parse_duration(defaults::DEFAULT_WAIT_LSN_TIMEOUT)?,
                 wal_redo_timeout: humantime::parse_duration(defaults::DEFAULT_WAL_REDO_TIMEOUT)?,
+                wal_redo_native_heap: false,
+                wal_redo_native_btree: false,
+                wal_redo_native_fpi: false,
+                wal_redo_policy: WalRedoPolicy::default(),
+                wal_redo_stderr: WalRedoStderrConfig::default(),
+                wal_redo_slow_threshold: humantime::parse_duration(
+                    defaults::DEFAULT_WAL_REDO_SLOW_THRESHOLD,
+                )?,
+                wal_redo_metrics_per_timeline: false,
+                wal_redo_ping_period: humantime::parse_duration(
+                    defaults::DEFAULT_WAL_REDO_PING_PERIOD,
+                )?,
+                wal_redo_threads: defaults::DEFAULT_WAL_REDO_THREADS,
                 superuser: defaults::DEFAULT_SUPERUSER.to_string(),
                 page_cache_size: defaults::DEFAULT_PAGE_CACHE_SIZE,
+                materialized_page_cache_size: None,
                 max_file_descriptors: defaults::DEFAULT_MAX_FILE_DESCRIPTORS,
                 workdir,
                 pg_distrib_dir,
@@ -1101,12 +2004,14 @@ background_task_maximum_delay = '334 s'
                 pg_auth_type: AuthType::Trust,
                 auth_validation_public_key_path: None,
                 remote_storage_config: None,
+                remote_storage_mirror_config: None,
                 default_tenant_conf: TenantConf::default(),
                 broker_endpoint: storage_broker::DEFAULT_ENDPOINT.parse().unwrap(),
                 broker_keepalive_interval: humantime::parse_duration(
                     storage_broker::DEFAULT_KEEPALIVE_INTERVAL
                 )?,
                 log_format: LogFormat::from_str(defaults::DEFAULT_LOG_FORMAT).unwrap(),
+                error_budgets: Vec::new(),
                 concurrent_tenant_size_logical_size_queries: ConfigurableSemaphore::default(),
                 eviction_task_immitated_concurrent_logical_size_queries:
                     ConfigurableSemaphore::default(),
@@ -1121,11 +2026,41 @@ background_task_maximum_delay = '334 s'
                     defaults::DEFAULT_SYNTHETIC_SIZE_CALCULATION_INTERVAL
                 )?,
                 disk_usage_based_eviction: None,
+                disk_space_monitor: None,
+                layer_watermarks: None,
+                ingest_coalescing: None,
+                getpage_timing: None,
+                metrics_push: None,
+                histogram_buckets: HistogramBucketsConfig::default(),
+                background_jobs: BackgroundJobsConfig::default(),
+                upload_throttle: UploadThrottleConfig::default(),
+                upload_queue_limits: UploadQueueLimitsConfig::default(),
+                remote_listing: RemoteListingConfig::default(),
+                remote_encryption: RemoteEncryptionConfig::default(),
+                remote_compression: RemoteCompressionConfig::default(),
+                remote_multipart_upload: RemoteMultipartUploadConfig::default(),
+                remote_layer_path_scheme: LayerPathScheme::default(),
+                remote_scrub: None,
+                remote_circuit_breaker: None,
+                remote_index_lsn_check: IndexLsnCheck::default(),
+                remote_retry: RemoteRetryConfig::default(),
+                remote_deletion_grace_period: humantime::parse_duration(
+                    defaults::DEFAULT_REMOTE_DELETION_GRACE_PERIOD
+                )?,
+                remote_index_segment_layers: None,
+                remote_index_snapshot_period: humantime::parse_duration(
+                    defaults::DEFAULT_REMOTE_INDEX_SNAPSHOT_PERIOD
+                )?,
+                remote_storage_prices: RemoteStoragePrices::default(),
+                upload_events: None,
+                parallel_download: None,
+                concurrent_layer_downloads: LayerDownloadLimiter::default(),
                 test_remote_failures: 0,
                 ondemand_download_behavior_treat_error_as_warn: false,
                 background_task_maximum_delay: humantime::parse_duration(
                     defaults::DEFAULT_BACKGROUND_TASK_MAXIMUM_DELAY
                 )?,
+                shutdown_deadline: humantime::parse_duration(defaults::DEFAULT_SHUTDOWN_DEADLINE)?,
             },
             "Correct defaults should be used when no config values are provided"
         );
@@ -1157,8 +2092,18 @@ background_task_maximum_delay = '334 s'
                 availability_zone: None,
                 wait_lsn_timeout: Duration::from_secs(111),
                 wal_redo_timeout: Duration::from_secs(111),
+                wal_redo_native_heap: true,
+                wal_redo_native_btree: true,
+                wal_redo_native_fpi: true,
+                wal_redo_policy: WalRedoPolicy::default(),
+                wal_redo_stderr: WalRedoStderrConfig::default(),
+                wal_redo_slow_threshold: Duration::from_millis(222),
+                wal_redo_metrics_per_timeline: true,
+                wal_redo_ping_period: Duration::from_secs(333),
+                wal_redo_threads: 3,
                 superuser: "zzzz".to_string(),
                 page_cache_size: 444,
+                materialized_page_cache_size: NonZeroUsize::new(222),
                 max_file_descriptors: 333,
                 workdir,
                 pg_distrib_dir,
@@ -1166,10 +2111,12 @@ background_task_maximum_delay = '334 s'
                 pg_auth_type: AuthType::Trust,
                 auth_validation_public_key_path: None,
                 remote_storage_config: None,
+                remote_storage_mirror_config: None,
                 default_tenant_conf: TenantConf::default(),
                 broker_endpoint: storage_broker::DEFAULT_ENDPOINT.parse().unwrap(),
                 broker_keepalive_interval: Duration::from_secs(5),
                 log_format: LogFormat::Json,
+                error_budgets: Vec::new(),
                 concurrent_tenant_size_logical_size_queries: ConfigurableSemaphore::default(),
                 eviction_task_immitated_concurrent_logical_size_queries:
                     ConfigurableSemaphore::default(),
@@ -1178,9 +2125,35 @@ background_task_maximum_delay = '334 s'
                 metric_collection_endpoint: Some(Url::parse("http://localhost:80/metrics")?),
                 synthetic_size_calculation_interval: Duration::from_secs(333),
                 disk_usage_based_eviction: None,
+                disk_space_monitor: None,
+                layer_watermarks: None,
+                ingest_coalescing: None,
+                getpage_timing: None,
+                metrics_push: None,
+                histogram_buckets: HistogramBucketsConfig::default(),
+                background_jobs: BackgroundJobsConfig::default(),
+                upload_throttle: UploadThrottleConfig::default(),
+                upload_queue_limits: UploadQueueLimitsConfig::default(),
+                remote_listing: RemoteListingConfig::default(),
+                remote_encryption: RemoteEncryptionConfig::default(),
+                remote_compression: RemoteCompressionConfig::default(),
+                remote_multipart_upload: RemoteMultipartUploadConfig::default(),
+                remote_layer_path_scheme: LayerPathScheme::default(),
+                remote_scrub: None,
+                remote_circuit_breaker: None,
+                remote_index_lsn_check: IndexLsnCheck::default(),
+                remote_retry: RemoteRetryConfig::default(),
+                remote_deletion_grace_period: Duration::from_secs(336),
+                remote_index_segment_layers: NonZeroUsize::new(337),
+                remote_index_snapshot_period: Duration::from_secs(338),
+                remote_storage_prices: RemoteStoragePrices::default(),
+                upload_events: None,
+                parallel_download: None,
+                concurrent_layer_downloads: LayerDownloadLimiter::default(),
                 test_remote_failures: 0,
                 ondemand_download_behavior_treat_error_as_warn: false,
                 background_task_maximum_delay: Duration::from_secs(334),
+                shutdown_deadline: Duration::from_secs(335),
             },
             "Should be able to parse all basic config values correctly"
         );
@@ -1306,6 +2279,8 @@ broker_endpoint = '{broker_endpoint}'
                         endpoint: Some(endpoint.clone()),
                         concurrency_limit: s3_concurrency_limit,
                         max_keys_per_list_response: None,
+                        server_side_encryption: None,
+                        credentials: None,
                     }),
                 },
                 "Remote storage config should correctly parse the S3 config"
@@ -1343,6 +2318,490 @@ trace_read_requests = {trace_read_requests}"#,
         Ok(())
     }
 
+    #[test]
+    fn parse_upload_throttle() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+
+        let parse = |throttle: &str| -> anyhow::Result<PageServerConf> {
+            let config_string = format!(
+                r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{}'
+broker_endpoint = 'http://127.0.0.1:7777'
+upload_throttle = {throttle}"#,
+                pg_distrib_dir.display(),
+            );
+            let toml = config_string.parse()?;
+            PageServerConf::parse_and_validate(&toml, &workdir)
+        };
+
+        let conf = parse("{ bytes_per_second = 1048576 }")?;
+        assert_eq!(
+            conf.upload_throttle,
+            UploadThrottleConfig {
+                bytes_per_second: NonZeroU64::new(1048576),
+                ops_per_second: None,
+            }
+        );
+
+        for invalid in ["{ ops_per_second = 0 }", "{ requests_per_second = 10 }"] {
+            assert!(
+                parse(invalid).is_err(),
+                "upload throttle {invalid} should be rejected"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_remote_encryption() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+
+        let parse = |encryption: &str| -> anyhow::Result<PageServerConf> {
+            let config_string = format!(
+                r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{}'
+broker_endpoint = 'http://127.0.0.1:7777'
+remote_encryption = {encryption}"#,
+                pg_distrib_dir.display(),
+            );
+            let toml = config_string.parse()?;
+            PageServerConf::parse_and_validate(&toml, &workdir)
+        };
+
+        let conf = parse("{ mode = 'server_side' }")?;
+        let expected = RemoteEncryptionConfig::ServerSide { kms_key_id: None };
+        assert_eq!(conf.remote_encryption, expected);
+
+        let conf = parse("{ mode = 'client_side', key_file = 'layers.key' }")?;
+        let expected = RemoteEncryptionConfig::ClientSide {
+            key_file: PathBuf::from("layers.key"),
+        };
+        assert_eq!(conf.remote_encryption, expected);
+
+        for invalid in [
+            "{ mode = 'client_side' }",
+            "{ mode = 'server_side', key_file = 'layers.key' }",
+            "{ mode = 'sse' }",
+        ] {
+            assert!(
+                parse(invalid).is_err(),
+                "remote encryption {invalid} should be rejected"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_remote_compression() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+
+        let parse = |compression: &str| -> anyhow::Result<PageServerConf> {
+            let config_string = format!(
+                r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{}'
+broker_endpoint = 'http://127.0.0.1:7777'
+remote_compression = {compression}"#,
+                pg_distrib_dir.display(),
+            );
+            let toml = config_string.parse()?;
+            PageServerConf::parse_and_validate(&toml, &workdir)
+        };
+
+        let conf = parse("{ algorithm = 'zstd' }")?;
+        let expected = RemoteCompressionConfig::Zstd { level: 3 };
+        assert_eq!(conf.remote_compression, expected);
+
+        let conf = parse("{ algorithm = 'zstd', level = 19 }")?;
+        let expected = RemoteCompressionConfig::Zstd { level: 19 };
+        assert_eq!(conf.remote_compression, expected);
+
+        let conf = parse("{ algorithm = 'none' }")?;
+        assert_eq!(conf.remote_compression, RemoteCompressionConfig::None);
+
+        for invalid in [
+            "{ algorithm = 'lz4' }",
+            "{ algorithm = 'zstd', levle = 19 }",
+            "'zstd'",
+        ] {
+            assert!(
+                parse(invalid).is_err(),
+                "remote compression {invalid} should be rejected"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_remote_scrub() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+
+        let parse = |scrub: &str| -> anyhow::Result<PageServerConf> {
+            let config_string = format!(
+                r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{}'
+broker_endpoint = 'http://127.0.0.1:7777'
+remote_scrub = {scrub}"#,
+                pg_distrib_dir.display(),
+            );
+            let toml = config_string.parse()?;
+            PageServerConf::parse_and_validate(&toml, &workdir)
+        };
+
+        let conf = parse("{ period = '1h' }")?;
+        let expected = RemoteScrubConfig {
+            period: Duration::from_secs(3600),
+            min_age: Duration::from_secs(24 * 3600),
+        };
+        assert_eq!(conf.remote_scrub, Some(expected));
+
+        let conf = parse("{ period = '1d', min_age = '30m' }")?;
+        let expected = RemoteScrubConfig {
+            period: Duration::from_secs(24 * 3600),
+            min_age: Duration::from_secs(30 * 60),
+        };
+        assert_eq!(conf.remote_scrub, Some(expected));
+
+        for invalid in ["{ min_age = '1h' }", "{ period = '1h', min_agee = '1h' }"] {
+            assert!(
+                parse(invalid).is_err(),
+                "remote scrub {invalid} should be rejected"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_remote_layer_path_scheme() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+
+        let parse = |scheme: &str| -> anyhow::Result<PageServerConf> {
+            let config_string = format!(
+                r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{}'
+broker_endpoint = 'http://127.0.0.1:7777'
+remote_layer_path_scheme = {scheme}"#,
+                pg_distrib_dir.display(),
+            );
+            let toml = config_string.parse()?;
+            PageServerConf::parse_and_validate(&toml, &workdir)
+        };
+
+        let conf = parse("'sharded'")?;
+        assert_eq!(conf.remote_layer_path_scheme, LayerPathScheme::Sharded);
+        let conf = parse("'flat'")?;
+        assert_eq!(conf.remote_layer_path_scheme, LayerPathScheme::Flat);
+        assert!(parse("'hashed'").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_remote_index_lsn_check() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+
+        let parse = |check: &str| -> anyhow::Result<PageServerConf> {
+            let config_string = format!(
+                r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{}'
+broker_endpoint = 'http://127.0.0.1:7777'
+remote_index_lsn_check = {check}"#,
+                pg_distrib_dir.display(),
+            );
+            let toml = config_string.parse()?;
+            PageServerConf::parse_and_validate(&toml, &workdir)
+        };
+
+        let conf = parse("'enforce'")?;
+        assert_eq!(conf.remote_index_lsn_check, IndexLsnCheck::Enforce);
+        let conf = parse("'off'")?;
+        assert_eq!(conf.remote_index_lsn_check, IndexLsnCheck::Off);
+        assert!(parse("'reject'").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_remote_retry() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+
+        let parse = |retry: &str| -> anyhow::Result<PageServerConf> {
+            let config_string = format!(
+                r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{}'
+broker_endpoint = 'http://127.0.0.1:7777'
+remote_retry = {retry}"#,
+                pg_distrib_dir.display(),
+            );
+            let toml = config_string.parse()?;
+            PageServerConf::parse_and_validate(&toml, &workdir)
+        };
+
+        // The fields left out keep the defaults of the kind
+        let conf = parse("{ upload = { max_attempts = 100, give_up = 'cancel' } }")?;
+        let expected = RetryPolicy {
+            max_attempts: 100,
+            give_up: GiveUp::Cancel,
+            ..RetryPolicy::upload()
+        };
+        assert_eq!(conf.remote_retry.upload, expected);
+        assert_eq!(conf.remote_retry.download, RetryPolicy::download());
+        assert_eq!(conf.remote_retry.deletion, RetryPolicy::deletion());
+
+        let conf = parse("{ download = { jitter = '1s', max_backoff = '10s' } }")?;
+        let expected = RetryPolicy {
+            jitter: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10),
+            ..RetryPolicy::download()
+        };
+        assert_eq!(conf.remote_retry.download, expected);
+        assert_eq!(conf.remote_retry.download.max_attempts, 11);
+        assert_eq!(conf.remote_retry.upload, RetryPolicy::upload());
+
+        for invalid in [
+            "{ uploads = { max_attempts = 1 } }",
+            "{ deletion = { max_attempt = 1 } }",
+            "{ upload = { give_up = 'never' } }",
+            "{ download = { jitter = 1 } }",
+        ] {
+            assert!(
+                parse(invalid).is_err(),
+                "remote retry {invalid} should be rejected"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_wal_redo_policy() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+
+        let parse = |policy: &str| -> anyhow::Result<PageServerConf> {
+            let config_string = format!(
+                r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{}'
+broker_endpoint = 'http://127.0.0.1:7777'
+wal_redo_policy = {policy}"#,
+                pg_distrib_dir.display(),
+            );
+            let toml = config_string.parse()?;
+            PageServerConf::parse_and_validate(&toml, &workdir)
+        };
+
+        // The fields left out keep their defaults
+        let conf = parse("{ timeout_per_record = '10ms', quarantine_threshold = 10 }")?;
+        let expected = WalRedoPolicy {
+            timeout_per_record: Duration::from_millis(10),
+            quarantine_threshold: 10,
+            ..WalRedoPolicy::default()
+        };
+        assert_eq!(conf.wal_redo_policy, expected);
+
+        let conf = parse("{ max_timeout = '5min', max_attempts = 3, quarantine_period = '1h' }")?;
+        let expected = WalRedoPolicy {
+            max_timeout: Some(Duration::from_secs(300)),
+            max_attempts: 3,
+            quarantine_period: Duration::from_secs(3600),
+            ..WalRedoPolicy::default()
+        };
+        assert_eq!(conf.wal_redo_policy, expected);
+
+        for invalid in ["{ max_retries = 1 }", "{ timeout_per_record = 10 }"] {
+            assert!(
+                parse(invalid).is_err(),
+                "WAL redo policy {invalid} should be rejected"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_wal_redo_stderr() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+
+        let parse = |stderr: &str| -> anyhow::Result<PageServerConf> {
+            let config_string = format!(
+                r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{}'
+broker_endpoint = 'http://127.0.0.1:7777'
+wal_redo_stderr = {stderr}"#,
+                pg_distrib_dir.display(),
+            );
+            let toml = config_string.parse()?;
+            PageServerConf::parse_and_validate(&toml, &workdir)
+        };
+
+        let conf = parse("{ max_lines_per_minute = 0, tail_lines = 100 }")?;
+        let expected = WalRedoStderrConfig {
+            max_lines_per_minute: 0,
+            tail_lines: 100,
+            ..WalRedoStderrConfig::default()
+        };
+        assert_eq!(conf.wal_redo_stderr, expected);
+
+        assert!(parse("{ buffer_size = 16384 }").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_remote_storage_prices() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+
+        let parse = |prices: &str| -> anyhow::Result<PageServerConf> {
+            let config_string = format!(
+                r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{}'
+broker_endpoint = 'http://127.0.0.1:7777'
+remote_storage_prices = {prices}"#,
+                pg_distrib_dir.display(),
+            );
+            let toml = config_string.parse()?;
+            PageServerConf::parse_and_validate(&toml, &workdir)
+        };
+
+        let conf = parse("{ get_per_1000 = 0.001, download_per_gib = 0.09 }")?;
+        assert_eq!(
+            conf.remote_storage_prices,
+            RemoteStoragePrices {
+                get_per_1000: 0.001,
+                download_per_gib: 0.09,
+                ..RemoteStoragePrices::default()
+            }
+        );
+
+        for invalid in ["{ put_per_1000 = -0.005 }", "{ head_per_1000 = 0.0004 }"] {
+            assert!(
+                parse(invalid).is_err(),
+                "remote storage prices {invalid} should be rejected"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_upload_events() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+
+        let parse = |upload_events: &str| -> anyhow::Result<PageServerConf> {
+            let config_string = format!(
+                r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{}'
+broker_endpoint = 'http://127.0.0.1:7777'
+upload_events = {upload_events}"#,
+                pg_distrib_dir.display(),
+            );
+            let toml = config_string.parse()?;
+            PageServerConf::parse_and_validate(&toml, &workdir)
+        };
+
+        let conf = parse("{ sink = 'log' }")?;
+        assert_eq!(conf.upload_events, Some(UploadEventsConfig::Log));
+
+        let conf = parse("{ sink = 'webhook', endpoint = 'http://127.0.0.1:1234/events' }")?;
+        let expected = UploadEventsConfig::Webhook {
+            endpoint: Url::parse("http://127.0.0.1:1234/events")?,
+        };
+        assert_eq!(conf.upload_events, Some(expected));
+
+        for invalid in ["{ sink = 'broker' }", "{ sink = 'webhook' }", "{ endpoint = 'x' }"] {
+            assert!(
+                parse(invalid).is_err(),
+                "upload events {invalid} should be rejected"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_histogram_buckets() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+
+        let parse = |buckets: &str| -> anyhow::Result<PageServerConf> {
+            let config_string = format!(
+                r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{}'
+broker_endpoint = 'http://127.0.0.1:7777'
+
+[histogram_buckets]
+{buckets}"#,
+                pg_distrib_dir.display(),
+            );
+            let toml = config_string.parse()?;
+            PageServerConf::parse_and_validate(&toml, &workdir)
+        };
+
+        let conf = parse("pageserver_wal_redo_seconds = [0.001, 0.1, 1]")?;
+        assert_eq!(
+            conf.histogram_buckets.0["pageserver_wal_redo_seconds"],
+            vec![0.001, 0.1, 1.0]
+        );
+
+        for invalid in [
+            "pageserver_wal_redo_seconds = [1, 0.1]",
+            "pageserver_wal_redo_seconds = []",
+            "pageserver_unknown_seconds = [0.1, 1]",
+        ] {
+            assert!(
+                parse(invalid).is_err(),
+                "histogram buckets {invalid} should be rejected"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_background_jobs() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let (workdir, pg_distrib_dir) = prepare_fs(&tempdir)?;
+
+        let config_string = format!(
+            r#"{ALL_BASE_VALUES_TOML}
+pg_distrib_dir='{}'
+broker_endpoint = 'http://127.0.0.1:7777'
+
+[background_jobs]
+concurrency_limits = {{ heavy = 2 }}
+
+[background_jobs.jobs.gc]
+jitter = '5s'
+concurrency_class = 'heavy'"#,
+            pg_distrib_dir.display(),
+        );
+        let toml = config_string.parse()?;
+        let conf = PageServerConf::parse_and_validate(&toml, &workdir)?;
+
+        let gc = &conf.background_jobs.jobs[&BackgroundJobKind::Gc];
+        assert_eq!(gc.jitter, Some(Duration::from_secs(5)));
+        assert_eq!(gc.concurrency_class.as_deref(), Some("heavy"));
+        assert_eq!(
+            conf.background_jobs.concurrency_limits["heavy"],
+            NonZeroUsize::new(2).unwrap()
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn eviction_pageserver_config_parse() -> anyhow::Result<()> {
         let tempdir = tempdir()?;
@@ -0,0 +1,398 @@
+//!
+//! Semantic checks of the pageserver configuration, for `pageserver check-config`.
+//!
+//! [`PageServerConf::parse_and_validate`] only checks that each setting is well-formed on
+//! its own. The checks here look at the environment and at combinations of settings,
+//! to catch a bad config before a restart takes down the tenants of the node:
+//!
+//! - the workdir and the tenants directory are writable
+//! - the postgres binaries are present for the postgres versions installed in `pg_distrib_dir`
+//! - the remote storage is reachable
+//! - settings don't conflict with or overlap each other
+//!
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use remote_storage::{GenericRemoteStorage, RemoteStorageConfig};
+use serde::Serialize;
+
+use crate::background_jobs::BackgroundJobKind;
+use crate::config::{PageServerConf, SUPPORTED_PG_VERSIONS};
+use crate::disk_space_monitor::ProtectiveAction;
+
+/// How long to wait for the remote storage to respond.
+const REMOTE_STORAGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    Parse,
+    Directories,
+    PgBinaries,
+    RemoteStorage,
+    OverlappingSettings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The pageserver won't start, or won't work as configured.
+    Error,
+    /// Probably not what was intended.
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub check: Check,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(check: Check, message: impl Into<String>) -> Self {
+        Diagnostic {
+            check,
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    fn warning(check: Check, message: impl Into<String>) -> Self {
+        Diagnostic {
+            check,
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+}
+
+/// The outcome of `pageserver check-config`, printed as JSON.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub ok: bool,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Report {
+    fn new(diagnostics: Vec<Diagnostic>) -> Self {
+        Report {
+            ok: !diagnostics.iter().any(|d| d.severity == Severity::Error),
+            diagnostics,
+        }
+    }
+
+    /// The config file couldn't be parsed, so none of the other checks can run.
+    pub fn parse_error(err: &anyhow::Error) -> Self {
+        Report::new(vec![Diagnostic::error(Check::Parse, format!("{err:#}"))])
+    }
+}
+
+/// Run all the checks on a parsed config.
+pub async fn check_config(conf: &PageServerConf) -> Report {
+    let mut diagnostics = Vec::new();
+    check_directories(conf, &mut diagnostics);
+    check_pg_binaries(conf, &mut diagnostics);
+    check_remote_storage(conf, &mut diagnostics).await;
+    check_overlapping_settings(conf, &mut diagnostics);
+    Report::new(diagnostics)
+}
+
+fn check_directories(conf: &PageServerConf, diagnostics: &mut Vec<Diagnostic>) {
+    let tenants_path = conf.tenants_path();
+    // The tenants directory is created at startup if it doesn't exist yet.
+    let dirs = if tenants_path.exists() {
+        vec![conf.workdir.as_path(), tenants_path.as_path()]
+    } else {
+        vec![conf.workdir.as_path()]
+    };
+
+    for dir in dirs {
+        if let Err(e) = check_writable(dir) {
+            diagnostics.push(Diagnostic::error(
+                Check::Directories,
+                format!("directory '{}' is not writable: {e}", dir.display()),
+            ));
+        }
+    }
+}
+
+/// Checks by creating and removing a file, which also catches read-only mounts.
+fn check_writable(dir: &Path) -> io::Result<()> {
+    if !dir.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "not a directory"));
+    }
+    let probe = dir.join(format!(".check-config-{}", std::process::id()));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    fs::remove_file(&probe)
+}
+
+/// An installation may ship only some of the supported postgres versions, but at least one, and
+/// all the binaries of those it ships.
+fn check_pg_binaries(conf: &PageServerConf, diagnostics: &mut Vec<Diagnostic>) {
+    let mut installed = 0;
+    for &pg_version in SUPPORTED_PG_VERSIONS {
+        let bin_dir = match conf.pg_bin_dir(pg_version) {
+            Ok(dir) => dir,
+            Err(e) => {
+                diagnostics.push(Diagnostic::error(Check::PgBinaries, format!("{e:#}")));
+                continue;
+            }
+        };
+        // The versions that aren't installed have no directory in pg_distrib_dir
+        if !bin_dir.parent().map_or(false, Path::is_dir) {
+            continue;
+        }
+        installed += 1;
+        // initdb for timeline creation, postgres for the WAL redo process
+        for binary in ["initdb", "postgres"] {
+            let path = bin_dir.join(binary);
+            if !path.is_file() {
+                diagnostics.push(Diagnostic::error(
+                    Check::PgBinaries,
+                    format!(
+                        "postgres {pg_version} binary '{}' not found",
+                        path.display()
+                    ),
+                ));
+            }
+        }
+    }
+    if installed == 0 {
+        diagnostics.push(Diagnostic::error(
+            Check::PgBinaries,
+            format!(
+                "no postgres version is installed in '{}'",
+                conf.pg_distrib_dir.display()
+            ),
+        ));
+    }
+}
+
+async fn check_remote_storage(conf: &PageServerConf, diagnostics: &mut Vec<Diagnostic>) {
+    let storages = [
+        ("remote storage", &conf.remote_storage_config),
+        ("remote storage mirror", &conf.remote_storage_mirror_config),
+    ];
+    for (name, config) in storages {
+        if let Some(config) = config {
+            check_remote_storage_reachable(name, config, diagnostics).await;
+        }
+    }
+}
+
+async fn check_remote_storage_reachable(
+    name: &str,
+    config: &RemoteStorageConfig,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let storage = match GenericRemoteStorage::from_config(config) {
+        Ok(storage) => storage,
+        Err(e) => {
+            diagnostics.push(Diagnostic::error(
+                Check::RemoteStorage,
+                format!("failed to create the {name} client: {e:#}"),
+            ));
+            return;
+        }
+    };
+
+    match tokio::time::timeout(REMOTE_STORAGE_TIMEOUT, storage.list_prefixes(None)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => diagnostics.push(Diagnostic::error(
+            Check::RemoteStorage,
+            format!("failed to list the {name}: {e}"),
+        )),
+        Err(_) => diagnostics.push(Diagnostic::error(
+            Check::RemoteStorage,
+            format!("{name} didn't respond within {REMOTE_STORAGE_TIMEOUT:?}"),
+        )),
+    }
+}
+
+fn check_overlapping_settings(conf: &PageServerConf, diagnostics: &mut Vec<Diagnostic>) {
+    let check = Check::OverlappingSettings;
+
+    if conf.listen_pg_addr == conf.listen_http_addr {
+        diagnostics.push(Diagnostic::error(
+            check,
+            format!(
+                "listen_pg_addr and listen_http_addr are both '{}'",
+                conf.listen_pg_addr
+            ),
+        ));
+    }
+
+    let has_remote_storage = conf.remote_storage_config.is_some();
+    if conf.disk_usage_based_eviction.is_some() && !has_remote_storage {
+        diagnostics.push(Diagnostic::warning(
+            check,
+            "disk_usage_based_eviction is ignored without remote_storage",
+        ));
+    }
+
+    if conf.remote_scrub.is_some() && !has_remote_storage {
+        diagnostics.push(Diagnostic::warning(
+            check,
+            "remote_scrub is ignored without remote_storage",
+        ));
+    }
+
+    if let Some(mirror) = &conf.remote_storage_mirror_config {
+        if !has_remote_storage {
+            diagnostics.push(Diagnostic::warning(
+                check,
+                "remote_storage_mirror is ignored without remote_storage",
+            ));
+        } else if conf.remote_storage_config.as_ref() == Some(mirror) {
+            diagnostics.push(Diagnostic::error(
+                check,
+                "remote_storage_mirror is the same as remote_storage",
+            ));
+        }
+    }
+
+    if let Some(monitor) = &conf.disk_space_monitor {
+        let evicts = monitor.actions.contains(&ProtectiveAction::Evict);
+        if evicts && !has_remote_storage {
+            diagnostics.push(Diagnostic::error(
+                check,
+                "the evict action of disk_space_monitor needs remote_storage",
+            ));
+        }
+        if monitor.actions.is_empty() {
+            diagnostics.push(Diagnostic::warning(
+                check,
+                "disk_space_monitor has no actions, it only exports metrics",
+            ));
+        }
+        if let Some(eviction) = &conf.disk_usage_based_eviction {
+            if evicts && monitor.critical_usage_pct.get() <= eviction.max_usage_pct.get() {
+                diagnostics.push(Diagnostic::warning(
+                    check,
+                    format!(
+                        "disk_space_monitor evicts at {}% usage, before disk_usage_based_eviction \
+                        does at {}%",
+                        monitor.critical_usage_pct.get(),
+                        eviction.max_usage_pct.get()
+                    ),
+                ));
+            }
+        }
+    }
+
+    for (kind, job) in &conf.background_jobs.jobs {
+        let enabled = match kind {
+            BackgroundJobKind::DiskUsageEviction => {
+                conf.disk_usage_based_eviction.is_some() && has_remote_storage
+            }
+            BackgroundJobKind::DiskSpaceMonitor => conf.disk_space_monitor.is_some(),
+            BackgroundJobKind::RemoteScrub => conf.remote_scrub.is_some() && has_remote_storage,
+            BackgroundJobKind::MetricsPush => conf.metrics_push.is_some(),
+            BackgroundJobKind::ConsumptionMetrics | BackgroundJobKind::SyntheticSize => {
+                conf.metric_collection_endpoint.is_some()
+            }
+            BackgroundJobKind::Compaction | BackgroundJobKind::Gc | BackgroundJobKind::Eviction => {
+                true
+            }
+        };
+        if !enabled {
+            diagnostics.push(Diagnostic::warning(
+                check,
+                format!("background_jobs configures {kind}, which is not enabled"),
+            ));
+        }
+        if let (Some(period), Some(jitter)) = (job.period, job.jitter) {
+            if jitter >= period {
+                diagnostics.push(Diagnostic::warning(
+                    check,
+                    format!("the jitter of {kind} is not shorter than its period: {jitter:?}"),
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlapping_settings() {
+        let mut conf = PageServerConf::dummy_conf(PageServerConf::test_repo_dir(
+            "check_config_overlapping_settings",
+        ));
+        conf.listen_http_addr = conf.listen_pg_addr.clone();
+        conf.disk_space_monitor = Some(crate::disk_space_monitor::DiskSpaceMonitorConfig {
+            period: Duration::from_secs(10),
+            critical_usage_pct: utils::serde_percent::Percent::new(95).unwrap(),
+            critical_avail_bytes: 0,
+            critical_inode_usage_pct: None,
+            actions: vec![ProtectiveAction::Evict],
+            #[cfg(feature = "testing")]
+            mock_statvfs: None,
+        });
+
+        let mut diagnostics = Vec::new();
+        check_overlapping_settings(&conf, &mut diagnostics);
+        let report = Report::new(diagnostics);
+
+        assert!(!report.ok);
+        let messages = report
+            .diagnostics
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                format!(
+                    "listen_pg_addr and listen_http_addr are both '{}'",
+                    conf.listen_pg_addr
+                ),
+                "the evict action of disk_space_monitor needs remote_storage".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn pg_binaries() {
+        let mut conf = PageServerConf::dummy_conf(PageServerConf::test_repo_dir(
+            "check_config_pg_binaries",
+        ));
+        let dir = tempfile::tempdir().unwrap();
+        conf.pg_distrib_dir = dir.path().to_path_buf();
+        let check = |conf: &PageServerConf| {
+            let mut diagnostics = Vec::new();
+            check_pg_binaries(conf, &mut diagnostics);
+            diagnostics.len()
+        };
+
+        assert_eq!(check(&conf), 1, "no version is installed");
+
+        // The versions that aren't installed are left out
+        let bin_dir = conf.pg_bin_dir(14).unwrap();
+        fs::create_dir_all(&bin_dir).unwrap();
+        fs::write(bin_dir.join("initdb"), "").unwrap();
+        fs::write(bin_dir.join("postgres"), "").unwrap();
+        assert_eq!(check(&conf), 0);
+
+        // An installed version needs all of its binaries
+        fs::create_dir_all(conf.pg_bin_dir(15).unwrap()).unwrap();
+        assert_eq!(check(&conf), 2);
+    }
+
+    #[test]
+    fn writable_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        check_writable(dir.path()).unwrap();
+        assert!(check_writable(&dir.path().join("missing")).is_err());
+        // The probe file is cleaned up.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -3,10 +3,11 @@
 //! and push them to a HTTP endpoint.
 //! Cache metrics to send only the updated ones.
 //!
+use crate::background_jobs::{run_job, BackgroundJob, BackgroundJobKind};
 use crate::context::{DownloadBehavior, RequestContext};
 use crate::task_mgr::{self, TaskKind, BACKGROUND_RUNTIME};
 use crate::tenant::{mgr, LogicalSizeCalculationCause};
-use anyhow;
+use anyhow::Context;
 use chrono::Utc;
 use consumption_metrics::{idempotency_key, Event, EventChunk, EventType, CHUNK_SIZE};
 use pageserver_api::models::TenantState;
@@ -15,6 +16,7 @@ use serde::Serialize;
 use serde_with::{serde_as, DisplayFromStr};
 use std::collections::HashMap;
 use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 use tracing::*;
 use utils::id::{NodeId, TenantId, TimelineId};
 
@@ -53,7 +55,6 @@ pub async fn collect_metrics(
     node_id: NodeId,
     ctx: RequestContext,
 ) -> anyhow::Result<()> {
-    let mut ticker = tokio::time::interval(metric_collection_interval);
     info!("starting collect_metrics");
 
     // spin up background worker that caclulates tenant sizes
@@ -79,33 +80,63 @@ pub async fn collect_metrics(
         .timeout(DEFAULT_HTTP_REPORTING_TIMEOUT)
         .build()
         .expect("Failed to create http client with timeout");
-    let mut cached_metrics: HashMap<PageserverConsumptionMetricsKey, u64> = HashMap::new();
-    let mut prev_iteration_time: std::time::Instant = std::time::Instant::now();
-
-    loop {
-        tokio::select! {
-            _ = task_mgr::shutdown_watcher() => {
-                info!("collect_metrics received cancellation request");
-                return Ok(());
-            },
-            tick_at = ticker.tick() => {
 
-                // send cached metrics every cached_metric_collection_interval
-                let send_cached = prev_iteration_time.elapsed() >= cached_metric_collection_interval;
+    let job = CollectMetricsJob {
+        client,
+        cached_metrics: HashMap::new(),
+        metric_collection_endpoint,
+        metric_collection_interval,
+        cached_metric_collection_interval,
+        prev_iteration_time: std::time::Instant::now(),
+        node_id,
+        ctx,
+    };
+    run_job(
+        BackgroundJobKind::ConsumptionMetrics,
+        job,
+        &task_mgr::shutdown_token(),
+    )
+    .await;
+    info!("collect_metrics received cancellation request");
+    Ok(())
+}
 
-                if send_cached {
-                    prev_iteration_time = std::time::Instant::now();
-                }
+struct CollectMetricsJob<'a> {
+    client: reqwest::Client,
+    cached_metrics: HashMap<PageserverConsumptionMetricsKey, u64>,
+    metric_collection_endpoint: &'a Url,
+    metric_collection_interval: Duration,
+    cached_metric_collection_interval: Duration,
+    prev_iteration_time: std::time::Instant,
+    node_id: NodeId,
+    ctx: RequestContext,
+}
 
-                collect_metrics_iteration(&client, &mut cached_metrics, metric_collection_endpoint, node_id, &ctx, send_cached).await;
+#[async_trait::async_trait]
+impl BackgroundJob for CollectMetricsJob<'_> {
+    fn period(&self) -> Duration {
+        self.metric_collection_interval
+    }
 
-                crate::tenant::tasks::warn_when_period_overrun(
-                    tick_at.elapsed(),
-                    metric_collection_interval,
-                    "consumption_metrics_collect_metrics",
-                );
-            }
+    async fn iteration(&mut self, _cancel: &CancellationToken) -> anyhow::Result<()> {
+        // send cached metrics every cached_metric_collection_interval
+        let send_cached =
+            self.prev_iteration_time.elapsed() >= self.cached_metric_collection_interval;
+
+        if send_cached {
+            self.prev_iteration_time = std::time::Instant::now();
         }
+
+        collect_metrics_iteration(
+            &self.client,
+            &mut self.cached_metrics,
+            self.metric_collection_endpoint,
+            self.node_id,
+            &self.ctx,
+            send_cached,
+        )
+        .await;
+        Ok(())
     }
 }
 
@@ -336,46 +367,53 @@ pub async fn calculate_synthetic_size_worker(
 ) -> anyhow::Result<()> {
     info!("starting calculate_synthetic_size_worker");
 
-    let mut ticker = tokio::time::interval(synthetic_size_calculation_interval);
-
-    loop {
-        tokio::select! {
-            _ = task_mgr::shutdown_watcher() => {
-                return Ok(());
-            },
-        tick_at = ticker.tick() => {
+    let job = SyntheticSizeJob {
+        synthetic_size_calculation_interval,
+        ctx,
+    };
+    run_job(
+        BackgroundJobKind::SyntheticSize,
+        job,
+        &task_mgr::shutdown_token(),
+    )
+    .await;
+    Ok(())
+}
 
-                let tenants = match mgr::list_tenants().await {
-                    Ok(tenants) => tenants,
-                    Err(e) => {
-                        warn!("cannot get tenant list: {e:#}");
-                        continue;
-                    }
-                };
-                // iterate through list of Active tenants and collect metrics
-                for (tenant_id, tenant_state) in tenants {
+struct SyntheticSizeJob<'a> {
+    synthetic_size_calculation_interval: Duration,
+    ctx: &'a RequestContext,
+}
 
-                    if tenant_state != TenantState::Active {
-                        continue;
-                    }
+#[async_trait::async_trait]
+impl BackgroundJob for SyntheticSizeJob<'_> {
+    fn period(&self) -> Duration {
+        self.synthetic_size_calculation_interval
+    }
 
-                    if let Ok(tenant) = mgr::get_tenant(tenant_id, true).await
-                    {
-                        if let Err(e) = tenant.calculate_synthetic_size(
-                            LogicalSizeCalculationCause::ConsumptionMetricsSyntheticSize,
-                            ctx).await {
-                            error!("failed to calculate synthetic size for tenant {}: {}", tenant_id, e);
-                        }
-                    }
+    async fn iteration(&mut self, _cancel: &CancellationToken) -> anyhow::Result<()> {
+        let tenants = mgr::list_tenants().await.context("cannot get tenant list")?;
+        // iterate through list of Active tenants and collect metrics
+        for (tenant_id, tenant_state) in tenants {
+            if tenant_state != TenantState::Active {
+                continue;
+            }
 
+            if let Ok(tenant) = mgr::get_tenant(tenant_id, true).await {
+                if let Err(e) = tenant
+                    .calculate_synthetic_size(
+                        LogicalSizeCalculationCause::ConsumptionMetricsSyntheticSize,
+                        self.ctx,
+                    )
+                    .await
+                {
+                    error!(
+                        "failed to calculate synthetic size for tenant {}: {}",
+                        tenant_id, e
+                    );
                 }
-
-                crate::tenant::tasks::warn_when_period_overrun(
-                    tick_at.elapsed(),
-                    synthetic_size_calculation_interval,
-                    "consumption_metrics_synthetic_size_worker",
-                );
             }
         }
+        Ok(())
     }
 }
@@ -31,9 +31,10 @@
 //!
 //! We do not yet have a systematic cancellation story in pageserver, and it is
 //! pretty clear that [`RequestContext`] will be responsible for that.
-//! So, the API already prepares for this role through the
-//! [`RequestContext::detached_child`] and [`RequestContext::attached_child`]  methods.
-//! See their doc comments for details on how we will use them in the future.
+//! So far, only the remote storage downloads observe the [`RequestContext::cancel`] token:
+//! an on-demand download is aborted when the context that needs it is cancelled.
+//! The [`RequestContext::detached_child`] and [`RequestContext::attached_child`] methods
+//! define how cancellation propagates, see their doc comments.
 //!
 //! It is not clear whether or how we will enforce Structured Concurrency, and
 //! what role [`RequestContext`] will play there.
@@ -85,6 +86,8 @@
 //! The solution is that all code paths are infected with precisely one
 //! [`RequestContext`] argument. Functions in the middle of the call chain
 //! only need to pass it on.
+use tokio_util::sync::CancellationToken;
+
 use crate::task_mgr::TaskKind;
 
 // The main structure of this module, see module-level comment.
@@ -92,6 +95,8 @@ use crate::task_mgr::TaskKind;
 pub struct RequestContext {
     task_kind: TaskKind,
     download_behavior: DownloadBehavior,
+    stage_timings: bool,
+    cancel: CancellationToken,
 }
 
 /// Desired behavior if the operation requires an on-demand download
@@ -117,15 +122,17 @@ impl RequestContext {
     /// form a tree (not implemented yet since cancellation will be
     /// the first feature that requires a tree).
     ///
-    /// # Future: Cancellation
+    /// # Cancellation
     ///
     /// The only reason why a context like this one can be canceled is
-    /// because someone explicitly canceled it.
+    /// because someone explicitly canceled it, see [`Self::with_cancel`].
     /// It has no parent, so it cannot inherit cancellation from there.
     pub fn new(task_kind: TaskKind, download_behavior: DownloadBehavior) -> Self {
         RequestContext {
             task_kind,
             download_behavior,
+            stage_timings: false,
+            cancel: CancellationToken::new(),
         }
     }
 
@@ -134,7 +141,7 @@ impl RequestContext {
     /// Use this when spawning new background activity that should complete
     /// even if the current request is canceled.
     ///
-    /// # Future: Cancellation
+    /// # Cancellation
     ///
     /// Cancellation of `self` will not propagate to the child context returned
     /// by this method.
@@ -147,14 +154,14 @@ impl RequestContext {
     ///
     /// We could make new calls to this function fail if `self` is already canceled.
     pub fn detached_child(&self, task_kind: TaskKind, download_behavior: DownloadBehavior) -> Self {
-        self.child_impl(task_kind, download_behavior)
+        self.child_impl(task_kind, download_behavior, CancellationToken::new())
     }
 
     /// Create a child of context `self` for a task that shall not outlive `self`.
     ///
     /// Use this when fanning-out work to other async tasks.
     ///
-    /// # Future: Cancellation
+    /// # Cancellation
     ///
     /// Cancelling a context will propagate to its attached children.
     ///
@@ -171,7 +178,11 @@ impl RequestContext {
     /// The method to wait for child tasks would return an error, indicating
     /// that the child task was not started because the context was canceled.
     pub fn attached_child(&self) -> Self {
-        self.child_impl(self.task_kind(), self.download_behavior())
+        self.child_impl(
+            self.task_kind(),
+            self.download_behavior(),
+            self.cancel.child_token(),
+        )
     }
 
     /// Use this function when you should be creating a child context using
@@ -183,10 +194,36 @@ impl RequestContext {
         Self::new(task_kind, download_behavior)
     }
 
-    fn child_impl(&self, task_kind: TaskKind, download_behavior: DownloadBehavior) -> Self {
+    fn child_impl(
+        &self,
+        task_kind: TaskKind,
+        download_behavior: DownloadBehavior,
+        cancel: CancellationToken,
+    ) -> Self {
         RequestContext {
             task_kind,
             download_behavior,
+            stage_timings: false,
+            cancel,
+        }
+    }
+
+    /// Use this when something else than the parent cancels the activity, e.g. the shutdown
+    /// of the `task_mgr` task running it. This replaces the cancellation inherited from the
+    /// parent, so it's meant for the contexts that have none, like detached children.
+    pub fn with_cancel(&self, cancel: CancellationToken) -> Self {
+        RequestContext {
+            cancel,
+            ..self.clone()
+        }
+    }
+
+    /// Use this when the request was sampled for the per-stage timing breakdown of the
+    /// getpage path, see [`crate::metrics::GetPageStageTimings`].
+    pub fn with_stage_timings(&self, stage_timings: bool) -> Self {
+        RequestContext {
+            stage_timings,
+            ..self.clone()
         }
     }
 
@@ -197,4 +234,14 @@ impl RequestContext {
     pub fn download_behavior(&self) -> DownloadBehavior {
         self.download_behavior
     }
+
+    pub fn stage_timings(&self) -> bool {
+        self.stage_timings
+    }
+
+    /// Cancelled when the activity is no longer needed: the on-demand downloads it waits for
+    /// are aborted.
+    pub fn cancel(&self) -> &CancellationToken {
+        &self.cancel
+    }
 }
@@ -0,0 +1,92 @@
+//!
+//! On-demand CPU profiling, to capture CPU regressions on production
+//! pageservers, e.g. in layer map traversal or when serializing huge index parts.
+//!
+//! Uses an in-process sampling profiler driven by `SIGPROF`. It only costs
+//! anything while a profile is being taken, and only one can be taken at a time.
+//!
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use anyhow::Context;
+
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+pub const MAX_DURATION: Duration = Duration::from_secs(300);
+
+/// Sampling frequency in Hz. Not a round number, so as to not sample in
+/// lockstep with periodic work.
+const DEFAULT_FREQUENCY: i32 = 99;
+
+/// Don't attribute samples taken while the signal handler or the unwinder run.
+const BLOCKLIST: &[&str] = &["libc", "libgcc", "pthread", "vdso"];
+
+#[derive(Debug, Clone, Copy, Default, strum_macros::EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum CpuProfileFormat {
+    /// pprof protobuf, for `go tool pprof` and friends.
+    #[default]
+    Pprof,
+    /// Flamegraph SVG, to look at in a browser right away.
+    Flamegraph,
+}
+
+impl CpuProfileFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            CpuProfileFormat::Pprof => "application/octet-stream",
+            CpuProfileFormat::Flamegraph => "image/svg+xml",
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CpuProfileError {
+    #[error("a CPU profile is already being taken")]
+    AlreadyRunning,
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+static PROFILING: AtomicBool = AtomicBool::new(false);
+
+/// Sample the CPU usage of the whole process for `duration`.
+///
+/// Blocks for `duration`, call it from `spawn_blocking`.
+pub fn profile_cpu(
+    duration: Duration,
+    format: CpuProfileFormat,
+) -> Result<Vec<u8>, CpuProfileError> {
+    if PROFILING
+        .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        return Err(CpuProfileError::AlreadyRunning);
+    }
+    scopeguard::defer! {
+        PROFILING.store(false, Ordering::Release);
+    }
+
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(DEFAULT_FREQUENCY)
+        .blocklist(BLOCKLIST)
+        .build()
+        .context("start CPU profiler")?;
+
+    std::thread::sleep(duration);
+
+    let report = guard.report().build().context("build CPU profile report")?;
+    drop(guard);
+
+    let mut body = Vec::new();
+    match format {
+        CpuProfileFormat::Pprof => {
+            use pprof::protos::Message;
+            let profile = report.pprof().context("convert CPU profile to pprof")?;
+            profile.encode(&mut body).context("encode CPU profile as pprof")?;
+        }
+        CpuProfileFormat::Flamegraph => {
+            report.flamegraph(&mut body).context("render CPU profile flamegraph")?;
+        }
+    }
+    Ok(body)
+}
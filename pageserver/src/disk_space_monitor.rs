@@ -0,0 +1,336 @@
+//!
+//! Monitor the free space and inodes of the volume of the pageserver workdir.
+//!
+//! The monitor exports the filesystem usage as metrics, and when usage reaches the
+//! configured critical thresholds, it takes the configured protective actions, rather
+//! than letting the pageserver run into ENOSPC in the middle of writing a layer file:
+//!
+//! - `evict`: run a disk usage based eviction iteration, evicting layers until the
+//!   usage is below the critical thresholds again. Needs remote storage.
+//! - `pause_wal_ingest`: stop reading WAL from the safekeepers. They retain it until
+//!   the pageserver catches up.
+//! - `refuse_timeline_creation`: fail timeline creation requests with
+//!   503 Service Unavailable.
+//!
+//! The ingest pause and the timeline creation refusal are lifted as soon as the usage
+//! is below the critical thresholds again.
+//!
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use remote_storage::GenericRemoteStorage;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::*;
+use utils::completion;
+use utils::serde_percent::Percent;
+
+use crate::background_jobs::{run_job, BackgroundJob, BackgroundJobKind};
+use crate::config::PageServerConf;
+use crate::disk_usage_eviction_task::{self, disk_usage_eviction_task_iteration_impl};
+use crate::metrics::DISK_SPACE_METRICS;
+use crate::statvfs::Statvfs;
+use crate::task_mgr::{self, TaskKind, BACKGROUND_RUNTIME};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiskSpaceMonitorConfig {
+    #[serde(with = "humantime_serde")]
+    pub period: Duration,
+    /// Usage is critical at this percentage of the space in use.
+    pub critical_usage_pct: Percent,
+    /// Usage is critical with less than this many bytes available.
+    #[serde(default)]
+    pub critical_avail_bytes: u64,
+    /// Usage is critical at this percentage of the inodes in use. Ignored on filesystems
+    /// that don't limit the number of inodes.
+    #[serde(default)]
+    pub critical_inode_usage_pct: Option<Percent>,
+    /// What to do while usage is critical.
+    #[serde(default)]
+    pub actions: Vec<ProtectiveAction>,
+    #[cfg(feature = "testing")]
+    #[serde(default)]
+    pub mock_statvfs: Option<crate::statvfs::mock::Behavior>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProtectiveAction {
+    Evict,
+    PauseWalIngest,
+    RefuseTimelineCreation,
+}
+
+static WAL_INGEST_PAUSED: Lazy<watch::Sender<bool>> = Lazy::new(|| watch::channel(false).0);
+
+static TIMELINE_CREATION_REFUSED: AtomicBool = AtomicBool::new(false);
+
+/// Returns once WAL ingestion is not paused by the monitor.
+pub(crate) async fn wal_ingest_allowed() {
+    let mut paused = WAL_INGEST_PAUSED.subscribe();
+    while *paused.borrow_and_update() {
+        if paused.changed().await.is_err() {
+            // The sender is a static, never dropped.
+            return;
+        }
+    }
+}
+
+/// Whether new timelines should be refused because the disk is (almost) full.
+pub(crate) fn timeline_creation_refused() -> bool {
+    TIMELINE_CREATION_REFUSED.load(Ordering::Relaxed)
+}
+
+pub fn launch_disk_space_monitor(
+    conf: &'static PageServerConf,
+    storage: Option<GenericRemoteStorage>,
+    eviction_state: Arc<disk_usage_eviction_task::State>,
+    background_jobs_barrier: completion::Barrier,
+) {
+    let Some(config) = &conf.disk_space_monitor else {
+        info!("disk space monitor not configured");
+        return;
+    };
+
+    if config.actions.contains(&ProtectiveAction::Evict) && storage.is_none() {
+        warn!("disk space monitor can't evict layers without remote storage");
+    }
+
+    info!("launching disk space monitor");
+
+    task_mgr::spawn(
+        BACKGROUND_RUNTIME.handle(),
+        TaskKind::DiskSpaceMonitor,
+        None,
+        None,
+        "disk space monitor",
+        false,
+        async move {
+            let cancel = task_mgr::shutdown_token();
+
+            // The eviction action can't evict from loading tenants.
+            tokio::select! {
+                _ = cancel.cancelled() => { return Ok(()); },
+                _ = background_jobs_barrier.wait() => { }
+            };
+
+            let job = DiskSpaceMonitor {
+                config,
+                workdir: &conf.workdir,
+                storage,
+                eviction_state,
+                critical: false,
+            };
+            run_job(BackgroundJobKind::DiskSpaceMonitor, job, &cancel)
+                .instrument(info_span!("disk_space_monitor"))
+                .await;
+            Ok(())
+        },
+    );
+}
+
+struct DiskSpaceMonitor<'a> {
+    config: &'a DiskSpaceMonitorConfig,
+    workdir: &'a Path,
+    storage: Option<GenericRemoteStorage>,
+    eviction_state: Arc<disk_usage_eviction_task::State>,
+    critical: bool,
+}
+
+#[async_trait::async_trait]
+impl BackgroundJob for DiskSpaceMonitor<'_> {
+    fn period(&self) -> Duration {
+        self.config.period
+    }
+
+    async fn iteration(&mut self, cancel: &CancellationToken) -> anyhow::Result<()> {
+        let usage = FilesystemUsage::get(self.workdir, self.config)?;
+        DISK_SPACE_METRICS.total_bytes.set(usage.total_bytes);
+        DISK_SPACE_METRICS.avail_bytes.set(usage.avail_bytes);
+        DISK_SPACE_METRICS.total_inodes.set(usage.total_inodes);
+        DISK_SPACE_METRICS.avail_inodes.set(usage.avail_inodes);
+
+        let critical = usage.is_critical();
+        if critical != self.critical {
+            if critical {
+                warn!(?usage, actions = ?self.config.actions, "disk usage is critical");
+            } else {
+                info!(?usage, "disk usage is no longer critical");
+            }
+            self.critical = critical;
+            DISK_SPACE_METRICS.critical.set(critical as i64);
+        }
+
+        let actions = &self.config.actions;
+        WAL_INGEST_PAUSED
+            .send_replace(critical && actions.contains(&ProtectiveAction::PauseWalIngest));
+        TIMELINE_CREATION_REFUSED.store(
+            critical && actions.contains(&ProtectiveAction::RefuseTimelineCreation),
+            Ordering::Relaxed,
+        );
+
+        if critical && actions.contains(&ProtectiveAction::Evict) {
+            if let Some(storage) = &self.storage {
+                let outcome = disk_usage_eviction_task_iteration_impl(
+                    &self.eviction_state,
+                    storage,
+                    usage,
+                    cancel,
+                )
+                .await
+                .context("evict layers")?;
+                debug!(?outcome, "eviction finished");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+struct FilesystemUsage<'a> {
+    config: &'a DiskSpaceMonitorConfig,
+    total_bytes: u64,
+    avail_bytes: u64,
+    /// Zero if the filesystem doesn't limit the number of inodes.
+    total_inodes: u64,
+    avail_inodes: u64,
+}
+
+impl std::fmt::Debug for FilesystemUsage<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FilesystemUsage")
+            .field("total_bytes", &self.total_bytes)
+            .field("avail_bytes", &self.avail_bytes)
+            .field("total_inodes", &self.total_inodes)
+            .field("avail_inodes", &self.avail_inodes)
+            .finish()
+    }
+}
+
+impl<'a> FilesystemUsage<'a> {
+    fn get(workdir: &Path, config: &'a DiskSpaceMonitorConfig) -> anyhow::Result<Self> {
+        let mock_config = {
+            #[cfg(feature = "testing")]
+            {
+                config.mock_statvfs.as_ref()
+            }
+            #[cfg(not(feature = "testing"))]
+            {
+                None
+            }
+        };
+
+        let stat = Statvfs::get(workdir, mock_config).context("statvfs the workdir")?;
+
+        // https://unix.stackexchange.com/a/703650
+        let blocksize = if stat.fragment_size() > 0 {
+            stat.fragment_size()
+        } else {
+            stat.block_size()
+        };
+
+        Ok(FilesystemUsage {
+            config,
+            total_bytes: stat.blocks() * blocksize,
+            avail_bytes: stat.blocks_available() * blocksize,
+            total_inodes: stat.files(),
+            avail_inodes: stat.files_available(),
+        })
+    }
+
+    fn is_critical(&self) -> bool {
+        let config = self.config;
+
+        let space = used_pct(self.avail_bytes, self.total_bytes)
+            .map_or(false, |pct| pct >= u64::from(config.critical_usage_pct.get()));
+        let avail_bytes = self.avail_bytes < config.critical_avail_bytes;
+        let inodes_pct = used_pct(self.avail_inodes, self.total_inodes);
+        let inodes = match (config.critical_inode_usage_pct, inodes_pct) {
+            (Some(max), Some(pct)) => pct >= u64::from(max.get()),
+            _ => false,
+        };
+
+        space || avail_bytes || inodes
+    }
+}
+
+/// `None` if the total is zero. Integer math, so that thresholds are hit exactly.
+fn used_pct(avail: u64, total: u64) -> Option<u64> {
+    if total == 0 {
+        return None;
+    }
+    let used = u128::from(total.saturating_sub(avail));
+    Some((used * 100 / u128::from(total)) as u64)
+}
+
+/// Evicting layers frees space, so the eviction stops once usage isn't critical anymore.
+impl disk_usage_eviction_task::Usage for FilesystemUsage<'_> {
+    fn has_pressure(&self) -> bool {
+        self.is_critical()
+    }
+
+    fn add_available_bytes(&mut self, bytes: u64) {
+        self.avail_bytes += bytes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(critical_inode_usage_pct: Option<u8>) -> DiskSpaceMonitorConfig {
+        DiskSpaceMonitorConfig {
+            period: Duration::from_secs(10),
+            critical_usage_pct: Percent::new(95).unwrap(),
+            critical_avail_bytes: 1000,
+            critical_inode_usage_pct: critical_inode_usage_pct.map(|p| Percent::new(p).unwrap()),
+            actions: vec![ProtectiveAction::RefuseTimelineCreation],
+            #[cfg(feature = "testing")]
+            mock_statvfs: None,
+        }
+    }
+
+    #[test]
+    fn critical_thresholds() {
+        let config = config(Some(90));
+        let usage = |avail_bytes, avail_inodes| FilesystemUsage {
+            config: &config,
+            total_bytes: 100_000,
+            avail_bytes,
+            total_inodes: 100,
+            avail_inodes,
+        };
+
+        assert!(!usage(50_000, 50).is_critical());
+        // 95% of the space used
+        assert!(usage(5_000, 50).is_critical());
+        // below critical_avail_bytes
+        assert!(usage(999, 50).is_critical());
+        // 90% of the inodes used
+        assert!(usage(50_000, 10).is_critical());
+
+        let mut usage = usage(5_000, 50);
+        disk_usage_eviction_task::Usage::add_available_bytes(&mut usage, 1_000);
+        assert!(!disk_usage_eviction_task::Usage::has_pressure(&usage));
+    }
+
+    #[test]
+    fn no_inode_limit() {
+        let config = config(Some(90));
+        let usage = FilesystemUsage {
+            config: &config,
+            total_bytes: 100_000,
+            avail_bytes: 50_000,
+            total_inodes: 0,
+            avail_inodes: 0,
+        };
+        assert!(!usage.is_critical());
+    }
+}
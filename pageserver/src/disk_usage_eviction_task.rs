@@ -51,13 +51,13 @@ use std::{
 use anyhow::Context;
 use remote_storage::GenericRemoteStorage;
 use serde::{Deserialize, Serialize};
-use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, instrument, warn, Instrument};
 use utils::completion;
 use utils::serde_percent::Percent;
 
 use crate::{
+    background_jobs::{run_job, BackgroundJob, BackgroundJobKind},
     config::PageServerConf,
     task_mgr::{self, TaskKind, BACKGROUND_RUNTIME},
     tenant::{self, storage_layer::PersistentLayer, Timeline},
@@ -129,49 +129,41 @@ async fn disk_usage_eviction_task(
         info!("disk usage based eviction task finishing");
     };
 
-    use crate::tenant::tasks::random_init_delay;
-    {
-        if random_init_delay(task_config.period, &cancel)
-            .await
-            .is_err()
-        {
-            return;
-        }
-    }
+    let job = DiskUsageEvictionJob {
+        state,
+        task_config,
+        storage,
+        tenants_dir,
+        iteration_no: 0,
+    };
+    run_job(BackgroundJobKind::DiskUsageEviction, job, &cancel).await;
+}
 
-    let mut iteration_no = 0;
-    loop {
-        iteration_no += 1;
-        let start = Instant::now();
+struct DiskUsageEvictionJob<'a> {
+    state: &'a State,
+    task_config: &'a DiskUsageEvictionTaskConfig,
+    storage: GenericRemoteStorage,
+    tenants_dir: &'a Path,
+    iteration_no: u64,
+}
 
-        async {
-            let res = disk_usage_eviction_task_iteration(
-                state,
-                task_config,
-                &storage,
-                tenants_dir,
-                &cancel,
-            )
-            .await;
-
-            match res {
-                Ok(()) => {}
-                Err(e) => {
-                    // these stat failures are expected to be very rare
-                    warn!("iteration failed, unexpected error: {e:#}");
-                }
-            }
-        }
-        .instrument(tracing::info_span!("iteration", iteration_no))
-        .await;
+#[async_trait::async_trait]
+impl BackgroundJob for DiskUsageEvictionJob<'_> {
+    fn period(&self) -> Duration {
+        self.task_config.period
+    }
 
-        let sleep_until = start + task_config.period;
-        tokio::select! {
-            _ = tokio::time::sleep_until(sleep_until) => {},
-            _ = cancel.cancelled() => {
-                break
-            }
-        }
+    async fn iteration(&mut self, cancel: &CancellationToken) -> anyhow::Result<()> {
+        self.iteration_no += 1;
+        disk_usage_eviction_task_iteration(
+            self.state,
+            self.task_config,
+            &self.storage,
+            self.tenants_dir,
+            cancel,
+        )
+        .instrument(tracing::info_span!("iteration", iteration_no = self.iteration_no))
+        .await
     }
 }
 
@@ -0,0 +1,94 @@
+//!
+//! Heap profiles and allocator stats, to diagnose memory growth on production
+//! pageservers without attaching a debugger.
+//!
+//! Only available when built with the `jemalloc` feature, which makes jemalloc
+//! the global allocator and enables its sampling heap profiler, see
+//! `bin/pageserver.rs`. The profiles are in jemalloc's heap profile format,
+//! which `jeprof` and `go tool pprof` read.
+//!
+use serde::Serialize;
+
+pub const ENABLED: bool = cfg!(feature = "jemalloc");
+
+/// Allocator-wide stats in bytes, see `man jemalloc` for their exact meaning.
+#[derive(Debug, Serialize)]
+pub struct HeapStats {
+    pub allocated: u64,
+    pub active: u64,
+    pub metadata: u64,
+    pub resident: u64,
+    pub mapped: u64,
+    pub retained: u64,
+}
+
+#[cfg(feature = "jemalloc")]
+pub fn heap_stats() -> anyhow::Result<HeapStats> {
+    use anyhow::anyhow;
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // The stats are cached, refresh them.
+    epoch::advance().map_err(|e| anyhow!("advance jemalloc epoch: {e}"))?;
+
+    let read = |name: &str, value: tikv_jemalloc_ctl::Result<usize>| {
+        value
+            .map(|v| v as u64)
+            .map_err(|e| anyhow!("read jemalloc stat {name}: {e}"))
+    };
+    Ok(HeapStats {
+        allocated: read("allocated", stats::allocated::read())?,
+        active: read("active", stats::active::read())?,
+        metadata: read("metadata", stats::metadata::read())?,
+        resident: read("resident", stats::resident::read())?,
+        mapped: read("mapped", stats::mapped::read())?,
+        retained: read("retained", stats::retained::read())?,
+    })
+}
+
+/// Dump a heap profile of the allocations sampled so far.
+///
+/// Blocks on file IO, call it from `spawn_blocking`.
+#[cfg(feature = "jemalloc")]
+pub fn dump_heap_profile() -> anyhow::Result<Vec<u8>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use anyhow::{anyhow, ensure, Context};
+    use tikv_jemalloc_ctl::raw;
+
+    static DUMP_SEQ: AtomicU64 = AtomicU64::new(0);
+
+    // SAFETY: opt.prof is a bool, per the jemalloc documentation.
+    let profiling: bool =
+        unsafe { raw::read(b"opt.prof\0") }.map_err(|e| anyhow!("read opt.prof: {e}"))?;
+    ensure!(profiling, "jemalloc heap profiling is disabled, check the malloc_conf");
+
+    // jemalloc can only dump to a file.
+    let path = std::env::temp_dir().join(format!(
+        "pageserver-heap-{}-{}.prof",
+        std::process::id(),
+        DUMP_SEQ.fetch_add(1, Ordering::Relaxed)
+    ));
+    let c_path = CString::new(path.as_os_str().as_bytes()).context("heap profile path")?;
+
+    // SAFETY: prof.dump takes a NUL-terminated file name, which outlives the call.
+    unsafe { raw::write(b"prof.dump\0", c_path.as_ptr()) }
+        .map_err(|e| anyhow!("dump heap profile to {}: {e}", path.display()))?;
+
+    let profile = std::fs::read(&path);
+    if let Err(e) = std::fs::remove_file(&path) {
+        tracing::warn!("failed to remove heap profile {}: {e}", path.display());
+    }
+    profile.with_context(|| format!("read heap profile {}", path.display()))
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn heap_stats() -> anyhow::Result<HeapStats> {
+    anyhow::bail!("pageserver was compiled without jemalloc support")
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn dump_heap_profile() -> anyhow::Result<Vec<u8>> {
+    anyhow::bail!("pageserver was compiled without jemalloc support")
+}
@@ -2,15 +2,28 @@
 //! Management HTTP API
 //!
 use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::path::{Component, Path};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{anyhow, Context, Result};
 use hyper::StatusCode;
 use hyper::{Body, Request, Response, Uri};
 use metrics::launch_timestamp::LaunchTimestamp;
-use pageserver_api::models::{DownloadRemoteLayersTaskSpawnRequest, TenantAttachRequest};
-use remote_storage::GenericRemoteStorage;
+use once_cell::sync::Lazy;
+use pageserver_api::models::{
+    BuildInfoResponse, DeletionDryRunFlushResponse, DownloadRemoteLayersTaskInfo,
+    DownloadRemoteLayersTaskSpawnRequest, ListDetail, ListQuery, PrefetchLayersRequest,
+    PrefetchLayersResponse, RelationSizesResponse, RemoteConsistencyReport, RemoteCopyReport,
+    RemoteRestoreReport, RemoteScrubReport, TenantAttachRequest, TenantConfig, TenantImportSource,
+    TenantRemoteCost, TenantState, TimelineCopyRemoteRequest, TimelineExportRequest,
+    TimelineExportResponse, TimelineRestoreIndexRequest, TimelineState, UploadQueueInfo,
+    UploadThrottleConfig, WalRedoProcessInfo,
+};
+use remote_storage::{GenericRemoteStorage, RemotePath};
 use storage_broker::BrokerClientChannel;
+use strum::VariantNames;
 use tenant_size_model::{SizeResult, StorageModel};
 use tokio_util::sync::CancellationToken;
 use tracing::*;
@@ -23,24 +36,31 @@ use super::models::{
     TimelineCreateRequest, TimelineGcRequest, TimelineInfo,
 };
 use crate::context::{DownloadBehavior, RequestContext};
-use crate::metrics::{StorageTimeOperation, STORAGE_TIME_GLOBAL};
+use crate::metrics::{tenant_remote_storage_costs, StorageTimeOperation, STORAGE_TIME_GLOBAL};
 use crate::pgdatadir_mapping::LsnForTimestamp;
+use crate::remote_scrubber;
+use crate::repository::Key;
 use crate::task_mgr::TaskKind;
-use crate::tenant::config::TenantConfOpt;
+use crate::tenant::config::{TenantConfOpt, TenantRemoteStorageConfig};
+use crate::tenant::generation::Generation;
 use crate::tenant::mgr::{
     GetTenantError, SetNewTenantConfigError, TenantMapInsertError, TenantStateError,
 };
 use crate::tenant::size::ModelInputs;
-use crate::tenant::storage_layer::LayerAccessStatsReset;
-use crate::tenant::{LogicalSizeCalculationCause, PageReconstructError, Timeline};
+use crate::tenant::storage_layer::{LayerAccessStatsReset, LayerFileName};
+use crate::tenant::{
+    CancelTaskError, LogicalSizeCalculationCause, PageReconstructError,
+    PersistIndexPartWithDeletedFlagError, RestoreIndexError, TenantImport, Timeline,
+};
 use crate::{config::PageServerConf, tenant::mgr};
 use crate::{disk_usage_eviction_task, tenant};
 use utils::{
     auth::JwtAuth,
     http::{
-        endpoint::{self, attach_openapi_ui, auth_middleware, check_permission_with},
-        error::{ApiError, HttpErrorBody},
+        endpoint::{self, attach_swagger_ui, auth_middleware, check_permission_with},
+        error::{ApiError, ErrorCode, HttpErrorBody},
         json::{json_request, json_response},
+        openapi::{OpenApi, Operation},
         request::parse_request_param,
         RequestExt, RouterBuilder,
     },
@@ -49,7 +69,10 @@ use utils::{
 };
 
 // Imports only used for testing APIs
-use super::models::ConfigureFailpointsRequest;
+use super::models::{
+    ConfigureFailpointsRequest, ConfigureRemoteStorageFaultsRequest, FailpointConfig,
+};
+use crate::tenant::REMOTE_STORAGE_FAULTS;
 
 struct State {
     conf: &'static PageServerConf,
@@ -68,7 +91,7 @@ impl State {
         broker_client: storage_broker::BrokerClientChannel,
         disk_usage_eviction_state: Arc<disk_usage_eviction_task::State>,
     ) -> anyhow::Result<Self> {
-        let allowlist_routes = ["/v1/status", "/v1/doc", "/swagger.yml"]
+        let allowlist_routes = ["/v1/status", "/v1/doc", "/v1/openapi.json"]
             .iter()
             .map(|v| v.parse().unwrap())
             .collect::<Vec<_>>();
@@ -106,7 +129,7 @@ fn check_permission(request: &Request<Body>, tenant_id: Option<TenantId>) -> Res
 impl From<PageReconstructError> for ApiError {
     fn from(pre: PageReconstructError) -> ApiError {
         match pre {
-            PageReconstructError::Other(pre) => ApiError::InternalServerError(pre),
+            PageReconstructError::Other(pre) => api_error_from_anyhow(pre),
             PageReconstructError::NeedsDownload(_, _) => {
                 // This shouldn't happen, because we use a RequestContext that requests to
                 // download any missing layer files on-demand.
@@ -114,9 +137,11 @@ impl From<PageReconstructError> for ApiError {
             }
             PageReconstructError::Cancelled => {
                 ApiError::InternalServerError(anyhow::anyhow!("request was cancelled"))
+                    .with_code(ErrorCode::Cancelled)
             }
             PageReconstructError::AncestorStopping(_) => {
                 ApiError::InternalServerError(anyhow::Error::new(pre))
+                    .with_code(ErrorCode::ShuttingDown)
             }
             PageReconstructError::WalRedo(pre) => {
                 ApiError::InternalServerError(anyhow::Error::new(pre))
@@ -128,13 +153,19 @@ impl From<PageReconstructError> for ApiError {
 impl From<TenantMapInsertError> for ApiError {
     fn from(tmie: TenantMapInsertError) -> ApiError {
         match tmie {
-            TenantMapInsertError::StillInitializing | TenantMapInsertError::ShuttingDown => {
+            TenantMapInsertError::StillInitializing => {
                 ApiError::InternalServerError(anyhow::Error::new(tmie))
+                    .with_code(ErrorCode::ServiceUnavailable)
+            }
+            TenantMapInsertError::ShuttingDown => {
+                ApiError::InternalServerError(anyhow::Error::new(tmie))
+                    .with_code(ErrorCode::ShuttingDown)
             }
             TenantMapInsertError::TenantAlreadyExists(id, state) => {
                 ApiError::Conflict(format!("tenant {id} already exists, state: {state:?}"))
+                    .with_code(ErrorCode::TenantAlreadyExists)
             }
-            TenantMapInsertError::Closure(e) => ApiError::InternalServerError(e),
+            TenantMapInsertError::Closure(e) => api_error_from_anyhow(e),
         }
     }
 }
@@ -142,8 +173,15 @@ impl From<TenantMapInsertError> for ApiError {
 impl From<TenantStateError> for ApiError {
     fn from(tse: TenantStateError) -> ApiError {
         match tse {
-            TenantStateError::NotFound(tid) => ApiError::NotFound(anyhow!("tenant {}", tid).into()),
-            _ => ApiError::InternalServerError(anyhow::Error::new(tse)),
+            TenantStateError::NotFound(tid) => {
+                ApiError::NotFound(anyhow!("tenant {}", tid).into())
+                    .with_code(ErrorCode::TenantNotFound)
+            }
+            TenantStateError::IsStopping(_) | TenantStateError::NotActive(_) => {
+                ApiError::InternalServerError(anyhow::Error::new(tse))
+                    .with_code(ErrorCode::TenantNotActive)
+            }
+            TenantStateError::Other(e) => api_error_from_anyhow(e),
         }
     }
 }
@@ -151,7 +189,10 @@ impl From<TenantStateError> for ApiError {
 impl From<GetTenantError> for ApiError {
     fn from(tse: GetTenantError) -> ApiError {
         match tse {
-            GetTenantError::NotFound(tid) => ApiError::NotFound(anyhow!("tenant {}", tid).into()),
+            GetTenantError::NotFound(tid) => {
+                ApiError::NotFound(anyhow!("tenant {}", tid).into())
+                    .with_code(ErrorCode::TenantNotFound)
+            }
             e @ GetTenantError::NotActive(_) => {
                 // Why is this not `ApiError::NotFound`?
                 // Because we must be careful to never return 404 for a tenant if it does
@@ -160,16 +201,29 @@ impl From<GetTenantError> for ApiError {
                 //
                 // (We can produce this variant only in `mgr::get_tenant(..., active=true)` calls).
                 ApiError::InternalServerError(anyhow::Error::new(e))
+                    .with_code(ErrorCode::TenantNotActive)
             }
         }
     }
 }
 
+impl From<crate::tenant::GetTimelineError> for ApiError {
+    fn from(e: crate::tenant::GetTimelineError) -> ApiError {
+        use crate::tenant::GetTimelineError::*;
+        let code = match e {
+            NotFound { .. } => ErrorCode::TimelineNotFound,
+            NotActive { .. } => ErrorCode::TimelineNotActive,
+        };
+        ApiError::NotFound(e.into()).with_code(code)
+    }
+}
+
 impl From<SetNewTenantConfigError> for ApiError {
     fn from(e: SetNewTenantConfigError) -> ApiError {
         match e {
             SetNewTenantConfigError::GetTenant(tid) => {
                 ApiError::NotFound(anyhow!("tenant {}", tid).into())
+                    .with_code(ErrorCode::TenantNotFound)
             }
             e @ SetNewTenantConfigError::Persist(_) => {
                 ApiError::InternalServerError(anyhow::Error::new(e))
@@ -182,13 +236,19 @@ impl From<crate::tenant::DeleteTimelineError> for ApiError {
     fn from(value: crate::tenant::DeleteTimelineError) -> Self {
         use crate::tenant::DeleteTimelineError::*;
         match value {
-            NotFound => ApiError::NotFound(anyhow::anyhow!("timeline not found").into()),
-            HasChildren(children) => ApiError::PreconditionFailed(
-                format!("Cannot delete timeline which has child timelines: {children:?}")
-                    .into_boxed_str(),
-            ),
-            a @ AlreadyInProgress => ApiError::Conflict(a.to_string()),
-            Other(e) => ApiError::InternalServerError(e),
+            NotFound => {
+                ApiError::NotFound(anyhow::anyhow!("timeline not found").into())
+                    .with_code(ErrorCode::TimelineNotFound)
+            }
+            HasChildren(children) => {
+                let msg = format!("Cannot delete timeline which has child timelines: {children:?}");
+                ApiError::PreconditionFailed(msg.into_boxed_str())
+                    .with_code(ErrorCode::TimelineHasChildren)
+            }
+            a @ AlreadyInProgress => {
+                ApiError::Conflict(a.to_string()).with_code(ErrorCode::DeletionInProgress)
+            }
+            Other(e) => api_error_from_anyhow(e),
         }
     }
 }
@@ -199,15 +259,53 @@ impl From<crate::tenant::mgr::DeleteTimelineError> for ApiError {
         match value {
             // Report Precondition failed so client can distinguish between
             // "tenant is missing" case from "timeline is missing"
-            Tenant(GetTenantError::NotFound(..)) => ApiError::PreconditionFailed(
-                "Requested tenant is missing".to_owned().into_boxed_str(),
-            ),
+            Tenant(GetTenantError::NotFound(..)) => {
+                ApiError::PreconditionFailed("Requested tenant is missing".into())
+                    .with_code(ErrorCode::TenantNotFound)
+            }
             Tenant(t) => ApiError::from(t),
             Timeline(t) => ApiError::from(t),
         }
     }
 }
 
+impl From<PersistIndexPartWithDeletedFlagError> for ApiError {
+    fn from(e: PersistIndexPartWithDeletedFlagError) -> Self {
+        match e {
+            PersistIndexPartWithDeletedFlagError::AlreadyInProgress(_) => {
+                ApiError::Conflict(e.to_string()).with_code(ErrorCode::DeletionInProgress)
+            }
+            PersistIndexPartWithDeletedFlagError::AlreadyDeleted(_) => {
+                ApiError::NotFound(e.into()).with_code(ErrorCode::TimelineNotFound)
+            }
+            PersistIndexPartWithDeletedFlagError::Other(e) => ApiError::InternalServerError(e),
+        }
+    }
+}
+
+impl From<CancelTaskError> for ApiError {
+    fn from(e: CancelTaskError) -> Self {
+        match e {
+            CancelTaskError::QueueNotInitialized => ApiError::Conflict(e.to_string()),
+            CancelTaskError::NoSuchTask(_) => ApiError::NotFound(e.into()),
+        }
+    }
+}
+
+/// Errors that reach the handlers as [`anyhow::Error`] still get the code of their typed
+/// cause, if it's one of the known ones.
+fn api_error_from_anyhow(e: anyhow::Error) -> ApiError {
+    let e = match e.downcast::<PersistIndexPartWithDeletedFlagError>() {
+        Ok(e) => return ApiError::from(e),
+        Err(e) => e,
+    };
+    let e = match e.downcast::<GetTenantError>() {
+        Ok(e) => return ApiError::from(e),
+        Err(e) => e,
+    };
+    ApiError::InternalServerError(e)
+}
+
 // Helper function to construct a TimelineInfo struct for a timeline
 async fn build_timeline_info(
     timeline: &Arc<Timeline>,
@@ -289,6 +387,7 @@ async fn build_timeline_info_common(
         pg_version: timeline.pg_version,
 
         state,
+        remote_layer_exists: None,
     };
     Ok(info)
 }
@@ -300,7 +399,22 @@ async fn status_handler(
 ) -> Result<Response<Body>, ApiError> {
     check_permission(&request, None)?;
     let config = get_config(&request);
-    json_response(StatusCode::OK, StatusResponse { id: config.id })
+    json_response(
+        StatusCode::OK,
+        StatusResponse {
+            id: config.id,
+            remote_storage_health: tenant::remote_storage_health(),
+        },
+    )
+}
+
+async fn build_info_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+    let config = get_config(&request);
+    json_response(StatusCode::OK, crate::build_info::build_info(config))
 }
 
 async fn timeline_create_handler(
@@ -335,21 +449,66 @@ async fn timeline_create_handler(
                     .map_err(ApiError::InternalServerError)?;
                 json_response(StatusCode::CREATED, timeline_info)
             }
-            Err(tenant::CreateTimelineError::AlreadyExists) => {
-                json_response(StatusCode::CONFLICT, ())
+            Err(err @ tenant::CreateTimelineError::AlreadyExists) => {
+                json_response(StatusCode::CONFLICT, HttpErrorBody::from_code(
+                    ErrorCode::TimelineAlreadyExists,
+                    err.to_string(),
+                ))
+            }
+            Err(err @ tenant::CreateTimelineError::DiskSpaceCritical) => {
+                json_response(StatusCode::SERVICE_UNAVAILABLE, HttpErrorBody::from_code(
+                    ErrorCode::DiskSpaceCritical,
+                    err.to_string(),
+                ))
             }
             Err(tenant::CreateTimelineError::AncestorLsn(err)) => {
-                json_response(StatusCode::NOT_ACCEPTABLE, HttpErrorBody::from_msg(
-                    format!("{err:#}")
+                json_response(StatusCode::NOT_ACCEPTABLE, HttpErrorBody::from_code(
+                    ErrorCode::AncestorLsnNotAcceptable,
+                    format!("{err:#}"),
                 ))
             }
-            Err(tenant::CreateTimelineError::Other(err)) => Err(ApiError::InternalServerError(err)),
+            Err(tenant::CreateTimelineError::Other(err)) => Err(api_error_from_anyhow(err)),
         }
     }
     .instrument(info_span!("timeline_create", %tenant_id, timeline_id = %new_timeline_id, lsn=?request_data.ancestor_start_lsn, pg_version=?request_data.pg_version))
     .await
 }
 
+/// Parse the pagination and filtering query parameters of the list endpoints, see
+/// [`ListQuery`]. `state` is a comma-separated list of the given `states`.
+fn parse_list_query<Id, E>(
+    request: &Request<Body>,
+    states: &[&str],
+) -> Result<ListQuery<Id>, ApiError>
+where
+    Id: std::str::FromStr<Err = E>,
+    E: std::fmt::Display,
+{
+    let query_states = parse_query_param::<_, String>(request, "state")?
+        .map(|param| {
+            param
+                .split(',')
+                .map(|state| {
+                    if states.contains(&state) {
+                        Ok(state.to_string())
+                    } else {
+                        Err(ApiError::BadRequest(anyhow!(
+                            "unknown state '{state}', expected one of {states:?}"
+                        )))
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+
+    Ok(ListQuery {
+        limit: parse_query_param(request, "limit")?,
+        cursor: parse_query_param(request, "cursor")?,
+        states: query_states,
+        detail: parse_query_param(request, "detail")?.unwrap_or_default(),
+    })
+}
+
 async fn timeline_list_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
@@ -357,19 +516,27 @@ async fn timeline_list_handler(
     let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
     let include_non_incremental_logical_size: Option<bool> =
         parse_query_param(&request, "include-non-incremental-logical-size")?;
+    let query: ListQuery<TimelineId> = parse_list_query(&request, TimelineState::VARIANTS)?;
     check_permission(&request, Some(tenant_id))?;
 
+    let include_non_incremental_logical_size =
+        include_non_incremental_logical_size.unwrap_or(query.detail == ListDetail::Full);
+
     let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Download);
 
     let response_data = async {
         let tenant = mgr::get_tenant(tenant_id, true).await?;
-        let timelines = tenant.list_timelines();
+        let timelines = query.apply(
+            tenant.list_timelines(),
+            |timeline| timeline.timeline_id,
+            |timeline| timeline.current_state().into(),
+        );
 
         let mut response_data = Vec::with_capacity(timelines.len());
         for timeline in timelines {
             let timeline_info = build_timeline_info(
                 &timeline,
-                include_non_incremental_logical_size.unwrap_or(false),
+                include_non_incremental_logical_size,
                 &ctx,
             )
             .instrument(info_span!("build_timeline_info", timeline_id = %timeline.timeline_id))
@@ -389,12 +556,17 @@ async fn timeline_list_handler(
 
 async fn timeline_detail_handler(
     request: Request<Body>,
-    _cancel: CancellationToken,
+    cancel: CancellationToken,
 ) -> Result<Response<Body>, ApiError> {
     let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
     let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
     let include_non_incremental_logical_size: Option<bool> =
         parse_query_param(&request, "include-non-incremental-logical-size")?;
+    let check_remote_layer: Option<String> = parse_query_param(&request, "check-remote-layer")?;
+    let check_remote_layer = check_remote_layer
+        .map(|name| name.parse::<LayerFileName>())
+        .transpose()
+        .map_err(|e| ApiError::BadRequest(anyhow!("invalid check-remote-layer: {e}")))?;
     check_permission(&request, Some(tenant_id))?;
 
     // Logical size calculation needs downloading.
@@ -403,11 +575,9 @@ async fn timeline_detail_handler(
     let timeline_info = async {
         let tenant = mgr::get_tenant(tenant_id, true).await?;
 
-        let timeline = tenant
-            .get_timeline(timeline_id, false)
-            .map_err(|e| ApiError::NotFound(e.into()))?;
+        let timeline = tenant.get_timeline(timeline_id, false)?;
 
-        let timeline_info = build_timeline_info(
+        let mut timeline_info = build_timeline_info(
             &timeline,
             include_non_incremental_logical_size.unwrap_or(false),
             &ctx,
@@ -416,6 +586,20 @@ async fn timeline_detail_handler(
         .context("get local timeline info")
         .map_err(ApiError::InternalServerError)?;
 
+        if let Some(layer_file_name) = check_remote_layer {
+            let Some(remote_client) = &timeline.remote_client else {
+                return Err(ApiError::BadRequest(anyhow!(
+                    "layer check is not possible because pageserver was configured without remote storage"
+                )));
+            };
+            let exists = remote_client
+                .layer_exists(&layer_file_name, &cancel)
+                .await
+                .context("check remote layer file")
+                .map_err(ApiError::InternalServerError)?;
+            timeline_info.remote_layer_exists = Some(exists);
+        }
+
         Ok::<_, ApiError>(timeline_info)
     }
     .instrument(info_span!("timeline_detail", %tenant_id, %timeline_id))
@@ -451,6 +635,61 @@ async fn get_lsn_by_timestamp_handler(
     json_response(StatusCode::OK, result)
 }
 
+async fn timeline_relation_sizes_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let lsn: Option<Lsn> = parse_query_param(&request, "lsn")?;
+
+    // The catalog pages may need downloading.
+    let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Download);
+
+    let response = async {
+        let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
+        let last_record_lsn = timeline.get_last_record_lsn();
+        let lsn = match lsn {
+            Some(lsn) if lsn > last_record_lsn => {
+                return Err(ApiError::BadRequest(anyhow!(
+                    "lsn {lsn} is ahead of the last record lsn {last_record_lsn}"
+                )));
+            }
+            Some(lsn) if lsn < *timeline.get_latest_gc_cutoff_lsn() => {
+                return Err(ApiError::BadRequest(anyhow!("lsn {lsn} is behind the gc cutoff lsn")));
+            }
+            Some(lsn) => lsn,
+            None => last_record_lsn,
+        };
+
+        let mut dbdirs = Vec::from_iter(timeline.list_dbdirs(lsn, &ctx).await?);
+        dbdirs.sort();
+        let mut databases = Vec::with_capacity(dbdirs.len());
+        for ((spcnode, dbnode), has_relmap_file) in dbdirs {
+            let sizes = crate::relation_sizes::database_sizes(
+                &timeline,
+                spcnode,
+                dbnode,
+                has_relmap_file,
+                lsn,
+                &ctx,
+            )
+            .await
+            .with_context(|| format!("calculate relation sizes of database {spcnode}/{dbnode}"))
+            .map_err(ApiError::InternalServerError)?;
+            databases.push(sizes);
+        }
+
+        Ok::<_, ApiError>(RelationSizesResponse { lsn, databases })
+    }
+    .instrument(info_span!("timeline_relation_sizes", %tenant_id, %timeline_id))
+    .await?;
+
+    json_response(StatusCode::OK, response)
+}
+
 async fn tenant_attach_handler(
     mut request: Request<Body>,
     _cancel: CancellationToken,
@@ -458,10 +697,20 @@ async fn tenant_attach_handler(
     let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
     check_permission(&request, Some(tenant_id))?;
 
+    let conf = get_config(&request);
+
     let maybe_body: Option<TenantAttachRequest> = json_request_or_empty_body(&mut request).await?;
-    let tenant_conf = match maybe_body {
-        Some(request) => TenantConfOpt::try_from(&*request.config).map_err(ApiError::BadRequest)?,
-        None => TenantConfOpt::default(),
+    let (tenant_conf, tenant_remote_storage, generation, import) = match maybe_body {
+        Some(request) => (
+            TenantConfOpt::try_from(&*request.config).map_err(ApiError::BadRequest)?,
+            parse_tenant_remote_storage(conf, &request.config)?,
+            request.generation.map(Generation::new),
+            request
+                .import
+                .map(|import| parse_tenant_import(conf, import))
+                .transpose()?,
+        ),
+        None => (TenantConfOpt::default(), None, None, None),
     };
 
     let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Warn);
@@ -470,23 +719,26 @@ async fn tenant_attach_handler(
 
     let state = get_state(&request);
 
-    if let Some(remote_storage) = &state.remote_storage {
-        mgr::attach_tenant(
-            state.conf,
-            tenant_id,
-            tenant_conf,
-            state.broker_client.clone(),
-            remote_storage.clone(),
-            &ctx,
-        )
-        .instrument(info_span!("tenant_attach", %tenant_id))
-        .await?;
-    } else {
+    if state.remote_storage.is_none() && tenant_remote_storage.is_none() {
         return Err(ApiError::BadRequest(anyhow!(
             "attach_tenant is not possible because pageserver was configured without remote storage"
         )));
     }
 
+    mgr::attach_tenant(
+        state.conf,
+        tenant_id,
+        tenant_conf,
+        tenant_remote_storage,
+        generation,
+        import,
+        state.broker_client.clone(),
+        state.remote_storage.clone(),
+        &ctx,
+    )
+    .instrument(info_span!("tenant_attach", %tenant_id))
+    .await?;
+
     json_response(StatusCode::ACCEPTED, ())
 }
 
@@ -568,25 +820,61 @@ async fn tenant_list_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
 ) -> Result<Response<Body>, ApiError> {
+    let query: ListQuery<TenantId> = parse_list_query(&request, TenantState::VARIANTS)?;
     check_permission(&request, None)?;
 
-    let response_data = mgr::list_tenants()
-        .instrument(info_span!("tenant_list"))
-        .await
-        .map_err(anyhow::Error::new)
-        .map_err(ApiError::InternalServerError)?
-        .iter()
-        .map(|(id, state)| TenantInfo {
-            id: *id,
-            state: state.clone(),
-            current_physical_size: None,
-            attachment_status: state.attachment_status(),
-        })
-        .collect::<Vec<TenantInfo>>();
+    let response_data = async {
+        let tenants = mgr::list_tenants()
+            .await
+            .map_err(anyhow::Error::new)
+            .map_err(ApiError::InternalServerError)?;
+        let tenants = query.apply(tenants, |(id, _)| *id, |(_, state)| state.into());
+
+        let mut response_data = Vec::with_capacity(tenants.len());
+        for (id, state) in tenants {
+            let current_physical_size = match query.detail {
+                ListDetail::Basic => None,
+                // The tenant may have been detached since it was listed.
+                ListDetail::Full => match mgr::get_tenant(id, false).await {
+                    Ok(tenant) => Some(tenant_physical_size(&tenant).await),
+                    Err(_) => None,
+                },
+            };
+            response_data.push(TenantInfo {
+                id,
+                attachment_status: state.attachment_status(),
+                state,
+                current_physical_size,
+            });
+        }
+        Ok::<Vec<TenantInfo>, ApiError>(response_data)
+    }
+    .instrument(info_span!("tenant_list"))
+    .await?;
 
     json_response(StatusCode::OK, response_data)
 }
 
+/// The remote storage requests and estimated cost of each tenant.
+async fn remote_cost_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+
+    let prices = &get_config(&request).remote_storage_prices;
+    json_response(StatusCode::OK, tenant_remote_storage_costs(prices))
+}
+
+/// Total physical size of all the timelines of the tenant.
+async fn tenant_physical_size(tenant: &tenant::Tenant) -> u64 {
+    let mut current_physical_size = 0;
+    for timeline in tenant.list_timelines().iter() {
+        current_physical_size += timeline.layer_size_sum().await;
+    }
+    current_physical_size
+}
+
 async fn tenant_status(
     request: Request<Body>,
     _cancel: CancellationToken,
@@ -596,12 +884,7 @@ async fn tenant_status(
 
     let tenant_info = async {
         let tenant = mgr::get_tenant(tenant_id, false).await?;
-
-        // Calculate total physical size of all timelines
-        let mut current_physical_size = 0;
-        for timeline in tenant.list_timelines().iter() {
-            current_physical_size += timeline.layer_size_sum().await;
-        }
+        let current_physical_size = tenant_physical_size(&tenant).await;
 
         let state = tenant.current_state();
         Result::<_, ApiError>::Ok(TenantInfo {
@@ -717,7 +1000,7 @@ async fn layer_map_info_handler(
 
 async fn layer_download_handler(
     request: Request<Body>,
-    _cancel: CancellationToken,
+    cancel: CancellationToken,
 ) -> Result<Response<Body>, ApiError> {
     let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
     check_permission(&request, Some(tenant_id))?;
@@ -727,7 +1010,7 @@ async fn layer_download_handler(
 
     let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
     let downloaded = timeline
-        .download_layer(layer_file_name)
+        .download_layer(layer_file_name, &cancel)
         .await
         .map_err(ApiError::InternalServerError)?;
 
@@ -822,6 +1105,38 @@ pub fn html_response(status: StatusCode, data: String) -> Result<Response<Body>,
     Ok(response)
 }
 
+/// The `remote_storage` of a tenant creation or attachment, checked by creating its client.
+fn parse_tenant_remote_storage(
+    conf: &'static PageServerConf,
+    config: &TenantConfig,
+) -> Result<Option<TenantRemoteStorageConfig>, ApiError> {
+    let Some(remote_storage) = &config.remote_storage else {
+        return Ok(None);
+    };
+    let remote_storage =
+        TenantRemoteStorageConfig::try_from(remote_storage).map_err(ApiError::BadRequest)?;
+    tenant::create_remote_storage(conf, &remote_storage.config)
+        .context("create the client of the tenant remote storage")
+        .map_err(ApiError::BadRequest)?;
+    Ok(Some(remote_storage))
+}
+
+/// The `import` of a tenant attachment, checked by creating the client of its remote storage.
+fn parse_tenant_import(
+    conf: &'static PageServerConf,
+    import: TenantImportSource,
+) -> Result<TenantImport, ApiError> {
+    let import = TenantImport::try_from(import)
+        .context("parse field `import`")
+        .map_err(ApiError::BadRequest)?;
+    if let Some(storage) = import.storage() {
+        tenant::create_remote_storage(conf, &storage.config)
+            .context("create the client of the import remote storage")
+            .map_err(ApiError::BadRequest)?;
+    }
+    Ok(import)
+}
+
 async fn tenant_create_handler(
     mut request: Request<Body>,
     _cancel: CancellationToken,
@@ -835,16 +1150,18 @@ async fn tenant_create_handler(
         .expect("bug")
         .start_timer();
 
+    let state = get_state(&request);
+
     let tenant_conf =
         TenantConfOpt::try_from(&request_data.config).map_err(ApiError::BadRequest)?;
+    let tenant_remote_storage = parse_tenant_remote_storage(state.conf, &request_data.config)?;
 
     let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Warn);
 
-    let state = get_state(&request);
-
     let new_tenant = mgr::create_tenant(
         state.conf,
         tenant_conf,
+        tenant_remote_storage,
         target_tenant_id,
         state.broker_client.clone(),
         state.remote_storage.clone(),
@@ -907,6 +1224,11 @@ async fn update_tenant_config_handler(
     let tenant_id = request_data.tenant_id;
     check_permission(&request, Some(tenant_id))?;
 
+    if request_data.config.remote_storage.is_some() {
+        return Err(ApiError::BadRequest(anyhow!(
+            "the remote storage of a tenant can only be set when it is created or attached"
+        )));
+    }
     let tenant_conf =
         TenantConfOpt::try_from(&request_data.config).map_err(ApiError::BadRequest)?;
 
@@ -918,6 +1240,60 @@ async fn update_tenant_config_handler(
     json_response(StatusCode::OK, ())
 }
 
+async fn get_upload_throttle_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let tenant = mgr::get_tenant(tenant_id, false).await?;
+
+    json_response(StatusCode::OK, tenant.upload_throttle().config())
+}
+
+/// Change the limits on the layer uploads of a tenant, until it's loaded again.
+async fn update_upload_throttle_handler(
+    mut request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+    let config: UploadThrottleConfig = json_request(&mut request).await?;
+
+    let tenant = mgr::get_tenant(tenant_id, false).await?;
+    info!(%tenant_id, ?config, "changing the upload throttle");
+    tenant.upload_throttle().set_config(config);
+
+    json_response(StatusCode::OK, config)
+}
+
+/// Take the deletions recorded by the deletion dry-run mode of a tenant since the last flush.
+async fn deletion_dry_run_flush_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let tenant = mgr::get_tenant(tenant_id, false).await?;
+
+    json_response(StatusCode::OK, tenant.deletion_dry_run().flush())
+}
+
+/// The WAL redo processes of a tenant, one per Postgres version that its timelines needed.
+async fn tenant_wal_redo_status_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let tenant = mgr::get_tenant(tenant_id, false).await?;
+
+    json_response(StatusCode::OK, tenant.walredo_status())
+}
+
 /// Testing helper to transition a tenant to [`crate::tenant::TenantState::Broken`].
 async fn handle_tenant_break(
     r: Request<Body>,
@@ -934,15 +1310,34 @@ async fn handle_tenant_break(
     json_response(StatusCode::OK, ())
 }
 
-async fn failpoints_handler(
-    mut request: Request<Body>,
-    _cancel: CancellationToken,
-) -> Result<Response<Body>, ApiError> {
+fn check_failpoints_support() -> Result<(), ApiError> {
     if !fail::has_failpoints() {
         return Err(ApiError::BadRequest(anyhow!(
             "Cannot manage failpoints because pageserver was compiled without failpoints support"
         )));
     }
+    Ok(())
+}
+
+async fn failpoints_list_handler(
+    _request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_failpoints_support()?;
+
+    let failpoints: ConfigureFailpointsRequest = fail::list()
+        .into_iter()
+        .map(|(name, actions)| FailpointConfig { name, actions })
+        .collect();
+
+    json_response(StatusCode::OK, failpoints)
+}
+
+async fn failpoints_handler(
+    mut request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_failpoints_support()?;
 
     let failpoints: ConfigureFailpointsRequest = json_request(&mut request).await?;
     for fp in failpoints {
@@ -969,6 +1364,40 @@ async fn failpoints_handler(
     json_response(StatusCode::OK, ())
 }
 
+async fn failpoint_remove_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_failpoints_support()?;
+
+    let name: String = parse_request_param(&request, "failpoint_name")?;
+    if !fail::list().iter().any(|(configured, _)| *configured == name) {
+        return Err(ApiError::NotFound(anyhow!("failpoint {name} is not configured")));
+    }
+
+    info!("remove failpoint: {name}");
+    fail::remove(name);
+
+    json_response(StatusCode::OK, ())
+}
+
+async fn remote_storage_faults_list_handler(
+    _request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    json_response(StatusCode::OK, REMOTE_STORAGE_FAULTS.get())
+}
+
+async fn remote_storage_faults_handler(
+    mut request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let faults: ConfigureRemoteStorageFaultsRequest = json_request(&mut request).await?;
+    REMOTE_STORAGE_FAULTS.set(faults);
+
+    json_response(StatusCode::OK, ())
+}
+
 // Run GC immediately on given timeline.
 async fn timeline_gc_handler(
     mut request: Request<Body>,
@@ -1041,46 +1470,362 @@ async fn timeline_checkpoint_handler(
     .await
 }
 
-async fn timeline_download_remote_layers_handler_post(
-    mut request: Request<Body>,
+/// Delete the layer files in the remote storage of a timeline that its index doesn't reference.
+/// The state of the upload queue of a timeline, in any state, to debug a stuck one.
+async fn timeline_upload_queue_handler(
+    request: Request<Body>,
     _cancel: CancellationToken,
 ) -> Result<Response<Body>, ApiError> {
     let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
     let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
-    let body: DownloadRemoteLayersTaskSpawnRequest = json_request(&mut request).await?;
     check_permission(&request, Some(tenant_id))?;
 
-    let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
-    match timeline.spawn_download_all_remote_layers(body).await {
-        Ok(st) => json_response(StatusCode::ACCEPTED, st),
-        Err(st) => json_response(StatusCode::CONFLICT, st),
-    }
+    let tenant = mgr::get_tenant(tenant_id, false).await?;
+    let timeline = tenant.get_timeline(timeline_id, false)?;
+    let Some(remote_client) = &timeline.remote_client else {
+        return Err(ApiError::BadRequest(anyhow!(
+            "timeline has no upload queue because pageserver was configured without remote storage"
+        )));
+    };
+
+    json_response(StatusCode::OK, remote_client.upload_queue_info())
 }
 
-async fn timeline_download_remote_layers_handler_get(
+async fn timeline_cancel_upload_task_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
 ) -> Result<Response<Body>, ApiError> {
     let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
-    check_permission(&request, Some(tenant_id))?;
     let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let task_id: u64 = parse_request_param(&request, "task_id")?;
+    check_permission(&request, Some(tenant_id))?;
 
-    let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
-    let info = timeline
-        .get_download_all_remote_layers_task_info()
-        .context("task never started since last pageserver process start")
-        .map_err(|e| ApiError::NotFound(e.into()))?;
-    json_response(StatusCode::OK, info)
+    let tenant = mgr::get_tenant(tenant_id, false).await?;
+    let timeline = tenant.get_timeline(timeline_id, false)?;
+    let Some(remote_client) = &timeline.remote_client else {
+        return Err(ApiError::BadRequest(anyhow!(
+            "timeline has no upload queue because pageserver was configured without remote storage"
+        )));
+    };
+
+    remote_client.cancel_task(task_id)?;
+    json_response(StatusCode::OK, ())
 }
 
-async fn active_timeline_of_active_tenant(
-    tenant_id: TenantId,
+async fn timeline_allow_lsn_regression_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let tenant = mgr::get_tenant(tenant_id, false).await?;
+    let timeline = tenant.get_timeline(timeline_id, false)?;
+    let Some(remote_client) = &timeline.remote_client else {
+        return Err(ApiError::BadRequest(anyhow!(
+            "timeline has no upload queue because pageserver was configured without remote storage"
+        )));
+    };
+
+    remote_client
+        .allow_index_lsn_regression()
+        .map_err(|e| ApiError::Conflict(format!("{e:#}")))?;
+    json_response(StatusCode::OK, ())
+}
+
+async fn timeline_scrub_remote_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
+    let Some(remote_client) = &timeline.remote_client else {
+        return Err(ApiError::BadRequest(anyhow!(
+            "scrub is not possible because pageserver was configured without remote storage"
+        )));
+    };
+    let min_age = remote_scrubber::scrub_min_age(get_config(&request));
+    let report = remote_client
+        .scrub_leaked_objects(min_age)
+        .instrument(info_span!("manual_remote_scrub", %tenant_id, %timeline_id))
+        .await
+        .map_err(ApiError::InternalServerError)?;
+
+    json_response(StatusCode::OK, report)
+}
+
+async fn timeline_remote_consistency_handler(
+    request: Request<Body>,
+    cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
+    let Some(remote_client) = &timeline.remote_client else {
+        return Err(ApiError::BadRequest(anyhow!(
+            "consistency check is not possible because pageserver was configured without remote storage"
+        )));
+    };
+    let report = remote_client
+        .validate_remote_consistency(&cancel)
+        .instrument(info_span!("remote_consistency_check", %tenant_id, %timeline_id))
+        .await
+        .map_err(ApiError::InternalServerError)?;
+
+    json_response(StatusCode::OK, report)
+}
+
+async fn timeline_copy_remote_handler(
+    mut request: Request<Body>,
+    cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let body: TimelineCopyRemoteRequest = json_request(&mut request).await?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let destination = TenantRemoteStorageConfig::try_from(&body.remote_storage)
+        .and_then(|config| tenant::create_remote_storage(get_config(&request), &config.config))
+        .context("create the client of the destination remote storage")
+        .map_err(ApiError::BadRequest)?;
+    let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
+    let Some(remote_client) = &timeline.remote_client else {
+        return Err(ApiError::BadRequest(anyhow!(
+            "copy is not possible because pageserver was configured without remote storage"
+        )));
+    };
+    let report = remote_client
+        .copy_timeline_remote(&destination, &cancel)
+        .instrument(info_span!("copy_timeline_remote", %tenant_id, %timeline_id))
+        .await
+        .map_err(ApiError::InternalServerError)?;
+
+    json_response(StatusCode::OK, report)
+}
+
+async fn timeline_export_handler(
+    mut request: Request<Body>,
+    cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let body: TimelineExportRequest = json_request(&mut request).await?;
+    check_permission(&request, Some(tenant_id))?;
+    let conf = get_config(&request);
+
+    let prefix = match &body.prefix {
+        Some(prefix) => {
+            let prefix = Path::new(prefix);
+            if !prefix
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+            {
+                return Err(ApiError::BadRequest(anyhow!(
+                    "export prefix must be a relative path without '..' components"
+                )));
+            }
+            Some(prefix)
+        }
+        None => None,
+    };
+    let destination = body
+        .remote_storage
+        .as_ref()
+        .map(|config| {
+            TenantRemoteStorageConfig::try_from(config)
+                .and_then(|config| tenant::create_remote_storage(conf, &config.config))
+                .context("create the client of the destination remote storage")
+        })
+        .transpose()
+        .map_err(ApiError::BadRequest)?;
+    // In the remote storage of the tenant, the exports go next to its timelines
+    let remote_dir = match (&destination, prefix) {
+        (Some(_), prefix) => RemotePath::new(prefix.unwrap_or(Path::new(""))),
+        (None, prefix) => conf.remote_path(&conf.tenant_path(&tenant_id)).map(|path| {
+            let exports = path.join(Path::new("exports"));
+            match prefix {
+                Some(prefix) => exports.join(prefix),
+                None => exports,
+            }
+        }),
+    }
+    .map_err(ApiError::InternalServerError)?;
+
+    let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
+    if timeline.remote_client.is_none() {
+        return Err(ApiError::BadRequest(anyhow!(
+            "export is not possible because pageserver was configured without remote storage"
+        )));
+    }
+    let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Download);
+    let lsn = match body.lsn {
+        Some(lsn) => {
+            timeline
+                .wait_lsn(lsn, &ctx)
+                .await
+                .map_err(ApiError::InternalServerError)?;
+            let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
+            timeline
+                .check_lsn_is_in_scope(lsn, &latest_gc_cutoff_lsn)
+                .context("invalid export lsn")
+                .map_err(ApiError::BadRequest)?;
+            lsn
+        }
+        None => timeline.get_last_record_lsn(),
+    };
+    let response = timeline
+        .export_basebackup(
+            lsn,
+            destination.as_ref(),
+            &remote_dir,
+            body.gzip,
+            &ctx,
+            &cancel,
+        )
+        .instrument(info_span!("export_timeline", %tenant_id, %timeline_id, %lsn))
+        .await
+        .map_err(ApiError::InternalServerError)?;
+
+    json_response(StatusCode::OK, response)
+}
+
+async fn timeline_restore_index_handler(
+    mut request: Request<Body>,
+    cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let body: TimelineRestoreIndexRequest = json_request(&mut request).await?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let state = get_state(&request);
+    // The attached tenant would keep uploading the index it has over the restored one
+    if mgr::get_tenant(tenant_id, false).await.is_ok() {
+        return Err(ApiError::Conflict(format!(
+            "tenant {tenant_id} is attached, detach it first"
+        )));
+    }
+    let storage = match &body.remote_storage {
+        Some(remote_storage) => TenantRemoteStorageConfig::try_from(remote_storage)
+            .and_then(|config| tenant::create_remote_storage(state.conf, &config.config))
+            .context("create the client of the tenant remote storage")
+            .map_err(ApiError::BadRequest)?,
+        None => state.remote_storage.clone().ok_or_else(|| {
+            ApiError::BadRequest(anyhow!(
+                "restore is not possible because pageserver was configured without remote storage"
+            ))
+        })?,
+    };
+
+    let report = tenant::restore_index_snapshot(
+        state.conf,
+        &storage,
+        tenant_id,
+        timeline_id,
+        body.lsn,
+        &cancel,
+    )
+    .instrument(info_span!("restore_index", %tenant_id, %timeline_id, lsn = %body.lsn))
+    .await
+    .map_err(|e| match e {
+        e @ RestoreIndexError::TimelineNotFound => ApiError::NotFound(e.into()),
+        e @ RestoreIndexError::SnapshotNotFound { .. } => ApiError::NotFound(e.into()),
+        e @ RestoreIndexError::MissingLayers(_) => ApiError::Conflict(e.to_string()),
+        RestoreIndexError::Other(e) => ApiError::InternalServerError(e),
+    })?;
+
+    json_response(StatusCode::OK, report)
+}
+
+async fn timeline_download_remote_layers_handler_post(
+    mut request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let body: DownloadRemoteLayersTaskSpawnRequest = json_request(&mut request).await?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
+    match timeline.spawn_download_all_remote_layers(body).await {
+        Ok(st) => json_response(StatusCode::ACCEPTED, st),
+        Err(st) => json_response(StatusCode::CONFLICT, st),
+    }
+}
+
+async fn timeline_download_remote_layers_handler_get(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+
+    let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
+    let info = timeline
+        .get_download_all_remote_layers_task_info()
+        .context("task never started since last pageserver process start")
+        .map_err(|e| ApiError::NotFound(e.into()))?;
+    json_response(StatusCode::OK, info)
+}
+
+/// The concurrent downloads of a layer prefetch, unless the request says otherwise.
+const DEFAULT_PREFETCH_CONCURRENT_DOWNLOADS: NonZeroUsize = match NonZeroUsize::new(4) {
+    Some(x) => x,
+    None => panic!("const unwrap is not yet stable"),
+};
+
+async fn timeline_prefetch_layers_handler(
+    mut request: Request<Body>,
+    cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: TenantId = parse_request_param(&request, "tenant_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let body: PrefetchLayersRequest = json_request_or_empty_body(&mut request)
+        .await?
+        .unwrap_or_default();
+    check_permission(&request, Some(tenant_id))?;
+
+    let parse_key = |key: Option<&str>, default: Key| match key {
+        Some(key) => Key::from_hex(key).map_err(ApiError::BadRequest),
+        None => Ok(default),
+    };
+    let key_start = parse_key(body.key_start.as_deref(), Key::MIN)?;
+    let key_end = parse_key(body.key_end.as_deref(), Key::MAX)?;
+    let lsn_range = match (body.lsn_start, body.lsn_end) {
+        (None, None) => None,
+        (start, end) => Some(start.unwrap_or(Lsn(0))..end.unwrap_or(Lsn::MAX)),
+    };
+    let max_concurrent_downloads = body
+        .max_concurrent_downloads
+        .unwrap_or(DEFAULT_PREFETCH_CONCURRENT_DOWNLOADS);
+
+    let timeline = active_timeline_of_active_tenant(tenant_id, timeline_id).await?;
+    let response = timeline
+        .prefetch_layers(
+            key_start..key_end,
+            lsn_range,
+            max_concurrent_downloads,
+            &cancel,
+        )
+        .instrument(info_span!("prefetch_layers", %tenant_id, %timeline_id))
+        .await
+        .map_err(ApiError::InternalServerError)?;
+    json_response(StatusCode::OK, response)
+}
+
+async fn active_timeline_of_active_tenant(
+    tenant_id: TenantId,
     timeline_id: TimelineId,
 ) -> Result<Arc<Timeline>, ApiError> {
     let tenant = mgr::get_tenant(tenant_id, true).await?;
-    tenant
-        .get_timeline(timeline_id, true)
-        .map_err(|e| ApiError::NotFound(e.into()))
+    Ok(tenant.get_timeline(timeline_id, true)?)
 }
 
 async fn always_panic_handler(
@@ -1181,6 +1926,95 @@ async fn disk_usage_eviction_run(
     json_response(StatusCode::OK, response)
 }
 
+/// List all live `task_mgr` tasks, to see what a busy pageserver is doing and to find stuck tasks.
+async fn task_list_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+
+    json_response(StatusCode::OK, crate::task_mgr::list_tasks())
+}
+
+fn check_jemalloc_support(desc: &str) -> Result<(), ApiError> {
+    if !crate::heap_profile::ENABLED {
+        return Err(ApiError::BadRequest(anyhow!(
+            "Cannot {desc} because pageserver was compiled without jemalloc support"
+        )));
+    }
+    Ok(())
+}
+
+async fn heap_profile_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+    check_jemalloc_support("dump a heap profile")?;
+
+    let profile = tokio::task::spawn_blocking(crate::heap_profile::dump_heap_profile)
+        .await
+        .context("heap profile dump task panicked")
+        .map_err(ApiError::InternalServerError)?
+        .map_err(ApiError::InternalServerError)?;
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "application/octet-stream")
+        .header(
+            hyper::header::CONTENT_DISPOSITION,
+            "attachment; filename=\"pageserver.heap\"",
+        )
+        .body(Body::from(profile))
+        .map_err(|e| ApiError::InternalServerError(e.into()))
+}
+
+async fn heap_stats_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&request, None)?;
+    check_jemalloc_support("get heap stats")?;
+
+    let stats = crate::heap_profile::heap_stats().map_err(ApiError::InternalServerError)?;
+    json_response(StatusCode::OK, stats)
+}
+
+async fn cpu_profile_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    use crate::cpu_profile::{self, CpuProfileError, CpuProfileFormat};
+
+    check_permission(&request, None)?;
+
+    let duration = parse_query_param::<_, u64>(&request, "seconds")?
+        .map(Duration::from_secs)
+        .unwrap_or(cpu_profile::DEFAULT_DURATION);
+    if duration.is_zero() || duration > cpu_profile::MAX_DURATION {
+        return Err(ApiError::BadRequest(anyhow!(
+            "seconds must be between 1 and {}",
+            cpu_profile::MAX_DURATION.as_secs()
+        )));
+    }
+    let format: CpuProfileFormat = parse_query_param(&request, "format")?.unwrap_or_default();
+
+    let profile = tokio::task::spawn_blocking(move || cpu_profile::profile_cpu(duration, format))
+        .await
+        .context("CPU profiling task panicked")
+        .map_err(ApiError::InternalServerError)?
+        .map_err(|e| match e {
+            e @ CpuProfileError::AlreadyRunning => ApiError::Conflict(e.to_string()),
+            CpuProfileError::Other(e) => ApiError::InternalServerError(e),
+        })?;
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, format.content_type())
+        .body(Body::from(profile))
+        .map_err(|e| ApiError::InternalServerError(e.into()))
+}
+
 async fn handler_404(_: Request<Body>) -> Result<Response<Body>, ApiError> {
     json_response(
         StatusCode::NOT_FOUND,
@@ -1300,6 +2134,324 @@ where
     }
 }
 
+/// The machine-readable description of the API, served at `/v1/openapi.json`. Generated from
+/// the request and response types of the handlers, keep the operations in sync with the
+/// routes in [`make_router`].
+fn api_description() -> OpenApi {
+    OpenApi::new("Page Server API", "1.0")
+        .operation(
+            Operation::get("/v1/status")
+                .summary("Healthcheck")
+                .response::<StatusResponse>(),
+        )
+        .operation(
+            Operation::get("/v1/status/build")
+                .summary("Build information of the running binary")
+                .response::<BuildInfoResponse>(),
+        )
+        .operation(
+            Operation::get("/v1/openapi.json")
+                .summary("This description of the API")
+                .response::<serde_json::Value>(),
+        )
+        .operation(
+            Operation::get("/v1/failpoints")
+                .summary("List failpoints")
+                .response::<ConfigureFailpointsRequest>()
+                .testing(),
+        )
+        .operation(
+            Operation::put("/v1/failpoints")
+                .summary("Configure failpoints")
+                .request::<ConfigureFailpointsRequest>()
+                .testing(),
+        )
+        .operation(
+            Operation::delete("/v1/failpoints/:failpoint_name")
+                .summary("Remove a failpoint")
+                .testing(),
+        )
+        .operation(
+            Operation::get("/v1/remote_storage_faults")
+                .summary("List the faults injected into the remote storage operations")
+                .response::<ConfigureRemoteStorageFaultsRequest>()
+                .testing(),
+        )
+        .operation(
+            Operation::put("/v1/remote_storage_faults")
+                .summary("Replace the faults injected into the remote storage operations")
+                .request::<ConfigureRemoteStorageFaultsRequest>()
+                .testing(),
+        )
+        .operation(
+            Operation::get("/v1/tenant")
+                .summary("List tenants, sorted by id")
+                .query::<Option<usize>>("limit")
+                .query::<Option<TenantId>>("cursor")
+                .query::<Option<String>>("state")
+                .query::<Option<ListDetail>>("detail")
+                .response::<Vec<TenantInfo>>(),
+        )
+        .operation(
+            Operation::post("/v1/tenant")
+                .summary("Create a tenant")
+                .request::<TenantCreateRequest>()
+                .status(201)
+                .response::<TenantCreateResponse>(),
+        )
+        .operation(
+            Operation::get("/v1/tenant/:tenant_id")
+                .summary("Get tenant status")
+                .response::<TenantInfo>(),
+        )
+        .operation(
+            Operation::get("/v1/remote_cost")
+                .summary("Estimate the remote storage cost of each tenant, the most expensive first")
+                .response::<Vec<TenantRemoteCost>>(),
+        )
+        .operation(
+            Operation::get("/v1/tenant/:tenant_id/synthetic_size")
+                .summary("Calculate the synthetic size of a tenant")
+                .query::<Option<bool>>("inputs_only")
+                .query::<Option<u64>>("retention_period")
+                .response::<serde_json::Value>(),
+        )
+        .operation(
+            Operation::put("/v1/tenant/config")
+                .summary("Update the config of a tenant")
+                .request::<TenantConfigRequest>(),
+        )
+        .operation(
+            Operation::get("/v1/tenant/:tenant_id/config")
+                .summary("Get the config overrides and the effective config of a tenant")
+                .response::<HashMap<String, serde_json::Value>>(),
+        )
+        .operation(
+            Operation::get("/v1/tenant/:tenant_id/upload_throttle")
+                .summary("Get the limits on the layer uploads of a tenant")
+                .response::<UploadThrottleConfig>(),
+        )
+        .operation(
+            Operation::put("/v1/tenant/:tenant_id/upload_throttle")
+                .summary("Change the limits on the layer uploads of a tenant")
+                .request::<UploadThrottleConfig>()
+                .response::<UploadThrottleConfig>(),
+        )
+        .operation(
+            Operation::post("/v1/tenant/:tenant_id/deletion_dry_run/flush")
+                .summary("Take the deletions recorded by the deletion dry-run mode of a tenant")
+                .response::<DeletionDryRunFlushResponse>(),
+        )
+        .operation(
+            Operation::get("/v1/tenant/:tenant_id/wal_redo")
+                .summary("Get the status of the WAL redo processes of a tenant")
+                .response::<Vec<WalRedoProcessInfo>>(),
+        )
+        .operation(
+            Operation::get("/v1/tenant/:tenant_id/timeline")
+                .summary("List the timelines of a tenant, sorted by id")
+                .query::<Option<bool>>("include-non-incremental-logical-size")
+                .query::<Option<usize>>("limit")
+                .query::<Option<TimelineId>>("cursor")
+                .query::<Option<String>>("state")
+                .query::<Option<ListDetail>>("detail")
+                .response::<Vec<TimelineInfo>>(),
+        )
+        .operation(
+            Operation::post("/v1/tenant/:tenant_id/timeline")
+                .summary("Create a timeline")
+                .request::<TimelineCreateRequest>()
+                .status(201)
+                .response::<TimelineInfo>(),
+        )
+        .operation(
+            Operation::post("/v1/tenant/:tenant_id/attach")
+                .summary("Attach a tenant from remote storage")
+                .request::<TenantAttachRequest>()
+                .status(202),
+        )
+        .operation(
+            Operation::post("/v1/tenant/:tenant_id/detach")
+                .summary("Detach a tenant")
+                .query::<Option<bool>>("detach_ignored"),
+        )
+        .operation(
+            Operation::post("/v1/tenant/:tenant_id/load")
+                .summary("Load an ignored tenant from local disk")
+                .status(202),
+        )
+        .operation(
+            Operation::post("/v1/tenant/:tenant_id/ignore")
+                .summary("Remove a tenant from memory, keeping its local files"),
+        )
+        .operation(
+            Operation::get("/v1/tenant/:tenant_id/timeline/:timeline_id")
+                .summary("Get timeline details")
+                .query::<Option<bool>>("include-non-incremental-logical-size")
+                .query::<Option<String>>("check-remote-layer")
+                .response::<TimelineInfo>(),
+        )
+        .operation(
+            Operation::get("/v1/tenant/:tenant_id/timeline/:timeline_id/get_lsn_by_timestamp")
+                .summary("Find the LSN of a timestamp, or 'future', 'past' or 'nodata'")
+                .query::<String>("timestamp")
+                .response::<String>(),
+        )
+        .operation(
+            Operation::get("/v1/tenant/:tenant_id/timeline/:timeline_id/relation_sizes")
+                .summary("Relation sizes, with the TimescaleDB chunks summed up per hypertable")
+                .query::<Option<Lsn>>("lsn")
+                .response::<RelationSizesResponse>(),
+        )
+        .operation(
+            Operation::put("/v1/tenant/:tenant_id/timeline/:timeline_id/do_gc")
+                .summary("Run garbage collection on a timeline")
+                .request::<TimelineGcRequest>()
+                .response::<serde_json::Value>(),
+        )
+        .operation(
+            Operation::put("/v1/tenant/:tenant_id/timeline/:timeline_id/compact")
+                .summary("Run compaction on a timeline")
+                .testing(),
+        )
+        .operation(
+            Operation::put("/v1/tenant/:tenant_id/timeline/:timeline_id/checkpoint")
+                .summary("Flush and compact a timeline")
+                .testing(),
+        )
+        .operation(
+            Operation::get("/v1/tenant/:tenant_id/timeline/:timeline_id/upload_queue")
+                .summary("Get the queued and in-progress remote operations of a timeline")
+                .response::<UploadQueueInfo>(),
+        )
+        .operation(
+            Operation::delete("/v1/tenant/:tenant_id/timeline/:timeline_id/upload_queue/:task_id")
+                .summary("Cancel an in-progress remote operation of a timeline"),
+        )
+        .operation(
+            Operation::put(
+                "/v1/tenant/:tenant_id/timeline/:timeline_id/upload_queue/allow_lsn_regression",
+            )
+            .summary("Allow the next index upload of a timeline to go back in disk_consistent_lsn"),
+        )
+        .operation(
+            Operation::post("/v1/tenant/:tenant_id/timeline/:timeline_id/scrub_remote")
+                .summary("Delete the remote layer files of a timeline that its index doesn't reference")
+                .response::<RemoteScrubReport>(),
+        )
+        .operation(
+            Operation::post("/v1/tenant/:tenant_id/timeline/:timeline_id/copy_remote")
+                .summary("Copy a timeline to another remote storage, as of its latest index")
+                .request::<TimelineCopyRemoteRequest>()
+                .response::<RemoteCopyReport>(),
+        )
+        .operation(
+            Operation::post("/v1/tenant/:tenant_id/timeline/:timeline_id/export")
+                .summary("Upload a basebackup tarball of a timeline at an LSN to remote storage")
+                .request::<TimelineExportRequest>()
+                .response::<TimelineExportResponse>(),
+        )
+        .operation(
+            Operation::post("/v1/tenant/:tenant_id/timeline/:timeline_id/restore_index")
+                .summary("Restore the index of a timeline of a detached tenant from a snapshot")
+                .request::<TimelineRestoreIndexRequest>()
+                .response::<RemoteRestoreReport>(),
+        )
+        .operation(
+            Operation::get("/v1/tenant/:tenant_id/timeline/:timeline_id/remote_consistency")
+                .summary("Check the remote layer files of a timeline against its index")
+                .response::<RemoteConsistencyReport>(),
+        )
+        .operation(
+            Operation::post("/v1/tenant/:tenant_id/timeline/:timeline_id/download_remote_layers")
+                .summary("Start downloading all the remote layers of a timeline")
+                .request::<DownloadRemoteLayersTaskSpawnRequest>()
+                .status(202)
+                .response::<DownloadRemoteLayersTaskInfo>(),
+        )
+        .operation(
+            Operation::get("/v1/tenant/:tenant_id/timeline/:timeline_id/download_remote_layers")
+                .summary("Get the status of the remote layers download")
+                .response::<DownloadRemoteLayersTaskInfo>(),
+        )
+        .operation(
+            Operation::post("/v1/tenant/:tenant_id/timeline/:timeline_id/prefetch_layers")
+                .summary("Download the remote layers of a timeline ahead of the reads")
+                .request::<PrefetchLayersRequest>()
+                .response::<PrefetchLayersResponse>(),
+        )
+        .operation(
+            Operation::delete("/v1/tenant/:tenant_id/timeline/:timeline_id")
+                .summary("Delete a timeline")
+                .status(202),
+        )
+        .operation(
+            Operation::get("/v1/tenant/:tenant_id/timeline/:timeline_id/layer")
+                .summary("Get the layer map of a timeline")
+                .query::<Option<String>>("reset")
+                .response::<serde_json::Value>(),
+        )
+        .operation(
+            Operation::get("/v1/tenant/:tenant_id/timeline/:timeline_id/layer/:layer_file_name")
+                .summary("Download a layer from remote storage"),
+        )
+        .operation(
+            Operation::delete("/v1/tenant/:tenant_id/timeline/:timeline_id/layer/:layer_file_name")
+                .summary("Evict a layer from local disk"),
+        )
+        .operation(
+            Operation::put("/v1/disk_usage_eviction/run")
+                .summary("Run an iteration of disk usage based eviction")
+                .request::<serde_json::Value>()
+                .response::<serde_json::Value>(),
+        )
+        .operation(
+            Operation::put("/v1/tenant/:tenant_id/break")
+                .summary("Set the tenant state to Broken")
+                .testing(),
+        )
+        .operation(
+            Operation::get("/v1/debug/tasks")
+                .summary("List the running tasks")
+                .response::<serde_json::Value>(),
+        )
+        .operation(
+            Operation::get("/v1/debug/heap_profile")
+                .summary("Get a heap profile, in the jemalloc format"),
+        )
+        .operation(
+            Operation::get("/v1/debug/heap_stats")
+                .summary("Get the allocator statistics")
+                .response::<serde_json::Value>(),
+        )
+        .operation(
+            Operation::get("/v1/debug/cpu_profile")
+                .summary("Take a CPU profile")
+                .query::<Option<u64>>("seconds")
+                .query::<Option<String>>("format"),
+        )
+        .operation(
+            Operation::get("/v1/panic")
+                .summary("Panic, to test the panic handling")
+                .status(204),
+        )
+        .operation(
+            Operation::post("/v1/tracing/event")
+                .summary("Emit a tracing event")
+                .request::<serde_json::Value>()
+                .testing(),
+        )
+}
+
+static API_DESCRIPTION: Lazy<serde_json::Value> = Lazy::new(|| api_description().to_json());
+
+async fn openapi_handler(
+    _request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    json_response(StatusCode::OK, &*API_DESCRIPTION)
+}
+
 pub fn make_router(
     conf: &'static PageServerConf,
     launch_ts: &'static LaunchTimestamp,
@@ -1308,8 +2460,7 @@ pub fn make_router(
     remote_storage: Option<GenericRemoteStorage>,
     disk_usage_eviction_state: Arc<disk_usage_eviction_task::State>,
 ) -> anyhow::Result<RouterBuilder<hyper::Body, ApiError>> {
-    let spec = include_bytes!("openapi_spec.yml");
-    let mut router = attach_openapi_ui(endpoint::make_router(), spec, "/swagger.yml", "/v1/doc");
+    let mut router = attach_swagger_ui(endpoint::make_router(), "/v1/openapi.json", "/v1/doc");
     if auth.is_some() {
         router = router.middleware(auth_middleware(|request| {
             let state = get_state(request);
@@ -1341,12 +2492,35 @@ pub fn make_router(
             .context("Failed to initialize router state")?,
         ))
         .get("/v1/status", |r| api_handler(r, status_handler))
+        .get("/v1/status/build", |r| api_handler(r, build_info_handler))
+        .get("/v1/openapi.json", |r| api_handler(r, openapi_handler))
+        .get("/v1/failpoints", |r| {
+            testing_api_handler("list failpoints", r, failpoints_list_handler)
+        })
         .put("/v1/failpoints", |r| {
             testing_api_handler("manage failpoints", r, failpoints_handler)
         })
+        .delete("/v1/failpoints/:failpoint_name", |r| {
+            testing_api_handler("remove failpoint", r, failpoint_remove_handler)
+        })
+        .get("/v1/remote_storage_faults", |r| {
+            testing_api_handler(
+                "list remote storage faults",
+                r,
+                remote_storage_faults_list_handler,
+            )
+        })
+        .put("/v1/remote_storage_faults", |r| {
+            testing_api_handler(
+                "inject remote storage faults",
+                r,
+                remote_storage_faults_handler,
+            )
+        })
         .get("/v1/tenant", |r| api_handler(r, tenant_list_handler))
         .post("/v1/tenant", |r| api_handler(r, tenant_create_handler))
         .get("/v1/tenant/:tenant_id", |r| api_handler(r, tenant_status))
+        .get("/v1/remote_cost", |r| api_handler(r, remote_cost_handler))
         .get("/v1/tenant/:tenant_id/synthetic_size", |r| {
             api_handler(r, tenant_size_handler)
         })
@@ -1356,6 +2530,18 @@ pub fn make_router(
         .get("/v1/tenant/:tenant_id/config", |r| {
             api_handler(r, get_tenant_config_handler)
         })
+        .get("/v1/tenant/:tenant_id/upload_throttle", |r| {
+            api_handler(r, get_upload_throttle_handler)
+        })
+        .put("/v1/tenant/:tenant_id/upload_throttle", |r| {
+            api_handler(r, update_upload_throttle_handler)
+        })
+        .post("/v1/tenant/:tenant_id/deletion_dry_run/flush", |r| {
+            api_handler(r, deletion_dry_run_flush_handler)
+        })
+        .get("/v1/tenant/:tenant_id/wal_redo", |r| {
+            api_handler(r, tenant_wal_redo_status_handler)
+        })
         .get("/v1/tenant/:tenant_id/timeline", |r| {
             api_handler(r, timeline_list_handler)
         })
@@ -1381,6 +2567,10 @@ pub fn make_router(
             "/v1/tenant/:tenant_id/timeline/:timeline_id/get_lsn_by_timestamp",
             |r| api_handler(r, get_lsn_by_timestamp_handler),
         )
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/relation_sizes",
+            |r| api_handler(r, timeline_relation_sizes_handler),
+        )
         .put("/v1/tenant/:tenant_id/timeline/:timeline_id/do_gc", |r| {
             api_handler(r, timeline_gc_handler)
         })
@@ -1391,6 +2581,37 @@ pub fn make_router(
             "/v1/tenant/:tenant_id/timeline/:timeline_id/checkpoint",
             |r| testing_api_handler("run timeline checkpoint", r, timeline_checkpoint_handler),
         )
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/upload_queue",
+            |r| api_handler(r, timeline_upload_queue_handler),
+        )
+        .delete(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/upload_queue/:task_id",
+            |r| api_handler(r, timeline_cancel_upload_task_handler),
+        )
+        .put(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/upload_queue/allow_lsn_regression",
+            |r| api_handler(r, timeline_allow_lsn_regression_handler),
+        )
+        .post(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/scrub_remote",
+            |r| api_handler(r, timeline_scrub_remote_handler),
+        )
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/remote_consistency",
+            |r| api_handler(r, timeline_remote_consistency_handler),
+        )
+        .post(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/copy_remote",
+            |r| api_handler(r, timeline_copy_remote_handler),
+        )
+        .post("/v1/tenant/:tenant_id/timeline/:timeline_id/export", |r| {
+            api_handler(r, timeline_export_handler)
+        })
+        .post(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/restore_index",
+            |r| api_handler(r, timeline_restore_index_handler),
+        )
         .post(
             "/v1/tenant/:tenant_id/timeline/:timeline_id/download_remote_layers",
             |r| api_handler(r, timeline_download_remote_layers_handler_post),
@@ -1399,6 +2620,10 @@ pub fn make_router(
             "/v1/tenant/:tenant_id/timeline/:timeline_id/download_remote_layers",
             |r| api_handler(r, timeline_download_remote_layers_handler_get),
         )
+        .post(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/prefetch_layers",
+            |r| api_handler(r, timeline_prefetch_layers_handler),
+        )
         .delete("/v1/tenant/:tenant_id/timeline/:timeline_id", |r| {
             api_handler(r, timeline_delete_handler)
         })
@@ -1419,6 +2644,10 @@ pub fn make_router(
         .put("/v1/tenant/:tenant_id/break", |r| {
             testing_api_handler("set tenant state to broken", r, handle_tenant_break)
         })
+        .get("/v1/debug/tasks", |r| api_handler(r, task_list_handler))
+        .get("/v1/debug/heap_profile", |r| api_handler(r, heap_profile_handler))
+        .get("/v1/debug/heap_stats", |r| api_handler(r, heap_stats_handler))
+        .get("/v1/debug/cpu_profile", |r| api_handler(r, cpu_profile_handler))
         .get("/v1/panic", |r| api_handler(r, always_panic_handler))
         .post("/v1/tracing/event", |r| {
             testing_api_handler("emit a tracing event", r, post_tracing_event_handler)
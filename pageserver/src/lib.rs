@@ -1,20 +1,33 @@
 mod auth;
+pub mod background_jobs;
 pub mod basebackup;
+pub mod build_info;
+pub mod catalog;
 pub mod config;
+pub mod config_check;
 pub mod consumption_metrics;
 pub mod context;
+pub mod cpu_profile;
+pub mod disk_space_monitor;
 pub mod disk_usage_eviction_task;
+pub mod heap_profile;
 pub mod http;
 pub mod import_datadir;
 pub mod keyspace;
 pub(crate) mod metrics;
+pub mod metrics_push;
 pub mod page_cache;
 pub mod page_service;
 pub mod pgdatadir_mapping;
+pub mod redo_fixture;
+pub mod relation_sizes;
+pub mod remote_scrubber;
 pub mod repository;
+pub mod shutdown;
 pub(crate) mod statvfs;
 pub mod task_mgr;
 pub mod tenant;
+pub mod timescaledb;
 pub mod trace;
 pub mod virtual_file;
 pub mod walingest;
@@ -23,7 +36,6 @@ pub mod walredo;
 
 use std::path::Path;
 
-use crate::task_mgr::TaskKind;
 use tracing::info;
 
 /// Current storage format version
@@ -43,31 +55,8 @@ pub const DELTA_FILE_MAGIC: u16 = 0x5A61;
 
 static ZERO_PAGE: bytes::Bytes = bytes::Bytes::from_static(&[0u8; 8192]);
 
-pub use crate::metrics::preinitialize_metrics;
-
-#[tracing::instrument]
-pub async fn shutdown_pageserver(exit_code: i32) {
-    // Shut down the libpq endpoint task. This prevents new connections from
-    // being accepted.
-    task_mgr::shutdown_tasks(Some(TaskKind::LibpqEndpointListener), None, None).await;
-
-    // Shut down any page service tasks.
-    task_mgr::shutdown_tasks(Some(TaskKind::PageRequestHandler), None, None).await;
-
-    // Shut down all the tenants. This flushes everything to disk and kills
-    // the checkpoint and GC tasks.
-    tenant::mgr::shutdown_all_tenants().await;
-
-    // Shut down the HTTP endpoint last, so that you can still check the server's
-    // status while it's shutting down.
-    // FIXME: We should probably stop accepting commands like attach/detach earlier.
-    task_mgr::shutdown_tasks(Some(TaskKind::HttpEndpointListener), None, None).await;
-
-    // There should be nothing left, but let's be sure
-    task_mgr::shutdown_tasks(None, None, None).await;
-    info!("Shut down successfully completed");
-    std::process::exit(exit_code);
-}
+pub use crate::metrics::{preinitialize_metrics, set_histogram_buckets};
+pub use crate::shutdown::shutdown_pageserver;
 
 const DEFAULT_BASE_BACKOFF_SECONDS: f64 = 0.1;
 const DEFAULT_MAX_BACKOFF_SECONDS: f64 = 3.0;
@@ -99,6 +88,22 @@ pub const METADATA_FILE_NAME: &str = "metadata";
 /// Full path: `tenants/<tenant_id>/config`.
 pub const TENANT_CONFIG_NAME: &str = "config";
 
+/// The generation the tenant was attached with, if any.
+/// Full path: `tenants/<tenant_id>/generation`.
+pub const TENANT_GENERATION_FILE_NAME: &str = "generation";
+
+/// The deletions of remote layer files waiting for their grace period.
+/// Full path: `tenants/<tenant_id>/deferred_deletions`.
+pub const TENANT_DEFERRED_DELETIONS_FILE_NAME: &str = "deferred_deletions";
+
+/// The remote storage of the tenant, if it doesn't use the one of the pageserver.
+/// Full path: `tenants/<tenant_id>/remote_storage`.
+pub const TENANT_REMOTE_STORAGE_FILE_NAME: &str = "remote_storage";
+
+/// The source of the timelines to import, until the attach of a tenant in import mode completes.
+/// Full path: `tenants/<tenant_id>/import`.
+pub const TENANT_IMPORT_FILE_NAME: &str = "import";
+
 /// A suffix used for various temporary files. Any temporary files found in the
 /// data directory at pageserver startup can be automatically removed.
 pub const TEMP_FILE_SUFFIX: &str = "___temp";
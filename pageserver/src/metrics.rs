@@ -1,12 +1,17 @@
+use anyhow::{ensure, Context as _};
+use metrics::core::{Collector, MetricVec, MetricVecBuilder};
 use metrics::metric_vec_duration::DurationResultObserver;
 use metrics::{
-    register_counter_vec, register_histogram, register_histogram_vec, register_int_counter,
-    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, register_uint_gauge,
-    register_uint_gauge_vec, Counter, CounterVec, Histogram, HistogramVec, IntCounter,
-    IntCounterVec, IntGauge, IntGaugeVec, UIntGauge, UIntGaugeVec,
+    register_counter, register_counter_vec, register_gauge_vec, register_histogram,
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, register_uint_gauge, register_uint_gauge_vec, Counter, CounterVec,
+    GaugeVec, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, UIntGauge,
+    UIntGaugeVec,
 };
-use once_cell::sync::Lazy;
-use pageserver_api::models::TenantState;
+use once_cell::sync::{Lazy, OnceCell};
+use pageserver_api::models::{TenantRemoteCost, TenantState};
+use remote_storage::{RequestKind, RequestObserver};
+use serde::Deserialize;
 use strum::VariantNames;
 use strum_macros::{EnumVariantNames, IntoStaticStr};
 use utils::id::{TenantId, TimelineId};
@@ -18,12 +23,85 @@ use utils::id::{TenantId, TimelineId};
 /// The buckets capture the majority of latencies in the microsecond and
 /// millisecond range but also extend far enough up to distinguish "bad" from
 /// "really bad".
+///
+/// The remote operation and WAL redo histograms have trace exemplars, see
+/// [`observe_traced`].
 const CRITICAL_OP_BUCKETS: &[f64] = &[
     0.000_001, 0.000_010, 0.000_100, // 1 us, 10 us, 100 us
     0.001_000, 0.010_000, 0.100_000, // 1 ms, 10 ms, 100 ms
     1.0, 10.0, 100.0, // 1 s, 10 s, 100 s
 ];
 
+/// Histogram families whose bucket boundaries can be overridden with the
+/// `histogram_buckets` config option, by metric name.
+const CONFIGURABLE_BUCKETS_HISTOGRAMS: &[&str] = &[
+    "pageserver_smgr_query_seconds",
+    "pageserver_remote_operation_seconds",
+    "pageserver_wal_redo_seconds",
+    "pageserver_background_job_seconds",
+    "pageserver_getpage_stage_seconds",
+];
+
+/// Bucket boundaries overriding the compiled-in ones, keyed by metric name.
+///
+/// The defaults don't fit all hardware, e.g. getpage on local NVMe is far below
+/// the millisecond range.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct HistogramBucketsConfig(pub HashMap<String, Vec<f64>>);
+
+// `validate` rejects NaN, so the float comparison is an equivalence relation.
+impl Eq for HistogramBucketsConfig {}
+
+impl HistogramBucketsConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, buckets) in &self.0 {
+            ensure!(
+                CONFIGURABLE_BUCKETS_HISTOGRAMS.contains(&name.as_str()),
+                "buckets of histogram '{name}' are not configurable, \
+                 configurable ones are: {CONFIGURABLE_BUCKETS_HISTOGRAMS:?}"
+            );
+            ensure!(!buckets.is_empty(), "no buckets for histogram '{name}'");
+            ensure!(
+                buckets.iter().all(|b| b.is_finite()),
+                "buckets of histogram '{name}' must be finite numbers"
+            );
+            ensure!(
+                buckets.windows(2).all(|w| w[0] < w[1]),
+                "buckets of histogram '{name}' must be in strictly increasing order"
+            );
+        }
+        Ok(())
+    }
+}
+
+static HISTOGRAM_BUCKETS: OnceCell<HistogramBucketsConfig> = OnceCell::new();
+
+/// Install the bucket overrides from the config.
+///
+/// Must be called before any of the [`CONFIGURABLE_BUCKETS_HISTOGRAMS`] is
+/// first used, i.e. at startup: they are registered with their buckets lazily,
+/// and fail this call once that happened.
+pub fn set_histogram_buckets(config: &HistogramBucketsConfig) -> anyhow::Result<()> {
+    config.validate().context("invalid histogram buckets")?;
+    HISTOGRAM_BUCKETS
+        .set(config.clone())
+        .map_err(|_| anyhow::anyhow!("histogram buckets were already set or used"))
+}
+
+/// Buckets to register the histogram `name` with.
+fn histogram_buckets(name: &str, default: Vec<f64>) -> Vec<f64> {
+    debug_assert!(CONFIGURABLE_BUCKETS_HISTOGRAMS.contains(&name));
+    // Initializing here makes a later set_histogram_buckets() call fail, rather
+    // than silently not apply to this histogram.
+    HISTOGRAM_BUCKETS
+        .get_or_init(HistogramBucketsConfig::default)
+        .0
+        .get(name)
+        .cloned()
+        .unwrap_or(default)
+}
+
 // Metrics collected on operations on the storage repository.
 #[derive(Debug, EnumVariantNames, IntoStaticStr)]
 #[strum(serialize_all = "kebab_case")]
@@ -104,6 +182,57 @@ pub static RECONSTRUCT_TIME: Lazy<Histogram> = Lazy::new(|| {
     .expect("failed to define a metric")
 });
 
+#[derive(Debug, Clone, Copy, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+enum GetPageStage {
+    LayerMapLookup,
+    OndemandDownloadWait,
+    RedoWait,
+    RedoExecution,
+    PageCache,
+}
+
+static GETPAGE_STAGE_TIME: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "pageserver_getpage_stage_seconds",
+        "Time spent in each stage of the page reconstruction of the sampled getpage requests",
+        &["stage"],
+        histogram_buckets("pageserver_getpage_stage_seconds", CRITICAL_OP_BUCKETS.into()),
+    )
+    .expect("failed to define a metric")
+});
+
+/// The time a page reconstruction spent in each stage. Measured for every `Timeline::get`,
+/// but only recorded for the getpage requests sampled by the `getpage_timing` setting.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GetPageStageTimings {
+    /// Searching the layer map and reading the layers, without the downloads.
+    pub layer_map_lookup: Duration,
+    pub ondemand_download_wait: Duration,
+    /// Waiting for access to the WAL redo process.
+    pub redo_wait: Duration,
+    pub redo_execution: Duration,
+    /// Looking up and memorizing materialized pages.
+    pub page_cache: Duration,
+}
+
+impl GetPageStageTimings {
+    pub fn record(&self) {
+        for (stage, duration) in [
+            (GetPageStage::LayerMapLookup, self.layer_map_lookup),
+            (GetPageStage::OndemandDownloadWait, self.ondemand_download_wait),
+            (GetPageStage::RedoWait, self.redo_wait),
+            (GetPageStage::RedoExecution, self.redo_execution),
+            (GetPageStage::PageCache, self.page_cache),
+        ] {
+            let stage: &'static str = stage.into();
+            GETPAGE_STAGE_TIME
+                .with_label_values(&[stage])
+                .observe(duration.as_secs_f64());
+        }
+    }
+}
+
 pub static MATERIALIZED_PAGE_CACHE_HIT_DIRECT: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
         "pageserver_materialized_cache_hits_direct_total",
@@ -139,6 +268,9 @@ pub struct PageCacheMetrics {
     pub read_hits_immutable: IntCounter,
     pub read_hits_materialized_page_exact: IntCounter,
     pub read_hits_materialized_page_older_lsn: IntCounter,
+    pub read_misses_materialized_page: IntCounter,
+
+    pub evictions_materialized_page: IntCounter,
 }
 
 static PAGE_CACHE_READ_HITS: Lazy<IntCounterVec> = Lazy::new(|| {
@@ -150,6 +282,24 @@ static PAGE_CACHE_READ_HITS: Lazy<IntCounterVec> = Lazy::new(|| {
     .expect("failed to define a metric")
 });
 
+static PAGE_CACHE_READ_MISSES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_page_cache_read_misses_total",
+        "Number of read accesses to the page cache that missed",
+        &["key_kind"]
+    )
+    .expect("failed to define a metric")
+});
+
+static PAGE_CACHE_EVICTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_page_cache_evictions_total",
+        "Number of pages evicted from the page cache to make room for another",
+        &["key_kind"]
+    )
+    .expect("failed to define a metric")
+});
+
 static PAGE_CACHE_READ_ACCESSES: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
         "pageserver_page_cache_read_accesses_total",
@@ -201,6 +351,18 @@ pub static PAGE_CACHE: Lazy<PageCacheMetrics> = Lazy::new(|| PageCacheMetrics {
             .get_metric_with_label_values(&["materialized_page", "older_lsn"])
             .unwrap()
     },
+
+    read_misses_materialized_page: {
+        PAGE_CACHE_READ_MISSES
+            .get_metric_with_label_values(&["materialized_page"])
+            .unwrap()
+    },
+
+    evictions_materialized_page: {
+        PAGE_CACHE_EVICTIONS
+            .get_metric_with_label_values(&["materialized_page"])
+            .unwrap()
+    },
 });
 
 pub struct PageCacheSizeMetrics {
@@ -274,6 +436,19 @@ static RESIDENT_PHYSICAL_SIZE: Lazy<UIntGaugeVec> = Lazy::new(|| {
     .expect("failed to define a metric")
 });
 
+static TIMELINE_WATERMARK_LEVEL: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "pageserver_timeline_watermark_level",
+        "Level of a resource of the timeline against the layer_watermarks setting: \
+         0 below the warning threshold, 1 warning, 2 critical",
+        &["tenant_id", "timeline_id", "resource"]
+    )
+    .expect("failed to define a metric")
+});
+
+/// The `resource` label values of [`TIMELINE_WATERMARK_LEVEL`].
+const WATERMARK_RESOURCES: &[&str] = &["layer_count", "local_size"];
+
 static REMOTE_PHYSICAL_SIZE: Lazy<UIntGaugeVec> = Lazy::new(|| {
     register_uint_gauge_vec!(
         "pageserver_remote_physical_size",
@@ -366,6 +541,47 @@ static EVICTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
     .expect("failed to define a metric")
 });
 
+/// Number of databases of a timeline whose ingested WAL is counted on its own: the ones that
+/// ingested the most WAL in the last [`INGEST_DATABASES_RANK_PERIOD`], or the first ones seen
+/// before the first ranking. The WAL of the other databases is counted under
+/// `database_oid="other"`.
+pub(crate) const MAX_INGEST_DATABASES: usize = 16;
+
+/// How often the databases are ranked by their ingested WAL. The series of a database that
+/// drops out of the top [`MAX_INGEST_DATABASES`] is removed, and starts again from zero if it
+/// gets back in. The `other` series is never removed, and only counts the WAL of the databases
+/// while they are out of the top.
+const INGEST_DATABASES_RANK_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+static WAL_INGEST_DATABASE_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_wal_ingest_database_bytes_total",
+        format!(
+            "Bytes of WAL ingested by the timeline, by the database the records modify. \
+             Database 0 are the shared relations, and the records of no database. Only the \
+             {MAX_INGEST_DATABASES} databases with the most WAL in the last {} minutes are \
+             counted on their own, the others are counted under database_oid=\"other\".",
+            INGEST_DATABASES_RANK_PERIOD.as_secs() / 60
+        ),
+        &["tenant_id", "timeline_id", "database_oid"]
+    )
+    .expect("failed to define a metric")
+});
+
+static WAL_INGEST_DATABASE_RECORDS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_wal_ingest_database_records_total",
+        format!(
+            "WAL records ingested by the timeline, by the database they modify. Only the \
+             {MAX_INGEST_DATABASES} databases with the most WAL in the last {} minutes are \
+             counted on their own, the others are counted under database_oid=\"other\".",
+            INGEST_DATABASES_RANK_PERIOD.as_secs() / 60
+        ),
+        &["tenant_id", "timeline_id", "database_oid"]
+    )
+    .expect("failed to define a metric")
+});
+
 static EVICTIONS_WITH_LOW_RESIDENCE_DURATION: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
         "pageserver_evictions_with_low_residence_duration",
@@ -476,8 +692,11 @@ impl EvictionsWithLowResidenceDuration {
                 // - "double-panic => illegal instruction" or
                 // - future "drop panick => abort"
                 //
-                // so just nag: (the error has the labels)
-                tracing::warn!("failed to remove EvictionsWithLowResidenceDuration, it was already removed? {e:#?}");
+                // It's also expected if the tenant was detached or the timeline deleted
+                // before the Timeline was dropped, see `remove_tenant_label_sets`.
+                //
+                // so just note it: (the error has the labels)
+                tracing::debug!("failed to remove EvictionsWithLowResidenceDuration, it was already removed? {e:#?}");
             }
             Ok(()) => {
                 // to help identify cases where we double-remove the same values, let's log all
@@ -536,7 +755,7 @@ pub static SMGR_QUERY_TIME: Lazy<HistogramVec> = Lazy::new(|| {
         "pageserver_smgr_query_seconds",
         "Time spent on smgr query handling",
         &["smgr_query_type", "tenant_id", "timeline_id"],
-        CRITICAL_OP_BUCKETS.into(),
+        histogram_buckets("pageserver_smgr_query_seconds", CRITICAL_OP_BUCKETS.into()),
     )
     .expect("failed to define a metric")
 });
@@ -562,6 +781,15 @@ impl DurationResultObserver for BasebackupQueryTime {
     }
 }
 
+pub(crate) static BUILD_INFO: Lazy<UIntGaugeVec> = Lazy::new(|| {
+    register_uint_gauge_vec!(
+        "pageserver_build_info",
+        "Build information of the running pageserver binary, the value is always 1",
+        &["revision", "build_time", "rustc_version", "features", "pg_versions"]
+    )
+    .expect("failed to define a metric")
+});
+
 pub static LIVE_CONNECTIONS_COUNT: Lazy<IntGaugeVec> = Lazy::new(|| {
     register_int_gauge_vec!(
         "pageserver_live_connections",
@@ -621,6 +849,188 @@ static REMOTE_TIMELINE_CLIENT_BYTES_FINISHED_COUNTER: Lazy<IntCounterVec> = Lazy
     .expect("failed to define a metric")
 });
 
+static REMOTE_UPLOAD_QUEUE_DEPTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "pageserver_remote_upload_queue_depth",
+        "Number of queued and in-progress operations of the upload queue of a timeline, not \
+         counting the barriers.",
+        &["tenant_id", "timeline_id"],
+    )
+    .expect("failed to define a metric")
+});
+
+static REMOTE_UPLOAD_QUEUE_OLDEST_OP: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "pageserver_remote_upload_queue_oldest_op_timestamp_seconds",
+        "Unix timestamp at which the oldest queued or in-progress operation of the upload queue \
+         of a timeline was scheduled, 0 if there is none. Its age is the current time minus it, \
+         which keeps growing while the operation is retried.",
+        &["tenant_id", "timeline_id"],
+    )
+    .expect("failed to define a metric")
+});
+
+static REMOTE_LAST_INDEX_UPLOAD: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "pageserver_remote_last_index_upload_timestamp_seconds",
+        "Unix timestamp of the last successful index upload of a timeline, or of the \
+         initialization of its upload queue if there was none since.",
+        &["tenant_id", "timeline_id"],
+    )
+    .expect("failed to define a metric")
+});
+
+static REMOTE_STORAGE_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_remote_storage_requests_total",
+        "Requests to the remote storage made by the timelines of a tenant, successful or not, \
+         by the request class they are billed as: put, get, delete or list.",
+        &["tenant_id", "request_kind"],
+    )
+    .expect("failed to define a metric")
+});
+
+static REMOTE_STORAGE_REQUEST_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_remote_storage_request_bytes_total",
+        "Bytes of object data uploaded by the put requests and downloaded by the get requests \
+         of a tenant.",
+        &["tenant_id", "request_kind"],
+    )
+    .expect("failed to define a metric")
+});
+
+fn request_kind_label(kind: RequestKind) -> &'static str {
+    match kind {
+        RequestKind::Put => "put",
+        RequestKind::Get => "get",
+        RequestKind::Delete => "delete",
+        RequestKind::List => "list",
+    }
+}
+
+/// Prices of the remote storage requests and transfers, in any currency, to estimate the
+/// remote storage cost of each tenant. The defaults are the list prices of S3 Standard in
+/// us-east-1 in USD, with the transfers within the region free.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RemoteStoragePrices {
+    pub put_per_1000: f64,
+    pub get_per_1000: f64,
+    pub delete_per_1000: f64,
+    pub list_per_1000: f64,
+    pub upload_per_gib: f64,
+    pub download_per_gib: f64,
+}
+
+// `validate` rejects NaN, so the float comparison is an equivalence relation.
+impl Eq for RemoteStoragePrices {}
+
+impl Default for RemoteStoragePrices {
+    fn default() -> Self {
+        RemoteStoragePrices {
+            put_per_1000: 0.005,
+            get_per_1000: 0.0004,
+            delete_per_1000: 0.0,
+            list_per_1000: 0.005,
+            upload_per_gib: 0.0,
+            download_per_gib: 0.0,
+        }
+    }
+}
+
+impl RemoteStoragePrices {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let RemoteStoragePrices {
+            put_per_1000,
+            get_per_1000,
+            delete_per_1000,
+            list_per_1000,
+            upload_per_gib,
+            download_per_gib,
+        } = *self;
+        for price in [
+            put_per_1000,
+            get_per_1000,
+            delete_per_1000,
+            list_per_1000,
+            upload_per_gib,
+            download_per_gib,
+        ] {
+            ensure!(
+                price.is_finite() && price >= 0.0,
+                "prices must be non-negative numbers, got {price}"
+            );
+        }
+        Ok(())
+    }
+
+    fn estimate(&self, cost: &TenantRemoteCost) -> f64 {
+        const GIB: f64 = (1u64 << 30) as f64;
+        (cost.put_requests as f64 * self.put_per_1000
+            + cost.get_requests as f64 * self.get_per_1000
+            + cost.delete_requests as f64 * self.delete_per_1000
+            + cost.list_requests as f64 * self.list_per_1000)
+            / 1000.0
+            + cost.uploaded_bytes as f64 / GIB * self.upload_per_gib
+            + cost.downloaded_bytes as f64 / GIB * self.download_per_gib
+    }
+}
+
+/// The remote storage requests of each tenant counted since it was attached, with their
+/// estimated cost, the most expensive tenants first.
+pub fn tenant_remote_storage_costs(prices: &RemoteStoragePrices) -> Vec<TenantRemoteCost> {
+    let mut costs: HashMap<TenantId, TenantRemoteCost> = HashMap::new();
+    let families = [
+        (&*REMOTE_STORAGE_REQUESTS, false),
+        (&*REMOTE_STORAGE_REQUEST_BYTES, true),
+    ];
+    for (family, is_bytes) in families {
+        for metric in family.collect().iter().flat_map(|family| family.get_metric()) {
+            let label = |name: &str| {
+                metric
+                    .get_label()
+                    .iter()
+                    .find(|pair| pair.get_name() == name)
+                    .map(|pair| pair.get_value())
+            };
+            let Some(Ok(tenant_id)) = label("tenant_id").map(str::parse::<TenantId>) else {
+                continue;
+            };
+            let cost = costs.entry(tenant_id).or_insert_with(|| TenantRemoteCost {
+                tenant_id,
+                put_requests: 0,
+                get_requests: 0,
+                delete_requests: 0,
+                list_requests: 0,
+                uploaded_bytes: 0,
+                downloaded_bytes: 0,
+                estimated_cost: 0.0,
+            });
+            let value = metric.get_counter().get_value() as u64;
+            let counter = match (label("request_kind"), is_bytes) {
+                (Some("put"), false) => &mut cost.put_requests,
+                (Some("get"), false) => &mut cost.get_requests,
+                (Some("delete"), false) => &mut cost.delete_requests,
+                (Some("list"), false) => &mut cost.list_requests,
+                (Some("put"), true) => &mut cost.uploaded_bytes,
+                (Some("get"), true) => &mut cost.downloaded_bytes,
+                _ => continue,
+            };
+            *counter += value;
+        }
+    }
+    let mut costs = costs
+        .into_values()
+        .map(|cost| TenantRemoteCost {
+            estimated_cost: prices.estimate(&cost),
+            ..cost
+        })
+        .collect::<Vec<_>>();
+    costs.sort_by(|a, b| b.estimated_cost.total_cmp(&a.estimated_cost));
+    costs
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RemoteOpKind {
     Upload,
@@ -657,7 +1067,149 @@ pub static REMOTE_OPERATION_TIME: Lazy<HistogramVec> = Lazy::new(|| {
         "Time spent on remote storage operations. \
         Grouped by tenant, timeline, operation_kind and status. \
         Does not account for time spent waiting in remote timeline client's queues.",
-        &["tenant_id", "timeline_id", "file_kind", "op_kind", "status"]
+        &["tenant_id", "timeline_id", "file_kind", "op_kind", "status"],
+        histogram_buckets(
+            "pageserver_remote_operation_seconds",
+            metrics::DEFAULT_BUCKETS.to_vec()
+        ),
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_UPLOAD_THROTTLED_SECONDS: Lazy<Counter> = Lazy::new(|| {
+    register_counter!(
+        "pageserver_remote_upload_throttled_seconds_total",
+        "Time layer uploads waited for the upload throttle of their tenant"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_UPLOAD_QUEUE_WAIT_SECONDS: Lazy<Counter> = Lazy::new(|| {
+    register_counter!(
+        "pageserver_remote_upload_queue_wait_seconds_total",
+        "Time flushes and compactions waited for the upload queue of their timeline \
+         to be within the upload_queue_limits"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_INDEX_UPLOADS_COALESCED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_remote_index_uploads_coalesced_total",
+        "Number of queued index uploads skipped because a later index upload superseded them"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_INDEX_LSN_REGRESSIONS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_remote_index_lsn_regressions_total",
+        "Number of index upload attempts with a disk_consistent_lsn behind the one of the last \
+         index uploaded, whether they were rejected or not"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_TASK_REPEATED_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_remote_task_repeated_failures_total",
+        "Number of failed remote upload or deletion attempts after the first few retries \
+         of the same task. Their warnings are rate limited, this counts all of them."
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_STORAGE_CONSECUTIVE_FAILURES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "pageserver_remote_storage_consecutive_failures",
+        "Number of remote task attempts that failed in a row, per remote storage",
+        &["storage"]
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_STORAGE_CIRCUIT_BREAKER_OPEN: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "pageserver_remote_storage_circuit_breaker_open",
+        "1 from when the circuit breaker of the remote storage opens until a remote task succeeds \
+         again",
+        &["storage"]
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_STORAGE_CIRCUIT_BREAKER_TRIPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_remote_storage_circuit_breaker_trips_total",
+        "Number of times the circuit breaker of the remote storage opened",
+        &["storage"]
+    )
+    .expect("failed to define a metric")
+});
+
+pub static UPLOAD_EVENTS_DROPPED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_upload_events_dropped_total",
+        "Number of upload queue events not delivered to the webhook, its queue full or its \
+         endpoint failing"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_SCRUB_DELETED_OBJECTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_remote_scrub_deleted_objects_total",
+        "Number of objects in the remote storage not referenced from the index of their \
+         timeline, deleted by the scrubber"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_SCRUB_DELETED_BYTES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_remote_scrub_deleted_bytes_total",
+        "Size of the objects deleted by the remote storage scrubber"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_DELETION_DRY_RUN_OBJECTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_remote_deletion_dry_run_objects_total",
+        "Number of objects that the upload queues would have deleted from the remote storage, \
+         had their tenants not been in the deletion dry-run mode"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_TIERED_LAYERS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_remote_tiered_layers_total",
+        "Number of layer files moved to the cold tier of the remote storage"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_TIERED_BYTES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_remote_tiered_bytes_total",
+        "Size of the layer files moved to the cold tier of the remote storage"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_MIRROR_QUEUED_OPERATIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "pageserver_remote_mirror_queued_operations",
+        "Number of operations performed in the remote storage and not yet in its mirror"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static REMOTE_MIRROR_QUEUED_BYTES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "pageserver_remote_mirror_queued_bytes",
+        "Size of the layer files uploaded to the remote storage and not yet to its mirror"
     )
     .expect("failed to define a metric")
 });
@@ -671,6 +1223,33 @@ pub static TENANT_TASK_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
     .expect("Failed to register tenant_task_events metric")
 });
 
+pub static TASK_POLL_TIME: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "pageserver_task_poll_seconds_total",
+        "Time spent polling task_mgr tasks, i.e., time the tasks occupied a runtime worker thread",
+        &["task_kind"],
+    )
+    .expect("failed to define a metric")
+});
+
+pub static TASK_WALL_TIME: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "pageserver_task_wall_seconds_total",
+        "Wall-clock time task_mgr tasks have been alive, including time spent waiting",
+        &["task_kind"],
+    )
+    .expect("failed to define a metric")
+});
+
+pub static SUPERVISED_TASK_PANICS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_supervised_task_panics_total",
+        "Number of panics of supervised task_mgr tasks, whether they were restarted or not",
+        &["task_kind"],
+    )
+    .expect("failed to define a metric")
+});
+
 pub static BACKGROUND_LOOP_PERIOD_OVERRUN_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
         "pageserver_background_loop_period_overrun_count",
@@ -680,6 +1259,82 @@ pub static BACKGROUND_LOOP_PERIOD_OVERRUN_COUNT: Lazy<IntCounterVec> = Lazy::new
     .expect("failed to define a metric")
 });
 
+pub(crate) static BACKGROUND_JOB_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "pageserver_background_job_seconds",
+        "Time spent in iterations of background jobs, excluding waiting for a concurrency permit",
+        &["job"],
+        histogram_buckets(
+            "pageserver_background_job_seconds",
+            vec![0.01, 0.1, 1.0, 10.0, 60.0, 300.0, 1800.0, 3600.0],
+        ),
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static BACKGROUND_JOB_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_background_job_failures_total",
+        "Number of background job iterations that returned an error",
+        &["job"],
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static BACKGROUND_JOB_LAST_RUN: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "pageserver_background_job_last_run_timestamp_seconds",
+        "Unix timestamp of the last finished iteration of a background job, of any tenant for per-tenant jobs",
+        &["job"],
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static SHUTDOWN_PHASE_SECONDS: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "pageserver_shutdown_phase_seconds",
+        "Time spent in each phase of the ongoing graceful shutdown",
+        &["phase"],
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) struct DiskSpaceMetrics {
+    pub total_bytes: UIntGauge,
+    pub avail_bytes: UIntGauge,
+    pub total_inodes: UIntGauge,
+    pub avail_inodes: UIntGauge,
+    pub critical: IntGauge,
+}
+
+pub(crate) static DISK_SPACE_METRICS: Lazy<DiskSpaceMetrics> = Lazy::new(|| DiskSpaceMetrics {
+    total_bytes: register_uint_gauge!(
+        "pageserver_disk_space_total_bytes",
+        "Size of the filesystem of the workdir, as seen by the disk space monitor"
+    )
+    .expect("failed to define a metric"),
+    avail_bytes: register_uint_gauge!(
+        "pageserver_disk_space_available_bytes",
+        "Space available to the pageserver on the filesystem of the workdir"
+    )
+    .expect("failed to define a metric"),
+    total_inodes: register_uint_gauge!(
+        "pageserver_disk_inodes_total",
+        "Number of inodes of the filesystem of the workdir, zero if it doesn't limit them"
+    )
+    .expect("failed to define a metric"),
+    avail_inodes: register_uint_gauge!(
+        "pageserver_disk_inodes_available",
+        "Number of inodes available on the filesystem of the workdir"
+    )
+    .expect("failed to define a metric"),
+    critical: register_int_gauge!(
+        "pageserver_disk_space_critical",
+        "1 while disk usage is above the critical thresholds of the disk space monitor"
+    )
+    .expect("failed to define a metric"),
+});
+
 // walreceiver metrics
 
 pub static WALRECEIVER_STARTED_CONNECTIONS: Lazy<IntCounter> = Lazy::new(|| {
@@ -730,6 +1385,30 @@ pub static WALRECEIVER_CANDIDATES_ADDED: Lazy<IntCounter> =
 pub static WALRECEIVER_CANDIDATES_REMOVED: Lazy<IntCounter> =
     Lazy::new(|| WALRECEIVER_CANDIDATES_EVENTS.with_label_values(&["remove"]));
 
+pub static INGEST_DEFERRED_FREEZES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_ingest_deferred_freezes_total",
+        "Number of open layers whose freeze on timeout was deferred to coalesce small relation writes"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static PREFETCH_PAGES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_prefetch_pages_total",
+        "Number of pages read ahead of sequential scans of a relation"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static PREFETCH_SKIPPED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_prefetch_skipped_total",
+        "Number of prefetches not started because the tenant had too many in flight"
+    )
+    .expect("failed to define a metric")
+});
+
 // Metrics collected on WAL redo operations
 //
 // We collect the time spent in actual WAL redo ('redo'), and time waiting
@@ -778,7 +1457,7 @@ pub static WAL_REDO_TIME: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
         "pageserver_wal_redo_seconds",
         "Time spent on WAL redo",
-        redo_histogram_time_buckets!()
+        histogram_buckets("pageserver_wal_redo_seconds", redo_histogram_time_buckets!())
     )
     .expect("failed to define a metric")
 });
@@ -818,6 +1497,132 @@ pub static WAL_REDO_RECORD_COUNTER: Lazy<IntCounter> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static WAL_REDO_NATIVE_RECORD_COUNTER: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_native_replayed_wal_records_total",
+        "Number of Postgres WAL records replayed without the WAL redo process"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static WAL_REDO_PROCESS_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_wal_redo_process_failures_total",
+        "Number of failed attempts to apply WAL records in the WAL redo process. \
+         Their errors are rate limited, this counts all of them."
+    )
+    .expect("failed to define a metric")
+});
+
+pub static WAL_REDO_TIMEOUTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_wal_redo_timeouts_total",
+        "Number of attempts to apply WAL records that timed out in the WAL redo process"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static WAL_REDO_CRASHES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_wal_redo_crashes_total",
+        "Number of attempts to apply WAL records that failed in the WAL redo process \
+         for another reason than a timeout or a limit of the tenant config"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static WAL_REDO_STDERR_SUPPRESSED_LINES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_wal_redo_stderr_suppressed_lines_total",
+        "Number of lines of the WAL redo process's stderr not logged because of the rate limit"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static WAL_REDO_QUARANTINES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_wal_redo_quarantines_total",
+        "Number of times the WAL redo of a tenant was quarantined after failing repeatedly"
+    )
+    .expect("failed to define a metric")
+});
+
+pub static WAL_REDO_LIMIT_VIOLATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_wal_redo_limit_violations_total",
+        "Number of WAL redo process failures caused by the limits of the tenant config",
+        &["limit"]
+    )
+    .expect("failed to define a metric")
+});
+
+pub static WAL_REDO_SLOW_REQUESTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_wal_redo_slow_requests_total",
+        "Number of WAL redo requests that took longer than wal_redo_slow_threshold. \
+         A sample of them is logged, this counts all of them."
+    )
+    .expect("failed to define a metric")
+});
+
+// The WAL redo of each tenant, as sums and counts rather than histograms to keep the number of
+// series down. The timeline_id label is empty, unless wal_redo_metrics_per_timeline is set.
+
+static WAL_REDO_TIME_SUM_PER_TENANT: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "pageserver_tenant_wal_redo_seconds_sum",
+        "Total time spent applying WAL records to reconstruct pages, per tenant",
+        &["tenant_id", "timeline_id"]
+    )
+    .expect("failed to define a metric")
+});
+
+static WAL_REDO_COUNT_PER_TENANT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_tenant_wal_redo_seconds_count",
+        "Number of WAL redo requests, per tenant",
+        &["tenant_id", "timeline_id"]
+    )
+    .expect("failed to define a metric")
+});
+
+static WAL_REDO_WAIT_TIME_SUM_PER_TENANT: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "pageserver_tenant_wal_redo_wait_seconds_sum",
+        "Total time spent waiting for access to the WAL redo process, per tenant",
+        &["tenant_id", "timeline_id"]
+    )
+    .expect("failed to define a metric")
+});
+
+static WAL_REDO_RECORDS_PER_TENANT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_tenant_wal_redo_records_total",
+        "Number of WAL records applied to reconstruct pages, per tenant",
+        &["tenant_id", "timeline_id"]
+    )
+    .expect("failed to define a metric")
+});
+
+static WAL_REDO_BYTES_PER_TENANT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_tenant_wal_redo_bytes_total",
+        "Bytes of Postgres WAL records applied to reconstruct pages, per tenant",
+        &["tenant_id", "timeline_id"]
+    )
+    .expect("failed to define a metric")
+});
+
+// The processes are shared by the timelines of the tenant, so this one has no timeline_id.
+pub static WAL_REDO_CPU_SECONDS_PER_TENANT: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "pageserver_tenant_wal_redo_cpu_seconds_total",
+        "User and system CPU time spent by the WAL redo processes, per tenant",
+        &["tenant_id"]
+    )
+    .expect("failed to define a metric")
+});
+
 /// Similar to [`prometheus::HistogramTimer`] but does not record on drop.
 pub struct StorageTimeMetricsTimer {
     metrics: StorageTimeMetrics,
@@ -882,6 +1687,175 @@ impl StorageTimeMetrics {
     }
 }
 
+/// The WAL ingested by a timeline, by database, for the top [`MAX_INGEST_DATABASES`].
+#[derive(Debug)]
+pub struct WalIngestDatabaseMetrics {
+    tenant_id: String,
+    timeline_id: String,
+    databases: Mutex<IngestDatabases>,
+}
+
+#[derive(Debug)]
+struct IngestDatabases {
+    /// The databases counted on their own.
+    top: HashSet<u32>,
+    /// The bytes ingested by each database since the last ranking.
+    recent: HashMap<u32, u64>,
+    rank_at: Instant,
+    /// The bytes and records counters, by database oid, `None` for the databases out of `top`.
+    series: HashMap<Option<u32>, (IntCounter, IntCounter)>,
+}
+
+impl WalIngestDatabaseMetrics {
+    fn new(tenant_id: &str, timeline_id: &str) -> Self {
+        WalIngestDatabaseMetrics {
+            tenant_id: tenant_id.to_string(),
+            timeline_id: timeline_id.to_string(),
+            databases: Mutex::new(IngestDatabases {
+                top: HashSet::new(),
+                recent: HashMap::new(),
+                rank_at: Instant::now() + INGEST_DATABASES_RANK_PERIOD,
+                series: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Count an ingested record of `bytes` that modifies the database `dboid`.
+    pub fn inc(&self, dboid: u32, bytes: u64) {
+        let mut databases = self.databases.lock().unwrap();
+        if Instant::now() >= databases.rank_at {
+            self.rank(&mut databases);
+        }
+        *databases.recent.entry(dboid).or_default() += bytes;
+        // Until the next ranking, the first databases seen take the free places
+        if databases.top.len() < MAX_INGEST_DATABASES {
+            databases.top.insert(dboid);
+        }
+        let database = databases.top.contains(&dboid).then_some(dboid);
+        let (bytes_counter, records_counter) =
+            databases.series.entry(database).or_insert_with(|| {
+                let database_oid = database_oid_label(database);
+                let labels = [&self.tenant_id, &self.timeline_id, &database_oid];
+                let bytes_counter = WAL_INGEST_DATABASE_BYTES
+                    .get_metric_with_label_values(&labels.map(String::as_str))
+                    .unwrap();
+                let records_counter = WAL_INGEST_DATABASE_RECORDS
+                    .get_metric_with_label_values(&labels.map(String::as_str))
+                    .unwrap();
+                (bytes_counter, records_counter)
+            });
+        bytes_counter.inc_by(bytes);
+        records_counter.inc();
+    }
+
+    /// Make the databases that ingested the most WAL since the last ranking the top ones, and
+    /// remove the series of the databases that drop out of it.
+    fn rank(&self, databases: &mut IngestDatabases) {
+        let mut recent = databases.recent.drain().collect::<Vec<_>>();
+        recent.sort_unstable_by_key(|(_, bytes)| std::cmp::Reverse(*bytes));
+        databases.top = recent
+            .into_iter()
+            .take(MAX_INGEST_DATABASES)
+            .map(|(dboid, _)| dboid)
+            .collect();
+        databases.rank_at = Instant::now() + INGEST_DATABASES_RANK_PERIOD;
+
+        let top = &databases.top;
+        databases.series.retain(|database, _| match database {
+            Some(dboid) if !top.contains(dboid) => {
+                self.remove_series(*database);
+                false
+            }
+            _ => true,
+        });
+    }
+
+    fn remove_series(&self, database: Option<u32>) {
+        let database_oid = database_oid_label(database);
+        let labels = [&self.tenant_id, &self.timeline_id, &database_oid];
+        let _ = WAL_INGEST_DATABASE_BYTES.remove_label_values(&labels.map(String::as_str));
+        let _ = WAL_INGEST_DATABASE_RECORDS.remove_label_values(&labels.map(String::as_str));
+    }
+}
+
+/// The WAL redo requests of a timeline, counted for its tenant, or for the timeline itself with
+/// `wal_redo_metrics_per_timeline`.
+#[derive(Debug)]
+pub struct WalRedoTenantMetrics {
+    tenant_id: String,
+    /// Empty if the requests are counted for the tenant.
+    timeline_id: String,
+    time_sum: Counter,
+    count: IntCounter,
+    wait_time_sum: Counter,
+    records: IntCounter,
+    bytes: IntCounter,
+}
+
+impl WalRedoTenantMetrics {
+    fn new(tenant_id: &str, timeline_id: &str, per_timeline: bool) -> Self {
+        let timeline_id = if per_timeline { timeline_id } else { "" };
+        let labels = [tenant_id, timeline_id];
+        WalRedoTenantMetrics {
+            tenant_id: tenant_id.to_string(),
+            timeline_id: timeline_id.to_string(),
+            time_sum: WAL_REDO_TIME_SUM_PER_TENANT
+                .get_metric_with_label_values(&labels)
+                .unwrap(),
+            count: WAL_REDO_COUNT_PER_TENANT
+                .get_metric_with_label_values(&labels)
+                .unwrap(),
+            wait_time_sum: WAL_REDO_WAIT_TIME_SUM_PER_TENANT
+                .get_metric_with_label_values(&labels)
+                .unwrap(),
+            records: WAL_REDO_RECORDS_PER_TENANT
+                .get_metric_with_label_values(&labels)
+                .unwrap(),
+            bytes: WAL_REDO_BYTES_PER_TENANT
+                .get_metric_with_label_values(&labels)
+                .unwrap(),
+        }
+    }
+
+    /// Count a request of `records` records, `bytes` of them in Postgres records.
+    pub fn observe(&self, wait: Duration, execution: Duration, records: usize, bytes: usize) {
+        self.time_sum.inc_by(execution.as_secs_f64());
+        self.count.inc();
+        self.wait_time_sum.inc_by(wait.as_secs_f64());
+        self.records.inc_by(records as u64);
+        self.bytes.inc_by(bytes as u64);
+    }
+}
+
+fn remove_wal_redo_label_values(labels: &[&str; 2]) {
+    let _ = WAL_REDO_TIME_SUM_PER_TENANT.remove_label_values(labels);
+    let _ = WAL_REDO_COUNT_PER_TENANT.remove_label_values(labels);
+    let _ = WAL_REDO_WAIT_TIME_SUM_PER_TENANT.remove_label_values(labels);
+    let _ = WAL_REDO_RECORDS_PER_TENANT.remove_label_values(labels);
+    let _ = WAL_REDO_BYTES_PER_TENANT.remove_label_values(labels);
+}
+
+impl Drop for WalRedoTenantMetrics {
+    fn drop(&mut self) {
+        // The label set of the tenant is shared by its timelines, see remove_tenant_metrics
+        if !self.timeline_id.is_empty() {
+            remove_wal_redo_label_values(&[&self.tenant_id, &self.timeline_id]);
+        }
+    }
+}
+
+fn database_oid_label(database: Option<u32>) -> String {
+    database.map_or_else(|| "other".to_string(), |oid| oid.to_string())
+}
+
+impl Drop for WalIngestDatabaseMetrics {
+    fn drop(&mut self) {
+        for database in self.databases.get_mut().unwrap().series.keys() {
+            self.remove_series(*database);
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct TimelineMetrics {
     tenant_id: String,
@@ -897,6 +1871,8 @@ pub struct TimelineMetrics {
     pub last_record_gauge: IntGauge,
     pub wait_lsn_time_histo: Histogram,
     pub resident_physical_size_gauge: UIntGauge,
+    pub layer_count_watermark_gauge: IntGauge,
+    pub local_size_watermark_gauge: IntGauge,
     pub read_num_fs_layers: Histogram,
     /// copy of LayeredTimeline.current_logical_size
     pub current_logical_size_gauge: UIntGauge,
@@ -904,6 +1880,8 @@ pub struct TimelineMetrics {
     pub persistent_bytes_written: IntCounter,
     pub evictions: IntCounter,
     pub evictions_with_low_residence_duration: std::sync::RwLock<EvictionsWithLowResidenceDuration>,
+    pub wal_ingest_databases: WalIngestDatabaseMetrics,
+    pub wal_redo: WalRedoTenantMetrics,
 }
 
 impl TimelineMetrics {
@@ -911,6 +1889,7 @@ impl TimelineMetrics {
         tenant_id: &TenantId,
         timeline_id: &TimelineId,
         evictions_with_low_residence_duration_builder: EvictionsWithLowResidenceDurationBuilder,
+        wal_redo_per_timeline: bool,
     ) -> Self {
         let tenant_id = tenant_id.to_string();
         let timeline_id = timeline_id.to_string();
@@ -943,6 +1922,12 @@ impl TimelineMetrics {
         let resident_physical_size_gauge = RESIDENT_PHYSICAL_SIZE
             .get_metric_with_label_values(&[&tenant_id, &timeline_id])
             .unwrap();
+        let layer_count_watermark_gauge = TIMELINE_WATERMARK_LEVEL
+            .get_metric_with_label_values(&[&tenant_id, &timeline_id, "layer_count"])
+            .unwrap();
+        let local_size_watermark_gauge = TIMELINE_WATERMARK_LEVEL
+            .get_metric_with_label_values(&[&tenant_id, &timeline_id, "local_size"])
+            .unwrap();
         let current_logical_size_gauge = CURRENT_LOGICAL_SIZE
             .get_metric_with_label_values(&[&tenant_id, &timeline_id])
             .unwrap();
@@ -960,6 +1945,8 @@ impl TimelineMetrics {
             .unwrap();
         let evictions_with_low_residence_duration =
             evictions_with_low_residence_duration_builder.build(&tenant_id, &timeline_id);
+        let wal_ingest_databases = WalIngestDatabaseMetrics::new(&tenant_id, &timeline_id);
+        let wal_redo = WalRedoTenantMetrics::new(&tenant_id, &timeline_id, wal_redo_per_timeline);
 
         TimelineMetrics {
             tenant_id,
@@ -975,6 +1962,8 @@ impl TimelineMetrics {
             last_record_gauge,
             wait_lsn_time_histo,
             resident_physical_size_gauge,
+            layer_count_watermark_gauge,
+            local_size_watermark_gauge,
             current_logical_size_gauge,
             num_persistent_files_created,
             persistent_bytes_written,
@@ -983,6 +1972,8 @@ impl TimelineMetrics {
                 evictions_with_low_residence_duration,
             ),
             read_num_fs_layers,
+            wal_ingest_databases,
+            wal_redo,
         }
     }
 }
@@ -1000,6 +1991,10 @@ impl Drop for TimelineMetrics {
         let _ = PERSISTENT_BYTES_WRITTEN.remove_label_values(&[tenant_id, timeline_id]);
         let _ = EVICTIONS.remove_label_values(&[tenant_id, timeline_id]);
         let _ = READ_NUM_FS_LAYERS.remove_label_values(&[tenant_id, timeline_id]);
+        for resource in WATERMARK_RESOURCES {
+            let _ =
+                TIMELINE_WATERMARK_LEVEL.remove_label_values(&[tenant_id, timeline_id, resource]);
+        }
 
         self.evictions_with_low_residence_duration
             .write()
@@ -1031,15 +2026,114 @@ pub fn remove_tenant_metrics(tenant_id: &TenantId) {
     for state in TenantState::VARIANTS {
         let _ = TENANT_STATE_METRIC.remove_label_values(&[&tid, state]);
     }
+    remove_wal_redo_label_values(&[&tid, ""]);
+    let _ = WAL_REDO_CPU_SECONDS_PER_TENANT.remove_label_values(&[&tid]);
+}
+
+/// A metric family whose label sets include `tenant_id`, and possibly
+/// `timeline_id`. Object-safe, so that families of different metric types can
+/// be listed in [`TENANT_SCOPED_METRIC_FAMILIES`].
+trait TenantScopedMetricFamily: Collector {
+    fn remove_label_set(&self, labels: &HashMap<&str, &str>);
+}
+
+impl<T: MetricVecBuilder> TenantScopedMetricFamily for MetricVec<T> {
+    fn remove_label_set(&self, labels: &HashMap<&str, &str>) {
+        let _ = self.remove(labels);
+    }
+}
+
+/// All metric families with a `tenant_id` label.
+///
+/// The label sets are normally removed when the owning [`TimelineMetrics`],
+/// [`RemoteTimelineClientMetrics`] or `Tenant` is dropped. That doesn't happen
+/// as long as some task or connection still holds a reference, so detach and
+/// timeline deletion also remove them explicitly through this list. A new family
+/// with a `tenant_id` label must be added here, the `no_leaked_label_sets` test
+/// checks that.
+static TENANT_SCOPED_METRIC_FAMILIES: Lazy<Vec<&'static dyn TenantScopedMetricFamily>> =
+    Lazy::new(|| {
+        vec![
+            &*STORAGE_TIME_SUM_PER_TIMELINE,
+            &*STORAGE_TIME_COUNT_PER_TIMELINE,
+            &*READ_NUM_FS_LAYERS,
+            &*GET_RECONSTRUCT_DATA_TIME,
+            &*WAIT_LSN_TIME,
+            &*LAST_RECORD_LSN,
+            &*RESIDENT_PHYSICAL_SIZE,
+            &*TIMELINE_WATERMARK_LEVEL,
+            &*REMOTE_PHYSICAL_SIZE,
+            &*CURRENT_LOGICAL_SIZE,
+            &*TENANT_STATE_METRIC,
+            &*TENANT_SYNTHETIC_SIZE_METRIC,
+            &*NUM_PERSISTENT_FILES_CREATED,
+            &*PERSISTENT_BYTES_WRITTEN,
+            &*EVICTIONS,
+            &*WAL_INGEST_DATABASE_BYTES,
+            &*WAL_INGEST_DATABASE_RECORDS,
+            &*EVICTIONS_WITH_LOW_RESIDENCE_DURATION,
+            &*STORAGE_IO_TIME,
+            &*STORAGE_IO_SIZE,
+            &*SMGR_QUERY_TIME,
+            &*REMOTE_TIMELINE_CLIENT_CALLS_UNFINISHED_GAUGE,
+            &*REMOTE_TIMELINE_CLIENT_CALLS_STARTED_HIST,
+            &*REMOTE_TIMELINE_CLIENT_BYTES_STARTED_COUNTER,
+            &*REMOTE_TIMELINE_CLIENT_BYTES_FINISHED_COUNTER,
+            &*REMOTE_UPLOAD_QUEUE_DEPTH,
+            &*REMOTE_UPLOAD_QUEUE_OLDEST_OP,
+            &*REMOTE_LAST_INDEX_UPLOAD,
+            &*REMOTE_OPERATION_TIME,
+            &*REMOTE_STORAGE_REQUESTS,
+            &*REMOTE_STORAGE_REQUEST_BYTES,
+            &*WAL_REDO_TIME_SUM_PER_TENANT,
+            &*WAL_REDO_COUNT_PER_TENANT,
+            &*WAL_REDO_WAIT_TIME_SUM_PER_TENANT,
+            &*WAL_REDO_RECORDS_PER_TENANT,
+            &*WAL_REDO_BYTES_PER_TENANT,
+            &*WAL_REDO_CPU_SECONDS_PER_TENANT,
+        ]
+    });
+
+/// Remove the label sets of the given tenant from all per-tenant and
+/// per-timeline metric families. Called when the tenant is detached or ignored.
+pub fn remove_tenant_label_sets(tenant_id: &TenantId) {
+    remove_label_sets_matching(&tenant_id.to_string(), None);
+}
+
+/// Remove the label sets of the given timeline from all per-timeline metric
+/// families. Called when the timeline is deleted.
+pub fn remove_timeline_label_sets(tenant_id: &TenantId, timeline_id: &TimelineId) {
+    remove_label_sets_matching(&tenant_id.to_string(), Some(&timeline_id.to_string()));
+}
+
+fn remove_label_sets_matching(tenant_id: &str, timeline_id: Option<&str>) {
+    for family in TENANT_SCOPED_METRIC_FAMILIES.iter() {
+        for metric_family in family.collect() {
+            for metric in metric_family.get_metric() {
+                let labels: HashMap<&str, &str> = metric
+                    .get_label()
+                    .iter()
+                    .map(|pair| (pair.get_name(), pair.get_value()))
+                    .collect();
+                if labels.get("tenant_id") != Some(&tenant_id) {
+                    continue;
+                }
+                if timeline_id.is_some() && labels.get("timeline_id").copied() != timeline_id {
+                    continue;
+                }
+                family.remove_label_set(&labels);
+            }
+        }
+    }
 }
 
 use futures::Future;
 use pin_project_lite::pin_project;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 pub struct RemoteTimelineClientMetrics {
     tenant_id: String,
@@ -1050,6 +2144,9 @@ pub struct RemoteTimelineClientMetrics {
     calls_started_hist: Mutex<HashMap<(&'static str, &'static str), Histogram>>,
     bytes_started_counter: Mutex<HashMap<(&'static str, &'static str), IntCounter>>,
     bytes_finished_counter: Mutex<HashMap<(&'static str, &'static str), IntCounter>>,
+    upload_queue_gauges: Mutex<Option<UploadQueueGauges>>,
+    /// Per tenant, shared with the other timelines of the tenant.
+    remote_request_counters: Mutex<HashMap<RequestKind, (IntCounter, IntCounter)>>,
 }
 
 impl RemoteTimelineClientMetrics {
@@ -1062,6 +2159,8 @@ impl RemoteTimelineClientMetrics {
             calls_started_hist: Mutex::new(HashMap::default()),
             bytes_started_counter: Mutex::new(HashMap::default()),
             bytes_finished_counter: Mutex::new(HashMap::default()),
+            upload_queue_gauges: Mutex::new(None),
+            remote_request_counters: Mutex::new(HashMap::default()),
             remote_physical_size_gauge: Mutex::new(None),
         }
     }
@@ -1179,6 +2278,49 @@ impl RemoteTimelineClientMetrics {
         });
         metric.clone()
     }
+
+    /// Update the gauges of the upload queue, after it has changed.
+    pub(crate) fn set_upload_queue_state(
+        &self,
+        depth: usize,
+        oldest_op_queued_at: Option<SystemTime>,
+        last_index_upload_at: SystemTime,
+    ) {
+        let mut guard = self.upload_queue_gauges.lock().unwrap();
+        let gauges = guard.get_or_insert_with(|| {
+            let labels = [self.tenant_id.as_str(), self.timeline_id.as_str()];
+            UploadQueueGauges {
+                depth: REMOTE_UPLOAD_QUEUE_DEPTH
+                    .get_metric_with_label_values(&labels)
+                    .unwrap(),
+                oldest_op: REMOTE_UPLOAD_QUEUE_OLDEST_OP
+                    .get_metric_with_label_values(&labels)
+                    .unwrap(),
+                last_index_upload: REMOTE_LAST_INDEX_UPLOAD
+                    .get_metric_with_label_values(&labels)
+                    .unwrap(),
+            }
+        });
+        gauges.depth.set(depth as i64);
+        gauges
+            .oldest_op
+            .set(oldest_op_queued_at.map_or(0, unix_timestamp));
+        gauges
+            .last_index_upload
+            .set(unix_timestamp(last_index_upload_at));
+    }
+}
+
+/// See [`RemoteTimelineClientMetrics::set_upload_queue_state`].
+struct UploadQueueGauges {
+    depth: IntGauge,
+    oldest_op: IntGauge,
+    last_index_upload: IntGauge,
+}
+
+fn unix_timestamp(at: SystemTime) -> i64 {
+    at.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs() as i64)
 }
 
 #[cfg(test)]
@@ -1202,6 +2344,17 @@ impl RemoteTimelineClientMetrics {
         let key = (file_kind.as_str(), op_kind.as_str());
         guard.get(&key).map(|counter| counter.get())
     }
+
+    /// The upload queue depth, oldest operation and last index upload timestamps.
+    pub fn get_upload_queue_state(&self) -> (i64, i64, i64) {
+        let guard = self.upload_queue_gauges.lock().unwrap();
+        let gauges = guard.as_ref().expect("upload queue is initialized");
+        (
+            gauges.depth.get(),
+            gauges.oldest_op.get(),
+            gauges.last_index_upload.get(),
+        )
+    }
 }
 
 /// See [`RemoteTimelineClientMetrics::call_begin`].
@@ -1322,6 +2475,8 @@ impl Drop for RemoteTimelineClientMetrics {
             calls_started_hist,
             bytes_started_counter,
             bytes_finished_counter,
+            upload_queue_gauges,
+            remote_request_counters: _,
         } = self;
         for ((a, b, c), _) in remote_operation_time.get_mut().unwrap().drain() {
             let _ = REMOTE_OPERATION_TIME.remove_label_values(&[tenant_id, timeline_id, a, b, c]);
@@ -1362,9 +2517,47 @@ impl Drop for RemoteTimelineClientMetrics {
             let _ = remote_physical_size_gauge; // use to avoid 'unused' warning in desctructuring above
             let _ = REMOTE_PHYSICAL_SIZE.remove_label_values(&[tenant_id, timeline_id]);
         }
+        if upload_queue_gauges.get_mut().unwrap().take().is_some() {
+            for family in [
+                &*REMOTE_UPLOAD_QUEUE_DEPTH,
+                &*REMOTE_UPLOAD_QUEUE_OLDEST_OP,
+                &*REMOTE_LAST_INDEX_UPLOAD,
+            ] {
+                let _ = family.remove_label_values(&[tenant_id, timeline_id]);
+            }
+        }
     }
 }
 
+/// Counts the remote storage requests of the timeline for its tenant, see
+/// [`tenant_remote_storage_costs`]. Their label sets stay after the timeline is gone, until the
+/// tenant is detached.
+impl RequestObserver for RemoteTimelineClientMetrics {
+    fn observe_requests(&self, kind: RequestKind, requests: u64, bytes: u64) {
+        let mut guard = self.remote_request_counters.lock().unwrap();
+        let (requests_counter, bytes_counter) = guard.entry(kind).or_insert_with(|| {
+            let labels = [self.tenant_id.as_str(), request_kind_label(kind)];
+            (
+                REMOTE_STORAGE_REQUESTS
+                    .get_metric_with_label_values(&labels)
+                    .unwrap(),
+                REMOTE_STORAGE_REQUEST_BYTES
+                    .get_metric_with_label_values(&labels)
+                    .unwrap(),
+            )
+        });
+        requests_counter.inc_by(requests);
+        bytes_counter.inc_by(bytes);
+    }
+}
+
+/// Observe `value` in `histogram`, with the trace of the current span as the exemplar of its
+/// bucket. There is a trace when the pageserver exports them over OTLP, and the exemplars are
+/// only in the OpenMetrics exposition, see [`metrics::exemplars`].
+pub(crate) fn observe_traced(histogram: &Histogram, value: f64) {
+    metrics::exemplars::observe_with_exemplar(histogram, value, tracing_utils::current_trace_id());
+}
+
 /// Wrapper future that measures the time spent by a remote storage operation,
 /// and records the time and success/failure as a prometheus metric.
 pub trait MeasureRemoteOp: Sized {
@@ -1414,9 +2607,8 @@ impl<F: Future<Output = Result<O, E>>, O, E> Future for MeasuredRemoteOp<F> {
         if let Poll::Ready(ref res) = poll_result {
             let duration = this.start.elapsed();
             let status = if res.is_ok() { &"success" } else { &"failure" };
-            this.metrics
-                .remote_operation_time(this.file_kind, this.op, status)
-                .observe(duration.as_secs_f64());
+            let histogram = this.metrics.remote_operation_time(this.file_kind, this.op, status);
+            observe_traced(&histogram, duration.as_secs_f64());
         }
         poll_result
     }
@@ -1431,7 +2623,371 @@ pub fn preinitialize_metrics() {
     // Same as above for this metric, but, it's a Vec-type metric for which we don't know all the labels.
     BACKGROUND_LOOP_PERIOD_OVERRUN_COUNT.reset();
 
+    // Initialize the failure counter of each job, so that alerts on it increasing work
+    // from the first failure on.
+    for job in crate::background_jobs::BackgroundJobKind::VARIANTS {
+        BACKGROUND_JOB_FAILURES.with_label_values(&[job]);
+    }
+
     // Python tests need these.
     MATERIALIZED_PAGE_CACHE_HIT_DIRECT.get();
     MATERIALIZED_PAGE_CACHE_HIT.get();
+
+    // Must be registered before the first gather(), see `metrics::register_internal`.
+    #[cfg(tokio_unstable)]
+    metrics::register_internal(Box::new(tokio_runtime::TokioRuntimeCollector::new()))
+        .expect("failed to register tokio runtime metrics");
+    TOKIO_RUNTIME_METRICS_ENABLED.set(i64::from(cfg!(tokio_unstable)));
+}
+
+/// Whether the build has the metrics of `tokio_runtime`, so that their absence can be told
+/// apart from a scrape problem.
+static TOKIO_RUNTIME_METRICS_ENABLED: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "pageserver_tokio_runtime_metrics_enabled",
+        "1 if the pageserver exports the pageserver_tokio_runtime_* metrics, 0 if it was built \
+         without --cfg tokio_unstable, which they need"
+    )
+    .expect("failed to define a metric")
+});
+
+/// Metrics of our tokio runtimes, to make async starvation visible, e.g. when
+/// runtime workers get blocked by synchronous WAL redo.
+///
+/// tokio only has the runtime metrics with `--cfg tokio_unstable`, which the
+/// Makefile and CI add to `RUSTFLAGS`. A build without it has none of these
+/// metrics, and `pageserver_tokio_runtime_metrics_enabled` is 0.
+#[cfg(tokio_unstable)]
+mod tokio_runtime {
+    use metrics::core::{Collector, Desc};
+    use metrics::proto::MetricFamily;
+    use metrics::{opts, CounterVec, IntGaugeVec};
+
+    use crate::task_mgr::{
+        BACKGROUND_RUNTIME, COMPUTE_REQUEST_RUNTIME, MGMT_REQUEST_RUNTIME, WALRECEIVER_RUNTIME,
+    };
+
+    pub struct TokioRuntimeCollector {
+        descs: Vec<Desc>,
+        workers: IntGaugeVec,
+        blocking_threads: IntGaugeVec,
+        idle_blocking_threads: IntGaugeVec,
+        blocking_queue_depth: IntGaugeVec,
+        injection_queue_depth: IntGaugeVec,
+        worker_busy_seconds: CounterVec,
+        worker_local_queue_depth: IntGaugeVec,
+    }
+
+    impl TokioRuntimeCollector {
+        pub fn new() -> Self {
+            let mut descs = Vec::new();
+
+            let workers = IntGaugeVec::new(
+                opts!("pageserver_tokio_runtime_workers", "Number of worker threads"),
+                &["runtime"],
+            )
+            .unwrap();
+            descs.extend(workers.desc().into_iter().cloned());
+
+            let blocking_threads = IntGaugeVec::new(
+                opts!(
+                    "pageserver_tokio_runtime_blocking_threads",
+                    "Number of threads in the blocking pool, including idle ones"
+                ),
+                &["runtime"],
+            )
+            .unwrap();
+            descs.extend(blocking_threads.desc().into_iter().cloned());
+
+            let idle_blocking_threads = IntGaugeVec::new(
+                opts!(
+                    "pageserver_tokio_runtime_idle_blocking_threads",
+                    "Number of idle threads in the blocking pool"
+                ),
+                &["runtime"],
+            )
+            .unwrap();
+            descs.extend(idle_blocking_threads.desc().into_iter().cloned());
+
+            let blocking_queue_depth = IntGaugeVec::new(
+                opts!(
+                    "pageserver_tokio_runtime_blocking_queue_depth",
+                    "Number of spawn_blocking tasks waiting for a blocking pool thread"
+                ),
+                &["runtime"],
+            )
+            .unwrap();
+            descs.extend(blocking_queue_depth.desc().into_iter().cloned());
+
+            let injection_queue_depth = IntGaugeVec::new(
+                opts!(
+                    "pageserver_tokio_runtime_injection_queue_depth",
+                    "Number of tasks scheduled from outside the runtime, waiting for a worker"
+                ),
+                &["runtime"],
+            )
+            .unwrap();
+            descs.extend(injection_queue_depth.desc().into_iter().cloned());
+
+            let worker_busy_seconds = CounterVec::new(
+                opts!(
+                    "pageserver_tokio_runtime_worker_busy_seconds_total",
+                    "Time the worker thread spent polling tasks. A worker that is busy \
+                     all the time is likely blocked by synchronous code"
+                ),
+                &["runtime", "worker"],
+            )
+            .unwrap();
+            descs.extend(worker_busy_seconds.desc().into_iter().cloned());
+
+            let worker_local_queue_depth = IntGaugeVec::new(
+                opts!(
+                    "pageserver_tokio_runtime_worker_local_queue_depth",
+                    "Number of tasks in the worker's local run queue"
+                ),
+                &["runtime", "worker"],
+            )
+            .unwrap();
+            descs.extend(worker_local_queue_depth.desc().into_iter().cloned());
+
+            Self {
+                descs,
+                workers,
+                blocking_threads,
+                idle_blocking_threads,
+                blocking_queue_depth,
+                injection_queue_depth,
+                worker_busy_seconds,
+                worker_local_queue_depth,
+            }
+        }
+    }
+
+    impl Collector for TokioRuntimeCollector {
+        fn desc(&self) -> Vec<&Desc> {
+            self.descs.iter().collect()
+        }
+
+        fn collect(&self) -> Vec<MetricFamily> {
+            let runtimes = [
+                ("compute_request", &*COMPUTE_REQUEST_RUNTIME),
+                ("mgmt_request", &*MGMT_REQUEST_RUNTIME),
+                ("walreceiver", &*WALRECEIVER_RUNTIME),
+                ("background", &*BACKGROUND_RUNTIME),
+            ];
+
+            // The busy time is cumulative in tokio, so just report it as is.
+            self.worker_busy_seconds.reset();
+
+            for (name, runtime) in runtimes {
+                let rt_metrics = runtime.handle().metrics();
+                let num_workers = rt_metrics.num_workers();
+
+                self.workers
+                    .with_label_values(&[name])
+                    .set(num_workers as i64);
+                self.blocking_threads
+                    .with_label_values(&[name])
+                    .set(rt_metrics.num_blocking_threads() as i64);
+                self.idle_blocking_threads
+                    .with_label_values(&[name])
+                    .set(rt_metrics.num_idle_blocking_threads() as i64);
+                self.blocking_queue_depth
+                    .with_label_values(&[name])
+                    .set(rt_metrics.blocking_queue_depth() as i64);
+                self.injection_queue_depth
+                    .with_label_values(&[name])
+                    .set(rt_metrics.injection_queue_depth() as i64);
+
+                for worker in 0..num_workers {
+                    let worker_label = worker.to_string();
+                    let labels = &[name, worker_label.as_str()];
+                    self.worker_busy_seconds
+                        .with_label_values(labels)
+                        .inc_by(rt_metrics.worker_total_busy_duration(worker).as_secs_f64());
+                    self.worker_local_queue_depth
+                        .with_label_values(labels)
+                        .set(rt_metrics.worker_local_queue_depth(worker) as i64);
+                }
+            }
+
+            let mut mfs = Vec::new();
+            mfs.extend(self.workers.collect());
+            mfs.extend(self.blocking_threads.collect());
+            mfs.extend(self.idle_blocking_threads.collect());
+            mfs.extend(self.blocking_queue_depth.collect());
+            mfs.extend(self.injection_queue_depth.collect());
+            mfs.extend(self.worker_busy_seconds.collect());
+            mfs.extend(self.worker_local_queue_depth.collect());
+            mfs
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    /// Label sets of all gathered metrics that have the given tenant_id, as
+    /// (family name, timeline_id) pairs.
+    fn gather_tenant_label_sets(tenant_id: &TenantId) -> Vec<(String, Option<String>)> {
+        let tenant_id = tenant_id.to_string();
+        let mut found = Vec::new();
+        for family in metrics::gather() {
+            for metric in family.get_metric() {
+                let label = |name: &str| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .find(|pair| pair.get_name() == name)
+                        .map(|pair| pair.get_value().to_string())
+                };
+                if label("tenant_id").as_ref() == Some(&tenant_id) {
+                    found.push((family.get_name().to_string(), label("timeline_id")));
+                }
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn tenant_remote_storage_costs() {
+        let tenant_id = TenantId::generate();
+        let remote_metrics = RemoteTimelineClientMetrics::new(&tenant_id, &TimelineId::generate());
+        remote_metrics.observe_requests(RequestKind::Put, 2000, 1 << 30);
+        remote_metrics.observe_requests(RequestKind::Get, 1000, 1 << 31);
+        remote_metrics.observe_requests(RequestKind::List, 1, 0);
+        // Another timeline of the tenant
+        let remote_metrics = RemoteTimelineClientMetrics::new(&tenant_id, &TimelineId::generate());
+        remote_metrics.observe_requests(RequestKind::List, 1, 0);
+
+        let prices = RemoteStoragePrices {
+            download_per_gib: 0.09,
+            ..RemoteStoragePrices::default()
+        };
+        let costs = super::tenant_remote_storage_costs(&prices);
+        let cost = costs
+            .iter()
+            .find(|cost| cost.tenant_id == tenant_id)
+            .expect("tenant has a cost");
+        assert_eq!(cost.put_requests, 2000);
+        assert_eq!(cost.get_requests, 1000);
+        assert_eq!(cost.delete_requests, 0);
+        assert_eq!(cost.list_requests, 2);
+        assert_eq!(cost.uploaded_bytes, 1 << 30);
+        assert_eq!(cost.downloaded_bytes, 1 << 31);
+        let expected = 2.0 * 0.005 + 0.0004 + 2.0 * 0.005 / 1000.0 + 2.0 * 0.09;
+        assert!((cost.estimated_cost - expected).abs() < 1e-9);
+
+        remove_tenant_label_sets(&tenant_id);
+    }
+
+    #[test]
+    fn wal_ingest_database_top_n() {
+        let metrics = WalIngestDatabaseMetrics::new(
+            &TenantId::generate().to_string(),
+            &TimelineId::generate().to_string(),
+        );
+        let top = MAX_INGEST_DATABASES as u32;
+
+        // Before the first ranking, the first databases seen are counted on their own
+        for dboid in 0..top {
+            metrics.inc(dboid, u64::from(dboid) + 1);
+        }
+        let busy = top;
+        metrics.inc(busy, 1000);
+        {
+            let databases = metrics.databases.lock().unwrap();
+            assert_eq!(databases.series[&None].0.get(), 1000);
+            assert!(!databases.series.contains_key(&Some(busy)));
+        }
+
+        // The busy database takes the place of the least busy one at the next ranking
+        metrics.databases.lock().unwrap().rank_at = Instant::now();
+        metrics.inc(busy, 10);
+        let databases = metrics.databases.lock().unwrap();
+        assert_eq!(databases.series[&Some(busy)].0.get(), 10);
+        assert!(!databases.series.contains_key(&Some(0)));
+        assert_eq!(databases.series[&None].0.get(), 1000);
+        assert_eq!(databases.series.len(), MAX_INGEST_DATABASES + 1);
+    }
+
+    #[test]
+    fn no_leaked_label_sets() {
+        let tenant_id = TenantId::generate();
+        let deleted_timeline_id = TimelineId::generate();
+        let remaining_timeline_id = TimelineId::generate();
+
+        // Keep the handles alive, like a Timeline that is still referenced by some task.
+        let mut handles = Vec::new();
+        for timeline_id in [&deleted_timeline_id, &remaining_timeline_id] {
+            // The WAL redo of one timeline is counted apart, the other for the tenant
+            let timeline_metrics = TimelineMetrics::new(
+                &tenant_id,
+                timeline_id,
+                EvictionsWithLowResidenceDurationBuilder::new("test", Duration::from_secs(10)),
+                timeline_id == &deleted_timeline_id,
+            );
+            timeline_metrics.wal_ingest_databases.inc(5, 1);
+            let remote_metrics = RemoteTimelineClientMetrics::new(&tenant_id, timeline_id);
+            remote_metrics.remote_physical_size_gauge().set(1);
+            remote_metrics
+                .remote_operation_time(&RemoteOpFileKind::Layer, &RemoteOpKind::Upload, "success")
+                .observe(1.0);
+            let _ = remote_metrics.call_begin(
+                &RemoteOpFileKind::Layer,
+                &RemoteOpKind::Upload,
+                RemoteTimelineClientMetricsCallTrackSize::Bytes(1),
+            );
+            remote_metrics.observe_requests(RequestKind::Put, 1, 1);
+            remote_metrics.set_upload_queue_state(1, Some(SystemTime::now()), SystemTime::now());
+            handles.push((timeline_metrics, remote_metrics));
+        }
+        TENANT_SYNTHETIC_SIZE_METRIC
+            .with_label_values(&[&tenant_id.to_string()])
+            .set(1);
+        WAL_REDO_CPU_SECONDS_PER_TENANT
+            .with_label_values(&[&tenant_id.to_string()])
+            .inc_by(1.0);
+
+        // Every family with a tenant_id label must be known to the facade.
+        let known: HashSet<String> = TENANT_SCOPED_METRIC_FAMILIES
+            .iter()
+            .flat_map(|family| family.desc().into_iter().map(|desc| desc.fq_name.clone()))
+            .collect();
+        for (family, _) in gather_tenant_label_sets(&tenant_id) {
+            assert!(
+                known.contains(&family),
+                "{family} has a tenant_id label but is missing from TENANT_SCOPED_METRIC_FAMILIES"
+            );
+        }
+
+        remove_timeline_label_sets(&tenant_id, &deleted_timeline_id);
+        let remaining = gather_tenant_label_sets(&tenant_id);
+        let deleted_timeline_id = deleted_timeline_id.to_string();
+        assert!(
+            !remaining
+                .iter()
+                .any(|(_, timeline_id)| timeline_id.as_ref() == Some(&deleted_timeline_id)),
+            "label sets of the deleted timeline leaked: {remaining:?}"
+        );
+        let remaining_timeline_id = remaining_timeline_id.to_string();
+        assert!(remaining
+            .iter()
+            .any(|(_, timeline_id)| timeline_id.as_ref() == Some(&remaining_timeline_id)));
+        assert!(remaining.iter().any(|(_, timeline_id)| timeline_id.is_none()));
+
+        remove_tenant_label_sets(&tenant_id);
+        let remaining = gather_tenant_label_sets(&tenant_id);
+        assert!(
+            remaining.is_empty(),
+            "label sets of the detached tenant leaked: {remaining:?}"
+        );
+
+        // Dropping the handles afterwards must not bring anything back.
+        drop(handles);
+        assert!(gather_tenant_label_sets(&tenant_id).is_empty());
+    }
 }
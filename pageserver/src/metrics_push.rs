@@ -0,0 +1,169 @@
+//!
+//! Periodically push the contents of the metrics registry to a Prometheus
+//! Pushgateway, for deployments where the pageserver cannot be scraped,
+//! e.g. because it runs behind NAT.
+//!
+//! The pushed metrics are the same as the ones served on `/metrics`. Every push
+//! replaces the previously pushed metrics of this pageserver, which is grouped
+//! by `job="pageserver"` and `instance=<node id>`.
+//!
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::Context;
+use metrics::{Encoder, TextEncoder};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::*;
+use utils::id::NodeId;
+
+use crate::background_jobs::{run_job, BackgroundJob, BackgroundJobKind};
+use crate::task_mgr;
+use crate::{exponential_backoff, DEFAULT_BASE_BACKOFF_SECONDS, DEFAULT_MAX_BACKOFF_SECONDS};
+
+const DEFAULT_HTTP_PUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of attempts to push the metrics per interval before waiting for the next one.
+const MAX_PUSH_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetricsPushConfig {
+    /// Base URL of the Pushgateway, e.g. `http://pushgateway:9091/`.
+    pub endpoint: Url,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    #[serde(default)]
+    pub basic_auth: Option<BasicAuth>,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: Option<String>,
+}
+
+/// The config may be logged, don't leak the password.
+impl fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BasicAuth")
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<hidden>"))
+            .finish()
+    }
+}
+
+/// Main loop of the metrics push task.
+pub async fn push_metrics_loop(config: &MetricsPushConfig, node_id: NodeId) -> anyhow::Result<()> {
+    let url = config
+        .endpoint
+        .join(&format!("metrics/job/pageserver/instance/{node_id}"))
+        .context("build pushgateway url")?;
+
+    let client = reqwest::ClientBuilder::new()
+        .timeout(DEFAULT_HTTP_PUSH_TIMEOUT)
+        .build()
+        .context("create http client")?;
+
+    info!(%url, interval = ?config.interval, "starting metrics push loop");
+
+    let job = MetricsPushJob {
+        config,
+        client,
+        url,
+    };
+    run_job(
+        BackgroundJobKind::MetricsPush,
+        job,
+        &task_mgr::shutdown_token(),
+    )
+    .await;
+    info!("metrics push loop received cancellation request");
+    Ok(())
+}
+
+struct MetricsPushJob<'a> {
+    config: &'a MetricsPushConfig,
+    client: reqwest::Client,
+    url: Url,
+}
+
+#[async_trait::async_trait]
+impl BackgroundJob for MetricsPushJob<'_> {
+    fn period(&self) -> Duration {
+        self.config.interval
+    }
+
+    async fn iteration(&mut self, _cancel: &CancellationToken) -> anyhow::Result<()> {
+        push_metrics_iteration(&self.client, &self.url, self.config.basic_auth.as_ref()).await;
+        Ok(())
+    }
+}
+
+/// Gather and push the metrics once, retrying failed attempts with a backoff.
+///
+/// Errors are logged, not returned, so that one failed push doesn't stop the loop.
+async fn push_metrics_iteration(client: &reqwest::Client, url: &Url, auth: Option<&BasicAuth>) {
+    let body = match tokio::task::spawn_blocking(encode_metrics).await {
+        Ok(Ok(body)) => body,
+        Ok(Err(e)) => {
+            error!("failed to encode metrics: {e:#}");
+            return;
+        }
+        Err(e) => {
+            error!("metrics encoding task failed: {e}");
+            return;
+        }
+    };
+
+    for attempt in 0..MAX_PUSH_ATTEMPTS {
+        if attempt > 0 {
+            tokio::select! {
+                _ = task_mgr::shutdown_watcher() => return,
+                _ = exponential_backoff(
+                    attempt,
+                    DEFAULT_BASE_BACKOFF_SECONDS,
+                    DEFAULT_MAX_BACKOFF_SECONDS,
+                ) => {}
+            }
+        }
+
+        let mut request = client
+            .put(url.clone())
+            .header(reqwest::header::CONTENT_TYPE, TextEncoder::new().format_type())
+            .body(body.clone());
+        if let Some(auth) = auth {
+            request = request.basic_auth(&auth.username, auth.password.as_ref());
+        }
+
+        match request.send().await {
+            Ok(res) if res.status().is_success() => {
+                trace!("pushed metrics");
+                return;
+            }
+            Ok(res) if res.status().is_client_error() => {
+                // Retrying won't help, e.g. bad credentials or a malformed URL.
+                error!(status = %res.status(), "metrics push endpoint refused the metrics");
+                return;
+            }
+            Ok(res) => {
+                warn!(attempt, status = %res.status(), "metrics push endpoint returned an error");
+            }
+            Err(e) => {
+                warn!(attempt, "failed to push metrics: {e}");
+            }
+        }
+    }
+
+    error!("giving up pushing metrics after {MAX_PUSH_ATTEMPTS} attempts, will retry next interval");
+}
+
+fn encode_metrics() -> anyhow::Result<Vec<u8>> {
+    // Gathering takes a lot of mutexes, hence this runs in spawn_blocking.
+    let metrics = metrics::gather();
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&metrics, &mut buffer)
+        .context("encode metrics")?;
+    Ok(buffer)
+}
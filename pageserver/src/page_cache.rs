@@ -35,10 +35,27 @@
 //! initialized it. If the guard is dropped without calling mark_valid(), the
 //! mapping is automatically removed and the slot is marked free.
 //!
+//! # Materialized pages
+//!
+//! The page images reconstructed with WAL redo are memorized, so that the
+//! repeated reads of a hot page skip the redo. The `materialized_page_cache_size`
+//! setting bounds how many slots they may take, so that they cannot push all the
+//! layer file pages out of the cache: once at the limit, a new materialized page
+//! evicts an older one, found with the same Clock algorithm as any other victim,
+//! and the sweep leaves the usage counts of the layer file pages alone.
+//!
+//! The `pageserver_page_cache_read_accesses_total`, `_read_hits_total` and
+//! `_read_misses_total` metrics with `key_kind="materialized_page"` count the
+//! lookups, hits and misses, and `pageserver_page_cache_evictions_total` the
+//! materialized pages evicted, by the budget or otherwise. The hits of the
+//! getpage@lsn requests themselves are in `pageserver_materialized_cache_hits_total`
+//! and `pageserver_materialized_cache_hits_direct_total`.
+//!
 
 use std::{
     collections::{hash_map::Entry, HashMap},
     convert::TryInto,
+    num::NonZeroUsize,
     sync::{
         atomic::{AtomicU8, AtomicUsize, Ordering},
         RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
@@ -62,8 +79,11 @@ const TEST_PAGE_CACHE_SIZE: usize = 50;
 ///
 /// Initialize the page cache. This must be called once at page server startup.
 ///
-pub fn init(size: usize) {
-    if PAGE_CACHE.set(PageCache::new(size)).is_err() {
+pub fn init(size: usize, materialized_size: Option<NonZeroUsize>) {
+    if PAGE_CACHE
+        .set(PageCache::new(size, materialized_size))
+        .is_err()
+    {
         panic!("page cache already initialized");
     }
 }
@@ -78,7 +98,7 @@ pub fn get() -> &'static PageCache {
     // page cache is usable in unit tests.
     //
     if cfg!(test) {
-        PAGE_CACHE.get_or_init(|| PageCache::new(TEST_PAGE_CACHE_SIZE))
+        PAGE_CACHE.get_or_init(|| PageCache::new(TEST_PAGE_CACHE_SIZE, None))
     } else {
         PAGE_CACHE.get().expect("page cache not initialized")
     }
@@ -163,6 +183,15 @@ impl Slot {
             Err(usage_count) => usage_count,
         }
     }
+
+    /// Whether the slot holds a materialized page. A slot that is locked for
+    /// writing is taken as not holding one.
+    fn holds_materialized_page(&self) -> bool {
+        match self.inner.try_read() {
+            Ok(inner) => matches!(inner.key, Some(CacheKey::MaterializedPage { .. })),
+            Err(_) => false,
+        }
+    }
 }
 
 pub struct PageCache {
@@ -188,6 +217,10 @@ pub struct PageCache {
     /// This is interpreted modulo the page cache size.
     next_evict_slot: AtomicUsize,
 
+    /// Number of slots holding materialized pages, and how many they may hold.
+    materialized_pages: AtomicUsize,
+    max_materialized_pages: usize,
+
     size_metrics: &'static PageCacheSizeMetrics,
 }
 
@@ -348,6 +381,9 @@ impl PageCache {
                 panic!("unexpected key type in slot");
             }
         } else {
+            crate::metrics::PAGE_CACHE
+                .read_misses_materialized_page
+                .inc();
             None
         }
     }
@@ -546,7 +582,7 @@ impl PageCache {
 
             // Not found. Find a victim buffer
             let (slot_idx, mut inner) =
-                self.find_victim().context("Failed to find evict victim")?;
+                self.find_victim(false).context("Failed to find evict victim")?;
 
             // Insert mapping for this. At this point, we may find that another
             // thread did the same thing concurrently. In that case, we evicted
@@ -606,9 +642,13 @@ impl PageCache {
                 return Ok(WriteBufResult::Found(write_guard));
             }
 
-            // Not found. Find a victim buffer
-            let (slot_idx, mut inner) =
-                self.find_victim().context("Failed to find evict victim")?;
+            // Not found. Find a victim buffer, among the materialized pages if they
+            // already take all the slots they may.
+            let materialized_only = matches!(cache_key, CacheKey::MaterializedPage { .. })
+                && self.materialized_pages.load(Ordering::Relaxed) >= self.max_materialized_pages;
+            let (slot_idx, mut inner) = self
+                .find_victim(materialized_only)
+                .context("Failed to find evict victim")?;
 
             // Insert mapping for this. At this point, we may find that another
             // thread did the same thing concurrently. In that case, we evicted
@@ -720,6 +760,7 @@ impl PageCache {
 
                     if let Ok(version_idx) = versions.binary_search_by_key(old_lsn, |v| v.lsn) {
                         versions.remove(version_idx);
+                        self.materialized_pages.fetch_sub(1, Ordering::Relaxed);
                         self.size_metrics
                             .current_bytes_materialized_page
                             .sub_page_sz(1);
@@ -769,6 +810,7 @@ impl PageCache {
                                 slot_idx,
                             },
                         );
+                        self.materialized_pages.fetch_add(1, Ordering::Relaxed);
                         self.size_metrics
                             .current_bytes_materialized_page
                             .add_page_sz(1);
@@ -805,10 +847,14 @@ impl PageCache {
     // Section 4: Misc internal helpers
     //
 
-    /// Find a slot to evict.
+    /// Find a slot to evict, only among the slots holding materialized pages if
+    /// `materialized_only` is set.
     ///
     /// On return, the slot is empty and write-locked.
-    fn find_victim(&self) -> anyhow::Result<(usize, RwLockWriteGuard<SlotInner>)> {
+    fn find_victim(
+        &self,
+        materialized_only: bool,
+    ) -> anyhow::Result<(usize, RwLockWriteGuard<SlotInner>)> {
         let iter_limit = self.slots.len() * 10;
         let mut iters = 0;
         loop {
@@ -817,6 +863,15 @@ impl PageCache {
 
             let slot = &self.slots[slot_idx];
 
+            // The slots that can't be the victim are passed over without decrementing
+            // their usage count: their pages aren't any less used for it.
+            if materialized_only && !slot.holds_materialized_page() {
+                if iters > iter_limit {
+                    anyhow::bail!("exceeded evict iter limit");
+                }
+                continue;
+            }
+
             if slot.dec_usage_count() == 0 {
                 let mut inner = match slot.inner.try_write() {
                     Ok(inner) => inner,
@@ -836,6 +891,15 @@ impl PageCache {
                         continue;
                     }
                 };
+                // The slot may have been recycled since it was checked above
+                if materialized_only
+                    && !matches!(inner.key, Some(CacheKey::MaterializedPage { .. }))
+                {
+                    if iters > iter_limit {
+                        anyhow::bail!("exceeded evict iter limit");
+                    }
+                    continue;
+                }
                 if let Some(old_key) = &inner.key {
                     if inner.dirty {
                         if let Err(err) = Self::writeback(old_key, inner.buf) {
@@ -853,6 +917,12 @@ impl PageCache {
                         }
                     }
 
+                    if let CacheKey::MaterializedPage { .. } = old_key {
+                        crate::metrics::PAGE_CACHE
+                            .evictions_materialized_page
+                            .inc();
+                    }
+
                     // remove mapping for old buffer
                     self.remove_mapping(old_key);
                     inner.dirty = false;
@@ -885,10 +955,11 @@ impl PageCache {
         }
     }
 
-    /// Initialize a new page cache
+    /// Initialize a new page cache, with at most `max_materialized_pages` of its
+    /// pages holding materialized pages, all of them by default.
     ///
     /// This should be called only once at page server startup.
-    fn new(num_pages: usize) -> Self {
+    fn new(num_pages: usize, max_materialized_pages: Option<NonZeroUsize>) -> Self {
         assert!(num_pages > 0, "page cache size must be > 0");
 
         let page_buffer = Box::leak(vec![0u8; num_pages * PAGE_SZ].into_boxed_slice());
@@ -921,6 +992,8 @@ impl PageCache {
             immutable_page_map: Default::default(),
             slots,
             next_evict_slot: AtomicUsize::new(0),
+            materialized_pages: AtomicUsize::new(0),
+            max_materialized_pages: max_materialized_pages.map_or(num_pages, NonZeroUsize::get),
             size_metrics,
         }
     }
@@ -948,3 +1021,70 @@ impl PageSzBytesMetric for metrics::UIntGauge {
         self.sub(count_times_page_sz(count));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn materialized_page_budget() {
+        let cache = PageCache::new(8, NonZeroUsize::new(2));
+        let tenant_id = TenantId::generate();
+        let timeline_id = TimelineId::generate();
+        let key = Key::from_hex("000000000000000000000000000000000000").unwrap();
+
+        for lsn in 1..=5 {
+            let img = [lsn as u8; PAGE_SZ];
+            cache
+                .memorize_materialized_page(tenant_id, timeline_id, key, Lsn(lsn), &img)
+                .unwrap();
+            assert!(cache.materialized_pages.load(Ordering::Relaxed) <= 2);
+
+            // The latest version is always there
+            let (found_lsn, page) = cache
+                .lookup_materialized_page(tenant_id, timeline_id, &key, Lsn(lsn))
+                .unwrap();
+            assert_eq!(found_lsn, Lsn(lsn));
+            assert_eq!(*page, img);
+        }
+        assert!(cache
+            .lookup_materialized_page(tenant_id, timeline_id, &key, Lsn(1))
+            .is_none());
+    }
+
+    #[test]
+    fn materialized_page_budget_spares_layer_file_pages() {
+        let cache = PageCache::new(8, NonZeroUsize::new(2));
+        let tenant_id = TenantId::generate();
+        let timeline_id = TimelineId::generate();
+        let key = Key::from_hex("000000000000000000000000000000000000").unwrap();
+        let file_id = 1;
+
+        // The cache is full: 6 layer file pages, and the 2 materialized pages of the budget
+        for blkno in 0..6 {
+            match cache.read_immutable_buf(file_id, blkno).unwrap() {
+                ReadBufResult::NotFound(mut write_guard) => write_guard.mark_valid(),
+                ReadBufResult::Found(_) => panic!("page {blkno} wasn't read yet"),
+            }
+        }
+        let img = [0u8; PAGE_SZ];
+        for lsn in 1..=20 {
+            cache
+                .memorize_materialized_page(tenant_id, timeline_id, key, Lsn(lsn), &img)
+                .unwrap();
+        }
+
+        for slot in cache.slots.iter() {
+            let inner = slot.inner.read().unwrap();
+            if let Some(CacheKey::ImmutableFilePage { .. }) = inner.key {
+                assert_eq!(slot.usage_count.load(Ordering::Relaxed), 1);
+            }
+        }
+        for blkno in 0..6 {
+            assert!(matches!(
+                cache.read_immutable_buf(file_id, blkno).unwrap(),
+                ReadBufResult::Found(_)
+            ));
+        }
+    }
+}
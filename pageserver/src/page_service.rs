@@ -25,8 +25,11 @@ use postgres_backend::{self, is_expected_io_error, AuthType, PostgresBackend, Qu
 use pq_proto::framed::ConnectionError;
 use pq_proto::FeStartupPacket;
 use pq_proto::{BeMessage, FeMessage, RowDescriptor};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::io;
 use std::net::TcpListener;
+use std::num::NonZeroU32;
 use std::pin::pin;
 use std::str;
 use std::str::FromStr;
@@ -126,6 +129,24 @@ where
     }
 }
 
+/// The `getpage_timing` setting: break down the time of a sample of the getpage requests by
+/// stage, see [`crate::metrics::GetPageStageTimings`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetPageTimingConfig {
+    /// Sample one in this many getpage requests.
+    pub sample_one_in: NonZeroU32,
+    /// Also log the breakdown of each sampled request, in the span of the request.
+    #[serde(default)]
+    pub trace: bool,
+}
+
+impl GetPageTimingConfig {
+    fn sample(&self) -> bool {
+        rand::thread_rng().gen_ratio(1, self.sample_one_in.get())
+    }
+}
+
 /// Read the end of a tar archive.
 ///
 /// A tar archive normally ends with two consecutive blocks of zeros, 512 bytes each.
@@ -279,6 +300,12 @@ async fn page_service_conn_main(
     socket.set_timeout(Some(std::time::Duration::from_secs(60 * 60 * 24 * 3)));
     let socket = std::pin::pin!(socket);
 
+    // The on-demand downloads of the connection are cancelled when its task is shut down, with
+    // the tenant or timeline it serves, and when the connection handling ends.
+    let cancel = task_mgr::shutdown_token().child_token();
+    let _cancel_on_exit = cancel.clone().drop_guard();
+    let connection_ctx = connection_ctx.with_cancel(cancel);
+
     // XXX: pgbackend.run() should take the connection_ctx,
     // and create a child per-query context when it invokes process_query.
     // But it's in a shared crate, so, we store connection_ctx inside PageServerHandler
@@ -340,7 +367,7 @@ impl PageRequestMetrics {
 }
 
 struct PageServerHandler {
-    _conf: &'static PageServerConf,
+    conf: &'static PageServerConf,
     broker_client: storage_broker::BrokerClientChannel,
     auth: Option<Arc<JwtAuth>>,
     claims: Option<Claims>,
@@ -360,7 +387,7 @@ impl PageServerHandler {
         connection_ctx: RequestContext,
     ) -> Self {
         PageServerHandler {
-            _conf: conf,
+            conf,
             broker_client,
             auth,
             claims: None,
@@ -501,6 +528,9 @@ impl PageServerHandler {
         // Create empty timeline
         info!("creating new timeline");
         let tenant = get_active_tenant_with_timeout(tenant_id, &ctx).await?;
+        tenant
+            .add_to_timeline_manifest(timeline_id, ctx.cancel())
+            .await?;
         let timeline = tenant.create_empty_timeline(timeline_id, base_lsn, pg_version, &ctx)?;
 
         // TODO mark timeline as not ready until it reaches end_lsn.
@@ -739,9 +769,21 @@ impl PageServerHandler {
         }
         */
 
+        let sampled = self
+            .conf
+            .getpage_timing
+            .as_ref()
+            .map_or(false, GetPageTimingConfig::sample);
         let page = timeline
-            .get_rel_page_at_lsn(req.rel, req.blkno, lsn, req.latest, ctx)
+            .get_rel_page_at_lsn(
+                req.rel,
+                req.blkno,
+                lsn,
+                req.latest,
+                &ctx.with_stage_timings(sampled),
+            )
             .await?;
+        timeline.note_getpage(req.rel, req.blkno, lsn, req.latest);
 
         Ok(PagestreamBeMessage::GetPage(PagestreamGetPageResponse {
             page,
@@ -759,6 +801,7 @@ impl PageServerHandler {
         prev_lsn: Option<Lsn>,
         full_backup: bool,
         gzip: bool,
+        timescaledb_versions: Option<Vec<String>>,
         ctx: RequestContext,
     ) -> anyhow::Result<()>
     where
@@ -780,6 +823,13 @@ impl PageServerHandler {
                 .context("invalid basebackup lsn")?;
         }
 
+        // Fail before sending anything if the compute cannot run the databases.
+        if let Some(timescaledb_versions) = &timescaledb_versions {
+            let lsn = lsn.unwrap_or_else(|| timeline.get_last_record_lsn());
+            basebackup::check_extension_versions(&timeline, lsn, timescaledb_versions, &ctx)
+                .await?;
+        }
+
         let lsn_awaited_after = started.elapsed();
 
         // switch client to COPYOUT
@@ -962,27 +1012,36 @@ where
 
             self.check_permission(Some(tenant_id))?;
 
-            let lsn = if params.len() >= 3 {
-                Some(
-                    Lsn::from_str(params[2])
-                        .with_context(|| format!("Failed to parse Lsn from {}", params[2]))?,
-                )
-            } else {
-                None
+            let lsn = match params.get(2) {
+                Some(param) if !param.starts_with("--") => Some(
+                    Lsn::from_str(param)
+                        .with_context(|| format!("Failed to parse Lsn from {param}"))?,
+                ),
+                _ => None,
             };
 
-            let gzip = if params.len() >= 4 {
-                if params[3] == "--gzip" {
-                    true
+            // The options follow the LSN, if any.
+            let mut gzip = false;
+            let mut timescaledb_versions = None;
+            let options_start = if lsn.is_some() { 3 } else { 2 };
+            for (position, param) in params.iter().enumerate().skip(options_start) {
+                if *param == "--gzip" {
+                    gzip = true;
+                } else if let Some(versions) = param.strip_prefix("--timescaledb-versions=") {
+                    // Empty if the compute doesn't ship the extension at all.
+                    timescaledb_versions = Some(
+                        versions
+                            .split(',')
+                            .filter(|version| !version.is_empty())
+                            .map(str::to_owned)
+                            .collect(),
+                    );
                 } else {
                     return Err(QueryError::Other(anyhow::anyhow!(
-                        "Parameter in position 3 unknown {}",
-                        params[3],
+                        "Parameter in position {position} unknown {param}",
                     )));
                 }
-            } else {
-                false
-            };
+            }
 
             metrics::metric_vec_duration::observe_async_block_duration_by_result(
                 &*crate::metrics::BASEBACKUP_QUERY_TIME,
@@ -995,6 +1054,7 @@ where
                         None,
                         false,
                         gzip,
+                        timescaledb_versions,
                         ctx,
                     )
                     .await?;
@@ -1088,6 +1148,7 @@ where
                 prev_lsn,
                 true,
                 false,
+                None,
                 ctx,
             )
             .await?;
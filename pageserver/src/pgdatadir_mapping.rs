@@ -10,11 +10,11 @@ use super::tenant::{PageReconstructError, Timeline};
 use crate::context::RequestContext;
 use crate::keyspace::{KeySpace, KeySpaceAccum};
 use crate::repository::*;
-use crate::walrecord::NeonWalRecord;
+use crate::walrecord::{NeonWalRecord, RelFileNode};
 use anyhow::Context;
 use bytes::{Buf, Bytes};
 use pageserver_api::reltag::{RelTag, SlruKind};
-use postgres_ffi::relfile_utils::{FSM_FORKNUM, VISIBILITYMAP_FORKNUM};
+use postgres_ffi::relfile_utils::{FSM_FORKNUM, INIT_FORKNUM, MAIN_FORKNUM, VISIBILITYMAP_FORKNUM};
 use postgres_ffi::BLCKSZ;
 use postgres_ffi::{Oid, TimestampTz, TransactionId};
 use serde::{Deserialize, Serialize};
@@ -93,6 +93,8 @@ impl Timeline {
             pending_updates: HashMap::new(),
             pending_deletions: Vec::new(),
             pending_nblocks: 0,
+            dbdir: None,
+            rel_dirs: HashMap::new(),
             lsn,
         }
     }
@@ -129,6 +131,42 @@ impl Timeline {
         self.get(key, lsn, ctx).await
     }
 
+    /// Look up the pages `blocks` of a relation, like [`Self::get_rel_page_at_lsn`] for each
+    /// of them, with their WAL redo in one request, see [`Timeline::get_batch`].
+    pub async fn get_rel_pages_at_lsn(
+        &self,
+        tag: RelTag,
+        blocks: Range<BlockNumber>,
+        lsn: Lsn,
+        latest: bool,
+        ctx: &RequestContext,
+    ) -> Result<Vec<Bytes>, PageReconstructError> {
+        if tag.relnode == 0 {
+            return Err(PageReconstructError::Other(
+                RelationError::InvalidRelnode.into(),
+            ));
+        }
+
+        let nblocks = self.get_rel_size(tag, lsn, latest, ctx).await?;
+        let keys: Vec<_> = (blocks.start..blocks.end.min(nblocks))
+            .map(|blknum| rel_block_to_key(tag, blknum))
+            .collect();
+        let mut pages = self
+            .get_batch(&keys, lsn, ctx)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        if blocks.end > nblocks {
+            debug!(
+                "read beyond EOF at {} blks {}..{} at {}, size is {}: returning all-zeros pages",
+                tag, blocks.start, blocks.end, lsn, nblocks
+            );
+            let n_beyond = blocks.end.saturating_sub(blocks.start.max(nblocks));
+            pages.extend((0..n_beyond).map(|_| ZERO_PAGE.clone()));
+        }
+        Ok(pages)
+    }
+
     // Get size of a database in blocks
     pub async fn get_db_size(
         &self,
@@ -679,6 +717,13 @@ pub struct DatadirModification<'a> {
     pending_updates: HashMap<Key, Value>,
     pending_deletions: Vec<Range<Key>>,
     pending_nblocks: i64,
+
+    // The db and rel directories read by the modification, kept decoded across commits. A
+    // TimescaleDB hypertable creates chunks at a high rate, so instead of reading and
+    // deserializing a directory for every relation created, and serializing it back, the
+    // modified ones are serialized once per commit.
+    dbdir: Option<CachedDirectory<DbDirectory>>,
+    rel_dirs: HashMap<(Oid, Oid), CachedDirectory<RelDirectory>>,
 }
 
 impl<'a> DatadirModification<'a> {
@@ -786,25 +831,20 @@ impl<'a> DatadirModification<'a> {
         ctx: &RequestContext,
     ) -> anyhow::Result<()> {
         // Add it to the directory (if it doesn't exist already)
-        let buf = self.get(DBDIR_KEY, ctx).await?;
-        let mut dbdir = DbDirectory::des(&buf)?;
+        let dbdir = self.load_dbdir(ctx).await?;
 
-        let r = dbdir.dbdirs.insert((spcnode, dbnode), true);
+        let r = dbdir.dir.dbdirs.insert((spcnode, dbnode), true);
         if r.is_none() || r == Some(false) {
             // The dbdir entry didn't exist, or it contained a
             // 'false'. The 'insert' call already updated it with
-            // 'true', now write the updated 'dbdirs' map back.
-            let buf = DbDirectory::ser(&dbdir)?;
-            self.put(DBDIR_KEY, Value::Image(buf.into()));
+            // 'true', the updated 'dbdirs' map is written back on commit.
+            dbdir.dirty = true;
         }
         if r.is_none() {
             // Create RelDirectory
-            let buf = RelDirectory::ser(&RelDirectory {
-                rels: HashSet::new(),
-            })?;
-            self.put(
-                rel_dir_to_key(spcnode, dbnode),
-                Value::Image(Bytes::from(buf)),
+            self.rel_dirs.insert(
+                (spcnode, dbnode),
+                CachedDirectory::created(RelDirectory::default()),
             );
         }
 
@@ -857,11 +897,9 @@ impl<'a> DatadirModification<'a> {
             .await?;
 
         // Remove entry from dbdir
-        let buf = self.get(DBDIR_KEY, ctx).await?;
-        let mut dir = DbDirectory::des(&buf)?;
-        if dir.dbdirs.remove(&(spcnode, dbnode)).is_some() {
-            let buf = DbDirectory::ser(&dir)?;
-            self.put(DBDIR_KEY, Value::Image(buf.into()));
+        let dbdir = self.load_dbdir(ctx).await?;
+        if dbdir.dir.dbdirs.remove(&(spcnode, dbnode)).is_some() {
+            dbdir.dirty = true;
         } else {
             warn!(
                 "dropped dbdir for spcnode {} dbnode {} did not exist in db directory",
@@ -873,6 +911,7 @@ impl<'a> DatadirModification<'a> {
         self.pending_nblocks -= total_blocks as i64;
 
         // Delete all relations and metadata files for the spcnode/dnode
+        self.rel_dirs.remove(&(spcnode, dbnode));
         self.delete(dbdir_key_range(spcnode, dbnode));
         Ok(())
     }
@@ -891,33 +930,28 @@ impl<'a> DatadirModification<'a> {
         }
         // It's possible that this is the first rel for this db in this
         // tablespace.  Create the reldir entry for it if so.
-        let mut dbdir = DbDirectory::des(&self.get(DBDIR_KEY, ctx).await.context("read db")?)
-            .context("deserialize db")?;
-        let rel_dir_key = rel_dir_to_key(rel.spcnode, rel.dbnode);
-        let mut rel_dir = if dbdir.dbdirs.get(&(rel.spcnode, rel.dbnode)).is_none() {
+        let dbdir = self.load_dbdir(ctx).await.context("read db")?;
+        if !dbdir.dir.dbdirs.contains_key(&(rel.spcnode, rel.dbnode)) {
             // Didn't exist. Update dbdir
-            dbdir.dbdirs.insert((rel.spcnode, rel.dbnode), false);
-            let buf = DbDirectory::ser(&dbdir).context("serialize db")?;
-            self.put(DBDIR_KEY, Value::Image(buf.into()));
+            dbdir.dir.dbdirs.insert((rel.spcnode, rel.dbnode), false);
+            dbdir.dirty = true;
 
             // and create the RelDirectory
-            RelDirectory::default()
-        } else {
-            // reldir already exists, fetch it
-            RelDirectory::des(&self.get(rel_dir_key, ctx).await.context("read db")?)
-                .context("deserialize db")?
-        };
+            self.rel_dirs.insert(
+                (rel.spcnode, rel.dbnode),
+                CachedDirectory::created(RelDirectory::default()),
+            );
+        }
 
-        // Add the new relation to the rel directory entry, and write it back
-        if !rel_dir.rels.insert((rel.relnode, rel.forknum)) {
+        // Add the new relation to the rel directory entry, it is written back on commit
+        let rel_dir = self
+            .load_rel_dir(rel.spcnode, rel.dbnode, ctx)
+            .await
+            .context("read db")?;
+        if !rel_dir.dir.rels.insert((rel.relnode, rel.forknum)) {
             return Err(RelationError::AlreadyExists);
         }
-        self.put(
-            rel_dir_key,
-            Value::Image(Bytes::from(
-                RelDirectory::ser(&rel_dir).context("serialize")?,
-            )),
-        );
+        rel_dir.dirty = true;
 
         // Put size
         let size_key = rel_size_to_key(rel);
@@ -996,12 +1030,9 @@ impl<'a> DatadirModification<'a> {
         anyhow::ensure!(rel.relnode != 0, RelationError::InvalidRelnode);
 
         // Remove it from the directory entry
-        let dir_key = rel_dir_to_key(rel.spcnode, rel.dbnode);
-        let buf = self.get(dir_key, ctx).await?;
-        let mut dir = RelDirectory::des(&buf)?;
-
-        if dir.rels.remove(&(rel.relnode, rel.forknum)) {
-            self.put(dir_key, Value::Image(Bytes::from(RelDirectory::ser(&dir)?)));
+        let rel_dir = self.load_rel_dir(rel.spcnode, rel.dbnode, ctx).await?;
+        if rel_dir.dir.rels.remove(&(rel.relnode, rel.forknum)) {
+            rel_dir.dirty = true;
         } else {
             warn!("dropped rel {} did not exist in rel directory", rel);
         }
@@ -1020,6 +1051,62 @@ impl<'a> DatadirModification<'a> {
         Ok(())
     }
 
+    /// Drop all the forks of the given relations that exist, like the ones that a transaction
+    /// dropped. A retention policy of TimescaleDB drops hundreds of chunks at once, so instead
+    /// of going through `put_rel_drop` for each fork, each rel directory is updated once.
+    pub async fn put_rel_drops(
+        &mut self,
+        nodes: &[RelFileNode],
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        let mut relnodes_by_db: HashMap<(Oid, Oid), Vec<Oid>> = HashMap::new();
+        for node in nodes {
+            anyhow::ensure!(node.relnode != 0, RelationError::InvalidRelnode);
+            relnodes_by_db
+                .entry((node.spcnode, node.dbnode))
+                .or_default()
+                .push(node.relnode);
+        }
+
+        for ((spcnode, dbnode), relnodes) in relnodes_by_db {
+            // Remove them from the directory entry
+            let rel_dir = self.load_rel_dir(spcnode, dbnode, ctx).await?;
+            let mut dropped = Vec::new();
+            for relnode in relnodes {
+                for forknum in MAIN_FORKNUM..=INIT_FORKNUM {
+                    if rel_dir.dir.rels.remove(&(relnode, forknum)) {
+                        dropped.push(RelTag {
+                            spcnode,
+                            dbnode,
+                            relnode,
+                            forknum,
+                        });
+                    }
+                }
+            }
+            if dropped.is_empty() {
+                continue;
+            }
+            rel_dir.dirty = true;
+
+            for rel in dropped {
+                // update logical size, from the relation size cache if possible
+                let old_size = match self.tline.get_cached_rel_size(&rel, self.lsn) {
+                    Some(nblocks) => nblocks,
+                    None => self.get(rel_size_to_key(rel), ctx).await?.get_u32_le(),
+                };
+                self.pending_nblocks -= old_size as i64;
+
+                self.tline.remove_cached_rel_size(&rel);
+
+                // Delete size entry, as well as all blocks
+                self.delete(rel_key_range(rel));
+            }
+        }
+
+        Ok(())
+    }
+
     pub async fn put_slru_segment_creation(
         &mut self,
         kind: SlruKind,
@@ -1180,6 +1267,30 @@ impl<'a> DatadirModification<'a> {
         let pending_nblocks = self.pending_nblocks;
         self.pending_nblocks = 0;
 
+        let rels = self
+            .pending_updates
+            .keys()
+            .filter(|key| is_rel_block_key(**key))
+            .filter_map(|key| key_to_rel_block(*key).ok())
+            .map(|(rel, _)| rel);
+        self.tline.note_ingested_rels(rels, lsn);
+
+        // Write back the directories modified since the last commit
+        let mut dirs = Vec::new();
+        if let Some(dbdir) = &mut self.dbdir {
+            if let Some(buf) = dbdir.take_modified()? {
+                dirs.push((DBDIR_KEY, buf));
+            }
+        }
+        for (&(spcnode, dbnode), rel_dir) in self.rel_dirs.iter_mut() {
+            if let Some(buf) = rel_dir.take_modified()? {
+                dirs.push((rel_dir_to_key(spcnode, dbnode), buf));
+            }
+        }
+        for (key, buf) in dirs {
+            self.put(key, Value::Image(buf));
+        }
+
         for (key, value) in self.pending_updates.drain() {
             writer.put(key, lsn, &value).await?;
         }
@@ -1223,6 +1334,34 @@ impl<'a> DatadirModification<'a> {
         }
     }
 
+    async fn load_dbdir(
+        &mut self,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<&mut CachedDirectory<DbDirectory>> {
+        if self.dbdir.is_none() {
+            let buf = self.get(DBDIR_KEY, ctx).await?;
+            self.dbdir = Some(CachedDirectory::read(DbDirectory::des(&buf)?));
+        }
+        Ok(self.dbdir.as_mut().expect("dbdir was just read"))
+    }
+
+    async fn load_rel_dir(
+        &mut self,
+        spcnode: Oid,
+        dbnode: Oid,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<&mut CachedDirectory<RelDirectory>> {
+        if !self.rel_dirs.contains_key(&(spcnode, dbnode)) {
+            let buf = self.get(rel_dir_to_key(spcnode, dbnode), ctx).await?;
+            let dir = CachedDirectory::read(RelDirectory::des(&buf)?);
+            self.rel_dirs.insert((spcnode, dbnode), dir);
+        }
+        Ok(self
+            .rel_dirs
+            .get_mut(&(spcnode, dbnode))
+            .expect("rel directory was just read"))
+    }
+
     fn put(&mut self, key: Key, val: Value) {
         self.pending_updates.insert(key, val);
     }
@@ -1233,6 +1372,32 @@ impl<'a> DatadirModification<'a> {
     }
 }
 
+/// A directory held decoded by a [`DatadirModification`].
+struct CachedDirectory<T> {
+    dir: T,
+    /// Whether it was modified since it was last written.
+    dirty: bool,
+}
+
+impl<T: Serialize> CachedDirectory<T> {
+    fn read(dir: T) -> Self {
+        CachedDirectory { dir, dirty: false }
+    }
+
+    fn created(dir: T) -> Self {
+        CachedDirectory { dir, dirty: true }
+    }
+
+    /// Serialize the directory, if it was modified since it was last written.
+    fn take_modified(&mut self) -> anyhow::Result<Option<Bytes>> {
+        if !self.dirty {
+            return Ok(None);
+        }
+        self.dirty = false;
+        Ok(Some(Bytes::from(self.dir.ser()?)))
+    }
+}
+
 //--- Metadata structs stored in key-value pairs in the repository.
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -1395,7 +1560,7 @@ fn rel_dir_to_key(spcnode: Oid, dbnode: Oid) -> Key {
     }
 }
 
-fn rel_block_to_key(rel: RelTag, blknum: BlockNumber) -> Key {
+pub(crate) fn rel_block_to_key(rel: RelTag, blknum: BlockNumber) -> Key {
     Key {
         field1: 0x00,
         field2: rel.spcnode,
@@ -1452,7 +1617,7 @@ fn slru_dir_to_key(kind: SlruKind) -> Key {
     }
 }
 
-fn slru_block_to_key(kind: SlruKind, segno: u32, blknum: BlockNumber) -> Key {
+pub(crate) fn slru_block_to_key(kind: SlruKind, segno: u32, blknum: BlockNumber) -> Key {
     Key {
         field1: 0x01,
         field2: match kind {
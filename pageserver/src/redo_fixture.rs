@@ -0,0 +1,199 @@
+//!
+//! Fixtures for the WAL redo tests, captured from the layer files of a timeline with
+//! `pageserver capture-redo-fixture`.
+//!
+//! A fixture holds what [`WalRedoManager::request_redo`] is called with for a key at an LSN:
+//! the base image, if any, and the WAL records to apply on top of it. The page the records
+//! reconstruct is written next to it, like `fixtures/short_v14_redo.page`, so the redo of real
+//! record sequences, of TimescaleDB compression or continuous aggregate refreshes, can be
+//! tested without crafting the records by hand.
+//!
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use utils::bin_ser::BeSer;
+use utils::id::{TenantId, TimelineId};
+use utils::lsn::Lsn;
+
+use crate::config::PageServerConf;
+use crate::context::{DownloadBehavior, RequestContext};
+use crate::repository::Key;
+use crate::task_mgr::TaskKind;
+use crate::tenant::layer_map::{LayerMap, SearchResult};
+use crate::tenant::metadata::{load_metadata, TimelineMetadata};
+use crate::tenant::storage_layer::{
+    DeltaLayer, ImageLayer, LayerFileName, PersistentLayer, ValueReconstructResult,
+    ValueReconstructState,
+};
+use crate::walrecord::NeonWalRecord;
+use crate::walredo::{PostgresRedoManager, RedoTimings, WalRedoManager};
+
+/// Extension of the file with the redo request of a fixture.
+pub const FIXTURE_EXTENSION: &str = "redo";
+
+/// Extension of the file with the page reconstructed by the redo request of a fixture.
+pub const PAGE_EXTENSION: &str = "page";
+
+/// A WAL redo request captured from a timeline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedoFixture {
+    pub key: Key,
+    pub lsn: Lsn,
+    pub pg_version: u32,
+    pub base_img: Option<(Lsn, Bytes)>,
+    /// The records to apply, oldest first.
+    pub records: Vec<(Lsn, NeonWalRecord)>,
+}
+
+impl RedoFixture {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let buf = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+        RedoFixture::des(&buf).with_context(|| format!("deserialize {}", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, self.ser()?).with_context(|| format!("write {}", path.display()))
+    }
+}
+
+/// The layers of a timeline, as found in its directory.
+struct TimelineLayers {
+    metadata: TimelineMetadata,
+    layer_map: LayerMap,
+    layers: HashMap<LayerFileName, Box<dyn PersistentLayer>>,
+}
+
+impl TimelineLayers {
+    fn load(
+        conf: &'static PageServerConf,
+        tenant_id: &TenantId,
+        timeline_id: &TimelineId,
+    ) -> anyhow::Result<Self> {
+        let metadata = load_metadata(conf, tenant_id, timeline_id)?;
+
+        let mut layer_map = LayerMap::default();
+        let mut updates = layer_map.batch_update();
+        let mut layers = HashMap::new();
+        let timeline_path = conf.timeline_path(tenant_id, timeline_id);
+        for entry in fs::read_dir(&timeline_path)
+            .with_context(|| format!("read directory {}", timeline_path.display()))?
+        {
+            let path = entry?.path();
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            // Not a layer file, like the metadata or a temporary file
+            let Ok(layer_file_name) = LayerFileName::from_str(file_name) else {
+                continue;
+            };
+            let file = File::open(&path).with_context(|| format!("open {}", path.display()))?;
+            let layer: Box<dyn PersistentLayer> = match layer_file_name {
+                LayerFileName::Image(_) => Box::new(ImageLayer::new_for_path(&path, file)?),
+                LayerFileName::Delta(_) => Box::new(DeltaLayer::new_for_path(&path, file)?),
+            };
+            updates.insert_historic(layer.layer_desc().clone());
+            layers.insert(layer.layer_desc().filename(), layer);
+        }
+        updates.flush();
+
+        Ok(TimelineLayers {
+            metadata,
+            layer_map,
+            layers,
+        })
+    }
+}
+
+/// Collect the base image and the WAL records of `key` at `lsn` from the local layer files of
+/// the timeline, and of its ancestors if need be, like the page reconstruction does.
+pub fn capture(
+    conf: &'static PageServerConf,
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+    key: Key,
+    lsn: Lsn,
+) -> anyhow::Result<RedoFixture> {
+    let ctx = RequestContext::new(TaskKind::DebugTool, DownloadBehavior::Error);
+    let mut state = ValueReconstructState {
+        records: Vec::new(),
+        img: None,
+    };
+
+    let mut timeline = TimelineLayers::load(conf, &tenant_id, &timeline_id)?;
+    let pg_version = timeline.metadata.pg_version();
+    let mut cont_lsn = Lsn(lsn.0 + 1);
+    loop {
+        let Some(SearchResult { layer, lsn_floor }) = timeline.layer_map.search(key, cont_lsn)
+        else {
+            let Some(ancestor_id) = timeline.metadata.ancestor_timeline() else {
+                bail!("no layer of the timeline has {key} before {cont_lsn}");
+            };
+            cont_lsn = Lsn::min(cont_lsn, Lsn(timeline.metadata.ancestor_lsn().0 + 1));
+            timeline = TimelineLayers::load(conf, &tenant_id, &ancestor_id)
+                .with_context(|| format!("load ancestor timeline {ancestor_id}"))?;
+            continue;
+        };
+        let Some(layer) = timeline.layers.get(&layer.filename()) else {
+            bail!("layer {} not found", layer.filename());
+        };
+        let result = layer.get_value_reconstruct_data(key, lsn_floor..cont_lsn, &mut state, &ctx)?;
+        match result {
+            ValueReconstructResult::Complete => break,
+            ValueReconstructResult::Continue => cont_lsn = lsn_floor,
+            ValueReconstructResult::Missing => bail!("layer {layer} is missing {key}"),
+        }
+    }
+
+    if state.records.is_empty() {
+        bail!("{key} has an image at {lsn}, there is nothing to redo");
+    }
+    state.records.reverse();
+    Ok(RedoFixture {
+        key,
+        lsn,
+        pg_version,
+        base_img: state.img,
+        records: state.records,
+    })
+}
+
+/// Redo the request of `fixture` with the postgres binaries of `conf`.
+///
+/// The debug tool runs without a tokio runtime: the request is awaited on the calling thread.
+pub fn redo(conf: &'static PageServerConf, fixture: &RedoFixture) -> anyhow::Result<Bytes> {
+    let manager = PostgresRedoManager::new(conf, TenantId::generate());
+    let page = futures::executor::block_on(manager.request_redo(
+        fixture.key,
+        fixture.lsn,
+        fixture.base_img.clone(),
+        fixture.records.clone(),
+        fixture.pg_version,
+        &mut RedoTimings::default(),
+    ))?;
+    Ok(page)
+}
+
+/// Capture the fixture of `key` at `lsn`, and write it and the page it reconstructs to
+/// `<output>.redo` and `<output>.page`.
+pub fn capture_to_files(
+    conf: &'static PageServerConf,
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+    key: Key,
+    lsn: Lsn,
+    output: &Path,
+) -> anyhow::Result<(PathBuf, PathBuf)> {
+    let fixture = capture(conf, tenant_id, timeline_id, key, lsn)?;
+    let page = redo(conf, &fixture).context("redo the captured records")?;
+
+    let fixture_path = output.with_extension(FIXTURE_EXTENSION);
+    let page_path = output.with_extension(PAGE_EXTENSION);
+    fixture.write(&fixture_path)?;
+    fs::write(&page_path, &page).with_context(|| format!("write {}", page_path.display()))?;
+    Ok((fixture_path, page_path))
+}
@@ -0,0 +1,125 @@
+//! Breakdown of the size of the databases of a timeline by relation, for the
+//! "relation_sizes" API call.
+//!
+//! The relations are named from the catalog of the database, see [`crate::catalog`]. The
+//! chunks of TimescaleDB hypertables are summed up per hypertable instead, see
+//! [`crate::timescaledb`]: a Timescale database easily has thousands of chunks, which are
+//! not useful one by one.
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Context;
+use pageserver_api::models::{DatabaseSizes, HypertableSize, RelationSize};
+use postgres_ffi::{Oid, BLCKSZ};
+use utils::lsn::Lsn;
+
+use crate::catalog::Catalog;
+use crate::context::RequestContext;
+use crate::tenant::Timeline;
+use crate::timescaledb::{chunk_of, ChunkPart, Hypertable};
+
+/// The sizes of the relations of a database at the given LSN.
+pub async fn database_sizes(
+    timeline: &Timeline,
+    spcnode: Oid,
+    dbnode: Oid,
+    has_relmap_file: bool,
+    lsn: Lsn,
+    ctx: &RequestContext,
+) -> anyhow::Result<DatabaseSizes> {
+    let mut sizes = BTreeMap::new();
+    for rel in timeline
+        .list_rels(spcnode, dbnode, lsn, ctx)
+        .await
+        .context("list relations")?
+    {
+        let nblocks = timeline
+            .get_rel_size(rel, lsn, false, ctx)
+            .await
+            .with_context(|| format!("read size of {rel}"))?;
+        *sizes.entry(rel.relnode).or_default() += nblocks as u64 * BLCKSZ as u64;
+    }
+
+    // Only the directories of databases have a relation mapper file, not the ones of
+    // tablespaces.
+    let catalog = if has_relmap_file {
+        Catalog::load(timeline, spcnode, dbnode, lsn, ctx)
+            .await
+            .context("read catalog")?
+    } else {
+        None
+    };
+    let hypertables = match &catalog {
+        Some(catalog) => {
+            Hypertable::load_all(timeline, catalog, spcnode, dbnode, lsn, ctx).await?
+        }
+        None => HashMap::new(),
+    };
+
+    let (relations, hypertables) = breakdown(sizes, catalog.as_ref(), &hypertables);
+    Ok(DatabaseSizes {
+        spcnode,
+        dbnode,
+        relations,
+        hypertables,
+    })
+}
+
+/// Name the relations, and sum up the chunks per hypertable.
+fn breakdown(
+    sizes: BTreeMap<Oid, u64>,
+    catalog: Option<&Catalog>,
+    hypertables: &HashMap<i32, Hypertable>,
+) -> (Vec<RelationSize>, Vec<HypertableSize>) {
+    let classes = catalog.map(Catalog::by_relfilenode).unwrap_or_default();
+    // The hypertables the user sees, by the ids of their internal compressed hypertables.
+    let compressed_of = hypertables
+        .values()
+        .filter_map(|hypertable| Some((hypertable.compressed_hypertable_id?, hypertable.id)))
+        .collect::<HashMap<_, _>>();
+
+    let mut relations = Vec::new();
+    let mut per_hypertable = BTreeMap::new();
+    for (relnode, size_bytes) in sizes {
+        let class = classes.get(&relnode).copied();
+        let chunk = catalog
+            .zip(class)
+            .and_then(|(catalog, class)| chunk_of(catalog, class));
+
+        let Some((chunk, part)) = chunk else {
+            relations.push(RelationSize {
+                relnode,
+                schema: class
+                    .and_then(|class| catalog?.namespace(class))
+                    .map(str::to_owned),
+                name: class.map(|class| class.relname.clone()),
+                size_bytes,
+            });
+            continue;
+        };
+
+        let (id, compressed) = match compressed_of.get(&chunk.hypertable_id) {
+            Some(id) => (*id, true),
+            None => (chunk.hypertable_id, chunk.compressed),
+        };
+        let entry = per_hypertable.entry(id).or_insert_with(|| {
+            let hypertable = hypertables.get(&id);
+            HypertableSize {
+                id,
+                schema: hypertable.map(|hypertable| hypertable.schema_name.clone()),
+                name: hypertable.map(|hypertable| hypertable.table_name.clone()),
+                chunks: 0,
+                size_bytes: 0,
+                compressed_size_bytes: 0,
+            }
+        });
+        entry.size_bytes += size_bytes;
+        if compressed {
+            entry.compressed_size_bytes += size_bytes;
+        } else if part == ChunkPart::Table {
+            // A compressed chunk replaces the data of a chunk, which stays in the catalog.
+            entry.chunks += 1;
+        }
+    }
+
+    (relations, per_hypertable.into_values().collect())
+}
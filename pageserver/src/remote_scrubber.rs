@@ -0,0 +1,146 @@
+//!
+//! Periodic scrub of the remote storage, deleting the layer files that the index of their
+//! timeline doesn't reference.
+//!
+//! A crash between a layer upload and the index upload referencing it, or between the index
+//! upload dereferencing a layer and the deletion of the layer, leaves the layer file in the
+//! remote storage forever. With `remote_scrub = { period = '1d' }`, the scrubber lists the
+//! remote storage of each timeline every period and deletes those files, see
+//! `RemoteTimelineClient::scrub_leaked_objects`. The same can be requested for a single
+//! timeline with `POST /v1/tenant/:tenant_id/timeline/:timeline_id/scrub_remote`.
+//!
+//! Only the files written at least `min_age` ago are deleted, as a safety margin for the
+//! uploads in flight.
+use std::time::Duration;
+
+use anyhow::Context;
+use pageserver_api::models::RemoteScrubReport;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::*;
+use utils::completion;
+
+use crate::background_jobs::{run_job, BackgroundJob, BackgroundJobKind};
+use crate::config::PageServerConf;
+use crate::task_mgr::{self, TaskKind, BACKGROUND_RUNTIME};
+use crate::tenant::mgr;
+
+pub const DEFAULT_REMOTE_SCRUB_MIN_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn default_min_age() -> Duration {
+    DEFAULT_REMOTE_SCRUB_MIN_AGE
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteScrubConfig {
+    #[serde(with = "humantime_serde")]
+    pub period: Duration,
+    /// Only delete the files written at least this long ago.
+    #[serde(with = "humantime_serde", default = "default_min_age")]
+    pub min_age: Duration,
+}
+
+/// The `min_age` of the scrubs, also of those requested through the management API.
+pub fn scrub_min_age(conf: &PageServerConf) -> Duration {
+    conf.remote_scrub
+        .map_or(DEFAULT_REMOTE_SCRUB_MIN_AGE, |config| config.min_age)
+}
+
+pub fn launch_remote_scrubber(
+    conf: &'static PageServerConf,
+    background_jobs_barrier: completion::Barrier,
+) {
+    let Some(config) = &conf.remote_scrub else {
+        info!("remote scrubber not configured");
+        return;
+    };
+
+    info!("launching remote scrubber");
+
+    task_mgr::spawn(
+        BACKGROUND_RUNTIME.handle(),
+        TaskKind::RemoteScrub,
+        None,
+        None,
+        "remote scrubber",
+        false,
+        async move {
+            let cancel = task_mgr::shutdown_token();
+
+            // The timelines of loading tenants have no upload queue yet.
+            tokio::select! {
+                _ = cancel.cancelled() => { return Ok(()); },
+                _ = background_jobs_barrier.wait() => { }
+            };
+
+            run_job(BackgroundJobKind::RemoteScrub, RemoteScrub { config }, &cancel)
+                .instrument(info_span!("remote_scrubber"))
+                .await;
+            Ok(())
+        },
+    );
+}
+
+struct RemoteScrub<'a> {
+    config: &'a RemoteScrubConfig,
+}
+
+#[async_trait::async_trait]
+impl BackgroundJob for RemoteScrub<'_> {
+    fn period(&self) -> Duration {
+        self.config.period
+    }
+
+    async fn iteration(&mut self, cancel: &CancellationToken) -> anyhow::Result<()> {
+        let tenants = mgr::list_tenants().await.context("get list of tenants")?;
+
+        let mut total = RemoteScrubReport::default();
+        for (tenant_id, _state) in tenants {
+            let tenant = match mgr::get_tenant(tenant_id, true).await {
+                Ok(tenant) => tenant,
+                Err(e) => {
+                    // this can happen if tenant has lifecycle transition after we fetched it
+                    debug!("failed to get tenant: {e:#}");
+                    continue;
+                }
+            };
+
+            for timeline in tenant.list_timelines() {
+                if cancel.is_cancelled() {
+                    return Ok(());
+                }
+                let Some(remote_client) = &timeline.remote_client else {
+                    continue;
+                };
+                if !timeline.is_active() {
+                    continue;
+                }
+
+                let timeline_id = timeline.timeline_id;
+                let span = info_span!("remote_scrub", %tenant_id, %timeline_id);
+                match remote_client
+                    .scrub_leaked_objects(self.config.min_age)
+                    .instrument(span)
+                    .await
+                {
+                    Ok(report) => {
+                        total.deleted_objects += report.deleted_objects;
+                        total.deleted_bytes += report.deleted_bytes;
+                        total.skipped_recent_objects += report.skipped_recent_objects;
+                    }
+                    Err(e) => {
+                        warn!(%tenant_id, %timeline_id, "failed to scrub remote storage: {e:#}")
+                    }
+                }
+            }
+        }
+
+        if total.deleted_objects > 0 {
+            info!(?total, "deleted leaked layer files from the remote storage");
+        } else {
+            debug!(?total, "no leaked layer files in the remote storage");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+//!
+//! Graceful shutdown of the pageserver, in phases:
+//!
+//! 1. Stop accepting requests: close the libpq listener, shut down the page service
+//!    connections, and set all tenants to Stopping state, which locks out new requests.
+//! 2. Stop the WAL receivers, so that no new data comes in.
+//! 3. Flush the in-memory layers of all timelines to disk.
+//! 4. Drain the upload queues, i.e. wait for the uploads of the flushed layers.
+//! 5. Stop all remaining tasks, the HTTP endpoint last, so that the server's status can be
+//!    checked while it's shutting down.
+//! 6. Stop the runtimes: let the work they still run outside of the tasks, on their blocking
+//!    threads, finish before the process exits and takes the runtimes down.
+//!
+//! The time spent in each phase is logged and exported as the
+//! `pageserver_shutdown_phase_seconds` metric. The whole sequence is bounded by the
+//! `shutdown_deadline` setting: when the deadline is reached, the process exits right away.
+//! The data that didn't make it to remote storage is uploaded after the restart.
+//!
+use std::future::Future;
+use std::time::Duration;
+
+use once_cell::sync::OnceCell;
+use strum_macros::{Display, IntoStaticStr};
+use tokio::time::Instant;
+use tracing::*;
+
+use crate::config::defaults::DEFAULT_SHUTDOWN_DEADLINE;
+use crate::metrics::SHUTDOWN_PHASE_SECONDS;
+use crate::task_mgr::{self, TaskKind};
+use crate::tenant::mgr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+enum ShutdownPhase {
+    StopAcceptingRequests,
+    StopWalReceivers,
+    FlushInMemoryLayers,
+    DrainUploadQueues,
+    StopTasks,
+    StopRuntimes,
+}
+
+static DEADLINE: OnceCell<Duration> = OnceCell::new();
+
+/// Set the `shutdown_deadline` from the config. Called at startup.
+pub fn set_deadline(deadline: Duration) {
+    if DEADLINE.set(deadline).is_err() {
+        warn!("shutdown deadline was already set");
+    }
+}
+
+fn deadline() -> Duration {
+    *DEADLINE.get_or_init(|| {
+        humantime::parse_duration(DEFAULT_SHUTDOWN_DEADLINE).expect("valid default")
+    })
+}
+
+#[tracing::instrument]
+pub async fn shutdown_pageserver(exit_code: i32) {
+    let started_at = Instant::now();
+    let shutdown = Shutdown {
+        deadline: started_at + deadline(),
+        exit_code,
+    };
+
+    let tenants = shutdown.run_phase(ShutdownPhase::StopAcceptingRequests, async {
+        // Shut down the libpq endpoint task. This prevents new connections from
+        // being accepted.
+        task_mgr::shutdown_tasks(Some(TaskKind::LibpqEndpointListener), None, None).await;
+
+        // Shut down any page service tasks.
+        task_mgr::shutdown_tasks(Some(TaskKind::PageRequestHandler), None, None).await;
+
+        // FIXME: We should probably stop accepting commands like attach/detach here too, but
+        // the HTTP endpoint stays up until the end.
+        mgr::begin_shutdown_all_tenants().await
+    })
+    .await;
+
+    shutdown.run_phase(ShutdownPhase::StopWalReceivers, async {
+        // The WAL receivers began to shut down when their tenants turned Stopping.
+        mgr::on_each_tenant(&tenants, |tenant| async move {
+            tenant.shutdown_walreceivers().await
+        })
+        .await
+    })
+    .await;
+
+    shutdown.run_phase(ShutdownPhase::FlushInMemoryLayers, async {
+        mgr::on_each_tenant(&tenants, |tenant| async move {
+            tenant.flush_on_shutdown().await
+        })
+        .await
+    })
+    .await;
+
+    shutdown.run_phase(ShutdownPhase::DrainUploadQueues, async {
+        mgr::on_each_tenant(&tenants, |tenant| async move {
+            tenant.drain_uploads_on_shutdown().await
+        })
+        .await
+    })
+    .await;
+
+    shutdown.run_phase(ShutdownPhase::StopTasks, async {
+        // This kills the checkpoint and GC tasks of the tenants.
+        mgr::on_each_tenant(&tenants, |tenant| async move {
+            tenant.shutdown_tasks().await
+        })
+        .await;
+
+        // Shut down the HTTP endpoint last, so that you can still check the server's
+        // status while it's shutting down.
+        task_mgr::shutdown_tasks(Some(TaskKind::HttpEndpointListener), None, None).await;
+
+        // There should be nothing left, but let's be sure
+        task_mgr::shutdown_tasks(None, None, None).await;
+    })
+    .await;
+
+    shutdown
+        .run_phase(ShutdownPhase::StopRuntimes, wait_for_idle_runtimes())
+        .await;
+
+    info!(elapsed = ?started_at.elapsed(), "Shut down successfully completed");
+    // Send the traces not exported yet, if any
+    tracing_utils::shutdown_tracing();
+    std::process::exit(exit_code);
+}
+
+/// Wait until the blocking threads of the runtimes are idle, e.g. that the WAL redo processes
+/// killed in the background are reaped, rather than left behind by the exit.
+///
+/// The runtimes can only be observed with `--cfg tokio_unstable`, without it this returns
+/// right away.
+async fn wait_for_idle_runtimes() {
+    #[cfg(tokio_unstable)]
+    loop {
+        let runtimes = [
+            &*task_mgr::COMPUTE_REQUEST_RUNTIME,
+            &*task_mgr::MGMT_REQUEST_RUNTIME,
+            &*task_mgr::WALRECEIVER_RUNTIME,
+            &*task_mgr::BACKGROUND_RUNTIME,
+        ];
+        let busy: usize = runtimes
+            .iter()
+            .map(|runtime| {
+                let metrics = runtime.handle().metrics();
+                // The two counts are read apart, at slightly different times
+                let idle = metrics.num_idle_blocking_threads();
+                metrics.num_blocking_threads().saturating_sub(idle)
+            })
+            .sum();
+        if busy == 0 {
+            break;
+        }
+        debug!(busy, "waiting for the blocking threads of the runtimes");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+struct Shutdown {
+    deadline: Instant,
+    exit_code: i32,
+}
+
+impl Shutdown {
+    /// Run one phase of the shutdown. Exits the process if the deadline is reached.
+    async fn run_phase<F: Future>(&self, phase: ShutdownPhase, fut: F) -> F::Output {
+        info!("shutdown phase {phase} started");
+        let started_at = Instant::now();
+        let res = tokio::time::timeout_at(self.deadline, fut).await;
+
+        let elapsed = started_at.elapsed();
+        let phase_str: &'static str = phase.into();
+        SHUTDOWN_PHASE_SECONDS
+            .with_label_values(&[phase_str])
+            .set(elapsed.as_secs_f64());
+
+        match res {
+            Ok(output) => {
+                info!(?elapsed, "shutdown phase {phase} finished");
+                output
+            }
+            Err(_) => {
+                warn!(?elapsed, "shutdown deadline reached in phase {phase}, exiting without completing the shutdown");
+                std::process::exit(self.exit_code);
+            }
+        }
+    }
+}
@@ -38,6 +38,25 @@ impl Statvfs {
         }
     }
 
+    /// Total number of inodes, zero if the filesystem doesn't limit them.
+    // NB: allow() because the file count type is u32 on macOS.
+    #[allow(clippy::useless_conversion)]
+    pub fn files(&self) -> u64 {
+        match self {
+            Statvfs::Real(stat) => u64::try_from(stat.files()).unwrap(),
+            Statvfs::Mock(stat) => stat.files,
+        }
+    }
+
+    // NB: allow() because the file count type is u32 on macOS.
+    #[allow(clippy::useless_conversion)]
+    pub fn files_available(&self) -> u64 {
+        match self {
+            Statvfs::Real(stat) => u64::try_from(stat.files_available()).unwrap(),
+            Statvfs::Mock(stat) => stat.files_available,
+        }
+    }
+
     pub fn fragment_size(&self) -> u64 {
         match self {
             Statvfs::Real(stat) => stat.fragment_size(),
@@ -113,6 +132,9 @@ pub mod mock {
                     blocks_available: avail_blocks,
                     fragment_size: *blocksize,
                     block_size: *blocksize,
+                    // no inode limit
+                    files: 0,
+                    files_available: 0,
                 })
             }
             Behavior::Failure { mocked_error } => Err((*mocked_error).into()),
@@ -146,5 +168,7 @@ pub mod mock {
         pub blocks_available: u64,
         pub fragment_size: u64,
         pub block_size: u64,
+        pub files: u64,
+        pub files_available: u64,
     }
 }
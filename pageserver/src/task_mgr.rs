@@ -18,16 +18,41 @@
 //! # Task shutdown
 //!
 //! To kill a task, we rely on co-operation from the victim. Each task is
-//! expected to periodically call the `is_shutdown_requested()` function, and
-//! if it returns true, exit gracefully. In addition to that, when waiting for
+//! expected to periodically check its `shutdown_token()`, and if it is
+//! cancelled, exit gracefully. In addition to that, when waiting for
 //! the network or other long-running operation, you can use
 //! `shutdown_watcher()` function to get a Future that will become ready if
 //! the current task has been requested to shut down. You can use that with
 //! Tokio select!().
 //!
-//! TODO: This would be a good place to also handle panics in a somewhat sane way.
-//! Depending on what task panics, we might want to kill the whole server, or
-//! only a single tenant or timeline.
+//! # Cancellation hierarchy
+//!
+//! The per-task cancellation tokens are arranged in a tree that mirrors the
+//! ownership of the tasks: pageserver → tenant → timeline → task. A task that
+//! is spawned for a timeline gets a child token of that timeline's token, a
+//! task spawned for a tenant only gets a child of the tenant's token, and
+//! global tasks get a child of the pageserver token.
+//!
+//! A token can't be moved to another parent, so a task that is spawned as a
+//! global task and then associated with a timeline with [`associate_with`], like
+//! a page_service connection, keeps the pageserver token as its parent. Its
+//! token is cancelled with the tenant or timeline subtree all the same, as long
+//! as the task stays associated with it.
+//!
+//! `shutdown_tasks(None, Some(tenant_id), None)` cancels the whole tenant
+//! subtree, including any tokens that were handed out with
+//! [`tenant_cancellation_token`] or [`timeline_cancellation_token`] to code
+//! that doesn't run as a task_mgr task (e.g. walredo or spawned helper tasks).
+//! So, code that gets hold of such a token is cancelled on detach/delete
+//! without any further wiring.
+//!
+//! # Panics
+//!
+//! A panic in a task is caught and logged by the task wrapper. For critical
+//! tasks that must not silently disappear, like the per-tenant compaction and
+//! GC loops, use [`spawn_supervised`]: it restarts the task with a backoff, and
+//! gives up after a configurable number of panics, letting the caller e.g. mark
+//! the tenant Broken.
 //!
 
 // Clippy 1.60 incorrectly complains about the tokio::task_local!() macro.
@@ -38,10 +63,15 @@ use std::collections::HashMap;
 use std::fmt;
 use std::future::Future;
 use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime};
 
 use futures::FutureExt;
+use metrics::Counter;
+use pin_project_lite::pin_project;
 use tokio::runtime::Runtime;
 use tokio::task::JoinHandle;
 use tokio::task_local;
@@ -50,10 +80,12 @@ use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
 use once_cell::sync::Lazy;
+use serde_with::serde_as;
 
 use utils::id::{TenantId, TimelineId};
 
-use crate::shutdown_pageserver;
+use crate::metrics::{SUPERVISED_TASK_PANICS, TASK_POLL_TIME, TASK_WALL_TIME};
+use crate::{exponential_backoff, shutdown_pageserver};
 
 //
 // There are four runtimes:
@@ -152,6 +184,107 @@ static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(1);
 static TASKS: Lazy<Mutex<HashMap<u64, Arc<PageServerTask>>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
+/// Root of the cancellation hierarchy. Cancelled only by the final
+/// `shutdown_tasks(None, None, None)` call during pageserver shutdown.
+static PAGESERVER_CANCEL: Lazy<CancellationToken> = Lazy::new(CancellationToken::new);
+
+/// Tenant and timeline nodes of the cancellation hierarchy, see the module docs.
+static CANCELLATION_TREE: Lazy<Mutex<CancellationTree>> =
+    Lazy::new(|| Mutex::new(CancellationTree::default()));
+
+#[derive(Default)]
+struct CancellationTree {
+    tenants: HashMap<TenantId, CancellationToken>,
+    timelines: HashMap<(TenantId, TimelineId), CancellationToken>,
+    /// The tokens of the tasks that were associated with a tenant or timeline after their
+    /// spawn, by task id, see [`associate_with`].
+    associated: HashMap<u64, (TenantId, Option<TimelineId>, CancellationToken)>,
+}
+
+impl CancellationTree {
+    fn tenant(&mut self, tenant_id: TenantId) -> &CancellationToken {
+        self.tenants
+            .entry(tenant_id)
+            .or_insert_with(|| PAGESERVER_CANCEL.child_token())
+    }
+
+    fn timeline(&mut self, tenant_id: TenantId, timeline_id: TimelineId) -> CancellationToken {
+        if let Some(token) = self.timelines.get(&(tenant_id, timeline_id)) {
+            return token.clone();
+        }
+        let token = self.tenant(tenant_id).child_token();
+        self.timelines
+            .insert((tenant_id, timeline_id), token.clone());
+        token
+    }
+
+    /// The token that a new task for the given tenant / timeline should derive its own token from.
+    fn parent_of(
+        &mut self,
+        tenant_id: Option<TenantId>,
+        timeline_id: Option<TimelineId>,
+    ) -> CancellationToken {
+        match (tenant_id, timeline_id) {
+            (Some(tenant_id), Some(timeline_id)) => self.timeline(tenant_id, timeline_id),
+            (Some(tenant_id), None) => self.tenant(tenant_id).clone(),
+            (None, _) => PAGESERVER_CANCEL.clone(),
+        }
+    }
+
+    /// Cancel the tenant subtree and forget it, so that a later re-attach of the
+    /// same tenant starts out with a fresh token.
+    fn cancel_tenant(&mut self, tenant_id: TenantId) {
+        if let Some(token) = self.tenants.remove(&tenant_id) {
+            token.cancel();
+        }
+        self.timelines.retain(|(t, _), _| *t != tenant_id);
+        self.cancel_associated(|t, _| t == tenant_id);
+    }
+
+    fn cancel_timeline(&mut self, tenant_id: TenantId, timeline_id: TimelineId) {
+        if let Some(token) = self.timelines.remove(&(tenant_id, timeline_id)) {
+            token.cancel();
+        }
+        self.cancel_associated(|t, tl| t == tenant_id && tl == Some(timeline_id));
+    }
+
+    fn cancel_associated(&mut self, matches: impl Fn(TenantId, Option<TimelineId>) -> bool) {
+        self.associated.retain(|_, (tenant_id, timeline_id, token)| {
+            if matches(*tenant_id, *timeline_id) {
+                token.cancel();
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+/// Get a token that is cancelled when the given tenant is shut down, i.e., on
+/// detach, ignore or pageserver shutdown.
+///
+/// Use it for work on behalf of the tenant that doesn't run as a task_mgr task.
+pub fn tenant_cancellation_token(tenant_id: TenantId) -> CancellationToken {
+    CANCELLATION_TREE
+        .lock()
+        .unwrap()
+        .tenant(tenant_id)
+        .child_token()
+}
+
+/// Like [`tenant_cancellation_token`], but is additionally cancelled when the
+/// timeline is deleted.
+pub fn timeline_cancellation_token(
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+) -> CancellationToken {
+    CANCELLATION_TREE
+        .lock()
+        .unwrap()
+        .timeline(tenant_id, timeline_id)
+        .child_token()
+}
+
 task_local! {
     // This is a cancellation token which will be cancelled when a task needs to shut down. The
     // root token is kept in the global registry, so that anyone can send the signal to request
@@ -231,12 +364,27 @@ pub enum TaskKind {
     // Compaction. One per tenant.
     Compaction,
 
+    /// See [`crate::tenant::deferred_deletion`]. One per tenant.
+    DeferredDeletion,
+
+    /// Moves the old layer files to the cold tier of the remote storage. One per tenant.
+    Tiering,
+
+    /// Checks that the WAL redo of the tenant works. One per tenant.
+    WalRedoPing,
+
     // Eviction. One per timeline.
     Eviction,
 
     /// See [`crate::disk_usage_eviction_task`].
     DiskUsageEviction,
 
+    /// See [`crate::disk_space_monitor`].
+    DiskSpaceMonitor,
+
+    /// See [`crate::remote_scrubber`].
+    RemoteScrub,
+
     // Initial logical size calculation
     InitialLogicalSizeCalculation,
 
@@ -251,6 +399,9 @@ pub enum TaskKind {
     // Task that downloads a file from remote storage
     RemoteDownloadTask,
 
+    /// Reads ahead of a sequential scan of a relation, see [`crate::tenant::timeline::prefetch`].
+    Prefetch,
+
     // task that handles the initial downloading of all tenants
     InitialLoad,
 
@@ -263,6 +414,12 @@ pub enum TaskKind {
     // task that handhes metrics collection
     MetricsCollection,
 
+    // task that pushes the metrics registry to a Prometheus Pushgateway
+    MetricsPush,
+
+    // task that posts the upload queue events to a webhook
+    UploadEvents,
+
     // task that drives downloading layers
     DownloadAllRemoteLayers,
     // Task that calculates synthetis size for all active tenants
@@ -289,19 +446,46 @@ struct MutableTaskState {
 }
 
 struct PageServerTask {
-    #[allow(dead_code)] // unused currently
     task_id: PageserverTaskId,
 
     kind: TaskKind,
 
     name: String,
 
+    /// When the task was spawned, for introspection.
+    spawned_at: SystemTime,
+
     // To request task shutdown, just cancel this token.
     cancel: CancellationToken,
 
     mutable: Mutex<MutableTaskState>,
 }
 
+/// State of a task as reported by [`list_tasks`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum TaskState {
+    Running,
+    /// Shutdown has been requested, but the task has not exited yet.
+    ShuttingDown,
+}
+
+/// A point-in-time description of a live task, for the `/v1/debug/tasks` endpoint.
+#[serde_as]
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TaskInfo {
+    pub task_id: u64,
+    pub kind: TaskKind,
+    pub name: String,
+    #[serde_as(as = "Option<serde_with::DisplayFromStr>")]
+    pub tenant_id: Option<TenantId>,
+    #[serde_as(as = "Option<serde_with::DisplayFromStr>")]
+    pub timeline_id: Option<TimelineId>,
+    #[serde(rename = "spawned_at_millis_since_epoch")]
+    #[serde_as(as = "serde_with::TimestampMilliSeconds")]
+    pub spawned_at: SystemTime,
+    pub state: TaskState,
+}
+
 /// Launch a new task
 /// Note: if shutdown_process_on_error is set to true failure
 ///   of the task will lead to shutdown of entire process
@@ -317,12 +501,17 @@ pub fn spawn<F>(
 where
     F: Future<Output = anyhow::Result<()>> + Send + 'static,
 {
-    let cancel = CancellationToken::new();
+    let cancel = CANCELLATION_TREE
+        .lock()
+        .unwrap()
+        .parent_of(tenant_id, timeline_id)
+        .child_token();
     let task_id = NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed);
     let task = Arc::new(PageServerTask {
         task_id: PageserverTaskId(task_id),
         kind,
         name: name.to_string(),
+        spawned_at: SystemTime::now(),
         cancel: cancel.clone(),
         mutable: Mutex::new(MutableTaskState {
             tenant_id,
@@ -352,6 +541,117 @@ where
     PageserverTaskId(task_id)
 }
 
+/// How [`spawn_supervised`] handles panics of the supervised task.
+#[derive(Debug, Clone, Copy)]
+pub struct RestartPolicy {
+    /// How many times the task is restarted after a panic before giving up.
+    pub max_restarts: u32,
+    pub base_backoff_seconds: f64,
+    pub max_backoff_seconds: f64,
+}
+
+impl RestartPolicy {
+    /// Policy for the per-tenant background loops like compaction and GC.
+    pub const CRITICAL_BACKGROUND_LOOP: RestartPolicy = RestartPolicy {
+        max_restarts: 5,
+        base_backoff_seconds: 1.0,
+        max_backoff_seconds: 60.0,
+    };
+}
+
+/// Launch a new task that is restarted if it panics.
+///
+/// `make_future` is called to create the future for every (re)start. Restarts
+/// happen within the same task_mgr task, so the task-locals like the shutdown
+/// token carry over, and no restart happens after shutdown has been requested.
+///
+/// Once the task panicked more than `policy.max_restarts` times, `on_give_up`
+/// is awaited with a description of the last panic, and the task exits.
+///
+/// Errors returned by the future are not retried; they are handled the same way
+/// as for [`spawn`].
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_supervised<M, F, G, GF>(
+    runtime: &tokio::runtime::Handle,
+    kind: TaskKind,
+    tenant_id: Option<TenantId>,
+    timeline_id: Option<TimelineId>,
+    name: &str,
+    policy: RestartPolicy,
+    make_future: M,
+    on_give_up: G,
+) -> PageserverTaskId
+where
+    M: Fn() -> F + Send + 'static,
+    F: Future<Output = anyhow::Result<()>> + Send + 'static,
+    G: FnOnce(String) -> GF + Send + 'static,
+    GF: Future<Output = ()> + Send + 'static,
+{
+    let task_name = name.to_string();
+    spawn(
+        runtime,
+        kind,
+        tenant_id,
+        timeline_id,
+        name,
+        false,
+        async move {
+            let mut panics = 0;
+            loop {
+                let panic = match AssertUnwindSafe(make_future()).catch_unwind().await {
+                    Ok(result) => return result,
+                    Err(panic) => panic_message(&*panic),
+                };
+                panics += 1;
+                SUPERVISED_TASK_PANICS
+                    .with_label_values(&[kind.into()])
+                    .inc();
+                error!(
+                    task = %task_name,
+                    ?kind,
+                    ?tenant_id,
+                    ?timeline_id,
+                    panics,
+                    max_restarts = policy.max_restarts,
+                    "supervised task panicked: {panic}"
+                );
+
+                if shutdown_token().is_cancelled() {
+                    return Ok(());
+                }
+                if panics > policy.max_restarts {
+                    error!(task = %task_name, "supervised task panicked too many times, giving up");
+                    on_give_up(format!(
+                        "task '{task_name}' panicked {panics} times, last panic: {panic}"
+                    ))
+                    .await;
+                    return Ok(());
+                }
+
+                tokio::select! {
+                    _ = shutdown_watcher() => return Ok(()),
+                    _ = exponential_backoff(
+                        panics,
+                        policy.base_backoff_seconds,
+                        policy.max_backoff_seconds,
+                    ) => {}
+                }
+                info!(task = %task_name, panics, "restarting supervised task");
+            }
+        },
+    )
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    if let Some(s) = panic.downcast_ref::<&'static str>() {
+        s.to_string()
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "<non-string panic payload>".to_string()
+    }
+}
+
 /// This wrapper function runs in a newly-spawned task. It initializes the
 /// task-local variables and calls the payload function.
 async fn task_wrapper<F>(
@@ -366,8 +666,10 @@ async fn task_wrapper<F>(
 {
     debug!("Starting task '{}'", task_name);
 
-    let result = SHUTDOWN_TOKEN
-        .scope(
+    let kind = task.kind;
+    let result = TimedTask::new(
+        kind,
+        SHUTDOWN_TOKEN.scope(
             shutdown_token,
             CURRENT_TASK.scope(task, {
                 // We use AssertUnwindSafe here so that the payload function
@@ -375,11 +677,64 @@ async fn task_wrapper<F>(
                 // unwinding that would expose us to unwind-unsafe behavior.
                 AssertUnwindSafe(future).catch_unwind()
             }),
-        )
-        .await;
+        ),
+    )
+    .await;
     task_finish(result, task_name, task_id, shutdown_process_on_error).await;
 }
 
+pin_project! {
+    /// Accounts the time spent polling the inner future (i.e. the time it occupied a
+    /// runtime worker thread) and the wall-clock time it has been alive, per [`TaskKind`].
+    ///
+    /// Wall time is accounted incrementally on every poll, so that long-lived tasks
+    /// like the background loops show up in the metrics before they exit.
+    ///
+    /// Each poll is also marked as async context for [`utils::instrumented_mutex`].
+    struct TimedTask<F> {
+        #[pin]
+        inner: F,
+        poll_time: Counter,
+        wall_time: Counter,
+        last_accounted: Option<Instant>,
+    }
+}
+
+impl<F> TimedTask<F> {
+    fn new(kind: TaskKind, inner: F) -> Self {
+        let kind: &'static str = kind.into();
+        TimedTask {
+            inner,
+            poll_time: TASK_POLL_TIME.with_label_values(&[kind]),
+            wall_time: TASK_WALL_TIME.with_label_values(&[kind]),
+            last_accounted: None,
+        }
+    }
+}
+
+impl<F: Future> Future for TimedTask<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let poll_start = Instant::now();
+        if let Some(last_accounted) = this.last_accounted {
+            this.wall_time
+                .inc_by(poll_start.duration_since(*last_accounted).as_secs_f64());
+        }
+        let res = {
+            let _async_poll = utils::instrumented_mutex::enter_async_poll();
+            this.inner.poll(cx)
+        };
+        let poll_end = Instant::now();
+        let poll_duration = poll_end.duration_since(poll_start).as_secs_f64();
+        this.poll_time.inc_by(poll_duration);
+        this.wall_time.inc_by(poll_duration);
+        *this.last_accounted = Some(poll_end);
+        res
+    }
+}
+
 async fn task_finish(
     result: std::result::Result<
         anyhow::Result<()>,
@@ -395,6 +750,11 @@ async fn task_finish(
         .unwrap()
         .remove(&task_id)
         .expect("no task in registry");
+    CANCELLATION_TREE
+        .lock()
+        .unwrap()
+        .associated
+        .remove(&task_id);
 
     let mut shutdown_process = false;
     {
@@ -446,6 +806,18 @@ pub fn associate_with(tenant_id: Option<TenantId>, timeline_id: Option<TimelineI
         let mut task_mut = ct.mutable.lock().unwrap();
         task_mut.tenant_id = tenant_id;
         task_mut.timeline_id = timeline_id;
+
+        // The parent of the task's token stays the one it was spawned with, see the module docs
+        let mut tree = CANCELLATION_TREE.lock().unwrap();
+        match tenant_id {
+            Some(tenant_id) => {
+                let associated = (tenant_id, timeline_id, ct.cancel.clone());
+                tree.associated.insert(ct.task_id.0, associated);
+            }
+            None => {
+                tree.associated.remove(&ct.task_id.0);
+            }
+        }
     });
 }
 
@@ -463,6 +835,9 @@ pub fn associate_with(tenant_id: Option<TenantId>, timeline_id: Option<TimelineI
 ///
 ///   shutdown_tasks(None, Some(tenant_id), Some(timeline_id))
 ///
+/// If `kind` is None, the corresponding subtree of the cancellation hierarchy is
+/// cancelled as well, see the module docs.
+///
 pub async fn shutdown_tasks(
     kind: Option<TaskKind>,
     tenant_id: Option<TenantId>,
@@ -470,6 +845,18 @@ pub async fn shutdown_tasks(
 ) {
     let mut victim_tasks = Vec::new();
 
+    if kind.is_none() {
+        let mut tree = CANCELLATION_TREE.lock().unwrap();
+        match (tenant_id, timeline_id) {
+            (Some(tenant_id), Some(timeline_id)) => tree.cancel_timeline(tenant_id, timeline_id),
+            (Some(tenant_id), None) => tree.cancel_tenant(tenant_id),
+            (None, None) => PAGESERVER_CANCEL.cancel(),
+            // Shutting down a timeline across all tenants is not a thing; only
+            // cancel the matching tasks below.
+            (None, Some(_)) => {}
+        }
+    }
+
     {
         let tasks = TASKS.lock().unwrap();
         for task in tasks.values() {
@@ -531,6 +918,38 @@ pub async fn shutdown_tasks(
     }
 }
 
+/// Describe all tasks that are currently registered, ordered by task id.
+///
+/// Tasks that have been requested to shut down but have not exited yet are
+/// reported as [`TaskState::ShuttingDown`], which makes it easy to spot tasks
+/// that are stuck and ignore their cancellation token.
+pub fn list_tasks() -> Vec<TaskInfo> {
+    let mut infos = {
+        let tasks = TASKS.lock().unwrap();
+        tasks
+            .values()
+            .map(|task| {
+                let task_mut = task.mutable.lock().unwrap();
+                TaskInfo {
+                    task_id: task.task_id.0,
+                    kind: task.kind,
+                    name: task.name.clone(),
+                    tenant_id: task_mut.tenant_id,
+                    timeline_id: task_mut.timeline_id,
+                    spawned_at: task.spawned_at,
+                    state: if task.cancel.is_cancelled() {
+                        TaskState::ShuttingDown
+                    } else {
+                        TaskState::Running
+                    },
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+    infos.sort_by_key(|info| info.task_id);
+    infos
+}
+
 pub fn current_task_kind() -> Option<TaskKind> {
     CURRENT_TASK.try_with(|ct| ct.kind).ok()
 }
@@ -559,15 +978,3 @@ pub fn shutdown_token() -> CancellationToken {
         .try_with(|t| t.clone())
         .expect("shutdown_token() called in an unexpected task or thread")
 }
-
-/// Has the current task been requested to shut down?
-pub fn is_shutdown_requested() -> bool {
-    if let Ok(cancel) = SHUTDOWN_TOKEN.try_with(|t| t.clone()) {
-        cancel.is_cancelled()
-    } else {
-        if !cfg!(test) {
-            warn!("is_shutdown_requested() called in an unexpected task or thread");
-        }
-        false
-    }
-}
@@ -13,13 +13,15 @@
 
 use anyhow::{bail, Context};
 use futures::FutureExt;
-use pageserver_api::models::TimelineState;
+use pageserver_api::models::{TimelineState, WalRedoProcessInfo};
 use remote_storage::DownloadError;
 use remote_storage::GenericRemoteStorage;
 use storage_broker::BrokerClientChannel;
 use tokio::sync::watch;
 use tokio::sync::OwnedMutexGuard;
+use tokio::sync::Semaphore;
 use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
 use tracing::*;
 use utils::completion;
 use utils::crashsafe::path_with_suffix_extension;
@@ -32,21 +34,24 @@ use std::ffi::OsStr;
 use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::future::Future;
 use std::io;
 use std::io::Write;
+use std::num::NonZeroUsize;
 use std::ops::Bound::Included;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 use std::process::Stdio;
 use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::sync::MutexGuard;
-use std::sync::{Mutex, RwLock};
-use std::time::{Duration, Instant};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
 
 use self::config::TenantConf;
+use self::generation::Generation;
 use self::metadata::TimelineMetadata;
 use self::remote_timeline_client::RemoteTimelineClient;
 use self::timeline::uninit::TimelineUninitMark;
@@ -60,11 +65,13 @@ use crate::metrics::{remove_tenant_metrics, TENANT_STATE_METRIC, TENANT_SYNTHETI
 use crate::repository::GcResult;
 use crate::task_mgr;
 use crate::task_mgr::TaskKind;
-use crate::tenant::config::TenantConfOpt;
+use crate::tenant::config::{TenantConfOpt, TenantRemoteStorageConfig, TieringPolicy};
+use crate::tenant::deferred_deletion::{DeferredDeletion, DeferredDeletions};
+use crate::tenant::deletion_dry_run::DeletionDryRun;
 use crate::tenant::metadata::load_metadata;
 use crate::tenant::remote_timeline_client::index::IndexPart;
 use crate::tenant::remote_timeline_client::MaybeDeletedIndexPart;
-use crate::tenant::remote_timeline_client::PersistIndexPartWithDeletedFlagError;
+use crate::tenant::remote_timeline_client::{UploadConcurrencyLimit, UploadThrottle};
 use crate::tenant::storage_layer::DeltaLayer;
 use crate::tenant::storage_layer::ImageLayer;
 use crate::tenant::storage_layer::Layer;
@@ -73,14 +80,19 @@ use crate::InitializationOrder;
 use crate::tenant::timeline::uninit::cleanup_timeline_directory;
 use crate::virtual_file::VirtualFile;
 use crate::walredo::PostgresRedoManager;
+use crate::walredo::WalRedoLimits;
 use crate::walredo::WalRedoManager;
 use crate::TEMP_FILE_SUFFIX;
+use crate::TENANT_GENERATION_FILE_NAME;
+use crate::TENANT_IMPORT_FILE_NAME;
+use crate::TENANT_REMOTE_STORAGE_FILE_NAME;
 pub use pageserver_api::models::TenantState;
 
 use toml_edit;
 use utils::{
     crashsafe,
     id::{TenantId, TimelineId},
+    instrumented_mutex::{Mutex, MutexGuard},
     lsn::{Lsn, RecordLsn},
 };
 
@@ -117,6 +129,10 @@ mod remote_timeline_client;
 pub mod storage_layer;
 
 pub mod config;
+pub mod deferred_deletion;
+pub mod deletion_dry_run;
+pub mod download_limiter;
+pub mod generation;
 pub mod mgr;
 pub mod tasks;
 pub mod upload_queue;
@@ -129,6 +145,26 @@ pub(crate) use timeline::span::debug_assert_current_span_has_tenant_and_timeline
 pub use timeline::{
     LocalLayerInfoForDiskUsageEviction, LogicalSizeCalculationCause, PageReconstructError, Timeline,
 };
+pub use remote_timeline_client::create_remote_storage;
+pub use remote_timeline_client::mirror::init as init_remote_storage_mirror;
+pub use remote_timeline_client::events::init_configured_sink as init_upload_event_sink;
+pub use remote_timeline_client::events::{
+    UploadEventSink, UploadEventsConfig, UploadQueueEvent, UploadQueueEventKind,
+};
+pub use remote_timeline_client::fault_injection::REMOTE_STORAGE_FAULTS;
+pub use remote_timeline_client::CancelTaskError;
+pub use remote_timeline_client::{LayerUploadEvent, LayerUploadFailure};
+pub use remote_timeline_client::ParallelDownloadConfig;
+pub use remote_timeline_client::PersistIndexPartWithDeletedFlagError;
+pub use remote_timeline_client::RemoteCompressionConfig;
+pub use remote_timeline_client::TenantImport;
+pub use remote_timeline_client::RemoteEncryptionConfig;
+pub use remote_timeline_client::RemoteListingConfig;
+pub use remote_timeline_client::RemoteMultipartUploadConfig;
+pub use remote_timeline_client::LayerPathScheme;
+pub use remote_timeline_client::{remote_storage_health, RemoteCircuitBreakerConfig};
+pub use remote_timeline_client::{restore_index_snapshot, RestoreIndexError};
+pub use remote_timeline_client::{GiveUp, RemoteRetryConfig, RetryPolicy};
 
 // re-export this function so that page_cache.rs can use it.
 pub use crate::tenant::ephemeral_file::writeback as writeback_ephemeral_file;
@@ -139,6 +175,11 @@ pub use crate::tenant::metadata::save_metadata;
 // re-export for use in walreceiver
 pub use crate::tenant::timeline::WalReceiverInfo;
 
+// re-export for the ingest_coalescing setting
+pub use crate::tenant::timeline::coalescing::IngestCoalescingConfig;
+// re-export for the layer_watermarks setting
+pub use crate::tenant::timeline::watermarks::{LayerWatermarksConfig, Watermark};
+
 /// Parts of the `.neon/tenants/<tenant_id>/timelines/<timeline_id>` directory prefix.
 pub const TIMELINES_SEGMENT_NAME: &str = "timelines";
 
@@ -177,11 +218,36 @@ pub struct Tenant {
     // provides access to timeline data sitting in the remote storage
     remote_storage: Option<GenericRemoteStorage>,
 
+    /// Limits the layer uploads of all the timelines, see [`UploadThrottle`].
+    upload_throttle: Arc<UploadThrottle>,
+
+    /// Limits the layer uploads in progress of each timeline, see [`UploadConcurrencyLimit`].
+    /// Follows the tenant config.
+    upload_concurrency_limit: Arc<UploadConcurrencyLimit>,
+
+    /// The remote layer file deletions of all the timelines waiting for their grace period,
+    /// see [`deferred_deletion`].
+    deferred_deletions: Arc<DeferredDeletions>,
+
+    /// Whether the remote layer file deletions of all the timelines are only recorded, see
+    /// [`deletion_dry_run`]. Follows the tenant config.
+    deletion_dry_run: Arc<DeletionDryRun>,
+
+    /// The generation the tenant was attached with, see [`generation`].
+    generation: Option<Generation>,
+
+    /// Serializes the updates of the timeline manifest in the remote storage, see
+    /// [`Tenant::add_to_timeline_manifest`].
+    timeline_manifest_lock: tokio::sync::Mutex<()>,
+
     /// Cached logical sizes updated updated on each [`Tenant::gather_size_inputs`].
     cached_logical_sizes: tokio::sync::Mutex<HashMap<(TimelineId, Lsn), u64>>,
     cached_synthetic_tenant_size: Arc<AtomicU64>,
 
     eviction_task_tenant_state: tokio::sync::Mutex<EvictionTaskTenantState>,
+
+    /// Number of prefetches of the timelines in flight, limited by `prefetch_max_inflight`.
+    prefetches_inflight: Arc<AtomicUsize>,
 }
 
 // We should not blindly overwrite local metadata with remote one.
@@ -334,6 +400,8 @@ impl DeletionGuard {
 pub enum CreateTimelineError {
     #[error("a timeline with the given ID already exists")]
     AlreadyExists,
+    #[error("disk usage is critical, not creating new timelines")]
+    DiskSpaceCritical,
     #[error(transparent)]
     AncestorLsn(anyhow::Error),
     #[error(transparent)]
@@ -471,6 +539,8 @@ impl Tenant {
         // TODO dedup with spawn_load
         let tenant_conf =
             Self::load_tenant_config(conf, &tenant_id).context("load tenant config")?;
+        let generation = Generation::load(&conf.tenant_generation_path(&tenant_id))
+            .context("load tenant generation")?;
 
         let wal_redo_manager = Arc::new(PostgresRedoManager::new(conf, tenant_id));
         let tenant = Arc::new(Tenant::new(
@@ -480,6 +550,7 @@ impl Tenant {
             wal_redo_manager,
             tenant_id,
             Some(remote_storage),
+            generation,
         ));
 
         // Do all the hard work in the background
@@ -494,7 +565,9 @@ impl Tenant {
             "attach tenant",
             false,
             async move {
-                match tenant_clone.attach(&ctx).await {
+                // Detaching the tenant cancels the downloads of the attach
+                let attach_ctx = ctx.with_cancel(task_mgr::shutdown_token());
+                match tenant_clone.attach(&attach_ctx).await {
                     Ok(()) => {
                         info!("attach finished, activating");
                         tenant_clone.activate(broker_client, None, &ctx);
@@ -545,32 +618,67 @@ impl Tenant {
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("cannot attach without remote storage"))?;
 
-        let remote_timeline_ids = remote_timeline_client::list_remote_timelines(
-            remote_storage,
+        let import_file = self.conf.tenant_import_path(&self.tenant_id);
+        let import = TenantImport::load(&import_file)?;
+        if let Some(import) = &import {
+            info!("importing remote timelines");
+            remote_timeline_client::import_remote_timelines(
+                self.conf,
+                remote_storage,
+                self.tenant_id,
+                self.generation,
+                import,
+                ctx.cancel(),
+            )
+            .await
+            .context("import remote timelines")?;
+        }
+
+        // The manifest predates the import, list the timelines imported
+        let remote_timelines = remote_timeline_client::list_timelines_for_attach(
             self.conf,
+            remote_storage,
             self.tenant_id,
+            import.is_none(),
+            ctx.cancel(),
         )
         .await?;
+        let from_manifest = remote_timelines.from_manifest;
 
-        info!("found {} timelines", remote_timeline_ids.len());
+        info!("found {} timelines", remote_timelines.timeline_ids.len());
 
         // Download & parse index parts
+        let max_downloads = self.conf.remote_listing.max_concurrent_index_downloads;
+        let download_permits = Arc::new(Semaphore::new(max_downloads.get()));
         let mut part_downloads = JoinSet::new();
-        for timeline_id in remote_timeline_ids {
+        for timeline_id in remote_timelines.timeline_ids {
             let client = RemoteTimelineClient::new(
                 remote_storage.clone(),
                 self.conf,
                 self.tenant_id,
                 timeline_id,
+                Arc::clone(&self.upload_throttle),
+                Arc::clone(&self.upload_concurrency_limit),
+                Arc::clone(&self.deferred_deletions),
+                Arc::clone(&self.deletion_dry_run),
+                self.generation,
             );
+            let cancel = ctx.cancel().clone();
+            let download_permits = Arc::clone(&download_permits);
             part_downloads.spawn(
                 async move {
+                    let _permit = download_permits
+                        .acquire()
+                        .await
+                        .expect("the semaphore is never closed");
                     debug!("starting index part download");
 
-                    let index_part = client
-                        .download_index_file()
-                        .await
-                        .context("download index file")?;
+                    let index_part = match client.download_index_file(&cancel).await {
+                        Ok(index_part) => Some(index_part),
+                        // The manifest may have timelines whose creation failed
+                        Err(DownloadError::NotFound) if from_manifest => None,
+                        Err(e) => return Err(e).context("download index file"),
+                    };
 
                     debug!("finished index part download");
 
@@ -589,6 +697,10 @@ impl Tenant {
             // NB: we already added timeline_id as context to the error
             let result: Result<_, anyhow::Error> = result.context("joinset task join")?;
             let (timeline_id, client, index_part) = result?;
+            let Some(index_part) = index_part else {
+                info!("timeline {timeline_id} of the manifest has no index, skipping");
+                continue;
+            };
             debug!("successfully downloaded index part for timeline {timeline_id}");
             match index_part {
                 MaybeDeletedIndexPart::IndexPart(index_part) => {
@@ -625,6 +737,16 @@ impl Tenant {
                 })?;
         }
 
+        // The timelines are all imported by now, an attach resumed without the import file
+        // finds them in the remote storage of the tenant
+        match std::fs::remove_file(&import_file) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("unlink import file {}", import_file.display()))
+            }
+        }
         std::fs::remove_file(&marker_file)
             .with_context(|| format!("unlink attach marker file {}", marker_file.display()))?;
         crashsafe::fsync(marker_file.parent().expect("marker file has parent dir"))
@@ -637,6 +759,28 @@ impl Tenant {
         Ok(())
     }
 
+    /// Record a timeline about to be created in the timeline manifest of the tenant, so that
+    /// an attach which takes the timelines from the manifest finds it. Call this before the
+    /// first index upload of the timeline.
+    pub(crate) async fn add_to_timeline_manifest(
+        &self,
+        timeline_id: TimelineId,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let Some(remote_storage) = &self.remote_storage else {
+            return Ok(());
+        };
+        let _guard = self.timeline_manifest_lock.lock().await;
+        remote_timeline_client::add_to_timeline_manifest(
+            self.conf,
+            remote_storage,
+            self.tenant_id,
+            timeline_id,
+            cancel,
+        )
+        .await
+    }
+
     /// get size of all remote timelines
     ///
     /// This function relies on the index_part instead of listing the remote storage
@@ -720,6 +864,7 @@ impl Tenant {
             wal_redo_manager,
             tenant_id,
             None,
+            None,
         ))
     }
 
@@ -750,6 +895,13 @@ impl Tenant {
                 return Tenant::create_broken_tenant(conf, tenant_id, format!("{e:#}"));
             }
         };
+        let generation = match Generation::load(&conf.tenant_generation_path(&tenant_id)) {
+            Ok(generation) => generation,
+            Err(e) => {
+                error!("load tenant generation failed: {:?}", e);
+                return Tenant::create_broken_tenant(conf, tenant_id, format!("{e:#}"));
+            }
+        };
 
         let wal_redo_manager = Arc::new(PostgresRedoManager::new(conf, tenant_id));
         let tenant = Tenant::new(
@@ -759,6 +911,7 @@ impl Tenant {
             wal_redo_manager,
             tenant_id,
             remote_storage,
+            generation,
         );
         let tenant = Arc::new(tenant);
 
@@ -780,7 +933,8 @@ impl Tenant {
                 // these tasks complete.
                 let _completion = init_order.as_mut().and_then(|x| x.initial_tenant_load.take());
 
-                match tenant_clone.load(init_order.as_ref(), &ctx).await {
+                let load_ctx = ctx.with_cancel(task_mgr::shutdown_token());
+                match tenant_clone.load(init_order.as_ref(), &load_ctx).await {
                     Ok(()) => {
                         debug!("load finished, activating");
                         let background_jobs_can_start = init_order.as_ref().map(|x| &x.background_jobs_can_start);
@@ -933,6 +1087,11 @@ impl Tenant {
 
         utils::failpoint_sleep_millis_async!("before-loading-tenant");
 
+        // Before the upload queues of the timelines defer any more deletions
+        self.deferred_deletions
+            .load()
+            .context("load deferred deletions")?;
+
         // Load in-memory state to reflect the local files on disk
         //
         // Scan the directory, peek into the metadata file of each timeline, and
@@ -981,6 +1140,11 @@ impl Tenant {
                 self.conf,
                 self.tenant_id,
                 timeline_id,
+                Arc::clone(&self.upload_throttle),
+                Arc::clone(&self.upload_concurrency_limit),
+                Arc::clone(&self.deferred_deletions),
+                Arc::clone(&self.deletion_dry_run),
+                self.generation,
             )
         });
 
@@ -993,7 +1157,7 @@ impl Tenant {
         };
 
         let (remote_startup_data, remote_client) = match remote_client {
-            Some(remote_client) => match remote_client.download_index_file().await {
+            Some(remote_client) => match remote_client.download_index_file(ctx.cancel()).await {
                 Ok(index_part) => {
                     let index_part = match index_part {
                         MaybeDeletedIndexPart::IndexPart(index_part) => index_part,
@@ -1251,6 +1415,13 @@ impl Tenant {
             return Err(CreateTimelineError::AlreadyExists);
         }
 
+        if crate::disk_space_monitor::timeline_creation_refused() {
+            return Err(CreateTimelineError::DiskSpaceCritical);
+        }
+
+        self.add_to_timeline_manifest(new_timeline_id, ctx.cancel())
+            .await?;
+
         let loaded_timeline = match ancestor_timeline_id {
             Some(ancestor_timeline_id) => {
                 let ancestor_timeline = self
@@ -1305,6 +1476,80 @@ impl Tenant {
         Ok(loaded_timeline)
     }
 
+    /// Check that the WAL redo works for the Postgres versions of the timelines, see
+    /// [`PostgresRedoManager::ping`]. Called when the tenant is activated, and periodically
+    /// after that, by the WAL redo ping task.
+    pub(crate) async fn ping_walredo(&self) -> anyhow::Result<()> {
+        let pg_versions: BTreeSet<u32> = self
+            .timelines
+            .lock()
+            .unwrap()
+            .values()
+            .map(|timeline| timeline.pg_version)
+            .collect();
+        for pg_version in pg_versions {
+            self.walredo_mgr
+                .ping(pg_version)
+                .await
+                .with_context(|| format!("WAL redo of Postgres {pg_version} is not working"))?;
+        }
+        Ok(())
+    }
+
+    /// Delete the remote layer files whose grace period has ended, see [`deferred_deletion`].
+    /// This is periodically called by the deferred deletion task.
+    pub(crate) async fn execute_deferred_deletions(&self) -> anyhow::Result<()> {
+        let Some(remote_storage) = &self.remote_storage else {
+            return Ok(());
+        };
+
+        let mut due_by_timeline: HashMap<TimelineId, Vec<DeferredDeletion>> = HashMap::new();
+        for deletion in self.deferred_deletions.due(SystemTime::now()) {
+            due_by_timeline
+                .entry(deletion.timeline_id)
+                .or_default()
+                .push(deletion);
+        }
+
+        for (timeline_id, deletions) in due_by_timeline {
+            let layer_file_names = deletions
+                .iter()
+                .map(|deletion| deletion.layer_file_name.clone())
+                .collect::<Vec<_>>();
+            let remote_client = match self.get_timeline(timeline_id, false) {
+                Ok(timeline) => timeline.remote_client.clone(),
+                Err(_) => None,
+            };
+            let res = match remote_client {
+                Some(remote_client) => {
+                    remote_client
+                        .execute_deferred_deletions(&layer_file_names)
+                        .await
+                }
+                // The timeline has been deleted since, along with its upload queue
+                None => {
+                    remote_timeline_client::delete_layer_files(
+                        self.conf,
+                        remote_storage,
+                        self.tenant_id,
+                        timeline_id,
+                        &layer_file_names,
+                    )
+                    .await
+                }
+            };
+            match res {
+                Ok(()) => {
+                    info!(%timeline_id, "executed {} deferred deletions", deletions.len());
+                    self.deferred_deletions.remove(&deletions)?;
+                }
+                // Retried in the next iteration
+                Err(e) => warn!(%timeline_id, "failed to execute deferred deletions: {e:#}"),
+            }
+        }
+        Ok(())
+    }
+
     /// perform one garbage collection iteration, removing old data files from disk.
     /// this function is periodically called by gc task.
     /// also it can be explicitly requested through page server api 'do_gc' command.
@@ -1375,54 +1620,135 @@ impl Tenant {
         Ok(())
     }
 
-    /// Flush all in-memory data to disk and remote storage, if any.
-    ///
-    /// Used at graceful shutdown.
-    async fn freeze_and_flush_on_shutdown(&self) {
-        let mut js = tokio::task::JoinSet::new();
+    /// Check the layer watermarks of all active timelines. Called after each compaction
+    /// iteration.
+    pub(crate) async fn check_layer_watermarks(&self) {
+        let timelines = self
+            .timelines
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|timeline| timeline.is_active())
+            .cloned()
+            .collect::<Vec<_>>();
 
-        // execute on each timeline on the JoinSet, join after.
-        let per_timeline = |timeline_id: TimelineId, timeline: Arc<Timeline>| {
+        for timeline in timelines {
+            let timeline_id = timeline.timeline_id;
+            timeline
+                .check_layer_watermarks()
+                .instrument(info_span!("check_layer_watermarks", %timeline_id))
+                .await;
+        }
+    }
+
+    /// Move the old layer files of all active timelines to the cold tier of the remote storage,
+    /// see [`TieringPolicy`]. Called periodically by the tiering task.
+    pub(crate) async fn tiering_iteration(
+        &self,
+        threshold: Duration,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let timelines = self
+            .timelines
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|timeline| timeline.is_active())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for timeline in timelines {
+            let Some(remote_client) = &timeline.remote_client else {
+                continue;
+            };
+            let timeline_id = timeline.timeline_id;
+            let res = remote_client
+                .tier_layers(threshold, cancel)
+                .instrument(info_span!("tier_layers", %timeline_id))
+                .await;
+            match res {
+                Ok(report) if report.tiered_layers > 0 || report.deleted_leftovers > 0 => {
+                    info!(
+                        %timeline_id,
+                        tiered_layers = report.tiered_layers,
+                        tiered_bytes = report.tiered_bytes,
+                        deleted_leftovers = report.deleted_leftovers,
+                        "moved layer files to the cold tier"
+                    );
+                }
+                Ok(_) => {}
+                // Retried in the next iteration
+                Err(e) => warn!(%timeline_id, "failed to tier layer files: {e:#}"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush all in-memory data to disk. Used at graceful shutdown.
+    pub(crate) async fn flush_on_shutdown(&self) {
+        self.on_each_timeline(|timeline_id, timeline| {
             async move {
                 debug_assert_current_span_has_tenant_and_timeline_id();
-
-                match timeline.freeze_and_flush().await {
-                    Ok(()) => {}
-                    Err(e) => {
-                        warn!("failed to freeze and flush: {e:#}");
-                        return;
-                    }
+                if let Err(e) = timeline.freeze_and_flush().await {
+                    warn!("failed to freeze and flush: {e:#}");
                 }
+            }
+            .instrument(tracing::info_span!("flush_on_shutdown", %timeline_id))
+        })
+        .await
+    }
 
-                let res = if let Some(client) = timeline.remote_client.as_ref() {
-                    // if we did not wait for completion here, it might be our shutdown process
-                    // didn't wait for remote uploads to complete at all, as new tasks can forever
-                    // be spawned.
-                    //
-                    // what is problematic is the shutting down of RemoteTimelineClient, because
-                    // obviously it does not make sense to stop while we wait for it, but what
-                    // about corner cases like s3 suddenly hanging up?
-                    client.wait_completion().await
-                } else {
-                    Ok(())
+    /// Wait for the uploads scheduled so far to complete. Used at graceful shutdown, after
+    /// [`Tenant::flush_on_shutdown`].
+    pub(crate) async fn drain_uploads_on_shutdown(&self) {
+        self.on_each_timeline(|timeline_id, timeline| {
+            async move {
+                debug_assert_current_span_has_tenant_and_timeline_id();
+                let Some(client) = timeline.remote_client.as_ref() else {
+                    return;
                 };
-
+                // if we did not wait for completion here, it might be our shutdown process
+                // didn't wait for remote uploads to complete at all, as new tasks can forever
+                // be spawned.
+                //
+                // what is problematic is the shutting down of RemoteTimelineClient, because
+                // obviously it does not make sense to stop while we wait for it, but what
+                // about corner cases like s3 suddenly hanging up? The caller's shutdown
+                // deadline takes care of that, we log what is left meanwhile.
+                let res = client
+                    .wait_completion_with_progress(
+                        remote_timeline_client::UPLOAD_PROGRESS_LOG_PERIOD,
+                        |progress| {
+                            info!(
+                                "waiting for uploads, {} operations and {} bytes remaining",
+                                progress.ops_remaining, progress.bytes_remaining
+                            )
+                        },
+                    )
+                    .await;
                 if let Err(e) = res {
                     warn!("failed to await for frozen and flushed uploads: {e:#}");
                 }
             }
-            .instrument(tracing::info_span!("freeze_and_flush_on_shutdown", %timeline_id))
-        };
+            .instrument(tracing::info_span!("drain_uploads_on_shutdown", %timeline_id))
+        })
+        .await
+    }
+
+    /// Run the futures returned by `f` for all timelines concurrently, and wait for them.
+    async fn on_each_timeline<F, Fut>(&self, f: F)
+    where
+        F: Fn(TimelineId, Arc<Timeline>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut js = tokio::task::JoinSet::new();
 
         {
             let timelines = self.timelines.lock().unwrap();
-            timelines
-                .iter()
-                .map(|(id, tl)| (*id, Arc::clone(tl)))
-                .for_each(|(timeline_id, timeline)| {
-                    js.spawn(per_timeline(timeline_id, timeline));
-                })
-        };
+            for (timeline_id, timeline) in timelines.iter() {
+                js.spawn(f(*timeline_id, Arc::clone(timeline)));
+            }
+        }
 
         while let Some(res) = js.join_next().await {
             match res {
@@ -1539,6 +1865,10 @@ impl Tenant {
             drop(timelines);
         }
 
+        // The Timeline may outlive this function if something still holds a reference,
+        // don't leave its metrics behind until then.
+        crate::metrics::remove_timeline_label_sets(&self.tenant_id, &timeline_id);
+
         drop(guard);
 
         Ok(())
@@ -1781,12 +2111,31 @@ impl Tenant {
     /// Shutdown the tenant and join all of the spawned tasks.
     ///
     /// The method caters for all use-cases:
-    /// - pageserver shutdown (freeze_and_flush == true)
+    /// - pageserver shutdown (freeze_and_flush == true), though [`crate::shutdown`] runs the
+    ///   same steps phase by phase across all tenants instead
     /// - detach + ignore (freeze_and_flush == false)
     ///
     /// This will attempt to shutdown even if tenant is broken.
     pub(crate) async fn shutdown(&self, freeze_and_flush: bool) -> Result<(), ShutdownError> {
         span::debug_assert_current_span_has_tenant_id();
+        self.begin_shutdown().await?;
+
+        if freeze_and_flush {
+            self.shutdown_walreceivers().await;
+            self.flush_on_shutdown().await;
+            // this will wait for uploads to complete; in the past, it was done outside tenant
+            // shutdown in pageserver::shutdown_pageserver.
+            self.drain_uploads_on_shutdown().await;
+        }
+
+        self.shutdown_tasks().await;
+        self.shutdown_walredo().await;
+        Ok(())
+    }
+
+    /// The first step of [`Tenant::shutdown`]: set the tenant and its timelines to Stopping
+    /// state.
+    pub(crate) async fn begin_shutdown(&self) -> Result<(), ShutdownError> {
         // Set tenant (and its timlines) to Stoppping state.
         //
         // Since we can only transition into Stopping state after activation is complete,
@@ -1794,46 +2143,50 @@ impl Tenant {
         //
         // Transitioning tenants to Stopping state has a couple of non-obvious side effects:
         // 1. Lock out any new requests to the tenants.
-        // 2. Signal cancellation to WAL receivers (we wait on it below).
+        // 2. Signal cancellation to WAL receivers (we wait on it in shutdown_walreceivers).
         // 3. Signal cancellation for other tenant background loops.
         // 4. ???
         //
         // The waiting for the cancellation is not done uniformly.
         // We certainly wait for WAL receivers to shut down.
         // That is necessary so that no new data comes in before the freeze_and_flush.
-        // But the tenant background loops are joined-on in our caller.
-        // It's mesed up.
+        // But the tenant background loops are joined-on in shutdown_tasks.
         // we just ignore the failure to stop
         match self.set_stopping().await {
-            Ok(()) => {}
+            Ok(()) => Ok(()),
             Err(SetStoppingError::Broken) => {
                 // assume that this is acceptable
+                Ok(())
             }
-            Err(SetStoppingError::AlreadyStopping) => return Err(ShutdownError::AlreadyStopping),
-        };
-
-        if freeze_and_flush {
-            // walreceiver has already began to shutdown with TenantState::Stopping, but we need to
-            // await for them to stop.
-            task_mgr::shutdown_tasks(
-                Some(TaskKind::WalReceiverManager),
-                Some(self.tenant_id),
-                None,
-            )
-            .await;
-
-            // this will wait for uploads to complete; in the past, it was done outside tenant
-            // shutdown in pageserver::shutdown_pageserver.
-            self.freeze_and_flush_on_shutdown().await;
+            Err(SetStoppingError::AlreadyStopping) => Err(ShutdownError::AlreadyStopping),
         }
+    }
 
-        // shutdown all tenant and timeline tasks: gc, compaction, page service
-        // No new tasks will be started for this tenant because it's in `Stopping` state.
-        //
-        // this will additionally shutdown and await all timeline tasks.
+    /// Wait for the WAL receivers, which began to shut down in [`Tenant::begin_shutdown`].
+    pub(crate) async fn shutdown_walreceivers(&self) {
+        task_mgr::shutdown_tasks(
+            Some(TaskKind::WalReceiverManager),
+            Some(self.tenant_id),
+            None,
+        )
+        .await;
+    }
+
+    /// shutdown all tenant and timeline tasks: gc, compaction, page service
+    /// No new tasks will be started for this tenant because it's in `Stopping` state.
+    ///
+    /// this will additionally shutdown and await all timeline tasks.
+    pub(crate) async fn shutdown_tasks(&self) {
         task_mgr::shutdown_tasks(None, Some(self.tenant_id), None).await;
+    }
 
-        Ok(())
+    /// Stop the WAL redo processes, once the requests that are still using them are done, see
+    /// [`PostgresRedoManager::shutdown`]. The tasks that make the requests are stopped by then.
+    pub(crate) async fn shutdown_walredo(&self) {
+        let walredo_mgr = Arc::clone(&self.walredo_mgr);
+        if let Err(e) = tokio::task::spawn_blocking(move || walredo_mgr.shutdown()).await {
+            warn!("failed to stop the WAL redo processes: {e}");
+        }
     }
 
     /// Change tenant status to Stopping, to mark that it is being shut down.
@@ -1914,6 +2267,16 @@ impl Tenant {
     ///
     /// In tests, we also use this to set tenants to Broken state on purpose.
     pub(crate) async fn set_broken(&self, reason: String) {
+        self.set_broken_impl(reason, false).await
+    }
+
+    /// Transition an Active tenant into Broken state because one of its critical background
+    /// tasks kept panicking, see [`task_mgr::spawn_supervised`].
+    pub(crate) async fn set_broken_due_to_task_failure(&self, reason: String) {
+        self.set_broken_impl(reason, true).await
+    }
+
+    async fn set_broken_impl(&self, reason: String, allow_active: bool) {
         let mut rx = self.state.subscribe();
 
         // The load & attach routines own the tenant state until it has reached `Active`.
@@ -1938,7 +2301,7 @@ impl Tenant {
                     unreachable!("we ensured above that we're done with activation, and, there is no re-activation")
                 }
                 TenantState::Active => {
-                    if cfg!(feature = "testing") {
+                    if allow_active || cfg!(feature = "testing") {
                         warn!("Changing Active tenant to Broken state, reason: {}", reason);
                         *current_state = TenantState::broken_from_reason(reason);
                     } else {
@@ -2042,6 +2405,22 @@ impl Tenant {
         *self.tenant_conf.read().unwrap()
     }
 
+    /// The limits on the layer uploads of the tenant. Changes to them are not persisted, the
+    /// tenant starts with the `upload_throttle` of the pageserver config when it's loaded.
+    pub fn upload_throttle(&self) -> &UploadThrottle {
+        &self.upload_throttle
+    }
+
+    /// The deletion dry-run mode of the tenant, for flushing its records.
+    pub fn deletion_dry_run(&self) -> &DeletionDryRun {
+        &self.deletion_dry_run
+    }
+
+    /// The WAL redo processes of the tenant, for debugging.
+    pub fn walredo_status(&self) -> Vec<WalRedoProcessInfo> {
+        self.walredo_mgr.status()
+    }
+
     pub fn effective_config(&self) -> TenantConf {
         self.tenant_specific_overrides()
             .merge(self.conf.default_tenant_conf)
@@ -2096,6 +2475,40 @@ impl Tenant {
             .unwrap_or(self.conf.default_tenant_conf.gc_period)
     }
 
+    pub fn get_deletion_dry_run(&self) -> bool {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .deletion_dry_run
+            .unwrap_or(self.conf.default_tenant_conf.deletion_dry_run)
+    }
+
+    pub fn get_max_inprogress_uploads(&self) -> Option<NonZeroUsize> {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .max_inprogress_uploads
+            .or(self.conf.default_tenant_conf.max_inprogress_uploads)
+    }
+
+    pub fn get_walredo_limits(&self) -> WalRedoLimits {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        let default_tenant_conf = &self.conf.default_tenant_conf;
+        WalRedoLimits {
+            memory: tenant_conf
+                .walredo_memory_limit
+                .or(default_tenant_conf.walredo_memory_limit),
+            cpu_time: tenant_conf
+                .walredo_cpu_time_limit
+                .or(default_tenant_conf.walredo_cpu_time_limit),
+        }
+    }
+
+    pub fn get_tiering_policy(&self) -> TieringPolicy {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .tiering_policy
+            .unwrap_or(self.conf.default_tenant_conf.tiering_policy)
+    }
+
     pub fn get_image_creation_threshold(&self) -> usize {
         let tenant_conf = self.tenant_conf.read().unwrap();
         tenant_conf
@@ -2126,6 +2539,11 @@ impl Tenant {
 
     pub fn set_new_tenant_config(&self, new_tenant_conf: TenantConfOpt) {
         *self.tenant_conf.write().unwrap() = new_tenant_conf;
+        self.deletion_dry_run
+            .set_enabled(self.get_deletion_dry_run());
+        self.upload_concurrency_limit
+            .set(self.get_max_inprogress_uploads());
+        self.walredo_mgr.set_limits(self.get_walredo_limits());
         // Don't hold self.timelines.lock() during the notifies.
         // There's no risk of deadlock right now, but there could be if we consolidate
         // mutexes in struct Timeline in the future.
@@ -2168,6 +2586,7 @@ impl Tenant {
             self.tenant_id,
             Arc::clone(&self.walredo_mgr),
             remote_client,
+            Arc::clone(&self.prefetches_inflight),
             pg_version,
             initial_logical_size_can_start.cloned(),
             initial_logical_size_attempt.cloned(),
@@ -2181,6 +2600,7 @@ impl Tenant {
         walredo_mgr: Arc<dyn WalRedoManager + Send + Sync>,
         tenant_id: TenantId,
         remote_storage: Option<GenericRemoteStorage>,
+        generation: Option<Generation>,
     ) -> Tenant {
         let (state, mut rx) = watch::channel(state);
 
@@ -2211,6 +2631,15 @@ impl Tenant {
             }
         });
 
+        walredo_mgr.set_limits(WalRedoLimits {
+            memory: tenant_conf
+                .walredo_memory_limit
+                .or(conf.default_tenant_conf.walredo_memory_limit),
+            cpu_time: tenant_conf
+                .walredo_cpu_time_limit
+                .or(conf.default_tenant_conf.walredo_cpu_time_limit),
+        });
+
         Tenant {
             tenant_id,
             conf,
@@ -2222,10 +2651,27 @@ impl Tenant {
             gc_cs: tokio::sync::Mutex::new(()),
             walredo_mgr,
             remote_storage,
+            upload_throttle: Arc::new(UploadThrottle::new(conf.upload_throttle)),
+            upload_concurrency_limit: Arc::new(UploadConcurrencyLimit::new(
+                tenant_conf
+                    .max_inprogress_uploads
+                    .or(conf.default_tenant_conf.max_inprogress_uploads),
+            )),
+            deferred_deletions: Arc::new(DeferredDeletions::new(
+                conf.tenant_deferred_deletions_path(&tenant_id),
+            )),
+            deletion_dry_run: Arc::new(DeletionDryRun::new(
+                tenant_conf
+                    .deletion_dry_run
+                    .unwrap_or(conf.default_tenant_conf.deletion_dry_run),
+            )),
+            generation,
+            timeline_manifest_lock: tokio::sync::Mutex::new(()),
             state,
             cached_logical_sizes: tokio::sync::Mutex::new(HashMap::new()),
             cached_synthetic_tenant_size: Arc::new(AtomicU64::new(0)),
             eviction_task_tenant_state: tokio::sync::Mutex::new(EvictionTaskTenantState::default()),
+            prefetches_inflight: Arc::new(AtomicUsize::new(0)),
         }
     }
 
@@ -2398,8 +2844,11 @@ impl Tenant {
         //
         // See comments in [`Tenant::branch_timeline`] for more information
         // about why branch creation task can run concurrently with timeline's GC iteration.
+        //
+        // The GC runs in the GC task of the tenant, or in the task of a management API request.
+        let cancel = task_mgr::tenant_cancellation_token(self.tenant_id);
         for timeline in gc_timelines {
-            if task_mgr::is_shutdown_requested() {
+            if cancel.is_cancelled() {
                 // We were requested to shut down. Stop and return with the progress we
                 // made.
                 break;
@@ -2805,6 +3254,11 @@ impl Tenant {
                 self.conf,
                 tenant_id,
                 new_timeline_id,
+                Arc::clone(&self.upload_throttle),
+                Arc::clone(&self.upload_concurrency_limit),
+                Arc::clone(&self.deferred_deletions),
+                Arc::clone(&self.deletion_dry_run),
+                self.generation,
             );
             remote_client.init_upload_queue_for_empty_remote(new_metadata)?;
             Some(remote_client)
@@ -2998,12 +3452,16 @@ fn remove_timeline_and_uninit_mark(timeline_dir: &Path, uninit_mark: &Path) -> a
 
 pub(crate) enum CreateTenantFilesMode {
     Create,
-    Attach,
+    Attach {
+        generation: Option<Generation>,
+        import: Option<TenantImport>,
+    },
 }
 
 pub(crate) fn create_tenant_files(
     conf: &'static PageServerConf,
     tenant_conf: TenantConfOpt,
+    remote_storage: Option<&TenantRemoteStorageConfig>,
     tenant_id: &TenantId,
     mode: CreateTenantFilesMode,
 ) -> anyhow::Result<PathBuf> {
@@ -3033,6 +3491,7 @@ pub(crate) fn create_tenant_files(
     let creation_result = try_create_target_tenant_dir(
         conf,
         tenant_conf,
+        remote_storage,
         tenant_id,
         mode,
         &temporary_tenant_dir,
@@ -3058,6 +3517,7 @@ pub(crate) fn create_tenant_files(
 fn try_create_target_tenant_dir(
     conf: &'static PageServerConf,
     tenant_conf: TenantConfOpt,
+    remote_storage: Option<&TenantRemoteStorageConfig>,
     tenant_id: &TenantId,
     mode: CreateTenantFilesMode,
     temporary_tenant_dir: &Path,
@@ -3065,7 +3525,7 @@ fn try_create_target_tenant_dir(
 ) -> Result<(), anyhow::Error> {
     match mode {
         CreateTenantFilesMode::Create => {} // needs no attach marker, writing tenant conf + atomic rename of dir is good enough
-        CreateTenantFilesMode::Attach => {
+        CreateTenantFilesMode::Attach { generation, import } => {
             let attach_marker_path = temporary_tenant_dir.join(TENANT_ATTACHING_MARKER_FILENAME);
             let file = std::fs::OpenOptions::new()
                 .create_new(true)
@@ -3077,7 +3537,13 @@ fn try_create_target_tenant_dir(
             file.sync_all().with_context(|| {
                 format!("could not sync attach marker file: {attach_marker_path:?}")
             })?;
-            // fsync of the directory in which the file resides comes later in this function
+            if let Some(generation) = generation {
+                generation.persist(&temporary_tenant_dir.join(TENANT_GENERATION_FILE_NAME))?;
+            }
+            if let Some(import) = import {
+                import.persist(&temporary_tenant_dir.join(TENANT_IMPORT_FILE_NAME))?;
+            }
+            // fsync of the directory in which the files reside comes later in this function
         }
     }
 
@@ -3095,6 +3561,9 @@ fn try_create_target_tenant_dir(
     .with_context(|| format!("resolve tenant {tenant_id} temporary config path"))?;
 
     Tenant::persist_tenant_config(tenant_id, &temporary_tenant_config_path, tenant_conf, true)?;
+    if let Some(remote_storage) = remote_storage {
+        remote_storage.persist(&temporary_tenant_dir.join(TENANT_REMOTE_STORAGE_FILE_NAME))?;
+    }
 
     crashsafe::create_dir(&temporary_tenant_timelines_dir).with_context(|| {
         format!(
@@ -3250,7 +3719,7 @@ pub mod harness {
         repository::Key,
         tenant::Tenant,
         walrecord::NeonWalRecord,
-        walredo::{WalRedoError, WalRedoManager},
+        walredo::{RedoTimings, WalRedoError, WalRedoManager},
     };
 
     use super::*;
@@ -3295,6 +3764,13 @@ pub mod harness {
                     tenant_conf.evictions_low_residence_duration_metric_threshold,
                 ),
                 gc_feedback: Some(tenant_conf.gc_feedback),
+                prefetch_distance: Some(tenant_conf.prefetch_distance),
+                prefetch_max_inflight: Some(tenant_conf.prefetch_max_inflight),
+                tiering_policy: Some(tenant_conf.tiering_policy),
+                deletion_dry_run: Some(tenant_conf.deletion_dry_run),
+                max_inprogress_uploads: tenant_conf.max_inprogress_uploads,
+                walredo_memory_limit: tenant_conf.walredo_memory_limit,
+                walredo_cpu_time_limit: tenant_conf.walredo_cpu_time_limit,
             }
         }
     }
@@ -3315,6 +3791,8 @@ pub mod harness {
                     // enable it in case in case the tests exercise code paths that use
                     // debug_assert_current_span_has_tenant_and_timeline_id
                     logging::TracingErrorLayerEnablement::EnableWithRustLogFilter,
+                    Vec::new(),
+                    None,
                 )
                 .expect("Failed to init test logging")
             });
@@ -3367,6 +3845,7 @@ pub mod harness {
                 walredo_mgr,
                 self.tenant_id,
                 None,
+                None,
             ));
             tenant
                 .load(None, ctx)
@@ -3389,14 +3868,16 @@ pub mod harness {
     // Mock WAL redo manager that doesn't do much
     pub struct TestRedoManager;
 
+    #[async_trait::async_trait]
     impl WalRedoManager for TestRedoManager {
-        fn request_redo(
+        async fn request_redo(
             &self,
             key: Key,
             lsn: Lsn,
             base_img: Option<(Lsn, Bytes)>,
             records: Vec<(Lsn, NeonWalRecord)>,
             _pg_version: u32,
+            _timings: &mut RedoTimings,
         ) -> Result<Bytes, WalRedoError> {
             let s = format!(
                 "redo for {} to get to {}, with {} and {} records",
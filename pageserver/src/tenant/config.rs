@@ -10,8 +10,12 @@
 //!
 use anyhow::Context;
 use pageserver_api::models;
+use remote_storage::RemoteStorageConfig;
 use serde::{Deserialize, Serialize};
-use std::num::NonZeroU64;
+use std::fmt;
+use std::io::Write;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::Path;
 use std::time::Duration;
 
 pub mod defaults {
@@ -42,6 +46,7 @@ pub mod defaults {
     pub const DEFAULT_WALRECEIVER_LAGGING_WAL_TIMEOUT: &str = "10 seconds";
     pub const DEFAULT_MAX_WALRECEIVER_LSN_WAL_LAG: u64 = 10 * 1024 * 1024;
     pub const DEFAULT_EVICTIONS_LOW_RESIDENCE_DURATION_METRIC_THRESHOLD: &str = "24 hour";
+    pub const DEFAULT_PREFETCH_MAX_INFLIGHT: usize = 8;
 }
 
 /// Per-tenant configuration options
@@ -100,6 +105,23 @@ pub struct TenantConf {
     #[serde(with = "humantime_serde")]
     pub evictions_low_residence_duration_metric_threshold: Duration,
     pub gc_feedback: bool,
+    /// How many blocks to read ahead of a sequential scan of a relation. 0 disables prefetching.
+    pub prefetch_distance: u32,
+    /// Maximum number of prefetches of the tenant in flight at a time.
+    pub prefetch_max_inflight: usize,
+    /// Which layer files to move to the cold tier of the remote storage.
+    pub tiering_policy: TieringPolicy,
+    /// Log and record the layer files that the upload queues would delete from the remote
+    /// storage, instead of deleting them.
+    pub deletion_dry_run: bool,
+    /// Maximum number of layer uploads in progress at a time, per timeline. `None` for no limit
+    /// but the `max_concurrent_syncs` of the remote storage.
+    pub max_inprogress_uploads: Option<NonZeroUsize>,
+    /// Limit of the address space of the WAL redo process, in bytes.
+    pub walredo_memory_limit: Option<u64>,
+    /// Limit of the CPU time the WAL redo process spends over its lifetime.
+    #[serde(with = "humantime_serde")]
+    pub walredo_cpu_time_limit: Option<Duration>,
 }
 
 /// Same as TenantConf, but this struct preserves the information about
@@ -180,6 +202,35 @@ pub struct TenantConfOpt {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub gc_feedback: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub prefetch_distance: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub prefetch_max_inflight: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub tiering_policy: Option<TieringPolicy>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub deletion_dry_run: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub max_inprogress_uploads: Option<NonZeroUsize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub walredo_memory_limit: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(with = "humantime_serde")]
+    #[serde(default)]
+    pub walredo_cpu_time_limit: Option<Duration>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -206,6 +257,32 @@ pub struct EvictionPolicyLayerAccessThreshold {
     pub threshold: Duration,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum TieringPolicy {
+    NoTiering,
+    AgeThreshold(TieringPolicyAgeThreshold),
+}
+
+impl TieringPolicy {
+    pub fn discriminant_str(&self) -> &'static str {
+        match self {
+            TieringPolicy::NoTiering => "NoTiering",
+            TieringPolicy::AgeThreshold(_) => "AgeThreshold",
+        }
+    }
+}
+
+/// Move the layer files uploaded at least `threshold` ago to the cold tier, checking every
+/// `period`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TieringPolicyAgeThreshold {
+    #[serde(with = "humantime_serde")]
+    pub period: Duration,
+    #[serde(with = "humantime_serde")]
+    pub threshold: Duration,
+}
+
 impl TenantConfOpt {
     pub fn merge(&self, global_conf: TenantConf) -> TenantConf {
         TenantConf {
@@ -248,6 +325,25 @@ impl TenantConfOpt {
                 .evictions_low_residence_duration_metric_threshold
                 .unwrap_or(global_conf.evictions_low_residence_duration_metric_threshold),
             gc_feedback: self.gc_feedback.unwrap_or(global_conf.gc_feedback),
+            prefetch_distance: self
+                .prefetch_distance
+                .unwrap_or(global_conf.prefetch_distance),
+            prefetch_max_inflight: self
+                .prefetch_max_inflight
+                .unwrap_or(global_conf.prefetch_max_inflight),
+            tiering_policy: self.tiering_policy.unwrap_or(global_conf.tiering_policy),
+            deletion_dry_run: self
+                .deletion_dry_run
+                .unwrap_or(global_conf.deletion_dry_run),
+            max_inprogress_uploads: self
+                .max_inprogress_uploads
+                .or(global_conf.max_inprogress_uploads),
+            walredo_memory_limit: self
+                .walredo_memory_limit
+                .or(global_conf.walredo_memory_limit),
+            walredo_cpu_time_limit: self
+                .walredo_cpu_time_limit
+                .or(global_conf.walredo_cpu_time_limit),
         }
     }
 }
@@ -285,6 +381,13 @@ impl Default for TenantConf {
             )
             .expect("cannot parse default evictions_low_residence_duration_metric_threshold"),
             gc_feedback: false,
+            prefetch_distance: 0,
+            prefetch_max_inflight: DEFAULT_PREFETCH_MAX_INFLIGHT,
+            tiering_policy: TieringPolicy::NoTiering,
+            deletion_dry_run: false,
+            max_inprogress_uploads: None,
+            walredo_memory_limit: None,
+            walredo_cpu_time_limit: None,
         }
     }
 }
@@ -380,11 +483,92 @@ impl TryFrom<&'_ models::TenantConfig> for TenantConfOpt {
             );
         }
         tenant_conf.gc_feedback = request_data.gc_feedback;
+        tenant_conf.prefetch_distance = request_data.prefetch_distance;
+        tenant_conf.prefetch_max_inflight = request_data.prefetch_max_inflight;
+
+        if let Some(tiering_policy) = &request_data.tiering_policy {
+            tenant_conf.tiering_policy = Some(
+                serde::Deserialize::deserialize(tiering_policy)
+                    .context("parse field `tiering_policy`")?,
+            );
+        }
+        tenant_conf.deletion_dry_run = request_data.deletion_dry_run;
+        tenant_conf.max_inprogress_uploads = request_data.max_inprogress_uploads;
+        tenant_conf.walredo_memory_limit = request_data.walredo_memory_limit;
+        if let Some(walredo_cpu_time_limit) = &request_data.walredo_cpu_time_limit {
+            tenant_conf.walredo_cpu_time_limit = Some(
+                humantime::parse_duration(walredo_cpu_time_limit)
+                    .with_context(bad_duration("walredo_cpu_time_limit", walredo_cpu_time_limit))?,
+            );
+        }
 
         Ok(tenant_conf)
     }
 }
 
+/// The remote storage of a tenant, in place of the one of the pageserver. Persisted in the
+/// tenant directory, in the toml format of the `remote_storage` pageserver setting.
+#[derive(Clone)]
+pub struct TenantRemoteStorageConfig {
+    toml: toml_edit::Document,
+    pub config: RemoteStorageConfig,
+}
+
+impl TenantRemoteStorageConfig {
+    pub fn parse(toml: &str) -> anyhow::Result<Self> {
+        let toml = toml.parse::<toml_edit::Document>()?;
+        let config = RemoteStorageConfig::from_toml(toml.as_item())?
+            .context("neither `local_path` nor `bucket_name` is given")?;
+        Ok(TenantRemoteStorageConfig { toml, config })
+    }
+
+    /// Read the remote storage file of a tenant directory, `None` if the tenant uses the
+    /// remote storage of the pageserver.
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("read remote storage file {}", path.display()))
+            }
+        };
+        Self::parse(&contents)
+            .map(Some)
+            .with_context(|| format!("parse remote storage file {}", path.display()))
+    }
+
+    /// Write the remote storage file of a tenant directory. The caller fsyncs the directory.
+    pub(crate) fn persist(&self, path: &Path) -> anyhow::Result<()> {
+        let write = || -> std::io::Result<()> {
+            let mut file = std::fs::File::create(path)?;
+            file.write_all(self.toml.to_string().as_bytes())?;
+            file.sync_all()
+        };
+        write().with_context(|| format!("write remote storage file {}", path.display()))
+    }
+}
+
+impl TryFrom<&'_ serde_json::Value> for TenantRemoteStorageConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &'_ serde_json::Value) -> Result<Self, Self::Error> {
+        toml_edit::ser::to_string(value)
+            .map_err(anyhow::Error::from)
+            .and_then(|toml| Self::parse(&toml))
+            .context("parse field `remote_storage`")
+    }
+}
+
+/// Leaves out the toml, which may have credentials.
+impl fmt::Debug for TenantRemoteStorageConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TenantRemoteStorageConfig")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -404,4 +588,39 @@ mod tests {
         assert_eq!(json_form, "{\"gc_horizon\":42}");
         assert_eq!(small_conf, serde_json::from_str(&json_form).unwrap());
     }
+    #[test]
+    fn tenant_remote_storage_config() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("remote_storage");
+        assert!(TenantRemoteStorageConfig::load(&path)?.is_none());
+
+        let json = serde_json::json!({
+            "bucket_name": "tenant-bucket",
+            "bucket_region": "eu-central-1",
+            "prefix_in_bucket": "pageserver/",
+            "access_key_id": "key id",
+            "secret_access_key": "s3cr3t",
+        });
+        let config = TenantRemoteStorageConfig::try_from(&json)?;
+        let remote_storage::RemoteStorageKind::AwsS3(s3_config) = &config.config.storage else {
+            panic!("expected an S3 config, got {config:?}");
+        };
+        assert_eq!(s3_config.bucket_name, "tenant-bucket");
+        let credentials = s3_config.credentials.as_ref().expect("credentials");
+        assert_eq!(credentials.secret_access_key, "s3cr3t");
+        assert!(!format!("{config:?}").contains("s3cr3t"));
+
+        config.persist(&path)?;
+        let loaded = TenantRemoteStorageConfig::load(&path)?.expect("persisted");
+        assert_eq!(loaded.config, config.config);
+
+        let without_secret = serde_json::json!({
+            "bucket_name": "tenant-bucket",
+            "bucket_region": "eu-central-1",
+            "access_key_id": "key id",
+        });
+        assert!(TenantRemoteStorageConfig::try_from(&without_secret).is_err());
+        assert!(TenantRemoteStorageConfig::try_from(&serde_json::json!({})).is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,192 @@
+//! Deferred deletion of the layer files in the remote storage.
+//!
+//! With `remote_deletion_grace_period` set, a layer file dereferenced from the index of its
+//! timeline isn't deleted from the remote storage right after the index upload. The upload
+//! queue records the deletion in the tenant directory instead, and the deferred deletion job
+//! of the tenant executes it once the grace period has passed. Until then, the layer files of
+//! the earlier indexes are all still there: an earlier index can be restored to recover from
+//! an accidental drop, and a pageserver the tenant is still attached to in a split brain can
+//! still read the layer files of its own index.
+//!
+//! The due deletions go through the upload queue of their timeline, after the operations
+//! queued so far. A layer file that the index references again by then, e.g. uploaded again
+//! with the same name, isn't deleted. The deletions of a timeline deletion aren't deferred.
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use utils::crashsafe::{self, path_with_suffix_extension};
+use utils::id::TimelineId;
+
+use crate::tenant::storage_layer::LayerFileName;
+use crate::TEMP_FILE_SUFFIX;
+
+/// How often the deferred deletion job looks for the due deletions, at most. With a shorter
+/// grace period, the job runs every grace period.
+const MAX_DEFERRED_DELETION_PERIOD: Duration = Duration::from_secs(60);
+
+/// The period of the deferred deletion job. It keeps running without a grace period, for the
+/// deletions deferred before a restart.
+pub(crate) fn job_period(grace_period: Duration) -> Duration {
+    if grace_period.is_zero() {
+        MAX_DEFERRED_DELETION_PERIOD
+    } else {
+        grace_period.min(MAX_DEFERRED_DELETION_PERIOD)
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct DeferredDeletion {
+    #[serde_as(as = "DisplayFromStr")]
+    pub(crate) timeline_id: TimelineId,
+    pub(crate) layer_file_name: LayerFileName,
+    /// When the grace period ends.
+    #[serde(with = "humantime_serde")]
+    pub(crate) due: SystemTime,
+}
+
+/// The deferred deletions of a tenant, persisted in the tenant directory.
+pub struct DeferredDeletions {
+    path: PathBuf,
+    pending: Mutex<Vec<DeferredDeletion>>,
+}
+
+impl DeferredDeletions {
+    pub fn new(path: PathBuf) -> Self {
+        DeferredDeletions {
+            path,
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Read the deletions deferred before a restart. Must be called before any deletion is
+    /// deferred.
+    pub(crate) fn load(&self) -> anyhow::Result<()> {
+        let contents = match std::fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("read deferred deletions file {}", self.path.display())
+                })
+            }
+        };
+        let loaded: Vec<DeferredDeletion> = serde_json::from_slice(&contents)
+            .with_context(|| format!("parse deferred deletions file {}", self.path.display()))?;
+        *self.pending.lock().unwrap() = loaded;
+        Ok(())
+    }
+
+    /// Defer the deletion of layer files of a timeline by `grace_period`.
+    pub(crate) fn push(
+        &self,
+        timeline_id: TimelineId,
+        layer_file_names: &[LayerFileName],
+        grace_period: Duration,
+    ) -> anyhow::Result<()> {
+        let due = SystemTime::now() + grace_period;
+        let mut pending = self.pending.lock().unwrap();
+        let len_before = pending.len();
+        pending.extend(layer_file_names.iter().map(|name| DeferredDeletion {
+            timeline_id,
+            layer_file_name: name.clone(),
+            due,
+        }));
+        if let Err(e) = self.persist(&pending) {
+            // The upload queue retries
+            pending.truncate(len_before);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// The deletions whose grace period has ended by `now`.
+    pub(crate) fn due(&self, now: SystemTime) -> Vec<DeferredDeletion> {
+        let pending = self.pending.lock().unwrap();
+        pending.iter().filter(|d| d.due <= now).cloned().collect()
+    }
+
+    /// Forget the executed deletions.
+    pub(crate) fn remove(&self, executed: &[DeferredDeletion]) -> anyhow::Result<()> {
+        let executed = executed.iter().collect::<HashSet<_>>();
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|d| !executed.contains(d));
+        self.persist(&pending)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Replace the file with a new one, so that a crash leaves either the old or the new list.
+    fn persist(&self, pending: &[DeferredDeletion]) -> anyhow::Result<()> {
+        let temp_path = path_with_suffix_extension(&self.path, TEMP_FILE_SUFFIX);
+        let write = || -> std::io::Result<()> {
+            let mut file = std::fs::File::create(&temp_path)?;
+            file.write_all(&serde_json::to_vec(pending)?)?;
+            file.sync_all()?;
+            std::fs::rename(&temp_path, &self.path)?;
+            crashsafe::fsync(self.path.parent().expect("file in the tenant directory"))
+        };
+        write().with_context(|| format!("write deferred deletions file {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persist_and_load() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("deferred_deletions");
+        let timeline_id = TimelineId::generate();
+        let layer_file_names: Vec<LayerFileName> = [
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51",
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D9-00000000016B5A52",
+        ]
+        .iter()
+        .map(|name| name.parse().unwrap())
+        .collect();
+
+        let deletions = DeferredDeletions::new(path.clone());
+        deletions.load()?;
+        assert!(deletions.is_empty());
+
+        deletions.push(timeline_id, &layer_file_names[..1], Duration::ZERO)?;
+        deletions.push(timeline_id, &layer_file_names[1..], Duration::from_secs(3600))?;
+        let due = deletions.due(SystemTime::now());
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].timeline_id, timeline_id);
+        assert_eq!(due[0].layer_file_name, layer_file_names[0]);
+
+        // Survives a restart
+        let reloaded = DeferredDeletions::new(path.clone());
+        reloaded.load()?;
+        assert_eq!(reloaded.len(), 2);
+        assert_eq!(reloaded.due(SystemTime::now()).len(), 1);
+        let later = SystemTime::now() + Duration::from_secs(7200);
+        assert_eq!(reloaded.due(later).len(), 2);
+
+        reloaded.remove(&due)?;
+        assert_eq!(reloaded.len(), 1);
+        assert!(reloaded.due(SystemTime::now()).is_empty());
+
+        let reloaded = DeferredDeletions::new(path);
+        reloaded.load()?;
+        assert_eq!(reloaded.len(), 1);
+
+        Ok(())
+    }
+}
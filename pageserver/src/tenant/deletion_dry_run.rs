@@ -0,0 +1,115 @@
+//! Dry-run mode of the layer file deletions of a tenant.
+//!
+//! With `deletion_dry_run` set in the tenant config, the upload queues of the tenant don't
+//! delete the layer files dereferenced from their indexes: they log the objects they would
+//! have deleted, and record them until an operator flushes the records through the management
+//! API. That's for checking new compaction or GC logic against a production bucket. The
+//! deferred deletions, see [`crate::tenant::deferred_deletion`], are recorded once they are
+//! due. The deletions of a timeline deletion are performed.
+//!
+//! The layer files stay in the remote storage, referenced from no index: scrub the timelines
+//! after disabling the mode to delete them. The records are kept in memory only, and lost when
+//! the tenant is loaded again.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use pageserver_api::models::{DeletionDryRunFlushResponse, DeletionDryRunRecord};
+use remote_storage::RemotePath;
+use tracing::info;
+use utils::id::TimelineId;
+
+use crate::metrics::REMOTE_DELETION_DRY_RUN_OBJECTS;
+
+/// Records kept until flushed, beyond which they are dropped.
+const MAX_RECORDS: usize = 100_000;
+
+/// The deletion dry-run mode of a tenant, shared by the upload queues of its timelines.
+pub struct DeletionDryRun {
+    enabled: AtomicBool,
+    recorded: Mutex<DeletionDryRunFlushResponse>,
+}
+
+impl DeletionDryRun {
+    pub fn new(enabled: bool) -> Self {
+        DeletionDryRun {
+            enabled: AtomicBool::new(enabled),
+            recorded: Mutex::new(DeletionDryRunFlushResponse::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Follow a change of the tenant config. The deletions already in progress are not
+    /// affected.
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        if self.enabled.swap(enabled, Ordering::Relaxed) != enabled {
+            info!(enabled, "changed the deletion dry-run mode");
+        }
+    }
+
+    /// Record the objects of a timeline that a deletion would have deleted.
+    pub(crate) fn record(&self, timeline_id: TimelineId, remote_paths: &[RemotePath]) {
+        info!(
+            "deletion dry-run, not deleting {} objects: {remote_paths:?}",
+            remote_paths.len()
+        );
+        REMOTE_DELETION_DRY_RUN_OBJECTS.inc_by(remote_paths.len() as u64);
+
+        let mut recorded = self.recorded.lock().unwrap();
+        for path in remote_paths {
+            if recorded.records.len() >= MAX_RECORDS {
+                recorded.dropped_records += 1;
+                continue;
+            }
+            recorded.records.push(DeletionDryRunRecord {
+                timeline_id,
+                remote_path: path.get_path().to_string_lossy().into_owned(),
+            });
+        }
+    }
+
+    /// Take the records kept since the last flush.
+    pub fn flush(&self) -> DeletionDryRunFlushResponse {
+        std::mem::take(&mut *self.recorded.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn record_and_flush() -> anyhow::Result<()> {
+        let dry_run = DeletionDryRun::new(true);
+        let timeline_id = TimelineId::generate();
+        let paths = [
+            RemotePath::new(Path::new("tenants/t/timelines/tl/layer"))?,
+            RemotePath::new(Path::new("cold/tenants/t/timelines/tl/layer"))?,
+        ];
+
+        dry_run.record(timeline_id, &paths);
+        let flushed = dry_run.flush();
+        assert_eq!(flushed.dropped_records, 0);
+        assert_eq!(
+            flushed.records,
+            [
+                DeletionDryRunRecord {
+                    timeline_id,
+                    remote_path: "tenants/t/timelines/tl/layer".to_string(),
+                },
+                DeletionDryRunRecord {
+                    timeline_id,
+                    remote_path: "cold/tenants/t/timelines/tl/layer".to_string(),
+                },
+            ]
+        );
+        assert!(dry_run.flush().records.is_empty());
+
+        Ok(())
+    }
+}
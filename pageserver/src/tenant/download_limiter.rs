@@ -0,0 +1,189 @@
+//! Limit on the concurrent layer downloads of the pageserver, with priorities.
+//!
+//! The on-demand downloads of the getpage requests wait for a permit ahead of the downloads
+//! of the prefetches, the layer warm-ups and the compactions, so that a background job
+//! downloading many layers doesn't starve the reads of the computes. A download already
+//! running isn't interrupted: a foreground download gets the next permit released.
+//!
+//! The permits are taken after the per-layer download lock of the
+//! [`crate::tenant::storage_layer::RemoteLayer`], so a getpage request needing a layer that a
+//! background download is already waiting for waits along with it.
+
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+
+use crate::task_mgr::TaskKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadPriority {
+    /// A read waits for the download.
+    Foreground,
+    /// Nothing waits for the download but a background job.
+    Background,
+}
+
+impl DownloadPriority {
+    /// The priority of the on-demand downloads of a task.
+    pub fn of_task(task_kind: TaskKind) -> Self {
+        match task_kind {
+            TaskKind::Prefetch | TaskKind::DownloadAllRemoteLayers | TaskKind::Compaction => {
+                DownloadPriority::Background
+            }
+            _ => DownloadPriority::Foreground,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    available: usize,
+    foreground_waiters: VecDeque<oneshot::Sender<()>>,
+    background_waiters: VecDeque<oneshot::Sender<()>>,
+}
+
+/// The `concurrent_layer_downloads` setting.
+#[derive(Debug, Clone)]
+pub struct LayerDownloadLimiter {
+    permits: NonZeroUsize,
+    state: Arc<Mutex<State>>,
+}
+
+impl LayerDownloadLimiter {
+    pub const DEFAULT_PERMITS: NonZeroUsize = match NonZeroUsize::new(64) {
+        Some(x) => x,
+        None => panic!("const unwrap is not yet stable"),
+    };
+
+    pub fn new(permits: NonZeroUsize) -> Self {
+        LayerDownloadLimiter {
+            permits,
+            state: Arc::new(Mutex::new(State {
+                available: permits.get(),
+                foreground_waiters: VecDeque::new(),
+                background_waiters: VecDeque::new(),
+            })),
+        }
+    }
+
+    pub fn permits(&self) -> NonZeroUsize {
+        self.permits
+    }
+
+    /// Wait for a permit to download a layer. The waiters of a priority get the permits in
+    /// order, the foreground ones before all background ones.
+    pub async fn acquire(&self, priority: DownloadPriority) -> LayerDownloadPermit {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            // The released permits go to the waiters first, so there are none with permits left
+            if state.available > 0 {
+                state.available -= 1;
+                return self.permit();
+            }
+            let (sender, receiver) = oneshot::channel();
+            match priority {
+                DownloadPriority::Foreground => state.foreground_waiters.push_back(sender),
+                DownloadPriority::Background => state.background_waiters.push_back(sender),
+            }
+            receiver
+        };
+
+        // If we are cancelled after being handed the permit, pass it on
+        let mut receiver = scopeguard::guard(receiver, |mut receiver| {
+            if receiver.try_recv().is_ok() {
+                release(&self.state);
+            }
+        });
+        (&mut *receiver)
+            .await
+            .expect("the waiters are only dropped when handed a permit");
+        scopeguard::ScopeGuard::into_inner(receiver);
+        self.permit()
+    }
+
+    fn permit(&self) -> LayerDownloadPermit {
+        LayerDownloadPermit {
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+/// Hand the permit to the next waiter, if any.
+fn release(state: &Mutex<State>) {
+    let mut state = state.lock().unwrap();
+    let State {
+        available,
+        foreground_waiters,
+        background_waiters,
+    } = &mut *state;
+    for waiters in [foreground_waiters, background_waiters] {
+        while let Some(waiter) = waiters.pop_front() {
+            // Fails if the waiter was cancelled
+            if waiter.send(()).is_ok() {
+                return;
+            }
+        }
+    }
+    *available += 1;
+}
+
+impl PartialEq for LayerDownloadLimiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.permits == other.permits
+    }
+}
+
+impl Eq for LayerDownloadLimiter {}
+
+impl Default for LayerDownloadLimiter {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_PERMITS)
+    }
+}
+
+/// Returns the permit to the [`LayerDownloadLimiter`] when dropped.
+pub struct LayerDownloadPermit {
+    state: Arc<Mutex<State>>,
+}
+
+impl Drop for LayerDownloadPermit {
+    fn drop(&mut self) {
+        release(&self.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    #[tokio::test]
+    async fn foreground_first() {
+        let limiter = LayerDownloadLimiter::new(NonZeroUsize::new(1).unwrap());
+        let permit = limiter.acquire(DownloadPriority::Background).await;
+
+        let mut background = Box::pin(limiter.acquire(DownloadPriority::Background));
+        let mut foreground = Box::pin(limiter.acquire(DownloadPriority::Foreground));
+        assert!((&mut background).now_or_never().is_none());
+        assert!((&mut foreground).now_or_never().is_none());
+
+        drop(permit);
+        assert!((&mut background).now_or_never().is_none());
+        let permit = (&mut foreground).now_or_never().expect("foreground goes first");
+
+        drop(permit);
+        let permit = background.await;
+
+        // A cancelled waiter passes the permit on
+        let mut cancelled = Box::pin(limiter.acquire(DownloadPriority::Foreground));
+        let mut waiting = Box::pin(limiter.acquire(DownloadPriority::Background));
+        assert!((&mut cancelled).now_or_never().is_none());
+        assert!((&mut waiting).now_or_never().is_none());
+        drop(permit);
+        assert!((&mut waiting).now_or_never().is_none());
+        drop(cancelled);
+        waiting.await;
+    }
+}
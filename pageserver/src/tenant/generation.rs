@@ -0,0 +1,131 @@
+//! Generation numbers of the attachments of a tenant.
+//!
+//! The control plane may pass a generation number when it attaches a tenant to a pageserver,
+//! increasing it with every attachment. The pageserver keeps the generation in the tenant
+//! directory, and writes the index files of the timelines under a name with the generation,
+//! `index_part.json-<generation>`, so that a pageserver the tenant used to be attached to
+//! doesn't overwrite the index files of the new one.
+//!
+//! When it loads a timeline, the pageserver reads the index file of the latest generation, and
+//! with its first index upload, and every few minutes after that, it checks that there is no
+//! index file of a later generation. If there is one, the tenant has been attached elsewhere
+//! since: the pageserver refuses to load the timeline, or stops its uploads and deletions, see
+//! [`StaleGenerationError`]. The index files of the earlier generations are deleted, so that
+//! the conditional uploads of their pageservers fail in between the checks.
+//!
+//! Tenants attached without a generation use the `index_part.json` name, as before.
+
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// The generation of an attachment of a tenant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Generation(u32);
+
+impl Generation {
+    pub const fn new(generation: u32) -> Self {
+        Generation(generation)
+    }
+
+    pub fn get(self) -> u32 {
+        self.0
+    }
+
+    /// Read the generation from the file of a tenant directory, `None` if the tenant was
+    /// attached without one.
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Option<Generation>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("read generation file {}", path.display()))
+            }
+        };
+        let generation = contents
+            .trim()
+            .parse()
+            .with_context(|| format!("parse generation file {}", path.display()))?;
+        Ok(Some(generation))
+    }
+
+    /// Write the generation into the file of a tenant directory. The caller fsyncs the
+    /// directory.
+    pub(crate) fn persist(self, path: &Path) -> anyhow::Result<()> {
+        let write = || -> std::io::Result<()> {
+            let mut file = std::fs::File::create(path)?;
+            file.write_all(format!("{self}\n").as_bytes())?;
+            file.sync_all()
+        };
+        write().with_context(|| format!("write generation file {}", path.display()))
+    }
+}
+
+/// Formatted as 8 hex digits, as in the index file names, so that they sort by generation.
+impl fmt::Display for Generation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
+
+impl FromStr for Generation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        anyhow::ensure!(
+            s.len() == 8 && s.bytes().all(|b| b.is_ascii_hexdigit()),
+            "generation {s:?} is not 8 hex digits"
+        );
+        let generation = u32::from_str_radix(s, 16)?;
+        Ok(Generation(generation))
+    }
+}
+
+/// The tenant has been attached with a later generation since it was attached to this
+/// pageserver, which must not write to its remote storage anymore.
+#[derive(Debug, thiserror::Error)]
+#[error("generation {current} is stale, the tenant has been attached with generation {latest}")]
+pub struct StaleGenerationError {
+    pub current: Generation,
+    pub latest: Generation,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_and_parse() {
+        let generation = Generation::new(0x2a);
+        assert_eq!(generation.to_string(), "0000002a");
+        assert_eq!("0000002a".parse::<Generation>().unwrap(), generation);
+        assert_eq!(
+            "ffffffff".parse::<Generation>().unwrap(),
+            Generation::new(u32::MAX)
+        );
+        assert!("2a".parse::<Generation>().is_err());
+        assert!("0000002g".parse::<Generation>().is_err());
+        assert!("+000002a".parse::<Generation>().is_err());
+    }
+
+    #[test]
+    fn load_and_persist() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("generation");
+        assert_eq!(Generation::load(&path)?, None);
+
+        Generation::new(3).persist(&path)?;
+        assert_eq!(Generation::load(&path)?, Some(Generation::new(3)));
+        Generation::new(4).persist(&path)?;
+        assert_eq!(Generation::load(&path)?, Some(Generation::new(4)));
+
+        std::fs::write(&path, "garbage")?;
+        assert!(Generation::load(&path).is_err());
+        Ok(())
+    }
+}
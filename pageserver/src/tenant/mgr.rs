@@ -3,6 +3,7 @@
 
 use std::collections::{hash_map, HashMap};
 use std::ffi::OsStr;
+use std::future::Future;
 use std::path::Path;
 use std::sync::Arc;
 use tokio::fs;
@@ -19,8 +20,11 @@ use utils::crashsafe;
 use crate::config::PageServerConf;
 use crate::context::{DownloadBehavior, RequestContext};
 use crate::task_mgr::{self, TaskKind};
-use crate::tenant::config::TenantConfOpt;
-use crate::tenant::{create_tenant_files, CreateTenantFilesMode, Tenant, TenantState};
+use crate::tenant::config::{TenantConfOpt, TenantRemoteStorageConfig};
+use crate::tenant::generation::Generation;
+use crate::tenant::{
+    create_tenant_files, CreateTenantFilesMode, Tenant, TenantImport, TenantState,
+};
 use crate::{InitializationOrder, IGNORED_TENANT_FILE_NAME};
 
 use utils::fs_ext::PathExt;
@@ -34,7 +38,7 @@ enum TenantsMap {
     /// [`init_tenant_mgr`] is done, all on-disk tenants have been loaded.
     /// New tenants can be added using [`tenant_map_insert`].
     Open(HashMap<TenantId, Arc<Tenant>>),
-    /// The pageserver has entered shutdown mode via [`shutdown_all_tenants`].
+    /// The pageserver has entered shutdown mode via [`begin_shutdown_all_tenants`].
     /// Existing tenants are still accessible, but no new tenants can be created.
     ShuttingDown(HashMap<TenantId, Arc<Tenant>>),
 }
@@ -190,6 +194,15 @@ pub fn schedule_local_tenant_processing(
         "Cannot load tenant, ignore mark found at {tenant_ignore_mark:?}"
     );
 
+    let remote_storage = match tenant_remote_storage(conf, &tenant_id) {
+        Ok(Some(tenant_remote_storage)) => Some(tenant_remote_storage),
+        Ok(None) => remote_storage,
+        Err(e) => {
+            error!("Failed to set up the remote storage of tenant {tenant_id}, reason: {e:#}");
+            return Ok(Tenant::create_broken_tenant(conf, tenant_id, format!("{e:#}")));
+        }
+    };
+
     let tenant = if conf.tenant_attaching_mark_file_path(&tenant_id).exists() {
         info!("tenant {tenant_id} has attaching mark file, resuming its attach operation");
         if let Some(remote_storage) = remote_storage {
@@ -223,6 +236,20 @@ pub fn schedule_local_tenant_processing(
     Ok(tenant)
 }
 
+/// The client of the remote storage of a tenant, `None` if the tenant uses the one of the
+/// pageserver.
+fn tenant_remote_storage(
+    conf: &'static PageServerConf,
+    tenant_id: &TenantId,
+) -> anyhow::Result<Option<GenericRemoteStorage>> {
+    let path = conf.tenant_remote_storage_path(tenant_id);
+    let Some(config) = TenantRemoteStorageConfig::load(&path)? else {
+        return Ok(None);
+    };
+    info!("tenant {tenant_id} uses its own remote storage: {config:?}");
+    super::create_remote_storage(conf, &config.config).map(Some)
+}
+
 ///
 /// Shut down all tenants. This runs as part of pageserver shutdown.
 ///
@@ -234,7 +261,12 @@ pub fn schedule_local_tenant_processing(
 /// management API. For example, it could attach the tenant on a different pageserver.
 /// We would then be in split-brain once this pageserver restarts.
 #[instrument]
-pub async fn shutdown_all_tenants() {
+/// The first step of the graceful shutdown of the tenants, see [`crate::shutdown`].
+///
+/// Prevents new tenants from being created or attached, and sets all tenants to Stopping
+/// state, which locks out new requests and signals their WAL receivers and background loops
+/// to stop. Returns the tenants to continue shutting down with [`on_each_tenant`].
+pub(crate) async fn begin_shutdown_all_tenants() -> Vec<Arc<Tenant>> {
     // Prevent new tenants from being created.
     let tenants_to_shut_down = {
         let mut m = TENANTS.write().await;
@@ -242,10 +274,10 @@ pub async fn shutdown_all_tenants() {
             TenantsMap::Initializing => {
                 *m = TenantsMap::ShuttingDown(HashMap::default());
                 info!("tenants map is empty");
-                return;
+                return Vec::new();
             }
             TenantsMap::Open(tenants) => {
-                let tenants_clone = tenants.clone();
+                let tenants_clone = tenants.values().cloned().collect::<Vec<_>>();
                 *m = TenantsMap::ShuttingDown(std::mem::take(tenants));
                 tenants_clone
             }
@@ -253,33 +285,46 @@ pub async fn shutdown_all_tenants() {
                 // TODO: it is possible that detach and shutdown happen at the same time. as a
                 // result, during shutdown we do not wait for detach.
                 error!("already shutting down, this function isn't supposed to be called more than once");
-                return;
+                return Vec::new();
             }
         }
     };
 
-    let mut join_set = JoinSet::new();
-    for (tenant_id, tenant) in tenants_to_shut_down {
-        join_set.spawn(
-            async move {
-                let freeze_and_flush = true;
-
-                match tenant.shutdown(freeze_and_flush).await {
-                    Ok(()) => debug!("tenant successfully stopped"),
-                    Err(super::ShutdownError::AlreadyStopping) => {
-                        warn!("tenant was already shutting down")
-                    }
-                }
+    on_each_tenant(&tenants_to_shut_down, |tenant| async move {
+        match tenant.begin_shutdown().await {
+            Ok(()) => Some(tenant),
+            Err(super::ShutdownError::AlreadyStopping) => {
+                warn!("tenant was already shutting down");
+                None
             }
-            .instrument(info_span!("shutdown", %tenant_id)),
-        );
+        }
+    })
+    .await
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Run the futures returned by `f` for all given tenants concurrently, and collect their
+/// outputs, in no particular order.
+pub(crate) async fn on_each_tenant<F, Fut, T>(tenants: &[Arc<Tenant>], f: F) -> Vec<T>
+where
+    F: Fn(Arc<Tenant>) -> Fut,
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let mut join_set = JoinSet::new();
+    for tenant in tenants {
+        let tenant_id = tenant.tenant_id;
+        join_set.spawn(f(Arc::clone(tenant)).instrument(info_span!("shutdown", %tenant_id)));
     }
 
+    let mut outputs = Vec::with_capacity(tenants.len());
     let mut panicked = 0;
 
     while let Some(res) = join_set.join_next().await {
         match res {
-            Ok(()) => {}
+            Ok(output) => outputs.push(output),
             Err(join_error) if join_error.is_cancelled() => {
                 unreachable!("we are not cancelling any of the futures");
             }
@@ -296,11 +341,14 @@ pub async fn shutdown_all_tenants() {
     if panicked > 0 {
         warn!(panicked, "observed panicks while shutting down tenants");
     }
+
+    outputs
 }
 
 pub async fn create_tenant(
     conf: &'static PageServerConf,
     tenant_conf: TenantConfOpt,
+    tenant_remote_storage: Option<TenantRemoteStorageConfig>,
     tenant_id: TenantId,
     broker_client: storage_broker::BrokerClientChannel,
     remote_storage: Option<GenericRemoteStorage>,
@@ -310,7 +358,7 @@ pub async fn create_tenant(
         // We're holding the tenants lock in write mode while doing local IO.
         // If this section ever becomes contentious, introduce a new `TenantState::Creating`
         // and do the work in that state.
-        let tenant_directory = super::create_tenant_files(conf, tenant_conf, &tenant_id, CreateTenantFilesMode::Create)?;
+        let tenant_directory = super::create_tenant_files(conf, tenant_conf, tenant_remote_storage.as_ref(), &tenant_id, CreateTenantFilesMode::Create)?;
         // TODO: tenant directory remains on disk if we bail out from here on.
         //       See https://github.com/neondatabase/neon/issues/4233
 
@@ -511,16 +559,20 @@ pub async fn list_tenants() -> Result<Vec<(TenantId, TenantState)>, TenantMapLis
 ///
 /// Downloading all the tenant data is performed in the background, this merely
 /// spawns the background task and returns quickly.
+#[allow(clippy::too_many_arguments)]
 pub async fn attach_tenant(
     conf: &'static PageServerConf,
     tenant_id: TenantId,
     tenant_conf: TenantConfOpt,
+    tenant_remote_storage: Option<TenantRemoteStorageConfig>,
+    generation: Option<Generation>,
+    import: Option<TenantImport>,
     broker_client: storage_broker::BrokerClientChannel,
-    remote_storage: GenericRemoteStorage,
+    remote_storage: Option<GenericRemoteStorage>,
     ctx: &RequestContext,
 ) -> Result<(), TenantMapInsertError> {
     tenant_map_insert(tenant_id, || {
-        let tenant_dir = create_tenant_files(conf, tenant_conf, &tenant_id, CreateTenantFilesMode::Attach)?;
+        let tenant_dir = create_tenant_files(conf, tenant_conf, tenant_remote_storage.as_ref(), &tenant_id, CreateTenantFilesMode::Attach { generation, import })?;
         // TODO: tenant directory remains on disk if we bail out from here on.
         //       See https://github.com/neondatabase/neon/issues/4233
 
@@ -531,7 +583,7 @@ pub async fn attach_tenant(
             .context("check for attach marker file existence")?;
         anyhow::ensure!(marker_file_exists, "create_tenant_files should have created the attach marker file");
 
-        let attached_tenant = schedule_local_tenant_processing(conf, &tenant_dir, broker_client, Some(remote_storage), None, ctx)?;
+        let attached_tenant = schedule_local_tenant_processing(conf, &tenant_dir, broker_client, remote_storage, None, ctx)?;
         // TODO: tenant object & its background loops remain, untracked in tenant map, if we fail here.
         //      See https://github.com/neondatabase/neon/issues/4233
 
@@ -636,6 +688,10 @@ where
             if tenants_accessor.remove(&tenant_id).is_none() {
                 warn!("Tenant {tenant_id} got removed from memory before operation finished");
             }
+            // Don't wait for the Tenant and its timelines to be dropped, they may still be
+            // referenced for a while. Do it under the lock so a concurrent re-attach
+            // cannot lose its freshly created metrics.
+            crate::metrics::remove_tenant_label_sets(&tenant_id);
             Ok(hook_value)
         }
         Err(e) => {
@@ -655,8 +711,9 @@ where
 }
 
 use {
-    crate::repository::GcResult, pageserver_api::models::TimelineGcRequest,
-    utils::http::error::ApiError,
+    crate::repository::GcResult,
+    pageserver_api::models::TimelineGcRequest,
+    utils::http::error::{ApiError, ErrorCode},
 };
 
 pub async fn immediate_gc(
@@ -670,7 +727,7 @@ pub async fn immediate_gc(
         .get(&tenant_id)
         .map(Arc::clone)
         .with_context(|| format!("tenant {tenant_id}"))
-        .map_err(|e| ApiError::NotFound(e.into()))?;
+        .map_err(|e| ApiError::NotFound(e.into()).with_code(ErrorCode::TenantNotFound))?;
 
     let gc_horizon = gc_req.gc_horizon.unwrap_or_else(|| tenant.get_gc_horizon());
     // Use tenant's pitr setting
@@ -719,11 +776,9 @@ pub async fn immediate_compact(
         .get(&tenant_id)
         .map(Arc::clone)
         .with_context(|| format!("tenant {tenant_id}"))
-        .map_err(|e| ApiError::NotFound(e.into()))?;
+        .map_err(|e| ApiError::NotFound(e.into()).with_code(ErrorCode::TenantNotFound))?;
 
-    let timeline = tenant
-        .get_timeline(timeline_id, true)
-        .map_err(|e| ApiError::NotFound(e.into()))?;
+    let timeline = tenant.get_timeline(timeline_id, true)?;
 
     // Run in task_mgr to avoid race with tenant_detach operation
     let ctx = ctx.detached_child(TaskKind::Compaction, DownloadBehavior::Download);
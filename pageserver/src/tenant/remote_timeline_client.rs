@@ -53,6 +53,13 @@
 //!
 //! Having the `IndexPart` also avoids expensive and slow `S3 list` commands.
 //!
+//! The index of a timeline with many layers can be written in a chunked form instead, as a
+//! manifest under the name of the index file and immutable segments, see [`index::chunked`].
+//!
+//! The index file is uploaded on the condition that it hasn't been written since we last read
+//! or wrote it, where the storage supports it. If it has, e.g. by another pageserver the tenant
+//! is attached to as well, the upload queue stops, like for a stale generation.
+//!
 //! # Consistency
 //!
 //! To have a consistent remote structure, it's important that uploads and
@@ -68,7 +75,8 @@
 //! and which operations act like a "barrier" that require preceding operations
 //! to finish. The calling code just needs to call the schedule-functions in the
 //! correct order, and the client will parallelize the operations in a way that
-//! is safe.
+//! is safe. Layer uploads may start ahead of the index uploads queued before them,
+//! and of back-to-back queued index uploads, only the latest is performed.
 //!
 //! The caller should be careful with deletion, though. They should not delete
 //! local files that have been scheduled for upload but not yet finished uploading.
@@ -82,10 +90,14 @@
 //!
 //! NB: Pageserver assumes that it has exclusive write access to the tenant in remote
 //! storage. Different tenants can be attached to different pageservers, but if the
-//! same tenant is attached to two pageservers at the same time, they will overwrite
-//! each other's index file updates, and confusion will ensue. There's no interlock or
-//! mechanism to detect that in the pageserver, we rely on the control plane to ensure
-//! that that doesn't happen.
+//! same tenant is attached to two pageservers at the same time, they could overwrite
+//! each other's index file updates. To detect that, the control plane passes a
+//! generation number with each attachment, see [`crate::tenant::generation`]: the index
+//! files are written as `index_part.json-<generation>`, and a pageserver that finds the
+//! index file of a later generation, when loading a timeline or when uploading its index
+//! file, stops writing to the timeline's remote storage. Tenants attached without a
+//! generation keep using `index_part.json`, with no interlock, and we rely on the control
+//! plane to ensure that they are not attached twice.
 //!
 //! ## Implementation Note
 //!
@@ -101,7 +113,9 @@
 //!
 //! # Retries & Error Handling
 //!
-//! The client retries operations indefinitely, using exponential back-off.
+//! The client retries operations using exponential back-off, by default
+//! indefinitely, see the `remote_retry` setting in [`retry`]. When an upload
+//! or deletion gives up, it stops the queue, or cancels the operation.
 //! There is no way to force a retry, i.e., interrupt the back-off.
 //! This could be built easily.
 //!
@@ -121,7 +135,11 @@
 //!
 //! The [`RemoteTimelineClient::wait_completion`] method can be used to wait
 //! for all pending operations to complete. It does not prevent more
-//! operations from getting scheduled.
+//! operations from getting scheduled. With the remote storage unreachable,
+//! the operations are retried and the wait doesn't end:
+//! [`RemoteTimelineClient::wait_completion_timeout`] gives up after a while, and
+//! [`RemoteTimelineClient::wait_completion_with_progress`] reports how much work
+//! is left meanwhile.
 //!
 //! # Crash Consistency
 //!
@@ -137,6 +155,12 @@
 //!   and remote state as per [`IndexPart`]. This is done in
 //!   [`Timeline::timeline_init_and_sync`] and [`Timeline::reconcile_with_remote`].
 //!
+//! A queue stopped with operations left, e.g. at shutdown, writes the index of the remote
+//! state they would have reached to the timeline directory, see
+//! [`IndexPart::PENDING_FILE_NAME`]. The next load diffs it against the remote index: the
+//! layer files the pending index no longer has, and that aren't present locally, are those of
+//! case (1) below, and their deletions are rescheduled.
+//!
 //! Note that if we crash during file deletion between the index update
 //! that removes the file from the list of files, and deleting the remote file,
 //! the file is leaked in the remote storage. Similarly, if a new file is created
@@ -199,37 +223,90 @@
 //! But note that we don't test any of this right now.
 //!
 
+mod compression;
+mod copy;
 mod delete;
 mod download;
+mod encryption;
+pub mod events;
+pub mod fault_injection;
+pub mod health;
+mod import;
 pub mod index;
+pub mod listing;
+pub(crate) mod mirror;
+pub mod multipart;
+mod retry;
+pub mod sharding;
+mod throttle;
+mod tiering;
 mod upload;
 
 use anyhow::Context;
 use chrono::{NaiveDateTime, Utc};
 // re-export these
-pub use download::{is_temp_download_file, list_remote_timelines};
+pub use compression::RemoteCompressionConfig;
+pub use download::{is_temp_download_file, list_remote_timelines, ParallelDownloadConfig};
+pub use encryption::RemoteEncryptionConfig;
+pub use health::{remote_storage_health, RemoteCircuitBreakerConfig};
+pub use import::{import_remote_timelines, TenantImport};
+pub use listing::{add_to_timeline_manifest, list_timelines_for_attach, RemoteListingConfig};
+pub use multipart::RemoteMultipartUploadConfig;
+pub use retry::{GiveUp, RemoteRetryConfig, RetryPolicy};
+pub use sharding::LayerPathScheme;
+pub use throttle::{UploadConcurrencyLimit, UploadThrottle};
+pub(crate) use tiering::TieringReport;
 use scopeguard::ScopeGuard;
 
 use std::collections::{HashMap, VecDeque};
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use remote_storage::{DownloadError, GenericRemoteStorage, RemotePath};
+use futures::{StreamExt, TryStreamExt};
+use pageserver_api::models::{
+    RemoteConsistencyReport, RemoteCopyReport, RemoteLayerMismatch, RemoteRestoreReport,
+    RemoteScrubReport, TimelineDeletionProgress, UploadQueueInfo,
+};
+use remote_storage::{
+    DownloadError, GenericRemoteStorage, RemotePath, RemoteStorageConfig, RemoteStorageKind,
+    S3ServerSideEncryption, UploadError, UploadPrecondition,
+};
 use std::ops::DerefMut;
 use tokio::runtime::Runtime;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, instrument, warn};
 use tracing::{info_span, Instrument};
+use utils::crashsafe::{self, path_with_suffix_extension};
 use utils::lsn::Lsn;
+use utils::warn_rate_limited;
 
 use crate::metrics::{
     MeasureRemoteOp, RemoteOpFileKind, RemoteOpKind, RemoteTimelineClientMetrics,
-    RemoteTimelineClientMetricsCallTrackSize, REMOTE_ONDEMAND_DOWNLOADED_BYTES,
-    REMOTE_ONDEMAND_DOWNLOADED_LAYERS,
+    RemoteTimelineClientMetricsCallTrackSize, REMOTE_INDEX_LSN_REGRESSIONS,
+    REMOTE_INDEX_UPLOADS_COALESCED, REMOTE_ONDEMAND_DOWNLOADED_BYTES,
+    REMOTE_ONDEMAND_DOWNLOADED_LAYERS, REMOTE_SCRUB_DELETED_BYTES, REMOTE_SCRUB_DELETED_OBJECTS,
+    REMOTE_TASK_REPEATED_FAILURES, REMOTE_TIERED_BYTES, REMOTE_TIERED_LAYERS,
+    REMOTE_UPLOAD_QUEUE_WAIT_SECONDS,
 };
 use crate::tenant::debug_assert_current_span_has_tenant_and_timeline_id;
+use crate::tenant::deferred_deletion::DeferredDeletions;
+use crate::tenant::deletion_dry_run::DeletionDryRun;
+use crate::tenant::generation::{Generation, StaleGenerationError};
+use crate::tenant::remote_timeline_client::index::chunked::{IndexSegments, SegmentRef};
 use crate::tenant::remote_timeline_client::index::LayerFileMetadata;
-use crate::tenant::upload_queue::Delete;
+use crate::tenant::remote_timeline_client::health::RemoteStorageHealth;
+use crate::tenant::remote_timeline_client::events::{
+    UploadEventSink, UploadQueueEvent, UploadQueueEventKind,
+};
+use crate::tenant::remote_timeline_client::mirror::{MirrorOp, TimelineMirror};
+use crate::tenant::remote_timeline_client::tiering::LayerTier;
+use crate::tenant::upload_queue::{
+    Delete, IndexLsnCheck, IndexLsnRegressionError, MAX_DELETE_BATCH_SIZE,
+};
 use crate::{
     config::PageServerConf,
     task_mgr,
@@ -237,29 +314,250 @@ use crate::{
     task_mgr::BACKGROUND_RUNTIME,
     tenant::metadata::TimelineMetadata,
     tenant::upload_queue::{
-        UploadOp, UploadQueue, UploadQueueInitialized, UploadQueueStopped, UploadTask,
+        QueuedOp, UploadOp, UploadQueue, UploadQueueInitialized, UploadQueueProgress,
+        UploadQueueStopped, UploadTask,
     },
-    {exponential_backoff, DEFAULT_BASE_BACKOFF_SECONDS, DEFAULT_MAX_BACKOFF_SECONDS},
 };
+use crate::TEMP_FILE_SUFFIX;
 
 use utils::id::{TenantId, TimelineId};
+use utils::instrumented_mutex::Mutex;
 
 use self::index::IndexPart;
+use self::upload::ConcurrentIndexModificationError;
 
 use super::storage_layer::LayerFileName;
 use super::upload_queue::SetDeletedFlagProgress;
 
-// Occasional network issues and such can cause remote operations to fail, and
-// that's expected. If a download fails, we log it at info-level, and retry.
-// But after FAILED_DOWNLOAD_WARN_THRESHOLD retries, we start to log it at WARN
-// level instead, as repeated failures can mean a more serious problem. If it
-// fails more than FAILED_DOWNLOAD_RETRIES times, we give up
-const FAILED_DOWNLOAD_WARN_THRESHOLD: u32 = 3;
-const FAILED_DOWNLOAD_RETRIES: u32 = 10;
+// How many layer files to check at once, in a consistency check of the remote storage.
+const MAX_CONCURRENT_LAYER_HEADS: usize = 16;
+
+// How many layer files to copy at once, in a copy of a timeline to another remote storage.
+const MAX_CONCURRENT_LAYER_COPIES: usize = 8;
+
+/// Layer files moved to the cold tier by one run of [`RemoteTimelineClient::tier_layers`], at
+/// most. The others are moved by the next runs.
+const MAX_TIERED_LAYERS_PER_RUN: usize = 1000;
+
+// How many layer upload events a subscriber may lag behind before missing some, see
+// `RemoteTimelineClient::subscribe_uploads`.
+const UPLOAD_EVENTS_CAPACITY: usize = 1024;
+
+/// How often the waits for the upload queue at shutdown and timeline deletion log the work left.
+pub(crate) const UPLOAD_PROGRESS_LOG_PERIOD: Duration = Duration::from_secs(10);
+
+/// How often the deletion of a timeline uploads the index without the layer files deleted so
+/// far, see [`RemoteTimelineClient::checkpoint_deletion`].
+const DELETION_CHECKPOINT_PERIOD: Duration = Duration::from_secs(60);
+
+/// How often the index uploads check for the index files of later generations, see
+/// [`upload::check_index_generation`]. In between, a stale attachment is caught by the
+/// conditional upload, once the new one has deleted its index file.
+const GENERATION_CHECK_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// Create the client of a remote storage, of the pageserver or of a tenant, with the
+/// `remote_encryption` and `test_remote_failures` settings applied, and with the `testing`
+/// feature, the faults of [`fault_injection`].
+pub fn create_remote_storage(
+    conf: &'static PageServerConf,
+    config: &RemoteStorageConfig,
+) -> anyhow::Result<GenericRemoteStorage> {
+    let mut config = config.clone();
+    match &conf.remote_encryption {
+        RemoteEncryptionConfig::None => {}
+        RemoteEncryptionConfig::ServerSide { kms_key_id } => {
+            let RemoteStorageKind::AwsS3(s3_config) = &mut config.storage else {
+                anyhow::bail!("server-side encryption is only supported with S3 remote storage");
+            };
+            s3_config.server_side_encryption = Some(match kms_key_id {
+                Some(key_id) => S3ServerSideEncryption::Kms {
+                    key_id: Some(key_id.clone()),
+                },
+                None => S3ServerSideEncryption::S3Managed,
+            });
+        }
+        RemoteEncryptionConfig::ClientSide { .. } => {
+            // Fail right away rather than on the first upload
+            conf.remote_encryption.check_key_file(conf)?;
+        }
+    }
+
+    // Create the client
+    let mut remote_storage = GenericRemoteStorage::from_config(&config)?;
+
+    // If `test_remote_failures` is non-zero, wrap the client with a
+    // wrapper that simulates failures.
+    if conf.test_remote_failures > 0 {
+        if !cfg!(feature = "testing") {
+            anyhow::bail!("test_remote_failures option is not available because pageserver was compiled without the 'testing' feature");
+        }
+        info!(
+            "Simulating remote failures for first {} attempts of each op",
+            conf.test_remote_failures
+        );
+        remote_storage =
+            GenericRemoteStorage::unreliable_wrapper(remote_storage, conf.test_remote_failures);
+    }
+
+    if cfg!(feature = "testing") {
+        let faults = Arc::clone(&fault_injection::REMOTE_STORAGE_FAULTS);
+        remote_storage = GenericRemoteStorage::fault_injecting(remote_storage, faults);
+    }
+
+    Ok(remote_storage)
+}
+
+/// Delete layer files of a timeline from the remote storage, outside of its upload queue, e.g.
+/// after the timeline has been deleted.
+pub(crate) async fn delete_layer_files(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+    layer_file_names: &[LayerFileName],
+) -> anyhow::Result<()> {
+    let timeline_path = conf.timeline_path(&tenant_id, &timeline_id);
+    for chunk in layer_file_names.chunks(MAX_DELETE_BATCH_SIZE) {
+        let paths = chunk
+            .iter()
+            .map(|name| timeline_path.join(name.file_name()))
+            .collect::<Vec<_>>();
+        delete::delete_layers(conf, storage, &paths, false).await?;
+    }
+    Ok(())
+}
+
+/// Errors of [`restore_index_snapshot`].
+#[derive(Debug, thiserror::Error)]
+pub enum RestoreIndexError {
+    #[error("timeline has no index file in the remote storage")]
+    TimelineNotFound,
+    #[error("no index snapshot at {lsn}, the available ones are at {available:?}")]
+    SnapshotNotFound { lsn: Lsn, available: Vec<Lsn> },
+    #[error("{} layer files of the index snapshot are missing: {}", .0.len(), .0.join(", "))]
+    MissingLayers(Vec<String>),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Replace the index file of a timeline with its snapshot at `lsn`, see
+/// [`IndexPart::snapshot_file_name`], e.g. to undo a bad compaction.
+///
+/// The tenant must not be attached anywhere meanwhile, its attachment would keep uploading
+/// the index it has. The restored index gets the generation of the latest index file, and
+/// its upload fails if that file changes meanwhile. Nothing is deleted: the layer files
+/// that only the replaced index references stay, so restoring a later snapshot undoes this.
+pub async fn restore_index_snapshot(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+    lsn: Lsn,
+    cancel: &CancellationToken,
+) -> Result<RemoteRestoreReport, RestoreIndexError> {
+    let timeline_path = conf.timeline_path(&tenant_id, &timeline_id);
+    let legacy_storage_path = conf.remote_path(&timeline_path.join(IndexPart::FILE_NAME))?;
+    let generations = download::list_index_generations(storage, &legacy_storage_path).await?;
+    // The index file without a generation is the oldest
+    let Some(generation) = generations.into_iter().max() else {
+        return Err(RestoreIndexError::TimelineNotFound);
+    };
 
-// Similarly log failed uploads and deletions at WARN level, after this many
-// retries. Uploads and deletions are retried forever, though.
-const FAILED_UPLOAD_WARN_THRESHOLD: u32 = 3;
+    let client = Arc::new(RemoteTimelineClient::new(
+        storage.clone(),
+        conf,
+        tenant_id,
+        timeline_id,
+        Arc::new(UploadThrottle::new(conf.upload_throttle)),
+        Arc::new(UploadConcurrencyLimit::new(None)),
+        Arc::new(DeferredDeletions::new(
+            conf.tenant_deferred_deletions_path(&tenant_id),
+        )),
+        Arc::new(DeletionDryRun::new(false)),
+        generation,
+    ));
+    // Sets up the conditional upload of the restored index over the current one
+    match client.download_index_file(cancel).await {
+        Ok(MaybeDeletedIndexPart::IndexPart(_)) => {}
+        Ok(MaybeDeletedIndexPart::Deleted(_)) => {
+            return Err(anyhow::anyhow!("timeline is being deleted").into())
+        }
+        Err(DownloadError::NotFound) => return Err(RestoreIndexError::TimelineNotFound),
+        Err(e) => return Err(anyhow::Error::new(e).context("download index file").into()),
+    }
+
+    let snapshot_path =
+        conf.remote_path(&timeline_path.join(IndexPart::snapshot_file_name(lsn)))?;
+    let download = download::download_bytes(conf, storage, &snapshot_path, cancel).await;
+    let snapshot_bytes = match download {
+        Ok((bytes, _etag)) => bytes,
+        Err(DownloadError::NotFound) => {
+            let snapshots_prefix =
+                conf.remote_path(&timeline_path.join(IndexPart::SNAPSHOT_FILE_PREFIX))?;
+            let mut available = storage
+                .list_files(Some(&snapshots_prefix))
+                .await
+                .context("list index snapshots")?
+                .iter()
+                .filter_map(|path| path.object_name())
+                .filter_map(IndexPart::parse_snapshot_file_name)
+                .collect::<Vec<_>>();
+            available.sort();
+            return Err(RestoreIndexError::SnapshotNotFound { lsn, available });
+        }
+        Err(e) => return Err(anyhow::Error::new(e).context("download index snapshot").into()),
+    };
+    let mut snapshot =
+        IndexPart::from_json_bytes(&snapshot_bytes).context("parse index snapshot")?;
+    let metadata = snapshot.parse_metadata().context("parse index snapshot metadata")?;
+
+    // The layer files at the default location in the snapshot may have been tiered since
+    let timeline_storage_path = &conf.remote_path(&timeline_path)?;
+    let heads = futures::stream::iter(snapshot.layer_metadata.iter())
+        .map(|(layer_file_name, index_metadata)| async move {
+            let head = |tier: Option<LayerTier>| async move {
+                let remote_path = sharding::remote_layer_path(
+                    timeline_storage_path,
+                    layer_file_name,
+                    index_metadata.path.as_deref(),
+                    tier,
+                );
+                download::head_layer_file(conf, storage, &remote_path, cancel)
+                    .await
+                    .with_context(|| format!("check remote layer file {layer_file_name}"))
+            };
+            let (tier, head) = match (index_metadata.tier, head(index_metadata.tier).await?) {
+                (None, None) => (Some(LayerTier::Cold), head(Some(LayerTier::Cold)).await?),
+                (tier, head) => (tier, head),
+            };
+            Ok::<_, anyhow::Error>((layer_file_name.clone(), tier, head))
+        })
+        .buffer_unordered(MAX_CONCURRENT_LAYER_HEADS)
+        .try_collect::<Vec<_>>()
+        .await?;
+    let mut missing_layers = Vec::new();
+    for (layer_file_name, tier, head) in heads {
+        if head.is_none() {
+            missing_layers.push(layer_file_name.file_name());
+        } else if let Some(index_metadata) = snapshot.layer_metadata.get_mut(&layer_file_name) {
+            index_metadata.tier = tier;
+        }
+    }
+    if !missing_layers.is_empty() {
+        missing_layers.sort();
+        return Err(RestoreIndexError::MissingLayers(missing_layers));
+    }
+
+    client.init_upload_queue(&snapshot)?;
+    client.schedule_index_upload_for_metadata_update(&metadata)?;
+    client.wait_completion().await?;
+
+    info!(%lsn, "restored the index of timeline {timeline_id} from its snapshot");
+    Ok(RemoteRestoreReport {
+        index_generation: generation.map(Generation::get),
+        restored_layers: snapshot.layer_metadata.len() as u64,
+    })
+}
 
 pub enum MaybeDeletedIndexPart {
     IndexPart(IndexPart),
@@ -275,6 +573,34 @@ pub enum StopError {
     QueueUninitialized,
 }
 
+/// Errors that can arise when calling [`RemoteTimelineClient::cancel_task`].
+#[derive(Debug, thiserror::Error)]
+pub enum CancelTaskError {
+    /// Returned if the upload queue is uninitialized or stopped.
+    #[error("queue is not initialized")]
+    QueueNotInitialized,
+    #[error("no in-progress upload task with id {0}")]
+    NoSuchTask(u64),
+}
+
+/// The outcome of a layer upload, see [`RemoteTimelineClient::subscribe_uploads`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerUploadEvent {
+    pub layer_file_name: LayerFileName,
+    pub result: Result<(), LayerUploadFailure>,
+}
+
+/// Why a scheduled layer upload didn't complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum LayerUploadFailure {
+    /// See [`RemoteTimelineClient::cancel_task`].
+    #[error("the upload was cancelled")]
+    Cancelled,
+    /// See [`RemoteTimelineClient::stop`].
+    #[error("the upload queue was stopped")]
+    QueueStopped,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum PersistIndexPartWithDeletedFlagError {
     #[error("another task is already setting the deleted_flag, started at {0:?}")]
@@ -317,6 +643,55 @@ pub struct RemoteTimelineClient {
     metrics: Arc<RemoteTimelineClientMetrics>,
 
     storage_impl: GenericRemoteStorage,
+
+    /// Shared by the timelines of the tenant.
+    upload_throttle: Arc<UploadThrottle>,
+
+    /// Shared by the timelines of the tenant, see [`throttle`].
+    upload_concurrency_limit: Arc<UploadConcurrencyLimit>,
+
+    /// Shared by the timelines of the tenant, see [`crate::tenant::deferred_deletion`].
+    deferred_deletions: Arc<DeferredDeletions>,
+
+    /// Shared by the timelines of the tenant, see [`crate::tenant::deletion_dry_run`].
+    deletion_dry_run: Arc<DeletionDryRun>,
+
+    /// Wakes up [`RemoteTimelineClient::wait_for_queue_space`] when a task completes.
+    queue_space_freed: tokio::sync::Notify,
+
+    /// See [`RemoteTimelineClient::subscribe_uploads`].
+    upload_events: tokio::sync::broadcast::Sender<LayerUploadEvent>,
+
+    /// The generation the tenant was attached with, in the names of the index files we write.
+    generation: Option<Generation>,
+
+    /// See [`mirror`].
+    mirror: Option<Arc<TimelineMirror>>,
+
+    /// See [`events`].
+    event_sink: Option<Arc<dyn UploadEventSink>>,
+
+    /// Shared by the timelines using the same remote storage, see [`health`].
+    storage_health: Arc<RemoteStorageHealth>,
+
+    /// The index file, as last uploaded, or downloaded before the first upload. `None` until
+    /// either.
+    remote_index: Mutex<Option<RemoteIndexState>>,
+}
+
+/// What we know of the index file of a timeline in the remote storage.
+#[derive(Clone)]
+struct RemoteIndexState {
+    /// The segments of the chunked index.
+    segments: Arc<IndexSegments>,
+    /// The condition for the next upload to overwrite the index file, `None` if the storage
+    /// doesn't report the entity tags.
+    precondition: Option<UploadPrecondition>,
+    /// When we last uploaded a snapshot of the index, see [`IndexPart::snapshot_file_name`].
+    last_snapshot: Option<Instant>,
+    /// When we last checked for the index files of later generations, see
+    /// [`GENERATION_CHECK_PERIOD`].
+    last_generation_check: Option<Instant>,
 }
 
 impl RemoteTimelineClient {
@@ -326,12 +701,31 @@ impl RemoteTimelineClient {
     /// Note: the caller must initialize the upload queue before any uploads can be scheduled,
     /// by calling init_upload_queue.
     ///
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         remote_storage: GenericRemoteStorage,
         conf: &'static PageServerConf,
         tenant_id: TenantId,
         timeline_id: TimelineId,
+        upload_throttle: Arc<UploadThrottle>,
+        upload_concurrency_limit: Arc<UploadConcurrencyLimit>,
+        deferred_deletions: Arc<DeferredDeletions>,
+        deletion_dry_run: Arc<DeletionDryRun>,
+        generation: Option<Generation>,
     ) -> RemoteTimelineClient {
+        let metrics = Arc::new(RemoteTimelineClientMetrics::new(&tenant_id, &timeline_id));
+        // Accounts every request of the timeline to the tenant, the retries included
+        let remote_storage = GenericRemoteStorage::metered(remote_storage, metrics.clone());
+        let mirror = mirror::tenant_mirror(conf, &tenant_id).map(|storage| {
+            Arc::new(TimelineMirror::new(
+                conf,
+                &BACKGROUND_RUNTIME,
+                tenant_id,
+                timeline_id,
+                remote_storage.clone(),
+                storage,
+            ))
+        });
         RemoteTimelineClient {
             conf,
             runtime: &BACKGROUND_RUNTIME,
@@ -339,17 +733,56 @@ impl RemoteTimelineClient {
             timeline_id,
             storage_impl: remote_storage,
             upload_queue: Mutex::new(UploadQueue::Uninitialized),
-            metrics: Arc::new(RemoteTimelineClientMetrics::new(&tenant_id, &timeline_id)),
+            metrics,
+            upload_throttle,
+            upload_concurrency_limit,
+            deferred_deletions,
+            deletion_dry_run,
+            queue_space_freed: tokio::sync::Notify::new(),
+            upload_events: tokio::sync::broadcast::channel(UPLOAD_EVENTS_CAPACITY).0,
+            generation,
+            mirror,
+            event_sink: events::sink(),
+            storage_health: health::tenant_storage_health(conf, &tenant_id),
+            remote_index: Mutex::new(None),
         }
     }
 
+    pub fn generation(&self) -> Option<Generation> {
+        self.generation
+    }
+
+    /// Subscribe to the outcomes of the layer uploads scheduled from now on: one event per
+    /// upload, when the layer file is in the remote storage, or when the upload is cancelled
+    /// or the queue stopped. The index referencing the layer file may be uploaded later.
+    ///
+    /// A subscriber lagging too far behind misses events, see
+    /// [`tokio::sync::broadcast::error::RecvError::Lagged`].
+    pub fn subscribe_uploads(&self) -> tokio::sync::broadcast::Receiver<LayerUploadEvent> {
+        self.upload_events.subscribe()
+    }
+
+    fn notify_layer_upload(
+        &self,
+        layer_file_name: &LayerFileName,
+        result: Result<(), LayerUploadFailure>,
+    ) {
+        // Fails only when there are no subscribers
+        let _ = self.upload_events.send(LayerUploadEvent {
+            layer_file_name: layer_file_name.clone(),
+            result,
+        });
+    }
+
     /// Initialize the upload queue for a remote storage that already received
     /// an index file upload, i.e., it's not empty.
     /// The given `index_part` must be the one on the remote.
     pub fn init_upload_queue(&self, index_part: &IndexPart) -> anyhow::Result<()> {
         let mut upload_queue = self.upload_queue.lock().unwrap();
-        upload_queue.initialize_with_current_remote_index_part(index_part)?;
+        let upload_queue = upload_queue.initialize_with_current_remote_index_part(index_part)?;
         self.update_remote_physical_size_gauge(Some(index_part));
+        self.metrics
+            .set_upload_queue_state(0, None, upload_queue.last_index_upload_at);
         Ok(())
     }
 
@@ -360,8 +793,10 @@ impl RemoteTimelineClient {
         local_metadata: &TimelineMetadata,
     ) -> anyhow::Result<()> {
         let mut upload_queue = self.upload_queue.lock().unwrap();
-        upload_queue.initialize_empty_remote(local_metadata)?;
+        let upload_queue = upload_queue.initialize_empty_remote(local_metadata)?;
         self.update_remote_physical_size_gauge(None);
+        self.metrics
+            .set_upload_queue_state(0, None, upload_queue.last_index_upload_at);
         Ok(())
     }
 
@@ -404,6 +839,29 @@ impl RemoteTimelineClient {
         }
     }
 
+    /// The state of the upload queue, for the management API.
+    pub fn upload_queue_info(&self) -> UploadQueueInfo {
+        self.upload_queue.lock().unwrap().info()
+    }
+
+    /// How far [`Self::delete_all`] got, once the index marks the timeline deleted.
+    pub fn deletion_progress(&self) -> Option<TimelineDeletionProgress> {
+        match &*self.upload_queue.lock().unwrap() {
+            UploadQueue::Stopped(stopped) => stopped.deletion_progress(),
+            UploadQueue::Uninitialized | UploadQueue::Initialized(_) => None,
+        }
+    }
+
+    /// The metadata of a layer file of the timeline, as of the latest scheduled index upload.
+    pub fn get_layer_metadata(
+        &self,
+        layer_file_name: &LayerFileName,
+    ) -> anyhow::Result<Option<LayerFileMetadata>> {
+        let mut guard = self.upload_queue.lock().unwrap();
+        let upload_queue = guard.initialized_mut()?;
+        Ok(upload_queue.latest_files.get(layer_file_name).cloned())
+    }
+
     fn update_remote_physical_size_gauge(&self, current_remote_index_part: Option<&IndexPart>) {
         let size: u64 = if let Some(current_remote_index_part) = current_remote_index_part {
             current_remote_index_part
@@ -430,7 +888,10 @@ impl RemoteTimelineClient {
     //
 
     /// Download index file
-    pub async fn download_index_file(&self) -> Result<MaybeDeletedIndexPart, DownloadError> {
+    pub async fn download_index_file(
+        &self,
+        cancel: &CancellationToken,
+    ) -> Result<MaybeDeletedIndexPart, DownloadError> {
         let _unfinished_gauge_guard = self.metrics.call_begin(
             &RemoteOpFileKind::Index,
             &RemoteOpKind::Download,
@@ -439,11 +900,13 @@ impl RemoteTimelineClient {
             },
         );
 
-        let index_part = download::download_index_part(
+        let (index_part, index_segments, precondition) = download::download_index_part(
             self.conf,
             &self.storage_impl,
             &self.tenant_id,
             &self.timeline_id,
+            self.generation,
+            cancel,
         )
         .measure_remote_op(
             self.tenant_id,
@@ -454,6 +917,17 @@ impl RemoteTimelineClient {
         )
         .await?;
 
+        // Once we have uploaded an index, a download may return an older one
+        self.remote_index
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| RemoteIndexState {
+                segments: Arc::new(index_segments),
+                precondition,
+                last_snapshot: None,
+                last_generation_check: None,
+            });
+
         if index_part.deleted_at.is_some() {
             Ok(MaybeDeletedIndexPart::Deleted(index_part))
         } else {
@@ -465,12 +939,19 @@ impl RemoteTimelineClient {
     ///
     /// 'layer_metadata' is the metadata from the remote index file.
     ///
-    /// On success, returns the size of the downloaded file.
+    /// On success, returns the size of the downloaded file. Cancelling `cancel` aborts the
+    /// download.
     pub async fn download_layer_file(
         &self,
         layer_file_name: &LayerFileName,
         layer_metadata: &LayerFileMetadata,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<u64> {
+        // The layer file may have been moved to the cold tier since the caller got its metadata,
+        // which may also predate its upload, and miss its path
+        let latest = self.get_layer_metadata(layer_file_name).ok().flatten();
+        let layer_metadata = latest.as_ref().unwrap_or(layer_metadata);
+
         let downloaded_size = {
             let _unfinished_gauge_guard = self.metrics.call_begin(
                 &RemoteOpFileKind::Layer,
@@ -486,6 +967,7 @@ impl RemoteTimelineClient {
                 self.timeline_id,
                 layer_file_name,
                 layer_metadata,
+                cancel,
             )
             .measure_remote_op(
                 self.tenant_id,
@@ -503,6 +985,26 @@ impl RemoteTimelineClient {
         Ok(downloaded_size)
     }
 
+    /// Whether the layer file is in the remote storage, with a HEAD request at its location in
+    /// the upload queue. A layer file still waiting for its upload doesn't exist yet: check this
+    /// before evicting a layer file, to not lose the only copy of it.
+    pub async fn layer_exists(
+        &self,
+        layer_file_name: &LayerFileName,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<bool> {
+        let timeline_path = self.conf.timeline_path(&self.tenant_id, &self.timeline_id);
+        let timeline_storage_path = self.conf.remote_path(&timeline_path)?;
+        // At the top of the timeline directory if it isn't in the queue
+        let remote_path = match self.get_layer_metadata(layer_file_name).ok().flatten() {
+            Some(metadata) => metadata.remote_path(&timeline_storage_path, layer_file_name),
+            None => timeline_storage_path.join(Path::new(&layer_file_name.file_name())),
+        };
+        let head =
+            download::head_layer_file(self.conf, &self.storage_impl, &remote_path, cancel).await?;
+        Ok(head.is_some())
+    }
+
     //
     // Upload operations.
     //
@@ -526,7 +1028,7 @@ impl RemoteTimelineClient {
         metadata: &TimelineMetadata,
     ) -> anyhow::Result<()> {
         let mut guard = self.upload_queue.lock().unwrap();
-        let upload_queue = guard.initialized_mut()?;
+        let upload_queue = guard.schedulable_mut()?;
 
         // As documented in the struct definition, it's ok for latest_metadata to be
         // ahead of what's _actually_ on the remote during index upload.
@@ -550,7 +1052,7 @@ impl RemoteTimelineClient {
     /// the upload to the upload queue and returns quickly.
     pub fn schedule_index_upload_for_file_changes(self: &Arc<Self>) -> anyhow::Result<()> {
         let mut guard = self.upload_queue.lock().unwrap();
-        let upload_queue = guard.initialized_mut()?;
+        let upload_queue = guard.schedulable_mut()?;
 
         if upload_queue.latest_files_changes_since_metadata_upload_scheduled > 0 {
             let metadata_bytes = upload_queue.latest_metadata.to_bytes()?;
@@ -574,14 +1076,15 @@ impl RemoteTimelineClient {
 
         let disk_consistent_lsn = upload_queue.latest_metadata.disk_consistent_lsn();
 
-        let index_part = IndexPart::new(
+        let mut index_part = IndexPart::new(
             upload_queue.latest_files.clone(),
             disk_consistent_lsn,
             metadata_bytes,
         );
+        index_part.generation = self.generation;
         let op = UploadOp::UploadMetadata(index_part, disk_consistent_lsn);
         self.calls_unfinished_metric_begin(&op);
-        upload_queue.queued_operations.push_back(op);
+        upload_queue.push_op(op);
         upload_queue.latest_files_changes_since_metadata_upload_scheduled = 0;
 
         // Launch the task immediately, if possible
@@ -597,16 +1100,17 @@ impl RemoteTimelineClient {
         layer_metadata: &LayerFileMetadata,
     ) -> anyhow::Result<()> {
         let mut guard = self.upload_queue.lock().unwrap();
-        let upload_queue = guard.initialized_mut()?;
+        let upload_queue = guard.schedulable_mut()?;
 
         upload_queue
             .latest_files
             .insert(layer_file_name.clone(), layer_metadata.clone());
         upload_queue.latest_files_changes_since_metadata_upload_scheduled += 1;
+        upload_queue.queued_layer_bytes += layer_metadata.file_size();
 
         let op = UploadOp::UploadLayer(layer_file_name.clone(), layer_metadata.clone());
         self.calls_unfinished_metric_begin(&op);
-        upload_queue.queued_operations.push_back(op);
+        upload_queue.push_op(op);
 
         info!("scheduled layer file upload {layer_file_name}");
 
@@ -629,7 +1133,7 @@ impl RemoteTimelineClient {
         names: &[LayerFileName],
     ) -> anyhow::Result<()> {
         let mut guard = self.upload_queue.lock().unwrap();
-        let upload_queue = guard.initialized_mut()?;
+        let upload_queue = guard.schedulable_mut()?;
 
         // Deleting layers doesn't affect the values stored in TimelineMetadata,
         // so we don't need update it. Just serialize it.
@@ -656,11 +1160,13 @@ impl RemoteTimelineClient {
             for name in names {
                 let op = UploadOp::Delete(Delete {
                     file_kind: RemoteOpFileKind::Layer,
-                    layer_file_name: name.clone(),
+                    layer_file_names: vec![name.clone()],
                     scheduled_from_timeline_delete: false,
+                    deferred: false,
+                    hot_copies_only: false,
                 });
                 self.calls_unfinished_metric_begin(&op);
-                upload_queue.queued_operations.push_back(op);
+                upload_queue.push_op(op);
                 info!("scheduled layer file deletion {name}");
             }
 
@@ -671,6 +1177,43 @@ impl RemoteTimelineClient {
         Ok(())
     }
 
+    /// Delete layer files whose deferred deletion is due, after the operations queued so far,
+    /// except those that the index references again. Returns when the deletions have completed.
+    /// See [`crate::tenant::deferred_deletion`].
+    pub(crate) async fn execute_deferred_deletions(
+        self: &Arc<Self>,
+        layer_file_names: &[LayerFileName],
+    ) -> anyhow::Result<()> {
+        let mut receiver = {
+            let mut guard = self.upload_queue.lock().unwrap();
+            let upload_queue = guard.schedulable_mut()?;
+
+            for name in layer_file_names {
+                if upload_queue.latest_files.contains_key(name) {
+                    info!("not deleting layer file {name}, the index references it again");
+                    continue;
+                }
+                let op = UploadOp::Delete(Delete {
+                    file_kind: RemoteOpFileKind::Layer,
+                    layer_file_names: vec![name.clone()],
+                    scheduled_from_timeline_delete: false,
+                    deferred: true,
+                    hot_copies_only: false,
+                });
+                self.calls_unfinished_metric_begin(&op);
+                upload_queue.push_op(op);
+            }
+
+            self.launch_queued_tasks(upload_queue);
+            self.schedule_barrier(upload_queue)
+        };
+
+        if receiver.changed().await.is_err() {
+            anyhow::bail!("deferred deletions aborted because upload queue was stopped");
+        }
+        Ok(())
+    }
+
     ///
     /// Wait for all previously scheduled uploads/deletions to complete
     ///
@@ -687,6 +1230,121 @@ impl RemoteTimelineClient {
         Ok(())
     }
 
+    /// Like [`Self::wait_completion`], but give up after `timeout`. The operations stay queued.
+    pub async fn wait_completion_timeout(
+        self: &Arc<Self>,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        match tokio::time::timeout(timeout, self.wait_completion()).await {
+            Ok(res) => res,
+            Err(_) => {
+                let progress = self.upload_queue.lock().unwrap().progress();
+                anyhow::bail!(
+                    "wait_completion timed out after {timeout:?} with {} operations and {} bytes remaining",
+                    progress.ops_remaining,
+                    progress.bytes_remaining
+                )
+            }
+        }
+    }
+
+    /// Like [`Self::wait_completion`], but call `on_progress` with the work left in the queue
+    /// every `period`, which must not be zero.
+    pub async fn wait_completion_with_progress(
+        self: &Arc<Self>,
+        period: Duration,
+        on_progress: impl FnMut(UploadQueueProgress),
+    ) -> anyhow::Result<()> {
+        let receiver = {
+            let mut guard = self.upload_queue.lock().unwrap();
+            let upload_queue = guard.initialized_mut()?;
+            self.schedule_barrier(upload_queue)
+        };
+
+        if self
+            .wait_barrier_with_progress(receiver, period, on_progress)
+            .await
+            .is_err()
+        {
+            anyhow::bail!("wait_completion aborted because upload queue was stopped");
+        }
+        Ok(())
+    }
+
+    async fn wait_barrier_with_progress(
+        &self,
+        mut receiver: tokio::sync::watch::Receiver<()>,
+        period: Duration,
+        mut on_progress: impl FnMut(UploadQueueProgress),
+    ) -> Result<(), tokio::sync::watch::error::RecvError> {
+        let mut ticks = tokio::time::interval(period);
+        // The first tick completes immediately
+        ticks.tick().await;
+        loop {
+            tokio::select! {
+                res = receiver.changed() => return res,
+                _ = ticks.tick() => {
+                    let progress = self.upload_queue.lock().unwrap().progress();
+                    on_progress(progress);
+                }
+            }
+        }
+    }
+
+    /// Stop accepting new operations, and wait for the queued ones to complete. From now on,
+    /// the `schedule_*` functions fail, while the queued operations go on.
+    ///
+    /// Unlike [`Self::stop`], which drops the queued operations, this leaves the remote storage
+    /// with the last scheduled index, for example before migrating the tenant to another
+    /// pageserver.
+    pub async fn drain(self: &Arc<Self>) -> anyhow::Result<()> {
+        let mut receiver = {
+            let mut guard = self.upload_queue.lock().unwrap();
+            let upload_queue = guard.initialized_mut()?;
+            if !upload_queue.draining {
+                info!("draining the upload queue");
+                upload_queue.draining = true;
+            }
+            // No operations can be queued after it
+            self.schedule_barrier(upload_queue)
+        };
+
+        if receiver.changed().await.is_err() {
+            anyhow::bail!("drain aborted because upload queue was stopped");
+        }
+        Ok(())
+    }
+
+    /// Wait until the upload queue is within the `upload_queue_limits` of the pageserver config,
+    /// for the flushes and compactions to slow down when the remote storage can't keep up,
+    /// instead of queueing operations without bounds.
+    ///
+    /// The limits are soft: once this returns, the caller may schedule any number of operations.
+    pub async fn wait_for_queue_space(&self) -> anyhow::Result<()> {
+        let limits = &self.conf.upload_queue_limits;
+        let mut waiting_since = None;
+        loop {
+            // Before checking the queue, not to miss a task completing after the check
+            let space_freed = self.queue_space_freed.notified();
+            {
+                let mut guard = self.upload_queue.lock().unwrap();
+                let upload_queue = guard.initialized_mut()?;
+                if !upload_queue.exceeds_limits(limits) {
+                    break;
+                }
+            }
+            if waiting_since.is_none() {
+                debug!("waiting for the upload queue to drain");
+                waiting_since = Some(std::time::Instant::now());
+            }
+            space_freed.await;
+        }
+        if let Some(waiting_since) = waiting_since {
+            REMOTE_UPLOAD_QUEUE_WAIT_SECONDS.inc_by(waiting_since.elapsed().as_secs_f64());
+        }
+        Ok(())
+    }
+
     fn schedule_barrier(
         self: &Arc<Self>,
         upload_queue: &mut UploadQueueInitialized,
@@ -694,7 +1352,7 @@ impl RemoteTimelineClient {
         let (sender, receiver) = tokio::sync::watch::channel(());
         let barrier_op = UploadOp::Barrier(sender);
 
-        upload_queue.queued_operations.push_back(barrier_op);
+        upload_queue.push_op(barrier_op);
         // Don't count this kind of operation!
 
         // Launch the task immediately, if possible
@@ -737,6 +1395,7 @@ impl RemoteTimelineClient {
             let mut index_part = IndexPart::try_from(&stopped.upload_queue_for_deletion)
                 .context("IndexPart serialize")?;
             index_part.deleted_at = Some(deleted_at);
+            index_part.generation = self.generation;
             index_part
         };
 
@@ -750,12 +1409,15 @@ impl RemoteTimelineClient {
 
         pausable_failpoint!("persist_deleted_index_part");
 
+        // Unconditionally: an index upload that was in progress when the queue stopped may
+        // still complete, after we read the state it updates
         upload::upload_index_part(
             self.conf,
             &self.storage_impl,
             &self.tenant_id,
             &self.timeline_id,
             &index_part_with_deleted_at,
+            None,
         )
         .await?;
 
@@ -780,67 +1442,77 @@ impl RemoteTimelineClient {
     /// Prerequisites: UploadQueue should be in stopped state and deleted_at should be successfuly set.
     /// The function deletes layer files one by one, then lists the prefix to see if we leaked something
     /// deletes leaked files if any and proceeds with deletion of index file at the end.
+    ///
+    /// While the layer files are being deleted, the index with deleted_at is periodically uploaded
+    /// without the ones deleted so far, so that a deletion resumed after a restart only deletes
+    /// the rest.
     pub(crate) async fn delete_all(self: &Arc<Self>) -> anyhow::Result<()> {
         debug_assert_current_span_has_tenant_and_timeline_id();
 
-        let (mut receiver, deletions_queued) = {
-            let mut deletions_queued = 0;
-
-            let mut locked = self.upload_queue.lock().unwrap();
-            let stopped = locked.stopped_mut()?;
-
-            if !matches!(stopped.deleted_at, SetDeletedFlagProgress::Successful(_)) {
-                anyhow::bail!("deleted_at is not set")
-            }
-
-            debug_assert!(stopped.upload_queue_for_deletion.no_pending_work());
-
-            stopped
-                .upload_queue_for_deletion
-                .queued_operations
-                .reserve(stopped.upload_queue_for_deletion.latest_files.len());
+        let (mut receiver, deletions_queued) = self.schedule_timeline_layer_deletions()?;
 
-            // schedule the actual deletions
-            for name in stopped.upload_queue_for_deletion.latest_files.keys() {
-                let op = UploadOp::Delete(Delete {
-                    file_kind: RemoteOpFileKind::Layer,
-                    layer_file_name: name.clone(),
-                    scheduled_from_timeline_delete: true,
-                });
-                self.calls_unfinished_metric_begin(&op);
-                stopped
-                    .upload_queue_for_deletion
-                    .queued_operations
-                    .push_back(op);
-
-                info!("scheduled layer file deletion {name}");
-                deletions_queued += 1;
+        let mut log_ticks = tokio::time::interval(UPLOAD_PROGRESS_LOG_PERIOD);
+        let mut checkpoint_ticks = tokio::time::interval(DELETION_CHECKPOINT_PERIOD);
+        // The first ticks complete immediately
+        log_ticks.tick().await;
+        checkpoint_ticks.tick().await;
+        loop {
+            tokio::select! {
+                res = receiver.changed() => {
+                    res?;
+                    break;
+                }
+                _ = log_ticks.tick() => {
+                    let progress = self.upload_queue.lock().unwrap().progress();
+                    info!(
+                        "waiting for the layer file deletions, {} operations remaining",
+                        progress.ops_remaining
+                    )
+                }
+                _ = checkpoint_ticks.tick() => {
+                    // The deletion goes on without it, a restart only deletes more again
+                    if let Err(e) = self.checkpoint_deletion().await {
+                        warn!("failed to checkpoint the timeline deletion progress: {e:#}");
+                    }
+                }
             }
-
-            self.launch_queued_tasks(&mut stopped.upload_queue_for_deletion);
-
-            (
-                self.schedule_barrier(&mut stopped.upload_queue_for_deletion),
-                deletions_queued,
-            )
-        };
-
-        receiver.changed().await?;
+        }
 
         // Do not delete index part yet, it is needed for possible retry. If we remove it first
         // and retry will arrive to different pageserver there wont be any traces of it on remote storage
         let timeline_path = self.conf.timeline_path(&self.tenant_id, &self.timeline_id);
         let timeline_storage_path = self.conf.remote_path(&timeline_path)?;
 
+        // The layer file deletions above delete the sharded copies too, these are leaked ones
+        let sharded_remaining = self
+            .storage_impl
+            .list_files(Some(&timeline_storage_path))
+            .await?
+            .into_iter()
+            .filter(|path| sharding::is_sharded_layer_path(&timeline_storage_path, path))
+            .collect::<Vec<_>>();
+        if !sharded_remaining.is_empty() {
+            warn!(
+                "Found {} files in the shard directories not bound to index_file.json, proceeding with their deletion",
+                sharded_remaining.len()
+            );
+            self.storage_impl.delete_objects(&sharded_remaining).await?;
+        }
+
         let remaining = self
             .storage_impl
             .list_prefixes(Some(&timeline_storage_path))
             .await?;
 
-        let remaining: Vec<RemotePath> = remaining
-            .into_iter()
-            .filter(|p| p.object_name() != Some(IndexPart::FILE_NAME))
-            .collect();
+        // The index with the deletion mark is plain, its segments are no longer referenced
+        let (index_files, remaining): (Vec<RemotePath>, Vec<RemotePath>) =
+            remaining.into_iter().partition(|p| {
+                p.object_name().map_or(false, |name| {
+                    IndexPart::parse_file_name(name).is_some()
+                        || IndexPart::parse_snapshot_file_name(name).is_some()
+                        || SegmentRef::is_file_name(name)
+                })
+            });
 
         if !remaining.is_empty() {
             warn!(
@@ -851,28 +1523,600 @@ impl RemoteTimelineClient {
             self.storage_impl.delete_objects(&remaining).await?;
         }
 
-        let index_file_path = timeline_storage_path.join(Path::new(IndexPart::FILE_NAME));
+        // The layer file deletions above delete the cold copies too, these are leaked ones
+        let cold_remaining = self
+            .storage_impl
+            .list_files(Some(&tiering::cold_path(&timeline_storage_path)))
+            .await?;
+        if !cold_remaining.is_empty() {
+            warn!(
+                "Found {} files in the cold tier not bound to index_file.json, proceeding with their deletion",
+                cold_remaining.len()
+            );
+            self.storage_impl.delete_objects(&cold_remaining).await?;
+        }
+
+        let index_file_path =
+            timeline_storage_path.join(Path::new(&IndexPart::file_name(self.generation)));
+
+        // Ours goes last, the others are of earlier generations or segments
+        let earlier_index_files: Vec<RemotePath> = index_files
+            .into_iter()
+            .filter(|p| p != &index_file_path)
+            .collect();
+        if !earlier_index_files.is_empty() {
+            debug!(
+                "deleting {} index files of earlier generations and index segments",
+                earlier_index_files.len()
+            );
+            self.storage_impl.delete_objects(&earlier_index_files).await?;
+        }
 
         debug!("deleting index part");
         self.storage_impl.delete(&index_file_path).await?;
 
+        if let Some(mirror) = &self.mirror {
+            mirror.push(MirrorOp::DeleteTimeline);
+        }
+
         info!(deletions_queued, "done deleting, including index_part.json");
 
         Ok(())
     }
 
-    ///
-    /// Pick next tasks from the queue, and start as many of them as possible without violating
-    /// the ordering constraints.
-    ///
+    /// Schedule the deletions of the layer files of the timeline left to delete, followed by
+    /// a barrier. Returns the barrier and the number of deletions scheduled.
+    fn schedule_timeline_layer_deletions(
+        self: &Arc<Self>,
+    ) -> anyhow::Result<(tokio::sync::watch::Receiver<()>, usize)> {
+        let mut deletions_queued = 0;
+
+        let mut locked = self.upload_queue.lock().unwrap();
+        let stopped = locked.stopped_mut()?;
+
+        if !matches!(stopped.deleted_at, SetDeletedFlagProgress::Successful(_)) {
+            anyhow::bail!("deleted_at is not set")
+        }
+
+        debug_assert!(stopped.upload_queue_for_deletion.no_pending_work());
+
+        stopped
+            .upload_queue_for_deletion
+            .queued_operations
+            .reserve(stopped.upload_queue_for_deletion.latest_files.len());
+
+        // schedule the actual deletions
+        for name in stopped.upload_queue_for_deletion.latest_files.keys() {
+            let op = UploadOp::Delete(Delete {
+                file_kind: RemoteOpFileKind::Layer,
+                layer_file_names: vec![name.clone()],
+                scheduled_from_timeline_delete: true,
+                deferred: false,
+                hot_copies_only: false,
+            });
+            self.calls_unfinished_metric_begin(&op);
+            stopped.upload_queue_for_deletion.push_op(op);
+
+            info!("scheduled layer file deletion {name}");
+            deletions_queued += 1;
+        }
+
+        self.launch_queued_tasks(&mut stopped.upload_queue_for_deletion);
+
+        Ok((
+            self.schedule_barrier(&mut stopped.upload_queue_for_deletion),
+            deletions_queued,
+        ))
+    }
+
+    /// Upload the index with deleted_at without the layer files deleted so far by
+    /// [`Self::delete_all`], if any were deleted since the last checkpoint. Uploading it
+    /// again is harmless, the layer files aren't referenced by anything else.
+    async fn checkpoint_deletion(&self) -> anyhow::Result<()> {
+        let (index_part, layers_deleted) = {
+            let mut locked = self.upload_queue.lock().unwrap();
+            let stopped = locked.stopped_mut()?;
+            let SetDeletedFlagProgress::Successful(deleted_at) = stopped.deleted_at else {
+                anyhow::bail!("deleted_at is not set")
+            };
+            if stopped.layers_deleted == stopped.layers_checkpointed {
+                return Ok(());
+            }
+
+            let mut index_part = IndexPart::try_from(&stopped.upload_queue_for_deletion)
+                .context("IndexPart serialize")?;
+            index_part.deleted_at = Some(deleted_at);
+            index_part.generation = self.generation;
+            (index_part, stopped.layers_deleted)
+        };
+
+        upload::upload_index_part(
+            self.conf,
+            &self.storage_impl,
+            &self.tenant_id,
+            &self.timeline_id,
+            &index_part,
+            None,
+        )
+        .await?;
+
+        let mut locked = self.upload_queue.lock().unwrap();
+        let stopped = locked.stopped_mut()?;
+        stopped.layers_checkpointed = stopped.layers_checkpointed.max(layers_deleted);
+        info!(
+            layers_deleted,
+            layers_remaining = index_part.layer_metadata.len(),
+            "checkpointed the timeline deletion progress"
+        );
+        Ok(())
+    }
+
+    /// Delete the objects under the timeline prefix in the remote storage that the index doesn't
+    /// reference, as left behind by a crash between a layer upload and the index upload
+    /// referencing it, or between the index upload dereferencing a layer and its deletion.
+    ///
+    /// Only the layer files written at least `min_age` ago are deleted, and they are deleted
+    /// through the upload queue, ordered with the uploads of layer files of the same name.
+    /// Index files and objects with unknown names are left alone.
+    pub async fn scrub_leaked_objects(
+        self: &Arc<Self>,
+        min_age: Duration,
+    ) -> anyhow::Result<RemoteScrubReport> {
+        let timeline_path = self.conf.timeline_path(&self.tenant_id, &self.timeline_id);
+        let timeline_storage_path = self.conf.remote_path(&timeline_path)?;
+        let objects = self
+            .storage_impl
+            .list_objects(Some(&timeline_storage_path))
+            .await
+            .context("list remote timeline objects")?;
+
+        // Once the tenant is attached elsewhere, the layers uploaded there aren't in our index
+        let latest_generation = objects
+            .iter()
+            .filter_map(|object| object.path.object_name())
+            .filter_map(IndexPart::parse_file_name)
+            .max()
+            .flatten();
+        if let Some(latest) = latest_generation {
+            match self.generation {
+                Some(current) if current >= latest => {}
+                Some(current) => return Err(StaleGenerationError { current, latest }.into()),
+                None => anyhow::bail!("the tenant has been attached with generation {latest}"),
+            }
+        }
+
+        let now = SystemTime::now();
+        let mut report = RemoteScrubReport::default();
+        let mut receiver = {
+            let mut guard = self.upload_queue.lock().unwrap();
+            let upload_queue = guard.schedulable_mut()?;
+
+            for object in objects {
+                let parent = object.path.get_path().parent();
+                if parent != Some(timeline_storage_path.get_path().as_path())
+                    && !sharding::is_sharded_layer_path(&timeline_storage_path, &object.path)
+                {
+                    continue;
+                }
+                let Some(name) = object.path.object_name() else {
+                    continue;
+                };
+                if IndexPart::parse_file_name(name).is_some()
+                    || IndexPart::parse_snapshot_file_name(name).is_some()
+                    || SegmentRef::is_file_name(name)
+                {
+                    continue;
+                }
+                let Ok(layer_file_name) = LayerFileName::from_str(name) else {
+                    warn!("not scrubbing object with unknown name {:?}", object.path);
+                    continue;
+                };
+                // Referenced from the index, or queued for upload
+                if upload_queue.latest_files.contains_key(&layer_file_name) {
+                    continue;
+                }
+                let age = object
+                    .last_modified
+                    .and_then(|last_modified| now.duration_since(last_modified).ok());
+                if !matches!(age, Some(age) if age >= min_age) {
+                    report.skipped_recent_objects += 1;
+                    continue;
+                }
+
+                let op = UploadOp::Delete(Delete {
+                    file_kind: RemoteOpFileKind::Layer,
+                    layer_file_names: vec![layer_file_name],
+                    scheduled_from_timeline_delete: false,
+                    deferred: false,
+                    hot_copies_only: false,
+                });
+                self.calls_unfinished_metric_begin(&op);
+                upload_queue.push_op(op);
+                info!("scheduled deletion of leaked layer file {name}");
+                report.deleted_objects += 1;
+                report.deleted_bytes += object.size;
+            }
+
+            self.schedule_barrier(upload_queue)
+        };
+
+        if receiver.changed().await.is_err() {
+            anyhow::bail!("scrub aborted because upload queue was stopped");
+        }
+        REMOTE_SCRUB_DELETED_OBJECTS.inc_by(report.deleted_objects);
+        REMOTE_SCRUB_DELETED_BYTES.inc_by(report.deleted_bytes);
+        Ok(report)
+    }
+
+    /// Move the layer files uploaded at least `threshold` ago to the cold tier, see [`tiering`]:
+    /// copy them there within the remote storage, record their tier in the index, and delete
+    /// the copies at the default location once that index is uploaded. The copies at the
+    /// default location left behind by an earlier run, interrupted before their deletion, are
+    /// deleted too. Returns when the deletions have completed.
+    ///
+    /// A layer file uploaded again or deleted while it is being copied is left alone.
+    pub(crate) async fn tier_layers(
+        self: &Arc<Self>,
+        threshold: Duration,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<TieringReport> {
+        let timeline_path = self.conf.timeline_path(&self.tenant_id, &self.timeline_id);
+        let timeline_storage_path = self.conf.remote_path(&timeline_path)?;
+        let objects = self
+            .storage_impl
+            .list_objects(Some(&timeline_storage_path))
+            .await
+            .context("list remote timeline objects")?;
+
+        let now = SystemTime::now();
+        let mut candidates = Vec::new();
+        let mut leftovers = Vec::new();
+        {
+            let mut guard = self.upload_queue.lock().unwrap();
+            let upload_queue = guard.schedulable_mut()?;
+
+            for object in objects {
+                let Some(Ok(layer_file_name)) =
+                    object.path.object_name().map(LayerFileName::from_str)
+                else {
+                    continue;
+                };
+                let Some(metadata) = upload_queue.latest_files.get(&layer_file_name) else {
+                    continue;
+                };
+                // Not a copy at the default location of a layer file the index references
+                let hot_path = sharding::remote_layer_path(
+                    &timeline_storage_path,
+                    &layer_file_name,
+                    metadata.path(),
+                    None,
+                );
+                if object.path != hot_path {
+                    continue;
+                }
+                if metadata.tier().is_some() {
+                    leftovers.push(layer_file_name);
+                    continue;
+                }
+                let age = object
+                    .last_modified
+                    .and_then(|last_modified| now.duration_since(last_modified).ok());
+                if matches!(age, Some(age) if age >= threshold)
+                    && !upload_queue.has_layer_upload(&layer_file_name)
+                    && candidates.len() < MAX_TIERED_LAYERS_PER_RUN
+                {
+                    candidates.push((layer_file_name, metadata.clone()));
+                }
+            }
+        }
+
+        let copies = futures::stream::iter(candidates)
+            .map(|(layer_file_name, metadata)| {
+                let path = metadata.remote_path(&timeline_storage_path, &layer_file_name);
+                async move {
+                    self.storage_impl
+                        .copy(&path, &tiering::cold_path(&path))
+                        .await
+                        .with_context(|| {
+                            format!("copy layer file {layer_file_name} to the cold tier")
+                        })?;
+                    Ok::<_, anyhow::Error>((layer_file_name, metadata))
+                }
+            })
+            .buffer_unordered(MAX_CONCURRENT_LAYER_COPIES)
+            .try_collect::<Vec<_>>();
+        let copied = tokio::select! {
+            copied = copies => copied?,
+            _ = cancel.cancelled() => anyhow::bail!("tiering cancelled"),
+        };
+
+        let mut report = TieringReport::default();
+        let mut receiver = {
+            let mut guard = self.upload_queue.lock().unwrap();
+            let upload_queue = guard.schedulable_mut()?;
+            let metadata_bytes = upload_queue.latest_metadata.to_bytes()?;
+
+            let mut hot_copies = Vec::new();
+            for (layer_file_name, metadata) in copied {
+                // Uploaded again or deleted meanwhile
+                if upload_queue.latest_files.get(&layer_file_name) != Some(&metadata)
+                    || upload_queue.has_layer_upload(&layer_file_name)
+                {
+                    continue;
+                }
+                report.tiered_layers += 1;
+                report.tiered_bytes += metadata.remote_size();
+                upload_queue
+                    .latest_files
+                    .insert(layer_file_name.clone(), metadata.tiered(LayerTier::Cold));
+                upload_queue.latest_files_changes_since_metadata_upload_scheduled += 1;
+                hot_copies.push(layer_file_name);
+            }
+            for layer_file_name in leftovers {
+                // Unless uploaded again since the listing
+                let tiered = upload_queue
+                    .latest_files
+                    .get(&layer_file_name)
+                    .map_or(false, |metadata| metadata.tier().is_some());
+                if tiered {
+                    report.deleted_leftovers += 1;
+                    hot_copies.push(layer_file_name);
+                }
+            }
+
+            // The index referencing the cold copies goes first, also for the leftovers: the
+            // index upload of the earlier run may have been cancelled
+            if !hot_copies.is_empty() {
+                self.schedule_index_upload(upload_queue, metadata_bytes);
+            }
+            for layer_file_name in hot_copies {
+                let op = UploadOp::Delete(Delete {
+                    file_kind: RemoteOpFileKind::Layer,
+                    layer_file_names: vec![layer_file_name],
+                    scheduled_from_timeline_delete: false,
+                    deferred: false,
+                    hot_copies_only: true,
+                });
+                self.calls_unfinished_metric_begin(&op);
+                upload_queue.push_op(op);
+            }
+
+            self.launch_queued_tasks(upload_queue);
+            self.schedule_barrier(upload_queue)
+        };
+
+        if receiver.changed().await.is_err() {
+            anyhow::bail!("tiering aborted because upload queue was stopped");
+        }
+        REMOTE_TIERED_LAYERS.inc_by(report.tiered_layers);
+        REMOTE_TIERED_BYTES.inc_by(report.tiered_bytes);
+        Ok(report)
+    }
+
+    /// Check the layer files in the remote storage against the latest index there: every layer
+    /// file that the index references must exist, with the size that the index records, and the
+    /// checksum too, when the remote storage has one.
+    ///
+    /// This checks the uploaded index, not the upload queue, so the layer files referenced by
+    /// the index uploads still queued aren't checked.
+    pub async fn validate_remote_consistency(
+        &self,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<RemoteConsistencyReport> {
+        let index_part = match self.download_index_file(cancel).await? {
+            MaybeDeletedIndexPart::IndexPart(index_part) => index_part,
+            MaybeDeletedIndexPart::Deleted(_) => anyhow::bail!("timeline is being deleted"),
+        };
+        let timeline_path = self.conf.timeline_path(&self.tenant_id, &self.timeline_id);
+        let timeline_storage_path = &self.conf.remote_path(&timeline_path)?;
+
+        let heads = futures::stream::iter(index_part.layer_metadata.iter())
+            .map(|(layer_file_name, index_metadata)| async move {
+                let metadata = LayerFileMetadata::from(index_metadata);
+                let remote_path = metadata.remote_path(timeline_storage_path, layer_file_name);
+                let head = download::head_layer_file(
+                    self.conf,
+                    &self.storage_impl,
+                    &remote_path,
+                    cancel,
+                )
+                .await
+                .with_context(|| format!("check remote layer file {layer_file_name}"))?;
+                Ok::<_, anyhow::Error>((layer_file_name, metadata, head))
+            })
+            .buffer_unordered(MAX_CONCURRENT_LAYER_HEADS)
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        let mut report = RemoteConsistencyReport {
+            index_generation: index_part.generation.map(Generation::get),
+            checked_layers: heads.len() as u64,
+            ..RemoteConsistencyReport::default()
+        };
+        for (layer_file_name, metadata, head) in heads {
+            let Some(head) = head else {
+                report.missing_layers.push(layer_file_name.file_name());
+                continue;
+            };
+            // The index has the checksum of the layer file before compression and encryption
+            let stored_as_is = metadata.compression().is_none() && metadata.encryption().is_none();
+            let crc32c_mismatch = match (metadata.crc32c(), head.crc32c) {
+                (Some(expected), Some(remote)) => stored_as_is && expected != remote,
+                _ => false,
+            };
+            if head.size != metadata.remote_size() || crc32c_mismatch {
+                report.mismatched_layers.push(RemoteLayerMismatch {
+                    layer_file_name: layer_file_name.file_name(),
+                    expected_size: metadata.remote_size(),
+                    remote_size: head.size,
+                    expected_crc32c: metadata.crc32c(),
+                    remote_crc32c: head.crc32c,
+                });
+            }
+        }
+        report.missing_layers.sort();
+        report
+            .mismatched_layers
+            .sort_by(|a, b| a.layer_file_name.cmp(&b.layer_file_name));
+
+        if !report.is_consistent() {
+            warn!(
+                missing = report.missing_layers.len(),
+                mismatched = report.mismatched_layers.len(),
+                "remote storage is inconsistent with the index"
+            );
+        }
+        Ok(report)
+    }
+
+    /// Copy the timeline as of its latest index in the remote storage to another remote
+    /// storage, e.g. to relocate the tenant to another bucket: the layer files the index
+    /// references, then the index. The layer files already at the destination with the expected
+    /// size are skipped, so an interrupted copy can be resumed. The index goes last, so the
+    /// timeline at the destination is complete once it has an index.
+    pub async fn copy_timeline_remote(
+        &self,
+        destination: &GenericRemoteStorage,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<RemoteCopyReport> {
+        let index_part = match self.download_index_file(cancel).await? {
+            MaybeDeletedIndexPart::IndexPart(index_part) => index_part,
+            MaybeDeletedIndexPart::Deleted(_) => anyhow::bail!("timeline is being deleted"),
+        };
+        let timeline_path = self.conf.timeline_path(&self.tenant_id, &self.timeline_id);
+        let timeline_storage_path = self.conf.remote_path(&timeline_path)?;
+
+        let copies = futures::stream::iter(index_part.layer_metadata.iter())
+            .map(|(layer_file_name, index_metadata)| {
+                let metadata = LayerFileMetadata::from(index_metadata);
+                // The destination gets the same paths and tiers, as its copy of the index says
+                let path = metadata.remote_path(&timeline_storage_path, layer_file_name);
+                async move {
+                    let expected_size = metadata.remote_size();
+                    match destination.head_object(&path).await {
+                        Ok(head) if head.size == expected_size => return Ok(None),
+                        Ok(_) | Err(DownloadError::NotFound) => {}
+                        Err(e) => {
+                            return Err(e).with_context(|| {
+                                format!("check layer file {layer_file_name} at the destination")
+                            })
+                        }
+                    }
+                    let size = copy::copy_object(&self.storage_impl, destination, &path)
+                        .await?
+                        .with_context(|| format!("layer file {layer_file_name} is missing"))?;
+                    Ok::<_, anyhow::Error>(Some(size))
+                }
+            })
+            .buffer_unordered(MAX_CONCURRENT_LAYER_COPIES)
+            .try_collect::<Vec<_>>();
+        let copies = tokio::select! {
+            copies = copies => copies?,
+            _ = cancel.cancelled() => anyhow::bail!("copy cancelled"),
+        };
+
+        let mut report = RemoteCopyReport {
+            index_generation: index_part.generation.map(Generation::get),
+            ..RemoteCopyReport::default()
+        };
+        for copied in copies {
+            match copied {
+                Some(size) => {
+                    report.copied_layers += 1;
+                    report.copied_bytes += size;
+                }
+                None => report.skipped_layers += 1,
+            }
+        }
+
+        copy::upload_index_copy(destination, &timeline_storage_path, &index_part).await?;
+        info!(
+            copied_layers = report.copied_layers,
+            copied_bytes = report.copied_bytes,
+            skipped_layers = report.skipped_layers,
+            "copied timeline to another remote storage"
+        );
+        Ok(report)
+    }
+
+    /// Upload a basebackup tarball of the timeline, see [`Timeline::export_basebackup`], to
+    /// `destination`, or to the remote storage of the timeline by default. Failed uploads are
+    /// retried like the operations of the upload queue, with its `remote_retry.upload` policy.
+    /// Returns the size of the uploaded tarball.
+    ///
+    /// [`Timeline::export_basebackup`]: crate::tenant::Timeline::export_basebackup
+    pub(crate) async fn upload_export(
+        &self,
+        local_path: &Path,
+        destination: Option<&GenericRemoteStorage>,
+        remote_path: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<u64> {
+        let storage = destination.unwrap_or(&self.storage_impl);
+        let policy = &self.conf.remote_retry.upload;
+        let mut retries = 0;
+        loop {
+            let result = tokio::select! {
+                biased;
+                _ = cancel.cancelled() => anyhow::bail!("export cancelled"),
+                result = upload::upload_file(storage, local_path, remote_path) => result,
+            };
+            match result {
+                Ok(size) => {
+                    if retries > 0 {
+                        info!("export upload succeeded after {retries} retries");
+                    }
+                    return Ok(size);
+                }
+                Err(e) if policy.gives_up(retries) => {
+                    warn!("export upload still failed after {retries} retries, giving up: {e:?}");
+                    return Err(e);
+                }
+                Err(e) if !policy.warns(retries) => {
+                    info!("export upload failed, will retry (attempt {retries}): {e:#}");
+                }
+                Err(e) => {
+                    warn!("export upload failed, will retry (attempt {retries}): {e:#}");
+                }
+            }
+            tokio::select! {
+                _ = cancel.cancelled() => anyhow::bail!("export cancelled"),
+                _ = policy.backoff(retries) => {}
+            }
+            retries += 1;
+        }
+    }
+
+    ///
+    /// Pick next tasks from the queue, and start as many of them as possible without violating
+    /// the ordering constraints.
+    ///
     /// The caller needs to already hold the `upload_queue` lock.
     fn launch_queued_tasks(self: &Arc<Self>, upload_queue: &mut UploadQueueInitialized) {
-        while let Some(next_op) = upload_queue.queued_operations.front() {
+        loop {
+            // An index upload followed by another one is superseded by it: the later index
+            // includes all the changes of the earlier one, so only upload the latest.
+            while upload_queue.next_index_upload_superseded() {
+                let superseded = upload_queue.queued_operations.pop_front().unwrap().op;
+                debug!("skipping superseded op: {}", superseded);
+                self.calls_unfinished_metric_end(&superseded);
+                REMOTE_INDEX_UPLOADS_COALESCED.inc();
+            }
+
+            let Some(QueuedOp { op: next_op, .. }) = upload_queue.queued_operations.front() else {
+                break;
+            };
+
+            // The `max_inprogress_uploads` of the tenant config, see `throttle`
+            let can_upload_layer = self
+                .upload_concurrency_limit
+                .allows(upload_queue.num_inprogress_layer_uploads);
+
             // Can we run this task now?
             let can_run_now = match next_op {
                 UploadOp::UploadLayer(_, _) => {
-                    // Can always be scheduled.
-                    true
+                    // Can be scheduled up to the limit of the tenant.
+                    can_upload_layer
                 }
                 UploadOp::UploadMetadata(_, _) => {
                     // These can only be performed after all the preceding operations
@@ -887,18 +2131,53 @@ impl RemoteTimelineClient {
                 UploadOp::Barrier(_) => upload_queue.inprogress_tasks.is_empty(),
             };
 
-            // If we cannot launch this task, don't look any further.
-            //
-            // In some cases, we could let some non-frontmost tasks to "jump the queue" and launch
-            // them now, but we don't try to do that currently.  For example, if the frontmost task
-            // is an index-file upload that cannot proceed until preceding uploads have finished, we
-            // could still start layer uploads that were scheduled later.
-            if !can_run_now {
-                break;
+            let next_index = if can_run_now {
+                0
+            } else {
+                // A layer upload may jump ahead of the index uploads waiting in front of it:
+                // they don't reference the layer, and the index uploads after it still wait
+                // for it. It may not jump ahead of deletions, which could be of a layer file
+                // with the same name, nor of barriers. Nor of an index upload referencing an
+                // earlier upload of a layer file with the same name, which would get the
+                // checksum of this one.
+                let first_not_index = upload_queue
+                    .queued_operations
+                    .iter()
+                    .position(|queued| !matches!(queued.op, UploadOp::UploadMetadata(_, _)));
+                match first_not_index.map(|i| (i, &upload_queue.queued_operations[i].op)) {
+                    Some((i, UploadOp::UploadLayer(name, _)))
+                        if can_upload_layer
+                            && !upload_queue.queued_index_upload_references(i, name) =>
+                    {
+                        i
+                    }
+                    // If we cannot launch any task, don't look any further.
+                    _ => break,
+                }
+            };
+
+            // Don't add to the load of a failing remote storage, see `health`. The barriers
+            // don't make requests.
+            let next_op = &upload_queue.queued_operations[next_index].op;
+            if !matches!(next_op, UploadOp::Barrier(_)) {
+                if let Some(until) = self.storage_health.paused_until() {
+                    self.relaunch_queued_tasks_at(upload_queue, until);
+                    break;
+                }
             }
 
             // We can launch this task. Remove it from the queue first.
-            let next_op = upload_queue.queued_operations.pop_front().unwrap();
+            let QueuedOp {
+                op: mut next_op,
+                queued_at,
+            } = upload_queue.queued_operations.remove(next_index).unwrap();
+
+            // Delete the layer files of the deletions queued after it with the same request
+            if let UploadOp::Delete(delete) = &mut next_op {
+                for merged in upload_queue.merge_queued_deletions(delete) {
+                    self.calls_unfinished_metric_end(&merged);
+                }
+            }
 
             debug!("starting op: {}", next_op);
 
@@ -928,6 +2207,9 @@ impl RemoteTimelineClient {
                 task_id: upload_task_id,
                 op: next_op,
                 retries: AtomicU32::new(0),
+                last_error: Mutex::new(None),
+                cancel: CancellationToken::new(),
+                queued_at,
             });
             upload_queue
                 .inprogress_tasks
@@ -953,98 +2235,415 @@ impl RemoteTimelineClient {
 
             // Loop back to process next task
         }
+
+        self.metrics.set_upload_queue_state(
+            upload_queue.progress().ops_remaining,
+            upload_queue.oldest_op_queued_at(),
+            upload_queue.last_index_upload_at,
+        );
     }
 
-    ///
-    /// Perform an upload task.
-    ///
-    /// The task is in the `inprogress_tasks` list. This function will try to
-    /// execute it, retrying forever. On successful completion, the task is
-    /// removed it from the `inprogress_tasks` list, and any next task(s) in the
-    /// queue that were waiting by the completion are launched.
-    ///
-    /// The task can be shut down, however. That leads to stopping the whole
-    /// queue.
-    ///
-    async fn perform_upload_task(self: &Arc<Self>, task: Arc<UploadTask>) {
-        // Loop to retry until it completes.
-        loop {
-            // If we're requested to shut down, close up shop and exit.
-            //
-            // Note: We only check for the shutdown requests between retries, so
-            // if a shutdown request arrives while we're busy uploading, in the
-            // upload::upload:*() call below, we will wait not exit until it has
-            // finished. We probably could cancel the upload by simply dropping
-            // the Future, but we're not 100% sure if the remote storage library
-            // is cancellation safe, so we don't dare to do that. Hopefully, the
-            // upload finishes or times out soon enough.
-            if task_mgr::is_shutdown_requested() {
-                info!("upload task cancelled by shutdown request");
-                match self.stop() {
-                    Ok(()) => {}
-                    Err(StopError::QueueUninitialized) => {
-                        unreachable!("we never launch an upload task if the queue is uninitialized, and once it is initialized, we never go back")
+    /// Launch the queued tasks at `until`, when the circuit breaker of the remote storage is
+    /// no longer open, unless a relaunch is already scheduled.
+    fn relaunch_queued_tasks_at(
+        self: &Arc<Self>,
+        upload_queue: &mut UploadQueueInitialized,
+        until: Instant,
+    ) {
+        if upload_queue.relaunch_scheduled {
+            return;
+        }
+        upload_queue.relaunch_scheduled = true;
+        debug!("remote storage circuit breaker open, pausing the upload queue");
+
+        let self_rc = Arc::clone(self);
+        let tenant_id = self.tenant_id;
+        let timeline_id = self.timeline_id;
+        task_mgr::spawn(
+            self.runtime.handle(),
+            TaskKind::RemoteUploadTask,
+            Some(tenant_id),
+            Some(timeline_id),
+            "relaunch remote tasks",
+            false,
+            async move {
+                tokio::select! {
+                    _ = task_mgr::shutdown_watcher() => {}
+                    _ = tokio::time::sleep_until(tokio::time::Instant::from_std(until)) => {
+                        let mut guard = self_rc.upload_queue.lock().unwrap();
+                        // Not if the queue has been stopped meanwhile
+                        if let Ok(upload_queue) = guard.initialized_mut() {
+                            upload_queue.relaunch_scheduled = false;
+                            self_rc.launch_queued_tasks(upload_queue);
+                        }
                     }
                 }
-                return;
+                Ok(())
             }
+            .instrument(info_span!(parent: None, "remote_relaunch", %tenant_id, %timeline_id)),
+        );
+    }
 
-            let upload_result: anyhow::Result<()> = match &task.op {
-                UploadOp::UploadLayer(ref layer_file_name, ref layer_metadata) => {
-                    let path = &self
-                        .conf
-                        .timeline_path(&self.tenant_id, &self.timeline_id)
-                        .join(layer_file_name.file_name());
-                    upload::upload_timeline_layer(
-                        self.conf,
-                        &self.storage_impl,
-                        path,
-                        layer_metadata,
-                    )
+    /// Check an index upload at `lsn` against the last index uploaded, see [`IndexLsnCheck`].
+    fn check_index_lsn(&self, lsn: Lsn) -> Result<(), IndexLsnRegressionError> {
+        let check = self.conf.remote_index_lsn_check;
+        if check == IndexLsnCheck::Off {
+            return Ok(());
+        }
+        let mut guard = self.upload_queue.lock().unwrap();
+        // The task notices a stopped queue on its own
+        let Ok(upload_queue) = guard.initialized_mut() else {
+            return Ok(());
+        };
+        let last_uploaded = upload_queue.last_uploaded_consistent_lsn;
+        if lsn >= last_uploaded {
+            return Ok(());
+        }
+
+        REMOTE_INDEX_LSN_REGRESSIONS.inc();
+        if check == IndexLsnCheck::Warn || upload_queue.allow_lsn_regression {
+            warn!("uploading an index at disk_consistent_lsn {lsn}, behind {last_uploaded}");
+            return Ok(());
+        }
+        let e = IndexLsnRegressionError { lsn, last_uploaded };
+        error!("rejecting index upload: {e}");
+        Err(e)
+    }
+
+    /// Whether the deletion is only recorded, see [`crate::tenant::deletion_dry_run`]. The
+    /// deferred deletions are once they are due.
+    fn dry_runs_deletion(&self, delete: &Delete) -> bool {
+        !delete.scheduled_from_timeline_delete
+            && !self.defers_deletion(delete)
+            && self.deletion_dry_run.is_enabled()
+    }
+
+    /// Whether the deletion is deferred by the `remote_deletion_grace_period` rather than
+    /// performed, see [`crate::tenant::deferred_deletion`].
+    fn defers_deletion(&self, delete: &Delete) -> bool {
+        !delete.deferred
+            && !delete.scheduled_from_timeline_delete
+            && !delete.hot_copies_only
+            && !self.conf.remote_deletion_grace_period.is_zero()
+    }
+
+    /// Upload the index, in the chunked form if it has enough layers, see [`index::chunked`].
+    async fn upload_index(&self, index_part: &IndexPart) -> anyhow::Result<()> {
+        // Index uploads don't run concurrently, the state only changes here
+        let state = self.remote_index.lock().unwrap().clone();
+        let (segments, precondition, mut last_snapshot, mut last_generation_check) = match state {
+            Some(state) => (
+                state.segments,
+                state.precondition,
+                state.last_snapshot,
+                state.last_generation_check,
+            ),
+            None => (Arc::default(), None, None, None),
+        };
+        let upload = segments.plan_upload(index_part, self.conf.remote_index_segment_layers)?;
+
+        let mut earlier_generations = Vec::new();
+        if let Some(generation) = index_part.generation {
+            if last_generation_check.map_or(true, |at| at.elapsed() >= GENERATION_CHECK_PERIOD) {
+                earlier_generations = upload::check_index_generation(
+                    self.conf,
+                    &self.storage_impl,
+                    &self.tenant_id,
+                    &self.timeline_id,
+                    generation,
+                )
+                .await?;
+                last_generation_check = Some(Instant::now());
+            }
+        }
+
+        for (segment_ref, segment) in &upload.segments {
+            upload::upload_index_segment(
+                self.conf,
+                &self.storage_impl,
+                &self.tenant_id,
+                &self.timeline_id,
+                segment_ref,
+                segment,
+            )
+            .await?;
+        }
+        let etag = upload::upload_index_file(
+            self.conf,
+            &self.storage_impl,
+            &self.tenant_id,
+            &self.timeline_id,
+            index_part.generation,
+            upload.index_bytes,
+            precondition.as_ref(),
+        )
+        .await?;
+
+        let snapshot_period = self.conf.remote_index_snapshot_period;
+        if !snapshot_period.is_zero()
+            && last_snapshot.map_or(true, |at| at.elapsed() >= snapshot_period)
+        {
+            // Otherwise retried with the next index upload
+            match self.upload_index_snapshot(index_part).await {
+                Ok(()) => last_snapshot = Some(Instant::now()),
+                Err(e) => warn!("failed to upload index snapshot: {e:#}"),
+            }
+        }
+
+        *self.remote_index.lock().unwrap() = Some(RemoteIndexState {
+            segments: Arc::new(upload.uploaded),
+            precondition: etag.map(UploadPrecondition::IfMatch),
+            last_snapshot,
+            last_generation_check,
+        });
+
+        if !earlier_generations.is_empty() {
+            // Left behind if this fails, until the next check
+            if let Err(e) = upload::delete_index_files(
+                self.conf,
+                &self.storage_impl,
+                &self.tenant_id,
+                &self.timeline_id,
+                &earlier_generations,
+            )
+            .await
+            {
+                warn!("failed to delete the index files of earlier generations: {e:#}");
+            }
+        }
+
+        if !upload.obsolete.is_empty() {
+            let timeline_path = self.conf.timeline_path(&self.tenant_id, &self.timeline_id);
+            let paths = upload
+                .obsolete
+                .iter()
+                .map(|segment_ref| {
+                    let path = timeline_path.join(segment_ref.file_name());
+                    self.conf.remote_path(&path)
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            // Left behind if this fails, like the layer files of a failed deletion
+            if let Err(e) = self.storage_impl.delete_objects(&paths).await {
+                warn!("failed to delete {} index segments: {e:#}", paths.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// Upload a snapshot of the index, see [`IndexPart::snapshot_file_name`], and delete the
+    /// snapshots older than `remote_deletion_grace_period`: their layer files may be gone.
+    async fn upload_index_snapshot(&self, index_part: &IndexPart) -> anyhow::Result<()> {
+        let timeline_path = self.conf.timeline_path(&self.tenant_id, &self.timeline_id);
+        let snapshot_name = IndexPart::snapshot_file_name(index_part.disk_consistent_lsn);
+        let storage_path = self.conf.remote_path(&timeline_path.join(&snapshot_name))?;
+
+        let bytes = serde_json::to_vec(index_part).context("serialize index snapshot")?;
+        let size = bytes.len();
+        // Immutable, a snapshot at the same LSN may have been uploaded before a restart
+        let upload = self
+            .storage_impl
+            .upload_conditional(
+                Box::new(tokio::io::BufReader::new(std::io::Cursor::new(bytes))),
+                size,
+                &storage_path,
+                Some(&UploadPrecondition::IfNoneMatch),
+            )
+            .await;
+        match upload {
+            Ok(_) | Err(UploadError::PreconditionFailed) => {}
+            Err(UploadError::Other(e)) => {
+                return Err(e.context(format!("upload index snapshot {storage_path:?}")))
+            }
+        }
+
+        let snapshots_prefix = self
+            .conf
+            .remote_path(&timeline_path.join(IndexPart::SNAPSHOT_FILE_PREFIX))?;
+        let now = SystemTime::now();
+        let grace_period = self.conf.remote_deletion_grace_period;
+        let expired = self
+            .storage_impl
+            .list_objects(Some(&snapshots_prefix))
+            .await
+            .context("list index snapshots")?
+            .into_iter()
+            .filter(|object| {
+                let is_snapshot = object
+                    .path
+                    .object_name()
+                    .and_then(IndexPart::parse_snapshot_file_name)
+                    .is_some();
+                let age = object
+                    .last_modified
+                    .and_then(|at| now.duration_since(at).ok());
+                is_snapshot && object.path != storage_path && age > Some(grace_period)
+            })
+            .map(|object| object.path)
+            .collect::<Vec<_>>();
+        if !expired.is_empty() {
+            debug!("deleting {} expired index snapshots", expired.len());
+            self.storage_impl
+                .delete_objects(&expired)
+                .await
+                .context("delete expired index snapshots")?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Perform an upload task.
+    ///
+    /// The task is in the `inprogress_tasks` list. This function will try to
+    /// execute it, retrying after the `remote_retry` policy of its kind, by
+    /// default forever. On successful completion, the task is removed it from
+    /// the `inprogress_tasks` list, and any next task(s) in the queue that were
+    /// waiting by the completion are launched.
+    ///
+    /// The task can be shut down, however. That leads to stopping the whole
+    /// queue. It can also be cancelled on its own, see [`Self::cancel_task`].
+    ///
+    async fn perform_upload_task(self: &Arc<Self>, task: Arc<UploadTask>) {
+        // Metadata of the uploaded layer file, with its checksum, to record in the index
+        let mut uploaded_metadata = None;
+        // Decided once, so that a change of the tenant config doesn't affect the retries
+        let dry_run = match &task.op {
+            UploadOp::Delete(delete) => self.dry_runs_deletion(delete),
+            _ => false,
+        };
+        // The deferred and dry-run deletions don't make requests, they tell nothing of the
+        // health of the remote storage
+        let makes_requests = match &task.op {
+            UploadOp::Delete(delete) => !dry_run && !self.defers_deletion(delete),
+            _ => true,
+        };
+
+        // Loop to retry until it completes.
+        loop {
+            if task.cancel.is_cancelled() {
+                // cancel_task() has already removed the task from the queue
+                info!("remote task {} cancelled", task.op);
+                return;
+            }
+
+            // If we're requested to shut down, close up shop and exit.
+            //
+            // Note: We only check for the shutdown requests between retries, so
+            // if a shutdown request arrives while we're busy uploading, in the
+            // upload::upload:*() call below, we will wait not exit until it has
+            // finished. We probably could cancel the upload by simply dropping
+            // the Future, but we're not 100% sure if the remote storage library
+            // is cancellation safe, so we don't dare to do that. Hopefully, the
+            // upload finishes or times out soon enough.
+            if task_mgr::shutdown_token().is_cancelled() {
+                info!("upload task cancelled by shutdown request");
+                match self.stop() {
+                    Ok(()) => {}
+                    Err(StopError::QueueUninitialized) => {
+                        unreachable!("we never launch an upload task if the queue is uninitialized, and once it is initialized, we never go back")
+                    }
+                }
+                return;
+            }
+
+            let upload_result: anyhow::Result<()> = match &task.op {
+                UploadOp::UploadLayer(ref layer_file_name, ref layer_metadata) => {
+                    // Wait for the tenant's limits, checking for shutdown requests meanwhile
+                    tokio::select! {
+                        _ = task_mgr::shutdown_watcher() => continue,
+                        _ = task.cancel.cancelled() => continue,
+                        _ = self.upload_throttle.acquire(layer_metadata.file_size()) => {}
+                    }
+                    let path = &self
+                        .conf
+                        .timeline_path(&self.tenant_id, &self.timeline_id)
+                        .join(layer_file_name.file_name());
+                    let layer_upload = upload::upload_timeline_layer(
+                        self.conf,
+                        &self.storage_impl,
+                        self.tenant_id,
+                        path,
+                        layer_metadata,
+                    )
                     .measure_remote_op(
                         self.tenant_id,
                         self.timeline_id,
                         RemoteOpFileKind::Layer,
                         RemoteOpKind::Upload,
                         Arc::clone(&self.metrics),
-                    )
-                    .await
+                    );
+                    // Unlike on shutdown, a wedged upload is dropped when cancelled: that's
+                    // what the cancellation is for. A partially written object is not
+                    // referenced from any index.
+                    tokio::select! {
+                        res = layer_upload => res.map(|metadata| uploaded_metadata = metadata),
+                        _ = task.cancel.cancelled() => continue,
+                    }
                 }
-                UploadOp::UploadMetadata(ref index_part, _lsn) => {
-                    let res = upload::upload_index_part(
-                        self.conf,
-                        &self.storage_impl,
-                        &self.tenant_id,
-                        &self.timeline_id,
-                        index_part,
-                    )
-                    .measure_remote_op(
+                UploadOp::UploadMetadata(ref index_part, lsn) => {
+                    let index_upload = self.upload_index(index_part).measure_remote_op(
                         self.tenant_id,
                         self.timeline_id,
                         RemoteOpFileKind::Index,
                         RemoteOpKind::Upload,
                         Arc::clone(&self.metrics),
-                    )
-                    .await;
+                    );
+                    let res = match self.check_index_lsn(*lsn) {
+                        // Retried like a failed upload, until the regression is allowed
+                        Err(e) => Err(e.into()),
+                        Ok(()) => tokio::select! {
+                            res = index_upload => res,
+                            _ = task.cancel.cancelled() => continue,
+                        },
+                    };
                     if res.is_ok() {
                         self.update_remote_physical_size_gauge(Some(index_part));
                     }
                     res
                 }
+                UploadOp::Delete(delete) if self.defers_deletion(delete) => {
+                    // Executed by the deferred deletion task once the grace period has passed
+                    self.deferred_deletions.push(
+                        self.timeline_id,
+                        &delete.layer_file_names,
+                        self.conf.remote_deletion_grace_period,
+                    )
+                }
+                UploadOp::Delete(delete) if dry_run => {
+                    let timeline_path = self.conf.timeline_path(&self.tenant_id, &self.timeline_id);
+                    let paths = delete
+                        .layer_file_names
+                        .iter()
+                        .map(|name| timeline_path.join(name.file_name()))
+                        .collect::<Vec<_>>();
+                    delete::remote_layer_paths(self.conf, &paths, delete.hot_copies_only)
+                        .map(|remote_paths| {
+                            self.deletion_dry_run.record(self.timeline_id, &remote_paths)
+                        })
+                }
                 UploadOp::Delete(delete) => {
-                    let path = &self
-                        .conf
-                        .timeline_path(&self.tenant_id, &self.timeline_id)
-                        .join(delete.layer_file_name.file_name());
-                    delete::delete_layer(self.conf, &self.storage_impl, path)
-                        .measure_remote_op(
-                            self.tenant_id,
-                            self.timeline_id,
-                            delete.file_kind,
-                            RemoteOpKind::Delete,
-                            Arc::clone(&self.metrics),
-                        )
-                        .await
+                    let timeline_path = self.conf.timeline_path(&self.tenant_id, &self.timeline_id);
+                    let paths = delete
+                        .layer_file_names
+                        .iter()
+                        .map(|name| timeline_path.join(name.file_name()))
+                        .collect::<Vec<_>>();
+                    let deletion = delete::delete_layers(
+                        self.conf,
+                        &self.storage_impl,
+                        &paths,
+                        delete.hot_copies_only,
+                    )
+                    .measure_remote_op(
+                        self.tenant_id,
+                        self.timeline_id,
+                        delete.file_kind,
+                        RemoteOpKind::Delete,
+                        Arc::clone(&self.metrics),
+                    );
+                    tokio::select! {
+                        res = deletion => res,
+                        _ = task.cancel.cancelled() => continue,
+                    }
                 }
                 UploadOp::Barrier(_) => {
                     // unreachable. Barrier operations are handled synchronously in
@@ -1056,36 +2655,97 @@ impl RemoteTimelineClient {
 
             match upload_result {
                 Ok(()) => {
+                    if makes_requests {
+                        self.storage_health.record_success();
+                    }
                     break;
                 }
+                Err(e)
+                    if e.downcast_ref::<StaleGenerationError>().is_some()
+                        || e.downcast_ref::<ConcurrentIndexModificationError>().is_some() =>
+                {
+                    // The tenant has been attached elsewhere since. Stop the queue, so that
+                    // none of the operations queued after this one, deletions in particular,
+                    // touch the remote storage of the new owner.
+                    error!(
+                        "stopping the upload queue, failed to perform remote task {}: {:#}",
+                        task.op, e
+                    );
+                    match self.stop() {
+                        Ok(()) => {}
+                        Err(StopError::QueueUninitialized) => {
+                            unreachable!("we never launch an upload task if the queue is uninitialized, and once it is initialized, we never go back")
+                        }
+                    }
+                    return;
+                }
                 Err(e) => {
+                    if makes_requests && e.downcast_ref::<IndexLsnRegressionError>().is_none() {
+                        self.storage_health.record_failure();
+                    }
                     let retries = task.retries.fetch_add(1, Ordering::SeqCst);
+                    *task.last_error.lock().unwrap() = Some(format!("{e:#}"));
+
+                    let policy = match task.op {
+                        UploadOp::Delete(_) => &self.conf.remote_retry.deletion,
+                        _ => &self.conf.remote_retry.upload,
+                    };
+                    if policy.gives_up(retries) {
+                        error!(
+                            "remote task {} still failed after {} attempts, giving up: {:?}",
+                            task.op,
+                            retries + 1,
+                            e
+                        );
+                        match policy.give_up {
+                            GiveUp::StopQueue => match self.stop() {
+                                Ok(()) => {}
+                                Err(StopError::QueueUninitialized) => {
+                                    unreachable!("we never launch an upload task if the queue is uninitialized, and once it is initialized, we never go back")
+                                }
+                            },
+                            GiveUp::Cancel => {
+                                if let Err(e) = self.cancel_task(task.task_id) {
+                                    // The queue has been stopped meanwhile
+                                    info!("could not cancel remote task {}: {}", task.op, e);
+                                }
+                            }
+                        }
+                        return;
+                    }
 
                     // Uploads can fail due to rate limits (IAM, S3), spurious network problems,
                     // or other external reasons. Such issues are relatively regular, so log them
                     // at info level at first, and only WARN if the operation fails repeatedly.
                     //
                     // (See similar logic for downloads in `download::download_retry`)
-                    if retries < FAILED_UPLOAD_WARN_THRESHOLD {
+                    if !policy.warns(retries) {
                         info!(
                             "failed to perform remote task {}, will retry (attempt {}): {:#}",
                             task.op, retries, e
                         );
                     } else {
-                        warn!(
+                        // A remote storage outage makes every timeline's queue fail at once,
+                        // don't flood the log with it.
+                        warn_rate_limited!(
+                            REMOTE_TASK_REPEATED_FAILURES,
                             "failed to perform remote task {}, will retry (attempt {}): {:?}",
-                            task.op, retries, e
+                            task.op,
+                            retries,
+                            e
                         );
                     }
 
-                    // sleep until it's time to retry, or we're cancelled
+                    // sleep until it's time to retry, and the remote storage isn't paused, or
+                    // we're cancelled
+                    let backoff = async {
+                        policy.backoff(retries).await;
+                        self.storage_health.wait_until_resumed().await;
+                    };
                     tokio::select! {
                         _ = task_mgr::shutdown_watcher() => { },
-                        _ = exponential_backoff(
-                            retries,
-                            DEFAULT_BASE_BACKOFF_SECONDS,
-                            DEFAULT_MAX_BACKOFF_SECONDS,
-                        ) => { },
+                        _ = task.cancel.cancelled() => { },
+                        _ = backoff => { },
                     };
                 }
             }
@@ -1101,9 +2761,80 @@ impl RemoteTimelineClient {
             debug!("remote task {} completed successfully", task.op);
         }
 
+        if let Some(mirror) = self.mirror.as_ref().filter(|_| !dry_run) {
+            match &task.op {
+                UploadOp::UploadLayer(layer_file_name, _) => {
+                    // Not uploaded if the local file was gone
+                    if let Some(metadata) = &uploaded_metadata {
+                        let name = layer_file_name.clone();
+                        mirror.push(MirrorOp::UploadLayer(name, metadata.clone()));
+                    }
+                }
+                UploadOp::UploadMetadata(index_part, _lsn) => {
+                    mirror.push(MirrorOp::UploadIndex(index_part.clone()));
+                }
+                UploadOp::Delete(delete) if delete.hot_copies_only => {
+                    // Where the layer files are, unless they have been deleted since
+                    let layers = delete
+                        .layer_file_names
+                        .iter()
+                        .filter_map(|name| {
+                            let metadata = self.get_layer_metadata(name).ok().flatten()?;
+                            Some((name.clone(), metadata))
+                        })
+                        .collect();
+                    mirror.push(MirrorOp::Tier(layers));
+                }
+                UploadOp::Delete(delete) if !self.defers_deletion(delete) => {
+                    mirror.push(MirrorOp::Delete(delete.layer_file_names.clone()));
+                }
+                UploadOp::Delete(_) | UploadOp::Barrier(_) => {}
+            }
+        }
+
+        if let Some(sink) = self.event_sink.as_ref().filter(|_| !dry_run) {
+            let kind = match &task.op {
+                UploadOp::UploadLayer(layer_file_name, _) => {
+                    uploaded_metadata
+                        .as_ref()
+                        .map(|metadata| UploadQueueEventKind::LayerUploaded {
+                            layer_file_name: layer_file_name.clone(),
+                            file_size: metadata.file_size(),
+                        })
+                }
+                UploadOp::UploadMetadata(index_part, _lsn) => {
+                    Some(UploadQueueEventKind::IndexUploaded {
+                        disk_consistent_lsn: index_part.disk_consistent_lsn,
+                    })
+                }
+                // The tiered layers are still referenced from the index
+                UploadOp::Delete(delete) if delete.hot_copies_only => None,
+                UploadOp::Delete(delete) if !self.defers_deletion(delete) => {
+                    Some(UploadQueueEventKind::LayersDeleted {
+                        layer_file_names: delete.layer_file_names.clone(),
+                    })
+                }
+                UploadOp::Delete(_) | UploadOp::Barrier(_) => None,
+            };
+            if let Some(kind) = kind {
+                sink.emit(UploadQueueEvent {
+                    tenant_id: self.tenant_id,
+                    timeline_id: self.timeline_id,
+                    generation: self.generation,
+                    kind,
+                });
+            }
+        }
+
         // The task has completed succesfully. Remove it from the in-progress list.
         {
             let mut upload_queue_guard = self.upload_queue.lock().unwrap();
+            if task.cancel.is_cancelled() {
+                // Cancelled while completing, cancel_task() has already removed it. The
+                // operations depending on it have been removed as well, so don't record it.
+                info!("remote task {} cancelled after completion", task.op);
+                return;
+            }
             let upload_queue = match upload_queue_guard.deref_mut() {
                 UploadQueue::Uninitialized => panic!("callers are responsible for ensuring this is only called on an initialized queue"),
                 UploadQueue::Stopped(stopped) => {
@@ -1111,7 +2842,10 @@ impl RemoteTimelineClient {
                     // then stop() took care of it so we just return.
                     // For deletions that come from delete_all we still want to maintain metrics, launch following tasks, etc.
                     match &task.op {
-                        UploadOp::Delete(delete) if delete.scheduled_from_timeline_delete => Some(&mut stopped.upload_queue_for_deletion),
+                        UploadOp::Delete(delete) if delete.scheduled_from_timeline_delete => {
+                            stopped.record_deleted_layers(&delete.layer_file_names);
+                            Some(&mut stopped.upload_queue_for_deletion)
+                        }
                         _ => None
                     }
                 },
@@ -1129,12 +2863,22 @@ impl RemoteTimelineClient {
             upload_queue.inprogress_tasks.remove(&task.task_id);
 
             match task.op {
-                UploadOp::UploadLayer(_, _) => {
+                UploadOp::UploadLayer(ref layer_file_name, ref layer_metadata) => {
                     upload_queue.num_inprogress_layer_uploads -= 1;
+                    upload_queue.queued_layer_bytes -= layer_metadata.file_size();
+                    if let Some(ref uploaded) = uploaded_metadata {
+                        upload_queue.record_uploaded_layer(layer_file_name, uploaded);
+                    }
+                    self.notify_layer_upload(layer_file_name, Ok(()));
                 }
                 UploadOp::UploadMetadata(_, lsn) => {
                     upload_queue.num_inprogress_metadata_uploads -= 1;
-                    upload_queue.last_uploaded_consistent_lsn = lsn; // XXX monotonicity check?
+                    if lsn < upload_queue.last_uploaded_consistent_lsn {
+                        // Checked by `check_index_lsn`, the regression has been allowed once
+                        upload_queue.allow_lsn_regression = false;
+                    }
+                    upload_queue.last_uploaded_consistent_lsn = lsn;
+                    upload_queue.last_index_upload_at = SystemTime::now();
                 }
                 UploadOp::Delete(_) => {
                     upload_queue.num_inprogress_deletions -= 1;
@@ -1145,6 +2889,7 @@ impl RemoteTimelineClient {
             // Launch any queued tasks that were unblocked by this one.
             self.launch_queued_tasks(upload_queue);
         }
+        self.queue_space_freed.notify_waiters();
         self.calls_unfinished_metric_end(&task.op);
     }
 
@@ -1234,11 +2979,16 @@ impl RemoteTimelineClient {
                         latest_files_changes_since_metadata_upload_scheduled: 0,
                         latest_metadata: initialized.latest_metadata.clone(),
                         last_uploaded_consistent_lsn: initialized.last_uploaded_consistent_lsn,
+                        last_index_upload_at: initialized.last_index_upload_at,
                         num_inprogress_layer_uploads: 0,
                         num_inprogress_metadata_uploads: 0,
                         num_inprogress_deletions: 0,
                         inprogress_tasks: HashMap::default(),
                         queued_operations: VecDeque::default(),
+                        queued_layer_bytes: 0,
+                        draining: false,
+                        allow_lsn_regression: false,
+                        relaunch_scheduled: false,
                     };
 
                     let upload_queue = std::mem::replace(
@@ -1246,6 +2996,8 @@ impl RemoteTimelineClient {
                         UploadQueue::Stopped(UploadQueueStopped {
                             upload_queue_for_deletion,
                             deleted_at: SetDeletedFlagProgress::NotRunning,
+                            layers_deleted: 0,
+                            layers_checkpointed: 0,
                         }),
                     );
                     if let UploadQueue::Initialized(qi) = upload_queue {
@@ -1263,25 +3015,186 @@ impl RemoteTimelineClient {
                     qi.inprogress_tasks.len()
                 );
 
+                // The operations left are lost with the queue: persist the remote state they
+                // would have reached, for the next load to reschedule them
+                if !qi.inprogress_tasks.is_empty() || !qi.queued_operations.is_empty() {
+                    if let Err(e) = self.persist_pending_index(&qi) {
+                        warn!("failed to persist the pending index: {e:#}");
+                    }
+                }
+
+                // The layer uploads in progress are not recorded when they finish
+                let layer_uploads = qi
+                    .inprogress_tasks
+                    .values()
+                    .map(|task| &task.op)
+                    .chain(qi.queued_operations.iter().map(|queued| &queued.op));
+                for op in layer_uploads {
+                    if let UploadOp::UploadLayer(layer_file_name, _) = op {
+                        self.notify_layer_upload(
+                            layer_file_name,
+                            Err(LayerUploadFailure::QueueStopped),
+                        );
+                    }
+                }
+
                 // We don't need to do anything here for in-progress tasks. They will finish
                 // on their own, decrement the unfinished-task counter themselves, and observe
                 // that the queue is Stopped.
                 drop(qi.inprogress_tasks);
 
                 // Tear down queued ops
-                for op in qi.queued_operations.into_iter() {
+                for QueuedOp { op, .. } in qi.queued_operations.into_iter() {
                     self.calls_unfinished_metric_end(&op);
                     // Dropping UploadOp::Barrier() here will make wait_completion() return with an Err()
                     // which is exactly what we want to happen.
                     drop(op);
                 }
+                self.metrics
+                    .set_upload_queue_state(0, None, qi.last_index_upload_at);
 
                 // We're done.
                 drop(guard);
+                // Those waiting for space in the queue see that it's stopped.
+                self.queue_space_freed.notify_waiters();
                 Ok(())
             }
         }
     }
+
+    fn pending_index_path(&self) -> PathBuf {
+        self.conf
+            .timeline_path(&self.tenant_id, &self.timeline_id)
+            .join(IndexPart::PENDING_FILE_NAME)
+    }
+
+    /// Write the index of the latest files and metadata of the queue to the timeline directory,
+    /// see [`IndexPart::PENDING_FILE_NAME`].
+    fn persist_pending_index(&self, upload_queue: &UploadQueueInitialized) -> anyhow::Result<()> {
+        let mut index_part = IndexPart::new(
+            upload_queue.latest_files.clone(),
+            upload_queue.latest_metadata.disk_consistent_lsn(),
+            upload_queue.latest_metadata.to_bytes()?,
+        );
+        index_part.generation = self.generation;
+        let bytes = serde_json::to_vec(&index_part).context("serialize pending index")?;
+
+        let path = self.pending_index_path();
+        let temp_path = path_with_suffix_extension(&path, TEMP_FILE_SUFFIX);
+        let write = || -> std::io::Result<()> {
+            let mut file = std::fs::File::create(&temp_path)?;
+            file.write_all(&bytes)?;
+            file.sync_all()?;
+            std::fs::rename(&temp_path, &path)?;
+            crashsafe::fsync(path.parent().expect("pending index has a parent dir"))
+        };
+        write().with_context(|| format!("write pending index {path:?}"))?;
+        info!(
+            layers = index_part.layer_metadata.len(),
+            "persisted the pending index of the stopped upload queue"
+        );
+        Ok(())
+    }
+
+    /// Read and remove the pending index that the upload queue of the previous load of the
+    /// timeline persisted when it was stopped, if any. Call this before initializing the queue.
+    pub fn take_pending_index(&self) -> anyhow::Result<Option<IndexPart>> {
+        let path = self.pending_index_path();
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("read pending index {path:?}")),
+        };
+        // Consumed once: a later load without a clean stop must not use a stale one
+        std::fs::remove_file(&path).with_context(|| format!("remove pending index {path:?}"))?;
+        match IndexPart::from_json_bytes(&bytes) {
+            Ok(index_part) => Ok(Some(index_part)),
+            Err(e) => {
+                warn!("ignoring unparseable pending index {path:?}: {e:#}");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Let the next index upload go back in `disk_consistent_lsn`, which
+    /// [`IndexLsnCheck::Enforce`] rejects otherwise. For recoveries that roll the timeline back
+    /// on purpose, once the safekeepers still have the WAL after the LSN it goes back to.
+    pub fn allow_index_lsn_regression(&self) -> anyhow::Result<()> {
+        let mut guard = self.upload_queue.lock().unwrap();
+        let upload_queue = guard.initialized_mut()?;
+        upload_queue.allow_lsn_regression = true;
+        info!(
+            "allowed the next index upload to go back from disk_consistent_lsn {}",
+            upload_queue.last_uploaded_consistent_lsn
+        );
+        Ok(())
+    }
+
+    /// Cancel an in-progress upload or delete task, without stopping the whole queue like
+    /// [`Self::stop`] does. For example, a huge layer upload that keeps failing holds up the
+    /// index uploads after it.
+    ///
+    /// The queued operations that depend on the task are removed as well, transitively: the
+    /// index uploads referencing the layer of a cancelled layer upload, and the deletions
+    /// waiting for a removed index upload. The later index uploads don't reference the layer
+    /// of a cancelled layer upload. The layer stays on the local disk, and is uploaded again
+    /// the next time the timeline is loaded, but until then, the remote storage lacks its data.
+    pub fn cancel_task(self: &Arc<Self>, task_id: u64) -> Result<(), CancelTaskError> {
+        {
+            let mut guard = self.upload_queue.lock().unwrap();
+            let upload_queue = guard
+                .initialized_mut()
+                .map_err(|_| CancelTaskError::QueueNotInitialized)?;
+            let task = upload_queue
+                .inprogress_tasks
+                .get(&task_id)
+                .cloned()
+                .ok_or(CancelTaskError::NoSuchTask(task_id))?;
+
+            // The task checks for the cancellation under the lock, and leaves the queue alone.
+            task.cancel.cancel();
+            let removed_ops = upload_queue.remove_cancelled_task(&task);
+
+            if let UploadOp::UploadLayer(layer_file_name, _) = &task.op {
+                warn!(
+                    "cancelled the upload of layer {}, it is missing from the remote storage until the timeline is reloaded",
+                    layer_file_name.file_name()
+                );
+                self.notify_layer_upload(layer_file_name, Err(LayerUploadFailure::Cancelled));
+            }
+            info!(
+                "cancelled remote task {} and {} queued operations depending on it",
+                task.op,
+                removed_ops.len()
+            );
+            self.calls_unfinished_metric_end(&task.op);
+            for op in removed_ops {
+                debug!("removed op: {}", op);
+                self.calls_unfinished_metric_end(&op);
+            }
+
+            // Launch the tasks that were waiting for it.
+            self.launch_queued_tasks(upload_queue);
+        }
+        self.queue_space_freed.notify_waiters();
+        Ok(())
+    }
+}
+
+/// CRC32C of the contents of a layer file, as recorded in the index.
+async fn layer_file_crc32c(path: &Path) -> std::io::Result<u32> {
+    use tokio::io::AsyncReadExt;
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut crc32c = 0;
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(crc32c);
+        }
+        crc32c = crc32c::crc32c_append(crc32c, &buf[..read]);
+    }
 }
 
 #[cfg(test)]
@@ -1291,11 +3204,13 @@ mod tests {
         context::RequestContext,
         tenant::{
             harness::{TenantHarness, TIMELINE_ID},
+            upload_queue::UploadQueueLimitsConfig,
             Tenant,
         },
         DEFAULT_PG_VERSION,
     };
-    use remote_storage::{RemoteStorageConfig, RemoteStorageKind};
+    use pageserver_api::models::{UploadOpInfo, UploadOpKind, UploadQueueState, UploadTaskInfo};
+    use remote_storage::{LocalFs, RemoteStorageConfig, RemoteStorageKind};
     use std::{
         collections::HashSet,
         path::{Path, PathBuf},
@@ -1409,6 +3324,19 @@ mod tests {
                     &harness.tenant_id,
                     &TIMELINE_ID,
                 )),
+                upload_throttle: Arc::new(UploadThrottle::new(Default::default())),
+                upload_concurrency_limit: Arc::new(UploadConcurrencyLimit::new(None)),
+                deferred_deletions: Arc::new(DeferredDeletions::new(
+                    harness.conf.tenant_deferred_deletions_path(&harness.tenant_id),
+                )),
+                deletion_dry_run: Arc::new(DeletionDryRun::new(false)),
+                queue_space_freed: tokio::sync::Notify::new(),
+                upload_events: tokio::sync::broadcast::channel(UPLOAD_EVENTS_CAPACITY).0,
+                generation: None,
+                mirror: None,
+                event_sink: None,
+                storage_health: Arc::new(RemoteStorageHealth::new("test".to_string(), None)),
+                remote_index: Mutex::new(None),
             });
 
             Ok(Self {
@@ -1520,7 +3448,8 @@ mod tests {
         }
 
         // Download back the index.json, and check that the list of files is correct
-        let index_part = match runtime.block_on(client.download_index_file())? {
+        let cancel = CancellationToken::new();
+        let index_part = match runtime.block_on(client.download_index_file(&cancel))? {
             MaybeDeletedIndexPart::IndexPart(index_part) => index_part,
             MaybeDeletedIndexPart::Deleted(_) => panic!("unexpectedly got deleted index part"),
         };
@@ -1535,6 +3464,11 @@ mod tests {
         let downloaded_metadata = index_part.parse_metadata()?;
         assert_eq!(downloaded_metadata, metadata);
 
+        // The index has the checksums of the layers
+        let layer_crc32c = |name: &LayerFileName| index_part.layer_metadata[name].crc32c;
+        assert_eq!(layer_crc32c(&layer_file_name_1), Some(crc32c::crc32c(&content_1)));
+        assert_eq!(layer_crc32c(&layer_file_name_2), Some(crc32c::crc32c(&content_2)));
+
         // Schedule upload and then a deletion. Check that the deletion is queued
         let content_baz = dummy_contents("baz");
         std::fs::write(timeline_path.join("baz"), &content_baz)?;
@@ -1663,4 +3597,1646 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn upload_queue_limits() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            client,
+            ..
+        } = TestSetup::new("upload_queue_limits")?;
+
+        let metadata = dummy_metadata(Lsn(0x10));
+        client.init_upload_queue_for_empty_remote(&metadata)?;
+
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let layer_file_name_1: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let layer_file_name_2: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D9-00000000016B5A52".parse().unwrap();
+        let content_1 = dummy_contents("foo");
+        let content_2 = dummy_contents("bar");
+        std::fs::write(
+            timeline_path.join(layer_file_name_1.file_name()),
+            &content_1,
+        )?;
+        std::fs::write(
+            timeline_path.join(layer_file_name_2.file_name()),
+            &content_2,
+        )?;
+        let queued_bytes = (content_1.len() + content_2.len()) as u64;
+
+        let limits = |max_queued_ops: usize, max_queued_bytes: u64| UploadQueueLimitsConfig {
+            max_queued_ops: std::num::NonZeroUsize::new(max_queued_ops),
+            max_queued_bytes: std::num::NonZeroU64::new(max_queued_bytes),
+        };
+        let exceeds_limits = |limits: UploadQueueLimitsConfig| {
+            let mut guard = client.upload_queue.lock().unwrap();
+            let upload_queue = guard.initialized_mut().unwrap();
+            upload_queue.exceeds_limits(&limits)
+        };
+
+        client.schedule_layer_file_upload(
+            &layer_file_name_1,
+            &LayerFileMetadata::new(content_1.len() as u64),
+        )?;
+        client.schedule_layer_file_upload(
+            &layer_file_name_2,
+            &LayerFileMetadata::new(content_2.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+
+        // Two layer uploads in progress and an index upload queued
+        assert!(!exceeds_limits(UploadQueueLimitsConfig::default()));
+        assert!(!exceeds_limits(limits(4, 0)));
+        assert!(exceeds_limits(limits(3, 0)));
+        assert!(!exceeds_limits(limits(0, queued_bytes + 1)));
+        assert!(exceeds_limits(limits(0, queued_bytes)));
+
+        runtime.block_on(client.wait_completion())?;
+        assert!(!exceeds_limits(limits(1, 1)));
+        {
+            let mut guard = client.upload_queue.lock().unwrap();
+            assert_eq!(guard.initialized_mut().unwrap().queued_layer_bytes, 0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn upload_queue_gauges() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            client,
+            ..
+        } = TestSetup::new("upload_queue_gauges")?;
+
+        let unix_now = || {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64
+        };
+        let initialized_at = unix_now();
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        let (depth, oldest_op, last_index_upload) = client.metrics.get_upload_queue_state();
+        assert_eq!((depth, oldest_op), (0, 0));
+        assert!(last_index_upload >= initialized_at);
+
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let layer_file_name: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let content = dummy_contents("foo");
+        std::fs::write(timeline_path.join(layer_file_name.file_name()), &content)?;
+
+        let scheduled_at = unix_now();
+        client.schedule_layer_file_upload(
+            &layer_file_name,
+            &LayerFileMetadata::new(content.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+
+        // The layer upload is in progress, the index upload waits for it
+        let (depth, oldest_op, _) = client.metrics.get_upload_queue_state();
+        assert_eq!(depth, 2);
+        assert!(oldest_op >= scheduled_at && oldest_op <= unix_now());
+
+        runtime.block_on(client.wait_completion())?;
+        let (depth, oldest_op, last_index_upload) = client.metrics.get_upload_queue_state();
+        assert_eq!((depth, oldest_op), (0, 0));
+        assert!(last_index_upload >= scheduled_at);
+
+        Ok(())
+    }
+
+    #[test]
+    fn index_uploads_coalesce_and_layer_uploads_jump_ahead() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            client,
+            ..
+        } = TestSetup::new("index_uploads_coalesce")?;
+
+        let metadata = dummy_metadata(Lsn(0x10));
+        client.init_upload_queue_for_empty_remote(&metadata)?;
+
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let layer_file_name_1: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let layer_file_name_2: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D9-00000000016B5A52".parse().unwrap();
+        let content_1 = dummy_contents("foo");
+        let content_2 = dummy_contents("bar");
+        std::fs::write(
+            timeline_path.join(layer_file_name_1.file_name()),
+            &content_1,
+        )?;
+        std::fs::write(
+            timeline_path.join(layer_file_name_2.file_name()),
+            &content_2,
+        )?;
+
+        client.schedule_layer_file_upload(
+            &layer_file_name_1,
+            &LayerFileMetadata::new(content_1.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x30)))?;
+        {
+            let mut guard = client.upload_queue.lock().unwrap();
+            let upload_queue = guard.initialized_mut().unwrap();
+            // The first index upload is superseded by the second one
+            assert_eq!(upload_queue.queued_operations.len(), 1);
+            assert_eq!(upload_queue.num_inprogress_layer_uploads, 1);
+        }
+
+        // The layer upload doesn't wait for the index upload in front of it
+        client.schedule_layer_file_upload(
+            &layer_file_name_2,
+            &LayerFileMetadata::new(content_2.len() as u64),
+        )?;
+        {
+            let mut guard = client.upload_queue.lock().unwrap();
+            let upload_queue = guard.initialized_mut().unwrap();
+            assert_eq!(upload_queue.queued_operations.len(), 1);
+            assert!(matches!(
+                upload_queue.queued_operations[0].op,
+                UploadOp::UploadMetadata(_, lsn) if lsn == Lsn(0x30)
+            ));
+            assert_eq!(upload_queue.num_inprogress_layer_uploads, 2);
+        }
+
+        // The index uploads after it wait for the layer upload that jumped ahead
+        let last_metadata = dummy_metadata(Lsn(0x40));
+        client.schedule_index_upload_for_metadata_update(&last_metadata)?;
+        runtime.block_on(client.wait_completion())?;
+        assert_eq!(client.last_uploaded_consistent_lsn(), Some(Lsn(0x40)));
+
+        let cancel = CancellationToken::new();
+        let index_part = match runtime.block_on(client.download_index_file(&cancel))? {
+            MaybeDeletedIndexPart::IndexPart(index_part) => index_part,
+            MaybeDeletedIndexPart::Deleted(_) => panic!("unexpectedly got deleted index part"),
+        };
+        assert_file_list(
+            &index_part.timeline_layers,
+            &[
+                &layer_file_name_1.file_name(),
+                &layer_file_name_2.file_name(),
+            ],
+        );
+        assert_eq!(index_part.parse_metadata()?, last_metadata);
+
+        Ok(())
+    }
+
+    #[test]
+    fn upload_concurrency_limit() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            remote_fs_dir,
+            client,
+            ..
+        } = TestSetup::new("upload_concurrency_limit")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let remote_timeline_dir =
+            remote_fs_dir.join(timeline_path.strip_prefix(&harness.conf.workdir)?);
+
+        let layer_file_names: Vec<LayerFileName> = [
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51",
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D9-00000000016B5A52",
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59DA-00000000016B5A53",
+        ]
+        .iter()
+        .map(|name| name.parse().unwrap())
+        .collect();
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        client
+            .upload_concurrency_limit
+            .set(std::num::NonZeroUsize::new(2));
+        for layer_file_name in &layer_file_names {
+            let content = dummy_contents(&layer_file_name.file_name());
+            std::fs::write(timeline_path.join(layer_file_name.file_name()), &content)?;
+            client.schedule_layer_file_upload(
+                layer_file_name,
+                &LayerFileMetadata::new(content.len() as u64),
+            )?;
+        }
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        {
+            let mut guard = client.upload_queue.lock().unwrap();
+            let upload_queue = guard.initialized_mut().unwrap();
+            // The third layer upload waits for one of the first two
+            assert_eq!(upload_queue.num_inprogress_layer_uploads, 2);
+            assert_eq!(upload_queue.queued_operations.len(), 2);
+            assert!(matches!(
+                upload_queue.queued_operations[0].op,
+                UploadOp::UploadLayer(ref name, _) if *name == layer_file_names[2]
+            ));
+        }
+
+        runtime.block_on(client.wait_completion())?;
+        assert_remote_files(
+            &[
+                &layer_file_names[0].file_name(),
+                &layer_file_names[1].file_name(),
+                &layer_file_names[2].file_name(),
+                "index_part.json",
+            ],
+            &remote_timeline_dir,
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn scrub_deletes_leaked_layers() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            remote_fs_dir,
+            client,
+            ..
+        } = TestSetup::new("scrub_deletes_leaked_layers")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let remote_timeline_dir =
+            remote_fs_dir.join(timeline_path.strip_prefix(&harness.conf.workdir)?);
+
+        let layer_file_name_1: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let layer_file_name_2: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D9-00000000016B5A52".parse().unwrap();
+        let content_1 = dummy_contents("foo");
+        let content_2 = dummy_contents("bar");
+        std::fs::write(
+            timeline_path.join(layer_file_name_1.file_name()),
+            &content_1,
+        )?;
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        client.schedule_layer_file_upload(
+            &layer_file_name_1,
+            &LayerFileMetadata::new(content_1.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        runtime.block_on(client.wait_completion())?;
+
+        // A layer uploaded without the index upload referencing it, and an unknown object
+        std::fs::write(
+            remote_timeline_dir.join(layer_file_name_2.file_name()),
+            &content_2,
+        )?;
+        std::fs::write(remote_timeline_dir.join("unknown"), "unknown")?;
+
+        let name_1 = layer_file_name_1.file_name();
+        let name_2 = layer_file_name_2.file_name();
+        let report = runtime.block_on(client.scrub_leaked_objects(Duration::from_secs(3600)))?;
+        assert_eq!(
+            report,
+            RemoteScrubReport {
+                deleted_objects: 0,
+                deleted_bytes: 0,
+                skipped_recent_objects: 1,
+            }
+        );
+        assert_remote_files(
+            &["index_part.json", &name_1, &name_2, "unknown"],
+            &remote_timeline_dir,
+        );
+
+        let report = runtime.block_on(client.scrub_leaked_objects(Duration::ZERO))?;
+        assert_eq!(
+            report,
+            RemoteScrubReport {
+                deleted_objects: 1,
+                deleted_bytes: content_2.len() as u64,
+                skipped_recent_objects: 0,
+            }
+        );
+        assert_remote_files(
+            &["index_part.json", &name_1, "unknown"],
+            &remote_timeline_dir,
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn tier_layers_moves_old_layers() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            remote_fs_dir,
+            client,
+            ..
+        } = TestSetup::new("tier_layers_moves_old_layers")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let relative_timeline_path = timeline_path.strip_prefix(&harness.conf.workdir)?;
+        let remote_timeline_dir = remote_fs_dir.join(relative_timeline_path);
+        let cold_timeline_dir = remote_fs_dir
+            .join(tiering::COLD_TIER_PREFIX)
+            .join(relative_timeline_path);
+
+        let layer_file_name: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let content = dummy_contents("foo");
+        std::fs::write(timeline_path.join(layer_file_name.file_name()), &content)?;
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        client.schedule_layer_file_upload(
+            &layer_file_name,
+            &LayerFileMetadata::new(content.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        runtime.block_on(client.wait_completion())?;
+
+        // Too recent
+        let cancel = CancellationToken::new();
+        let report = runtime.block_on(client.tier_layers(Duration::from_secs(3600), &cancel))?;
+        assert_eq!(report.tiered_layers, 0);
+        let name = layer_file_name.file_name();
+        assert_remote_files(&["index_part.json", &name], &remote_timeline_dir);
+        assert!(!cold_timeline_dir.exists());
+
+        let report = runtime.block_on(client.tier_layers(Duration::ZERO, &cancel))?;
+        assert_eq!(report.tiered_layers, 1);
+        assert_eq!(report.tiered_bytes, content.len() as u64);
+        assert_remote_files(&["index_part.json"], &remote_timeline_dir);
+        assert_eq!(std::fs::read(cold_timeline_dir.join(&name))?, content);
+        let metadata = client.get_layer_metadata(&layer_file_name)?.unwrap();
+        assert_eq!(metadata.tier(), Some(LayerTier::Cold));
+
+        // The index references the cold copy
+        let index_part = match runtime.block_on(client.download_index_file(&cancel))? {
+            MaybeDeletedIndexPart::IndexPart(index_part) => index_part,
+            MaybeDeletedIndexPart::Deleted(_) => panic!("unexpectedly got deleted index part"),
+        };
+        assert_eq!(
+            index_part.layer_metadata[&layer_file_name].tier,
+            Some(LayerTier::Cold)
+        );
+        let report = runtime.block_on(client.validate_remote_consistency(&cancel))?;
+        assert!(report.is_consistent(), "{report:?}");
+
+        // A copy left at the default location by an interrupted run is deleted by the next one
+        std::fs::write(remote_timeline_dir.join(&name), &content)?;
+        let report = runtime.block_on(client.tier_layers(Duration::ZERO, &cancel))?;
+        assert_eq!(report.tiered_layers, 0);
+        assert_eq!(report.deleted_leftovers, 1);
+        assert_remote_files(&["index_part.json"], &remote_timeline_dir);
+
+        // The deletion of the layer deletes the cold copy
+        client.schedule_layer_file_deletion(&[layer_file_name])?;
+        runtime.block_on(client.wait_completion())?;
+        assert!(!cold_timeline_dir.join(&name).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn upload_queue_info() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            client,
+            ..
+        } = TestSetup::new("upload_queue_info")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        assert_eq!(
+            client.upload_queue_info().state,
+            UploadQueueState::Uninitialized
+        );
+
+        let layer_file_name: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let content = dummy_contents("foo");
+        std::fs::write(timeline_path.join(layer_file_name.file_name()), &content)?;
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        client.schedule_layer_file_upload(
+            &layer_file_name,
+            &LayerFileMetadata::new(content.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+
+        // The layer upload is launched, and the index upload waits for it
+        let info = client.upload_queue_info();
+        assert_eq!(info.state, UploadQueueState::Initialized);
+        assert_eq!(info.last_uploaded_consistent_lsn, Some(Lsn(0)));
+        assert_eq!(info.queued_layer_bytes, content.len() as u64);
+        assert_eq!(
+            info.inprogress_tasks,
+            vec![UploadTaskInfo {
+                task_id: 1,
+                op: UploadOpInfo {
+                    kind: UploadOpKind::UploadLayer,
+                    layer_file_name: Some(layer_file_name.file_name()),
+                    file_size: Some(content.len() as u64),
+                    disk_consistent_lsn: None,
+                    layer_count: None,
+                },
+                retries: 0,
+                last_error: None,
+            }]
+        );
+        assert_eq!(
+            info.queued_operations,
+            vec![UploadOpInfo {
+                kind: UploadOpKind::UploadIndex,
+                layer_file_name: None,
+                file_size: None,
+                disk_consistent_lsn: Some(Lsn(0x20)),
+                layer_count: None,
+            }]
+        );
+
+        runtime.block_on(client.wait_completion())?;
+        let info = client.upload_queue_info();
+        assert_eq!(info.last_uploaded_consistent_lsn, Some(Lsn(0x20)));
+        assert_eq!(info.queued_layer_bytes, 0);
+        assert!(info.inprogress_tasks.is_empty());
+        assert!(info.queued_operations.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn remote_consistency_check() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            remote_fs_dir,
+            client,
+            ..
+        } = TestSetup::new("remote_consistency_check")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let remote_timeline_dir =
+            remote_fs_dir.join(timeline_path.strip_prefix(&harness.conf.workdir)?);
+
+        let layer_file_name_1: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let layer_file_name_2: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D9-00000000016B5A52".parse().unwrap();
+        let content_1 = dummy_contents("foo");
+        let content_2 = dummy_contents("bar");
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        for (layer_file_name, content) in [
+            (&layer_file_name_1, &content_1),
+            (&layer_file_name_2, &content_2),
+        ] {
+            std::fs::write(timeline_path.join(layer_file_name.file_name()), content)?;
+            client.schedule_layer_file_upload(
+                layer_file_name,
+                &LayerFileMetadata::new(content.len() as u64),
+            )?;
+        }
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        runtime.block_on(client.wait_completion())?;
+
+        let cancel = CancellationToken::new();
+        let report = runtime.block_on(client.validate_remote_consistency(&cancel))?;
+        assert_eq!(
+            report,
+            RemoteConsistencyReport {
+                index_generation: None,
+                checked_layers: 2,
+                missing_layers: Vec::new(),
+                mismatched_layers: Vec::new(),
+            }
+        );
+
+        // Lose a layer, and overwrite another one with other contents
+        let name_1 = layer_file_name_1.file_name();
+        let name_2 = layer_file_name_2.file_name();
+        std::fs::remove_file(remote_timeline_dir.join(&name_1))?;
+        std::fs::write(remote_timeline_dir.join(&name_2), "other contents")?;
+
+        let report = runtime.block_on(client.validate_remote_consistency(&cancel))?;
+        assert_eq!(
+            report,
+            RemoteConsistencyReport {
+                index_generation: None,
+                checked_layers: 2,
+                missing_layers: vec![name_1],
+                mismatched_layers: vec![RemoteLayerMismatch {
+                    layer_file_name: name_2,
+                    expected_size: content_2.len() as u64,
+                    remote_size: "other contents".len() as u64,
+                    expected_crc32c: Some(crc32c::crc32c(&content_2)),
+                    remote_crc32c: None,
+                }],
+            }
+        );
+        assert!(!report.is_consistent());
+
+        Ok(())
+    }
+
+    /// A client of the same timeline, for an attachment with the given generation.
+    fn client_with_generation(
+        client: &RemoteTimelineClient,
+        generation: u32,
+    ) -> Arc<RemoteTimelineClient> {
+        Arc::new(RemoteTimelineClient {
+            conf: client.conf,
+            runtime: client.runtime,
+            tenant_id: client.tenant_id,
+            timeline_id: client.timeline_id,
+            storage_impl: client.storage_impl.clone(),
+            upload_queue: Mutex::new(UploadQueue::Uninitialized),
+            metrics: Arc::clone(&client.metrics),
+            upload_throttle: Arc::clone(&client.upload_throttle),
+            upload_concurrency_limit: Arc::clone(&client.upload_concurrency_limit),
+            deferred_deletions: Arc::clone(&client.deferred_deletions),
+            deletion_dry_run: Arc::clone(&client.deletion_dry_run),
+            queue_space_freed: tokio::sync::Notify::new(),
+            upload_events: tokio::sync::broadcast::channel(UPLOAD_EVENTS_CAPACITY).0,
+            generation: Some(Generation::new(generation)),
+            mirror: None,
+            event_sink: None,
+            storage_health: Arc::clone(&client.storage_health),
+            remote_index: Mutex::new(None),
+        })
+    }
+
+    /// Collects the upload queue events.
+    #[derive(Default)]
+    struct EventCollector(std::sync::Mutex<Vec<UploadQueueEvent>>);
+
+    impl UploadEventSink for EventCollector {
+        fn emit(&self, event: UploadQueueEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    impl EventCollector {
+        fn take(&self) -> Vec<UploadQueueEventKind> {
+            let events = std::mem::take(&mut *self.0.lock().unwrap());
+            events.into_iter().map(|event| event.kind).collect()
+        }
+    }
+
+    #[test]
+    fn upload_queue_events() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            client,
+            ..
+        } = TestSetup::new("upload_queue_events")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let collector = Arc::new(EventCollector::default());
+        let client = Arc::new(RemoteTimelineClient {
+            conf: client.conf,
+            runtime: client.runtime,
+            tenant_id: client.tenant_id,
+            timeline_id: client.timeline_id,
+            storage_impl: client.storage_impl.clone(),
+            upload_queue: Mutex::new(UploadQueue::Uninitialized),
+            metrics: Arc::clone(&client.metrics),
+            upload_throttle: Arc::clone(&client.upload_throttle),
+            upload_concurrency_limit: Arc::clone(&client.upload_concurrency_limit),
+            deferred_deletions: Arc::clone(&client.deferred_deletions),
+            deletion_dry_run: Arc::clone(&client.deletion_dry_run),
+            queue_space_freed: tokio::sync::Notify::new(),
+            upload_events: tokio::sync::broadcast::channel(UPLOAD_EVENTS_CAPACITY).0,
+            generation: Some(Generation::new(1)),
+            mirror: None,
+            event_sink: Some(collector.clone()),
+            storage_health: Arc::clone(&client.storage_health),
+            remote_index: Mutex::new(None),
+        });
+
+        let layer_file_name: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let content = dummy_contents("foo");
+        std::fs::write(timeline_path.join(layer_file_name.file_name()), &content)?;
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        client.schedule_layer_file_upload(
+            &layer_file_name,
+            &LayerFileMetadata::new(content.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        runtime.block_on(client.wait_completion())?;
+        assert_eq!(
+            collector.take(),
+            vec![
+                UploadQueueEventKind::LayerUploaded {
+                    layer_file_name: layer_file_name.clone(),
+                    file_size: content.len() as u64,
+                },
+                UploadQueueEventKind::IndexUploaded {
+                    disk_consistent_lsn: Lsn(0x20),
+                },
+            ]
+        );
+
+        // The deletion follows the upload of the index that no longer references the layer
+        client.schedule_layer_file_deletion(&[layer_file_name.clone()])?;
+        runtime.block_on(client.wait_completion())?;
+        assert_eq!(
+            collector.take(),
+            vec![
+                UploadQueueEventKind::IndexUploaded {
+                    disk_consistent_lsn: Lsn(0x20),
+                },
+                UploadQueueEventKind::LayersDeleted {
+                    layer_file_names: vec![layer_file_name],
+                },
+            ]
+        );
+
+        // The events carry the timeline and the generation
+        let event = UploadQueueEvent {
+            tenant_id: harness.tenant_id,
+            timeline_id: TIMELINE_ID,
+            generation: Some(Generation::new(1)),
+            kind: UploadQueueEventKind::IndexUploaded {
+                disk_consistent_lsn: Lsn(0x30),
+            },
+        };
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x30)))?;
+        runtime.block_on(client.wait_completion())?;
+        assert_eq!(*collector.0.lock().unwrap(), vec![event]);
+
+        Ok(())
+    }
+
+    /// A client of the same timeline, mirroring to the given mirror.
+    fn client_with_mirror(
+        client: &RemoteTimelineClient,
+        mirror: &Arc<TimelineMirror>,
+    ) -> Arc<RemoteTimelineClient> {
+        Arc::new(RemoteTimelineClient {
+            conf: client.conf,
+            runtime: client.runtime,
+            tenant_id: client.tenant_id,
+            timeline_id: client.timeline_id,
+            storage_impl: client.storage_impl.clone(),
+            upload_queue: Mutex::new(UploadQueue::Uninitialized),
+            metrics: Arc::clone(&client.metrics),
+            upload_throttle: Arc::clone(&client.upload_throttle),
+            upload_concurrency_limit: Arc::clone(&client.upload_concurrency_limit),
+            deferred_deletions: Arc::clone(&client.deferred_deletions),
+            deletion_dry_run: Arc::clone(&client.deletion_dry_run),
+            queue_space_freed: tokio::sync::Notify::new(),
+            upload_events: tokio::sync::broadcast::channel(UPLOAD_EVENTS_CAPACITY).0,
+            generation: None,
+            mirror: Some(Arc::clone(mirror)),
+            event_sink: None,
+            storage_health: Arc::clone(&client.storage_health),
+            remote_index: Mutex::new(None),
+        })
+    }
+
+    #[test]
+    fn mirror_uploads() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            client,
+            ..
+        } = TestSetup::new("mirror_uploads")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let mirror_fs_dir = harness.conf.workdir.join("mirror_fs");
+        std::fs::create_dir_all(&mirror_fs_dir)?;
+        let mirror_fs_dir = std::fs::canonicalize(mirror_fs_dir)?;
+        let mirror_timeline_dir =
+            mirror_fs_dir.join(timeline_path.strip_prefix(&harness.conf.workdir)?);
+        let new_mirror = || -> anyhow::Result<Arc<TimelineMirror>> {
+            Ok(Arc::new(TimelineMirror::new(
+                harness.conf,
+                runtime,
+                harness.tenant_id,
+                TIMELINE_ID,
+                client.storage_impl.clone(),
+                GenericRemoteStorage::LocalFs(LocalFs::new(mirror_fs_dir.clone())?),
+            )))
+        };
+        let wait_mirror_idle = |mirror: &TimelineMirror| {
+            runtime.block_on(async {
+                while !mirror.is_idle() {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+        };
+
+        let layer_file_name_1: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let layer_file_name_2: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D9-00000000016B5A52".parse().unwrap();
+        let content_1 = dummy_contents("foo");
+        let content_2 = dummy_contents("bar");
+        std::fs::write(
+            timeline_path.join(layer_file_name_1.file_name()),
+            &content_1,
+        )?;
+        std::fs::write(
+            timeline_path.join(layer_file_name_2.file_name()),
+            &content_2,
+        )?;
+
+        // The layer and index uploads are mirrored
+        let mirror = new_mirror()?;
+        let mirrored_client = client_with_mirror(&client, &mirror);
+        mirrored_client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        mirrored_client.schedule_layer_file_upload(
+            &layer_file_name_1,
+            &LayerFileMetadata::new(content_1.len() as u64),
+        )?;
+        mirrored_client.schedule_layer_file_upload(
+            &layer_file_name_2,
+            &LayerFileMetadata::new(content_2.len() as u64),
+        )?;
+        mirrored_client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        runtime.block_on(mirrored_client.wait_completion())?;
+        wait_mirror_idle(&mirror);
+        assert_remote_files(
+            &[
+                &layer_file_name_1.file_name(),
+                &layer_file_name_2.file_name(),
+                "index_part.json",
+            ],
+            &mirror_timeline_dir,
+        );
+        assert_eq!(
+            std::fs::read(mirror_timeline_dir.join(layer_file_name_2.file_name()))?,
+            content_2
+        );
+
+        // So are the deletions
+        mirrored_client.schedule_layer_file_deletion(&[layer_file_name_1.clone()])?;
+        runtime.block_on(mirrored_client.wait_completion())?;
+        wait_mirror_idle(&mirror);
+        assert_remote_files(
+            &[&layer_file_name_2.file_name(), "index_part.json"],
+            &mirror_timeline_dir,
+        );
+
+        // After a restart, the next index upload copies the layer files missing from the mirror
+        std::fs::remove_file(mirror_timeline_dir.join(layer_file_name_2.file_name()))?;
+        let mirror = new_mirror()?;
+        let mirrored_client = client_with_mirror(&client, &mirror);
+        let cancel = CancellationToken::new();
+        let index_part = match runtime.block_on(mirrored_client.download_index_file(&cancel))? {
+            MaybeDeletedIndexPart::IndexPart(index_part) => index_part,
+            MaybeDeletedIndexPart::Deleted(_) => panic!("unexpectedly got deleted index part"),
+        };
+        mirrored_client.init_upload_queue(&index_part)?;
+        mirrored_client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x30)))?;
+        runtime.block_on(mirrored_client.wait_completion())?;
+        wait_mirror_idle(&mirror);
+        assert_remote_files(
+            &[&layer_file_name_2.file_name(), "index_part.json"],
+            &mirror_timeline_dir,
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn copy_timeline_remote() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            client,
+            ..
+        } = TestSetup::new("copy_timeline_remote")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let destination_fs_dir = harness.conf.workdir.join("destination_fs");
+        std::fs::create_dir_all(&destination_fs_dir)?;
+        let destination_fs_dir = std::fs::canonicalize(destination_fs_dir)?;
+        let destination_timeline_dir =
+            destination_fs_dir.join(timeline_path.strip_prefix(&harness.conf.workdir)?);
+        let destination = GenericRemoteStorage::LocalFs(LocalFs::new(destination_fs_dir)?);
+        let cancel = CancellationToken::new();
+
+        let layer_file_name_1: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let layer_file_name_2: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D9-00000000016B5A52".parse().unwrap();
+        let content_1 = dummy_contents("foo");
+        let content_2 = dummy_contents("bar");
+        std::fs::write(
+            timeline_path.join(layer_file_name_1.file_name()),
+            &content_1,
+        )?;
+        std::fs::write(
+            timeline_path.join(layer_file_name_2.file_name()),
+            &content_2,
+        )?;
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        client.schedule_layer_file_upload(
+            &layer_file_name_1,
+            &LayerFileMetadata::new(content_1.len() as u64),
+        )?;
+        client.schedule_layer_file_upload(
+            &layer_file_name_2,
+            &LayerFileMetadata::new(content_2.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        runtime.block_on(client.wait_completion())?;
+
+        let report = runtime.block_on(client.copy_timeline_remote(&destination, &cancel))?;
+        assert_eq!(
+            report,
+            RemoteCopyReport {
+                index_generation: None,
+                copied_layers: 2,
+                copied_bytes: (content_1.len() + content_2.len()) as u64,
+                skipped_layers: 0,
+            }
+        );
+        assert_remote_files(
+            &[
+                &layer_file_name_1.file_name(),
+                &layer_file_name_2.file_name(),
+                "index_part.json",
+            ],
+            &destination_timeline_dir,
+        );
+        assert_eq!(
+            std::fs::read(destination_timeline_dir.join(layer_file_name_1.file_name()))?,
+            content_1
+        );
+
+        // A copy of the same index again only copies the layer files that differ
+        std::fs::write(
+            destination_timeline_dir.join(layer_file_name_2.file_name()),
+            "truncated",
+        )?;
+        let report = runtime.block_on(client.copy_timeline_remote(&destination, &cancel))?;
+        assert_eq!((report.copied_layers, report.skipped_layers), (1, 1));
+        assert_eq!(
+            std::fs::read(destination_timeline_dir.join(layer_file_name_2.file_name()))?,
+            content_2
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn stale_generation_stops_uploads() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            remote_fs_dir,
+            client,
+            ..
+        } = TestSetup::new("stale_generation_stops_uploads")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let remote_timeline_dir =
+            remote_fs_dir.join(timeline_path.strip_prefix(&harness.conf.workdir)?);
+        let cancel = CancellationToken::new();
+        let download_index_part = |client: &Arc<RemoteTimelineClient>| {
+            match runtime.block_on(client.download_index_file(&cancel)) {
+                Ok(MaybeDeletedIndexPart::IndexPart(index_part)) => Ok(index_part),
+                Ok(MaybeDeletedIndexPart::Deleted(_)) => {
+                    panic!("unexpectedly got deleted index part")
+                }
+                Err(e) => Err(e),
+            }
+        };
+
+        // The first attachment writes its index
+        let old_client = client_with_generation(&client, 1);
+        old_client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        old_client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        runtime.block_on(old_client.wait_completion())?;
+        assert_remote_files(&["index_part.json-00000001"], &remote_timeline_dir);
+
+        // The second one starts from it, writes its own, and deletes the earlier one
+        let new_client = client_with_generation(&client, 2);
+        let index_part = download_index_part(&new_client)?;
+        assert_eq!(index_part.generation, Some(Generation::new(1)));
+        assert_eq!(index_part.parse_metadata()?, dummy_metadata(Lsn(0x20)));
+        new_client.init_upload_queue(&index_part)?;
+        new_client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x30)))?;
+        runtime.block_on(new_client.wait_completion())?;
+        assert_remote_files(&["index_part.json-00000002"], &remote_timeline_dir);
+
+        // The first one can no longer upload, nor load the timeline
+        old_client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x40)))?;
+        assert!(runtime.block_on(old_client.wait_completion()).is_err());
+        assert!(old_client.upload_queue.lock().unwrap().stopped_mut().is_ok());
+        match download_index_part(&old_client) {
+            Err(DownloadError::Other(e)) => {
+                assert!(e.downcast_ref::<StaleGenerationError>().is_some())
+            }
+            _ => panic!("stale attachment loaded the index"),
+        }
+
+        let index_part = download_index_part(&new_client)?;
+        assert_eq!(index_part.generation, Some(Generation::new(2)));
+        assert_eq!(index_part.parse_metadata()?, dummy_metadata(Lsn(0x30)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_index_modification_stops_uploads() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            remote_fs_dir,
+            client,
+            ..
+        } = TestSetup::new("concurrent_index_modification_stops_uploads")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let remote_timeline_dir =
+            remote_fs_dir.join(timeline_path.strip_prefix(&harness.conf.workdir)?);
+        let cancel = CancellationToken::new();
+        let download_index_part = |client: &Arc<RemoteTimelineClient>| {
+            match runtime.block_on(client.download_index_file(&cancel)) {
+                Ok(MaybeDeletedIndexPart::IndexPart(index_part)) => Ok(index_part),
+                Ok(MaybeDeletedIndexPart::Deleted(_)) => {
+                    panic!("unexpectedly got deleted index part")
+                }
+                Err(e) => Err(e),
+            }
+        };
+
+        // Two attachments with the same generation, which the generation check can't tell apart
+        let first_client = client_with_generation(&client, 1);
+        first_client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        first_client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        runtime.block_on(first_client.wait_completion())?;
+
+        let second_client = client_with_generation(&client, 1);
+        let index_part = download_index_part(&second_client)?;
+        second_client.init_upload_queue(&index_part)?;
+        second_client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x30)))?;
+        runtime.block_on(second_client.wait_completion())?;
+
+        // The first one doesn't overwrite the index written since it wrote its own
+        first_client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x40)))?;
+        assert!(runtime.block_on(first_client.wait_completion()).is_err());
+        assert!(first_client.upload_queue.lock().unwrap().stopped_mut().is_ok());
+        assert_remote_files(&["index_part.json-00000001"], &remote_timeline_dir);
+        let index_part = download_index_part(&second_client)?;
+        assert_eq!(index_part.parse_metadata()?, dummy_metadata(Lsn(0x30)));
+
+        // While the second one goes on
+        second_client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x50)))?;
+        runtime.block_on(second_client.wait_completion())?;
+        let index_part = download_index_part(&second_client)?;
+        assert_eq!(index_part.parse_metadata()?, dummy_metadata(Lsn(0x50)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn restore_index_from_snapshot() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            remote_fs_dir,
+            client,
+            ..
+        } = TestSetup::new("restore_index_from_snapshot")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let remote_timeline_dir =
+            remote_fs_dir.join(timeline_path.strip_prefix(&harness.conf.workdir)?);
+        let cancel = CancellationToken::new();
+        let download_index_part = || match runtime.block_on(client.download_index_file(&cancel)) {
+            Ok(MaybeDeletedIndexPart::IndexPart(index_part)) => Ok(index_part),
+            Ok(MaybeDeletedIndexPart::Deleted(_)) => panic!("unexpectedly got deleted index part"),
+            Err(e) => Err(e),
+        };
+        let restore = |lsn| {
+            runtime.block_on(super::restore_index_snapshot(
+                harness.conf,
+                &client.storage_impl,
+                harness.tenant_id,
+                TIMELINE_ID,
+                lsn,
+                &cancel,
+            ))
+        };
+
+        let layer_file_name_1: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let layer_file_name_2: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D9-00000000016B5A52".parse().unwrap();
+        let content_1 = dummy_contents("foo");
+        let content_2 = dummy_contents("bar");
+        std::fs::write(
+            timeline_path.join(layer_file_name_1.file_name()),
+            &content_1,
+        )?;
+        std::fs::write(
+            timeline_path.join(layer_file_name_2.file_name()),
+            &content_2,
+        )?;
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        client.schedule_layer_file_upload(
+            &layer_file_name_1,
+            &LayerFileMetadata::new(content_1.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        runtime.block_on(client.wait_completion())?;
+        runtime.block_on(client.upload_index_snapshot(&download_index_part()?))?;
+
+        client.schedule_layer_file_upload(
+            &layer_file_name_2,
+            &LayerFileMetadata::new(content_2.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x30)))?;
+        runtime.block_on(client.wait_completion())?;
+        let remote_files: [&str; 4] = [
+            "index_part.json",
+            "index_part-0000000000000020.json",
+            &layer_file_name_1.file_name(),
+            &layer_file_name_2.file_name(),
+        ];
+        assert_remote_files(&remote_files, &remote_timeline_dir);
+
+        match restore(Lsn(0x30)) {
+            Err(RestoreIndexError::SnapshotNotFound { available, .. }) => {
+                assert_eq!(available, vec![Lsn(0x20)])
+            }
+            other => panic!("unexpected restore result {other:?}"),
+        }
+
+        let report = restore(Lsn(0x20))?;
+        assert_eq!(
+            report,
+            RemoteRestoreReport {
+                index_generation: None,
+                restored_layers: 1,
+            }
+        );
+        let index_part = download_index_part()?;
+        assert_eq!(index_part.parse_metadata()?, dummy_metadata(Lsn(0x20)));
+        assert_eq!(
+            index_part.layer_metadata.keys().collect::<Vec<_>>(),
+            vec![&layer_file_name_1]
+        );
+        // The layer files of the replaced index stay
+        assert_remote_files(&remote_files, &remote_timeline_dir);
+
+        // A snapshot isn't restored without its layer files
+        std::fs::remove_file(remote_timeline_dir.join(layer_file_name_1.file_name()))?;
+        match restore(Lsn(0x20)) {
+            Err(RestoreIndexError::MissingLayers(missing)) => {
+                assert_eq!(missing, vec![layer_file_name_1.file_name()])
+            }
+            other => panic!("unexpected restore result {other:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn cancel_task() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            remote_fs_dir,
+            client,
+            ..
+        } = TestSetup::new("cancel_task")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let remote_timeline_dir =
+            remote_fs_dir.join(timeline_path.strip_prefix(&harness.conf.workdir)?);
+
+        let layer_file_name_1: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let layer_file_name_2: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D9-00000000016B5A52".parse().unwrap();
+        let content_1 = dummy_contents("foo");
+        let content_2 = dummy_contents("bar");
+        std::fs::write(
+            timeline_path.join(layer_file_name_1.file_name()),
+            &content_1,
+        )?;
+        std::fs::write(
+            timeline_path.join(layer_file_name_2.file_name()),
+            &content_2,
+        )?;
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        client.schedule_layer_file_upload(
+            &layer_file_name_1,
+            &LayerFileMetadata::new(content_1.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        runtime.block_on(client.wait_completion())?;
+
+        // The index uploads referencing the second layer wait for its upload, and the
+        // deletion of the first layer waits for the index uploads
+        client.schedule_layer_file_upload(
+            &layer_file_name_2,
+            &LayerFileMetadata::new(content_2.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x30)))?;
+        client.schedule_layer_file_deletion(&[layer_file_name_1.clone()])?;
+        let info = client.upload_queue_info();
+        assert_eq!(info.inprogress_tasks.len(), 1);
+        let task_id = info.inprogress_tasks[0].task_id;
+        assert_eq!(info.inprogress_tasks[0].op.kind, UploadOpKind::UploadLayer);
+        assert!(!info.queued_operations.is_empty());
+
+        assert!(client.cancel_task(task_id + 1).is_err());
+        client.cancel_task(task_id)?;
+        assert!(client.cancel_task(task_id).is_err());
+
+        // All of them are cancelled, transitively
+        let info = client.upload_queue_info();
+        assert!(info.inprogress_tasks.is_empty());
+        assert!(info.queued_operations.is_empty());
+        assert_eq!(info.queued_layer_bytes, 0);
+        assert_eq!(info.last_uploaded_consistent_lsn, Some(Lsn(0x20)));
+        runtime.block_on(client.wait_completion())?;
+        assert_remote_files(
+            &[&layer_file_name_1.file_name(), "index_part.json"],
+            &remote_timeline_dir,
+        );
+
+        // The queue goes on, without the second layer
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x40)))?;
+        runtime.block_on(client.wait_completion())?;
+        let cancel = CancellationToken::new();
+        let index_part = match runtime.block_on(client.download_index_file(&cancel))? {
+            MaybeDeletedIndexPart::IndexPart(index_part) => index_part,
+            MaybeDeletedIndexPart::Deleted(_) => panic!("unexpectedly got deleted index part"),
+        };
+        assert_eq!(index_part.parse_metadata()?, dummy_metadata(Lsn(0x40)));
+        assert!(index_part.timeline_layers.is_empty());
+        assert!(client.get_layer_metadata(&layer_file_name_2)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn drain() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            remote_fs_dir,
+            client,
+            ..
+        } = TestSetup::new("drain")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let remote_timeline_dir =
+            remote_fs_dir.join(timeline_path.strip_prefix(&harness.conf.workdir)?);
+
+        let layer_file_name_1: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let content_1 = dummy_contents("foo");
+        std::fs::write(
+            timeline_path.join(layer_file_name_1.file_name()),
+            &content_1,
+        )?;
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        client.schedule_layer_file_upload(
+            &layer_file_name_1,
+            &LayerFileMetadata::new(content_1.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+
+        // The queued operations complete
+        runtime.block_on(client.drain())?;
+        let info = client.upload_queue_info();
+        assert!(info.inprogress_tasks.is_empty());
+        assert!(info.queued_operations.is_empty());
+        assert_eq!(info.last_uploaded_consistent_lsn, Some(Lsn(0x20)));
+        assert_remote_files(
+            &[&layer_file_name_1.file_name(), "index_part.json"],
+            &remote_timeline_dir,
+        );
+
+        // No new operations are accepted
+        assert!(client
+            .schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x30)))
+            .is_err());
+        assert!(client
+            .schedule_layer_file_deletion(&[layer_file_name_1.clone()])
+            .is_err());
+        assert!(client.get_layer_metadata(&layer_file_name_1)?.is_some());
+
+        // Waiting, or draining again, still works
+        runtime.block_on(client.wait_completion())?;
+        runtime.block_on(client.drain())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn batch_deletion() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            remote_fs_dir,
+            client,
+            ..
+        } = TestSetup::new("batch_deletion")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let remote_timeline_dir =
+            remote_fs_dir.join(timeline_path.strip_prefix(&harness.conf.workdir)?);
+
+        let layer_file_names: Vec<LayerFileName> = [
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51",
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D9-00000000016B5A52",
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59DA-00000000016B5A53",
+        ]
+        .iter()
+        .map(|name| name.parse().unwrap())
+        .collect();
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        for layer_file_name in &layer_file_names {
+            let content = dummy_contents(&layer_file_name.file_name());
+            std::fs::write(timeline_path.join(layer_file_name.file_name()), &content)?;
+            client.schedule_layer_file_upload(
+                layer_file_name,
+                &LayerFileMetadata::new(content.len() as u64),
+            )?;
+        }
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        runtime.block_on(client.wait_completion())?;
+
+        let task_counter = |client: &RemoteTimelineClient| {
+            let mut guard = client.upload_queue.lock().unwrap();
+            let upload_queue = guard.initialized_mut().unwrap();
+            upload_queue.task_counter
+        };
+        let tasks_before = task_counter(&client);
+
+        // One deletion is queued for each layer, after the index upload
+        client.schedule_layer_file_deletion(&layer_file_names[..2])?;
+        let info = client.upload_queue_info();
+        let queued_kinds = info
+            .queued_operations
+            .iter()
+            .map(|op| op.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(queued_kinds, [UploadOpKind::Delete, UploadOpKind::Delete]);
+        runtime.block_on(client.wait_completion())?;
+
+        // The index upload, and one request for both deletions
+        assert_eq!(task_counter(&client), tasks_before + 2);
+        assert_remote_files(
+            &[&layer_file_names[2].file_name(), "index_part.json"],
+            &remote_timeline_dir,
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn deletion_dry_run() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            remote_fs_dir,
+            client,
+            ..
+        } = TestSetup::new("deletion_dry_run")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let remote_timeline_dir =
+            remote_fs_dir.join(timeline_path.strip_prefix(&harness.conf.workdir)?);
+
+        let layer_file_names: Vec<LayerFileName> = [
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51",
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D9-00000000016B5A52",
+        ]
+        .iter()
+        .map(|name| name.parse().unwrap())
+        .collect();
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        for layer_file_name in &layer_file_names {
+            let content = dummy_contents(&layer_file_name.file_name());
+            std::fs::write(timeline_path.join(layer_file_name.file_name()), &content)?;
+            client.schedule_layer_file_upload(
+                layer_file_name,
+                &LayerFileMetadata::new(content.len() as u64),
+            )?;
+        }
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        runtime.block_on(client.wait_completion())?;
+
+        // The deletion is recorded, the index upload isn't affected
+        client.deletion_dry_run.set_enabled(true);
+        client.schedule_layer_file_deletion(&layer_file_names[..1])?;
+        runtime.block_on(client.wait_completion())?;
+        assert_remote_files(
+            &[
+                &layer_file_names[0].file_name(),
+                &layer_file_names[1].file_name(),
+                "index_part.json",
+            ],
+            &remote_timeline_dir,
+        );
+        let cancel = CancellationToken::new();
+        let index_part = match runtime.block_on(client.download_index_file(&cancel))? {
+            MaybeDeletedIndexPart::IndexPart(index_part) => index_part,
+            MaybeDeletedIndexPart::Deleted(_) => panic!("unexpectedly got deleted index part"),
+        };
+        assert_eq!(index_part.timeline_layers.len(), 1);
+
+        // The object at the top of the timeline directory and in its shard directory, at the
+        // default location and in the cold tier
+        let flushed = client.deletion_dry_run.flush();
+        let remote_paths = flushed
+            .records
+            .iter()
+            .map(|record| {
+                assert_eq!(record.timeline_id, TIMELINE_ID);
+                record.remote_path.as_str()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(remote_paths.len(), 4);
+        for remote_path in &remote_paths {
+            assert!(remote_path.ends_with(&layer_file_names[0].file_name()));
+        }
+        assert!(!remote_paths[1].starts_with(tiering::COLD_TIER_PREFIX));
+        assert!(remote_paths[2].starts_with(tiering::COLD_TIER_PREFIX));
+        assert!(remote_paths[3].starts_with(tiering::COLD_TIER_PREFIX));
+        assert!(client.deletion_dry_run.flush().records.is_empty());
+
+        // Deleted once the mode is disabled
+        client.deletion_dry_run.set_enabled(false);
+        client.schedule_layer_file_deletion(&layer_file_names[1..])?;
+        runtime.block_on(client.wait_completion())?;
+        assert_remote_files(
+            &[&layer_file_names[0].file_name(), "index_part.json"],
+            &remote_timeline_dir,
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn timeline_deletion_checkpoint() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            remote_fs_dir,
+            client,
+            ..
+        } = TestSetup::new("timeline_deletion_checkpoint")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+        let remote_timeline_dir =
+            remote_fs_dir.join(timeline_path.strip_prefix(&harness.conf.workdir)?);
+
+        let layer_file_names: Vec<LayerFileName> = [
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51",
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D9-00000000016B5A52",
+        ]
+        .iter()
+        .map(|name| name.parse().unwrap())
+        .collect();
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        for layer_file_name in &layer_file_names {
+            let content = dummy_contents(&layer_file_name.file_name());
+            std::fs::write(timeline_path.join(layer_file_name.file_name()), &content)?;
+            client.schedule_layer_file_upload(
+                layer_file_name,
+                &LayerFileMetadata::new(content.len() as u64),
+            )?;
+        }
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        runtime.block_on(client.wait_completion())?;
+
+        client.stop()?;
+        assert_eq!(client.deletion_progress(), None);
+        runtime.block_on(client.persist_index_part_with_deleted_flag())?;
+        assert_eq!(
+            client.deletion_progress(),
+            Some(TimelineDeletionProgress {
+                layers_deleted: 0,
+                layers_remaining: 2,
+                layers_checkpointed: 0,
+            })
+        );
+
+        let (mut receiver, deletions_queued) = client.schedule_timeline_layer_deletions()?;
+        assert_eq!(deletions_queued, 2);
+        runtime.block_on(receiver.changed())?;
+        assert_remote_files(&["index_part.json"], &remote_timeline_dir);
+        assert_eq!(
+            client.deletion_progress(),
+            Some(TimelineDeletionProgress {
+                layers_deleted: 2,
+                layers_remaining: 0,
+                layers_checkpointed: 0,
+            })
+        );
+
+        // A deletion resumed from the checkpointed index has no layer files left to delete
+        runtime.block_on(client.checkpoint_deletion())?;
+        assert_eq!(
+            client.upload_queue_info().deletion_progress,
+            Some(TimelineDeletionProgress {
+                layers_deleted: 2,
+                layers_remaining: 0,
+                layers_checkpointed: 2,
+            })
+        );
+        let cancel = CancellationToken::new();
+        let index_part = match runtime.block_on(client.download_index_file(&cancel))? {
+            MaybeDeletedIndexPart::Deleted(index_part) => index_part,
+            MaybeDeletedIndexPart::IndexPart(_) => panic!("the index lost its deletion mark"),
+        };
+        assert!(index_part.layer_metadata.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn subscribe_uploads() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            client,
+            ..
+        } = TestSetup::new("subscribe_uploads")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+
+        let layer_file_names: Vec<LayerFileName> = [
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51",
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D9-00000000016B5A52",
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59DA-00000000016B5A53",
+        ]
+        .iter()
+        .map(|name| name.parse().unwrap())
+        .collect();
+        let content = dummy_contents("foo");
+        for layer_file_name in &layer_file_names {
+            std::fs::write(timeline_path.join(layer_file_name.file_name()), &content)?;
+        }
+        let layer_metadata = LayerFileMetadata::new(content.len() as u64);
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        let mut events = client.subscribe_uploads();
+
+        client.schedule_layer_file_upload(&layer_file_names[0], &layer_metadata)?;
+        runtime.block_on(client.wait_completion())?;
+        let event = events.try_recv()?;
+        assert_eq!(event.layer_file_name, layer_file_names[0]);
+        assert_eq!(event.result, Ok(()));
+
+        // Cancelled before it runs
+        client.schedule_layer_file_upload(&layer_file_names[1], &layer_metadata)?;
+        client.cancel_task(client.upload_queue_info().inprogress_tasks[0].task_id)?;
+        let event = events.try_recv()?;
+        assert_eq!(event.layer_file_name, layer_file_names[1]);
+        assert_eq!(event.result, Err(LayerUploadFailure::Cancelled));
+
+        // Dropped by a shutdown
+        client.schedule_layer_file_upload(&layer_file_names[2], &layer_metadata)?;
+        client.stop()?;
+        let event = events.try_recv()?;
+        assert_eq!(event.layer_file_name, layer_file_names[2]);
+        assert_eq!(event.result, Err(LayerUploadFailure::QueueStopped));
+
+        assert!(events.try_recv().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn wait_completion_timeout() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            harness,
+            client,
+            ..
+        } = TestSetup::new("wait_completion_timeout")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+
+        let layer_file_name_1: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let layer_path_1 = timeline_path.join(layer_file_name_1.file_name());
+        let content_1 = dummy_contents("foo");
+        // The upload keeps failing while the size on disk doesn't match the metadata
+        std::fs::write(&layer_path_1, dummy_contents("truncated"))?;
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        client.schedule_layer_file_upload(
+            &layer_file_name_1,
+            &LayerFileMetadata::new(content_1.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+
+        let err = runtime
+            .block_on(client.wait_completion_timeout(Duration::from_millis(100)))
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{err:#}");
+
+        // The operations stay queued
+        let expected_progress = UploadQueueProgress {
+            ops_remaining: 2,
+            bytes_remaining: content_1.len() as u64,
+        };
+        assert_eq!(client.upload_queue.lock().unwrap().progress(), expected_progress);
+
+        let mut reports = Vec::new();
+        let wait = client.wait_completion_with_progress(Duration::from_millis(10), |progress| {
+            reports.push(progress)
+        });
+        let fix_layer = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            std::fs::write(&layer_path_1, &content_1)
+        };
+        let (wait_res, fix_res) = runtime.block_on(async { tokio::join!(wait, fix_layer) });
+        fix_res?;
+        wait_res?;
+
+        assert_eq!(reports.first(), Some(&expected_progress));
+        assert_eq!(
+            client.upload_queue.lock().unwrap().progress(),
+            UploadQueueProgress::default()
+        );
+        assert_eq!(
+            client.upload_queue_info().last_uploaded_consistent_lsn,
+            Some(Lsn(0x20))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn index_lsn_regression() -> anyhow::Result<()> {
+        let TestSetup {
+            runtime,
+            client,
+            ..
+        } = TestSetup::new("index_lsn_regression")?;
+        let mut conf = client.conf.clone();
+        conf.remote_index_lsn_check = IndexLsnCheck::Enforce;
+        conf.remote_retry.upload.max_backoff = Duration::from_millis(10);
+        let client = Arc::new(RemoteTimelineClient {
+            conf: Box::leak(Box::new(conf)),
+            runtime: client.runtime,
+            tenant_id: client.tenant_id,
+            timeline_id: client.timeline_id,
+            storage_impl: client.storage_impl.clone(),
+            upload_queue: Mutex::new(UploadQueue::Uninitialized),
+            metrics: Arc::clone(&client.metrics),
+            upload_throttle: Arc::clone(&client.upload_throttle),
+            upload_concurrency_limit: Arc::clone(&client.upload_concurrency_limit),
+            deferred_deletions: Arc::clone(&client.deferred_deletions),
+            deletion_dry_run: Arc::clone(&client.deletion_dry_run),
+            queue_space_freed: tokio::sync::Notify::new(),
+            upload_events: tokio::sync::broadcast::channel(UPLOAD_EVENTS_CAPACITY).0,
+            generation: None,
+            mirror: None,
+            event_sink: None,
+            storage_health: Arc::clone(&client.storage_health),
+            remote_index: Mutex::new(None),
+        });
+
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x30)))?;
+        runtime.block_on(client.wait_completion())?;
+
+        // An index going back in LSN is rejected, and blocks the queue
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        let res = runtime.block_on(client.wait_completion_timeout(Duration::from_millis(100)));
+        assert!(res.is_err());
+        let info = client.upload_queue_info();
+        assert_eq!(info.last_uploaded_consistent_lsn, Some(Lsn(0x30)));
+        assert_eq!(info.inprogress_tasks.len(), 1);
+        let last_error = info.inprogress_tasks[0].last_error.as_deref().unwrap_or_default();
+        assert!(last_error.contains("behind"), "{last_error}");
+
+        // Until it's allowed, once
+        client.allow_index_lsn_regression()?;
+        assert!(client.upload_queue_info().allow_lsn_regression);
+        runtime.block_on(client.wait_completion())?;
+        let info = client.upload_queue_info();
+        assert_eq!(info.last_uploaded_consistent_lsn, Some(Lsn(0x20)));
+        assert!(!info.allow_lsn_regression);
+
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x10)))?;
+        let res = runtime.block_on(client.wait_completion_timeout(Duration::from_millis(100)));
+        assert!(res.is_err());
+        assert_eq!(
+            client.upload_queue_info().last_uploaded_consistent_lsn,
+            Some(Lsn(0x20))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn pending_index_on_stop() -> anyhow::Result<()> {
+        let TestSetup {
+            harness,
+            client,
+            ..
+        } = TestSetup::new("pending_index_on_stop")?;
+        let timeline_path = harness.timeline_path(&TIMELINE_ID);
+
+        let layer_file_name: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let content = dummy_contents("foo");
+        std::fs::write(timeline_path.join(layer_file_name.file_name()), &content)?;
+
+        // Stopped before the runtime gets to run the operations
+        client.init_upload_queue_for_empty_remote(&dummy_metadata(Lsn(0x10)))?;
+        client.schedule_layer_file_upload(
+            &layer_file_name,
+            &LayerFileMetadata::new(content.len() as u64),
+        )?;
+        client.schedule_index_upload_for_metadata_update(&dummy_metadata(Lsn(0x20)))?;
+        client.stop()?;
+
+        let pending = client.take_pending_index()?.expect("operations were left");
+        assert_eq!(pending.timeline_layers, HashSet::from([layer_file_name]));
+        assert_eq!(pending.disk_consistent_lsn, Lsn(0x20));
+        // A later load doesn't find it again
+        assert!(client.take_pending_index()?.is_none());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,149 @@
+//! Compression of the layer files stored remotely.
+//!
+//! With `remote_compression = { algorithm = "zstd" }`, the layer files are compressed before
+//! upload, and before encryption if they are encrypted too, and decompressed after download.
+//! The index records which layers are compressed and their compressed size, see
+//! [`LayerCompression`], so that the layers uploaded before the setting changed are still read
+//! back.
+
+use std::path::Path;
+
+use anyhow::Context;
+use async_compression::tokio::bufread::ZstdDecoder;
+use async_compression::tokio::write::ZstdEncoder;
+use async_compression::Level;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
+
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+fn default_zstd_level() -> i32 {
+    DEFAULT_ZSTD_LEVEL
+}
+
+/// The `remote_compression` setting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "snake_case", deny_unknown_fields)]
+pub enum RemoteCompressionConfig {
+    #[default]
+    None,
+    /// Compress the layer files with zstd, at the given level.
+    Zstd {
+        #[serde(default = "default_zstd_level")]
+        level: i32,
+    },
+}
+
+/// How a layer file stored remotely is compressed, recorded in the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "snake_case")]
+pub enum LayerCompression {
+    /// A single zstd frame.
+    Zstd { compressed_size: u64 },
+}
+
+impl LayerCompression {
+    pub fn compressed_size(self) -> u64 {
+        match self {
+            LayerCompression::Zstd { compressed_size } => compressed_size,
+        }
+    }
+}
+
+/// Write the compressed form of the file at `source_path` to `dest_path`.
+pub(super) async fn compress_file(
+    level: i32,
+    source_path: &Path,
+    dest_path: &Path,
+) -> anyhow::Result<LayerCompression> {
+    let mut source = fs::File::open(source_path)
+        .await
+        .with_context(|| format!("open {}", source_path.display()))?;
+    let dest = fs::File::create(dest_path)
+        .await
+        .with_context(|| format!("create {}", dest_path.display()))?;
+
+    let mut encoder = ZstdEncoder::with_quality(BufWriter::new(dest), Level::Precise(level));
+    tokio::io::copy(&mut source, &mut encoder)
+        .await
+        .with_context(|| format!("compress {}", source_path.display()))?;
+    // Ends the frame, and flushes the file
+    encoder.shutdown().await?;
+
+    let compressed_size = fs::metadata(dest_path).await?.len();
+    Ok(LayerCompression::Zstd { compressed_size })
+}
+
+/// Write the decompressed form of the file at `source_path` to `dest_path`, returning its size.
+pub(super) async fn decompress_file(
+    compression: LayerCompression,
+    source_path: &Path,
+    dest_path: &Path,
+) -> anyhow::Result<u64> {
+    let source = fs::File::open(source_path)
+        .await
+        .with_context(|| format!("open {}", source_path.display()))?;
+    let mut dest = BufWriter::new(
+        fs::File::create(dest_path)
+            .await
+            .with_context(|| format!("create {}", dest_path.display()))?,
+    );
+
+    let decompressed_size = match compression {
+        LayerCompression::Zstd { .. } => {
+            let mut decoder = ZstdDecoder::new(BufReader::new(source));
+            tokio::io::copy(&mut decoder, &mut dest)
+                .await
+                .with_context(|| format!("decompress {}", source_path.display()))?
+        }
+    };
+    dest.flush().await?;
+    Ok(decompressed_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trip() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        for size in [0, 1, 8192, 3 * 1024 * 1024 + 1] {
+            let contents: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+            let path = dir.path().join("layer");
+            let compressed_path = dir.path().join("compressed");
+            let decompressed_path = dir.path().join("decompressed");
+            std::fs::write(&path, &contents)?;
+
+            let compression = compress_file(DEFAULT_ZSTD_LEVEL, &path, &compressed_path).await?;
+            let compressed_size = std::fs::metadata(&compressed_path)?.len();
+            assert_eq!(compression, LayerCompression::Zstd { compressed_size });
+            if size >= 8192 {
+                assert!(compressed_size < size as u64 / 10);
+            }
+
+            let decompressed_size =
+                decompress_file(compression, &compressed_path, &decompressed_path).await?;
+            assert_eq!(decompressed_size, size as u64);
+            assert_eq!(std::fs::read(&decompressed_path)?, contents);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn not_compressed_is_detected() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("layer");
+        let decompressed_path = dir.path().join("decompressed");
+        std::fs::write(&path, b"not a zstd frame")?;
+
+        let compression = LayerCompression::Zstd {
+            compressed_size: 16,
+        };
+        assert!(decompress_file(compression, &path, &decompressed_path)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+//! Helper functions to copy the files of a timeline from one remote storage to another.
+//!
+//! The files are streamed through the pageserver memory, not its local disk, and copied as they
+//! are: the compressed and encrypted layer files stay so, and the layer files evicted locally
+//! can be copied too.
+
+use std::path::Path;
+
+use anyhow::Context;
+use remote_storage::{DownloadError, GenericRemoteStorage, RemotePath};
+
+use super::index::IndexPart;
+
+/// Copy an object with its metadata. Returns its size, or `None` if the source doesn't have it.
+pub(super) async fn copy_object(
+    source: &GenericRemoteStorage,
+    destination: &GenericRemoteStorage,
+    path: &RemotePath,
+) -> anyhow::Result<Option<u64>> {
+    copy_object_to(source, path, destination, path).await
+}
+
+/// Like [`copy_object`], to another path at the destination.
+pub(super) async fn copy_object_to(
+    source: &GenericRemoteStorage,
+    from: &RemotePath,
+    destination: &GenericRemoteStorage,
+    to: &RemotePath,
+) -> anyhow::Result<Option<u64>> {
+    let download = async {
+        let head = source.head_object(from).await?;
+        let download = source.download(from).await?;
+        Ok::<_, DownloadError>((head.size, download))
+    };
+    let (size, download) = match download.await {
+        Ok(download) => download,
+        Err(DownloadError::NotFound) => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("download {from:?} to copy it")),
+    };
+    destination
+        .upload(
+            download.download_stream,
+            size as usize,
+            to,
+            download.metadata,
+        )
+        .await
+        .with_context(|| format!("upload copy of {from:?} to {to:?}"))?;
+    Ok(Some(size))
+}
+
+/// Upload a copy of an index under the name of its generation. Unlike
+/// [`super::upload::upload_index_part`], there's no check for the later generations: the copy
+/// follows whatever the source has.
+pub(super) async fn upload_index_copy(
+    storage: &GenericRemoteStorage,
+    timeline_storage_path: &RemotePath,
+    index_part: &IndexPart,
+) -> anyhow::Result<()> {
+    let bytes = serde_json::to_vec(index_part).context("serialize index part")?;
+    let size = bytes.len();
+    let path = timeline_storage_path.join(Path::new(&IndexPart::file_name(index_part.generation)));
+    storage
+        .upload_storage_object(
+            Box::new(tokio::io::BufReader::new(std::io::Cursor::new(bytes))),
+            size,
+            &path,
+        )
+        .await
+        .with_context(|| format!("upload copy of index part {path:?}"))
+}
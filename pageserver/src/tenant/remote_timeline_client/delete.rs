@@ -1,29 +1,67 @@
 //! Helper functions to delete files from remote storage with a RemoteStorage
 use anyhow::Context;
-use std::path::Path;
+use std::path::PathBuf;
 use tracing::debug;
 
-use remote_storage::GenericRemoteStorage;
+use remote_storage::{GenericRemoteStorage, RemotePath};
 
 use crate::config::PageServerConf;
 
-pub(super) async fn delete_layer<'a>(
+use super::{sharding, tiering};
+
+/// Delete the layers with one DeleteObjects request per location, for up to
+/// [`MAX_DELETE_BATCH_SIZE`](crate::tenant::upload_queue::MAX_DELETE_BATCH_SIZE) layers.
+///
+/// Both the copies at the default location and in the cold tier are deleted, whichever the
+/// layers have, unless `hot_copies_only`, which is for the layers just moved to the cold tier.
+/// The layers are deleted both at the top of the timeline directory and in their shard
+/// directory, see [`sharding`].
+pub(super) async fn delete_layers<'a>(
     conf: &'static PageServerConf,
     storage: &'a GenericRemoteStorage,
-    local_layer_path: &'a Path,
+    local_layer_paths: &'a [PathBuf],
+    hot_copies_only: bool,
 ) -> anyhow::Result<()> {
     fail::fail_point!("before-delete-layer", |_| {
         anyhow::bail!("failpoint before-delete-layer")
     });
-    debug!("Deleting layer from remote storage: {local_layer_path:?}",);
+    debug!("Deleting layers from remote storage: {local_layer_paths:?}",);
 
-    let path_to_delete = conf.remote_path(local_layer_path)?;
+    let paths_to_delete = remote_layer_paths(conf, local_layer_paths, hot_copies_only)?;
 
     // We don't want to print an error if the delete failed if the file has
     // already been deleted. Thankfully, in this situation S3 already
     // does not yield an error. While OS-provided local file system APIs do yield
     // errors, we avoid them in the `LocalFs` wrapper.
-    storage.delete(&path_to_delete).await.with_context(|| {
-        format!("Failed to delete remote layer from storage at {path_to_delete:?}")
-    })
+    storage
+        .delete_objects(&paths_to_delete)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to delete {} remote layers from storage, the first at {:?}",
+                paths_to_delete.len(),
+                paths_to_delete.first()
+            )
+        })
+}
+
+/// The objects [`delete_layers`] deletes.
+pub(super) fn remote_layer_paths(
+    conf: &'static PageServerConf,
+    local_layer_paths: &[PathBuf],
+    hot_copies_only: bool,
+) -> anyhow::Result<Vec<RemotePath>> {
+    let mut paths = local_layer_paths
+        .iter()
+        .map(|path| conf.remote_path(path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    // Whichever path scheme they were uploaded with
+    let sharded_paths = paths.iter().map(sharding::sharded_path).collect::<Vec<_>>();
+    paths.extend(sharded_paths);
+    if !hot_copies_only {
+        // Deleting an object that doesn't exist succeeds
+        let cold_paths = paths.iter().map(tiering::cold_path).collect::<Vec<_>>();
+        paths.extend(cold_paths);
+    }
+    Ok(paths)
 }
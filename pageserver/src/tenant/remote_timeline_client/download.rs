@@ -1,29 +1,41 @@
 //! Helper functions to download files from remote storage with a RemoteStorage
 //!
 //! The functions in this module retry failed operations automatically, according
-//! to the FAILED_DOWNLOAD_RETRIES constant.
+//! to the `remote_retry.download` policy. They stop with [`DownloadError::Cancelled`] once
+//! their `cancel` token is cancelled, dropping the request in flight.
 
 use std::collections::HashSet;
 use std::future::Future;
-use std::path::Path;
+use std::io::SeekFrom;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, ensure, Context};
+use serde::Deserialize;
 use tokio::fs;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
 
 use tracing::{info, warn};
 
 use crate::config::PageServerConf;
+use crate::tenant::generation::{Generation, StaleGenerationError};
 use crate::tenant::storage_layer::LayerFileName;
 use crate::tenant::timeline::span::debug_assert_current_span_has_tenant_and_timeline_id;
-use crate::{exponential_backoff, DEFAULT_BASE_BACKOFF_SECONDS, DEFAULT_MAX_BACKOFF_SECONDS};
-use remote_storage::{DownloadError, GenericRemoteStorage};
+use remote_storage::{
+    DownloadError, GenericRemoteStorage, ObjectHead, RemotePath, UploadPrecondition,
+};
 use utils::crashsafe::path_with_suffix_extension;
 use utils::id::{TenantId, TimelineId};
 
+use super::compression::{self, LayerCompression};
+use super::encryption;
+use super::index::chunked::{IndexManifest, IndexSegment, IndexSegments};
 use super::index::{IndexPart, LayerFileMetadata};
-use super::{FAILED_DOWNLOAD_RETRIES, FAILED_DOWNLOAD_WARN_THRESHOLD};
+use super::listing;
+use super::{layer_file_crc32c, RetryPolicy};
 
 async fn fsync_path(path: impl AsRef<std::path::Path>) -> Result<(), std::io::Error> {
     fs::File::open(path).await?.sync_all().await
@@ -31,11 +43,42 @@ async fn fsync_path(path: impl AsRef<std::path::Path>) -> Result<(), std::io::Er
 
 static MAX_DOWNLOAD_DURATION: Duration = Duration::from_secs(120);
 
+/// The parts of a layer file downloaded in parallel are at least this large.
+const MIN_DOWNLOAD_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// The `parallel_download` setting: download the large layer files with concurrent range
+/// requests, as a single stream from S3 tops out at around 100 MB/s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParallelDownloadConfig {
+    /// The layer files of at least this size in the remote storage are downloaded in parallel.
+    pub min_size: u64,
+    /// Into how many range requests to split the download of a layer file.
+    #[serde(default = "ParallelDownloadConfig::default_chunks")]
+    pub chunks: NonZeroUsize,
+}
+
+impl ParallelDownloadConfig {
+    fn default_chunks() -> NonZeroUsize {
+        NonZeroUsize::new(8).unwrap()
+    }
+
+    /// Into how many range requests to split the download of a layer file of `size` bytes.
+    fn chunks_for(&self, size: u64) -> usize {
+        if size < self.min_size {
+            return 1;
+        }
+        let max_chunks = usize::try_from(size / MIN_DOWNLOAD_CHUNK_SIZE).unwrap_or(usize::MAX);
+        self.chunks.get().min(max_chunks).max(1)
+    }
+}
+
 ///
-/// If 'metadata' is given, we will validate that the downloaded file's size matches that
-/// in the metadata. (In the future, we might do more cross-checks, like CRC validation)
+/// We validate that the downloaded file's size matches that in the metadata, and so does its
+/// checksum, if the metadata has one. Encrypted layer files are decrypted, and compressed ones
+/// decompressed.
 ///
-/// Returns the size of the downloaded file.
+/// Returns the size of the downloaded file, once decrypted and decompressed.
 pub async fn download_layer_file<'a>(
     conf: &'static PageServerConf,
     storage: &'a GenericRemoteStorage,
@@ -43,6 +86,7 @@ pub async fn download_layer_file<'a>(
     timeline_id: TimelineId,
     layer_file_name: &'a LayerFileName,
     layer_metadata: &'a LayerFileMetadata,
+    cancel: &CancellationToken,
 ) -> Result<u64, DownloadError> {
     debug_assert_current_span_has_tenant_and_timeline_id();
 
@@ -50,9 +94,10 @@ pub async fn download_layer_file<'a>(
 
     let local_path = timeline_path.join(layer_file_name.file_name());
 
-    let remote_path = conf
-        .remote_path(&local_path)
+    let timeline_storage_path = conf
+        .remote_path(&timeline_path)
         .map_err(DownloadError::Other)?;
+    let remote_path = layer_metadata.remote_path(&timeline_storage_path, layer_file_name);
 
     // Perform a rename inspired by durable_rename from file_utils.c.
     // The sequence:
@@ -66,36 +111,51 @@ pub async fn download_layer_file<'a>(
     // If pageserver crashes the temp file will be deleted on startup and re-downloaded.
     let temp_file_path = path_with_suffix_extension(&local_path, TEMP_DOWNLOAD_EXTENSION);
 
-    let (mut destination_file, bytes_amount) = download_retry(
-        || async {
-            // TODO: this doesn't use the cached fd for some reason?
-            let mut destination_file = fs::File::create(&temp_file_path).await.with_context(|| {
-                format!(
-                    "create a destination file for layer '{}'",
-                    temp_file_path.display()
-                )
-            })
-            .map_err(DownloadError::Other)?;
-            let mut download = storage.download(&remote_path).await.with_context(|| {
-                format!(
-                    "open a download stream for layer with remote storage path '{remote_path:?}'"
-                )
-            })
-            .map_err(DownloadError::Other)?;
-
-            let bytes_amount = tokio::time::timeout(MAX_DOWNLOAD_DURATION, tokio::io::copy(&mut download.download_stream, &mut destination_file))
-                .await
-                .map_err(|e| DownloadError::Other(anyhow::anyhow!("Timed out  {:?}", e)))?
-                .with_context(|| {
-                    format!("Failed to download layer with remote storage path '{remote_path:?}' into file {temp_file_path:?}")
-                })
-                .map_err(DownloadError::Other)?;
-
-            Ok((destination_file, bytes_amount))
+    // The retries continue from where the failed attempts stopped, but not from the temp file
+    // of an earlier call, which may be of an older version of the layer file.
+    fs::File::create(&temp_file_path)
+        .await
+        .with_context(|| {
+            format!(
+                "create a destination file for layer '{}'",
+                temp_file_path.display()
+            )
+        })
+        .map_err(DownloadError::Other)?;
 
-        },
-        &format!("download {remote_path:?}"),
-    ).await?;
+    let expected = layer_metadata.remote_size();
+    let chunks = conf
+        .parallel_download
+        .map_or(1, |config| config.chunks_for(expected));
+    let (mut destination_file, bytes_amount) = if chunks > 1 {
+        // The retries download only the chunks that failed
+        let done = (0..chunks)
+            .map(|_| AtomicBool::new(false))
+            .collect::<Vec<_>>();
+        download_retry(
+            &conf.remote_retry.download,
+            || {
+                download_chunks_to_temp_file(
+                    storage,
+                    &remote_path,
+                    &temp_file_path,
+                    expected,
+                    &done,
+                )
+            },
+            &format!("download {remote_path:?} in {chunks} chunks"),
+            cancel,
+        )
+        .await?
+    } else {
+        download_retry(
+            &conf.remote_retry.download,
+            || download_to_temp_file(storage, &remote_path, &temp_file_path, expected),
+            &format!("download {remote_path:?}"),
+            cancel,
+        )
+        .await?
+    };
 
     // Tokio doc here: https://docs.rs/tokio/1.17.0/tokio/fs/struct.File.html states that:
     // A file will not be closed immediately when it goes out of scope if there are any IO operations
@@ -116,13 +176,57 @@ pub async fn download_layer_file<'a>(
         })
         .map_err(DownloadError::Other)?;
 
-    let expected = layer_metadata.file_size();
     if expected != bytes_amount {
         return Err(DownloadError::Other(anyhow!(
             "According to layer file metadata should have downloaded {expected} bytes but downloaded {bytes_amount} bytes into file {temp_file_path:?}",
         )));
     }
 
+    // Move the decrypted file into place instead, if the layer is encrypted
+    let (destination_file, temp_file_path) = match layer_metadata.encryption() {
+        None => (destination_file, temp_file_path),
+        Some(_) => {
+            drop(destination_file);
+            decrypt_downloaded_layer(
+                conf,
+                tenant_id,
+                &temp_file_path,
+                &local_path,
+                layer_metadata.compressed_size(),
+            )
+            .await
+            .map_err(DownloadError::Other)?
+        }
+    };
+
+    // And the decompressed one, if the layer is compressed
+    let (destination_file, temp_file_path) = match layer_metadata.compression() {
+        None => (destination_file, temp_file_path),
+        Some(compression) => {
+            drop(destination_file);
+            decompress_downloaded_layer(
+                compression,
+                &temp_file_path,
+                &local_path,
+                layer_metadata.file_size(),
+            )
+            .await
+            .map_err(DownloadError::Other)?
+        }
+    };
+
+    if let Some(expected) = layer_metadata.crc32c() {
+        let crc32c = layer_file_crc32c(&temp_file_path)
+            .await
+            .with_context(|| format!("Failed to compute the checksum of file {temp_file_path:?}"))
+            .map_err(DownloadError::Other)?;
+        if expected != crc32c {
+            return Err(DownloadError::Other(anyhow!(
+                "According to layer file metadata should have downloaded a file with checksum {expected:#010x} but downloaded one with checksum {crc32c:#010x} into file {temp_file_path:?}",
+            )));
+        }
+    }
+
     // not using sync_data because it can lose file size update
     destination_file
         .sync_all()
@@ -159,10 +263,243 @@ pub async fn download_layer_file<'a>(
 
     tracing::debug!("download complete: {}", local_path.display());
 
-    Ok(bytes_amount)
+    Ok(layer_metadata.file_size())
+}
+
+/// Download a layer file into its temporary file, continuing from the end of the file with a
+/// range request if an earlier attempt wrote a part of it. Returns the file and its size.
+async fn download_to_temp_file(
+    storage: &GenericRemoteStorage,
+    remote_path: &RemotePath,
+    temp_file_path: &Path,
+    expected_size: u64,
+) -> Result<(fs::File, u64), DownloadError> {
+    // TODO: this doesn't use the cached fd for some reason?
+    let mut destination_file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(temp_file_path)
+        .await
+        .with_context(|| {
+            format!(
+                "open a destination file for layer '{}'",
+                temp_file_path.display()
+            )
+        })
+        .map_err(DownloadError::Other)?;
+    let mut offset = destination_file
+        .metadata()
+        .await
+        .with_context(|| format!("get the size of file {temp_file_path:?}"))
+        .map_err(DownloadError::Other)?
+        .len();
+    if offset > expected_size {
+        // Can't be a part of the layer file, start over
+        destination_file
+            .set_len(0)
+            .await
+            .with_context(|| format!("truncate file {temp_file_path:?}"))
+            .map_err(DownloadError::Other)?;
+        offset = 0;
+    }
+    if offset > 0 && offset == expected_size {
+        return Ok((destination_file, offset));
+    }
+
+    let download = if offset == 0 {
+        storage.download(remote_path).await
+    } else {
+        info!("resuming the download of {remote_path:?} from offset {offset}");
+        storage.download_byte_range(remote_path, offset, None).await
+    };
+    let mut download = download
+        .with_context(|| {
+            format!("open a download stream for layer with remote storage path '{remote_path:?}'")
+        })
+        .map_err(DownloadError::Other)?;
+
+    let copy = tokio::io::copy(&mut download.download_stream, &mut destination_file);
+    let copied = match tokio::time::timeout(MAX_DOWNLOAD_DURATION, copy).await {
+        Ok(res) => res
+            .with_context(|| {
+                format!("Failed to download layer with remote storage path '{remote_path:?}' into file {temp_file_path:?}")
+            })
+            .map_err(DownloadError::Other),
+        Err(e) => Err(DownloadError::Other(anyhow!("Timed out  {:?}", e))),
+    };
+    match copied {
+        Ok(copied) => Ok((destination_file, offset + copied)),
+        Err(e) => {
+            // Let the write in flight complete, for the next attempt to see the size it leaves
+            let _ = destination_file.flush().await;
+            Err(e)
+        }
+    }
+}
+
+/// Download a layer file into its temporary file with concurrent range requests, one for each
+/// of the `done.len()` chunks of the file, skipping the chunks that earlier attempts completed.
+/// Returns the file and its size.
+async fn download_chunks_to_temp_file(
+    storage: &GenericRemoteStorage,
+    remote_path: &RemotePath,
+    temp_file_path: &Path,
+    expected_size: u64,
+    done: &[AtomicBool],
+) -> Result<(fs::File, u64), DownloadError> {
+    let destination_file = fs::OpenOptions::new()
+        .write(true)
+        .open(temp_file_path)
+        .await
+        .with_context(|| {
+            format!(
+                "open a destination file for layer '{}'",
+                temp_file_path.display()
+            )
+        })
+        .map_err(DownloadError::Other)?;
+    destination_file
+        .set_len(expected_size)
+        .await
+        .with_context(|| format!("allocate file {temp_file_path:?}"))
+        .map_err(DownloadError::Other)?;
+
+    let chunk_size = (expected_size + done.len() as u64 - 1) / done.len() as u64;
+    let downloads = done
+        .iter()
+        .enumerate()
+        .filter(|(_, chunk_done)| !chunk_done.load(Ordering::Relaxed))
+        .map(|(i, chunk_done)| async move {
+            let start = i as u64 * chunk_size;
+            let end = (start + chunk_size).min(expected_size);
+            download_chunk(storage, remote_path, temp_file_path, start, end, expected_size)
+                .await?;
+            chunk_done.store(true, Ordering::Relaxed);
+            Ok::<_, DownloadError>(())
+        });
+    tokio::time::timeout(
+        MAX_DOWNLOAD_DURATION,
+        futures::future::try_join_all(downloads),
+    )
+    .await
+    .map_err(|e| DownloadError::Other(anyhow!("Timed out  {:?}", e)))??;
+
+    Ok((destination_file, expected_size))
+}
+
+/// Download the bytes from `start` to `end` of a layer file into the same range of its
+/// temporary file.
+async fn download_chunk(
+    storage: &GenericRemoteStorage,
+    remote_path: &RemotePath,
+    temp_file_path: &Path,
+    start: u64,
+    end: u64,
+    expected_size: u64,
+) -> Result<(), DownloadError> {
+    // The last chunk goes to the end of the file
+    let end_exclusive = if end < expected_size { Some(end) } else { None };
+    let download = storage
+        .download_byte_range(remote_path, start, end_exclusive)
+        .await
+        .with_context(|| {
+            format!("open a download stream for bytes {start}..{end} of layer with remote storage path '{remote_path:?}'")
+        })
+        .map_err(DownloadError::Other)?;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .open(temp_file_path)
+        .await
+        .with_context(|| format!("open file {temp_file_path:?}"))
+        .map_err(DownloadError::Other)?;
+    file.seek(SeekFrom::Start(start))
+        .await
+        .with_context(|| format!("seek to offset {start} of file {temp_file_path:?}"))
+        .map_err(DownloadError::Other)?;
+    let copied = tokio::io::copy(&mut download.download_stream.take(end - start), &mut file)
+        .await
+        .with_context(|| {
+            format!("Failed to download bytes {start}..{end} of layer with remote storage path '{remote_path:?}' into file {temp_file_path:?}")
+        })
+        .map_err(DownloadError::Other);
+    // Let the write in flight complete, before another attempt writes the chunk again
+    let flushed = file.flush().await;
+    let copied = copied?;
+    if copied != end - start {
+        return Err(DownloadError::Other(anyhow!(
+            "Downloaded {copied} bytes instead of {} for bytes {start}..{end} of layer with remote storage path '{remote_path:?}'",
+            end - start
+        )));
+    }
+    flushed
+        .with_context(|| format!("flush file {temp_file_path:?}"))
+        .map_err(DownloadError::Other)
+}
+
+/// Decrypt a downloaded layer file into another temporary file, returning it and its path.
+async fn decrypt_downloaded_layer(
+    conf: &'static PageServerConf,
+    tenant_id: TenantId,
+    encrypted_path: &Path,
+    local_path: &Path,
+    expected_size: u64,
+) -> anyhow::Result<(fs::File, PathBuf)> {
+    let key = encryption::tenant_key(conf, tenant_id)
+        .await?
+        .context("Layer file is encrypted, but no client-side encryption key is configured")?;
+
+    let decrypted_path = path_with_suffix_extension(local_path, TEMP_DECRYPTED_DOWNLOAD_SUFFIX);
+    let decrypted_size = encryption::decrypt_file(&key, encrypted_path, &decrypted_path)
+        .await
+        .with_context(|| format!("Failed to decrypt downloaded layer file {encrypted_path:?}"))?;
+    ensure!(
+        decrypted_size == expected_size,
+        "According to layer file metadata should have decrypted {expected_size} bytes but decrypted {decrypted_size} bytes into file {decrypted_path:?}",
+    );
+    fs::remove_file(encrypted_path)
+        .await
+        .with_context(|| format!("Failed to remove encrypted layer file {encrypted_path:?}"))?;
+
+    let decrypted_file = fs::File::open(&decrypted_path)
+        .await
+        .with_context(|| format!("Failed to open decrypted layer file {decrypted_path:?}"))?;
+    Ok((decrypted_file, decrypted_path))
+}
+
+/// Decompress a downloaded layer file into another temporary file, returning it and its path.
+async fn decompress_downloaded_layer(
+    compression: LayerCompression,
+    compressed_path: &Path,
+    local_path: &Path,
+    expected_size: u64,
+) -> anyhow::Result<(fs::File, PathBuf)> {
+    let decompressed_path =
+        path_with_suffix_extension(local_path, TEMP_DECOMPRESSED_DOWNLOAD_SUFFIX);
+    let decompressed_size =
+        compression::decompress_file(compression, compressed_path, &decompressed_path)
+            .await
+            .with_context(|| {
+                format!("Failed to decompress downloaded layer file {compressed_path:?}")
+            })?;
+    ensure!(
+        decompressed_size == expected_size,
+        "According to layer file metadata should have decompressed {expected_size} bytes but decompressed {decompressed_size} bytes into file {decompressed_path:?}",
+    );
+    fs::remove_file(compressed_path)
+        .await
+        .with_context(|| format!("Failed to remove compressed layer file {compressed_path:?}"))?;
+
+    let decompressed_file = fs::File::open(&decompressed_path)
+        .await
+        .with_context(|| format!("Failed to open decompressed layer file {decompressed_path:?}"))?;
+    Ok((decompressed_file, decompressed_path))
 }
 
 const TEMP_DOWNLOAD_EXTENSION: &str = "temp_download";
+/// Decrypted and decompressed layer files are temporary download files too.
+const TEMP_DECRYPTED_DOWNLOAD_SUFFIX: &str = "decrypted.temp_download";
+const TEMP_DECOMPRESSED_DOWNLOAD_SUFFIX: &str = "decompressed.temp_download";
 
 pub fn is_temp_download_file(path: &Path) -> bool {
     let extension = path.extension().map(|pname| {
@@ -182,6 +519,7 @@ pub async fn list_remote_timelines<'a>(
     storage: &'a GenericRemoteStorage,
     conf: &'static PageServerConf,
     tenant_id: TenantId,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<HashSet<TimelineId>> {
     let tenant_path = conf.timelines_path(&tenant_id);
     let tenant_storage_path = conf.remote_path(&tenant_path)?;
@@ -191,8 +529,13 @@ pub async fn list_remote_timelines<'a>(
     });
 
     let timelines = download_retry(
-        || storage.list_prefixes(Some(&tenant_storage_path)),
+        &conf.remote_retry.download,
+        || async {
+            let _permit = listing::list_permit(conf).await;
+            storage.list_prefixes(Some(&tenant_storage_path)).await
+        },
         &format!("list prefixes for {tenant_path:?}"),
+        cancel,
     )
     .await?;
 
@@ -226,64 +569,205 @@ pub async fn list_remote_timelines<'a>(
     Ok(timeline_ids)
 }
 
+/// The size and checksum of the layer file at `remote_path`, see
+/// [`LayerFileMetadata::remote_path`], `None` if it doesn't exist.
+pub(super) async fn head_layer_file(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    remote_path: &RemotePath,
+    cancel: &CancellationToken,
+) -> anyhow::Result<Option<ObjectHead>> {
+    let head = download_retry(
+        &conf.remote_retry.download,
+        || storage.head_object(remote_path),
+        &format!("head {remote_path:?}"),
+        cancel,
+    )
+    .await;
+    match head {
+        Ok(head) => Ok(Some(head)),
+        Err(DownloadError::NotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Download the index file of the timeline.
+///
+/// With a generation, this is the index file of the latest generation, which must not be later
+/// than ours, or the one written without a generation if there is none yet.
+///
+/// The index file may be the manifest of a chunked index, which is put back together from its
+/// segments, also returned.
+///
+/// Also returns the precondition for the next upload of our index file to overwrite only what
+/// has been read here, if the storage has entity tags: the version downloaded if the index file
+/// is ours, or none if our generation hasn't written one yet.
 pub(super) async fn download_index_part(
     conf: &'static PageServerConf,
     storage: &GenericRemoteStorage,
     tenant_id: &TenantId,
     timeline_id: &TimelineId,
-) -> Result<IndexPart, DownloadError> {
-    let index_part_path = conf
+    generation: Option<Generation>,
+    cancel: &CancellationToken,
+) -> Result<(IndexPart, IndexSegments, Option<UploadPrecondition>), DownloadError> {
+    let legacy_index_part_path = conf
         .metadata_path(tenant_id, timeline_id)
         .with_file_name(IndexPart::FILE_NAME);
+    let own_index_part_path =
+        legacy_index_part_path.with_file_name(IndexPart::file_name(generation));
+    let legacy_storage_path = conf
+        .remote_path(&legacy_index_part_path)
+        .map_err(DownloadError::BadInput)?;
+
+    let index_part_path = match generation {
+        None => legacy_index_part_path,
+        Some(generation) => {
+            let generations = download_retry(
+                &conf.remote_retry.download,
+                || async {
+                    let _permit = listing::list_permit(conf).await;
+                    list_index_generations(storage, &legacy_storage_path)
+                        .await
+                        .map_err(DownloadError::Other)
+                },
+                &format!("list index files {legacy_storage_path:?}"),
+                cancel,
+            )
+            .await?;
+            // The index file without a generation sorts first
+            match generations.into_iter().max() {
+                None => return Err(DownloadError::NotFound),
+                Some(Some(latest)) if latest > generation => {
+                    let stale = StaleGenerationError {
+                        current: generation,
+                        latest,
+                    };
+                    return Err(DownloadError::Other(stale.into()));
+                }
+                Some(latest) => legacy_index_part_path.with_file_name(IndexPart::file_name(latest)),
+            }
+        }
+    };
     let part_storage_path = conf
         .remote_path(&index_part_path)
         .map_err(DownloadError::BadInput)?;
 
-    let index_part_bytes = download_retry(
-        || async {
-            let mut index_part_download = storage.download(&part_storage_path).await?;
+    let (index_part_bytes, etag) =
+        download_bytes(conf, storage, &part_storage_path, cancel).await?;
+    let precondition = if index_part_path == own_index_part_path {
+        etag.map(UploadPrecondition::IfMatch)
+    } else {
+        Some(UploadPrecondition::IfNoneMatch)
+    };
 
-            let mut index_part_bytes = Vec::new();
-            tokio::io::copy(
-                &mut index_part_download.download_stream,
-                &mut index_part_bytes,
-            )
-            .await
+    let manifest = IndexManifest::from_json_bytes(&index_part_bytes)
+        .with_context(|| {
+            format!("Failed to deserialize index part file into file {index_part_path:?}")
+        })
+        .map_err(DownloadError::Other)?;
+    let Some(manifest) = manifest else {
+        let index_part = IndexPart::from_json_bytes(&index_part_bytes)
             .with_context(|| {
-                format!("Failed to download an index part into file {index_part_path:?}")
+                format!("Failed to deserialize index part file into file {index_part_path:?}")
             })
             .map_err(DownloadError::Other)?;
-            Ok(index_part_bytes)
+        return Ok((index_part, IndexSegments::default(), precondition));
+    };
+
+    let mut segments = Vec::with_capacity(manifest.segments.len());
+    for segment_ref in &manifest.segments {
+        let segment_path = index_part_path.with_file_name(segment_ref.file_name());
+        let segment_storage_path = conf
+            .remote_path(&segment_path)
+            .map_err(DownloadError::BadInput)?;
+        // Not the index missing, which the callers take for a timeline that doesn't exist
+        let (bytes, _) = download_bytes(conf, storage, &segment_storage_path, cancel)
+            .await
+            .map_err(|e| match e {
+                DownloadError::NotFound => {
+                    DownloadError::Other(anyhow!("index segment {segment_path:?} is missing"))
+                }
+                e => e,
+            })?;
+        let segment: IndexSegment = serde_json::from_slice(&bytes)
+            .with_context(|| format!("Failed to deserialize index segment {segment_path:?}"))
+            .map_err(DownloadError::Other)?;
+        segments.push(segment);
+    }
+    let (index_part, segments) = manifest
+        .into_index_part(segments)
+        .with_context(|| format!("Failed to read chunked index {index_part_path:?}"))
+        .map_err(DownloadError::Other)?;
+    Ok((index_part, segments, precondition))
+}
+
+/// Download a small object into memory, with its entity tag.
+pub(super) async fn download_bytes(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    storage_path: &RemotePath,
+    cancel: &CancellationToken,
+) -> Result<(Vec<u8>, Option<String>), DownloadError> {
+    download_retry(
+        &conf.remote_retry.download,
+        || async {
+            let mut download = storage.download(storage_path).await?;
+
+            let mut bytes = Vec::new();
+            tokio::io::copy(&mut download.download_stream, &mut bytes)
+                .await
+                .with_context(|| format!("Failed to download {storage_path:?}"))
+                .map_err(DownloadError::Other)?;
+            Ok((bytes, download.etag))
         },
-        &format!("download {part_storage_path:?}"),
+        &format!("download {storage_path:?}"),
+        cancel,
     )
-    .await?;
-
-    let index_part: IndexPart = serde_json::from_slice(&index_part_bytes)
-        .with_context(|| {
-            format!("Failed to deserialize index part file into file {index_part_path:?}")
-        })
-        .map_err(DownloadError::Other)?;
+    .await
+}
 
-    Ok(index_part)
+/// The generations of the index files of a timeline, given the path of its index file without
+/// a generation. `None` stands for that index file.
+pub(super) async fn list_index_generations(
+    storage: &GenericRemoteStorage,
+    legacy_storage_path: &RemotePath,
+) -> anyhow::Result<Vec<Option<Generation>>> {
+    let objects = storage
+        .list_files(Some(legacy_storage_path))
+        .await
+        .with_context(|| format!("list index files {legacy_storage_path:?}"))?;
+    Ok(objects
+        .iter()
+        .filter_map(|path| path.object_name())
+        .filter_map(IndexPart::parse_file_name)
+        .collect())
 }
 
 ///
 /// Helper function to handle retries for a download operation.
 ///
 /// Remote operations can fail due to rate limits (IAM, S3), spurious network
-/// problems, or other external reasons. Retry FAILED_DOWNLOAD_RETRIES times,
-/// with backoff.
+/// problems, or other external reasons. Retry after the policy, with backoff.
 ///
 /// (See similar logic for uploads in `perform_upload_task`)
-async fn download_retry<T, O, F>(mut op: O, description: &str) -> Result<T, DownloadError>
+pub(super) async fn download_retry<T, O, F>(
+    policy: &RetryPolicy,
+    mut op: O,
+    description: &str,
+    cancel: &CancellationToken,
+) -> Result<T, DownloadError>
 where
     O: FnMut() -> F,
     F: Future<Output = Result<T, DownloadError>>,
 {
     let mut attempts = 0;
     loop {
-        let result = op().await;
+        // Dropping the attempt aborts its request, and frees its remote storage permit
+        let result = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return Err(DownloadError::Cancelled),
+            result = op() => result,
+        };
         match result {
             Ok(_) => {
                 if attempts > 0 {
@@ -293,30 +777,151 @@ where
             }
 
             // These are "permanent" errors that should not be retried.
-            Err(DownloadError::BadInput(_)) | Err(DownloadError::NotFound) => {
+            Err(DownloadError::BadInput(_))
+            | Err(DownloadError::NotFound)
+            | Err(DownloadError::Cancelled) => {
                 return result;
             }
             // Assume that any other failure might be transient, and the operation might
             // succeed if we just keep trying.
-            Err(DownloadError::Other(err)) if attempts < FAILED_DOWNLOAD_WARN_THRESHOLD => {
+            Err(DownloadError::Other(ref err)) if policy.gives_up(attempts) => {
+                // Out of attempts. Time to give up.
+                warn!("{description} still failed after {attempts} retries, giving up: {err:?}");
+                return result;
+            }
+            Err(DownloadError::Other(err)) if !policy.warns(attempts) => {
                 info!("{description} failed, will retry (attempt {attempts}): {err:#}");
             }
-            Err(DownloadError::Other(err)) if attempts < FAILED_DOWNLOAD_RETRIES => {
+            Err(DownloadError::Other(err)) => {
                 warn!("{description} failed, will retry (attempt {attempts}): {err:#}");
             }
-            Err(DownloadError::Other(ref err)) => {
-                // Operation failed FAILED_DOWNLOAD_RETRIES times. Time to give up.
-                warn!("{description} still failed after {attempts} retries, giving up: {err:?}");
-                return result;
-            }
         }
         // sleep and retry
-        exponential_backoff(
-            attempts,
-            DEFAULT_BASE_BACKOFF_SECONDS,
-            DEFAULT_MAX_BACKOFF_SECONDS,
+        tokio::select! {
+            _ = cancel.cancelled() => return Err(DownloadError::Cancelled),
+            _ = policy.backoff(attempts) => {}
+        }
+        attempts += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use remote_storage::LocalFs;
+
+    #[tokio::test]
+    async fn download_to_temp_file_resumes() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let storage = GenericRemoteStorage::LocalFs(LocalFs::new(dir.path().join("remote"))?);
+        let remote_path = RemotePath::new(Path::new("layer"))?;
+        let contents = (0..=255u8).cycle().take(10_000).collect::<Vec<_>>();
+        std::fs::write(dir.path().join("remote").join("layer"), &contents)?;
+        let temp_file_path = dir.path().join("layer.temp_download");
+        let expected_size = contents.len() as u64;
+
+        // Continues after the part written by an earlier attempt
+        std::fs::write(&temp_file_path, &contents[..4000])?;
+        let (mut file, size) =
+            download_to_temp_file(&storage, &remote_path, &temp_file_path, expected_size).await?;
+        file.flush().await?;
+        assert_eq!(size, expected_size);
+        assert_eq!(std::fs::read(&temp_file_path)?, contents);
+
+        // Nothing left to download
+        let (_, size) =
+            download_to_temp_file(&storage, &remote_path, &temp_file_path, expected_size).await?;
+        assert_eq!(size, expected_size);
+
+        // Starts over from a file that can't be a part of the layer file
+        std::fs::write(&temp_file_path, vec![0; contents.len() + 1])?;
+        let (mut file, size) =
+            download_to_temp_file(&storage, &remote_path, &temp_file_path, expected_size).await?;
+        file.flush().await?;
+        assert_eq!(size, expected_size);
+        assert_eq!(std::fs::read(&temp_file_path)?, contents);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn download_chunks_to_temp_file_skips_done() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let storage = GenericRemoteStorage::LocalFs(LocalFs::new(dir.path().join("remote"))?);
+        let remote_path = RemotePath::new(Path::new("layer"))?;
+        let size = 3 * MIN_DOWNLOAD_CHUNK_SIZE as usize + 5;
+        let contents = (0..=255u8).cycle().take(size).collect::<Vec<_>>();
+        std::fs::write(dir.path().join("remote").join("layer"), &contents)?;
+        let temp_file_path = dir.path().join("layer.temp_download");
+        std::fs::write(&temp_file_path, b"")?;
+
+        let config = ParallelDownloadConfig {
+            min_size: MIN_DOWNLOAD_CHUNK_SIZE,
+            chunks: NonZeroUsize::new(8).unwrap(),
+        };
+        assert_eq!(config.chunks_for(MIN_DOWNLOAD_CHUNK_SIZE - 1), 1);
+        let chunks = config.chunks_for(size as u64);
+        assert_eq!(chunks, 3);
+
+        // The chunk completed by an earlier attempt isn't downloaded again
+        let done = (0..chunks)
+            .map(|i| AtomicBool::new(i == 1))
+            .collect::<Vec<_>>();
+        let (_, downloaded) = download_chunks_to_temp_file(
+            &storage,
+            &remote_path,
+            &temp_file_path,
+            size as u64,
+            &done,
+        )
+        .await?;
+        assert_eq!(downloaded, size as u64);
+        assert!(done.iter().all(|chunk_done| chunk_done.load(Ordering::Relaxed)));
+        let chunk_size = (size + chunks - 1) / chunks;
+        let written = std::fs::read(&temp_file_path)?;
+        assert_eq!(written.len(), size);
+        assert_eq!(written[..chunk_size], contents[..chunk_size]);
+        assert!(written[chunk_size..2 * chunk_size].iter().all(|b| *b == 0));
+        assert_eq!(written[2 * chunk_size..], contents[2 * chunk_size..]);
+
+        let done = (0..chunks)
+            .map(|_| AtomicBool::new(false))
+            .collect::<Vec<_>>();
+        download_chunks_to_temp_file(
+            &storage,
+            &remote_path,
+            &temp_file_path,
+            size as u64,
+            &done,
+        )
+        .await?;
+        assert_eq!(std::fs::read(&temp_file_path)?, contents);
+
+        Ok(())
+    }
+    #[tokio::test]
+    async fn download_retry_cancelled() {
+        let cancel = CancellationToken::new();
+        let policy = RetryPolicy {
+            max_attempts: 0,
+            ..RetryPolicy::download()
+        };
+        let mut attempts = 0;
+        let result: Result<(), _> = download_retry(
+            &policy,
+            || {
+                attempts += 1;
+                if attempts == 2 {
+                    cancel.cancel();
+                }
+                async { Err(DownloadError::Other(anyhow!("failed"))) }
+            },
+            "download",
+            &cancel,
         )
         .await;
-        attempts += 1;
+        // The attempt in flight is dropped
+        assert!(matches!(result, Err(DownloadError::Cancelled)));
+        assert_eq!(attempts, 2);
     }
 }
@@ -0,0 +1,323 @@
+//! Encryption of the layer files stored remotely.
+//!
+//! With `remote_encryption = { mode = "client_side", key_file = .. }`, the layer files are
+//! encrypted before upload, with a key derived for each tenant from the master key in the key
+//! file, and decrypted after download. The index files are not encrypted: they record which
+//! layers are, see [`LayerEncryption`].
+//!
+//! An encrypted layer file is a header, made of a magic and a random nonce prefix, followed by
+//! the layer file in chunks of [`CHUNK_SIZE`] bytes, each sealed with AES-256-GCM. The nonce of
+//! a chunk is the nonce prefix and the chunk number, and the associated data of the last chunk
+//! marks it as such, so that reordering or truncating the chunks fails the decryption.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, ensure, Context};
+use rand::Rng;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hkdf;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use utils::id::TenantId;
+
+use crate::config::PageServerConf;
+
+const MAGIC: &[u8; 8] = b"NEONENC1";
+const NONCE_PREFIX_LEN: usize = NONCE_LEN - 4;
+const HEADER_LEN: usize = MAGIC.len() + NONCE_PREFIX_LEN;
+const CHUNK_SIZE: usize = 1024 * 1024;
+const TAG_LEN: usize = 16;
+
+const KEY_DERIVATION_SALT: &[u8] = b"pageserver remote layer encryption";
+
+/// The `remote_encryption` setting.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case", deny_unknown_fields)]
+pub enum RemoteEncryptionConfig {
+    #[default]
+    None,
+    /// Have the remote storage encrypt the objects, with the given KMS key (SSE-KMS) or with
+    /// keys managed by S3 (SSE-S3).
+    ServerSide {
+        #[serde(default)]
+        kms_key_id: Option<String>,
+    },
+    /// Encrypt the layer files before upload. `key_file` holds the hex-encoded 256-bit master
+    /// key, from which the keys of the tenants are derived.
+    ClientSide { key_file: PathBuf },
+}
+
+impl RemoteEncryptionConfig {
+    /// Check that the master key of client-side encryption can be loaded.
+    pub fn check_key_file(&self, conf: &PageServerConf) -> anyhow::Result<()> {
+        if let RemoteEncryptionConfig::ClientSide { key_file } = self {
+            let key_file = conf.workdir.join(key_file);
+            let contents = std::fs::read_to_string(&key_file)
+                .with_context(|| format!("read encryption key file {}", key_file.display()))?;
+            parse_master_key(&contents)
+                .with_context(|| format!("parse encryption key file {}", key_file.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// How a layer file stored remotely is encrypted, recorded in the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerEncryption {
+    /// AES-256-GCM in chunks, with the key of the tenant.
+    ChunkedAes256Gcm,
+}
+
+impl LayerEncryption {
+    /// Size of the encrypted form of a layer file of `file_size` bytes.
+    pub fn encrypted_size(self, file_size: u64) -> u64 {
+        match self {
+            LayerEncryption::ChunkedAes256Gcm => {
+                let chunks = chunk_count(file_size, CHUNK_SIZE as u64);
+                HEADER_LEN as u64 + file_size + chunks * TAG_LEN as u64
+            }
+        }
+    }
+}
+
+/// The key of the layer files of a tenant.
+pub(super) struct TenantKey(LessSafeKey);
+
+/// The key of the layer files of the tenant, if they are encrypted client-side.
+pub(super) async fn tenant_key(
+    conf: &PageServerConf,
+    tenant_id: TenantId,
+) -> anyhow::Result<Option<TenantKey>> {
+    let RemoteEncryptionConfig::ClientSide { key_file } = &conf.remote_encryption else {
+        return Ok(None);
+    };
+    let key_file = conf.workdir.join(key_file);
+    let contents = fs::read_to_string(&key_file)
+        .await
+        .with_context(|| format!("read encryption key file {}", key_file.display()))?;
+    let master_key = parse_master_key(&contents)
+        .with_context(|| format!("parse encryption key file {}", key_file.display()))?;
+    Ok(Some(derive_tenant_key(&master_key, tenant_id)))
+}
+
+fn parse_master_key(contents: &str) -> anyhow::Result<[u8; 32]> {
+    let key = hex::decode(contents.trim()).context("key is not hex-encoded")?;
+    <[u8; 32]>::try_from(key).map_err(|key| anyhow!("key is {} bytes, not 32", key.len()))
+}
+
+fn derive_tenant_key(master_key: &[u8; 32], tenant_id: TenantId) -> TenantKey {
+    let tenant_id = tenant_id.as_arr();
+    let info = [&tenant_id[..]];
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, KEY_DERIVATION_SALT).extract(master_key);
+    let okm = prk
+        .expand(&info, &AES_256_GCM)
+        .expect("AES-256 key length is valid for HKDF-SHA256");
+    TenantKey(LessSafeKey::new(UnboundKey::from(okm)))
+}
+
+fn chunk_count(size: u64, chunk_size: u64) -> u64 {
+    // An empty file still has a chunk, to mark the end
+    std::cmp::max(1, (size + chunk_size - 1) / chunk_size)
+}
+
+fn chunk_nonce(nonce_prefix: &[u8; NONCE_PREFIX_LEN], chunk: u64) -> anyhow::Result<Nonce> {
+    let chunk = u32::try_from(chunk).context("too many chunks")?;
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(nonce_prefix);
+    nonce[NONCE_PREFIX_LEN..].copy_from_slice(&chunk.to_be_bytes());
+    Ok(Nonce::assume_unique_for_key(nonce))
+}
+
+fn chunk_aad(last: bool) -> Aad<[u8; 1]> {
+    Aad::from([u8::from(last)])
+}
+
+/// Write the encrypted form of the file at `source_path` to `dest_path`.
+pub(super) async fn encrypt_file(
+    key: &TenantKey,
+    source_path: &Path,
+    dest_path: &Path,
+) -> anyhow::Result<()> {
+    let mut source = fs::File::open(source_path)
+        .await
+        .with_context(|| format!("open {}", source_path.display()))?;
+    let file_size = source.metadata().await?.len();
+    let mut dest = BufWriter::new(
+        fs::File::create(dest_path)
+            .await
+            .with_context(|| format!("create {}", dest_path.display()))?,
+    );
+
+    let mut nonce_prefix = [0; NONCE_PREFIX_LEN];
+    rand::thread_rng().fill(&mut nonce_prefix);
+    dest.write_all(MAGIC).await?;
+    dest.write_all(&nonce_prefix).await?;
+
+    let chunks = chunk_count(file_size, CHUNK_SIZE as u64);
+    let mut remaining = file_size;
+    let mut buf = Vec::with_capacity(CHUNK_SIZE + TAG_LEN);
+    for chunk in 0..chunks {
+        let len = std::cmp::min(remaining, CHUNK_SIZE as u64) as usize;
+        buf.resize(len, 0);
+        source
+            .read_exact(&mut buf)
+            .await
+            .with_context(|| format!("read {}", source_path.display()))?;
+        remaining -= len as u64;
+
+        let nonce = chunk_nonce(&nonce_prefix, chunk)?;
+        key.0
+            .seal_in_place_append_tag(nonce, chunk_aad(chunk + 1 == chunks), &mut buf)
+            .map_err(|_| anyhow!("failed to encrypt chunk {chunk}"))?;
+        dest.write_all(&buf).await?;
+    }
+    dest.flush().await?;
+    Ok(())
+}
+
+/// Write the decrypted form of the file at `source_path` to `dest_path`, returning its size.
+pub(super) async fn decrypt_file(
+    key: &TenantKey,
+    source_path: &Path,
+    dest_path: &Path,
+) -> anyhow::Result<u64> {
+    let mut source = fs::File::open(source_path)
+        .await
+        .with_context(|| format!("open {}", source_path.display()))?;
+    let file_size = source.metadata().await?.len();
+    ensure!(
+        file_size >= HEADER_LEN as u64,
+        "encrypted file {} is too short",
+        source_path.display()
+    );
+    let mut dest = BufWriter::new(
+        fs::File::create(dest_path)
+            .await
+            .with_context(|| format!("create {}", dest_path.display()))?,
+    );
+
+    let mut magic = [0; MAGIC.len()];
+    let mut nonce_prefix = [0; NONCE_PREFIX_LEN];
+    source.read_exact(&mut magic).await?;
+    source.read_exact(&mut nonce_prefix).await?;
+    ensure!(
+        &magic == MAGIC,
+        "{} is not an encrypted layer file",
+        source_path.display()
+    );
+
+    let sealed_chunk_size = (CHUNK_SIZE + TAG_LEN) as u64;
+    let mut remaining = file_size - HEADER_LEN as u64;
+    let chunks = chunk_count(remaining, sealed_chunk_size);
+    let mut decrypted_size = 0;
+    let mut buf = Vec::with_capacity(CHUNK_SIZE + TAG_LEN);
+    for chunk in 0..chunks {
+        let len = std::cmp::min(remaining, sealed_chunk_size) as usize;
+        buf.resize(len, 0);
+        source
+            .read_exact(&mut buf)
+            .await
+            .with_context(|| format!("read {}", source_path.display()))?;
+        remaining -= len as u64;
+
+        let nonce = chunk_nonce(&nonce_prefix, chunk)?;
+        let decrypted = key
+            .0
+            .open_in_place(nonce, chunk_aad(chunk + 1 == chunks), &mut buf)
+            .map_err(|_| {
+                anyhow!(
+                    "chunk {chunk} of {} failed to decrypt, it is corrupted or was encrypted with another key",
+                    source_path.display()
+                )
+            })?;
+        dest.write_all(decrypted).await?;
+        decrypted_size += decrypted.len() as u64;
+    }
+    dest.flush().await?;
+    Ok(decrypted_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER_KEY: [u8; 32] = [7; 32];
+
+    fn tenant_key(tenant_id: &str) -> TenantKey {
+        derive_tenant_key(&MASTER_KEY, tenant_id.parse().unwrap())
+    }
+
+    #[test]
+    fn parse_key_file() {
+        let key = parse_master_key(&format!("{}\n", hex::encode(MASTER_KEY))).unwrap();
+        assert_eq!(key, MASTER_KEY);
+        assert!(parse_master_key("not hex").is_err());
+        assert!(parse_master_key(&hex::encode([7; 16])).is_err());
+    }
+
+    #[tokio::test]
+    async fn round_trip() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let key = tenant_key("11000000000000000000000000000000");
+        for size in [0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, 2 * CHUNK_SIZE + 1] {
+            let contents: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+            let path = dir.path().join("layer");
+            let encrypted_path = dir.path().join("encrypted");
+            let decrypted_path = dir.path().join("decrypted");
+            std::fs::write(&path, &contents)?;
+
+            encrypt_file(&key, &path, &encrypted_path).await?;
+            let encrypted = std::fs::read(&encrypted_path)?;
+            let expected_size = LayerEncryption::ChunkedAes256Gcm.encrypted_size(size as u64);
+            assert_eq!(encrypted.len() as u64, expected_size);
+            if size >= 64 {
+                assert!(!encrypted.windows(size).any(|w| w == contents));
+            }
+
+            let decrypted_size = decrypt_file(&key, &encrypted_path, &decrypted_path).await?;
+            assert_eq!(decrypted_size, size as u64);
+            assert_eq!(std::fs::read(&decrypted_path)?, contents);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn corruption_is_detected() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let key = tenant_key("11000000000000000000000000000000");
+        let path = dir.path().join("layer");
+        let encrypted_path = dir.path().join("encrypted");
+        let decrypted_path = dir.path().join("decrypted");
+        std::fs::write(&path, vec![1; 2 * CHUNK_SIZE + 100])?;
+        encrypt_file(&key, &path, &encrypted_path).await?;
+        let encrypted = std::fs::read(&encrypted_path)?;
+
+        // A flipped bit
+        let mut corrupted = encrypted.clone();
+        corrupted[HEADER_LEN + CHUNK_SIZE + 10] ^= 1;
+        std::fs::write(&encrypted_path, &corrupted)?;
+        assert!(decrypt_file(&key, &encrypted_path, &decrypted_path)
+            .await
+            .is_err());
+
+        // Truncated at a chunk boundary
+        let truncated = &encrypted[..HEADER_LEN + 2 * (CHUNK_SIZE + TAG_LEN)];
+        std::fs::write(&encrypted_path, truncated)?;
+        assert!(decrypt_file(&key, &encrypted_path, &decrypted_path)
+            .await
+            .is_err());
+
+        // The key of another tenant
+        std::fs::write(&encrypted_path, &encrypted)?;
+        let other_key = tenant_key("22000000000000000000000000000000");
+        assert!(decrypt_file(&other_key, &encrypted_path, &decrypted_path)
+            .await
+            .is_err());
+        assert!(decrypt_file(&key, &encrypted_path, &decrypted_path)
+            .await
+            .is_ok());
+        Ok(())
+    }
+}
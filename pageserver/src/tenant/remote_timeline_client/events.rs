@@ -0,0 +1,269 @@
+//! Structured events of the upload queues, for the control plane to track the remote
+//! consistent LSN of the timelines without polling each pageserver.
+//!
+//! An [`UploadEventSink`] receives an event whenever an upload queue has completed an
+//! operation in the remote storage: a layer file uploaded, an index uploaded, with the
+//! `disk_consistent_lsn` that is now the remote consistent LSN of the timeline, or layer files
+//! deleted. The `upload_events` setting installs one of the built-in sinks at startup, that
+//! logs the events or POSTs them to a webhook. Other consumers, e.g. one publishing to the
+//! storage broker, implement the trait and are installed with [`init`].
+//!
+//! The events are best effort: the webhook drops them when it can't keep up, and the ones
+//! queued at a shutdown are lost. The latest index event of a timeline supersedes the earlier
+//! ones, so a consumer catches up with the next index upload.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use once_cell::sync::OnceCell;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use tokio::sync::mpsc;
+use tracing::{error, info, info_span, trace, warn, Instrument};
+use utils::id::{TenantId, TimelineId};
+use utils::lsn::Lsn;
+use utils::warn_rate_limited;
+
+use crate::config::PageServerConf;
+use crate::metrics::UPLOAD_EVENTS_DROPPED;
+use crate::task_mgr::{self, TaskKind, BACKGROUND_RUNTIME};
+use crate::tenant::generation::Generation;
+use crate::tenant::storage_layer::LayerFileName;
+use crate::{exponential_backoff, DEFAULT_BASE_BACKOFF_SECONDS, DEFAULT_MAX_BACKOFF_SECONDS};
+
+const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Events queued for the webhook, beyond which they are dropped.
+const WEBHOOK_QUEUE_CAPACITY: usize = 10_000;
+
+/// Events POSTed to the webhook in one request, at most.
+const MAX_WEBHOOK_BATCH: usize = 1000;
+
+/// Number of attempts to POST a batch of events before dropping it.
+const MAX_WEBHOOK_ATTEMPTS: u32 = 3;
+
+/// The `upload_events` setting.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "sink", rename_all = "snake_case")]
+pub enum UploadEventsConfig {
+    /// Log the events at info level.
+    Log,
+    /// POST the events to the endpoint, as JSON arrays.
+    Webhook { endpoint: Url },
+}
+
+/// An operation completed by the upload queue of a timeline.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UploadQueueEvent {
+    #[serde_as(as = "DisplayFromStr")]
+    pub tenant_id: TenantId,
+    #[serde_as(as = "DisplayFromStr")]
+    pub timeline_id: TimelineId,
+    /// The generation the tenant is attached with, see [`Generation`].
+    pub generation: Option<Generation>,
+    #[serde(flatten)]
+    pub kind: UploadQueueEventKind,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum UploadQueueEventKind {
+    /// The layer file is in the remote storage. The index referencing it may be uploaded later.
+    LayerUploaded {
+        layer_file_name: LayerFileName,
+        file_size: u64,
+    },
+    /// The index is in the remote storage, the timeline can be restored up to the LSN.
+    IndexUploaded {
+        #[serde_as(as = "DisplayFromStr")]
+        disk_consistent_lsn: Lsn,
+    },
+    /// The layer files, no longer referenced from the index, are deleted.
+    LayersDeleted { layer_file_names: Vec<LayerFileName> },
+}
+
+/// A consumer of the events of all the upload queues.
+pub trait UploadEventSink: Send + Sync {
+    /// Called from the upload tasks, right after the operation has completed: must not block.
+    fn emit(&self, event: UploadQueueEvent);
+}
+
+static UPLOAD_EVENT_SINK: OnceCell<Arc<dyn UploadEventSink>> = OnceCell::new();
+
+///
+/// Install the consumer of the upload queue events. This must be called at most once, at page
+/// server startup, before any timeline is loaded.
+///
+pub fn init(sink: Arc<dyn UploadEventSink>) {
+    if UPLOAD_EVENT_SINK.set(sink).is_err() {
+        panic!("upload event sink already initialized");
+    }
+}
+
+/// Install the sink configured with `upload_events`, if any.
+pub fn init_configured_sink(conf: &'static PageServerConf) -> anyhow::Result<()> {
+    let Some(config) = &conf.upload_events else {
+        return Ok(());
+    };
+    let sink: Arc<dyn UploadEventSink> = match config {
+        UploadEventsConfig::Log => Arc::new(LogSink),
+        UploadEventsConfig::Webhook { endpoint } => Arc::new(WebhookSink::launch(endpoint)?),
+    };
+    init(sink);
+    Ok(())
+}
+
+/// The installed consumer, `None` if there is none.
+pub(crate) fn sink() -> Option<Arc<dyn UploadEventSink>> {
+    UPLOAD_EVENT_SINK.get().cloned()
+}
+
+/// Logs the events as JSON.
+pub struct LogSink;
+
+impl UploadEventSink for LogSink {
+    fn emit(&self, event: UploadQueueEvent) {
+        match serde_json::to_string(&event) {
+            Ok(json) => info!("upload queue event: {json}"),
+            Err(e) => warn!("failed to serialize upload queue event {event:?}: {e}"),
+        }
+    }
+}
+
+/// Queues the events for a background task that POSTs them to an HTTP endpoint, in batches.
+pub struct WebhookSink {
+    sender: mpsc::Sender<UploadQueueEvent>,
+}
+
+impl WebhookSink {
+    fn launch(endpoint: &'static Url) -> anyhow::Result<Self> {
+        let client = reqwest::ClientBuilder::new()
+            .timeout(DEFAULT_HTTP_TIMEOUT)
+            .build()
+            .context("create http client")?;
+        let (sender, receiver) = mpsc::channel(WEBHOOK_QUEUE_CAPACITY);
+
+        task_mgr::spawn(
+            BACKGROUND_RUNTIME.handle(),
+            TaskKind::UploadEvents,
+            None,
+            None,
+            "upload events webhook",
+            false,
+            async move {
+                webhook_loop(receiver, client, endpoint)
+                    .instrument(info_span!("upload_events_webhook"))
+                    .await;
+                Ok(())
+            },
+        );
+        Ok(WebhookSink { sender })
+    }
+}
+
+impl UploadEventSink for WebhookSink {
+    fn emit(&self, event: UploadQueueEvent) {
+        if let Err(e) = self.sender.try_send(event) {
+            // The queue is full, or the task has exited at shutdown
+            warn_rate_limited!(UPLOAD_EVENTS_DROPPED, "dropped upload queue event: {e}");
+        }
+    }
+}
+
+/// Main loop of the webhook task.
+async fn webhook_loop(
+    mut receiver: mpsc::Receiver<UploadQueueEvent>,
+    client: reqwest::Client,
+    endpoint: &Url,
+) {
+    info!(%endpoint, "starting upload events webhook");
+    loop {
+        let event = tokio::select! {
+            _ = task_mgr::shutdown_watcher() => break,
+            event = receiver.recv() => event,
+        };
+        let Some(event) = event else {
+            break;
+        };
+        let mut batch = vec![event];
+        while batch.len() < MAX_WEBHOOK_BATCH {
+            match receiver.try_recv() {
+                Ok(event) => batch.push(event),
+                Err(_) => break,
+            }
+        }
+        post_events(&client, endpoint, &batch).await;
+    }
+    info!("upload events webhook received cancellation request");
+}
+
+/// POST a batch of events, retrying failed attempts with a backoff.
+///
+/// Errors are logged, not returned: the batch is dropped and the loop goes on.
+async fn post_events(client: &reqwest::Client, endpoint: &Url, batch: &[UploadQueueEvent]) {
+    for attempt in 0..MAX_WEBHOOK_ATTEMPTS {
+        if attempt > 0 {
+            tokio::select! {
+                _ = task_mgr::shutdown_watcher() => return,
+                _ = exponential_backoff(
+                    attempt,
+                    DEFAULT_BASE_BACKOFF_SECONDS,
+                    DEFAULT_MAX_BACKOFF_SECONDS,
+                ) => {}
+            }
+        }
+
+        match client.post(endpoint.clone()).json(batch).send().await {
+            Ok(res) if res.status().is_success() => {
+                trace!(events = batch.len(), "posted upload queue events");
+                return;
+            }
+            Ok(res) if res.status().is_client_error() => {
+                // Retrying won't help
+                error!(status = %res.status(), "upload events webhook refused the events");
+                break;
+            }
+            Ok(res) => {
+                warn!(attempt, status = %res.status(), "upload events webhook returned an error");
+            }
+            Err(e) => {
+                warn!(attempt, "failed to post upload queue events: {e}");
+            }
+        }
+    }
+
+    error!("dropping {} upload queue events", batch.len());
+    UPLOAD_EVENTS_DROPPED.inc_by(batch.len() as u64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_json() -> anyhow::Result<()> {
+        let event = UploadQueueEvent {
+            tenant_id: TenantId::from_array([1; 16]),
+            timeline_id: TimelineId::from_array([2; 16]),
+            generation: Some(Generation::new(3)),
+            kind: UploadQueueEventKind::IndexUploaded {
+                disk_consistent_lsn: Lsn(0x16B59D8),
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&event)?,
+            serde_json::json!({
+                "tenant_id": "01010101010101010101010101010101",
+                "timeline_id": "02020202020202020202020202020202",
+                "generation": 3,
+                "event": "index_uploaded",
+                "disk_consistent_lsn": "0/16B59D8",
+            })
+        );
+        Ok(())
+    }
+}
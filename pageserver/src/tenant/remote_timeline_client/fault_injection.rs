@@ -0,0 +1,139 @@
+//! Faults injected into the remote storage requests of the pageserver, to exercise the retries
+//! and the barriers of the upload queues in the integration tests.
+//!
+//! With the `testing` feature, [`super::create_remote_storage`] wraps every remote storage
+//! client of the pageserver to inject the faults configured through the management API, see
+//! [`RemoteStorageFault`]: latency, errors, and partial writes, per operation kind and path.
+//! Unlike the failpoints, they apply to all the remote storage requests, whichever code path
+//! makes them.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use pageserver_api::models::{RemoteStorageFault, RemoteStorageOpKind};
+use remote_storage::{Fault, FaultInjector, FaultOpKind, RemotePath};
+use tracing::info;
+
+/// The faults of all the remote storage clients of the pageserver.
+pub static REMOTE_STORAGE_FAULTS: Lazy<Arc<RemoteStorageFaults>> = Lazy::new(Arc::default);
+
+#[derive(Default)]
+pub struct RemoteStorageFaults {
+    faults: Mutex<Vec<RemoteStorageFault>>,
+}
+
+impl RemoteStorageFaults {
+    /// The faults configured, with the errors left to inject.
+    pub fn get(&self) -> Vec<RemoteStorageFault> {
+        self.faults.lock().unwrap().clone()
+    }
+
+    /// Replace the faults configured, an empty list removes them.
+    pub fn set(&self, faults: Vec<RemoteStorageFault>) {
+        info!("configured remote storage faults: {faults:?}");
+        *self.faults.lock().unwrap() = faults;
+    }
+}
+
+fn op_kind(kind: FaultOpKind) -> RemoteStorageOpKind {
+    match kind {
+        FaultOpKind::List => RemoteStorageOpKind::List,
+        FaultOpKind::Head => RemoteStorageOpKind::Head,
+        FaultOpKind::Upload => RemoteStorageOpKind::Upload,
+        FaultOpKind::Download => RemoteStorageOpKind::Download,
+        FaultOpKind::Delete => RemoteStorageOpKind::Delete,
+        FaultOpKind::Copy => RemoteStorageOpKind::Copy,
+    }
+}
+
+impl FaultInjector for RemoteStorageFaults {
+    fn fault(&self, kind: FaultOpKind, path: Option<&RemotePath>) -> Fault {
+        let op = op_kind(kind);
+        let mut fault = Fault::default();
+        let mut faults = self.faults.lock().unwrap();
+        for configured in faults.iter_mut().filter(|configured| configured.op == op) {
+            if let Some(pattern) = &configured.path_contains {
+                let path = path.map(|path| path.get_path().to_string_lossy());
+                if !path.map_or(false, |path| path.contains(pattern.as_str())) {
+                    continue;
+                }
+            }
+            fault.latency = fault
+                .latency
+                .max(Duration::from_millis(configured.latency_ms));
+            // The first of the faults with errors left injects the error
+            if fault.error {
+                continue;
+            }
+            match &mut configured.errors {
+                Some(0) => {}
+                Some(errors) => {
+                    *errors -= 1;
+                    fault.error = true;
+                }
+                None => fault.error = true,
+            }
+            fault.partial = fault.error && configured.partial;
+        }
+        fault
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn matching_faults() -> anyhow::Result<()> {
+        let faults = RemoteStorageFaults::default();
+        let index = RemotePath::new(Path::new("tenants/t/timelines/t/index_part.json"))?;
+        let layer = RemotePath::new(Path::new("tenants/t/timelines/t/layer"))?;
+        faults.set(vec![
+            RemoteStorageFault {
+                op: RemoteStorageOpKind::Upload,
+                path_contains: Some("index_part".to_string()),
+                latency_ms: 0,
+                errors: Some(1),
+                partial: true,
+            },
+            RemoteStorageFault {
+                op: RemoteStorageOpKind::Upload,
+                path_contains: None,
+                latency_ms: 10,
+                errors: Some(0),
+                partial: false,
+            },
+        ]);
+
+        // The index upload fails once, all the uploads are delayed
+        let fault = faults.fault(FaultOpKind::Upload, Some(&index));
+        assert!(fault.error && fault.partial);
+        assert_eq!(fault.latency, Duration::from_millis(10));
+        let fault = faults.fault(FaultOpKind::Upload, Some(&index));
+        assert!(!fault.error);
+        assert_eq!(faults.get()[0].errors, Some(0));
+        let fault = faults.fault(FaultOpKind::Upload, Some(&layer));
+        assert_eq!(
+            fault,
+            Fault {
+                latency: Duration::from_millis(10),
+                error: false,
+                partial: false,
+            }
+        );
+        assert_eq!(
+            faults.fault(FaultOpKind::Download, Some(&index)),
+            Fault::default()
+        );
+
+        faults.set(Vec::new());
+        assert_eq!(
+            faults.fault(FaultOpKind::Upload, Some(&index)),
+            Fault::default()
+        );
+
+        Ok(())
+    }
+}
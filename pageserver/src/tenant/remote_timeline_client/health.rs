@@ -0,0 +1,284 @@
+//! Health of the remote storages, as seen from the remote tasks of the upload queues.
+//!
+//! When a remote storage fails, the upload queues of all the timelines using it retry their
+//! tasks at once: thousands of retries, each adding to the load of a storage that may well be
+//! failing because of the load. Each remote storage, the one of the pageserver and those of the
+//! tenants that have their own, counts the attempts of the remote tasks that failed in a row,
+//! across all the timelines. With the `remote_circuit_breaker` setting, for example:
+//!
+//! ```toml
+//! remote_circuit_breaker = { failure_threshold = 100, cooldown = '30s' }
+//! ```
+//!
+//! the circuit breaker of a storage opens after `failure_threshold` failures in a row: for
+//! `cooldown`, the upload queues launch no new tasks, and the tasks in progress wait before
+//! their next retry. The circuit breaker is then half-open: the tasks go on, the first one that
+//! succeeds closes it, the first one that fails opens it again.
+//!
+//! Without the setting, the failures are only counted. The health of the storages is reported
+//! by the `/v1/status` endpoint, and the `pageserver_remote_storage_*` metrics.
+
+use std::collections::HashMap;
+use std::num::NonZeroU64;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use pageserver_api::models::{CircuitBreakerState, RemoteStorageHealthInfo};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use utils::id::TenantId;
+
+use crate::config::PageServerConf;
+use crate::metrics::{
+    REMOTE_STORAGE_CIRCUIT_BREAKER_OPEN, REMOTE_STORAGE_CIRCUIT_BREAKER_TRIPS,
+    REMOTE_STORAGE_CONSECUTIVE_FAILURES,
+};
+
+pub const DEFAULT_FAILURE_THRESHOLD: NonZeroU64 = match NonZeroU64::new(100) {
+    Some(x) => x,
+    None => panic!("const unwrap is not yet stable"),
+};
+
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// The name of the remote storage of the pageserver, the tenants with a remote storage of their
+/// own are named by their id.
+pub const PAGESERVER_STORAGE_NAME: &str = "pageserver";
+
+fn default_failure_threshold() -> NonZeroU64 {
+    DEFAULT_FAILURE_THRESHOLD
+}
+
+fn default_cooldown() -> Duration {
+    DEFAULT_COOLDOWN
+}
+
+/// The `remote_circuit_breaker` setting, see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteCircuitBreakerConfig {
+    /// Open the circuit breaker after this many failed attempts in a row.
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: NonZeroU64,
+    /// How long the remote tasks stay paused once the circuit breaker opens.
+    #[serde(with = "humantime_serde", default = "default_cooldown")]
+    pub cooldown: Duration,
+}
+
+impl Default for RemoteCircuitBreakerConfig {
+    fn default() -> Self {
+        RemoteCircuitBreakerConfig {
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            cooldown: DEFAULT_COOLDOWN,
+        }
+    }
+}
+
+/// The remote storages in use, by name.
+static STORAGES: Lazy<Mutex<HashMap<String, Arc<RemoteStorageHealth>>>> =
+    Lazy::new(Default::default);
+
+/// The health of the remote storage of a tenant, shared by all the timelines using the same
+/// storage.
+pub(crate) fn tenant_storage_health(
+    conf: &PageServerConf,
+    tenant_id: &TenantId,
+) -> Arc<RemoteStorageHealth> {
+    let name = if conf.tenant_remote_storage_path(tenant_id).exists() {
+        tenant_id.to_string()
+    } else {
+        PAGESERVER_STORAGE_NAME.to_string()
+    };
+    let mut storages = STORAGES.lock().unwrap();
+    let health = storages.entry(name).or_insert_with_key(|name| {
+        Arc::new(RemoteStorageHealth::new(name.clone(), conf.remote_circuit_breaker))
+    });
+    Arc::clone(health)
+}
+
+/// The health of the remote storages in use: the one of the pageserver first, then those of
+/// the tenants, by id.
+pub fn remote_storage_health() -> Vec<RemoteStorageHealthInfo> {
+    let mut storages = STORAGES.lock().unwrap();
+    // Forget the storages of the tenants that have been detached since
+    storages.retain(|name, health| {
+        let in_use = name == PAGESERVER_STORAGE_NAME || Arc::strong_count(health) > 1;
+        if !in_use {
+            health.remove_metrics();
+        }
+        in_use
+    });
+    let mut infos = storages
+        .values()
+        .map(|health| health.info())
+        .collect::<Vec<_>>();
+    infos.sort_by_key(|info| (info.storage != PAGESERVER_STORAGE_NAME, info.storage.clone()));
+    infos
+}
+
+/// The failures of a remote storage, and its circuit breaker, see the module docs.
+pub struct RemoteStorageHealth {
+    name: String,
+    config: Option<RemoteCircuitBreakerConfig>,
+    state: Mutex<HealthState>,
+}
+
+#[derive(Debug, Default)]
+struct HealthState {
+    consecutive_failures: u64,
+    /// Until when the circuit breaker is open, in the past once it's half-open. `None` while
+    /// it's closed.
+    open_until: Option<Instant>,
+}
+
+impl RemoteStorageHealth {
+    pub(crate) fn new(name: String, config: Option<RemoteCircuitBreakerConfig>) -> Self {
+        RemoteStorageHealth {
+            name,
+            config,
+            state: Mutex::new(HealthState::default()),
+        }
+    }
+
+    /// An attempt of a remote task succeeded.
+    pub(crate) fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.open_until.take().is_some() {
+            info!(
+                "remote storage {} is back after {} failures in a row, resuming the remote tasks",
+                self.name, state.consecutive_failures
+            );
+            REMOTE_STORAGE_CIRCUIT_BREAKER_OPEN
+                .with_label_values(&[&self.name])
+                .set(0);
+        }
+        if state.consecutive_failures > 0 {
+            state.consecutive_failures = 0;
+            REMOTE_STORAGE_CONSECUTIVE_FAILURES
+                .with_label_values(&[&self.name])
+                .set(0);
+        }
+    }
+
+    /// An attempt of a remote task failed.
+    pub(crate) fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        REMOTE_STORAGE_CONSECUTIVE_FAILURES
+            .with_label_values(&[&self.name])
+            .set(state.consecutive_failures as i64);
+
+        let Some(config) = &self.config else {
+            return;
+        };
+        let now = Instant::now();
+        let open = state.open_until.map_or(false, |until| until > now);
+        if !open && state.consecutive_failures >= config.failure_threshold.get() {
+            warn!(
+                "remote storage {} failed {} times in a row, pausing the remote tasks for {:?}",
+                self.name, state.consecutive_failures, config.cooldown
+            );
+            state.open_until = Some(now + config.cooldown);
+            REMOTE_STORAGE_CIRCUIT_BREAKER_OPEN
+                .with_label_values(&[&self.name])
+                .set(1);
+            REMOTE_STORAGE_CIRCUIT_BREAKER_TRIPS
+                .with_label_values(&[&self.name])
+                .inc();
+        }
+    }
+
+    /// Until when the remote tasks are paused, `None` unless the circuit breaker is open.
+    pub(crate) fn paused_until(&self) -> Option<Instant> {
+        let state = self.state.lock().unwrap();
+        state.open_until.filter(|until| *until > Instant::now())
+    }
+
+    /// Wait while the circuit breaker is open.
+    pub(crate) async fn wait_until_resumed(&self) {
+        while let Some(until) = self.paused_until() {
+            tokio::time::sleep_until(tokio::time::Instant::from_std(until)).await;
+        }
+    }
+
+    pub fn info(&self) -> RemoteStorageHealthInfo {
+        let state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let (circuit_breaker, paused_for) = match state.open_until {
+            None => (CircuitBreakerState::Closed, None),
+            Some(until) if until > now => (CircuitBreakerState::Open, Some(until - now)),
+            Some(_) => (CircuitBreakerState::HalfOpen, None),
+        };
+        RemoteStorageHealthInfo {
+            storage: self.name.clone(),
+            circuit_breaker,
+            consecutive_failures: state.consecutive_failures,
+            paused_for_secs: paused_for.map(|paused_for| paused_for.as_secs()),
+        }
+    }
+
+    fn remove_metrics(&self) {
+        let _ = REMOTE_STORAGE_CONSECUTIVE_FAILURES.remove_label_values(&[&self.name]);
+        let _ = REMOTE_STORAGE_CIRCUIT_BREAKER_OPEN.remove_label_values(&[&self.name]);
+        let _ = REMOTE_STORAGE_CIRCUIT_BREAKER_TRIPS.remove_label_values(&[&self.name]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(failure_threshold: u64, cooldown: Duration) -> Option<RemoteCircuitBreakerConfig> {
+        Some(RemoteCircuitBreakerConfig {
+            failure_threshold: NonZeroU64::new(failure_threshold).unwrap(),
+            cooldown,
+        })
+    }
+
+    #[test]
+    fn circuit_breaker() {
+        // Without a circuit breaker, the failures are only counted
+        let health = RemoteStorageHealth::new("no_breaker".to_string(), None);
+        for _ in 0..1000 {
+            health.record_failure();
+        }
+        assert_eq!(health.info().consecutive_failures, 1000);
+        assert_eq!(health.info().circuit_breaker, CircuitBreakerState::Closed);
+        assert_eq!(health.paused_until(), None);
+
+        // Opens after the threshold, a success closes it
+        let name = "breaker";
+        let config = breaker(2, Duration::from_secs(3600));
+        let health = RemoteStorageHealth::new(name.to_string(), config);
+        health.record_failure();
+        assert_eq!(health.paused_until(), None);
+        health.record_failure();
+        assert!(health.paused_until().is_some());
+        let info = health.info();
+        assert_eq!(info.circuit_breaker, CircuitBreakerState::Open);
+        assert!(info.paused_for_secs.unwrap() > 3500);
+        // The failures of the tasks in progress don't extend the pause
+        let paused_until = health.paused_until();
+        health.record_failure();
+        assert_eq!(health.paused_until(), paused_until);
+        health.record_success();
+        assert_eq!(health.info().circuit_breaker, CircuitBreakerState::Closed);
+        assert_eq!(health.info().consecutive_failures, 0);
+        let trips = REMOTE_STORAGE_CIRCUIT_BREAKER_TRIPS.with_label_values(&[name]);
+        assert_eq!(trips.get(), 1);
+
+        // Half-open once the cooldown has passed: the next failure opens it again
+        let name = "half_open_breaker";
+        let health = RemoteStorageHealth::new(name.to_string(), breaker(2, Duration::ZERO));
+        health.record_failure();
+        health.record_failure();
+        assert_eq!(health.info().circuit_breaker, CircuitBreakerState::HalfOpen);
+        assert_eq!(health.paused_until(), None);
+        health.record_failure();
+        let trips = REMOTE_STORAGE_CIRCUIT_BREAKER_TRIPS.with_label_values(&[name]);
+        assert_eq!(trips.get(), 2);
+        health.record_success();
+        assert_eq!(health.info().circuit_breaker, CircuitBreakerState::Closed);
+    }
+}
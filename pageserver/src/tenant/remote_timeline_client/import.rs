@@ -0,0 +1,261 @@
+//! Import of the timelines written by an offline import tool, when a tenant is attached.
+//!
+//! The tool writes a directory per timeline under a prefix of a remote storage, named by the
+//! timeline id, with the layer files of the timeline and its `metadata` file, in the format of
+//! the local metadata file of a timeline, but no index. A tenant attached in import mode first
+//! copies each of these timelines to its own remote storage: the layer files, then an index
+//! constructed from their listing and the metadata file. The timelines are then listed and
+//! attached like any other.
+//!
+//! The timelines that already have an index in the remote storage of the tenant are skipped,
+//! so an attach interrupted by a restart resumes the import, which stays persisted in the
+//! tenant directory until the attach completes.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Component, Path};
+
+use anyhow::Context;
+use futures::{StreamExt, TryStreamExt};
+use pageserver_api::models::TenantImportSource;
+use remote_storage::{DownloadError, GenericRemoteStorage, RemotePath};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use utils::id::{TenantId, TimelineId};
+
+use super::index::{IndexPart, LayerFileMetadata};
+use super::{copy, download, listing, upload, MAX_CONCURRENT_LAYER_COPIES};
+use crate::config::PageServerConf;
+use crate::tenant::config::TenantRemoteStorageConfig;
+use crate::tenant::generation::Generation;
+use crate::tenant::metadata::TimelineMetadata;
+use crate::tenant::storage_layer::LayerFileName;
+use crate::METADATA_FILE_NAME;
+
+/// The source of the timelines of a tenant attached in import mode, see the module docs.
+pub struct TenantImport {
+    source: TenantImportSource,
+    storage: Option<TenantRemoteStorageConfig>,
+    prefix: RemotePath,
+}
+
+impl TryFrom<TenantImportSource> for TenantImport {
+    type Error = anyhow::Error;
+
+    fn try_from(source: TenantImportSource) -> Result<Self, Self::Error> {
+        let prefix = Path::new(&source.prefix);
+        anyhow::ensure!(
+            prefix
+                .components()
+                .all(|component| matches!(component, Component::Normal(_))),
+            "import prefix must be a relative path without '..' components"
+        );
+        let prefix = RemotePath::new(prefix)?;
+        let storage = source
+            .remote_storage
+            .as_ref()
+            .map(TenantRemoteStorageConfig::try_from)
+            .transpose()?;
+        Ok(TenantImport {
+            source,
+            storage,
+            prefix,
+        })
+    }
+}
+
+impl TenantImport {
+    /// The remote storage of the prefix, `None` for the one of the tenant.
+    pub fn storage(&self) -> Option<&TenantRemoteStorageConfig> {
+        self.storage.as_ref()
+    }
+
+    /// Read the import file of a tenant directory, `None` if the tenant isn't attached in
+    /// import mode, or has completed its attach.
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        let contents = match std::fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("read import file {path:?}")),
+        };
+        serde_json::from_slice::<TenantImportSource>(&contents)
+            .map_err(anyhow::Error::from)
+            .and_then(Self::try_from)
+            .map(Some)
+            .with_context(|| format!("parse import file {path:?}"))
+    }
+
+    /// Write the import file of a tenant directory. The caller fsyncs the directory.
+    pub(crate) fn persist(&self, path: &Path) -> anyhow::Result<()> {
+        let write = || -> anyhow::Result<()> {
+            let mut file = std::fs::File::create(path)?;
+            file.write_all(&serde_json::to_vec(&self.source)?)?;
+            file.sync_all()?;
+            Ok(())
+        };
+        write().with_context(|| format!("write import file {path:?}"))
+    }
+}
+
+/// Copy the timelines of the import prefix to the remote storage of the tenant, skipping the
+/// ones it already has. The indexes are written with the generation of the attachment.
+pub async fn import_remote_timelines(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    tenant_id: TenantId,
+    generation: Option<Generation>,
+    import: &TenantImport,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let source = match &import.storage {
+        Some(config) => super::create_remote_storage(conf, &config.config)
+            .context("create the client of the import remote storage")?,
+        None => storage.clone(),
+    };
+
+    let prefixes = download::download_retry(
+        &conf.remote_retry.download,
+        || async {
+            let _permit = listing::list_permit(conf).await;
+            source.list_prefixes(Some(&import.prefix)).await
+        },
+        &format!("list prefixes for {:?}", import.prefix),
+        cancel,
+    )
+    .await?;
+    let mut timeline_ids = HashSet::new();
+    for prefix in prefixes {
+        let object_name = prefix
+            .object_name()
+            .with_context(|| format!("failed to get timeline id from import prefix {prefix:?}"))?;
+        let timeline_id: TimelineId = object_name.parse().with_context(|| {
+            format!("failed to parse object name into timeline id '{object_name}'")
+        })?;
+        timeline_ids.insert(timeline_id);
+    }
+    anyhow::ensure!(!timeline_ids.is_empty(), "no timelines found to import");
+
+    for timeline_id in timeline_ids {
+        import_remote_timeline(
+            conf,
+            &source,
+            storage,
+            tenant_id,
+            timeline_id,
+            generation,
+            &import.prefix,
+            cancel,
+        )
+        .await
+        .with_context(|| format!("import timeline {timeline_id}"))?;
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn import_remote_timeline(
+    conf: &'static PageServerConf,
+    source: &GenericRemoteStorage,
+    storage: &GenericRemoteStorage,
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+    generation: Option<Generation>,
+    import_prefix: &RemotePath,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let timeline_path = conf.timeline_path(&tenant_id, &timeline_id);
+    let timeline_storage_path = conf.remote_path(&timeline_path)?;
+    let legacy_index_path = timeline_storage_path.join(Path::new(IndexPart::FILE_NAME));
+    let generations = download::download_retry(
+        &conf.remote_retry.download,
+        || async {
+            let _permit = listing::list_permit(conf).await;
+            download::list_index_generations(storage, &legacy_index_path)
+                .await
+                .map_err(DownloadError::Other)
+        },
+        &format!("list index files {legacy_index_path:?}"),
+        cancel,
+    )
+    .await?;
+    if !generations.is_empty() {
+        info!(%timeline_id, "timeline already imported, skipping");
+        return Ok(());
+    }
+
+    let source_path = import_prefix.join(Path::new(&timeline_id.to_string()));
+    let objects = download::download_retry(
+        &conf.remote_retry.download,
+        || async {
+            let _permit = listing::list_permit(conf).await;
+            source
+                .list_objects(Some(&source_path))
+                .await
+                .map_err(DownloadError::Other)
+        },
+        &format!("list objects {source_path:?}"),
+        cancel,
+    )
+    .await?;
+
+    let mut metadata = None;
+    let mut layers = HashMap::new();
+    for object in objects {
+        let Some(name) = object.path.object_name() else {
+            continue;
+        };
+        if name == METADATA_FILE_NAME {
+            let (bytes, _) = download::download_bytes(conf, source, &object.path, cancel).await?;
+            metadata = Some(
+                TimelineMetadata::from_bytes(&bytes)
+                    .with_context(|| format!("parse metadata file {:?}", object.path))?,
+            );
+            continue;
+        }
+        match name.parse::<LayerFileName>() {
+            Ok(layer_file_name) => {
+                layers.insert(layer_file_name, object);
+            }
+            Err(_) => warn!(%timeline_id, "skipping unknown file {:?} to import", object.path),
+        }
+    }
+    let metadata = metadata.context("the timeline to import has no metadata file")?;
+
+    let copies = futures::stream::iter(layers.iter())
+        .map(|(layer_file_name, object)| {
+            let path = timeline_storage_path.join(Path::new(&layer_file_name.file_name()));
+            async move {
+                copy::copy_object_to(source, &object.path, storage, &path)
+                    .await?
+                    .with_context(|| format!("layer file {layer_file_name} is missing"))
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_LAYER_COPIES)
+        .try_collect::<Vec<_>>();
+    let copied_bytes: u64 = tokio::select! {
+        copies = copies => copies?.into_iter().sum(),
+        _ = cancel.cancelled() => anyhow::bail!("import cancelled"),
+    };
+
+    // The index goes last, so a timeline with an index has all its layer files
+    let layers = layers
+        .into_iter()
+        .map(|(layer_file_name, object)| (layer_file_name, LayerFileMetadata::new(object.size)))
+        .collect();
+    let mut index_part = IndexPart::new(
+        layers,
+        metadata.disk_consistent_lsn(),
+        metadata.to_bytes().context("serialize metadata")?,
+    );
+    index_part.generation = generation;
+    upload::upload_index_part(conf, storage, &tenant_id, &timeline_id, &index_part, None).await?;
+
+    info!(
+        %timeline_id,
+        layers = index_part.layer_metadata.len(),
+        copied_bytes,
+        disk_consistent_lsn = %metadata.disk_consistent_lsn(),
+        "imported timeline"
+    );
+    Ok(())
+}
@@ -2,17 +2,27 @@
 //! Able to restore itself from the storage index parts, that are located in every timeline's remote directory and contain all data about
 //! remote timeline layers and its metadata.
 
+pub(crate) mod chunked;
+
 use std::collections::{HashMap, HashSet};
 
+use anyhow::Context;
 use chrono::NaiveDateTime;
+use remote_storage::RemotePath;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 use utils::bin_ser::SerializeError;
 
+use super::compression::LayerCompression;
+use super::encryption::LayerEncryption;
+use super::sharding;
+use super::tiering::LayerTier;
+use crate::tenant::generation::Generation;
 use crate::tenant::metadata::TimelineMetadata;
 use crate::tenant::storage_layer::LayerFileName;
 use crate::tenant::upload_queue::UploadQueueInitialized;
 
+use utils::id::NodeId;
 use utils::lsn::Lsn;
 
 /// Metadata gathered for each of the layer files.
@@ -23,24 +33,181 @@ use utils::lsn::Lsn;
 #[cfg_attr(test, derive(Default))]
 pub struct LayerFileMetadata {
     file_size: u64,
+
+    /// CRC32C of the file contents, known once the layer has been uploaded.
+    crc32c: Option<u32>,
+
+    /// How the file is compressed remotely, known once the layer has been uploaded.
+    compression: Option<LayerCompression>,
+
+    /// How the file is encrypted remotely, known once the layer has been uploaded.
+    encryption: Option<LayerEncryption>,
+
+    /// How the file was created, known for the layers created since the index version 7.
+    creation: Option<LayerCreation>,
+
+    /// The tier the file has been moved to, `None` if it is at the default location.
+    tier: Option<LayerTier>,
+
+    /// Where the file is relative to the remote directory of its timeline, `None` if it is at the
+    /// top of it, see [`sharding`].
+    path: Option<String>,
 }
 
 impl From<&'_ IndexLayerMetadata> for LayerFileMetadata {
     fn from(other: &IndexLayerMetadata) -> Self {
         LayerFileMetadata {
             file_size: other.file_size,
+            crc32c: other.crc32c,
+            compression: other.compression,
+            encryption: other.encryption,
+            creation: other.creation,
+            tier: other.tier,
+            path: other.path.clone(),
         }
     }
 }
 
 impl LayerFileMetadata {
     pub fn new(file_size: u64) -> Self {
-        LayerFileMetadata { file_size }
+        LayerFileMetadata {
+            file_size,
+            crc32c: None,
+            compression: None,
+            encryption: None,
+            creation: None,
+            tier: None,
+            path: None,
+        }
+    }
+
+    /// The metadata of a layer file just written by this pageserver.
+    pub fn created(file_size: u64, creation: LayerCreation) -> Self {
+        LayerFileMetadata {
+            creation: Some(creation),
+            ..Self::new(file_size)
+        }
     }
 
     pub fn file_size(&self) -> u64 {
         self.file_size
     }
+
+    pub fn crc32c(&self) -> Option<u32> {
+        self.crc32c
+    }
+
+    pub fn compression(&self) -> Option<LayerCompression> {
+        self.compression
+    }
+
+    pub fn encryption(&self) -> Option<LayerEncryption> {
+        self.encryption
+    }
+
+    pub fn creation(&self) -> Option<LayerCreation> {
+        self.creation
+    }
+
+    pub fn tier(&self) -> Option<LayerTier> {
+        self.tier
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Where the file is in the remote storage, given the remote directory of its timeline.
+    pub(super) fn remote_path(
+        &self,
+        timeline_storage_path: &RemotePath,
+        layer_file_name: &LayerFileName,
+    ) -> RemotePath {
+        sharding::remote_layer_path(
+            timeline_storage_path,
+            layer_file_name,
+            self.path(),
+            self.tier,
+        )
+    }
+
+    /// Size of the file once compressed, if it is.
+    pub fn compressed_size(&self) -> u64 {
+        match self.compression {
+            Some(compression) => compression.compressed_size(),
+            None => self.file_size,
+        }
+    }
+
+    /// Size of the file in the remote storage.
+    pub fn remote_size(&self) -> u64 {
+        let compressed_size = self.compressed_size();
+        match self.encryption {
+            Some(encryption) => encryption.encrypted_size(compressed_size),
+            None => compressed_size,
+        }
+    }
+
+    /// The metadata of the file as uploaded to `path`, relative to the remote directory of its
+    /// timeline, out of any tier.
+    pub(super) fn uploaded(
+        self,
+        path: Option<String>,
+        crc32c: u32,
+        compression: Option<LayerCompression>,
+        encryption: Option<LayerEncryption>,
+    ) -> Self {
+        LayerFileMetadata {
+            crc32c: Some(crc32c),
+            compression,
+            encryption,
+            tier: None,
+            path,
+            ..self
+        }
+    }
+
+    /// The metadata of the file once moved to the tier.
+    pub(super) fn tiered(self, tier: LayerTier) -> Self {
+        LayerFileMetadata {
+            tier: Some(tier),
+            ..self
+        }
+    }
+}
+
+/// What wrote a layer file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerSource {
+    /// An in-memory layer flushed to disk.
+    Flush,
+    /// A compaction of other layer files.
+    Compaction,
+    /// The initial import of the timeline, from initdb or a basebackup.
+    Import,
+}
+
+/// When, where and how a layer file was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LayerCreation {
+    pub created_at: NaiveDateTime,
+    /// The pageserver that wrote the file.
+    pub node_id: NodeId,
+    /// 0 for the L0 delta layers, 1 for the L1 delta layers and the image layers.
+    pub compaction_level: u32,
+    pub source: LayerSource,
+}
+
+impl LayerCreation {
+    pub fn now(node_id: NodeId, compaction_level: u32, source: LayerSource) -> Self {
+        LayerCreation {
+            created_at: chrono::Utc::now().naive_utc(),
+            node_id,
+            compaction_level,
+            source,
+        }
+    }
 }
 
 // TODO seems like another part of the remote storage file format
@@ -49,12 +216,13 @@ impl LayerFileMetadata {
 ///
 /// Contains the data about all files in the timeline, present remotely and its metadata.
 ///
-/// This type needs to be backwards and forwards compatible. When changing the fields,
-/// remember to add a test case for the changed version.
+/// This type needs to be backwards and forwards compatible. When changing the fields, increment
+/// [`IndexPart::LATEST_VERSION`] and add a fixture of the new version, see
+/// [`IndexPart::from_json_bytes`].
 #[serde_as]
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct IndexPart {
-    /// Debugging aid describing the version of this type.
+    /// The version of the format, see [`IndexPart::LATEST_VERSION`]. Missing before version 1.
     #[serde(default)]
     version: usize,
 
@@ -62,6 +230,13 @@ pub struct IndexPart {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub deleted_at: Option<NaiveDateTime>,
 
+    /// The generation of the attachment that wrote this index.
+    ///
+    /// Added in version 6. Missing for the tenants attached without a generation.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation: Option<Generation>,
+
     /// Layer names, which are stored on the remote storage.
     ///
     /// Additional metadata can might exist in `layer_metadata`.
@@ -84,10 +259,71 @@ impl IndexPart {
     /// When adding or modifying any parts of `IndexPart`, increment the version so that it can be
     /// used to understand later versions.
     ///
-    /// Version is currently informative only.
-    const LATEST_VERSION: usize = 2;
+    /// A change must keep the index readable by the previous release, for a rollback: add
+    /// optional fields, which that release ignores. A change that can't, e.g. a removed or
+    /// renamed field, also needs a [`Migration`] from the older versions.
+    ///
+    /// 1. `timeline_layers`, `layer_metadata` with the file sizes.
+    /// 2. `deleted_at`, and no `missing_layers`.
+    /// 3. `crc32c` of the layer files.
+    /// 4. `encryption` of the layer files.
+    /// 5. `compression` of the layer files.
+    /// 6. `generation`.
+    /// 7. `creation` of the layer files.
+    /// 8. The chunked form, see [`chunked`], in which the index file is a manifest that the
+    ///    previous releases can't read. The plain form is unchanged.
+    /// 9. `tier` of the layer files, see [`super::tiering`]. The previous releases read the
+    ///    index, but not the tiered layer files.
+    /// 10. `path` of the layer files, see [`super::sharding`]. The previous releases read the
+    ///     index, but not the sharded layer files.
+    const LATEST_VERSION: usize = 10;
     pub const FILE_NAME: &'static str = "index_part.json";
 
+    pub const SNAPSHOT_FILE_PREFIX: &'static str = "index_part-";
+
+    /// The index of the remote state the upload queue of a timeline was stopped short of, in
+    /// the timeline directory, see [`super::RemoteTimelineClient::stop`].
+    pub const PENDING_FILE_NAME: &'static str = "index_part.pending.json";
+
+    /// Name of the index file written by the attachment of the given generation.
+    pub fn file_name(generation: Option<Generation>) -> String {
+        match generation {
+            Some(generation) => format!("{}-{generation}", Self::FILE_NAME),
+            None => Self::FILE_NAME.to_string(),
+        }
+    }
+
+    /// Inverse of [`IndexPart::file_name`]: `None` if the name is not of an index file.
+    pub fn parse_file_name(name: &str) -> Option<Option<Generation>> {
+        let suffix = name.strip_prefix(Self::FILE_NAME)?;
+        if suffix.is_empty() {
+            return Some(None);
+        }
+        let generation = suffix.strip_prefix('-')?.parse().ok()?;
+        Some(Some(generation))
+    }
+
+    /// Name of the snapshot of the index at the given `disk_consistent_lsn`: an immutable copy
+    /// of the index file, in the plain form, for the index to be restored from.
+    pub fn snapshot_file_name(disk_consistent_lsn: Lsn) -> String {
+        format!(
+            "{}{:016X}.json",
+            Self::SNAPSHOT_FILE_PREFIX,
+            disk_consistent_lsn.0
+        )
+    }
+
+    /// Inverse of [`IndexPart::snapshot_file_name`]: `None` if the name is not of a snapshot.
+    pub fn parse_snapshot_file_name(name: &str) -> Option<Lsn> {
+        let lsn = name
+            .strip_prefix(Self::SNAPSHOT_FILE_PREFIX)?
+            .strip_suffix(".json")?;
+        if lsn.len() != 16 || !lsn.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        u64::from_str_radix(lsn, 16).ok().map(Lsn)
+    }
+
     pub fn new(
         layers_and_metadata: HashMap<LayerFileName, LayerFileMetadata>,
         disk_consistent_lsn: Lsn,
@@ -109,12 +345,67 @@ impl IndexPart {
             disk_consistent_lsn,
             metadata_bytes,
             deleted_at: None,
+            generation: None,
         }
     }
 
+    /// Parse an index file, upgrading an older version of the format to the latest one with the
+    /// [`MIGRATIONS`]. A later version is parsed as it is, ignoring the fields added since.
+    pub fn from_json_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut value: serde_json::Value = serde_json::from_slice(bytes)?;
+        let index = value
+            .as_object_mut()
+            .context("index part is not a JSON object")?;
+        let version = match index.get("version") {
+            Some(version) => version.as_u64().context("invalid index part version")? as usize,
+            None => 0,
+        };
+        if version < Self::LATEST_VERSION {
+            for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+                (migration.upgrade)(index).with_context(|| {
+                    format!("upgrade index part to version {}", migration.version)
+                })?;
+            }
+            index.insert("version".to_string(), Self::LATEST_VERSION.into());
+        }
+        let index_part: Self = serde_json::from_value(value)?;
+        index_part.check_layer_paths()?;
+        Ok(index_part)
+    }
+
+    /// Check that the layer file paths are in the timeline directory, where they are joined to,
+    /// see [`sharding::is_layer_path`].
+    fn check_layer_paths(&self) -> anyhow::Result<()> {
+        for (layer_file_name, metadata) in &self.layer_metadata {
+            if let Some(path) = &metadata.path {
+                anyhow::ensure!(
+                    sharding::is_layer_path(layer_file_name, path),
+                    "invalid path {path:?} of layer file {}",
+                    layer_file_name.file_name()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
     pub fn parse_metadata(&self) -> anyhow::Result<TimelineMetadata> {
         TimelineMetadata::from_bytes(&self.metadata_bytes)
     }
+
+    /// Record the metadata of a layer of this index, once it has been uploaded.
+    pub(crate) fn set_uploaded_layer_metadata(
+        &mut self,
+        layer_file_name: &LayerFileName,
+        uploaded: &LayerFileMetadata,
+    ) {
+        if let Some(metadata) = self.layer_metadata.get_mut(layer_file_name) {
+            *metadata = IndexLayerMetadata::from(uploaded);
+        }
+    }
 }
 
 impl TryFrom<&UploadQueueInitialized> for IndexPart {
@@ -132,16 +423,72 @@ impl TryFrom<&UploadQueueInitialized> for IndexPart {
     }
 }
 
+/// An upgrade of the serialized [`IndexPart`] from the version before `version`.
+struct Migration {
+    version: usize,
+    upgrade: fn(&mut serde_json::Map<String, serde_json::Value>) -> anyhow::Result<()>,
+}
+
+/// In the order of the versions.
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: 2,
+    upgrade: drop_missing_layers,
+}];
+
+/// The layer files referenced from the index but missing in the remote storage, never read.
+fn drop_missing_layers(
+    index: &mut serde_json::Map<String, serde_json::Value>,
+) -> anyhow::Result<()> {
+    index.remove("missing_layers");
+    Ok(())
+}
+
 /// Serialized form of [`LayerFileMetadata`].
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Default)]
 pub struct IndexLayerMetadata {
     pub(super) file_size: u64,
+
+    /// Added in version 3. Missing for the layers uploaded by older versions.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) crc32c: Option<u32>,
+
+    /// Added in version 5. Missing for the layers stored uncompressed.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) compression: Option<LayerCompression>,
+
+    /// Added in version 4. Missing for the layers stored as is.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) encryption: Option<LayerEncryption>,
+
+    /// Added in version 7. Missing for the layers created by older versions.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) creation: Option<LayerCreation>,
+
+    /// Added in version 9. Missing for the layers at the default location.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) tier: Option<LayerTier>,
+
+    /// Added in version 10. Missing for the layers at the top of the timeline directory.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) path: Option<String>,
 }
 
 impl From<&'_ LayerFileMetadata> for IndexLayerMetadata {
     fn from(other: &'_ LayerFileMetadata) -> Self {
         IndexLayerMetadata {
             file_size: other.file_size,
+            crc32c: other.crc32c,
+            compression: other.compression,
+            encryption: other.encryption,
+            creation: other.creation,
+            tier: other.tier,
+            path: other.path.clone(),
         }
     }
 }
@@ -149,6 +496,7 @@ impl From<&'_ LayerFileMetadata> for IndexLayerMetadata {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::Path;
 
     #[test]
     fn v1_indexpart_is_parsed() {
@@ -170,16 +518,29 @@ mod tests {
             layer_metadata: HashMap::from([
                 ("000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap(), IndexLayerMetadata {
                     file_size: 25600000,
+                    crc32c: None,
+                    compression: None,
+                    encryption: None,
+                    creation: None,
+                    tier: None,
+                    path: None,
                 }),
                 ("000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap(), IndexLayerMetadata {
                     // serde_json should always parse this but this might be a double with jq for
                     // example.
                     file_size: 9007199254741001,
+                    crc32c: None,
+                    compression: None,
+                    encryption: None,
+                    creation: None,
+                    tier: None,
+                    path: None,
                 })
             ]),
             disk_consistent_lsn: "0/16960E8".parse::<Lsn>().unwrap(),
             metadata_bytes: [113,11,159,210,0,54,0,4,0,0,0,0,1,105,96,232,1,0,0,0,0,1,105,96,112,0,0,0,0,0,0,0,0,0,0,0,0,0,1,105,96,112,0,0,0,0,1,105,96,112,0,0,0,14,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0].to_vec(),
             deleted_at: None,
+            generation: None,
         };
 
         let part = serde_json::from_str::<IndexPart>(example).unwrap();
@@ -207,20 +568,407 @@ mod tests {
             layer_metadata: HashMap::from([
                 ("000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap(), IndexLayerMetadata {
                     file_size: 25600000,
+                    crc32c: None,
+                    compression: None,
+                    encryption: None,
+                    creation: None,
+                    tier: None,
+                    path: None,
                 }),
                 ("000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap(), IndexLayerMetadata {
                     // serde_json should always parse this but this might be a double with jq for
                     // example.
                     file_size: 9007199254741001,
+                    crc32c: None,
+                    compression: None,
+                    encryption: None,
+                    creation: None,
+                    tier: None,
+                    path: None,
                 })
             ]),
             disk_consistent_lsn: "0/16960E8".parse::<Lsn>().unwrap(),
             metadata_bytes: [112,11,159,210,0,54,0,4,0,0,0,0,1,105,96,232,1,0,0,0,0,1,105,96,112,0,0,0,0,0,0,0,0,0,0,0,0,0,1,105,96,112,0,0,0,0,1,105,96,112,0,0,0,14,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0].to_vec(),
             deleted_at: None,
+            generation: None,
+        };
+
+        let part = serde_json::from_str::<IndexPart>(example).unwrap();
+        assert_eq!(part, expected);
+    }
+
+    #[test]
+    fn v3_indexpart_is_parsed_with_layer_checksums() {
+        let example = r#"{
+            "version":3,
+            "timeline_layers":["000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9"],
+            "layer_metadata":{
+                "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9": { "file_size": 25600000, "crc32c": 3817370166 },
+                "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51": { "file_size": 9007199254741001 }
+            },
+            "disk_consistent_lsn":"0/16960E8",
+            "metadata_bytes":[1,2,3]
+        }"#;
+
+        let expected = IndexPart {
+            version: 3,
+            timeline_layers: HashSet::from(["000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap()]),
+            layer_metadata: HashMap::from([
+                ("000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap(), IndexLayerMetadata {
+                    file_size: 25600000,
+                    crc32c: Some(3817370166),
+                    compression: None,
+                    encryption: None,
+                    creation: None,
+                    tier: None,
+                    path: None,
+                }),
+                ("000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap(), IndexLayerMetadata {
+                    // uploaded by an older version
+                    file_size: 9007199254741001,
+                    crc32c: None,
+                    compression: None,
+                    encryption: None,
+                    creation: None,
+                    tier: None,
+                    path: None,
+                })
+            ]),
+            disk_consistent_lsn: "0/16960E8".parse::<Lsn>().unwrap(),
+            metadata_bytes: vec![1, 2, 3],
+            deleted_at: None,
+            generation: None,
+        };
+
+        let part = serde_json::from_str::<IndexPart>(example).unwrap();
+        assert_eq!(part, expected);
+
+        // Writing the index back keeps the checksums
+        let reparsed = serde_json::from_slice::<IndexPart>(&serde_json::to_vec(&part).unwrap());
+        assert_eq!(reparsed.unwrap(), expected);
+    }
+
+    #[test]
+    fn v4_indexpart_is_parsed_with_layer_encryption() {
+        let example = r#"{
+            "version":4,
+            "timeline_layers":["000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9"],
+            "layer_metadata":{
+                "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9": { "file_size": 25600000, "crc32c": 3817370166, "encryption": "chunked_aes256_gcm" }
+            },
+            "disk_consistent_lsn":"0/16960E8",
+            "metadata_bytes":[1,2,3]
+        }"#;
+
+        let expected = IndexPart {
+            version: 4,
+            timeline_layers: HashSet::from(["000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap()]),
+            layer_metadata: HashMap::from([
+                ("000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap(), IndexLayerMetadata {
+                    file_size: 25600000,
+                    crc32c: Some(3817370166),
+                    compression: None,
+                    encryption: Some(LayerEncryption::ChunkedAes256Gcm),
+                    creation: None,
+                    tier: None,
+                    path: None,
+                }),
+            ]),
+            disk_consistent_lsn: "0/16960E8".parse::<Lsn>().unwrap(),
+            metadata_bytes: vec![1, 2, 3],
+            deleted_at: None,
+            generation: None,
+        };
+
+        let part = serde_json::from_str::<IndexPart>(example).unwrap();
+        assert_eq!(part, expected);
+    }
+
+    #[test]
+    fn v5_indexpart_is_parsed_with_layer_compression() {
+        let example = r#"{
+            "version":5,
+            "timeline_layers":["000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9"],
+            "layer_metadata":{
+                "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9": { "file_size": 25600000, "crc32c": 3817370166, "compression": { "algorithm": "zstd", "compressed_size": 6400000 }, "encryption": "chunked_aes256_gcm" }
+            },
+            "disk_consistent_lsn":"0/16960E8",
+            "metadata_bytes":[1,2,3]
+        }"#;
+
+        let expected = IndexPart {
+            version: 5,
+            timeline_layers: HashSet::from(["000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap()]),
+            layer_metadata: HashMap::from([
+                ("000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap(), IndexLayerMetadata {
+                    file_size: 25600000,
+                    crc32c: Some(3817370166),
+                    compression: Some(LayerCompression::Zstd { compressed_size: 6400000 }),
+                    encryption: Some(LayerEncryption::ChunkedAes256Gcm),
+                    creation: None,
+                    tier: None,
+                    path: None,
+                }),
+            ]),
+            disk_consistent_lsn: "0/16960E8".parse::<Lsn>().unwrap(),
+            metadata_bytes: vec![1, 2, 3],
+            deleted_at: None,
+            generation: None,
+        };
+
+        let part = serde_json::from_str::<IndexPart>(example).unwrap();
+        assert_eq!(part, expected);
+
+        // The layer is compressed, then encrypted
+        let layer_metadata =
+            LayerFileMetadata::from(expected.layer_metadata.values().next().unwrap());
+        assert_eq!(layer_metadata.file_size(), 25600000);
+        assert_eq!(layer_metadata.compressed_size(), 6400000);
+        assert_eq!(
+            layer_metadata.remote_size(),
+            LayerEncryption::ChunkedAes256Gcm.encrypted_size(6400000)
+        );
+    }
+
+    #[test]
+    fn v6_indexpart_is_parsed_with_generation() {
+        let example = r#"{
+            "version":6,
+            "generation":7,
+            "timeline_layers":["000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9"],
+            "layer_metadata":{
+                "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9": { "file_size": 25600000, "crc32c": 3817370166 }
+            },
+            "disk_consistent_lsn":"0/16960E8",
+            "metadata_bytes":[1,2,3]
+        }"#;
+
+        let expected = IndexPart {
+            version: 6,
+            timeline_layers: HashSet::from(["000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap()]),
+            layer_metadata: HashMap::from([
+                ("000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap(), IndexLayerMetadata {
+                    file_size: 25600000,
+                    crc32c: Some(3817370166),
+                    compression: None,
+                    encryption: None,
+                    creation: None,
+                    tier: None,
+                    path: None,
+                }),
+            ]),
+            disk_consistent_lsn: "0/16960E8".parse::<Lsn>().unwrap(),
+            metadata_bytes: vec![1, 2, 3],
+            deleted_at: None,
+            generation: Some(Generation::new(7)),
+        };
+
+        let part = serde_json::from_str::<IndexPart>(example).unwrap();
+        assert_eq!(part, expected);
+    }
+
+    #[test]
+    fn v7_indexpart_is_parsed_with_layer_creation() {
+        let example = r#"{
+            "version":7,
+            "timeline_layers":["000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9"],
+            "layer_metadata":{
+                "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9": { "file_size": 25600000, "crc32c": 3817370166, "creation": { "created_at": "2023-08-14T12:30:00.456", "node_id": 1, "compaction_level": 0, "source": "flush" } }
+            },
+            "disk_consistent_lsn":"0/16960E8",
+            "metadata_bytes":[1,2,3]
+        }"#;
+
+        let creation = LayerCreation {
+            created_at: "2023-08-14T12:30:00.456".parse().unwrap(),
+            node_id: NodeId(1),
+            compaction_level: 0,
+            source: LayerSource::Flush,
+        };
+        let expected = IndexPart {
+            version: 7,
+            timeline_layers: HashSet::from(["000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap()]),
+            layer_metadata: HashMap::from([
+                ("000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap(), IndexLayerMetadata {
+                    file_size: 25600000,
+                    crc32c: Some(3817370166),
+                    compression: None,
+                    encryption: None,
+                    creation: Some(creation),
+                    tier: None,
+                    path: None,
+                }),
+            ]),
+            disk_consistent_lsn: "0/16960E8".parse::<Lsn>().unwrap(),
+            metadata_bytes: vec![1, 2, 3],
+            deleted_at: None,
+            generation: None,
+        };
+
+        let part = serde_json::from_str::<IndexPart>(example).unwrap();
+        assert_eq!(part, expected);
+
+        // The creation is kept once the layer is uploaded again
+        let layer_metadata =
+            LayerFileMetadata::from(expected.layer_metadata.values().next().unwrap());
+        let uploaded = layer_metadata.uploaded(None, 3817370166, None, None);
+        assert_eq!(uploaded.creation(), Some(creation));
+    }
+
+    #[test]
+    fn v9_indexpart_is_parsed_with_layer_tier() {
+        let example = r#"{
+            "version":9,
+            "timeline_layers":["000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9"],
+            "layer_metadata":{
+                "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9": { "file_size": 25600000, "crc32c": 3817370166, "tier": "cold" }
+            },
+            "disk_consistent_lsn":"0/16960E8",
+            "metadata_bytes":[1,2,3]
+        }"#;
+
+        let expected = IndexPart {
+            version: 9,
+            timeline_layers: HashSet::from(["000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap()]),
+            layer_metadata: HashMap::from([
+                ("000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap(), IndexLayerMetadata {
+                    file_size: 25600000,
+                    crc32c: Some(3817370166),
+                    compression: None,
+                    encryption: None,
+                    creation: None,
+                    tier: Some(LayerTier::Cold),
+                    path: None,
+                }),
+            ]),
+            disk_consistent_lsn: "0/16960E8".parse::<Lsn>().unwrap(),
+            metadata_bytes: vec![1, 2, 3],
+            deleted_at: None,
+            generation: None,
         };
 
         let part = serde_json::from_str::<IndexPart>(example).unwrap();
         assert_eq!(part, expected);
+
+        // A layer uploaded again is at the default location
+        let layer_metadata =
+            LayerFileMetadata::from(expected.layer_metadata.values().next().unwrap());
+        assert_eq!(layer_metadata.tier(), Some(LayerTier::Cold));
+        let uploaded = layer_metadata.uploaded(None, 3817370166, None, None);
+        assert_eq!(uploaded.tier(), None);
+    }
+
+    #[test]
+    fn v10_indexpart_is_parsed_with_layer_path() {
+        let example = r#"{
+            "version":10,
+            "timeline_layers":["000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9"],
+            "layer_metadata":{
+                "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9": { "file_size": 25600000, "crc32c": 3817370166, "tier": "cold", "path": "3a/000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9" }
+            },
+            "disk_consistent_lsn":"0/16960E8",
+            "metadata_bytes":[1,2,3]
+        }"#;
+
+        let expected = IndexPart {
+            version: 10,
+            timeline_layers: HashSet::from(["000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap()]),
+            layer_metadata: HashMap::from([
+                ("000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap(), IndexLayerMetadata {
+                    file_size: 25600000,
+                    crc32c: Some(3817370166),
+                    compression: None,
+                    encryption: None,
+                    creation: None,
+                    tier: Some(LayerTier::Cold),
+                    path: Some("3a/000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".to_string()),
+                }),
+            ]),
+            disk_consistent_lsn: "0/16960E8".parse::<Lsn>().unwrap(),
+            metadata_bytes: vec![1, 2, 3],
+            deleted_at: None,
+            generation: None,
+        };
+
+        let part = serde_json::from_str::<IndexPart>(example).unwrap();
+        assert_eq!(part, expected);
+
+        // The tier applies to the path of the layer
+        let (layer_file_name, index_metadata) = expected.layer_metadata.iter().next().unwrap();
+        let layer_metadata = LayerFileMetadata::from(index_metadata);
+        let timeline_path = RemotePath::new(Path::new("tenants/t/timelines/tl")).unwrap();
+        assert_eq!(
+            layer_metadata.remote_path(&timeline_path, layer_file_name),
+            RemotePath::new(Path::new(&format!(
+                "cold/tenants/t/timelines/tl/3a/{}",
+                layer_file_name.file_name()
+            )))
+            .unwrap()
+        );
+
+        // A layer uploaded again goes where the scheme says
+        let uploaded = layer_metadata.uploaded(None, 3817370166, None, None);
+        assert_eq!(uploaded.path(), None);
+        assert_eq!(
+            uploaded.remote_path(&timeline_path, layer_file_name),
+            timeline_path.join(Path::new(&layer_file_name.file_name()))
+        );
+    }
+
+    #[test]
+    fn layer_paths_out_of_the_timeline_are_rejected() {
+        let layer = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9";
+        let index = |path: &str| {
+            format!(
+                r#"{{
+                    "version":10,
+                    "timeline_layers":["{layer}"],
+                    "layer_metadata":{{ "{layer}": {{ "file_size": 25600000, "path": "{path}" }} }},
+                    "disk_consistent_lsn":"0/16960E8",
+                    "metadata_bytes":[1,2,3]
+                }}"#
+            )
+        };
+
+        assert!(IndexPart::from_json_bytes(index(&format!("3a/{layer}")).as_bytes()).is_ok());
+        for path in [
+            format!("/{layer}"),
+            format!("/3a/{layer}"),
+            format!("../{layer}"),
+            format!("3a/../../{layer}"),
+            format!("3a/{layer}/.."),
+            format!("3a/b/{layer}"),
+            format!("3g/{layer}"),
+            "3a/000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960EA".to_string(),
+            layer.to_string(),
+        ] {
+            let res = IndexPart::from_json_bytes(index(&path).as_bytes());
+            assert!(res.is_err(), "{path} was accepted");
+        }
+    }
+
+    #[test]
+    fn index_file_names() {
+        assert_eq!(IndexPart::file_name(None), "index_part.json");
+        assert_eq!(
+            IndexPart::file_name(Some(Generation::new(0x1c))),
+            "index_part.json-0000001c"
+        );
+
+        assert_eq!(IndexPart::parse_file_name("index_part.json"), Some(None));
+        assert_eq!(
+            IndexPart::parse_file_name("index_part.json-0000001c"),
+            Some(Some(Generation::new(0x1c)))
+        );
+        assert_eq!(IndexPart::parse_file_name("index_part.json-1c"), None);
+        assert_eq!(IndexPart::parse_file_name("index_part.json-0000001c___temp"), None);
+        assert_eq!(IndexPart::parse_file_name("index_part.json.old"), None);
+        assert_eq!(
+            IndexPart::parse_file_name(
+                "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9"
+            ),
+            None
+        );
     }
 
     #[test]
@@ -262,10 +1010,83 @@ mod tests {
             ]
             .to_vec(),
             deleted_at: None,
+            generation: None,
         };
 
         let empty_layers_parsed = serde_json::from_str::<IndexPart>(empty_layers_json).unwrap();
 
         assert_eq!(empty_layers_parsed, expected);
     }
+
+    fn read_fixture(version: usize) -> anyhow::Result<Vec<u8>> {
+        std::fs::read(format!("fixtures/index_part/v{version}.json"))
+            .with_context(|| format!("no fixture of index part version {version}"))
+    }
+
+    /// Every version has a fixture, written by the release that introduced it, and is upgraded
+    /// to the latest version when read.
+    #[test]
+    fn all_versions_are_upgraded() -> anyhow::Result<()> {
+        for version in 1..=IndexPart::LATEST_VERSION {
+            let bytes = read_fixture(version)?;
+            let raw: serde_json::Value = serde_json::from_slice(&bytes)?;
+            assert_eq!(raw["version"], version);
+
+            let index_part = IndexPart::from_json_bytes(&bytes)
+                .with_context(|| format!("parse index part version {version}"))?;
+            assert_eq!(index_part.version(), IndexPart::LATEST_VERSION);
+            assert_eq!(index_part.timeline_layers.len(), 2);
+            assert_eq!(index_part.layer_metadata.len(), 2);
+            assert_eq!(index_part.disk_consistent_lsn, Lsn(0x16960E8));
+            let metadata = index_part.parse_metadata()?;
+            assert_eq!(metadata.disk_consistent_lsn(), index_part.disk_consistent_lsn);
+
+            let written = serde_json::to_vec(&index_part)?;
+            assert_eq!(IndexPart::from_json_bytes(&written)?, index_part);
+        }
+        Ok(())
+    }
+
+    /// The index written by a later release is read by this one, as after a rollback.
+    #[test]
+    fn later_version_is_parsed() -> anyhow::Result<()> {
+        let layer_file_name = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9";
+        let mut raw: serde_json::Value =
+            serde_json::from_slice(&read_fixture(IndexPart::LATEST_VERSION)?)?;
+        raw["version"] = (IndexPart::LATEST_VERSION + 1).into();
+        raw["added_later"] = "ignored".into();
+        raw["layer_metadata"][layer_file_name]["added_later"] = "ignored".into();
+
+        let index_part = IndexPart::from_json_bytes(&serde_json::to_vec(&raw)?)?;
+        assert_eq!(index_part.version(), IndexPart::LATEST_VERSION + 1);
+        let expected = IndexPart::from_json_bytes(&read_fixture(IndexPart::LATEST_VERSION)?)?;
+        assert_eq!(index_part.layer_metadata, expected.layer_metadata);
+        assert_eq!(index_part.generation, expected.generation);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unversioned_index_is_upgraded() -> anyhow::Result<()> {
+        let mut raw: serde_json::Value = serde_json::from_slice(&read_fixture(1)?)?;
+        raw.as_object_mut().unwrap().remove("version");
+
+        let index_part = IndexPart::from_json_bytes(&serde_json::to_vec(&raw)?)?;
+        assert_eq!(index_part.version(), IndexPart::LATEST_VERSION);
+        assert_eq!(index_part, IndexPart::from_json_bytes(&read_fixture(1)?)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_file_name() {
+        let lsn = "0/16960E8".parse::<Lsn>().unwrap();
+        let name = IndexPart::snapshot_file_name(lsn);
+        assert_eq!(name, "index_part-00000000016960E8.json");
+        assert_eq!(IndexPart::parse_snapshot_file_name(&name), Some(lsn));
+
+        assert_eq!(IndexPart::parse_snapshot_file_name(IndexPart::FILE_NAME), None);
+        assert_eq!(IndexPart::parse_snapshot_file_name("index_part-16960E8.json"), None);
+        assert_eq!(IndexPart::parse_file_name(&name), None);
+    }
 }
@@ -0,0 +1,441 @@
+//! The chunked form of the index, for the timelines with many layers.
+//!
+//! The index of a timeline is uploaded again on every change of its layers, which for hundreds
+//! of thousands of layers is megabytes each time. With `remote_index_segment_layers` set, the
+//! index of a timeline with more layers than that is written as immutable segments of
+//! `remote_index_segment_layers` layers each, and a manifest referencing them, stored under the
+//! name of the index file:
+//!
+//! - the manifest holds the layers that aren't in a segment, the tail, and lists the layers of
+//!   the segments that have been removed or changed since. An index upload rewrites the
+//!   manifest, and writes a segment only once the tail has enough layers for one;
+//! - a segment with half of its layers removed is dropped, its other layers going back to the
+//!   tail, and so are all the segments once the manifest lists a segment's worth of removed
+//!   layers.
+//!
+//! The index of a timeline with fewer layers, and the index with the deletion mark, are written
+//! in the plain form, and both forms are read the same. The releases before index version 8
+//! can't read a manifest, and fail to load the timeline: before a rollback, unset
+//! `remote_index_segment_layers` and have the timelines upload their index again.
+
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use anyhow::{ensure, Context};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use utils::lsn::Lsn;
+
+use super::{IndexLayerMetadata, IndexPart};
+use crate::tenant::generation::Generation;
+use crate::tenant::storage_layer::LayerFileName;
+
+/// The manifest of a chunked index, stored under the name of the index file.
+#[serde_as]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct IndexManifest {
+    version: usize,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<NaiveDateTime>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation: Option<Generation>,
+
+    /// In the order they were written.
+    pub(crate) segments: Vec<SegmentRef>,
+
+    /// The layers of the segments that have been removed from the index, or changed since.
+    removed_layers: HashSet<LayerFileName>,
+
+    /// The layers that aren't in any segment.
+    tail: HashMap<LayerFileName, IndexLayerMetadata>,
+
+    #[serde_as(as = "DisplayFromStr")]
+    disk_consistent_lsn: Lsn,
+    metadata_bytes: Vec<u8>,
+}
+
+impl IndexManifest {
+    /// Parse an index file as a manifest, `None` if it is a plain index part.
+    pub(crate) fn from_json_bytes(bytes: &[u8]) -> anyhow::Result<Option<Self>> {
+        let value: serde_json::Value = serde_json::from_slice(bytes)?;
+        if value.get("segments").is_none() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_value(value)?))
+    }
+
+    /// Put the index back together, from the segments read in the order of
+    /// [`IndexManifest::segments`].
+    pub(crate) fn into_index_part(
+        self,
+        segments: Vec<IndexSegment>,
+    ) -> anyhow::Result<(IndexPart, IndexSegments)> {
+        ensure!(
+            segments.len() == self.segments.len(),
+            "got {} index segments for the {} of the manifest",
+            segments.len(),
+            self.segments.len()
+        );
+
+        let mut layer_metadata = HashMap::new();
+        let mut read = Vec::with_capacity(segments.len());
+        for (segment_ref, segment) in self.segments.iter().zip(segments) {
+            ensure!(
+                segment.layer_metadata.len() == segment_ref.layers,
+                "index segment {} has {} layers instead of {}",
+                segment_ref.file_name(),
+                segment.layer_metadata.len(),
+                segment_ref.layers
+            );
+            layer_metadata.extend(
+                segment
+                    .layer_metadata
+                    .iter()
+                    .map(|(name, metadata)| (name.clone(), metadata.clone())),
+            );
+            read.push((*segment_ref, Arc::new(segment)));
+        }
+        for name in &self.removed_layers {
+            layer_metadata.remove(name);
+        }
+        layer_metadata.extend(self.tail);
+
+        let next_seq = self.segments.iter().map(|s| s.seq + 1).max().unwrap_or(0);
+        let index_part = IndexPart {
+            version: self.version,
+            deleted_at: self.deleted_at,
+            generation: self.generation,
+            timeline_layers: layer_metadata.keys().cloned().collect(),
+            layer_metadata,
+            disk_consistent_lsn: self.disk_consistent_lsn,
+            metadata_bytes: self.metadata_bytes,
+        };
+        index_part.check_layer_paths()?;
+        let segments = IndexSegments {
+            segments: read,
+            next_seq,
+        };
+        Ok((index_part, segments))
+    }
+}
+
+/// A segment referenced from the manifest.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct SegmentRef {
+    /// Increasing over the segments of the timeline.
+    seq: u32,
+
+    /// The generation of the attachment that wrote the segment.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation: Option<Generation>,
+
+    /// Checked when reading the segment.
+    layers: usize,
+}
+
+impl SegmentRef {
+    const FILE_NAME_PREFIX: &'static str = "index_segment-";
+
+    pub(crate) fn file_name(&self) -> String {
+        match self.generation {
+            Some(generation) => {
+                format!("{}{:08x}-{generation}", Self::FILE_NAME_PREFIX, self.seq)
+            }
+            None => format!("{}{:08x}", Self::FILE_NAME_PREFIX, self.seq),
+        }
+    }
+
+    /// Whether the object name is of an index segment.
+    pub(crate) fn is_file_name(name: &str) -> bool {
+        name.starts_with(Self::FILE_NAME_PREFIX)
+    }
+}
+
+/// A segment of a chunked index, never modified once written.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub(crate) struct IndexSegment {
+    version: usize,
+    layer_metadata: HashMap<LayerFileName, IndexLayerMetadata>,
+}
+
+/// The segments of the index of a timeline, as last uploaded or downloaded.
+#[derive(Debug, Default)]
+pub(crate) struct IndexSegments {
+    segments: Vec<(SegmentRef, Arc<IndexSegment>)>,
+    next_seq: u32,
+}
+
+/// The objects to write for an index upload.
+pub(crate) struct IndexUpload {
+    /// The new segments, to upload before the index file.
+    pub(crate) segments: Vec<(SegmentRef, Arc<IndexSegment>)>,
+
+    /// The manifest, or the plain index part if there are no segments.
+    pub(crate) index_bytes: Vec<u8>,
+
+    /// The segments no longer referenced once the index file is uploaded.
+    pub(crate) obsolete: Vec<SegmentRef>,
+
+    /// The segments once the index file is uploaded.
+    pub(crate) uploaded: IndexSegments,
+}
+
+impl IndexSegments {
+    /// Plan the upload of an index, keeping the segments that are still mostly accurate.
+    pub(crate) fn plan_upload(
+        &self,
+        index_part: &IndexPart,
+        segment_layers: Option<NonZeroUsize>,
+    ) -> anyhow::Result<IndexUpload> {
+        let mut kept = Vec::new();
+        let mut obsolete = Vec::new();
+        for (segment_ref, segment) in &self.segments {
+            let removed = segment
+                .layer_metadata
+                .iter()
+                .filter(|(name, metadata)| index_part.layer_metadata.get(*name) != Some(*metadata))
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>();
+            if segment_layers.is_none() || removed.len() * 2 >= segment.layer_metadata.len() {
+                obsolete.push(*segment_ref);
+            } else {
+                kept.push((segment_ref, segment, removed));
+            }
+        }
+        let removed_count: usize = kept.iter().map(|(_, _, removed)| removed.len()).sum();
+        if matches!(segment_layers, Some(layers) if removed_count >= layers.get()) {
+            obsolete.extend(kept.drain(..).map(|(segment_ref, _, _)| *segment_ref));
+        }
+
+        let mut removed_layers = HashSet::new();
+        let mut sealed = HashSet::new();
+        for (_, segment, removed) in &kept {
+            removed_layers.extend(removed.iter().map(|name| (*name).clone()));
+            sealed.extend(segment.layer_metadata.keys());
+        }
+
+        // A removed layer added back stays in the tail, the readers drop it from every segment
+        let (mut tail, mut sealable): (Vec<_>, Vec<_>) = index_part
+            .layer_metadata
+            .iter()
+            .filter(|(name, _)| !sealed.contains(name) || removed_layers.contains(*name))
+            .partition(|(name, _)| removed_layers.contains(*name));
+        sealable.sort_by_cached_key(|(name, _)| name.file_name());
+
+        let mut segments = kept
+            .iter()
+            .map(|(segment_ref, segment, _)| (**segment_ref, Arc::clone(segment)))
+            .collect::<Vec<_>>();
+        let mut new_segments = Vec::new();
+        let mut next_seq = self.next_seq;
+        if let Some(layers) = segment_layers {
+            let chunks = sealable.chunks_exact(layers.get());
+            tail.extend_from_slice(chunks.remainder());
+            for chunk in chunks {
+                let segment = IndexSegment {
+                    version: IndexPart::LATEST_VERSION,
+                    layer_metadata: chunk
+                        .iter()
+                        .map(|(name, metadata)| ((*name).clone(), (*metadata).clone()))
+                        .collect(),
+                };
+                let segment_ref = SegmentRef {
+                    seq: next_seq,
+                    generation: index_part.generation,
+                    layers: chunk.len(),
+                };
+                next_seq += 1;
+                new_segments.push((segment_ref, Arc::new(segment)));
+            }
+        }
+        segments.extend(new_segments.iter().cloned());
+
+        let index_bytes = if segments.is_empty() {
+            serde_json::to_vec(index_part).context("serialize index part")?
+        } else {
+            let manifest = IndexManifest {
+                version: IndexPart::LATEST_VERSION,
+                deleted_at: index_part.deleted_at,
+                generation: index_part.generation,
+                segments: segments.iter().map(|(segment_ref, _)| *segment_ref).collect(),
+                removed_layers,
+                tail: tail
+                    .into_iter()
+                    .map(|(name, metadata)| (name.clone(), metadata.clone()))
+                    .collect(),
+                disk_consistent_lsn: index_part.disk_consistent_lsn,
+                metadata_bytes: index_part.metadata_bytes.clone(),
+            };
+            serde_json::to_vec(&manifest).context("serialize index manifest")?
+        };
+
+        Ok(IndexUpload {
+            segments: new_segments,
+            index_bytes,
+            obsolete,
+            uploaded: IndexSegments { segments, next_seq },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenant::remote_timeline_client::index::LayerFileMetadata;
+
+    fn layer_file_name(i: u64) -> LayerFileName {
+        format!(
+            "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__{i:016X}-{:016X}",
+            i + 1
+        )
+        .parse()
+        .unwrap()
+    }
+
+    fn index_part(layers: impl Iterator<Item = u64>) -> IndexPart {
+        let layers = layers
+            .map(|i| (layer_file_name(i), LayerFileMetadata::new(i + 1)))
+            .collect();
+        IndexPart::new(layers, Lsn(0x16960E8), vec![1, 2, 3])
+    }
+
+    /// Upload the index to a map of the object names to their contents, and read it back.
+    fn upload(
+        segments: &IndexSegments,
+        index_part: &IndexPart,
+        objects: &mut HashMap<String, Vec<u8>>,
+    ) -> anyhow::Result<IndexSegments> {
+        let upload = segments.plan_upload(index_part, NonZeroUsize::new(10))?;
+        for (segment_ref, segment) in &upload.segments {
+            let name = segment_ref.file_name();
+            anyhow::ensure!(!objects.contains_key(&name), "segment {name} written twice");
+            objects.insert(name, serde_json::to_vec(segment.as_ref())?);
+        }
+        objects.insert(IndexPart::FILE_NAME.to_string(), upload.index_bytes);
+        for segment_ref in &upload.obsolete {
+            objects.remove(&segment_ref.file_name());
+        }
+
+        let (read_index, read_segments) = read(objects)?;
+        assert_eq!(read_index.layer_metadata, index_part.layer_metadata);
+        assert_eq!(read_index.timeline_layers, index_part.timeline_layers);
+        assert_eq!(read_index.disk_consistent_lsn, index_part.disk_consistent_lsn);
+        assert_eq!(read_segments.segments.len(), upload.uploaded.segments.len());
+        Ok(upload.uploaded)
+    }
+
+    fn read(objects: &HashMap<String, Vec<u8>>) -> anyhow::Result<(IndexPart, IndexSegments)> {
+        let bytes = &objects[IndexPart::FILE_NAME];
+        let Some(manifest) = IndexManifest::from_json_bytes(bytes)? else {
+            return Ok((IndexPart::from_json_bytes(bytes)?, IndexSegments::default()));
+        };
+        let segments = manifest
+            .segments
+            .iter()
+            .map(|segment_ref| {
+                let bytes = objects
+                    .get(&segment_ref.file_name())
+                    .context("missing index segment")?;
+                Ok(serde_json::from_slice(bytes)?)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        manifest.into_index_part(segments)
+    }
+
+    #[test]
+    fn small_index_is_plain() -> anyhow::Result<()> {
+        let mut objects = HashMap::new();
+        let segments = upload(&IndexSegments::default(), &index_part(0..9), &mut objects)?;
+        assert!(segments.segments.is_empty());
+        assert_eq!(objects.len(), 1);
+        assert!(IndexManifest::from_json_bytes(&objects[IndexPart::FILE_NAME])?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn only_tail_is_rewritten() -> anyhow::Result<()> {
+        let mut objects = HashMap::new();
+        let segments = upload(&IndexSegments::default(), &index_part(0..25), &mut objects)?;
+        assert_eq!(segments.segments.len(), 2);
+        assert_eq!(objects.len(), 3);
+
+        // The layers added since go to the tail, until there are enough for a segment
+        let segments = upload(&segments, &index_part(0..29), &mut objects)?;
+        assert_eq!(segments.segments.len(), 2);
+        let segments = upload(&segments, &index_part(0..31), &mut objects)?;
+        assert_eq!(segments.segments.len(), 3);
+        assert_eq!(objects.len(), 4);
+
+        // Downloaded segments are kept too
+        let (_, downloaded) = read(&objects)?;
+        let segments = upload(&downloaded, &index_part(0..32), &mut objects)?;
+        assert_eq!(segments.segments.len(), 3);
+        assert_eq!(objects.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn removed_layers_are_dropped_from_segments() -> anyhow::Result<()> {
+        let mut objects = HashMap::new();
+        let segments = upload(&IndexSegments::default(), &index_part(0..30), &mut objects)?;
+        assert_eq!(segments.segments.len(), 3);
+
+        // A few removals are listed in the manifest
+        let segments = upload(&segments, &index_part((3..30).chain(30..33)), &mut objects)?;
+        assert_eq!(segments.segments.len(), 3);
+
+        // A removed layer added back is read back too
+        let segments = upload(&segments, &index_part((2..30).chain(30..33)), &mut objects)?;
+        assert_eq!(segments.segments.len(), 3);
+
+        // Half of the first segment removed, it is dropped
+        let segments = upload(&segments, &index_part((5..30).chain(30..33)), &mut objects)?;
+        assert_eq!(segments.segments.len(), 2);
+        assert_eq!(objects.len(), 3);
+        assert!(!objects.contains_key(&SegmentRef {
+            seq: 0,
+            generation: None,
+            layers: 10,
+        }
+        .file_name()));
+
+        // Fewer layers than a segment
+        let segments = upload(&segments, &index_part(25..33), &mut objects)?;
+        assert!(segments.segments.is_empty());
+        assert_eq!(objects.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn changed_layers_are_read_back() -> anyhow::Result<()> {
+        let mut objects = HashMap::new();
+        let mut index = index_part(0..20);
+        let segments = upload(&IndexSegments::default(), &index, &mut objects)?;
+        assert_eq!(segments.segments.len(), 2);
+
+        let metadata = index.layer_metadata.get_mut(&layer_file_name(3)).unwrap();
+        metadata.crc32c = Some(42);
+        let segments = upload(&segments, &index, &mut objects)?;
+        assert_eq!(segments.segments.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_segment_layers_are_detected() -> anyhow::Result<()> {
+        let mut objects = HashMap::new();
+        upload(&IndexSegments::default(), &index_part(0..20), &mut objects)?;
+        let manifest = IndexManifest::from_json_bytes(&objects[IndexPart::FILE_NAME])?;
+        let mut manifest = manifest.unwrap();
+        manifest.segments[0].layers += 1;
+        objects.insert(IndexPart::FILE_NAME.to_string(), serde_json::to_vec(&manifest)?);
+        assert!(read(&objects).is_err());
+        Ok(())
+    }
+}
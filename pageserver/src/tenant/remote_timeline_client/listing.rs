@@ -0,0 +1,264 @@
+//! Listing of the timelines of a tenant in the remote storage, when it's attached.
+//!
+//! With thousands of timelines, an attach would list them, then download all their indexes at
+//! once, with a LIST request of the index files of each when the tenant has a generation. The
+//! `remote_listing` setting limits that fan-out, for example:
+//!
+//! ```toml
+//! remote_listing = { max_concurrent_lists = 16, max_concurrent_index_downloads = 64, manifest_max_age = '1d' }
+//! ```
+//!
+//! - the LIST requests of all the tenants share `max_concurrent_lists` permits,
+//! - an attach downloads at most `max_concurrent_index_downloads` indexes at once,
+//! - the timelines found by a listing are recorded in the timeline manifest of the tenant, in
+//!   its remote storage. An attach within `manifest_max_age` of that listing takes the
+//!   timelines from the manifest instead of listing them again.
+//!
+//! A timeline is added to the manifest before its first index upload, and only removed by the
+//! next listing: the manifest has all the timelines of the tenant, and maybe some that have
+//! been deleted or whose creation failed since, which the attach skips.
+
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{NaiveDateTime, Utc};
+use once_cell::sync::OnceCell;
+use remote_storage::{DownloadError, GenericRemoteStorage, RemotePath};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use utils::id::{TenantId, TimelineId};
+
+use super::download;
+use crate::config::PageServerConf;
+
+pub const DEFAULT_MAX_CONCURRENT_LISTS: NonZeroUsize = match NonZeroUsize::new(16) {
+    Some(x) => x,
+    None => panic!("const unwrap is not yet stable"),
+};
+
+pub const DEFAULT_MAX_CONCURRENT_INDEX_DOWNLOADS: NonZeroUsize = match NonZeroUsize::new(64) {
+    Some(x) => x,
+    None => panic!("const unwrap is not yet stable"),
+};
+
+pub const DEFAULT_MANIFEST_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The timeline manifest, next to the `timelines` prefix of the tenant.
+pub const TIMELINE_MANIFEST_FILE_NAME: &str = "timeline_manifest.json";
+
+fn default_max_concurrent_lists() -> NonZeroUsize {
+    DEFAULT_MAX_CONCURRENT_LISTS
+}
+
+fn default_max_concurrent_index_downloads() -> NonZeroUsize {
+    DEFAULT_MAX_CONCURRENT_INDEX_DOWNLOADS
+}
+
+fn default_manifest_max_age() -> Duration {
+    DEFAULT_MANIFEST_MAX_AGE
+}
+
+/// The `remote_listing` setting, see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteListingConfig {
+    #[serde(default = "default_max_concurrent_lists")]
+    pub max_concurrent_lists: NonZeroUsize,
+    #[serde(default = "default_max_concurrent_index_downloads")]
+    pub max_concurrent_index_downloads: NonZeroUsize,
+    /// Zero always lists the timelines.
+    #[serde(with = "humantime_serde", default = "default_manifest_max_age")]
+    pub manifest_max_age: Duration,
+}
+
+impl Default for RemoteListingConfig {
+    fn default() -> Self {
+        RemoteListingConfig {
+            max_concurrent_lists: DEFAULT_MAX_CONCURRENT_LISTS,
+            max_concurrent_index_downloads: DEFAULT_MAX_CONCURRENT_INDEX_DOWNLOADS,
+            manifest_max_age: DEFAULT_MANIFEST_MAX_AGE,
+        }
+    }
+}
+
+static LIST_PERMITS: OnceCell<Semaphore> = OnceCell::new();
+
+/// Wait for one of the `max_concurrent_lists` permits, to make a LIST request.
+pub(super) async fn list_permit(conf: &'static PageServerConf) -> SemaphorePermit<'static> {
+    LIST_PERMITS
+        .get_or_init(|| Semaphore::new(conf.remote_listing.max_concurrent_lists.get()))
+        .acquire()
+        .await
+        .expect("the semaphore is never closed")
+}
+
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct TimelineManifest {
+    /// When the timelines were last listed.
+    listed_at: NaiveDateTime,
+    #[serde_as(as = "HashSet<DisplayFromStr>")]
+    timelines: HashSet<TimelineId>,
+}
+
+/// The timelines of a tenant to attach, see [`list_timelines_for_attach`].
+#[derive(Debug)]
+pub struct AttachTimelines {
+    pub timeline_ids: HashSet<TimelineId>,
+    /// The timelines come from the manifest, some may not exist.
+    pub from_manifest: bool,
+}
+
+/// The timelines of a tenant to attach: those of its manifest if it's recent enough and
+/// `use_manifest`, else those listed, recorded in a new manifest.
+pub async fn list_timelines_for_attach(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    tenant_id: TenantId,
+    use_manifest: bool,
+    cancel: &CancellationToken,
+) -> anyhow::Result<AttachTimelines> {
+    let max_age = conf.remote_listing.manifest_max_age;
+    if use_manifest && !max_age.is_zero() {
+        match download_manifest(conf, storage, tenant_id, cancel).await {
+            Ok(Some(manifest)) => {
+                let age = (Utc::now().naive_utc() - manifest.listed_at)
+                    .to_std()
+                    .unwrap_or_default();
+                if age < max_age {
+                    info!(
+                        "found {} timelines in the manifest listed {}s ago",
+                        manifest.timelines.len(),
+                        age.as_secs()
+                    );
+                    return Ok(AttachTimelines {
+                        timeline_ids: manifest.timelines,
+                        from_manifest: true,
+                    });
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("failed to download the timeline manifest, listing: {e:#}"),
+        }
+    }
+
+    let timeline_ids = download::list_remote_timelines(storage, conf, tenant_id, cancel).await?;
+    if !max_age.is_zero() {
+        let manifest = TimelineManifest {
+            listed_at: Utc::now().naive_utc(),
+            timelines: timeline_ids.clone(),
+        };
+        if let Err(e) = upload_manifest(conf, storage, tenant_id, &manifest).await {
+            warn!("failed to upload the timeline manifest: {e:#}");
+        }
+    }
+    Ok(AttachTimelines {
+        timeline_ids,
+        from_manifest: false,
+    })
+}
+
+/// Add a timeline about to be created to the manifest of the tenant, if it has one. The caller
+/// serializes the manifest updates of the tenant.
+pub async fn add_to_timeline_manifest(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let Some(mut manifest) = download_manifest(conf, storage, tenant_id, cancel).await? else {
+        return Ok(());
+    };
+    if manifest.timelines.insert(timeline_id) {
+        upload_manifest(conf, storage, tenant_id, &manifest)
+            .await
+            .context("add the timeline to the timeline manifest")?;
+    }
+    Ok(())
+}
+
+fn manifest_path(conf: &'static PageServerConf, tenant_id: TenantId) -> anyhow::Result<RemotePath> {
+    let tenant_storage_path = conf.remote_path(&conf.tenant_path(&tenant_id))?;
+    Ok(tenant_storage_path.join(Path::new(TIMELINE_MANIFEST_FILE_NAME)))
+}
+
+async fn download_manifest(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    tenant_id: TenantId,
+    cancel: &CancellationToken,
+) -> anyhow::Result<Option<TimelineManifest>> {
+    let path = manifest_path(conf, tenant_id)?;
+    let bytes = match download::download_bytes(conf, storage, &path, cancel).await {
+        Ok((bytes, _)) => bytes,
+        Err(DownloadError::NotFound) => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("download {path:?}")),
+    };
+    let manifest = serde_json::from_slice(&bytes).with_context(|| format!("parse {path:?}"))?;
+    Ok(Some(manifest))
+}
+
+async fn upload_manifest(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    tenant_id: TenantId,
+    manifest: &TimelineManifest,
+) -> anyhow::Result<()> {
+    let path = manifest_path(conf, tenant_id)?;
+    let bytes = serde_json::to_vec(manifest).context("serialize timeline manifest")?;
+    let size = bytes.len();
+    storage
+        .upload_storage_object(
+            Box::new(tokio::io::BufReader::new(std::io::Cursor::new(bytes))),
+            size,
+            &path,
+        )
+        .await
+        .with_context(|| format!("upload {path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use remote_storage::LocalFs;
+
+    #[tokio::test]
+    async fn timeline_manifest() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let conf = Box::leak(Box::new(PageServerConf::dummy_conf(dir.path().join("repo"))));
+        let storage = GenericRemoteStorage::LocalFs(LocalFs::new(dir.path().join("remote"))?);
+        let tenant_id = TenantId::generate();
+        let cancel = CancellationToken::new();
+        let (first, second) = (TimelineId::generate(), TimelineId::generate());
+        let timelines_path = dir
+            .path()
+            .join("remote")
+            .join(conf.remote_path(&conf.timelines_path(&tenant_id))?.get_path());
+        std::fs::create_dir_all(timelines_path.join(first.to_string()))?;
+
+        // Without a manifest, the timelines are listed, and recorded in a new manifest
+        let timelines = list_timelines_for_attach(conf, &storage, tenant_id, true, &cancel).await?;
+        assert!(!timelines.from_manifest);
+        assert_eq!(timelines.timeline_ids, HashSet::from([first]));
+
+        // The timelines created since are added to it, the attach takes them from there
+        add_to_timeline_manifest(conf, &storage, tenant_id, second, &cancel).await?;
+        let timelines = list_timelines_for_attach(conf, &storage, tenant_id, true, &cancel).await?;
+        assert!(timelines.from_manifest);
+        assert_eq!(timelines.timeline_ids, HashSet::from([first, second]));
+
+        // Unless told to list them
+        let timelines = list_timelines_for_attach(conf, &storage, tenant_id, false, &cancel).await?;
+        assert!(!timelines.from_manifest);
+        assert_eq!(timelines.timeline_ids, HashSet::from([first]));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,405 @@
+//! Mirroring of the remote storage to a secondary remote storage.
+//!
+//! With `remote_storage_mirror` set, every layer file and index uploaded to the remote storage
+//! is copied to the mirror too, and the layer file deletions are repeated there. Each timeline
+//! has a mirror queue of its own, separate from the upload queue: an operation is queued once
+//! it has completed in the remote storage, and the queued operations are performed in order,
+//! one at a time, retried until they succeed. The mirror lags behind, by the
+//! `pageserver_remote_mirror_queued_*` metrics, but an outage of the mirror doesn't hold up
+//! the uploads.
+//!
+//! The layer files are copied from the remote storage rather than uploaded from the local
+//! files, so that the mirror gets the same compressed or encrypted bytes, and a layer file
+//! evicted meanwhile is still copied. The queue isn't persisted: the operations queued at a
+//! shutdown are lost. The mirror catches up with the next index upload, which first copies
+//! the layer files referenced by the index that the mirror doesn't have.
+//!
+//! The layer files moved to the cold tier are moved in the mirror too, by a copy within the
+//! mirror, so that its index references them where they are.
+//!
+//! The tenants with a remote storage of their own aren't mirrored.
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use once_cell::sync::OnceCell;
+use remote_storage::{GenericRemoteStorage, RemotePath};
+use tokio::runtime::Runtime;
+use tracing::{debug, info, info_span, warn, Instrument};
+use utils::id::{TenantId, TimelineId};
+use utils::warn_rate_limited;
+
+use crate::config::PageServerConf;
+use crate::metrics::{
+    REMOTE_MIRROR_QUEUED_BYTES, REMOTE_MIRROR_QUEUED_OPERATIONS, REMOTE_TASK_REPEATED_FAILURES,
+};
+use crate::task_mgr::{self, TaskKind};
+use crate::tenant::storage_layer::LayerFileName;
+
+use super::copy;
+use super::delete;
+use super::index::{IndexPart, LayerFileMetadata};
+use super::sharding;
+use super::tiering::{self, LayerTier};
+
+static REMOTE_STORAGE_MIRROR: OnceCell<GenericRemoteStorage> = OnceCell::new();
+
+///
+/// Initialize the mirror of the remote storage. This must be called once at page server
+/// startup, if the mirror is configured.
+///
+pub fn init(storage: GenericRemoteStorage) {
+    if REMOTE_STORAGE_MIRROR.set(storage).is_err() {
+        panic!("remote storage mirror already initialized");
+    }
+}
+
+/// The mirror of the timelines of a tenant, `None` without a mirror or if the tenant has a
+/// remote storage of its own.
+pub(crate) fn tenant_mirror(
+    conf: &PageServerConf,
+    tenant_id: &TenantId,
+) -> Option<GenericRemoteStorage> {
+    let storage = REMOTE_STORAGE_MIRROR.get()?;
+    if conf.tenant_remote_storage_path(tenant_id).exists() {
+        return None;
+    }
+    Some(storage.clone())
+}
+
+/// An operation completed in the remote storage, to repeat in the mirror.
+#[derive(Debug)]
+pub(crate) enum MirrorOp {
+    UploadLayer(LayerFileName, LayerFileMetadata),
+    UploadIndex(IndexPart),
+    Delete(Vec<LayerFileName>),
+    /// Move the layer files to the cold tier, with their metadata.
+    Tier(Vec<(LayerFileName, LayerFileMetadata)>),
+    /// Delete all the files of the timeline.
+    DeleteTimeline,
+}
+
+impl MirrorOp {
+    fn queued_bytes(&self) -> i64 {
+        match self {
+            MirrorOp::UploadLayer(_, metadata) => metadata.file_size() as i64,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for MirrorOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MirrorOp::UploadLayer(name, _) => write!(f, "upload layer {}", name.file_name()),
+            MirrorOp::UploadIndex(index_part) => {
+                write!(f, "upload index at lsn {}", index_part.disk_consistent_lsn)
+            }
+            MirrorOp::Delete(names) => write!(f, "delete {} layers", names.len()),
+            MirrorOp::Tier(layers) => write!(f, "tier {} layers", layers.len()),
+            MirrorOp::DeleteTimeline => write!(f, "delete timeline"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct MirrorQueue {
+    ops: VecDeque<MirrorOp>,
+    /// Whether a task is performing the queued operations.
+    running: bool,
+}
+
+/// The mirror queue of a timeline.
+pub(crate) struct TimelineMirror {
+    conf: &'static PageServerConf,
+    runtime: &'static Runtime,
+
+    tenant_id: TenantId,
+    timeline_id: TimelineId,
+
+    /// The remote storage the layer files are copied from.
+    source: GenericRemoteStorage,
+    storage: GenericRemoteStorage,
+
+    queue: Mutex<MirrorQueue>,
+
+    /// The layer files in the mirror, listed before the first index upload.
+    mirrored: Mutex<Option<HashSet<LayerFileName>>>,
+}
+
+impl TimelineMirror {
+    pub(crate) fn new(
+        conf: &'static PageServerConf,
+        runtime: &'static Runtime,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        source: GenericRemoteStorage,
+        storage: GenericRemoteStorage,
+    ) -> Self {
+        TimelineMirror {
+            conf,
+            runtime,
+            tenant_id,
+            timeline_id,
+            source,
+            storage,
+            queue: Mutex::new(MirrorQueue::default()),
+            mirrored: Mutex::new(None),
+        }
+    }
+
+    /// Queue an operation, and start performing the queued operations if not yet.
+    pub(crate) fn push(self: &Arc<Self>, op: MirrorOp) {
+        REMOTE_MIRROR_QUEUED_OPERATIONS.inc();
+        REMOTE_MIRROR_QUEUED_BYTES.add(op.queued_bytes());
+
+        let mut queue = self.queue.lock().unwrap();
+        queue.ops.push_back(op);
+        if queue.running {
+            return;
+        }
+        queue.running = true;
+
+        let mirror = Arc::clone(self);
+        let tenant_id = self.tenant_id;
+        let timeline_id = self.timeline_id;
+        task_mgr::spawn(
+            self.runtime.handle(),
+            TaskKind::RemoteUploadTask,
+            Some(self.tenant_id),
+            Some(self.timeline_id),
+            "remote mirror",
+            false,
+            async move {
+                mirror.run().await;
+                Ok(())
+            }
+            .instrument(info_span!(parent: None, "remote_mirror", %tenant_id, %timeline_id)),
+        );
+    }
+
+    /// Whether all the queued operations have been performed.
+    pub(crate) fn is_idle(&self) -> bool {
+        !self.queue.lock().unwrap().running
+    }
+
+    async fn run(&self) {
+        loop {
+            let op = {
+                let mut queue = self.queue.lock().unwrap();
+                match queue.ops.pop_front() {
+                    Some(op) => op,
+                    None => {
+                        queue.running = false;
+                        return;
+                    }
+                }
+            };
+
+            // Unlike the upload tasks, drop an operation in progress at shutdown: the next index
+            // upload after the restart catches up with it.
+            let performed = tokio::select! {
+                _ = task_mgr::shutdown_watcher() => false,
+                _ = self.perform_with_retries(&op) => true,
+            };
+            REMOTE_MIRROR_QUEUED_OPERATIONS.dec();
+            REMOTE_MIRROR_QUEUED_BYTES.sub(op.queued_bytes());
+
+            if !performed {
+                let mut queue = self.queue.lock().unwrap();
+                info!(
+                    "shutting down, dropping {} queued mirror operations",
+                    queue.ops.len() + 1
+                );
+                for op in queue.ops.drain(..) {
+                    REMOTE_MIRROR_QUEUED_OPERATIONS.dec();
+                    REMOTE_MIRROR_QUEUED_BYTES.sub(op.queued_bytes());
+                }
+                queue.running = false;
+                return;
+            }
+        }
+    }
+
+    async fn perform_with_retries(&self, op: &MirrorOp) {
+        let policy = &self.conf.remote_retry.upload;
+        let mut retries = 0;
+        loop {
+            match self.perform(op).await {
+                Ok(()) => {
+                    debug!("mirror operation {op} completed");
+                    return;
+                }
+                Err(e) if !policy.warns(retries) => {
+                    info!(
+                        "failed to perform mirror operation {}, will retry (attempt {}): {:#}",
+                        op, retries, e
+                    );
+                }
+                Err(e) => {
+                    warn_rate_limited!(
+                        REMOTE_TASK_REPEATED_FAILURES,
+                        "failed to perform mirror operation {}, will retry (attempt {}): {:?}",
+                        op,
+                        retries,
+                        e
+                    );
+                }
+            }
+            policy.backoff(retries).await;
+            retries += 1;
+        }
+    }
+
+    async fn perform(&self, op: &MirrorOp) -> anyhow::Result<()> {
+        match op {
+            MirrorOp::UploadLayer(name, metadata) => self.copy_layer(name, metadata).await,
+            MirrorOp::UploadIndex(index_part) => {
+                for name in self.missing_layers(index_part).await? {
+                    let Some(metadata) = index_part.layer_metadata.get(&name) else {
+                        continue;
+                    };
+                    let metadata = LayerFileMetadata::from(metadata);
+                    self.copy_layer(&name, &metadata).await?;
+                }
+                let timeline_storage_path = self.timeline_storage_path()?;
+                copy::upload_index_copy(&self.storage, &timeline_storage_path, index_part).await
+            }
+            MirrorOp::Delete(names) => {
+                let timeline_path = self.conf.timeline_path(&self.tenant_id, &self.timeline_id);
+                let paths = names
+                    .iter()
+                    .map(|name| timeline_path.join(name.file_name()))
+                    .collect::<Vec<_>>();
+                delete::delete_layers(self.conf, &self.storage, &paths, false).await?;
+                if let Some(mirrored) = self.mirrored.lock().unwrap().as_mut() {
+                    for name in names {
+                        mirrored.remove(name);
+                    }
+                }
+                Ok(())
+            }
+            MirrorOp::Tier(layers) => {
+                for (name, metadata) in layers {
+                    self.tier_layer(name, metadata).await?;
+                }
+                let timeline_path = self.conf.timeline_path(&self.tenant_id, &self.timeline_id);
+                let paths = layers
+                    .iter()
+                    .map(|(name, _)| timeline_path.join(name.file_name()))
+                    .collect::<Vec<_>>();
+                delete::delete_layers(self.conf, &self.storage, &paths, true).await
+            }
+            MirrorOp::DeleteTimeline => self.delete_timeline().await,
+        }
+    }
+
+    fn timeline_storage_path(&self) -> anyhow::Result<RemotePath> {
+        let timeline_path = self.conf.timeline_path(&self.tenant_id, &self.timeline_id);
+        self.conf.remote_path(&timeline_path)
+    }
+
+    async fn copy_layer(
+        &self,
+        name: &LayerFileName,
+        metadata: &LayerFileMetadata,
+    ) -> anyhow::Result<()> {
+        let path = metadata.remote_path(&self.timeline_storage_path()?, name);
+        let copied = copy::copy_object(&self.source, &self.storage, &path)
+            .await
+            .context("copy layer file to the mirror")?;
+        if copied.is_none() {
+            // Deleted from the remote storage since, the deletion is queued after this
+            warn!(
+                "layer file {} is no longer in the remote storage, not mirroring it",
+                name.file_name()
+            );
+            return Ok(());
+        }
+        if let Some(mirrored) = self.mirrored.lock().unwrap().as_mut() {
+            mirrored.insert(name.clone());
+        }
+        Ok(())
+    }
+
+    /// Copy a layer file to the cold tier within the mirror, or from the remote storage if the
+    /// mirror doesn't have it.
+    async fn tier_layer(
+        &self,
+        name: &LayerFileName,
+        metadata: &LayerFileMetadata,
+    ) -> anyhow::Result<()> {
+        let timeline_storage_path = self.timeline_storage_path()?;
+        let path = sharding::remote_layer_path(&timeline_storage_path, name, metadata.path(), None);
+        let cold_path = tiering::cold_path(&path);
+        match self.storage.copy(&path, &cold_path).await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                debug!(
+                    "failed to tier layer file {} within the mirror, copying it from the remote storage: {e:#}",
+                    name.file_name()
+                );
+                let tiered = metadata.clone().tiered(LayerTier::Cold);
+                self.copy_layer(name, &tiered).await
+            }
+        }
+    }
+
+    /// The files of the timeline in the mirror, at the default location and in the cold tier.
+    async fn list_timeline_files(&self) -> anyhow::Result<Vec<RemotePath>> {
+        let timeline_storage_path = self.timeline_storage_path()?;
+        let mut files = Vec::new();
+        for prefix in [
+            tiering::cold_path(&timeline_storage_path),
+            timeline_storage_path,
+        ] {
+            files.extend(
+                self.storage
+                    .list_files(Some(&prefix))
+                    .await
+                    .context("list the timeline files in the mirror")?,
+            );
+        }
+        Ok(files)
+    }
+
+    /// The layer files referenced by the index that aren't in the mirror, as after a restart
+    /// that lost queued layer uploads.
+    async fn missing_layers(&self, index_part: &IndexPart) -> anyhow::Result<Vec<LayerFileName>> {
+        if self.mirrored.lock().unwrap().is_none() {
+            let files = self.list_timeline_files().await?;
+            let mirrored = files
+                .iter()
+                .filter_map(|path| path.object_name())
+                .filter_map(|name| LayerFileName::from_str(name).ok())
+                .collect();
+            *self.mirrored.lock().unwrap() = Some(mirrored);
+        }
+        let mirrored = self.mirrored.lock().unwrap();
+        let mirrored = mirrored.as_ref().expect("listed above");
+        Ok(index_part
+            .layer_metadata
+            .keys()
+            .filter(|name| !mirrored.contains(name))
+            .cloned()
+            .collect())
+    }
+
+    /// Delete the files of the timeline, the index files last.
+    async fn delete_timeline(&self) -> anyhow::Result<()> {
+        let files = self.list_timeline_files().await?;
+        let (index_files, others): (Vec<RemotePath>, Vec<RemotePath>) = files
+            .into_iter()
+            .partition(|p| p.object_name().and_then(IndexPart::parse_file_name).is_some());
+        for files in [others, index_files] {
+            if !files.is_empty() {
+                self.storage.delete_objects(&files).await?;
+            }
+        }
+        *self.mirrored.lock().unwrap() = None;
+        Ok(())
+    }
+}
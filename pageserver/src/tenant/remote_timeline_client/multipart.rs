@@ -0,0 +1,290 @@
+//! Multipart uploads of the large layer files.
+//!
+//! S3 rejects the objects over 5 GB uploaded with a single PUT, which the image layers of large
+//! relations can exceed. The layer files larger than the `threshold` of the
+//! `remote_multipart_upload` setting are uploaded in parts of `part_size` bytes instead, for
+//! example:
+//!
+//! ```toml
+//! remote_multipart_upload = { threshold = 4294967296, part_size = 67108864, max_part_attempts = 3 }
+//! ```
+//!
+//! Each part is retried on its own, with the backoff of the upload retries, up to
+//! `max_part_attempts` times. Then the multipart upload is aborted, which removes its parts,
+//! and the upload of the layer fails as a whole, for the upload queue to retry it. An upload
+//! interrupted by a shutdown leaves its parts behind, which S3 keeps until a lifecycle rule of
+//! the bucket aborts the incomplete multipart uploads.
+//!
+//! S3 takes at most 10000 parts, of at least 5 MiB but the last one: the part size grows for
+//! the files that would have more parts.
+
+use std::num::{NonZeroU32, NonZeroU64};
+use std::path::Path;
+
+use anyhow::Context;
+use remote_storage::{GenericRemoteStorage, RemotePath, UploadedPart};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::{info, warn};
+
+use crate::config::PageServerConf;
+
+pub const DEFAULT_THRESHOLD: u64 = 4 * 1024 * 1024 * 1024;
+
+pub const DEFAULT_PART_SIZE: NonZeroU64 = match NonZeroU64::new(64 * 1024 * 1024) {
+    Some(x) => x,
+    None => panic!("const unwrap is not yet stable"),
+};
+
+pub const DEFAULT_MAX_PART_ATTEMPTS: NonZeroU32 = match NonZeroU32::new(3) {
+    Some(x) => x,
+    None => panic!("const unwrap is not yet stable"),
+};
+
+/// The most parts of a multipart upload on S3.
+const MAX_PARTS: u64 = 10_000;
+
+fn default_threshold() -> u64 {
+    DEFAULT_THRESHOLD
+}
+
+fn default_part_size() -> NonZeroU64 {
+    DEFAULT_PART_SIZE
+}
+
+fn default_max_part_attempts() -> NonZeroU32 {
+    DEFAULT_MAX_PART_ATTEMPTS
+}
+
+/// The `remote_multipart_upload` setting, see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteMultipartUploadConfig {
+    /// Upload the layer files larger than this many bytes in parts.
+    #[serde(default = "default_threshold")]
+    pub threshold: u64,
+    #[serde(default = "default_part_size")]
+    pub part_size: NonZeroU64,
+    #[serde(default = "default_max_part_attempts")]
+    pub max_part_attempts: NonZeroU32,
+}
+
+impl Default for RemoteMultipartUploadConfig {
+    fn default() -> Self {
+        RemoteMultipartUploadConfig {
+            threshold: DEFAULT_THRESHOLD,
+            part_size: DEFAULT_PART_SIZE,
+            max_part_attempts: DEFAULT_MAX_PART_ATTEMPTS,
+        }
+    }
+}
+
+impl RemoteMultipartUploadConfig {
+    /// The size of the parts to upload a file of `size` bytes in, `None` to upload it with a
+    /// single request.
+    fn part_size_for(&self, size: u64) -> Option<u64> {
+        if size <= self.threshold {
+            return None;
+        }
+        Some(self.part_size.get().max((size + MAX_PARTS - 1) / MAX_PARTS))
+    }
+}
+
+/// Uploads the local file at `source_path`, of `size` bytes, opened as `source_file`, in parts
+/// if it's over the threshold.
+pub(super) async fn upload_maybe_in_parts(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    source_path: &Path,
+    source_file: fs::File,
+    size: u64,
+    storage_path: &RemotePath,
+) -> anyhow::Result<()> {
+    let Some(part_size) = conf.remote_multipart_upload.part_size_for(size) else {
+        let size = usize::try_from(size)?;
+        return storage.upload(source_file, size, storage_path, None).await;
+    };
+    // Each part reads from a file of its own
+    drop(source_file);
+
+    let parts = (size + part_size - 1) / part_size;
+    info!("uploading {source_path:?} of {size} bytes in {parts} parts");
+    let upload_id = storage
+        .create_multipart_upload(storage_path, None)
+        .await
+        .context("Failed to start a multipart upload")?;
+
+    let res = async {
+        let parts = upload_parts(
+            conf,
+            storage,
+            source_path,
+            size,
+            part_size,
+            storage_path,
+            &upload_id,
+        )
+        .await?;
+        storage
+            .complete_multipart_upload(storage_path, &upload_id, &parts)
+            .await
+            .context("Failed to complete the multipart upload")
+    }
+    .await;
+
+    if res.is_err() {
+        // The retry of the upload starts over, the parts uploaded so far are of no use
+        let abort = storage.abort_multipart_upload(storage_path, &upload_id);
+        if let Err(e) = abort.await {
+            warn!("Failed to abort the multipart upload {upload_id} of {storage_path:?}: {e:#}");
+        }
+    }
+    res
+}
+
+async fn upload_parts(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    source_path: &Path,
+    size: u64,
+    part_size: u64,
+    storage_path: &RemotePath,
+    upload_id: &str,
+) -> anyhow::Result<Vec<UploadedPart>> {
+    let policy = &conf.remote_retry.upload;
+    let max_attempts = conf.remote_multipart_upload.max_part_attempts.get();
+
+    let mut parts = Vec::new();
+    let mut offset = 0;
+    let mut part_number = 1;
+    while offset < size {
+        let len = part_size.min(size - offset);
+        let description = format!("upload of part {part_number} of {storage_path:?}");
+        let mut attempts = 0;
+        let etag = loop {
+            let upload = async {
+                let mut file = fs::File::open(source_path)
+                    .await
+                    .with_context(|| format!("Failed to open {source_path:?}"))?;
+                file.seek(std::io::SeekFrom::Start(offset)).await?;
+                let part_len = usize::try_from(len)?;
+                storage
+                    .upload_part(file.take(len), part_len, storage_path, upload_id, part_number)
+                    .await
+            };
+            match upload.await {
+                Ok(etag) => break etag,
+                Err(e) if attempts + 1 >= max_attempts => {
+                    warn!("{description} still failed after {attempts} retries, giving up: {e:#}");
+                    return Err(e.context(format!("Failed the {description}")));
+                }
+                Err(e) if !policy.warns(attempts) => {
+                    info!("{description} failed, will retry (attempt {attempts}): {e:#}");
+                }
+                Err(e) => {
+                    warn!("{description} failed, will retry (attempt {attempts}): {e:#}");
+                }
+            }
+            policy.backoff(attempts).await;
+            attempts += 1;
+        };
+        parts.push(UploadedPart { part_number, etag });
+        offset += len;
+        part_number += 1;
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use remote_storage::{Fault, FaultInjector, FaultOpKind, LocalFs};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn part_sizes() {
+        let config = RemoteMultipartUploadConfig {
+            threshold: 100,
+            part_size: NonZeroU64::new(40).unwrap(),
+            max_part_attempts: DEFAULT_MAX_PART_ATTEMPTS,
+        };
+        assert_eq!(config.part_size_for(100), None);
+        assert_eq!(config.part_size_for(101), Some(40));
+        // No more than 10000 parts
+        assert_eq!(config.part_size_for(400_000), Some(40));
+        assert_eq!(config.part_size_for(400_001), Some(41));
+    }
+
+    /// Fails the upload requests for which `fails` is true, given their order from 0.
+    struct FailUploads {
+        requests: AtomicUsize,
+        fails: fn(usize) -> bool,
+    }
+
+    impl FaultInjector for FailUploads {
+        fn fault(&self, kind: FaultOpKind, _path: Option<&RemotePath>) -> Fault {
+            let error = kind == FaultOpKind::Upload
+                && (self.fails)(self.requests.fetch_add(1, Ordering::Relaxed));
+            Fault {
+                error,
+                ..Fault::default()
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn upload_in_parts() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut conf = PageServerConf::dummy_conf(dir.path().join("repo"));
+        conf.remote_multipart_upload = RemoteMultipartUploadConfig {
+            threshold: 10,
+            part_size: NonZeroU64::new(10).unwrap(),
+            max_part_attempts: NonZeroU32::new(2).unwrap(),
+        };
+        conf.remote_retry.upload.max_backoff = Duration::ZERO;
+        let conf = Box::leak(Box::new(conf));
+        let remote_root = dir.path().join("remote");
+        let local_storage = GenericRemoteStorage::LocalFs(LocalFs::new(remote_root.clone())?);
+        let contents = b"a layer file in three parts";
+        let source_path = dir.path().join("layer");
+        std::fs::write(&source_path, contents)?;
+        let size = contents.len() as u64;
+        let upload = |fails: fn(usize) -> bool, name: &'static str| {
+            let injector = Arc::new(FailUploads {
+                requests: AtomicUsize::new(0),
+                fails,
+            });
+            let storage = GenericRemoteStorage::fault_injecting(local_storage.clone(), injector);
+            let source_path = source_path.clone();
+            async move {
+                let storage_path = Path::new("timelines/some_timeline").join(name);
+                let storage_path = RemotePath::new(&storage_path)?;
+                let source_file = fs::File::open(&source_path).await?;
+                upload_maybe_in_parts(
+                    conf,
+                    &storage,
+                    &source_path,
+                    source_file,
+                    size,
+                    &storage_path,
+                )
+                .await
+            }
+        };
+        let timeline_dir = remote_root.join("timelines").join("some_timeline");
+
+        // The first request of the first part fails, and is retried
+        upload(|request| request == 1, "layer").await?;
+        assert_eq!(std::fs::read(timeline_dir.join("layer"))?, contents);
+        assert_eq!(std::fs::read_dir(&timeline_dir)?.count(), 1);
+
+        // The second part fails for good, the upload is aborted
+        assert!(upload(|request| request >= 2, "other_layer").await.is_err());
+        assert_eq!(std::fs::read_dir(&timeline_dir)?.count(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+//! Retry policies of the remote storage operations.
+//!
+//! Remote operations can fail due to rate limits (IAM, S3), spurious network problems, or
+//! other external reasons, so they are retried with exponential backoff. The `remote_retry`
+//! setting has a [`RetryPolicy`] for each kind of operation, for example:
+//!
+//! ```toml
+//! [remote_retry]
+//! upload = { max_attempts = 100, give_up = 'cancel' }
+//! download = { jitter = '1s' }
+//! ```
+//!
+//! The fields left out keep the defaults of their kind: downloads give up after 11 attempts,
+//! uploads and deletions are retried forever.
+
+use std::time::Duration;
+
+use rand::Rng;
+use serde::{Deserialize, Deserializer};
+use tracing::info;
+
+use crate::{
+    exponential_backoff_duration_seconds, DEFAULT_BASE_BACKOFF_SECONDS,
+    DEFAULT_MAX_BACKOFF_SECONDS,
+};
+
+// Occasional network issues and such can cause remote operations to fail, and
+// that's expected. If an operation fails, we log it at info-level, and retry.
+// But after this many retries, we start to log it at WARN level instead, as
+// repeated failures can mean a more serious problem.
+const DEFAULT_WARN_THRESHOLD: u32 = 3;
+
+// The first attempt, and 10 retries.
+const DEFAULT_DOWNLOAD_MAX_ATTEMPTS: u32 = 11;
+
+/// What an upload or deletion does when it runs out of attempts. A download returns the error
+/// to its caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GiveUp {
+    /// Stop the upload queue of the timeline, as on shutdown.
+    StopQueue,
+    /// Cancel the operation, and the queued operations depending on it, see
+    /// [`super::RemoteTimelineClient::cancel_task`]. The upload queue goes on.
+    Cancel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts, including the first one, before giving up. 0 retries forever.
+    pub max_attempts: u32,
+    /// Failed attempts are logged at INFO level, and at WARN level after this many retries.
+    pub warn_threshold: u32,
+    /// The backoff after `n` retries is `(1 + base_backoff)^n` seconds, with `base_backoff`
+    /// in seconds, up to `max_backoff`. There's no backoff before the first retry.
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Add a random duration up to this to each backoff.
+    pub jitter: Duration,
+    pub give_up: GiveUp,
+}
+
+impl RetryPolicy {
+    pub fn upload() -> Self {
+        RetryPolicy {
+            max_attempts: 0,
+            warn_threshold: DEFAULT_WARN_THRESHOLD,
+            base_backoff: Duration::from_secs_f64(DEFAULT_BASE_BACKOFF_SECONDS),
+            max_backoff: Duration::from_secs_f64(DEFAULT_MAX_BACKOFF_SECONDS),
+            jitter: Duration::ZERO,
+            give_up: GiveUp::StopQueue,
+        }
+    }
+
+    pub fn download() -> Self {
+        RetryPolicy {
+            max_attempts: DEFAULT_DOWNLOAD_MAX_ATTEMPTS,
+            ..Self::upload()
+        }
+    }
+
+    pub fn deletion() -> Self {
+        Self::upload()
+    }
+
+    /// Whether to give up after the failure of the attempt following `retries` retries.
+    pub(super) fn gives_up(&self, retries: u32) -> bool {
+        self.max_attempts != 0 && retries >= self.max_attempts - 1
+    }
+
+    pub(super) fn warns(&self, retries: u32) -> bool {
+        retries >= self.warn_threshold
+    }
+
+    /// Wait before the retry following `retries` retries.
+    pub(super) async fn backoff(&self, retries: u32) {
+        let mut backoff = Duration::from_secs_f64(exponential_backoff_duration_seconds(
+            retries,
+            self.base_backoff.as_secs_f64(),
+            self.max_backoff.as_secs_f64(),
+        ));
+        if !self.jitter.is_zero() {
+            backoff += rand::thread_rng().gen_range(Duration::ZERO..=self.jitter);
+        }
+        if !backoff.is_zero() {
+            info!("Backoff: waiting {backoff:?} before retrying");
+            tokio::time::sleep(backoff).await;
+        }
+    }
+}
+
+/// The `remote_retry` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteRetryConfig {
+    #[serde(default = "RetryPolicy::upload", deserialize_with = "deserialize_upload")]
+    pub upload: RetryPolicy,
+    #[serde(default = "RetryPolicy::download", deserialize_with = "deserialize_download")]
+    pub download: RetryPolicy,
+    #[serde(default = "RetryPolicy::deletion", deserialize_with = "deserialize_deletion")]
+    pub deletion: RetryPolicy,
+}
+
+impl Default for RemoteRetryConfig {
+    fn default() -> Self {
+        RemoteRetryConfig {
+            upload: RetryPolicy::upload(),
+            download: RetryPolicy::download(),
+            deletion: RetryPolicy::deletion(),
+        }
+    }
+}
+
+/// A policy in the config, the fields left out keep the defaults of the kind.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RetryPolicyOverrides {
+    max_attempts: Option<u32>,
+    warn_threshold: Option<u32>,
+    #[serde(default, with = "humantime_serde")]
+    base_backoff: Option<Duration>,
+    #[serde(default, with = "humantime_serde")]
+    max_backoff: Option<Duration>,
+    #[serde(default, with = "humantime_serde")]
+    jitter: Option<Duration>,
+    give_up: Option<GiveUp>,
+}
+
+impl RetryPolicyOverrides {
+    fn apply(self, default: RetryPolicy) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts.unwrap_or(default.max_attempts),
+            warn_threshold: self.warn_threshold.unwrap_or(default.warn_threshold),
+            base_backoff: self.base_backoff.unwrap_or(default.base_backoff),
+            max_backoff: self.max_backoff.unwrap_or(default.max_backoff),
+            jitter: self.jitter.unwrap_or(default.jitter),
+            give_up: self.give_up.unwrap_or(default.give_up),
+        }
+    }
+}
+
+fn deserialize_upload<'de, D: Deserializer<'de>>(d: D) -> Result<RetryPolicy, D::Error> {
+    Ok(RetryPolicyOverrides::deserialize(d)?.apply(RetryPolicy::upload()))
+}
+
+fn deserialize_download<'de, D: Deserializer<'de>>(d: D) -> Result<RetryPolicy, D::Error> {
+    Ok(RetryPolicyOverrides::deserialize(d)?.apply(RetryPolicy::download()))
+}
+
+fn deserialize_deletion<'de, D: Deserializer<'de>>(d: D) -> Result<RetryPolicy, D::Error> {
+    Ok(RetryPolicyOverrides::deserialize(d)?.apply(RetryPolicy::deletion()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gives_up() {
+        let download = RetryPolicy::download();
+        assert!(!download.gives_up(0));
+        assert!(!download.gives_up(9));
+        assert!(download.gives_up(10));
+
+        let upload = RetryPolicy::upload();
+        assert!(!upload.gives_up(0));
+        assert!(!upload.gives_up(u32::MAX - 1));
+    }
+}
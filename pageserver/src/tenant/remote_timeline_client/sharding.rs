@@ -0,0 +1,160 @@
+//! Sharded paths of the layer files in the remote storage.
+//!
+//! S3 partitions a bucket by key prefix, and splits a busy prefix only gradually: all the layer
+//! files of a timeline share the prefix of its remote directory, which caps the request rate of
+//! a very write-heavy timeline. With the `remote_layer_path_scheme` setting:
+//!
+//! ```toml
+//! remote_layer_path_scheme = 'sharded'
+//! ```
+//!
+//! the layer files are uploaded to a shard directory of the timeline directory, named by two hex
+//! digits of a hash of the layer file name:
+//! `tenants/<tenant>/timelines/<timeline>/<shard>/<layer>`, which spreads them over 256 prefixes.
+//! The other files of the timeline, the index files in particular, stay at the top of the
+//! timeline directory.
+//!
+//! The index records the path of each sharded layer file, relative to the timeline directory,
+//! and an index with any other path than that of a shard directory is rejected when read. The
+//! layer files are downloaded from wherever the index says they are: the ones uploaded
+//! with the default `flat` scheme stay where they are, and switching back to it only changes
+//! where the next uploads go. The layer file deletions delete both locations. A layer file moved
+//! to the cold tier keeps its path under the cold tier prefix, see [`super::tiering`].
+//!
+//! The releases before index version 10 read the index, but look for the layer files at the top
+//! of the timeline directory: switch back to the flat scheme and copy the sharded layer files
+//! there before rolling back to them.
+
+use std::path::Path;
+
+use remote_storage::RemotePath;
+use serde::{Deserialize, Serialize};
+
+use super::tiering::{self, LayerTier};
+use crate::tenant::storage_layer::LayerFileName;
+
+/// The `remote_layer_path_scheme` setting, see the module docs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerPathScheme {
+    /// At the top of the timeline directory.
+    #[default]
+    Flat,
+    /// In the shard directory of the layer file name.
+    Sharded,
+}
+
+/// The shard directory of a layer file: the low byte of the CRC32C of its name, in hex.
+fn shard(file_name: &str) -> String {
+    format!("{:02x}", crc32c::crc32c(file_name.as_bytes()) & 0xff)
+}
+
+/// Where to upload a layer file under the scheme, given where it is under the flat scheme, and
+/// its path relative to the timeline directory for the index, `None` under the flat scheme.
+pub(super) fn upload_path(
+    scheme: LayerPathScheme,
+    flat_path: &RemotePath,
+) -> (RemotePath, Option<String>) {
+    match scheme {
+        LayerPathScheme::Flat => (flat_path.clone(), None),
+        LayerPathScheme::Sharded => {
+            let file_name = flat_path.object_name().unwrap_or_default();
+            let relative_path = format!("{}/{file_name}", shard(file_name));
+            (sharded_path(flat_path), Some(relative_path))
+        }
+    }
+}
+
+/// Where a layer file is under the sharded scheme, given where it is under the flat scheme.
+pub(super) fn sharded_path(flat_path: &RemotePath) -> RemotePath {
+    let file_name = flat_path.object_name().unwrap_or_default();
+    let timeline_dir = flat_path.get_path().parent().unwrap_or(Path::new(""));
+    RemotePath::new(&timeline_dir.join(shard(file_name)).join(file_name))
+        .expect("the parent of a remote path is a relative path")
+}
+
+/// Whether a file listed under the remote directory of a timeline is in the shard directory of
+/// its name, where the sharded layer files are.
+pub(super) fn is_sharded_layer_path(timeline_storage_path: &RemotePath, path: &RemotePath) -> bool {
+    match path.object_name() {
+        Some(file_name) => {
+            sharded_path(&timeline_storage_path.join(Path::new(file_name))) == *path
+        }
+        None => false,
+    }
+}
+
+/// Whether `relative_path`, from the index, is a path of the layer file in a shard directory:
+/// two hex digits, and the file name of the layer. Anything else, an absolute path or one with
+/// `..` in particular, could point out of the timeline directory it is joined to.
+pub(super) fn is_layer_path(layer_file_name: &LayerFileName, relative_path: &str) -> bool {
+    match relative_path.split_once('/') {
+        Some((shard, file_name)) => {
+            shard.len() == 2
+                && shard.bytes().all(|b| b.is_ascii_hexdigit())
+                && file_name == layer_file_name.file_name()
+        }
+        None => false,
+    }
+}
+
+/// Where a layer file is in the remote storage, given the remote directory of its timeline, its
+/// path relative to it from the index, `None` at the top of the directory, and its tier.
+pub(super) fn remote_layer_path(
+    timeline_storage_path: &RemotePath,
+    layer_file_name: &LayerFileName,
+    relative_path: Option<&str>,
+    tier: Option<LayerTier>,
+) -> RemotePath {
+    let path = match relative_path {
+        Some(relative_path) => timeline_storage_path.join(Path::new(relative_path)),
+        None => timeline_storage_path.join(Path::new(&layer_file_name.file_name())),
+    };
+    tiering::tiered_path(&path, tier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sharded_paths() -> anyhow::Result<()> {
+        let timeline_path = RemotePath::new(Path::new("tenants/t/timelines/tl"))?;
+        let layer_file_name: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9".parse().unwrap();
+        let file_name = layer_file_name.file_name();
+        let flat_path = timeline_path.join(Path::new(&file_name));
+
+        assert_eq!(
+            upload_path(LayerPathScheme::Flat, &flat_path),
+            (flat_path.clone(), None)
+        );
+        let (path, relative_path) = upload_path(LayerPathScheme::Sharded, &flat_path);
+        let relative_path = relative_path.unwrap();
+        let shard = relative_path.strip_suffix(&format!("/{file_name}")).unwrap();
+        assert_eq!(shard.len(), 2);
+        assert!(shard.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(path, sharded_path(&flat_path));
+        assert!(is_sharded_layer_path(&timeline_path, &path));
+        assert!(!is_sharded_layer_path(&timeline_path, &flat_path));
+
+        // The index says where the layer file is, in which tier
+        assert_eq!(
+            remote_layer_path(&timeline_path, &layer_file_name, None, None),
+            flat_path
+        );
+        assert_eq!(
+            remote_layer_path(&timeline_path, &layer_file_name, Some(&relative_path), None),
+            path
+        );
+        assert_eq!(
+            remote_layer_path(
+                &timeline_path,
+                &layer_file_name,
+                Some(&relative_path),
+                Some(LayerTier::Cold)
+            ),
+            tiering::cold_path(&path)
+        );
+        Ok(())
+    }
+}
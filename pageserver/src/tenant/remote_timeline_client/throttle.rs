@@ -0,0 +1,246 @@
+//! Throttling of the layer uploads of a tenant.
+//!
+//! The timelines of a tenant share one [`UploadThrottle`], so that a tenant uploading the
+//! layers of a big compaction doesn't take all the bandwidth to the remote storage from the
+//! other tenants. Each limit is a token bucket holding up to a second worth of tokens. An
+//! upload waits until the buckets have its tokens, or are full for a layer larger than a
+//! second worth of bytes, and then takes them, running the bytes bucket into debt if need be.
+//!
+//! The `max_inprogress_uploads` tenant setting, an [`UploadConcurrencyLimit`] shared by the
+//! timelines of the tenant, limits the layer uploads each of them runs at once, so that a
+//! timeline ingesting in bulk doesn't take all the `max_concurrent_syncs` permits of the remote
+//! storage from the other timelines. The layer uploads over the limit wait in the upload queue.
+
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use pageserver_api::models::UploadThrottleConfig;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::metrics::REMOTE_UPLOAD_THROTTLED_SECONDS;
+
+pub struct UploadThrottle {
+    inner: Mutex<Buckets>,
+    /// Wakes up the waiting uploads when the limits change.
+    config_changed: Notify,
+}
+
+struct Buckets {
+    config: UploadThrottleConfig,
+    bytes: Bucket,
+    ops: Bucket,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn full(rate: Option<NonZeroU64>, now: Instant) -> Self {
+        Bucket {
+            tokens: rate.map_or(0.0, |rate| rate.get() as f64),
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, rate: Option<NonZeroU64>, now: Instant) {
+        if let Some(rate) = rate {
+            let rate = rate.get() as f64;
+            let elapsed = now.saturating_duration_since(self.updated_at);
+            self.tokens = f64::min(rate, self.tokens + elapsed.as_secs_f64() * rate);
+        }
+        self.updated_at = now;
+    }
+
+    /// How long until the bucket has `amount` tokens, or is full.
+    fn wait_time(&self, rate: Option<NonZeroU64>, amount: f64) -> Duration {
+        let Some(rate) = rate else {
+            return Duration::ZERO;
+        };
+        let rate = rate.get() as f64;
+        let missing = f64::min(amount, rate) - self.tokens;
+        if missing > 0.0 {
+            Duration::from_secs_f64(missing / rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+impl Buckets {
+    fn new(config: UploadThrottleConfig, now: Instant) -> Self {
+        Buckets {
+            config,
+            bytes: Bucket::full(config.bytes_per_second, now),
+            ops: Bucket::full(config.ops_per_second, now),
+        }
+    }
+
+    /// Take the tokens of an upload of `bytes`, or return how long to wait for them.
+    fn try_acquire(&mut self, bytes: u64, now: Instant) -> Result<(), Duration> {
+        let UploadThrottleConfig {
+            bytes_per_second,
+            ops_per_second,
+        } = self.config;
+        self.bytes.refill(bytes_per_second, now);
+        self.ops.refill(ops_per_second, now);
+
+        let wait = Duration::max(
+            self.bytes.wait_time(bytes_per_second, bytes as f64),
+            self.ops.wait_time(ops_per_second, 1.0),
+        );
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        self.bytes.tokens -= bytes as f64;
+        self.ops.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+impl UploadThrottle {
+    pub fn new(config: UploadThrottleConfig) -> Self {
+        UploadThrottle {
+            inner: Mutex::new(Buckets::new(config, Instant::now())),
+            config_changed: Notify::new(),
+        }
+    }
+
+    pub fn config(&self) -> UploadThrottleConfig {
+        self.inner.lock().unwrap().config
+    }
+
+    /// Change the limits. The uploads waiting for the old limits wait for the new ones instead.
+    pub fn set_config(&self, config: UploadThrottleConfig) {
+        *self.inner.lock().unwrap() = Buckets::new(config, Instant::now());
+        self.config_changed.notify_waiters();
+    }
+
+    /// Wait until an upload of `bytes` is within the limits.
+    pub(super) async fn acquire(&self, bytes: u64) {
+        let started_at = Instant::now();
+        let mut throttled = false;
+        loop {
+            // Before checking the limits, not to miss a change after the check
+            let config_changed = self.config_changed.notified();
+            let acquired = self.inner.lock().unwrap().try_acquire(bytes, Instant::now());
+            let Err(wait) = acquired else {
+                break;
+            };
+            throttled = true;
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = config_changed => {}
+            }
+        }
+        if throttled {
+            REMOTE_UPLOAD_THROTTLED_SECONDS.inc_by(started_at.elapsed().as_secs_f64());
+        }
+    }
+}
+
+/// The layer uploads an upload queue runs at once, see the module docs. Follows the tenant
+/// config.
+pub struct UploadConcurrencyLimit {
+    /// Zero for no limit.
+    max_inprogress_uploads: AtomicUsize,
+}
+
+impl UploadConcurrencyLimit {
+    pub fn new(max_inprogress_uploads: Option<NonZeroUsize>) -> Self {
+        let max_inprogress_uploads = max_inprogress_uploads.map_or(0, NonZeroUsize::get);
+        UploadConcurrencyLimit {
+            max_inprogress_uploads: AtomicUsize::new(max_inprogress_uploads),
+        }
+    }
+
+    pub fn get(&self) -> Option<NonZeroUsize> {
+        NonZeroUsize::new(self.max_inprogress_uploads.load(Ordering::Relaxed))
+    }
+
+    /// Change the limit. The uploads in progress over a lowered limit complete, a raised limit
+    /// applies from the next upload queue update of each timeline.
+    pub(crate) fn set(&self, max_inprogress_uploads: Option<NonZeroUsize>) {
+        let max_inprogress_uploads = max_inprogress_uploads.map_or(0, NonZeroUsize::get);
+        self.max_inprogress_uploads
+            .store(max_inprogress_uploads, Ordering::Relaxed);
+    }
+
+    /// Whether a queue with `inprogress_uploads` layer uploads may start another one.
+    pub(super) fn allows(&self, inprogress_uploads: usize) -> bool {
+        self.get().map_or(true, |max| inprogress_uploads < max.get())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(bytes_per_second: u64, ops_per_second: u64) -> UploadThrottleConfig {
+        UploadThrottleConfig {
+            bytes_per_second: NonZeroU64::new(bytes_per_second),
+            ops_per_second: NonZeroU64::new(ops_per_second),
+        }
+    }
+
+    #[test]
+    fn unlimited() {
+        let now = Instant::now();
+        let mut buckets = Buckets::new(UploadThrottleConfig::default(), now);
+        for _ in 0..1000 {
+            assert_eq!(buckets.try_acquire(u64::MAX, now), Ok(()));
+        }
+    }
+
+    #[test]
+    fn ops_per_second() {
+        let now = Instant::now();
+        let mut buckets = Buckets::new(config(0, 2), now);
+        // A second worth of uploads right away
+        assert_eq!(buckets.try_acquire(100, now), Ok(()));
+        assert_eq!(buckets.try_acquire(100, now), Ok(()));
+        assert_eq!(buckets.try_acquire(100, now), Err(Duration::from_millis(500)));
+        let now = now + Duration::from_millis(500);
+        assert_eq!(buckets.try_acquire(100, now), Ok(()));
+        assert!(buckets.try_acquire(100, now).is_err());
+        // Doesn't fill up beyond a second worth
+        let now = now + Duration::from_secs(10);
+        assert_eq!(buckets.try_acquire(100, now), Ok(()));
+        assert_eq!(buckets.try_acquire(100, now), Ok(()));
+        assert!(buckets.try_acquire(100, now).is_err());
+    }
+
+    #[test]
+    fn bytes_per_second() {
+        let now = Instant::now();
+        let mut buckets = Buckets::new(config(1000, 0), now);
+        assert_eq!(buckets.try_acquire(750, now), Ok(()));
+        assert_eq!(buckets.try_acquire(750, now), Err(Duration::from_millis(500)));
+        let now = now + Duration::from_millis(500);
+        assert_eq!(buckets.try_acquire(750, now), Ok(()));
+
+        // Layers larger than a second worth wait for a full bucket, and go into debt
+        assert!(buckets.try_acquire(3000, now).is_err());
+        let now = now + Duration::from_secs(1);
+        assert_eq!(buckets.try_acquire(3000, now), Ok(()));
+        assert_eq!(buckets.try_acquire(500, now), Err(Duration::from_millis(2500)));
+        let now = now + Duration::from_millis(2500);
+        assert_eq!(buckets.try_acquire(500, now), Ok(()));
+    }
+
+    #[test]
+    fn set_config() {
+        let throttle = UploadThrottle::new(config(0, 1));
+        let now = Instant::now();
+        assert_eq!(throttle.inner.lock().unwrap().try_acquire(0, now), Ok(()));
+        assert!(throttle.inner.lock().unwrap().try_acquire(0, now).is_err());
+
+        throttle.set_config(UploadThrottleConfig::default());
+        assert_eq!(throttle.config(), UploadThrottleConfig::default());
+        assert_eq!(throttle.inner.lock().unwrap().try_acquire(0, now), Ok(()));
+    }
+}
@@ -0,0 +1,73 @@
+//! Cold tiering of the old layer files in the remote storage.
+//!
+//! With an `AgeThreshold` tiering policy in the tenant config, the layer files uploaded longer
+//! than the threshold ago are moved under the [`COLD_TIER_PREFIX`] of the remote storage: each
+//! is copied there by the storage itself, the index records the [`LayerTier`] of the layer,
+//! and the copy at the default location is deleted once that index is uploaded. A lifecycle
+//! rule of the bucket on the prefix transitions the objects to a cheaper storage class, e.g.
+//! S3 Glacier Instant Retrieval, which the pageserver doesn't need to know about. The layer
+//! files are downloaded from wherever the index says they are.
+//!
+//! A layer file uploaded again, e.g. by a compaction writing a layer of the same name, is back
+//! at the default location. The layer file deletions delete both locations, so the tiered
+//! layers are deleted even once the policy has been disabled.
+//!
+//! The releases before index version 9 read the index, but look for the tiered layer files at
+//! the default location: disable the policy and copy the tiered layer files back before rolling
+//! back to them.
+
+use std::path::Path;
+
+use remote_storage::RemotePath;
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the cold tier in the remote storage, followed by the default path of the files.
+pub(crate) const COLD_TIER_PREFIX: &str = "cold";
+
+/// A tier of the remote storage that a layer file has been moved to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerTier {
+    /// Under [`COLD_TIER_PREFIX`].
+    Cold,
+}
+
+/// Where a file is in the cold tier, given where it is by default.
+pub(super) fn cold_path(path: &RemotePath) -> RemotePath {
+    RemotePath::new(Path::new(COLD_TIER_PREFIX))
+        .expect("cold tier prefix is a relative path")
+        .join(path.get_path())
+}
+
+/// Where a layer file is in the remote storage, given where it is by default and its tier.
+pub(super) fn tiered_path(path: &RemotePath, tier: Option<LayerTier>) -> RemotePath {
+    match tier {
+        None => path.clone(),
+        Some(LayerTier::Cold) => cold_path(path),
+    }
+}
+
+/// What a tiering run of a timeline did.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct TieringReport {
+    pub(crate) tiered_layers: u64,
+    pub(crate) tiered_bytes: u64,
+    /// The hot copies of the layers tiered earlier, left behind by an interrupted run.
+    pub(crate) deleted_leftovers: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiered_paths() -> anyhow::Result<()> {
+        let path = RemotePath::new(Path::new("tenants/t/timelines/tl/layer"))?;
+        assert_eq!(tiered_path(&path, None), path);
+        assert_eq!(
+            tiered_path(&path, Some(LayerTier::Cold)),
+            RemotePath::new(Path::new("cold/tenants/t/timelines/tl/layer"))?
+        );
+        Ok(())
+    }
+}
@@ -5,58 +5,224 @@ use fail::fail_point;
 use std::{io::ErrorKind, path::Path};
 use tokio::fs;
 
+use crate::tenant::generation::{Generation, StaleGenerationError};
+use crate::TEMP_FILE_SUFFIX;
 use crate::{config::PageServerConf, tenant::remote_timeline_client::index::IndexPart};
-use remote_storage::GenericRemoteStorage;
+use remote_storage::{GenericRemoteStorage, RemotePath, UploadError, UploadPrecondition};
+use utils::crashsafe::path_with_suffix_extension;
 use utils::id::{TenantId, TimelineId};
 
+use super::compression::{self, LayerCompression, RemoteCompressionConfig};
+use super::download;
+use super::encryption::{self, LayerEncryption, TenantKey};
+use super::index::chunked::{IndexSegment, SegmentRef};
 use super::index::LayerFileMetadata;
+use super::layer_file_crc32c;
+use super::multipart;
+use super::sharding;
 
-use tracing::info;
+use tracing::{info, warn};
 
 /// Serializes and uploads the given index part data to the remote storage.
+///
+/// An index part with a generation is uploaded under the name with that generation, after the
+/// check of [`check_index_generation`]: this is for the rare uploads outside of the upload
+/// queue, which keeps the LIST off the path of its uploads.
+///
+/// The upload only overwrites the index file if it matches the precondition, else this fails
+/// with a [`ConcurrentIndexModificationError`]. Returns the entity tag of the uploaded file.
 pub(super) async fn upload_index_part<'a>(
     conf: &'static PageServerConf,
     storage: &'a GenericRemoteStorage,
     tenant_id: &TenantId,
     timeline_id: &TimelineId,
     index_part: &'a IndexPart,
+    precondition: Option<&UploadPrecondition>,
+) -> anyhow::Result<Option<String>> {
+    if let Some(generation) = index_part.generation {
+        check_index_generation(conf, storage, tenant_id, timeline_id, generation).await?;
+    }
+    let index_part_bytes = serde_json::to_vec(&index_part)
+        .context("Failed to serialize index part file into bytes")?;
+    upload_index_file(
+        conf,
+        storage,
+        tenant_id,
+        timeline_id,
+        index_part.generation,
+        index_part_bytes,
+        precondition,
+    )
+    .await
+}
+
+/// The index file has been written by someone else since we last read or wrote it, e.g. by
+/// another pageserver the tenant is attached to as well, and has been left as it is.
+#[derive(Debug, thiserror::Error)]
+#[error("index file {path:?} has been modified concurrently")]
+pub(crate) struct ConcurrentIndexModificationError {
+    pub path: RemotePath,
+}
+
+/// Checks that no index file of a later generation than `generation` exists: the tenant has
+/// been attached elsewhere since, and this fails with a [`StaleGenerationError`]. Returns the
+/// earlier generations that have an index file, for [`delete_index_files`] to clean up once
+/// the index of `generation` is uploaded.
+pub(super) async fn check_index_generation(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    tenant_id: &TenantId,
+    timeline_id: &TimelineId,
+    generation: Generation,
+) -> anyhow::Result<Vec<Generation>> {
+    let legacy_index_part_path = conf
+        .metadata_path(tenant_id, timeline_id)
+        .with_file_name(IndexPart::FILE_NAME);
+    let legacy_storage_path = conf.remote_path(&legacy_index_part_path)?;
+    let generations = download::list_index_generations(storage, &legacy_storage_path).await?;
+    // The legacy index file, without a generation, is left for the pageservers that predate
+    // the generations
+    let generations = generations.into_iter().flatten().collect::<Vec<_>>();
+    if let Some(latest) = generations.iter().copied().max() {
+        if latest > generation {
+            return Err(StaleGenerationError {
+                current: generation,
+                latest,
+            }
+            .into());
+        }
+    }
+    Ok(generations
+        .into_iter()
+        .filter(|&other| other < generation)
+        .collect())
+}
+
+/// Deletes the index files of the given generations, as returned by
+/// [`check_index_generation`].
+///
+/// Once the tenant is attached with a later generation, nothing reads them any more. The
+/// conditional upload of a stale attachment fails once its index file is gone, as its entity
+/// tag no longer matches.
+pub(super) async fn delete_index_files(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    tenant_id: &TenantId,
+    timeline_id: &TimelineId,
+    generations: &[Generation],
 ) -> anyhow::Result<()> {
+    let legacy_index_part_path = conf
+        .metadata_path(tenant_id, timeline_id)
+        .with_file_name(IndexPart::FILE_NAME);
+    let paths = generations
+        .iter()
+        .map(|&generation| {
+            let path =
+                legacy_index_part_path.with_file_name(IndexPart::file_name(Some(generation)));
+            conf.remote_path(&path)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    storage
+        .delete_objects(&paths)
+        .await
+        .with_context(|| format!("delete the index files of generations {generations:?}"))
+}
+
+/// Uploads a serialized index file, a plain index part or the manifest of a chunked one, under
+/// the name of `generation`. Unlike [`upload_index_part`], this doesn't check for the later
+/// generations, see [`check_index_generation`].
+pub(super) async fn upload_index_file(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    tenant_id: &TenantId,
+    timeline_id: &TimelineId,
+    generation: Option<Generation>,
+    index_part_bytes: Vec<u8>,
+    precondition: Option<&UploadPrecondition>,
+) -> anyhow::Result<Option<String>> {
     tracing::trace!("uploading new index part");
 
     fail_point!("before-upload-index", |_| {
         bail!("failpoint before-upload-index")
     });
 
-    let index_part_bytes = serde_json::to_vec(&index_part)
-        .context("Failed to serialize index part file into bytes")?;
     let index_part_size = index_part_bytes.len();
     let index_part_bytes = tokio::io::BufReader::new(std::io::Cursor::new(index_part_bytes));
 
-    let index_part_path = conf
+    let legacy_index_part_path = conf
         .metadata_path(tenant_id, timeline_id)
         .with_file_name(IndexPart::FILE_NAME);
+    let index_part_path = legacy_index_part_path.with_file_name(IndexPart::file_name(generation));
     let storage_path = conf.remote_path(&index_part_path)?;
 
+    let upload = storage
+        .upload_conditional(
+            Box::new(index_part_bytes),
+            index_part_size,
+            &storage_path,
+            precondition,
+        )
+        .await;
+    match upload {
+        Ok(etag) => Ok(etag),
+        Err(UploadError::PreconditionFailed) => {
+            Err(ConcurrentIndexModificationError { path: storage_path }.into())
+        }
+        Err(UploadError::Other(e)) => Err(e.context(format!(
+            "Failed to upload index part for '{tenant_id} / {timeline_id}'"
+        ))),
+    }
+}
+
+/// Uploads a segment of a chunked index, see [`super::index::chunked`].
+pub(super) async fn upload_index_segment(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    tenant_id: &TenantId,
+    timeline_id: &TimelineId,
+    segment_ref: &SegmentRef,
+    segment: &IndexSegment,
+) -> anyhow::Result<()> {
+    let bytes = serde_json::to_vec(segment).context("serialize index segment")?;
+    let size = bytes.len();
+    let path = conf
+        .timeline_path(tenant_id, timeline_id)
+        .join(segment_ref.file_name());
+    let storage_path = conf.remote_path(&path)?;
+
     storage
-        .upload_storage_object(Box::new(index_part_bytes), index_part_size, &storage_path)
+        .upload_storage_object(
+            Box::new(tokio::io::BufReader::new(std::io::Cursor::new(bytes))),
+            size,
+            &storage_path,
+        )
         .await
-        .with_context(|| format!("Failed to upload index part for '{tenant_id} / {timeline_id}'"))
+        .with_context(|| format!("upload index segment {storage_path:?}"))
 }
 
 /// Attempts to upload given layer files.
 /// No extra checks for overlapping files is made and any files that are already present remotely will be overwritten, if submitted during the upload.
 ///
 /// On an error, bumps the retries count and reschedules the entire task.
+///
+/// Compresses the file first, if configured so, and encrypts it, if the tenant's layers are
+/// encrypted client-side. Uploads the large files in parts, see [`multipart`], to the location
+/// of the `remote_layer_path_scheme`, see [`sharding`].
+///
+/// Returns the metadata of the uploaded file, or `None` if the file no longer exists.
 pub(super) async fn upload_timeline_layer<'a>(
     conf: &'static PageServerConf,
     storage: &'a GenericRemoteStorage,
+    tenant_id: TenantId,
     source_path: &'a Path,
     known_metadata: &'a LayerFileMetadata,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Option<LayerFileMetadata>> {
     fail_point!("before-upload-layer", |_| {
         bail!("failpoint before-upload-layer")
     });
-    let storage_path = conf.remote_path(source_path)?;
+    let flat_storage_path = conf.remote_path(source_path)?;
+    let (storage_path, relative_path) =
+        sharding::upload_path(conf.remote_layer_path_scheme, &flat_storage_path);
 
     let source_file_res = fs::File::open(&source_path).await;
     let source_file = match source_file_res {
@@ -69,7 +235,7 @@ pub(super) async fn upload_timeline_layer<'a>(
             // a bug. Still log the situation so that we can keep an eye on it.
             // See https://github.com/neondatabase/neon/issues/4526
             info!(path = %source_path.display(), "File to upload doesn't exist. Likely the file has been deleted and an upload is not required any more.");
-            return Ok(());
+            return Ok(None);
         }
         Err(e) => Err(e)
             .with_context(|| format!("Failed to open a source file for layer {source_path:?}"))?,
@@ -88,12 +254,21 @@ pub(super) async fn upload_timeline_layer<'a>(
         bail!("File {source_path:?} has its current FS size {fs_size} diferent from initially determined {metadata_size}");
     }
 
-    let fs_size = usize::try_from(fs_size).with_context(|| {
-        format!("File {source_path:?} size {fs_size} could not be converted to usize")
-    })?;
+    // Layer files are immutable, so this is the checksum of what gets uploaded
+    let crc32c = layer_file_crc32c(source_path)
+        .await
+        .with_context(|| format!("Failed to compute the checksum of layer {source_path:?}"))?;
 
-    storage
-        .upload(source_file, fs_size, &storage_path, None)
+    let encryption_key = encryption::tenant_key(conf, tenant_id).await?;
+    if conf.remote_compression == RemoteCompressionConfig::None && encryption_key.is_none() {
+        multipart::upload_maybe_in_parts(
+            conf,
+            storage,
+            source_path,
+            source_file,
+            fs_size,
+            &storage_path,
+        )
         .await
         .with_context(|| {
             format!(
@@ -101,6 +276,120 @@ pub(super) async fn upload_timeline_layer<'a>(
                 source_path.display()
             )
         })?;
+        let uploaded = known_metadata
+            .clone()
+            .uploaded(relative_path, crc32c, None, None);
+        return Ok(Some(uploaded));
+    }
+    drop(source_file);
+
+    let (compression, encryption) = upload_transformed_layer(
+        conf,
+        storage,
+        encryption_key.as_ref(),
+        source_path,
+        metadata_size,
+        &storage_path,
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "Failed to upload a layer from local path '{}'",
+            source_path.display()
+        )
+    })?;
+
+    let uploaded = known_metadata
+        .clone()
+        .uploaded(relative_path, crc32c, compression, encryption);
+    Ok(Some(uploaded))
+}
+
+/// Compresses and encrypts the layer file as configured, into temporary files next to it, and
+/// uploads the result.
+async fn upload_transformed_layer(
+    conf: &'static PageServerConf,
+    storage: &GenericRemoteStorage,
+    key: Option<&TenantKey>,
+    source_path: &Path,
+    file_size: u64,
+    storage_path: &RemotePath,
+) -> anyhow::Result<(Option<LayerCompression>, Option<LayerEncryption>)> {
+    let compressed_path =
+        path_with_suffix_extension(source_path, &format!("compressed.{TEMP_FILE_SUFFIX}"));
+    let encrypted_path =
+        path_with_suffix_extension(source_path, &format!("encrypted.{TEMP_FILE_SUFFIX}"));
 
-    Ok(())
+    let res = async {
+        let mut upload_path = source_path;
+
+        let mut compression = None;
+        if let RemoteCompressionConfig::Zstd { level } = conf.remote_compression {
+            let compressed = compression::compress_file(level, source_path, &compressed_path)
+                .await
+                .with_context(|| format!("Failed to compress layer {source_path:?}"))?;
+            // Not worth decompressing on download if it doesn't save anything
+            if compressed.compressed_size() < file_size {
+                upload_path = compressed_path.as_path();
+                compression = Some(compressed);
+            }
+        }
+
+        let mut encryption = None;
+        if let Some(key) = key {
+            encryption::encrypt_file(key, upload_path, &encrypted_path)
+                .await
+                .with_context(|| format!("Failed to encrypt layer {upload_path:?}"))?;
+            upload_path = encrypted_path.as_path();
+            encryption = Some(LayerEncryption::ChunkedAes256Gcm);
+        }
+
+        let upload_file = fs::File::open(upload_path)
+            .await
+            .with_context(|| format!("Failed to open layer file {upload_path:?}"))?;
+        let upload_size = upload_file.metadata().await?.len();
+        multipart::upload_maybe_in_parts(
+            conf,
+            storage,
+            upload_path,
+            upload_file,
+            upload_size,
+            storage_path,
+        )
+        .await?;
+
+        Ok::<_, anyhow::Error>((compression, encryption))
+    }
+    .await;
+
+    // Startup would remove them as temporary files, but don't leave them around until then
+    for temp_path in [&compressed_path, &encrypted_path] {
+        if let Err(e) = fs::remove_file(temp_path).await {
+            if e.kind() != ErrorKind::NotFound {
+                warn!("Failed to remove temporary layer file {temp_path:?}: {e}");
+            }
+        }
+    }
+    res
+}
+
+/// Uploads a local file as is to the given path of the remote storage, returns its size.
+pub(super) async fn upload_file(
+    storage: &GenericRemoteStorage,
+    source_path: &Path,
+    storage_path: &RemotePath,
+) -> anyhow::Result<u64> {
+    let source_file = fs::File::open(source_path)
+        .await
+        .with_context(|| format!("Failed to open a source file {source_path:?}"))?;
+    let size = source_file
+        .metadata()
+        .await
+        .with_context(|| format!("Failed to get the source file metadata for {source_path:?}"))?
+        .len();
+    storage
+        .upload(source_file, usize::try_from(size)?, storage_path, None)
+        .await
+        .with_context(|| format!("Failed to upload {source_path:?} to {storage_path:?}"))?;
+    Ok(size)
 }
@@ -1,213 +1,372 @@
 //! This module contains functions to serve per-tenant background processes,
-//! such as compaction and GC
+//! such as compaction, GC, deferred deletion, cold tiering and the WAL redo ping
 
 use std::ops::ControlFlow;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
+use crate::background_jobs::{run_job, BackgroundJob, BackgroundJobKind};
 use crate::context::{DownloadBehavior, RequestContext};
 use crate::metrics::TENANT_TASK_EVENTS;
 use crate::task_mgr;
-use crate::task_mgr::{TaskKind, BACKGROUND_RUNTIME};
-use crate::tenant::{Tenant, TenantState};
+use crate::task_mgr::{RestartPolicy, TaskKind, BACKGROUND_RUNTIME};
+use crate::tenant::config::TieringPolicy;
+use crate::tenant::{deferred_deletion, Tenant, TenantState};
 use tokio_util::sync::CancellationToken;
 use tracing::*;
 use utils::completion;
 
-/// Start per tenant background loops: compaction and gc.
+/// Start per tenant background loops: compaction, gc, deferred deletion, cold tiering and the
+/// WAL redo ping.
 pub fn start_background_loops(
     tenant: &Arc<Tenant>,
     background_jobs_can_start: Option<&completion::Barrier>,
 ) {
     let tenant_id = tenant.tenant_id;
-    task_mgr::spawn(
+    task_mgr::spawn_supervised(
         BACKGROUND_RUNTIME.handle(),
         TaskKind::Compaction,
         Some(tenant_id),
         None,
         &format!("compactor for tenant {tenant_id}"),
-        false,
+        RestartPolicy::CRITICAL_BACKGROUND_LOOP,
         {
             let tenant = Arc::clone(tenant);
             let background_jobs_can_start = background_jobs_can_start.cloned();
-            async move {
-                let cancel = task_mgr::shutdown_token();
-                tokio::select! {
-                    _ = cancel.cancelled() => { return Ok(()) },
-                    _ = completion::Barrier::maybe_wait(background_jobs_can_start) => {}
-                };
-                compaction_loop(tenant, cancel)
-                    .instrument(info_span!("compaction_loop", tenant_id = %tenant_id))
-                    .await;
-                Ok(())
+            move || {
+                let tenant = Arc::clone(&tenant);
+                let background_jobs_can_start = background_jobs_can_start.clone();
+                async move {
+                    let cancel = task_mgr::shutdown_token();
+                    tokio::select! {
+                        _ = cancel.cancelled() => { return Ok(()) },
+                        _ = completion::Barrier::maybe_wait(background_jobs_can_start) => {}
+                    };
+                    compaction_loop(tenant, cancel)
+                        .instrument(info_span!("compaction_loop", tenant_id = %tenant_id))
+                        .await;
+                    Ok(())
+                }
             }
         },
+        set_broken_on_give_up(tenant),
     );
-    task_mgr::spawn(
+    task_mgr::spawn_supervised(
         BACKGROUND_RUNTIME.handle(),
         TaskKind::GarbageCollector,
         Some(tenant_id),
         None,
         &format!("garbage collector for tenant {tenant_id}"),
-        false,
+        RestartPolicy::CRITICAL_BACKGROUND_LOOP,
         {
             let tenant = Arc::clone(tenant);
             let background_jobs_can_start = background_jobs_can_start.cloned();
-            async move {
-                let cancel = task_mgr::shutdown_token();
-                tokio::select! {
-                    _ = cancel.cancelled() => { return Ok(()) },
-                    _ = completion::Barrier::maybe_wait(background_jobs_can_start) => {}
-                };
-                gc_loop(tenant, cancel)
-                    .instrument(info_span!("gc_loop", tenant_id = %tenant_id))
-                    .await;
-                Ok(())
+            move || {
+                let tenant = Arc::clone(&tenant);
+                let background_jobs_can_start = background_jobs_can_start.clone();
+                async move {
+                    let cancel = task_mgr::shutdown_token();
+                    tokio::select! {
+                        _ = cancel.cancelled() => { return Ok(()) },
+                        _ = completion::Barrier::maybe_wait(background_jobs_can_start) => {}
+                    };
+                    gc_loop(tenant, cancel)
+                        .instrument(info_span!("gc_loop", tenant_id = %tenant_id))
+                        .await;
+                    Ok(())
+                }
             }
         },
+        set_broken_on_give_up(tenant),
+    );
+    task_mgr::spawn_supervised(
+        BACKGROUND_RUNTIME.handle(),
+        TaskKind::DeferredDeletion,
+        Some(tenant_id),
+        None,
+        &format!("deferred deletion for tenant {tenant_id}"),
+        RestartPolicy::CRITICAL_BACKGROUND_LOOP,
+        {
+            let tenant = Arc::clone(tenant);
+            let background_jobs_can_start = background_jobs_can_start.cloned();
+            move || {
+                let tenant = Arc::clone(&tenant);
+                let background_jobs_can_start = background_jobs_can_start.clone();
+                async move {
+                    let cancel = task_mgr::shutdown_token();
+                    tokio::select! {
+                        _ = cancel.cancelled() => { return Ok(()) },
+                        _ = completion::Barrier::maybe_wait(background_jobs_can_start) => {}
+                    };
+                    deferred_deletion_loop(tenant, cancel)
+                        .instrument(info_span!("deferred_deletion_loop", tenant_id = %tenant_id))
+                        .await;
+                    Ok(())
+                }
+            }
+        },
+        set_broken_on_give_up(tenant),
+    );
+    task_mgr::spawn_supervised(
+        BACKGROUND_RUNTIME.handle(),
+        TaskKind::Tiering,
+        Some(tenant_id),
+        None,
+        &format!("cold tiering for tenant {tenant_id}"),
+        RestartPolicy::CRITICAL_BACKGROUND_LOOP,
+        {
+            let tenant = Arc::clone(tenant);
+            let background_jobs_can_start = background_jobs_can_start.cloned();
+            move || {
+                let tenant = Arc::clone(&tenant);
+                let background_jobs_can_start = background_jobs_can_start.clone();
+                async move {
+                    let cancel = task_mgr::shutdown_token();
+                    tokio::select! {
+                        _ = cancel.cancelled() => { return Ok(()) },
+                        _ = completion::Barrier::maybe_wait(background_jobs_can_start) => {}
+                    };
+                    tiering_loop(tenant, cancel)
+                        .instrument(info_span!("tiering_loop", tenant_id = %tenant_id))
+                        .await;
+                    Ok(())
+                }
+            }
+        },
+        set_broken_on_give_up(tenant),
+    );
+    task_mgr::spawn_supervised(
+        BACKGROUND_RUNTIME.handle(),
+        TaskKind::WalRedoPing,
+        Some(tenant_id),
+        None,
+        &format!("WAL redo ping for tenant {tenant_id}"),
+        RestartPolicy::CRITICAL_BACKGROUND_LOOP,
+        {
+            let tenant = Arc::clone(tenant);
+            let background_jobs_can_start = background_jobs_can_start.cloned();
+            move || {
+                let tenant = Arc::clone(&tenant);
+                let background_jobs_can_start = background_jobs_can_start.clone();
+                async move {
+                    let cancel = task_mgr::shutdown_token();
+                    tokio::select! {
+                        _ = cancel.cancelled() => { return Ok(()) },
+                        _ = completion::Barrier::maybe_wait(background_jobs_can_start) => {}
+                    };
+                    wal_redo_ping_loop(tenant, cancel)
+                        .instrument(info_span!("wal_redo_ping_loop", tenant_id = %tenant_id))
+                        .await;
+                    Ok(())
+                }
+            }
+        },
+        set_broken_on_give_up(tenant),
     );
 }
 
+/// A background loop that keeps panicking leaves the tenant without compaction or GC,
+/// so rather than letting it degrade silently, mark it Broken.
+fn set_broken_on_give_up(
+    tenant: &Arc<Tenant>,
+) -> impl FnOnce(String) -> futures::future::BoxFuture<'static, ()> + Send + 'static {
+    let tenant = Arc::clone(tenant);
+    move |reason| Box::pin(async move { tenant.set_broken_due_to_task_failure(reason).await })
+}
+
 ///
 /// Compaction task's main loop
 ///
 async fn compaction_loop(tenant: Arc<Tenant>, cancel: CancellationToken) {
-    let wait_duration = Duration::from_secs(2);
     info!("starting");
     TENANT_TASK_EVENTS.with_label_values(&["start"]).inc();
-    async {
-        let ctx = RequestContext::todo_child(TaskKind::Compaction, DownloadBehavior::Download);
-        let mut first = true;
-        loop {
-            trace!("waking up");
-
-            tokio::select! {
-                _ = cancel.cancelled() => {
-                    info!("received cancellation request");
-                    return;
-                },
-                tenant_wait_result = wait_for_active_tenant(&tenant) => match tenant_wait_result {
-                    ControlFlow::Break(()) => return,
-                    ControlFlow::Continue(()) => (),
-                },
-            }
+    let ctx = RequestContext::todo_child(TaskKind::Compaction, DownloadBehavior::Download);
+    run_job(
+        BackgroundJobKind::Compaction,
+        CompactionJob { tenant, ctx },
+        &cancel,
+    )
+    .await;
+    TENANT_TASK_EVENTS.with_label_values(&["stop"]).inc();
 
-            let period = tenant.get_compaction_period();
+    trace!("compaction loop stopped.");
+}
 
-            // TODO: we shouldn't need to await to find tenant and this could be moved outside of
-            // loop, #3501. There are also additional "allowed_errors" in tests.
-            if first {
-                first = false;
-                if random_init_delay(period, &cancel).await.is_err() {
-                    break;
-                }
-            }
+struct CompactionJob {
+    tenant: Arc<Tenant>,
+    ctx: RequestContext,
+}
 
-            let started_at = Instant::now();
-
-            let sleep_duration = if period == Duration::ZERO {
-                info!("automatic compaction is disabled");
-                // check again in 10 seconds, in case it's been enabled again.
-                Duration::from_secs(10)
-            } else {
-                // Run compaction
-                if let Err(e) = tenant.compaction_iteration(&ctx).await {
-                    error!("Compaction failed, retrying in {:?}: {e:?}", wait_duration);
-                    wait_duration
-                } else {
-                    period
-                }
-            };
+#[async_trait::async_trait]
+impl BackgroundJob for CompactionJob {
+    fn period(&self) -> Duration {
+        self.tenant.get_compaction_period()
+    }
 
-            warn_when_period_overrun(started_at.elapsed(), period, "compaction");
+    async fn wait_until_ready(&mut self) -> ControlFlow<()> {
+        wait_for_active_tenant(&self.tenant).await
+    }
 
-            // Sleep
-            tokio::select! {
-                _ = cancel.cancelled() => {
-                    info!("received cancellation request during idling");
-                    break;
-                },
-                _ = tokio::time::sleep(sleep_duration) => {},
-            }
+    async fn iteration(&mut self, _cancel: &CancellationToken) -> anyhow::Result<()> {
+        let res = self.tenant.compaction_iteration(&self.ctx).await;
+        // Also after a failed compaction, the layers pile up when compaction keeps failing.
+        self.tenant.check_layer_watermarks().await;
+        res
+    }
+}
+
+///
+/// GC task's main loop
+///
+async fn gc_loop(tenant: Arc<Tenant>, cancel: CancellationToken) {
+    info!("starting");
+    TENANT_TASK_EVENTS.with_label_values(&["start"]).inc();
+    // GC might require downloading, to find the cutoff LSN that corresponds to the
+    // cutoff specified as time.
+    let ctx = RequestContext::todo_child(TaskKind::GarbageCollector, DownloadBehavior::Download);
+    run_job(BackgroundJobKind::Gc, GcJob { tenant, ctx }, &cancel).await;
+    TENANT_TASK_EVENTS.with_label_values(&["stop"]).inc();
+    trace!("GC loop stopped.");
+}
+
+struct GcJob {
+    tenant: Arc<Tenant>,
+    ctx: RequestContext,
+}
+
+#[async_trait::async_trait]
+impl BackgroundJob for GcJob {
+    fn period(&self) -> Duration {
+        if self.tenant.get_gc_horizon() == 0 {
+            // GC is disabled.
+            Duration::ZERO
+        } else {
+            self.tenant.get_gc_period()
         }
     }
+
+    async fn wait_until_ready(&mut self) -> ControlFlow<()> {
+        wait_for_active_tenant(&self.tenant).await
+    }
+
+    async fn iteration(&mut self, _cancel: &CancellationToken) -> anyhow::Result<()> {
+        let tenant = &self.tenant;
+        tenant
+            .gc_iteration(
+                None,
+                tenant.get_gc_horizon(),
+                tenant.get_pitr_interval(),
+                &self.ctx,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+///
+/// Deferred deletion task's main loop
+///
+async fn deferred_deletion_loop(tenant: Arc<Tenant>, cancel: CancellationToken) {
+    info!("starting");
+    TENANT_TASK_EVENTS.with_label_values(&["start"]).inc();
+    run_job(
+        BackgroundJobKind::DeferredDeletion,
+        DeferredDeletionJob { tenant },
+        &cancel,
+    )
     .await;
     TENANT_TASK_EVENTS.with_label_values(&["stop"]).inc();
+    trace!("deferred deletion loop stopped.");
+}
 
-    trace!("compaction loop stopped.");
+struct DeferredDeletionJob {
+    tenant: Arc<Tenant>,
+}
+
+#[async_trait::async_trait]
+impl BackgroundJob for DeferredDeletionJob {
+    fn period(&self) -> Duration {
+        deferred_deletion::job_period(self.tenant.conf.remote_deletion_grace_period)
+    }
+
+    async fn wait_until_ready(&mut self) -> ControlFlow<()> {
+        wait_for_active_tenant(&self.tenant).await
+    }
+
+    async fn iteration(&mut self, _cancel: &CancellationToken) -> anyhow::Result<()> {
+        self.tenant.execute_deferred_deletions().await
+    }
 }
 
 ///
-/// GC task's main loop
+/// Cold tiering task's main loop
 ///
-async fn gc_loop(tenant: Arc<Tenant>, cancel: CancellationToken) {
-    let wait_duration = Duration::from_secs(2);
+async fn tiering_loop(tenant: Arc<Tenant>, cancel: CancellationToken) {
     info!("starting");
     TENANT_TASK_EVENTS.with_label_values(&["start"]).inc();
-    async {
-        // GC might require downloading, to find the cutoff LSN that corresponds to the
-        // cutoff specified as time.
-        let ctx =
-            RequestContext::todo_child(TaskKind::GarbageCollector, DownloadBehavior::Download);
-        let mut first = true;
-        loop {
-            trace!("waking up");
-
-            tokio::select! {
-                _ = cancel.cancelled() => {
-                    info!("received cancellation request");
-                    return;
-                },
-                tenant_wait_result = wait_for_active_tenant(&tenant) => match tenant_wait_result {
-                    ControlFlow::Break(()) => return,
-                    ControlFlow::Continue(()) => (),
-                },
-            }
-
-            let period = tenant.get_gc_period();
+    run_job(BackgroundJobKind::Tiering, TieringJob { tenant }, &cancel).await;
+    TENANT_TASK_EVENTS.with_label_values(&["stop"]).inc();
+    trace!("tiering loop stopped.");
+}
 
-            if first {
-                first = false;
-                if random_init_delay(period, &cancel).await.is_err() {
-                    break;
-                }
-            }
+struct TieringJob {
+    tenant: Arc<Tenant>,
+}
 
-            let started_at = Instant::now();
-
-            let gc_horizon = tenant.get_gc_horizon();
-            let sleep_duration = if period == Duration::ZERO || gc_horizon == 0 {
-                info!("automatic GC is disabled");
-                // check again in 10 seconds, in case it's been enabled again.
-                Duration::from_secs(10)
-            } else {
-                // Run gc
-                let res = tenant
-                    .gc_iteration(None, gc_horizon, tenant.get_pitr_interval(), &ctx)
-                    .await;
-                if let Err(e) = res {
-                    error!("Gc failed, retrying in {:?}: {e:?}", wait_duration);
-                    wait_duration
-                } else {
-                    period
-                }
-            };
+#[async_trait::async_trait]
+impl BackgroundJob for TieringJob {
+    fn period(&self) -> Duration {
+        match self.tenant.get_tiering_policy() {
+            // Tiering is disabled.
+            TieringPolicy::NoTiering => Duration::ZERO,
+            TieringPolicy::AgeThreshold(policy) => policy.period,
+        }
+    }
 
-            warn_when_period_overrun(started_at.elapsed(), period, "gc");
+    async fn wait_until_ready(&mut self) -> ControlFlow<()> {
+        wait_for_active_tenant(&self.tenant).await
+    }
 
-            // Sleep
-            tokio::select! {
-                _ = cancel.cancelled() => {
-                    info!("received cancellation request during idling");
-                    break;
-                },
-                _ = tokio::time::sleep(sleep_duration) => {},
+    async fn iteration(&mut self, cancel: &CancellationToken) -> anyhow::Result<()> {
+        match self.tenant.get_tiering_policy() {
+            TieringPolicy::NoTiering => Ok(()),
+            TieringPolicy::AgeThreshold(policy) => {
+                self.tenant.tiering_iteration(policy.threshold, cancel).await
             }
         }
     }
-    .await;
+}
+
+///
+/// WAL redo ping task's main loop. The first ping is right after the activation of the tenant,
+/// so that a missing postgres binary or a broken sandbox shows before the first getpage. Only a
+/// few tenants ping at once, see `DEFAULT_CONCURRENCY_CLASSES` in [`crate::background_jobs`].
+///
+async fn wal_redo_ping_loop(tenant: Arc<Tenant>, cancel: CancellationToken) {
+    info!("starting");
+    TENANT_TASK_EVENTS.with_label_values(&["start"]).inc();
+    run_job(BackgroundJobKind::WalRedoPing, WalRedoPingJob { tenant }, &cancel).await;
     TENANT_TASK_EVENTS.with_label_values(&["stop"]).inc();
-    trace!("GC loop stopped.");
+    trace!("WAL redo ping loop stopped.");
+}
+
+struct WalRedoPingJob {
+    tenant: Arc<Tenant>,
+}
+
+#[async_trait::async_trait]
+impl BackgroundJob for WalRedoPingJob {
+    fn period(&self) -> Duration {
+        self.tenant.conf.wal_redo_ping_period
+    }
+
+    async fn wait_until_ready(&mut self) -> ControlFlow<()> {
+        wait_for_active_tenant(&self.tenant).await
+    }
+
+    async fn iteration(&mut self, _cancel: &CancellationToken) -> anyhow::Result<()> {
+        self.tenant.ping_walredo().await
+    }
 }
 
 async fn wait_for_active_tenant(tenant: &Arc<Tenant>) -> ControlFlow<()> {
@@ -1,9 +1,13 @@
+pub mod coalescing;
 mod eviction_task;
+mod export;
 pub mod layer_manager;
 mod logical_size;
+pub mod prefetch;
 pub mod span;
 pub mod uninit;
 mod walreceiver;
+pub mod watermarks;
 
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use bytes::Bytes;
@@ -13,7 +17,7 @@ use itertools::Itertools;
 use pageserver_api::models::{
     DownloadRemoteLayersTaskInfo, DownloadRemoteLayersTaskSpawnRequest,
     DownloadRemoteLayersTaskState, LayerMapInfo, LayerResidenceEventReason, LayerResidenceStatus,
-    TimelineState,
+    PrefetchLayersResponse, TimelineState,
 };
 use remote_storage::GenericRemoteStorage;
 use serde_with::serde_as;
@@ -24,17 +28,22 @@ use tracing::*;
 use utils::id::TenantTimelineId;
 
 use std::cmp::{max, min, Ordering};
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::fs;
+use std::num::NonZeroUsize;
 use std::ops::{Deref, Range};
 use std::path::{Path, PathBuf};
 use std::pin::pin;
-use std::sync::atomic::Ordering as AtomicOrdering;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::{Arc, Mutex, RwLock, Weak};
 use std::time::{Duration, Instant, SystemTime};
 
 use crate::context::{DownloadBehavior, RequestContext};
-use crate::tenant::remote_timeline_client::{self, index::LayerFileMetadata};
+use crate::tenant::download_limiter::DownloadPriority;
+use crate::tenant::remote_timeline_client::{
+    self,
+    index::{LayerCreation, LayerFileMetadata, LayerSource},
+};
 use crate::tenant::storage_layer::{
     DeltaFileName, DeltaLayerWriter, ImageFileName, ImageLayerWriter, InMemoryLayer,
     LayerAccessStats, LayerFileName, RemoteLayer,
@@ -51,9 +60,11 @@ use crate::tenant::{
 use crate::config::PageServerConf;
 use crate::keyspace::{KeyPartitioning, KeySpace, KeySpaceRandomAccum};
 use crate::metrics::{
-    TimelineMetrics, MATERIALIZED_PAGE_CACHE_HIT, MATERIALIZED_PAGE_CACHE_HIT_DIRECT,
-    RECONSTRUCT_TIME, UNEXPECTED_ONDEMAND_DOWNLOADS,
+    GetPageStageTimings, TimelineMetrics, MATERIALIZED_PAGE_CACHE_HIT,
+    MATERIALIZED_PAGE_CACHE_HIT_DIRECT, RECONSTRUCT_TIME, UNEXPECTED_ONDEMAND_DOWNLOADS,
+    WAL_REDO_SLOW_REQUESTS,
 };
+use crate::pgdatadir_mapping::key_to_rel_block;
 use crate::pgdatadir_mapping::LsnForTimestamp;
 use crate::pgdatadir_mapping::{is_rel_fsm_block_key, is_rel_vm_block_key};
 use crate::pgdatadir_mapping::{BlockNumber, CalculateLogicalSizeError};
@@ -68,18 +79,22 @@ use utils::{
     lsn::{AtomicLsn, Lsn, RecordLsn},
     seqwait::SeqWait,
     simple_rcu::{Rcu, RcuReadGuard},
+    warn_rate_limited,
 };
 
 use crate::page_cache;
 use crate::repository::GcResult;
 use crate::repository::{Key, Value};
 use crate::task_mgr::TaskKind;
-use crate::walredo::WalRedoManager;
+use crate::walrecord::NeonWalRecord;
+use crate::walredo::{RedoRequest, RedoTimings, WalRedoError, WalRedoManager};
 use crate::METADATA_FILE_NAME;
 use crate::ZERO_PAGE;
 use crate::{is_temporary, task_mgr};
 
 pub(super) use self::eviction_task::EvictionTaskTenantState;
+use self::coalescing::IngestChurn;
+use self::prefetch::PrefetchScans;
 use self::eviction_task::EvictionTaskTimelineState;
 use self::layer_manager::LayerManager;
 use self::logical_size::LogicalSize;
@@ -89,7 +104,8 @@ use super::config::TenantConf;
 use super::remote_timeline_client::index::IndexPart;
 use super::remote_timeline_client::RemoteTimelineClient;
 use super::storage_layer::{
-    AsLayerDesc, DeltaLayer, ImageLayer, Layer, LayerAccessStatsReset, PersistentLayerDesc,
+    range_overlaps, AsLayerDesc, DeltaLayer, ImageLayer, Layer, LayerAccessStatsReset,
+    PersistentLayerDesc,
 };
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -178,6 +194,12 @@ pub struct Timeline {
     last_freeze_at: AtomicLsn,
     // Atomic would be more appropriate here.
     last_freeze_ts: RwLock<Instant>,
+    /// What was written since the last freeze, see [`coalescing`].
+    ingest_churn: IngestChurn,
+    /// The sequential scans of relations, see [`prefetch`].
+    prefetch_scans: Mutex<PrefetchScans>,
+    /// Number of prefetches of the tenant in flight, shared by its timelines.
+    prefetches_inflight: Arc<AtomicUsize>,
 
     // WAL redo manager
     walredo_mgr: Arc<dyn WalRedoManager + Sync + Send>,
@@ -398,6 +420,43 @@ pub enum LogicalSizeCalculationCause {
     TenantSizeHandler,
 }
 
+/// The version of a page, or the WAL redo that reconstructs it.
+enum Reconstruction {
+    Value(Bytes),
+    Redo(RedoRequest),
+}
+
+/// What is accounted for a WAL redo request once it's done, see [`Timeline::finish_redo`].
+struct RedoSummary {
+    key: Key,
+    request_lsn: Lsn,
+    base_img_lsn: Lsn,
+    last_rec_lsn: Lsn,
+    n_records: usize,
+    /// Of the Postgres records.
+    nbytes: usize,
+}
+
+impl RedoSummary {
+    fn new(request: &RedoRequest) -> Self {
+        RedoSummary {
+            key: request.key,
+            request_lsn: request.lsn,
+            base_img_lsn: request.base_img.as_ref().map_or(Lsn::INVALID, |(lsn, _)| *lsn),
+            last_rec_lsn: request.records.last().map_or(Lsn::INVALID, |(lsn, _)| *lsn),
+            n_records: request.records.len(),
+            nbytes: request
+                .records
+                .iter()
+                .map(|(_, rec)| match rec {
+                    NeonWalRecord::Postgres { rec, .. } => rec.len(),
+                    _ => 0,
+                })
+                .sum(),
+        }
+    }
+}
+
 /// Public interface functions
 impl Timeline {
     /// Get the LSN where this branch was created
@@ -447,17 +506,116 @@ impl Timeline {
             ctx.task_kind()
         );
 
+        let mut timings = GetPageStageTimings::default();
+        let res = self.get_impl(key, lsn, &mut timings, ctx).await;
+
+        if ctx.stage_timings() {
+            timings.record();
+            if self.conf.getpage_timing.as_ref().map_or(false, |config| config.trace) {
+                info!(
+                    layer_map_lookup = ?timings.layer_map_lookup,
+                    ondemand_download_wait = ?timings.ondemand_download_wait,
+                    redo_wait = ?timings.redo_wait,
+                    redo_execution = ?timings.redo_execution,
+                    page_cache = ?timings.page_cache,
+                    "sampled getpage request of {key}@{lsn}"
+                );
+            }
+        }
+        res
+    }
+
+    /// Look up the versions of several pages, like [`Self::get`] for each of them, with the WAL
+    /// redo that they need in one request, see [`WalRedoManager::request_redo_batch`].
+    ///
+    /// The results are in the order of `keys`. The stages of the lookups are not sampled.
+    pub async fn get_batch(
+        &self,
+        keys: &[Key],
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> Vec<Result<Bytes, PageReconstructError>> {
+        if !lsn.is_valid() {
+            return keys
+                .iter()
+                .map(|_| Err(PageReconstructError::Other(anyhow::anyhow!("Invalid LSN"))))
+                .collect();
+        }
+
+        let mut timings = GetPageStageTimings::default();
+        let mut results = Vec::with_capacity(keys.len());
+        let mut requests = Vec::new();
+        for &key in keys {
+            match self.get_reconstruction(key, lsn, &mut timings, ctx).await {
+                Ok(Reconstruction::Value(img)) => results.push(Some(Ok(img))),
+                Ok(Reconstruction::Redo(request)) => {
+                    requests.push((results.len(), request));
+                    results.push(None);
+                }
+                Err(e) => results.push(Some(Err(e))),
+            }
+        }
+
+        if !requests.is_empty() {
+            let _timer = RECONSTRUCT_TIME.start_timer();
+            let (indexes, requests): (Vec<_>, Vec<_>) = requests.into_iter().unzip();
+            let summaries: Vec<_> = requests.iter().map(RedoSummary::new).collect();
+            let mut redo_timings = RedoTimings::default();
+            let redone = self
+                .walredo_mgr
+                .request_redo_batch(requests, self.pg_version, &mut redo_timings)
+                .await;
+            // The time of the batch is shared out among its pages
+            let n = summaries.len() as u32;
+            let redo_timings = RedoTimings {
+                wait: redo_timings.wait / n,
+                execution: redo_timings.execution / n,
+            };
+            for ((i, summary), res) in indexes.into_iter().zip(summaries).zip(redone) {
+                results[i] = Some(self.finish_redo(summary, res, redo_timings, &mut timings));
+            }
+        }
+        results
+            .into_iter()
+            .map(|res| res.expect("every key is looked up"))
+            .collect()
+    }
+
+    async fn get_impl(
+        &self,
+        key: Key,
+        lsn: Lsn,
+        timings: &mut GetPageStageTimings,
+        ctx: &RequestContext,
+    ) -> Result<Bytes, PageReconstructError> {
+        let reconstruction = self.get_reconstruction(key, lsn, timings, ctx).await?;
+        let _timer = RECONSTRUCT_TIME.start_timer();
+        self.reconstruct_value(reconstruction, timings).await
+    }
+
+    /// Find the version of the page at `lsn`, or what it takes to reconstruct it.
+    async fn get_reconstruction(
+        &self,
+        key: Key,
+        lsn: Lsn,
+        timings: &mut GetPageStageTimings,
+        ctx: &RequestContext,
+    ) -> Result<Reconstruction, PageReconstructError> {
         // Check the page cache. We will get back the most recent page with lsn <= `lsn`.
         // The cached image can be returned directly if there is no WAL between the cached image
         // and requested LSN. The cached image can also be used to reduce the amount of WAL needed
         // for redo.
-        let cached_page_img = match self.lookup_cached_page(&key, lsn) {
+        let started = Instant::now();
+        let cached_page = self.lookup_cached_page(&key, lsn);
+        timings.page_cache += started.elapsed();
+        let cached_page_img = match cached_page {
             Some((cached_lsn, cached_img)) => {
                 match cached_lsn.cmp(&lsn) {
                     Ordering::Less => {} // there might be WAL between cached_lsn and lsn, we need to check
                     Ordering::Equal => {
                         MATERIALIZED_PAGE_CACHE_HIT_DIRECT.inc();
-                        return Ok(cached_img); // exact LSN match, return the image
+                        // exact LSN match, return the image
+                        return Ok(Reconstruction::Value(cached_img));
                     }
                     Ordering::Greater => {
                         unreachable!("the returned lsn should never be after the requested lsn")
@@ -474,12 +632,15 @@ impl Timeline {
         };
 
         let timer = self.metrics.get_reconstruct_data_time_histo.start_timer();
-        self.get_reconstruct_data(key, lsn, &mut reconstruct_state, ctx)
+        let started = Instant::now();
+        let mut download_wait = Duration::ZERO;
+        self.get_reconstruct_data(key, lsn, &mut reconstruct_state, &mut download_wait, ctx)
             .await?;
+        timings.layer_map_lookup += started.elapsed().saturating_sub(download_wait);
+        timings.ondemand_download_wait += download_wait;
         timer.stop_and_record();
 
-        RECONSTRUCT_TIME
-            .observe_closure_duration(|| self.reconstruct_value(key, lsn, reconstruct_state))
+        self.prepare_reconstruction(key, lsn, reconstruct_state)
     }
 
     /// Get last or prev record separately. Same as get_last_record_rlsn().last/prev.
@@ -525,6 +686,11 @@ impl Timeline {
         self.metrics.resident_physical_size_gauge.get()
     }
 
+    /// Count an ingested WAL record of `bytes` in the WAL ingested for the database `dboid`.
+    pub(crate) fn note_ingested_record(&self, dboid: u32, bytes: usize) {
+        self.metrics.wal_ingest_databases.inc(dboid, bytes as u64);
+    }
+
     ///
     /// Wait until WAL has been received and processed up to this LSN.
     ///
@@ -664,7 +830,9 @@ impl Timeline {
 
             let mut downloads = rls
                 .into_iter()
-                .map(|rl| self.download_remote_layer(rl))
+                .map(|rl| {
+                    self.download_remote_layer(rl, DownloadPriority::Background, ctx.cancel())
+                })
                 .collect::<futures::stream::FuturesUnordered<_>>();
 
             let mut failed = 0;
@@ -755,10 +923,11 @@ impl Timeline {
                 // 2. Create new image layers for partitions that have been modified
                 // "enough".
                 let layer_paths_to_upload = self
-                    .create_image_layers(&partitioning, lsn, false, ctx)
+                    .create_image_layers(&partitioning, lsn, false, LayerSource::Compaction, ctx)
                     .await
                     .map_err(anyhow::Error::from)?;
                 if let Some(remote_client) = &self.remote_client {
+                    remote_client.wait_for_queue_space().await?;
                     for (path, layer_metadata) in layer_paths_to_upload {
                         remote_client.schedule_layer_file_upload(&path, &layer_metadata)?;
                     }
@@ -837,9 +1006,14 @@ impl Timeline {
         // S3 has a 5 GB limit on the size of one upload (without multi-part upload), and
         // we want to stay below that with a big margin.  The LSN distance determines how
         // much WAL the safekeepers need to store.
+        //
+        // The timeout can be deferred to coalesce the churn on small relations.
+        let timed_out = distance > 0
+            && last_freeze_ts.elapsed() >= self.get_checkpoint_timeout()
+            && !self.defer_timeout_freeze(last_freeze_ts.elapsed());
         if distance >= self.get_checkpoint_distance().into()
             || open_layer_size > self.get_checkpoint_distance()
-            || (distance > 0 && last_freeze_ts.elapsed() >= self.get_checkpoint_timeout())
+            || timed_out
         {
             info!(
                 "check_checkpoint_distance {}, layer size {}, elapsed since last flush {:?}",
@@ -851,6 +1025,7 @@ impl Timeline {
             self.freeze_inmem_layer(true).await;
             self.last_freeze_at.store(last_lsn);
             *(self.last_freeze_ts.write().unwrap()) = Instant::now();
+            self.reset_ingest_churn();
 
             // Wake up the layer flusher
             self.flush_frozen_layers();
@@ -978,14 +1153,19 @@ impl Timeline {
     }
 
     #[instrument(skip_all, fields(tenant_id = %self.tenant_id, timeline_id = %self.timeline_id))]
-    pub async fn download_layer(&self, layer_file_name: &str) -> anyhow::Result<Option<bool>> {
+    pub async fn download_layer(
+        &self,
+        layer_file_name: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Option<bool>> {
         let Some(layer) = self.find_layer(layer_file_name).await else { return Ok(None) };
         let Some(remote_layer) = layer.downcast_remote_layer() else { return  Ok(Some(false)) };
         if self.remote_client.is_none() {
             return Ok(Some(false));
         }
 
-        self.download_remote_layer(remote_layer).await?;
+        self.download_remote_layer(remote_layer, DownloadPriority::Foreground, cancel)
+            .await?;
         Ok(Some(true))
     }
 
@@ -1084,7 +1264,12 @@ impl Timeline {
             let res = if cancel.is_cancelled() {
                 None
             } else {
-                Some(self.evict_layer_batch_impl(&layer_removal_guard, l, &mut guard))
+                Some(self.evict_layer_batch_impl(
+                    &layer_removal_guard,
+                    remote_client,
+                    l,
+                    &mut guard,
+                ))
             };
             results.push(res);
         }
@@ -1100,6 +1285,7 @@ impl Timeline {
     fn evict_layer_batch_impl(
         &self,
         _layer_removal_cs: &tokio::sync::MutexGuard<'_, ()>,
+        remote_client: &RemoteTimelineClient,
         local_layer: &Arc<dyn PersistentLayer>,
         layer_mgr: &mut LayerManager,
     ) -> anyhow::Result<bool> {
@@ -1127,7 +1313,11 @@ impl Timeline {
                 Ok(delta) => Some(delta),
             };
 
-        let layer_metadata = LayerFileMetadata::new(layer_file_size);
+        // Keep the checksum of the uploaded layer, to verify the file when downloading it again
+        let layer_metadata = remote_client
+            .get_layer_metadata(&local_layer.filename())?
+            .filter(|metadata| metadata.file_size() == layer_file_size)
+            .unwrap_or_else(|| LayerFileMetadata::new(layer_file_size));
 
         let new_remote_layer = Arc::new(match local_layer.filename() {
             LayerFileName::Image(image_name) => RemoteLayer::new_img(
@@ -1263,6 +1453,20 @@ impl Timeline {
             .unwrap_or(self.conf.default_tenant_conf.gc_feedback)
     }
 
+    fn get_prefetch_distance(&self) -> u32 {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .prefetch_distance
+            .unwrap_or(self.conf.default_tenant_conf.prefetch_distance)
+    }
+
+    fn get_prefetch_max_inflight(&self) -> usize {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .prefetch_max_inflight
+            .unwrap_or(self.conf.default_tenant_conf.prefetch_max_inflight)
+    }
+
     pub(super) fn tenant_conf_updated(&self) {
         // NB: Most tenant conf options are read by background loops, so,
         // changes will automatically be picked up.
@@ -1296,6 +1500,7 @@ impl Timeline {
         tenant_id: TenantId,
         walredo_mgr: Arc<dyn WalRedoManager + Send + Sync>,
         remote_client: Option<RemoteTimelineClient>,
+        prefetches_inflight: Arc<AtomicUsize>,
         pg_version: u32,
         initial_logical_size_can_start: Option<completion::Barrier>,
         initial_logical_size_attempt: Option<completion::Completion>,
@@ -1340,6 +1545,9 @@ impl Timeline {
 
                 last_freeze_at: AtomicLsn::new(disk_consistent_lsn.0),
                 last_freeze_ts: RwLock::new(Instant::now()),
+                ingest_churn: IngestChurn::default(),
+                prefetch_scans: Mutex::new(PrefetchScans::default()),
+                prefetches_inflight,
 
                 ancestor_timeline: ancestor,
                 ancestor_lsn: metadata.ancestor_lsn(),
@@ -1351,6 +1559,7 @@ impl Timeline {
                         "mtime",
                         evictions_low_residence_duration_metric_threshold,
                     ),
+                    conf.wal_redo_metrics_per_timeline,
                 ),
 
                 flush_loop_state: Mutex::new(FlushLoopState::NotStarted),
@@ -1593,7 +1802,10 @@ impl Timeline {
                 total_physical_size += file_size;
                 loaded_layers.push(Arc::new(layer));
                 num_layers += 1;
-            } else if fname == METADATA_FILE_NAME || fname.ends_with(".old") {
+            } else if fname == METADATA_FILE_NAME
+                || fname == IndexPart::PENDING_FILE_NAME
+                || fname.ends_with(".old")
+            {
                 // ignore these
             } else if remote_timeline_client::is_temp_download_file(&direntry_path) {
                 info!(
@@ -1637,6 +1849,7 @@ impl Timeline {
         index_part: &IndexPart,
         local_layers: HashMap<LayerFileName, Arc<dyn PersistentLayer>>,
         up_to_date_disk_consistent_lsn: Lsn,
+        lost_deletions: &HashSet<LayerFileName>,
     ) -> anyhow::Result<HashMap<LayerFileName, Arc<dyn PersistentLayer>>> {
         // Are we missing some files that are present in remote storage?
         // Create RemoteLayer instances for them.
@@ -1649,6 +1862,10 @@ impl Timeline {
         let mut corrupted_local_layers = Vec::new();
         let mut added_remote_layers = Vec::new();
         for remote_layer_name in &index_part.timeline_layers {
+            if lost_deletions.contains(remote_layer_name) {
+                // Deleted locally, its remote deletion is rescheduled
+                continue;
+            }
             let local_layer = local_only_layers.remove(remote_layer_name);
 
             let remote_layer_metadata = index_part
@@ -1768,6 +1985,8 @@ impl Timeline {
     ///    So, the layer map must have been loaded already.
     /// 3. Schedule upload of local-only layer files (which will then also update the remote
     ///    IndexPart to include the new layer files).
+    /// 4. Schedule the deletion of the layer files that the pending index of the previous
+    ///    upload queue had dropped, see [`IndexPart::PENDING_FILE_NAME`].
     ///
     /// Refer to the `storage_sync` module comment for more context.
     ///
@@ -1797,6 +2016,33 @@ impl Timeline {
                 .collect::<HashMap<_, _>>()
         };
 
+        // The remote state the upload queue of the previous load was stopped short of. An index
+        // written since by a later attachment supersedes it.
+        let pending_index = remote_client
+            .take_pending_index()?
+            .filter(|pending| index_part.map_or(true, |i| i.generation <= pending.generation));
+        let lost_deletions: HashSet<LayerFileName> = match (index_part, &pending_index) {
+            (Some(index_part), Some(pending)) => index_part
+                .timeline_layers
+                .difference(&pending.timeline_layers)
+                .filter(|name| !local_layers.contains_key(name))
+                .cloned()
+                .collect(),
+            _ => HashSet::new(),
+        };
+        if let Some(pending) = &pending_index {
+            let lost_uploads = pending
+                .timeline_layers
+                .iter()
+                .filter(|name| index_part.map_or(true, |i| !i.timeline_layers.contains(name)))
+                .count();
+            info!(
+                "found the pending index of the previous upload queue, {} layer uploads and {} deletions were lost",
+                lost_uploads,
+                lost_deletions.len()
+            );
+        }
+
         // If no writes happen, new branches do not have any layers, only the metadata file.
         let has_local_layers = !local_layers.is_empty();
         let local_only_layers = match index_part {
@@ -1806,8 +2052,13 @@ impl Timeline {
                     index_part.timeline_layers.len()
                 );
                 remote_client.init_upload_queue(index_part)?;
-                self.create_remote_layers(index_part, local_layers, disk_consistent_lsn)
-                    .await?
+                self.create_remote_layers(
+                    index_part,
+                    local_layers,
+                    disk_consistent_lsn,
+                    &lost_deletions,
+                )
+                .await?
             }
             None => {
                 info!("initializing upload queue as empty");
@@ -1851,6 +2102,21 @@ impl Timeline {
             // Local timeline has a metadata file, remote one too, both have no layers to sync.
         }
 
+        if !lost_deletions.is_empty() {
+            let names = lost_deletions.into_iter().collect::<Vec<_>>();
+            info!("rescheduling the deletion of {} layer files", names.len());
+            remote_client.schedule_layer_file_deletion(&names)?;
+        }
+
+        // The remote index is of an earlier attachment of the tenant. Write ours right away,
+        // for that attachment to stop writing at its next index upload.
+        if let (Some(index_part), Some(generation)) = (index_part, remote_client.generation()) {
+            if index_part.generation < Some(generation) {
+                info!("scheduling the upload of the index of generation {generation}");
+                remote_client.schedule_index_upload_for_metadata_update(up_to_date_metadata)?;
+            }
+        }
+
         info!("Done");
 
         Ok(())
@@ -2215,11 +2481,14 @@ impl Timeline {
     ///
     /// This function takes the current timeline's locked LayerMap as an argument,
     /// so callers can avoid potential race conditions.
+    ///
+    /// The time spent waiting for on-demand downloads is added to `download_wait`.
     async fn get_reconstruct_data(
         &self,
         key: Key,
         request_lsn: Lsn,
         reconstruct_state: &mut ValueReconstructState,
+        download_wait: &mut Duration,
         ctx: &RequestContext,
     ) -> Result<(), PageReconstructError> {
         // Start from the current timeline.
@@ -2484,7 +2753,13 @@ impl Timeline {
                             "on-demand downloading remote layer {id} for task kind {:?}",
                             ctx.task_kind()
                         );
-                        timeline.download_remote_layer(remote_layer).await?;
+                        let started = Instant::now();
+                        let priority = DownloadPriority::of_task(ctx.task_kind());
+                        let res = timeline
+                            .download_remote_layer(remote_layer, priority, ctx.cancel())
+                            .await;
+                        *download_wait += started.elapsed();
+                        res?;
                         continue 'layer_map_search;
                     }
                     (DownloadBehavior::Warn, _) | (DownloadBehavior::Error, true) => {
@@ -2494,7 +2769,13 @@ impl Timeline {
                             ctx.task_kind()
                         );
                         UNEXPECTED_ONDEMAND_DOWNLOADS.inc();
-                        timeline.download_remote_layer(remote_layer).await?;
+                        let started = Instant::now();
+                        let priority = DownloadPriority::of_task(ctx.task_kind());
+                        let res = timeline
+                            .download_remote_layer(remote_layer, priority, ctx.cancel())
+                            .await;
+                        *download_wait += started.elapsed();
+                        res?;
                         continue 'layer_map_search;
                     }
                     (DownloadBehavior::Error, false) => {
@@ -2694,8 +2975,14 @@ impl Timeline {
                 let (partitioning, _lsn) = self
                     .repartition(self.initdb_lsn, self.get_compaction_target_size(), ctx)
                     .await?;
-                self.create_image_layers(&partitioning, self.initdb_lsn, true, ctx)
-                    .await?
+                self.create_image_layers(
+                    &partitioning,
+                    self.initdb_lsn,
+                    true,
+                    LayerSource::Import,
+                    ctx,
+                )
+                .await?
             } else {
                 #[cfg(test)]
                 match &mut *self.flush_loop_state.lock().unwrap() {
@@ -2733,6 +3020,12 @@ impl Timeline {
 
         fail_point!("checkpoint-after-sync");
 
+        // Don't add to the uploads while the remote storage is behind, this holds back the
+        // ingestion of WAL
+        if let Some(remote_client) = &self.remote_client {
+            remote_client.wait_for_queue_space().await?;
+        }
+
         // Update the metadata file, with new 'disk_consistent_lsn'
         //
         // TODO: This perhaps should be done in 'flush_frozen_layers', after flushing
@@ -2872,7 +3165,8 @@ impl Timeline {
         self.metrics.num_persistent_files_created.inc_by(1);
         self.metrics.persistent_bytes_written.inc_by(sz);
 
-        Ok((new_delta_name, LayerFileMetadata::new(sz)))
+        let creation = LayerCreation::now(self.conf.id, 0, LayerSource::Flush);
+        Ok((new_delta_name, LayerFileMetadata::created(sz, creation)))
     }
 
     async fn repartition(
@@ -2990,6 +3284,7 @@ impl Timeline {
         partitioning: &KeyPartitioning,
         lsn: Lsn,
         force: bool,
+        source: LayerSource,
         ctx: &RequestContext,
     ) -> Result<HashMap<LayerFileName, LayerFileMetadata>, PageReconstructError> {
         let timer = self.metrics.create_images_time_histo.start_timer();
@@ -3102,7 +3397,9 @@ impl Timeline {
                 .metadata()
                 .with_context(|| format!("reading metadata of layer file {}", path.file_name()))?;
 
-            layer_paths_to_upload.insert(path, LayerFileMetadata::new(metadata.len()));
+            let creation = LayerCreation::now(self.conf.id, 1, source);
+            let layer_metadata = LayerFileMetadata::created(metadata.len(), creation);
+            layer_paths_to_upload.insert(path, layer_metadata);
 
             self.metrics
                 .resident_physical_size_gauge
@@ -3718,11 +4015,10 @@ impl Timeline {
                 )
             })?;
 
+            let creation = LayerCreation::now(self.conf.id, 1, LayerSource::Compaction);
+            let layer_metadata = LayerFileMetadata::created(metadata.len(), creation);
             if let Some(remote_client) = &self.remote_client {
-                remote_client.schedule_layer_file_upload(
-                    &l.filename(),
-                    &LayerFileMetadata::new(metadata.len()),
-                )?;
+                remote_client.schedule_layer_file_upload(&l.filename(), &layer_metadata)?;
             }
 
             // update the timeline's physical size
@@ -3730,7 +4026,7 @@ impl Timeline {
                 .resident_physical_size_gauge
                 .add(metadata.len());
 
-            new_layer_paths.insert(new_delta_path, LayerFileMetadata::new(metadata.len()));
+            new_layer_paths.insert(new_delta_path, layer_metadata);
             let x: Arc<dyn PersistentLayer + 'static> = Arc::new(l);
             x.access_stats().record_residence_event(
                 &guard,
@@ -4109,27 +4405,27 @@ impl Timeline {
     }
 
     ///
-    /// Reconstruct a value, using the given base image and WAL records in 'data'.
+    /// Check the base image and WAL records in 'data' of a value: the value is the image if
+    /// there are no records, otherwise they need WAL redo.
     ///
-    fn reconstruct_value(
+    fn prepare_reconstruction(
         &self,
         key: Key,
         request_lsn: Lsn,
         mut data: ValueReconstructState,
-    ) -> Result<Bytes, PageReconstructError> {
-        // Perform WAL redo if needed
+    ) -> Result<Reconstruction, PageReconstructError> {
         data.records.reverse();
 
         // If we have a page image, and no WAL, we're all set
         if data.records.is_empty() {
-            if let Some((img_lsn, img)) = &data.img {
+            if let Some((img_lsn, img)) = data.img {
                 trace!(
                     "found page image for key {} at {}, no WAL redo required, req LSN {}",
                     key,
                     img_lsn,
                     request_lsn,
                 );
-                Ok(img.clone())
+                Ok(Reconstruction::Value(img))
             } else {
                 Err(PageReconstructError::from(anyhow!(
                     "base image for {key} at {request_lsn} not found"
@@ -4159,36 +4455,102 @@ impl Timeline {
                     trace!("found {} WAL records that will init the page for {} at {}, performing WAL redo", data.records.len(), key, request_lsn);
                 };
 
-                let last_rec_lsn = data.records.last().unwrap().0;
+                Ok(Reconstruction::Redo(RedoRequest {
+                    key,
+                    lsn: request_lsn,
+                    base_img: data.img,
+                    records: data.records,
+                }))
+            }
+        }
+    }
 
-                let img = match self
-                    .walredo_mgr
-                    .request_redo(key, request_lsn, data.img, data.records, self.pg_version)
-                    .context("Failed to reconstruct a page image:")
-                {
-                    Ok(img) => img,
-                    Err(e) => return Err(PageReconstructError::from(e)),
-                };
+    ///
+    /// Reconstruct a value, performing the WAL redo that it needs.
+    ///
+    async fn reconstruct_value(
+        &self,
+        reconstruction: Reconstruction,
+        timings: &mut GetPageStageTimings,
+    ) -> Result<Bytes, PageReconstructError> {
+        let request = match reconstruction {
+            Reconstruction::Value(img) => return Ok(img),
+            Reconstruction::Redo(request) => request,
+        };
+        let summary = RedoSummary::new(&request);
+        let mut redo_timings = RedoTimings::default();
+        let res = self
+            .walredo_mgr
+            .request_redo(
+                request.key,
+                request.lsn,
+                request.base_img,
+                request.records,
+                self.pg_version,
+                &mut redo_timings,
+            )
+            .await;
+        self.finish_redo(summary, res, redo_timings, timings)
+    }
 
-                if img.len() == page_cache::PAGE_SZ {
-                    let cache = page_cache::get();
-                    if let Err(e) = cache
-                        .memorize_materialized_page(
-                            self.tenant_id,
-                            self.timeline_id,
-                            key,
-                            last_rec_lsn,
-                            &img,
-                        )
-                        .context("Materialized page memoization failed")
-                    {
-                        return Err(PageReconstructError::from(e));
-                    }
-                }
+    /// Account for the WAL redo of a value, and memorize the page that it reconstructed.
+    fn finish_redo(
+        &self,
+        summary: RedoSummary,
+        res: Result<Bytes, WalRedoError>,
+        redo_timings: RedoTimings,
+        timings: &mut GetPageStageTimings,
+    ) -> Result<Bytes, PageReconstructError> {
+        let RedoSummary {
+            key,
+            request_lsn,
+            base_img_lsn,
+            last_rec_lsn,
+            n_records,
+            nbytes,
+        } = summary;
+        let res = res.context("Failed to reconstruct a page image:");
+        timings.redo_wait += redo_timings.wait;
+        timings.redo_execution += redo_timings.execution;
+        self.metrics
+            .wal_redo
+            .observe(redo_timings.wait, redo_timings.execution, n_records, nbytes);
+        if redo_timings.wait + redo_timings.execution >= self.conf.wal_redo_slow_threshold {
+            let page = match key_to_rel_block(key) {
+                Ok((rel, blknum)) => format!("block {blknum} of relation {rel}"),
+                Err(_) => format!("key {key}"),
+            };
+            warn_rate_limited!(
+                WAL_REDO_SLOW_REQUESTS,
+                "slow WAL redo of {n_records} records ({nbytes} bytes) over the base image at LSN {base_img_lsn} to reconstruct {page} at LSN {request_lsn}: waited {:?}, applied in {:?}",
+                redo_timings.wait,
+                redo_timings.execution,
+            );
+        }
+        let img = match res {
+            Ok(img) => img,
+            Err(e) => return Err(PageReconstructError::from(e)),
+        };
 
-                Ok(img)
+        if img.len() == page_cache::PAGE_SZ {
+            let started = Instant::now();
+            let cache = page_cache::get();
+            let res = cache
+                .memorize_materialized_page(
+                    self.tenant_id,
+                    self.timeline_id,
+                    key,
+                    last_rec_lsn,
+                    &img,
+                )
+                .context("Materialized page memoization failed");
+            timings.page_cache += started.elapsed();
+            if let Err(e) = res {
+                return Err(PageReconstructError::from(e));
             }
         }
+
+        Ok(img)
     }
 
     /// Download a layer file from remote storage and insert it into the layer map.
@@ -4210,19 +4572,25 @@ impl Timeline {
     /// If the caller has a deadline or needs a timeout, they can simply stop polling:
     /// we're **cancellation-safe** because the download happens in a separate task_mgr task.
     /// So, the current download attempt will run to completion even if we stop polling.
+    /// To abort it instead, and free its download permits, cancel `cancel`, typically the
+    /// token of the [`RequestContext`] needing the layer. Shutting down the timeline aborts
+    /// it too. A concurrent caller waiting for the same layer then starts a new attempt.
     #[instrument(skip_all, fields(layer=%remote_layer))]
     pub async fn download_remote_layer(
         &self,
         remote_layer: Arc<RemoteLayer>,
+        priority: DownloadPriority,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
         span::debug_assert_current_span_has_tenant_and_timeline_id();
 
         use std::sync::atomic::Ordering::Relaxed;
 
-        let permit = match Arc::clone(&remote_layer.ongoing_download)
-            .acquire_owned()
-            .await
-        {
+        let permit = tokio::select! {
+            permit = Arc::clone(&remote_layer.ongoing_download).acquire_owned() => permit,
+            _ = cancel.cancelled() => anyhow::bail!("layer download cancelled"),
+        };
+        let permit = match permit {
             Ok(permit) => permit,
             Err(_closed) => {
                 if remote_layer.download_replacement_failure.load(Relaxed) {
@@ -4239,8 +4607,13 @@ impl Timeline {
                 }
             }
         };
+        let download_permit = tokio::select! {
+            permit = self.conf.concurrent_layer_downloads.acquire(priority) => permit,
+            _ = cancel.cancelled() => anyhow::bail!("layer download cancelled"),
+        };
 
         let (sender, receiver) = tokio::sync::oneshot::channel();
+        let cancel = cancel.clone();
         // Spawn a task so that download does not outlive timeline when we detach tenant / delete timeline.
         let self_clone = self.myself.upgrade().expect("timeline is gone");
         task_mgr::spawn(
@@ -4255,9 +4628,16 @@ impl Timeline {
 
                 // Does retries + exponential back-off internally.
                 // When this fails, don't layer further retry attempts here.
-                let result = remote_client
-                    .download_layer_file(&remote_layer.filename(), &remote_layer.layer_metadata)
-                    .await;
+                let layer_file_name = remote_layer.filename();
+                let download = remote_client.download_layer_file(
+                    &layer_file_name,
+                    &remote_layer.layer_metadata,
+                    &cancel,
+                );
+                let result = tokio::select! {
+                    result = download => result,
+                    _ = task_mgr::shutdown_watcher() => Err(anyhow::anyhow!("shutting down")),
+                };
 
                 if let Ok(size) = &result {
                     info!("layer file download finished");
@@ -4310,6 +4690,9 @@ impl Timeline {
                     // this download return Ok(()).
                     assert!(!remote_layer.ongoing_download.is_closed());
                     remote_layer.ongoing_download.close();
+                } else if cancel.is_cancelled() || task_mgr::shutdown_token().is_cancelled() {
+                    // Keep semaphore open, as below.
+                    info!("layer file download cancelled");
                 } else {
                     // Keep semaphore open. We'll drop the permit at the end of the function.
                     error!(
@@ -4326,6 +4709,7 @@ impl Timeline {
                 // of them retry the download in a new task.
                 // XXX: This resets the exponential backoff because it's a new call to
                 // download_layer file.
+                drop(download_permit);
                 drop(permit);
 
                 Ok(())
@@ -4398,6 +4782,7 @@ impl Timeline {
         self: &Arc<Self>,
         request: DownloadRemoteLayersTaskSpawnRequest,
     ) {
+        let cancel = task_mgr::shutdown_token();
         let mut downloads = Vec::new();
         {
             let guard = self.layers.read().await;
@@ -4406,7 +4791,7 @@ impl Timeline {
                 .iter_historic_layers()
                 .map(|l| guard.get_from_desc(&l))
                 .filter_map(|l| l.downcast_remote_layer())
-                .map(|l| self.download_remote_layer(l))
+                .map(|l| self.download_remote_layer(l, DownloadPriority::Background, &cancel))
                 .for_each(|dl| downloads.push(dl))
         }
         let total_layer_count = downloads.len();
@@ -4451,8 +4836,7 @@ impl Timeline {
                     }
                 }
                 _ = task_mgr::shutdown_watcher() => {
-                    // Kind of pointless to watch for shutdowns here,
-                    // as download_remote_layer spawns other task_mgr tasks internally.
+                    // The shutdown cancels the downloads too, so they finish soon.
                     lock_status!(st);
                     st.state = DownloadRemoteLayersTaskState::ShutDown;
                 }
@@ -4470,6 +4854,101 @@ impl Timeline {
             .unwrap()
             .clone()
     }
+
+    /// Download the remote layers overlapping `key_range` and `lsn_range` ahead of the reads,
+    /// e.g. after an attach, with the background download priority. Without an LSN range, the
+    /// layers that the reads at the latest LSN need, see [`latest_layers`].
+    pub async fn prefetch_layers(
+        &self,
+        key_range: Range<Key>,
+        lsn_range: Option<Range<Lsn>>,
+        max_concurrent_downloads: NonZeroUsize,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<PrefetchLayersResponse> {
+        anyhow::ensure!(
+            self.remote_client.is_some(),
+            "prefetching layers is not possible without remote storage"
+        );
+
+        let mut response = PrefetchLayersResponse::default();
+        let remote_layers = {
+            let guard = self.layers.read().await;
+            let overlapping = guard
+                .layer_map()
+                .iter_historic_layers()
+                .filter(|desc| range_overlaps(&desc.key_range, &key_range))
+                .collect::<Vec<_>>();
+            let selected = match lsn_range {
+                Some(lsn_range) => overlapping
+                    .into_iter()
+                    .filter(|desc| range_overlaps(&desc.lsn_range, &lsn_range))
+                    .collect(),
+                None => latest_layers(overlapping),
+            };
+            let mut remote_layers = Vec::new();
+            for desc in selected {
+                match guard.get_from_desc(&desc).downcast_remote_layer() {
+                    Some(remote_layer) => remote_layers.push(remote_layer),
+                    None => response.resident_layers += 1,
+                }
+            }
+            remote_layers
+        };
+
+        let downloads = remote_layers.into_iter().map(|remote_layer| async move {
+            let size = remote_layer.layer_metadata.file_size();
+            let res = self
+                .download_remote_layer(remote_layer, DownloadPriority::Background, cancel)
+                .await;
+            (size, res)
+        });
+        let mut downloads =
+            futures::stream::iter(downloads).buffer_unordered(max_concurrent_downloads.get());
+        while let Some((size, res)) = downloads.next().await {
+            match res {
+                Ok(()) => {
+                    response.downloaded_layers += 1;
+                    response.downloaded_bytes += size;
+                }
+                Err(e) => {
+                    warn!("failed to prefetch layer: {e:#}");
+                    response.failed_layers += 1;
+                }
+            }
+        }
+
+        info!(
+            "prefetched {} layers, {} bytes, {} resident already, {} failed",
+            response.downloaded_layers,
+            response.downloaded_bytes,
+            response.resident_layers,
+            response.failed_layers
+        );
+        Ok(response)
+    }
+}
+
+/// The layers that the reads at the latest LSN need: the latest image layer of each key range,
+/// and the delta layers above the oldest of them. All the delta layers, without image layers.
+fn latest_layers(layers: Vec<Arc<PersistentLayerDesc>>) -> Vec<Arc<PersistentLayerDesc>> {
+    let mut latest_images: HashMap<Range<Key>, Lsn> = HashMap::new();
+    for desc in layers.iter().filter(|desc| !desc.is_delta) {
+        let latest = latest_images
+            .entry(desc.key_range.clone())
+            .or_insert(desc.lsn_range.start);
+        *latest = (*latest).max(desc.lsn_range.start);
+    }
+    let oldest_latest_image = latest_images.values().min().copied().unwrap_or(Lsn(0));
+    layers
+        .into_iter()
+        .filter(|desc| {
+            if desc.is_delta {
+                desc.lsn_range.end > oldest_latest_image
+            } else {
+                latest_images.get(&desc.key_range) == Some(&desc.lsn_range.start)
+            }
+        })
+        .collect()
 }
 
 pub struct DiskUsageEvictionInfo {
@@ -0,0 +1,135 @@
+//! Coalescing of the write churn on small relations into fewer layers.
+//!
+//! The continuous aggregates of TimescaleDB keep rewriting a few pages of their small
+//! invalidation-log relations. With nothing else going on, every `checkpoint_timeout` freezes
+//! an open layer holding only a handful of page versions, and each of those tiny L0 layers
+//! makes reads and compaction more expensive. With the `ingest_coalescing` setting, the freeze
+//! on timeout is deferred for up to `max_delay` while all the relations written since the last
+//! freeze are small, so that the churn lands in one layer. The `checkpoint_distance` limits
+//! apply as usual, so the open layer cannot grow beyond them.
+//!
+//! Whether a relation is small is decided per relfilenode and fork, from its size in the
+//! relation size cache at the time of the write.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use anyhow::ensure;
+use pageserver_api::reltag::RelTag;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+use utils::lsn::Lsn;
+
+use super::Timeline;
+use crate::metrics::INGEST_DEFERRED_FREEZES;
+
+/// The `ingest_coalescing` setting.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IngestCoalescingConfig {
+    /// Relation forks of at most this many blocks are small.
+    #[serde(default = "default_max_rel_blocks")]
+    pub max_rel_blocks: u32,
+    /// Relfilenodes that are small regardless of their size.
+    #[serde(default)]
+    pub relfilenodes: Vec<u32>,
+    /// How long after the last freeze the open layer may stay open, instead of
+    /// `checkpoint_timeout`.
+    #[serde(with = "humantime_serde")]
+    pub max_delay: Duration,
+}
+
+fn default_max_rel_blocks() -> u32 {
+    16
+}
+
+impl IngestCoalescingConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(self.max_delay > Duration::ZERO, "max_delay must be positive");
+        Ok(())
+    }
+
+    fn is_small(&self, timeline: &Timeline, rel: &RelTag, lsn: Lsn) -> bool {
+        self.relfilenodes.contains(&rel.relnode)
+            || timeline
+                .get_cached_rel_size(rel, lsn)
+                .map_or(false, |nblocks| nblocks <= self.max_rel_blocks)
+    }
+}
+
+/// What was written since the open layer was last frozen on timeout or distance.
+#[derive(Debug, Default)]
+pub(super) struct IngestChurn {
+    /// Whether a relation that is not small was written.
+    large_writes: AtomicBool,
+    /// Whether the freeze on timeout has been deferred.
+    deferred: AtomicBool,
+}
+
+impl Timeline {
+    /// Note the relations written by an ingested WAL record.
+    pub(crate) fn note_ingested_rels(&self, rels: impl Iterator<Item = RelTag>, lsn: Lsn) {
+        let Some(config) = &self.conf.ingest_coalescing else {
+            return;
+        };
+        if self.ingest_churn.large_writes.load(Ordering::Relaxed) {
+            return;
+        }
+        for rel in rels {
+            if !config.is_small(self, &rel, lsn) {
+                self.ingest_churn.large_writes.store(true, Ordering::Relaxed);
+                return;
+            }
+        }
+    }
+
+    /// Whether the freeze of the open layer on `checkpoint_timeout` should wait, given the time
+    /// since the last freeze.
+    pub(super) fn defer_timeout_freeze(&self, elapsed: Duration) -> bool {
+        let Some(config) = &self.conf.ingest_coalescing else {
+            return false;
+        };
+        if self.ingest_churn.large_writes.load(Ordering::Relaxed) || elapsed >= config.max_delay {
+            return false;
+        }
+        if !self.ingest_churn.deferred.swap(true, Ordering::Relaxed) {
+            debug!("deferring the freeze of the open layer, only small relations were written");
+            INGEST_DEFERRED_FREEZES.inc();
+        }
+        true
+    }
+
+    /// Start over after the open layer was frozen.
+    pub(super) fn reset_ingest_churn(&self) {
+        self.ingest_churn.large_writes.store(false, Ordering::Relaxed);
+        self.ingest_churn.deferred.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let toml = "max_delay = '1h'";
+        let config: IngestCoalescingConfig = toml_edit::de::from_str(toml).unwrap();
+        assert_eq!(
+            config,
+            IngestCoalescingConfig {
+                max_rel_blocks: 16,
+                relfilenodes: Vec::new(),
+                max_delay: Duration::from_secs(3600),
+            }
+        );
+
+        let toml = "max_rel_blocks = 4\nrelfilenodes = [16384]\nmax_delay = '30m'";
+        let config: IngestCoalescingConfig = toml_edit::de::from_str(toml).unwrap();
+        assert_eq!(config.max_rel_blocks, 4);
+        assert_eq!(config.relfilenodes, vec![16384]);
+        config.validate().unwrap();
+
+        let config: IngestCoalescingConfig = toml_edit::de::from_str("max_delay = '0s'").unwrap();
+        assert!(config.validate().is_err());
+        assert!(toml_edit::de::from_str::<IngestCoalescingConfig>("max_rel_blocks = 4").is_err());
+    }
+}
@@ -25,6 +25,7 @@ use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, info_span, instrument, warn, Instrument};
 
 use crate::{
+    background_jobs::{run_job, BackgroundJob, BackgroundJobKind},
     context::{DownloadBehavior, RequestContext},
     task_mgr::{self, TaskKind, BACKGROUND_RUNTIME},
     tenant::{
@@ -80,35 +81,12 @@ impl Timeline {
         scopeguard::defer! {
             info!("eviction task finishing");
         }
-        use crate::tenant::tasks::random_init_delay;
-        {
-            let policy = self.get_eviction_policy();
-            let period = match policy {
-                EvictionPolicy::LayerAccessThreshold(lat) => lat.period,
-                EvictionPolicy::NoEviction => Duration::from_secs(10),
-            };
-            if random_init_delay(period, &cancel).await.is_err() {
-                return;
-            }
-        }
-
         let ctx = RequestContext::new(TaskKind::Eviction, DownloadBehavior::Warn);
-        loop {
-            let policy = self.get_eviction_policy();
-            let cf = self.eviction_iteration(&policy, &cancel, &ctx).await;
-
-            match cf {
-                ControlFlow::Break(()) => break,
-                ControlFlow::Continue(sleep_until) => {
-                    tokio::select! {
-                        _ = cancel.cancelled() => {
-                            break;
-                        }
-                        _ = tokio::time::sleep_until(sleep_until) => { }
-                    }
-                }
-            }
-        }
+        let job = EvictionJob {
+            timeline: self,
+            ctx,
+        };
+        run_job(BackgroundJobKind::Eviction, job, &cancel).await;
     }
 
     #[instrument(skip_all, fields(policy_kind = policy.discriminant_str()))]
@@ -117,21 +95,19 @@ impl Timeline {
         policy: &EvictionPolicy,
         cancel: &CancellationToken,
         ctx: &RequestContext,
-    ) -> ControlFlow<(), Instant> {
+    ) {
         debug!("eviction iteration: {policy:?}");
         match policy {
             EvictionPolicy::NoEviction => {
-                // check again in 10 seconds; XXX config watch mechanism
-                ControlFlow::Continue(Instant::now() + Duration::from_secs(10))
+                // The job is disabled, see EvictionJob::period. The policy changed since.
             }
             EvictionPolicy::LayerAccessThreshold(p) => {
                 let start = Instant::now();
                 match self.eviction_iteration_threshold(p, cancel, ctx).await {
-                    ControlFlow::Break(()) => return ControlFlow::Break(()),
+                    ControlFlow::Break(()) => return,
                     ControlFlow::Continue(()) => (),
                 }
                 let elapsed = start.elapsed();
-                crate::tenant::tasks::warn_when_period_overrun(elapsed, p.period, "eviction");
                 crate::metrics::EVICTION_ITERATION_DURATION
                     .get_metric_with_label_values(&[
                         &format!("{}", p.period.as_secs()),
@@ -139,7 +115,6 @@ impl Timeline {
                     ])
                     .unwrap()
                     .observe(elapsed.as_secs_f64());
-                ControlFlow::Continue(start + p.period)
             }
         }
     }
@@ -459,3 +434,24 @@ impl Timeline {
         }
     }
 }
+
+struct EvictionJob {
+    timeline: Arc<Timeline>,
+    ctx: RequestContext,
+}
+
+#[async_trait::async_trait]
+impl BackgroundJob for EvictionJob {
+    fn period(&self) -> Duration {
+        match self.timeline.get_eviction_policy() {
+            EvictionPolicy::LayerAccessThreshold(p) => p.period,
+            EvictionPolicy::NoEviction => Duration::ZERO,
+        }
+    }
+
+    async fn iteration(&mut self, cancel: &CancellationToken) -> anyhow::Result<()> {
+        let policy = self.timeline.get_eviction_policy();
+        self.timeline.eviction_iteration(&policy, cancel, &self.ctx).await;
+        Ok(())
+    }
+}
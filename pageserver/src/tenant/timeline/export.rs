@@ -0,0 +1,99 @@
+//! Export of a timeline as a basebackup tarball to a remote storage.
+//!
+//! Unlike the basebackups of the computes, the exported tarball is a full backup: it includes
+//! the relation data, so postgres starts from it like from the output of
+//! `pg_basebackup --format=tar`, without a pageserver. That's for archiving a timeline at an
+//! LSN, or migrating it off this storage. The tarball is written to a temporary file in the
+//! timeline directory, which the timeline load removes if the pageserver stops midway, then
+//! uploaded with the retries of the upload queue.
+use std::path::Path;
+
+use anyhow::Context;
+use async_compression::tokio::write::GzipEncoder;
+use pageserver_api::models::TimelineExportResponse;
+use remote_storage::{GenericRemoteStorage, RemotePath};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio_util::sync::CancellationToken;
+use tracing::*;
+use utils::lsn::Lsn;
+
+use super::Timeline;
+use crate::basebackup;
+use crate::context::RequestContext;
+use crate::TEMP_FILE_SUFFIX;
+
+impl Timeline {
+    /// Materialize the timeline at `lsn` into a basebackup tarball, and upload it to
+    /// `destination`, or to the remote storage of the timeline by default, under `remote_dir`.
+    /// The caller has checked that the LSN is valid.
+    pub async fn export_basebackup(
+        &self,
+        lsn: Lsn,
+        destination: Option<&GenericRemoteStorage>,
+        remote_dir: &RemotePath,
+        gzip: bool,
+        ctx: &RequestContext,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<TimelineExportResponse> {
+        let remote_client = self
+            .remote_client
+            .as_ref()
+            .context("export requires remote storage")?;
+
+        let extension = if gzip { "tar.gz" } else { "tar" };
+        let file_name = format!("{}-{:016X}.{extension}", self.timeline_id, lsn.0);
+        let remote_path = remote_dir.join(Path::new(&file_name));
+        let local_path = self
+            .conf
+            .timeline_path(&self.tenant_id, &self.timeline_id)
+            .join(format!("export-{file_name}.{TEMP_FILE_SUFFIX}"));
+
+        let res = async {
+            self.write_basebackup_file(&local_path, lsn, gzip, ctx)
+                .await
+                .context("write the basebackup tarball")?;
+            remote_client
+                .upload_export(&local_path, destination, &remote_path, cancel)
+                .await
+        }
+        .await;
+        if let Err(e) = tokio::fs::remove_file(&local_path).await {
+            warn!("failed to remove the exported tarball {local_path:?}: {e}");
+        }
+        let size = res?;
+
+        let remote_path = remote_path.get_path().to_string_lossy().into_owned();
+        info!(%lsn, %remote_path, size, "exported timeline");
+        Ok(TimelineExportResponse {
+            lsn,
+            remote_path,
+            size,
+        })
+    }
+
+    async fn write_basebackup_file(
+        &self,
+        path: &Path,
+        lsn: Lsn,
+        gzip: bool,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        let file = tokio::fs::File::create(path)
+            .await
+            .with_context(|| format!("create {path:?}"))?;
+        let mut writer = BufWriter::new(file);
+        if gzip {
+            // The tarball is not on the critical path of anything, compress it well
+            let mut encoder = GzipEncoder::with_quality(writer, async_compression::Level::Default);
+            basebackup::send_basebackup_tarball(&mut encoder, self, Some(lsn), None, true, ctx)
+                .await?;
+            // shutdown the encoder to ensure the gzip footer is written
+            encoder.shutdown().await?;
+        } else {
+            basebackup::send_basebackup_tarball(&mut writer, self, Some(lsn), None, true, ctx)
+                .await?;
+            writer.flush().await?;
+        }
+        Ok(())
+    }
+}
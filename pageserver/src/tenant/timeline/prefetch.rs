@@ -0,0 +1,294 @@
+//! Prefetching for sequential scans of a relation.
+//!
+//! A time-range query on a TimescaleDB hypertable scans the chunks of the range one after
+//! another, each of them a relation read from its first block to its last. The compute asks for
+//! one page at a time, so every page that has to be reconstructed, or whose layer has to be
+//! downloaded first, stalls the scan. When the getpage requests for a relfilenode and fork form
+//! a sequential run, the pages ahead of the scan are read in the background, up to
+//! `prefetch_distance` blocks ahead. Reading them downloads the layers they are in, if they
+//! are not resident, and leaves the pages in the page cache for the requests to come.
+//!
+//! A tenant has at most `prefetch_max_inflight` prefetches running at a time, prefetches beyond
+//! that are not started.
+//!
+//! The pages are read [`PREFETCH_BATCH_BLOCKS`] at a time, with the WAL redo of a batch in one
+//! request to the WAL redo process.
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use pageserver_api::reltag::RelTag;
+use tokio_util::sync::CancellationToken;
+use tracing::*;
+use utils::lsn::Lsn;
+
+use super::Timeline;
+use crate::context::{DownloadBehavior, RequestContext};
+use crate::metrics::{PREFETCH_PAGES, PREFETCH_SKIPPED};
+use crate::pgdatadir_mapping::BlockNumber;
+use crate::task_mgr::{self, TaskKind, BACKGROUND_RUNTIME};
+
+/// Number of consecutive blocks read before the reads are taken for a sequential scan.
+const SCAN_TRIGGER_BLOCKS: u32 = 3;
+
+/// Number of blocks read together by a prefetch, see [`Timeline::get_rel_pages_at_lsn`].
+const PREFETCH_BATCH_BLOCKS: u32 = 8;
+
+/// Number of relations whose scans are tracked per timeline.
+const MAX_TRACKED_SCANS: usize = 64;
+
+/// The getpage requests of a relation seen so far.
+#[derive(Debug, Clone, Copy)]
+struct Scan {
+    /// The block a sequential scan reads next.
+    next_blkno: BlockNumber,
+    /// Number of consecutive blocks read up to `next_blkno`.
+    run: u32,
+    /// The end of the blocks prefetched for the scan.
+    prefetched_until: BlockNumber,
+    last_access: Instant,
+}
+
+/// The scans of the relations of a timeline.
+#[derive(Debug, Default)]
+pub(super) struct PrefetchScans {
+    scans: HashMap<RelTag, Scan>,
+}
+
+impl PrefetchScans {
+    /// Note a read of `blkno`, and return the blocks to prefetch for it, if any.
+    ///
+    /// Prefetching starts once the scan is sequential, and then goes on in batches of at least
+    /// half the `distance`.
+    fn on_read(
+        &mut self,
+        rel: RelTag,
+        blkno: BlockNumber,
+        distance: u32,
+        now: Instant,
+    ) -> Option<Range<BlockNumber>> {
+        if self.scans.len() >= MAX_TRACKED_SCANS && !self.scans.contains_key(&rel) {
+            let oldest = self
+                .scans
+                .iter()
+                .min_by_key(|(_, scan)| scan.last_access)
+                .map(|(rel, _)| *rel);
+            if let Some(oldest) = oldest {
+                self.scans.remove(&oldest);
+            }
+        }
+
+        let scan = self.scans.entry(rel).or_insert(Scan {
+            next_blkno: blkno,
+            run: 0,
+            prefetched_until: 0,
+            last_access: now,
+        });
+        scan.last_access = now;
+        if blkno == scan.next_blkno {
+            scan.run += 1;
+        } else if blkno.saturating_add(1) != scan.next_blkno {
+            // Not a repeated read of the last block either, the scan starts over.
+            scan.run = 1;
+            scan.prefetched_until = 0;
+        }
+        scan.next_blkno = blkno.saturating_add(1);
+
+        if scan.run < SCAN_TRIGGER_BLOCKS {
+            return None;
+        }
+        if scan.prefetched_until > blkno.saturating_add(distance / 2) {
+            return None;
+        }
+        let start = scan.prefetched_until.max(scan.next_blkno);
+        let end = scan.next_blkno.saturating_add(distance);
+        (start < end).then_some(start..end)
+    }
+
+    /// Note that the scan of `rel` was prefetched up to `end`.
+    fn prefetched(&mut self, rel: RelTag, end: BlockNumber) {
+        if let Some(scan) = self.scans.get_mut(&rel) {
+            scan.prefetched_until = end;
+        }
+    }
+}
+
+/// A prefetch counted in the prefetches of the tenant in flight.
+struct InflightPrefetch(Arc<AtomicUsize>);
+
+impl InflightPrefetch {
+    fn try_start(inflight: &Arc<AtomicUsize>, max_inflight: usize) -> Option<Self> {
+        inflight
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < max_inflight).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| InflightPrefetch(Arc::clone(inflight)))
+    }
+}
+
+impl Drop for InflightPrefetch {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Timeline {
+    /// Note a getpage request for `blkno` of `rel` at `lsn`, and prefetch the blocks ahead of
+    /// it if it continues a sequential scan.
+    pub(crate) fn note_getpage(&self, rel: RelTag, blkno: BlockNumber, lsn: Lsn, latest: bool) {
+        let distance = self.get_prefetch_distance();
+        if distance == 0 {
+            return;
+        }
+
+        let (blocks, inflight) = {
+            let mut scans = self.prefetch_scans.lock().unwrap();
+            let Some(blocks) = scans.on_read(rel, blkno, distance, Instant::now()) else {
+                return;
+            };
+            let max_inflight = self.get_prefetch_max_inflight();
+            let inflight = InflightPrefetch::try_start(&self.prefetches_inflight, max_inflight);
+            let Some(inflight) = inflight else {
+                PREFETCH_SKIPPED.inc();
+                return;
+            };
+            scans.prefetched(rel, blocks.end);
+            (blocks, inflight)
+        };
+
+        let Some(self_clone) = self.myself.upgrade() else {
+            return;
+        };
+        task_mgr::spawn(
+            BACKGROUND_RUNTIME.handle(),
+            TaskKind::Prefetch,
+            Some(self.tenant_id),
+            Some(self.timeline_id),
+            "prefetch",
+            false,
+            async move {
+                let _inflight = inflight;
+                let ctx = RequestContext::new(TaskKind::Prefetch, DownloadBehavior::Download);
+                let cancel = task_mgr::shutdown_token();
+                let prefetch = self_clone.prefetch(rel, blocks, lsn, latest, &cancel, &ctx);
+                if let Err(e) = prefetch.await {
+                    // The compute asks for the page itself, and gets the error if it persists.
+                    debug!("prefetch failed: {e:#}");
+                }
+                Ok(())
+            }
+            .instrument(info_span!(
+                "prefetch",
+                tenant_id = %self.tenant_id,
+                timeline_id = %self.timeline_id,
+                %rel
+            )),
+        );
+    }
+
+    async fn prefetch(
+        &self,
+        rel: RelTag,
+        blocks: Range<BlockNumber>,
+        lsn: Lsn,
+        latest: bool,
+        cancel: &CancellationToken,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        let nblocks = self.get_rel_size(rel, lsn, latest, ctx).await?;
+        let end = blocks.end.min(nblocks);
+        let mut start = blocks.start;
+        while start < end {
+            if cancel.is_cancelled() {
+                break;
+            }
+            let batch_end = end.min(start.saturating_add(PREFETCH_BATCH_BLOCKS));
+            let pages = self
+                .get_rel_pages_at_lsn(rel, start..batch_end, lsn, latest, ctx)
+                .await?;
+            PREFETCH_PAGES.inc_by(pages.len() as u64);
+            start = batch_end;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn rel(relnode: u32) -> RelTag {
+        RelTag {
+            forknum: 0,
+            spcnode: 1663,
+            dbnode: 16384,
+            relnode,
+        }
+    }
+
+    #[test]
+    fn sequential_scan() {
+        let mut scans = PrefetchScans::default();
+        let now = Instant::now();
+
+        // Random reads are not prefetched.
+        assert_eq!(scans.on_read(rel(1), 10, 16, now), None);
+        assert_eq!(scans.on_read(rel(1), 3, 16, now), None);
+        assert_eq!(scans.on_read(rel(1), 40, 16, now), None);
+
+        // The third block in a row starts the prefetching.
+        assert_eq!(scans.on_read(rel(2), 0, 16, now), None);
+        assert_eq!(scans.on_read(rel(2), 1, 16, now), None);
+        assert_eq!(scans.on_read(rel(2), 2, 16, now), Some(3..19));
+        scans.prefetched(rel(2), 19);
+
+        // A repeated read does not break the scan, and the next batch starts once less than
+        // half the distance is left prefetched.
+        assert_eq!(scans.on_read(rel(2), 2, 16, now), None);
+        for blkno in 3..=10 {
+            assert_eq!(scans.on_read(rel(2), blkno, 16, now), None);
+        }
+        assert_eq!(scans.on_read(rel(2), 11, 16, now), Some(19..28));
+
+        // Without the batch being started, it is retried on the next read.
+        assert_eq!(scans.on_read(rel(2), 12, 16, now), Some(19..29));
+        scans.prefetched(rel(2), 29);
+
+        // Other relations are tracked on their own.
+        assert_eq!(scans.on_read(rel(1), 41, 16, now), None);
+        assert_eq!(scans.on_read(rel(1), 42, 16, now), Some(43..59));
+
+        // A jump starts over.
+        assert_eq!(scans.on_read(rel(2), 100, 16, now), None);
+        assert_eq!(scans.on_read(rel(2), 101, 16, now), None);
+        assert_eq!(scans.on_read(rel(2), 102, 16, now), Some(103..119));
+    }
+
+    #[test]
+    fn tracked_scans_are_bounded() {
+        let mut scans = PrefetchScans::default();
+        let now = Instant::now();
+        for relnode in 0..(MAX_TRACKED_SCANS as u32 * 2) {
+            let at = now + Duration::from_millis(relnode as u64);
+            scans.on_read(rel(relnode + 1), 0, 16, at);
+        }
+        assert_eq!(scans.scans.len(), MAX_TRACKED_SCANS);
+        assert!(scans.scans.contains_key(&rel(MAX_TRACKED_SCANS as u32 * 2)));
+        assert!(!scans.scans.contains_key(&rel(1)));
+    }
+
+    #[test]
+    fn inflight_limit() {
+        let inflight = Arc::new(AtomicUsize::new(0));
+        let first = InflightPrefetch::try_start(&inflight, 2).unwrap();
+        let _second = InflightPrefetch::try_start(&inflight, 2).unwrap();
+        assert!(InflightPrefetch::try_start(&inflight, 2).is_none());
+        drop(first);
+        assert!(InflightPrefetch::try_start(&inflight, 2).is_some());
+        assert_eq!(inflight.load(Ordering::Relaxed), 1);
+    }
+}
@@ -26,6 +26,7 @@ use tracing::{debug, error, info, trace, warn, Instrument};
 use super::TaskStateUpdate;
 use crate::{
     context::RequestContext,
+    disk_space_monitor,
     metrics::{LIVE_CONNECTIONS_COUNT, WALRECEIVER_STARTED_CONNECTIONS},
     task_mgr,
     task_mgr::TaskKind,
@@ -211,7 +212,11 @@ pub(super) async fn handle_walreceiver_connection(
                 debug!("walreceiver interrupted");
                 None
             }
-            replication_message = physical_stream.next() => replication_message,
+            replication_message = async {
+                // Don't write more layers while the disk is about to fill up.
+                disk_space_monitor::wal_ingest_allowed().await;
+                physical_stream.next().await
+            } => replication_message,
         }
     } {
         let replication_message = replication_message?;
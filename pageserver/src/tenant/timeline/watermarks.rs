@@ -0,0 +1,195 @@
+//! Warning and critical thresholds on the layer count and the local size of a timeline.
+//!
+//! When compaction or GC keeps failing, the layers of a timeline pile up until the node runs
+//! out of disk space. The watermarks catch that early: they are checked after every
+//! compaction iteration of the tenant, failed or not. Each time a timeline crosses a threshold,
+//! in either direction, the crossing is logged, and the `pageserver_timeline_watermark_level`
+//! metric of the resource changes to the new level.
+use anyhow::ensure;
+use metrics::IntGauge;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use super::Timeline;
+
+/// The `layer_watermarks` setting.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayerWatermarksConfig {
+    /// Number of layer files of a timeline, both resident and evicted ones.
+    #[serde(default)]
+    pub layer_count: Option<Watermark>,
+    /// Size of the layer files of a timeline that are present locally, in bytes.
+    #[serde(default)]
+    pub local_size_bytes: Option<Watermark>,
+}
+
+impl LayerWatermarksConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, watermark) in [
+            ("layer_count", &self.layer_count),
+            ("local_size_bytes", &self.local_size_bytes),
+        ] {
+            if let Some(watermark) = watermark {
+                ensure!(
+                    watermark.warning <= watermark.critical,
+                    "the warning threshold of {name} is above the critical one"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Watermark {
+    pub warning: u64,
+    pub critical: u64,
+}
+
+impl Watermark {
+    fn level(&self, value: u64) -> WatermarkLevel {
+        if value >= self.critical {
+            WatermarkLevel::Critical
+        } else if value >= self.warning {
+            WatermarkLevel::Warning
+        } else {
+            WatermarkLevel::Normal
+        }
+    }
+}
+
+/// The value of the `pageserver_timeline_watermark_level` metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display)]
+#[strum(serialize_all = "snake_case")]
+enum WatermarkLevel {
+    Normal = 0,
+    Warning = 1,
+    Critical = 2,
+}
+
+impl WatermarkLevel {
+    fn from_gauge(gauge: &IntGauge) -> Self {
+        match gauge.get() {
+            2 => WatermarkLevel::Critical,
+            1 => WatermarkLevel::Warning,
+            _ => WatermarkLevel::Normal,
+        }
+    }
+}
+
+impl Timeline {
+    /// Check the layer count and the local size against the `layer_watermarks` setting.
+    pub(crate) async fn check_layer_watermarks(&self) {
+        let Some(config) = &self.conf.layer_watermarks else {
+            return;
+        };
+
+        if let Some(watermark) = &config.layer_count {
+            let layer_count = {
+                let guard = self.layers.read().await;
+                guard.layer_map().iter_historic_layers().count() as u64
+            };
+            update_level(
+                "layer_count",
+                &self.metrics.layer_count_watermark_gauge,
+                watermark,
+                layer_count,
+            );
+        }
+
+        if let Some(watermark) = &config.local_size_bytes {
+            update_level(
+                "local_size",
+                &self.metrics.local_size_watermark_gauge,
+                watermark,
+                self.get_resident_physical_size(),
+            );
+        }
+    }
+}
+
+/// Set the level of the resource, and log if that crossed a threshold.
+fn update_level(resource: &str, gauge: &IntGauge, watermark: &Watermark, value: u64) {
+    let level = watermark.level(value);
+    let previous = WatermarkLevel::from_gauge(gauge);
+    gauge.set(level as i64);
+    if level == previous {
+        return;
+    }
+
+    let (warning, critical) = (watermark.warning, watermark.critical);
+    match level {
+        WatermarkLevel::Critical => error!(
+            resource,
+            value,
+            warning,
+            critical,
+            %previous,
+            "timeline {resource} crossed the critical watermark"
+        ),
+        WatermarkLevel::Warning => warn!(
+            resource,
+            value,
+            warning,
+            critical,
+            %previous,
+            "timeline {resource} crossed the warning watermark"
+        ),
+        WatermarkLevel::Normal => info!(
+            resource,
+            value,
+            warning,
+            critical,
+            %previous,
+            "timeline {resource} is below the watermarks again"
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        let watermark = Watermark {
+            warning: 10,
+            critical: 20,
+        };
+        let gauge = IntGauge::new("watermark_level", "test").unwrap();
+
+        for (value, expected) in [
+            (0, WatermarkLevel::Normal),
+            (10, WatermarkLevel::Warning),
+            (25, WatermarkLevel::Critical),
+            (19, WatermarkLevel::Warning),
+            (9, WatermarkLevel::Normal),
+        ] {
+            update_level("layer_count", &gauge, &watermark, value);
+            assert_eq!(WatermarkLevel::from_gauge(&gauge), expected, "value {value}");
+        }
+    }
+
+    #[test]
+    fn parse_config() {
+        let toml = "layer_count = { warning = 1000, critical = 5000 }";
+        let config: LayerWatermarksConfig = toml_edit::de::from_str(toml).unwrap();
+        assert_eq!(
+            config,
+            LayerWatermarksConfig {
+                layer_count: Some(Watermark {
+                    warning: 1000,
+                    critical: 5000,
+                }),
+                local_size_bytes: None,
+            }
+        );
+        assert!(toml_edit::de::from_str::<LayerWatermarksConfig>("layer_count = 1000").is_err());
+
+        let toml = "local_size_bytes = { warning = 2000, critical = 1000 }";
+        let config: LayerWatermarksConfig = toml_edit::de::from_str(toml).unwrap();
+        assert!(config.validate().is_err());
+    }
+}
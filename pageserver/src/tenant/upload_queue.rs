@@ -6,12 +6,21 @@ use crate::tenant::remote_timeline_client::index::IndexPart;
 use crate::tenant::remote_timeline_client::index::LayerFileMetadata;
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::time::SystemTime;
 
 use chrono::NaiveDateTime;
+use pageserver_api::models::{
+    TimelineDeletionProgress, UploadOpInfo, UploadOpKind, UploadQueueInfo, UploadQueueState,
+    UploadTaskInfo,
+};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tracing::info;
 
-use std::sync::atomic::AtomicU32;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tokio_util::sync::CancellationToken;
+use utils::instrumented_mutex::Mutex;
 use utils::lsn::Lsn;
 
 // clippy warns that Uninitialized is much smaller than Initialized, which wastes
@@ -35,6 +44,55 @@ impl UploadQueue {
     }
 }
 
+/// The `upload_queue_limits` setting: how much work the upload queue of a timeline may have
+/// pending before the flushes and compactions of the timeline wait for it, see
+/// [`crate::tenant::remote_timeline_client::RemoteTimelineClient::wait_for_queue_space`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UploadQueueLimitsConfig {
+    /// Queued and in-progress operations.
+    pub max_queued_ops: Option<NonZeroUsize>,
+    /// Size of the layers queued or in progress for upload.
+    pub max_queued_bytes: Option<NonZeroU64>,
+}
+
+/// The `remote_index_lsn_check` setting: what to do with an index upload whose
+/// `disk_consistent_lsn` is behind the one of the last index uploaded. The safekeepers trim
+/// their WAL up to the `remote_consistent_lsn` we report, so an index that goes back in LSN
+/// references layer files missing WAL that's no longer anywhere.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexLsnCheck {
+    /// Upload the index as it is.
+    Off,
+    /// Upload the index, with a warning and the `pageserver_remote_index_lsn_regressions_total`
+    /// metric.
+    #[default]
+    Warn,
+    /// Reject the upload and retry it, which blocks the queue, unless the regression has been
+    /// allowed for the timeline, see
+    /// [`crate::tenant::remote_timeline_client::RemoteTimelineClient::allow_index_lsn_regression`].
+    Enforce,
+}
+
+/// An index upload rejected by [`IndexLsnCheck::Enforce`].
+#[derive(Debug, thiserror::Error)]
+#[error("index upload at disk_consistent_lsn {lsn} is behind the last uploaded {last_uploaded}")]
+pub(crate) struct IndexLsnRegressionError {
+    pub lsn: Lsn,
+    pub last_uploaded: Lsn,
+}
+
+/// How much work the upload queue has left, reported while waiting for it, see
+/// [`crate::tenant::remote_timeline_client::RemoteTimelineClient::wait_completion_with_progress`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UploadQueueProgress {
+    /// Queued and in-progress operations, not counting the barriers.
+    pub ops_remaining: usize,
+    /// Size of the layers queued or in progress for upload.
+    pub bytes_remaining: u64,
+}
+
 /// This keeps track of queued and in-progress tasks.
 pub(crate) struct UploadQueueInitialized {
     /// Counter to assign task IDs
@@ -59,6 +117,10 @@ pub(crate) struct UploadQueueInitialized {
     /// Safekeeper can rely on it to make decisions for WAL storage.
     pub(crate) last_uploaded_consistent_lsn: Lsn,
 
+    /// When the last index upload completed, or when the queue was initialized if none has
+    /// since. The remote state of the timeline was current then.
+    pub(crate) last_index_upload_at: SystemTime,
+
     // Breakdown of different kinds of tasks currently in-progress
     pub(crate) num_inprogress_layer_uploads: usize,
     pub(crate) num_inprogress_metadata_uploads: usize,
@@ -72,14 +134,203 @@ pub(crate) struct UploadQueueInitialized {
 
     /// Queued operations that have not been launched yet. They might depend on previous
     /// tasks to finish. For example, metadata upload cannot be performed before all
-    /// preceding layer file uploads have completed.
-    pub(crate) queued_operations: VecDeque<UploadOp>,
+    /// preceding layer file uploads have completed. In the order they were scheduled, see
+    /// [`Self::push_op`].
+    pub(crate) queued_operations: VecDeque<QueuedOp>,
+
+    /// Size of the layer uploads in `queued_operations` and `inprogress_tasks`.
+    pub(crate) queued_layer_bytes: u64,
+
+    /// No new operations are accepted, the queued ones run to completion. See
+    /// `RemoteTimelineClient::drain`.
+    pub(crate) draining: bool,
+
+    /// The next index upload may regress the `disk_consistent_lsn`, see [`IndexLsnCheck`].
+    pub(crate) allow_lsn_regression: bool,
+
+    /// The launches are paused by the circuit breaker of the remote storage, and a task will
+    /// launch the queued operations once it's no longer open. See
+    /// [`crate::tenant::remote_timeline_client::health`].
+    pub(crate) relaunch_scheduled: bool,
 }
 
 impl UploadQueueInitialized {
     pub(super) fn no_pending_work(&self) -> bool {
         self.inprogress_tasks.is_empty() && self.queued_operations.is_empty()
     }
+
+    /// Whether the next queued operation is an index upload followed by another one, which
+    /// supersedes it.
+    pub(super) fn next_index_upload_superseded(&self) -> bool {
+        let mut ops = self.queued_operations.iter().map(|queued| &queued.op);
+        matches!(
+            (ops.next(), ops.next()),
+            (
+                Some(UploadOp::UploadMetadata(_, _)),
+                Some(UploadOp::UploadMetadata(_, _))
+            )
+        )
+    }
+
+    /// Whether any of the first `end` queued operations is an index upload referencing the layer.
+    pub(super) fn queued_index_upload_references(
+        &self,
+        end: usize,
+        layer_file_name: &LayerFileName,
+    ) -> bool {
+        self.queued_operations.range(..end).any(|queued| match &queued.op {
+            UploadOp::UploadMetadata(index_part, _) => {
+                index_part.layer_metadata.contains_key(layer_file_name)
+            }
+            _ => false,
+        })
+    }
+
+    /// Record the metadata of an uploaded layer, with its checksum. The queued index uploads
+    /// referencing the layer were scheduled after its upload, and have been waiting for it.
+    pub(super) fn record_uploaded_layer(
+        &mut self,
+        layer_file_name: &LayerFileName,
+        uploaded: &LayerFileMetadata,
+    ) {
+        if let Some(metadata) = self.latest_files.get_mut(layer_file_name) {
+            *metadata = uploaded.clone();
+        }
+        for queued in self.queued_operations.iter_mut() {
+            if let UploadOp::UploadMetadata(index_part, _) = &mut queued.op {
+                index_part.set_uploaded_layer_metadata(layer_file_name, uploaded);
+            }
+        }
+    }
+
+    /// Remove a cancelled in-progress task, along with the queued operations that depend on it,
+    /// transitively: the index uploads referencing the layer of a cancelled layer upload, and
+    /// the deletions waiting for a removed index upload, which dereferenced their layers.
+    /// Returns the removed queued operations.
+    pub(super) fn remove_cancelled_task(&mut self, task: &UploadTask) -> Vec<UploadOp> {
+        self.inprogress_tasks.remove(&task.task_id);
+
+        let mut cancelled_layer = None;
+        // Whether the last index upload queued so far has been removed
+        let mut index_upload_removed = false;
+        match &task.op {
+            UploadOp::UploadLayer(layer_file_name, layer_metadata) => {
+                self.num_inprogress_layer_uploads -= 1;
+                self.queued_layer_bytes -= layer_metadata.file_size();
+                // The later index uploads must not reference it
+                self.latest_files.remove(layer_file_name);
+                cancelled_layer = Some(layer_file_name);
+            }
+            UploadOp::UploadMetadata(_, _) => {
+                self.num_inprogress_metadata_uploads -= 1;
+                index_upload_removed = true;
+            }
+            UploadOp::Delete(_) => {
+                self.num_inprogress_deletions -= 1;
+            }
+            UploadOp::Barrier(_) => unreachable!("barriers are never in progress"),
+        }
+
+        let mut removed = Vec::new();
+        let mut kept = VecDeque::with_capacity(self.queued_operations.len());
+        for queued in self.queued_operations.drain(..) {
+            let depends = match &queued.op {
+                UploadOp::UploadMetadata(index_part, _) => {
+                    index_upload_removed = cancelled_layer
+                        .map_or(false, |name| index_part.layer_metadata.contains_key(name));
+                    index_upload_removed
+                }
+                UploadOp::Delete(_) => index_upload_removed,
+                UploadOp::UploadLayer(_, _) | UploadOp::Barrier(_) => false,
+            };
+            if depends {
+                removed.push(queued.op);
+            } else {
+                kept.push_back(queued);
+            }
+        }
+        self.queued_operations = kept;
+        removed
+    }
+
+    /// Whether an upload of the layer file is queued or in progress.
+    pub(crate) fn has_layer_upload(&self, layer_file_name: &LayerFileName) -> bool {
+        self.queued_operations
+            .iter()
+            .map(|queued| &queued.op)
+            .chain(self.inprogress_tasks.values().map(|task| &task.op))
+            .any(|op| matches!(op, UploadOp::UploadLayer(name, _) if name == layer_file_name))
+    }
+
+    /// Merge the deletions queued right after a launched deletion into it, so that their layer
+    /// files are deleted with one request. Returns the merged operations.
+    pub(super) fn merge_queued_deletions(&mut self, delete: &mut Delete) -> Vec<UploadOp> {
+        let mut merged = Vec::new();
+        while let Some(QueuedOp {
+            op: UploadOp::Delete(next),
+            ..
+        }) = self.queued_operations.front()
+        {
+            let batch_size = delete.layer_file_names.len() + next.layer_file_names.len();
+            if next.file_kind != delete.file_kind
+                || next.scheduled_from_timeline_delete != delete.scheduled_from_timeline_delete
+                || next.deferred != delete.deferred
+                || next.hot_copies_only != delete.hot_copies_only
+                || batch_size > MAX_DELETE_BATCH_SIZE
+            {
+                break;
+            }
+            let op = self.queued_operations.pop_front().unwrap().op;
+            if let UploadOp::Delete(next) = &op {
+                delete
+                    .layer_file_names
+                    .extend(next.layer_file_names.iter().cloned());
+            }
+            merged.push(op);
+        }
+        merged
+    }
+
+    pub(super) fn progress(&self) -> UploadQueueProgress {
+        let queued_ops = self
+            .queued_operations
+            .iter()
+            .filter(|queued| !matches!(queued.op, UploadOp::Barrier(_)))
+            .count();
+        UploadQueueProgress {
+            ops_remaining: self.inprogress_tasks.len() + queued_ops,
+            bytes_remaining: self.queued_layer_bytes,
+        }
+    }
+
+    /// Schedule an operation, after the ones already queued.
+    pub(super) fn push_op(&mut self, op: UploadOp) {
+        self.queued_operations.push_back(QueuedOp {
+            op,
+            queued_at: SystemTime::now(),
+        });
+    }
+
+    /// When the oldest queued or in-progress operation was scheduled, `None` if there is none.
+    pub(super) fn oldest_op_queued_at(&self) -> Option<SystemTime> {
+        // The queued operations are in the order they were scheduled, but the in-progress
+        // ones are not: layer uploads jump ahead of index uploads.
+        let oldest_queued = self.queued_operations.front().map(|queued| queued.queued_at);
+        self.inprogress_tasks
+            .values()
+            .map(|task| task.queued_at)
+            .chain(oldest_queued)
+            .min()
+    }
+
+    pub(super) fn exceeds_limits(&self, limits: &UploadQueueLimitsConfig) -> bool {
+        let queued_ops = self.queued_operations.len() + self.inprogress_tasks.len();
+        let too_many_ops = limits.max_queued_ops.map_or(false, |max| queued_ops >= max.get());
+        let too_many_bytes = limits
+            .max_queued_bytes
+            .map_or(false, |max| self.queued_layer_bytes >= max.get());
+        too_many_ops || too_many_bytes
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -92,6 +343,40 @@ pub(super) enum SetDeletedFlagProgress {
 pub(super) struct UploadQueueStopped {
     pub(super) upload_queue_for_deletion: UploadQueueInitialized,
     pub(super) deleted_at: SetDeletedFlagProgress,
+    /// The layer files deleted by the deletion of the timeline, which removes them from
+    /// `latest_files` of `upload_queue_for_deletion` as they are deleted.
+    pub(super) layers_deleted: usize,
+    /// Of those, the ones deleted before the last upload of the index with `deleted_at`.
+    pub(super) layers_checkpointed: usize,
+}
+
+impl UploadQueueStopped {
+    /// Record the deletion of layer files scheduled from the deletion of the timeline, so
+    /// that the next checkpoint of the index no longer references them.
+    pub(super) fn record_deleted_layers(&mut self, layer_file_names: &[LayerFileName]) {
+        for name in layer_file_names {
+            if self
+                .upload_queue_for_deletion
+                .latest_files
+                .remove(name)
+                .is_some()
+            {
+                self.layers_deleted += 1;
+            }
+        }
+    }
+
+    /// The progress of the deletion of the timeline, once `deleted_at` is set.
+    pub(super) fn deletion_progress(&self) -> Option<TimelineDeletionProgress> {
+        match self.deleted_at {
+            SetDeletedFlagProgress::Successful(_) => Some(TimelineDeletionProgress {
+                layers_deleted: self.layers_deleted as u64,
+                layers_remaining: self.upload_queue_for_deletion.latest_files.len() as u64,
+                layers_checkpointed: self.layers_checkpointed as u64,
+            }),
+            SetDeletedFlagProgress::NotRunning | SetDeletedFlagProgress::InProgress(_) => None,
+        }
+    }
 }
 
 impl UploadQueue {
@@ -116,6 +401,7 @@ impl UploadQueue {
             // We haven't uploaded anything yet, so, `last_uploaded_consistent_lsn` must be 0 to prevent
             // safekeepers from garbage-collecting anything.
             last_uploaded_consistent_lsn: Lsn(0),
+            last_index_upload_at: SystemTime::now(),
             // what follows are boring default initializations
             task_counter: 0,
             num_inprogress_layer_uploads: 0,
@@ -123,6 +409,10 @@ impl UploadQueue {
             num_inprogress_deletions: 0,
             inprogress_tasks: HashMap::new(),
             queued_operations: VecDeque::new(),
+            queued_layer_bytes: 0,
+            draining: false,
+            allow_lsn_regression: false,
+            relaunch_scheduled: false,
         };
 
         *self = UploadQueue::Initialized(state);
@@ -170,6 +460,7 @@ impl UploadQueue {
             latest_files_changes_since_metadata_upload_scheduled: 0,
             latest_metadata: index_part_metadata.clone(),
             last_uploaded_consistent_lsn: index_part_metadata.disk_consistent_lsn(),
+            last_index_upload_at: SystemTime::now(),
             // what follows are boring default initializations
             task_counter: 0,
             num_inprogress_layer_uploads: 0,
@@ -177,6 +468,10 @@ impl UploadQueue {
             num_inprogress_deletions: 0,
             inprogress_tasks: HashMap::new(),
             queued_operations: VecDeque::new(),
+            queued_layer_bytes: 0,
+            draining: false,
+            allow_lsn_regression: false,
+            relaunch_scheduled: false,
         };
 
         *self = UploadQueue::Initialized(state);
@@ -192,6 +487,60 @@ impl UploadQueue {
         }
     }
 
+    /// Like [`Self::initialized_mut`], to schedule new operations, which a draining queue
+    /// doesn't accept.
+    pub(crate) fn schedulable_mut(&mut self) -> anyhow::Result<&mut UploadQueueInitialized> {
+        let queue = self.initialized_mut()?;
+        anyhow::ensure!(!queue.draining, "queue is draining");
+        Ok(queue)
+    }
+
+    /// The state of the queue, for the management API.
+    pub(crate) fn info(&self) -> UploadQueueInfo {
+        let (state, queue) = match self {
+            UploadQueue::Uninitialized => (UploadQueueState::Uninitialized, None),
+            UploadQueue::Initialized(queue) => (UploadQueueState::Initialized, Some(queue)),
+            UploadQueue::Stopped(stopped) => (
+                UploadQueueState::Stopped,
+                Some(&stopped.upload_queue_for_deletion),
+            ),
+        };
+        let mut info = UploadQueueInfo {
+            state,
+            last_uploaded_consistent_lsn: None,
+            allow_lsn_regression: false,
+            queued_layer_bytes: 0,
+            inprogress_tasks: Vec::new(),
+            queued_operations: Vec::new(),
+            deletion_progress: None,
+        };
+        if let UploadQueue::Stopped(stopped) = self {
+            info.deletion_progress = stopped.deletion_progress();
+        }
+        if let Some(queue) = queue {
+            info.last_uploaded_consistent_lsn = Some(queue.last_uploaded_consistent_lsn);
+            info.allow_lsn_regression = queue.allow_lsn_regression;
+            info.queued_layer_bytes = queue.queued_layer_bytes;
+            info.inprogress_tasks = queue.inprogress_tasks.values().map(|t| t.info()).collect();
+            info.inprogress_tasks.sort_by_key(|task| task.task_id);
+            info.queued_operations = queue
+                .queued_operations
+                .iter()
+                .map(|queued| queued.op.info())
+                .collect();
+        }
+        info
+    }
+
+    /// The work left in the queue. A stopped queue only has the deletions of the timeline.
+    pub(crate) fn progress(&self) -> UploadQueueProgress {
+        match self {
+            UploadQueue::Uninitialized => UploadQueueProgress::default(),
+            UploadQueue::Initialized(queue) => queue.progress(),
+            UploadQueue::Stopped(stopped) => stopped.upload_queue_for_deletion.progress(),
+        }
+    }
+
     pub(crate) fn stopped_mut(&mut self) -> anyhow::Result<&mut UploadQueueStopped> {
         match self {
             UploadQueue::Initialized(_) | UploadQueue::Uninitialized => {
@@ -208,15 +557,52 @@ pub(crate) struct UploadTask {
     /// Unique ID of this task. Used as the key in `inprogress_tasks` above.
     pub(crate) task_id: u64,
     pub(crate) retries: AtomicU32,
+    /// The error of the last failed attempt.
+    pub(crate) last_error: Mutex<Option<String>>,
+    /// Cancelled by `RemoteTimelineClient::cancel_task`, under the `upload_queue` lock.
+    pub(crate) cancel: CancellationToken,
+    /// When the operation was scheduled, see [`QueuedOp::queued_at`].
+    pub(crate) queued_at: SystemTime,
 
     pub(crate) op: UploadOp,
 }
 
+impl UploadTask {
+    fn info(&self) -> UploadTaskInfo {
+        UploadTaskInfo {
+            task_id: self.task_id,
+            op: self.op.info(),
+            retries: self.retries.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// The maximum number of keys in an S3 DeleteObjects request.
+pub(crate) const MAX_DELETE_BATCH_SIZE: usize = 1000;
+
 #[derive(Debug)]
 pub(crate) struct Delete {
     pub(crate) file_kind: RemoteOpFileKind,
-    pub(crate) layer_file_name: LayerFileName,
+    /// Deleted with one request. Each deletion is scheduled with its own layer file, and
+    /// the ones queued together are merged when launched, see
+    /// [`UploadQueueInitialized::merge_queued_deletions`].
+    pub(crate) layer_file_names: Vec<LayerFileName>,
     pub(crate) scheduled_from_timeline_delete: bool,
+    /// The deletion was deferred before and is due now, so it isn't deferred again, see
+    /// [`crate::tenant::deferred_deletion`].
+    pub(crate) deferred: bool,
+    /// Delete only the copies at the default location of layer files moved to the cold tier,
+    /// which the index references, see [`super::remote_timeline_client::tiering`].
+    pub(crate) hot_copies_only: bool,
+}
+
+/// An operation waiting in the queue.
+#[derive(Debug)]
+pub(crate) struct QueuedOp {
+    pub(crate) op: UploadOp,
+    /// When the operation was scheduled, for the age of the oldest operation of the queue.
+    pub(crate) queued_at: SystemTime,
 }
 
 #[derive(Debug)]
@@ -227,13 +613,43 @@ pub(crate) enum UploadOp {
     /// Upload the metadata file
     UploadMetadata(IndexPart, Lsn),
 
-    /// Delete a layer file
+    /// Delete layer files
     Delete(Delete),
 
     /// Barrier. When the barrier operation is reached,
     Barrier(tokio::sync::watch::Sender<()>),
 }
 
+impl UploadOp {
+    fn info(&self) -> UploadOpInfo {
+        let mut info = UploadOpInfo {
+            kind: UploadOpKind::Barrier,
+            layer_file_name: None,
+            file_size: None,
+            disk_consistent_lsn: None,
+            layer_count: None,
+        };
+        match self {
+            UploadOp::UploadLayer(layer_file_name, metadata) => {
+                info.kind = UploadOpKind::UploadLayer;
+                info.layer_file_name = Some(layer_file_name.file_name());
+                info.file_size = Some(metadata.file_size());
+            }
+            UploadOp::UploadMetadata(_, lsn) => {
+                info.kind = UploadOpKind::UploadIndex;
+                info.disk_consistent_lsn = Some(*lsn);
+            }
+            UploadOp::Delete(delete) => {
+                info.kind = UploadOpKind::Delete;
+                info.layer_file_name = delete.layer_file_names.first().map(|n| n.file_name());
+                info.layer_count = Some(delete.layer_file_names.len() as u64);
+            }
+            UploadOp::Barrier(_) => {}
+        }
+        info
+    }
+}
+
 impl std::fmt::Display for UploadOp {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
@@ -246,12 +662,20 @@ impl std::fmt::Display for UploadOp {
                 )
             }
             UploadOp::UploadMetadata(_, lsn) => write!(f, "UploadMetadata(lsn: {})", lsn),
-            UploadOp::Delete(delete) => write!(
-                f,
-                "Delete(path: {}, scheduled_from_timeline_delete: {})",
-                delete.layer_file_name.file_name(),
-                delete.scheduled_from_timeline_delete
-            ),
+            UploadOp::Delete(delete) => match delete.layer_file_names.as_slice() {
+                [layer_file_name] => write!(
+                    f,
+                    "Delete(path: {}, scheduled_from_timeline_delete: {})",
+                    layer_file_name.file_name(),
+                    delete.scheduled_from_timeline_delete
+                ),
+                layer_file_names => write!(
+                    f,
+                    "Delete({} paths, scheduled_from_timeline_delete: {})",
+                    layer_file_names.len(),
+                    delete.scheduled_from_timeline_delete
+                ),
+            },
             UploadOp::Barrier(_) => write!(f, "Barrier"),
         }
     }
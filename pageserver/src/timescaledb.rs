@@ -0,0 +1,189 @@
+//! Knowledge of the storage layout of the TimescaleDB extension.
+//!
+//! TimescaleDB partitions a hypertable by time into chunks, which are ordinary tables in the
+//! `_timescaledb_internal` schema named `_hyper_<hypertable id>_<chunk id>_chunk`. The indexes
+//! of a chunk are named after the chunk, with the name of the index of the hypertable
+//! appended. A compressed chunk `compress_hyper_<id>_<chunk id>_chunk` belongs to an internal
+//! hypertable of its own, which the hypertable the user sees refers to with its
+//! `compressed_hypertable_id`.
+use std::collections::HashMap;
+
+use anyhow::Context;
+use pageserver_api::reltag::RelTag;
+use postgres_ffi::relfile_utils::MAIN_FORKNUM;
+use postgres_ffi::Oid;
+use utils::lsn::Lsn;
+
+use crate::catalog::{scan_heap, Catalog, HeapTuple, PgClass};
+use crate::context::RequestContext;
+use crate::tenant::Timeline;
+
+/// The name of the extension in `pg_extension`.
+pub const EXTENSION_NAME: &str = "timescaledb";
+/// The schema of the chunks.
+pub const INTERNAL_SCHEMA: &str = "_timescaledb_internal";
+/// The schema of the catalog tables of the extension.
+pub const CATALOG_SCHEMA: &str = "_timescaledb_catalog";
+
+/// A chunk, or an index of one, recognized by its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkName {
+    pub hypertable_id: i32,
+    pub chunk_id: i32,
+    pub compressed: bool,
+    pub index: bool,
+}
+
+impl ChunkName {
+    pub fn parse(relname: &str) -> Option<Self> {
+        let (rest, compressed) = match relname.strip_prefix("compress_hyper_") {
+            Some(rest) => (rest, true),
+            None => (relname.strip_prefix("_hyper_")?, false),
+        };
+        let (hypertable_id, rest) = rest.split_once('_')?;
+        let (chunk_id, rest) = rest.split_once('_')?;
+        let index = match rest.strip_prefix("chunk")? {
+            "" => false,
+            index_name if index_name.starts_with('_') => true,
+            _ => return None,
+        };
+        Some(ChunkName {
+            hypertable_id: hypertable_id.parse().ok()?,
+            chunk_id: chunk_id.parse().ok()?,
+            compressed,
+            index,
+        })
+    }
+}
+
+/// What a relation is to a chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkPart {
+    Table,
+    Index,
+    /// The TOAST table of the chunk, or its index.
+    Toast,
+}
+
+/// The chunk that the relation is a part of, if any.
+pub fn chunk_of(catalog: &Catalog, class: &PgClass) -> Option<(ChunkName, ChunkPart)> {
+    match catalog.namespace(class)? {
+        INTERNAL_SCHEMA => {
+            let name = ChunkName::parse(&class.relname)?;
+            let part = if name.index {
+                ChunkPart::Index
+            } else {
+                ChunkPart::Table
+            };
+            Some((name, part))
+        }
+        "pg_toast" => {
+            // Named after the OID of the table: pg_toast_<oid> and pg_toast_<oid>_index.
+            let owner = class.relname.strip_prefix("pg_toast_")?;
+            let owner = owner.strip_suffix("_index").unwrap_or(owner);
+            let owner = catalog.class(owner.parse().ok()?)?;
+            match chunk_of(catalog, owner)? {
+                (name, ChunkPart::Table) => Some((name, ChunkPart::Toast)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// A row of `_timescaledb_catalog.hypertable`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hypertable {
+    pub id: i32,
+    pub schema_name: String,
+    pub table_name: String,
+    /// The internal hypertable of the compressed chunks.
+    pub compressed_hypertable_id: Option<i32>,
+}
+
+// Offsets of the attributes of _timescaledb_catalog.hypertable, the same in all the 2.x
+// versions. The attributes before compressed_hypertable_id are all NOT NULL.
+const SCHEMA_NAME_OFFSET: usize = 4;
+const TABLE_NAME_OFFSET: usize = 68;
+const COMPRESSED_HYPERTABLE_ID_ATTNUM: usize = 10;
+const COMPRESSED_HYPERTABLE_ID_OFFSET: usize = 404;
+
+impl Hypertable {
+    fn decode(tuple: &HeapTuple) -> Option<(i32, Self)> {
+        let id = tuple.i32_at(0)?;
+        let compressed_hypertable_id = if tuple.is_null(COMPRESSED_HYPERTABLE_ID_ATTNUM) {
+            None
+        } else {
+            Some(tuple.i32_at(COMPRESSED_HYPERTABLE_ID_OFFSET)?)
+        };
+        let hypertable = Hypertable {
+            id,
+            schema_name: tuple.name_at(SCHEMA_NAME_OFFSET)?,
+            table_name: tuple.name_at(TABLE_NAME_OFFSET)?,
+            compressed_hypertable_id,
+        };
+        Some((id, hypertable))
+    }
+
+    /// Read the hypertables of a database, by id. Empty if the extension is not installed.
+    pub async fn load_all(
+        timeline: &Timeline,
+        catalog: &Catalog,
+        spcnode: Oid,
+        dbnode: Oid,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<HashMap<i32, Hypertable>> {
+        let hypertable = catalog.find(CATALOG_SCHEMA, "hypertable");
+        let Some(relnode) = hypertable.and_then(|class| catalog.relfilenode(class)) else {
+            return Ok(HashMap::new());
+        };
+        let rel = RelTag {
+            spcnode,
+            dbnode,
+            relnode,
+            forknum: MAIN_FORKNUM,
+        };
+        scan_heap(timeline, rel, lsn, ctx, Hypertable::decode)
+            .await
+            .context("read _timescaledb_catalog.hypertable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_names() {
+        let chunk = |hypertable_id, chunk_id, compressed, index| {
+            Some(ChunkName {
+                hypertable_id,
+                chunk_id,
+                compressed,
+                index,
+            })
+        };
+        assert_eq!(ChunkName::parse("_hyper_1_2_chunk"), chunk(1, 2, false, false));
+        assert_eq!(
+            ChunkName::parse("_hyper_12_345_chunk_conditions_time_idx"),
+            chunk(12, 345, false, true)
+        );
+        assert_eq!(ChunkName::parse("compress_hyper_2_3_chunk"), chunk(2, 3, true, false));
+        assert_eq!(
+            ChunkName::parse("compress_hyper_2_3_chunk__compressed_hypertable_2_device_idx"),
+            chunk(2, 3, true, true)
+        );
+
+        for relname in [
+            "conditions",
+            "_hyper_1_chunk",
+            "_hyper_1_2_chunks",
+            "_hyper_x_2_chunk",
+            "_hyper_1_x_chunk",
+            "_compressed_hypertable_2",
+        ] {
+            assert_eq!(ChunkName::parse(relname), None, "{relname}");
+        }
+    }
+}
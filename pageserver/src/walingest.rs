@@ -37,7 +37,7 @@ use crate::walrecord::*;
 use crate::ZERO_PAGE;
 use pageserver_api::reltag::{RelTag, SlruKind};
 use postgres_ffi::pg_constants;
-use postgres_ffi::relfile_utils::{FSM_FORKNUM, INIT_FORKNUM, MAIN_FORKNUM, VISIBILITYMAP_FORKNUM};
+use postgres_ffi::relfile_utils::{FSM_FORKNUM, MAIN_FORKNUM, VISIBILITYMAP_FORKNUM};
 use postgres_ffi::v14::nonrelfile_utils::mx_offset_to_member_segment;
 use postgres_ffi::v14::xlog_utils::*;
 use postgres_ffi::v14::CheckPoint;
@@ -90,6 +90,11 @@ impl<'a> WalIngest<'a> {
         modification.lsn = lsn;
         decode_wal_record(recdata, decoded, self.timeline.pg_version)?;
 
+        // Count the record for the database of the first block it modifies, if any
+        let dboid = decoded.blocks.first().map_or(0, |blk| blk.rnode_dbnode);
+        self.timeline
+            .note_ingested_record(dboid, decoded.record.len());
+
         let mut buf = decoded.record.clone();
         buf.advance(decoded.main_data_offset);
 
@@ -770,24 +775,7 @@ impl<'a> WalIngest<'a> {
             },
         )?;
 
-        for xnode in &parsed.xnodes {
-            for forknum in MAIN_FORKNUM..=INIT_FORKNUM {
-                let rel = RelTag {
-                    forknum,
-                    spcnode: xnode.spcnode,
-                    dbnode: xnode.dbnode,
-                    relnode: xnode.relnode,
-                };
-                let last_lsn = self.timeline.get_last_record_lsn();
-                if modification
-                    .tline
-                    .get_rel_exists(rel, last_lsn, true, ctx)
-                    .await?
-                {
-                    self.put_rel_drop(modification, rel, ctx).await?;
-                }
-            }
-        }
+        modification.put_rel_drops(&parsed.xnodes, ctx).await?;
         Ok(())
     }
 
@@ -1040,16 +1028,6 @@ impl<'a> WalIngest<'a> {
         Ok(())
     }
 
-    async fn put_rel_drop(
-        &mut self,
-        modification: &mut DatadirModification<'_>,
-        rel: RelTag,
-        ctx: &RequestContext,
-    ) -> Result<()> {
-        modification.put_rel_drop(rel, ctx).await?;
-        Ok(())
-    }
-
     async fn get_relsize(
         &mut self,
         rel: RelTag,
@@ -1178,6 +1156,7 @@ mod tests {
     use crate::tenant::Timeline;
     use postgres_ffi::v14::xlog_utils::SIZEOF_CHECKPOINT;
     use postgres_ffi::RELSEG_SIZE;
+    use std::collections::HashSet;
 
     use crate::DEFAULT_PG_VERSION;
 
@@ -1459,7 +1438,7 @@ mod tests {
 
         // Drop rel
         let mut m = tline.begin_modification(Lsn(0x30));
-        walingest.put_rel_drop(&mut m, TESTREL_A, &ctx).await?;
+        m.put_rel_drop(TESTREL_A, &ctx).await?;
         m.commit().await?;
 
         // Check that rel is not visible anymore
@@ -1497,6 +1476,122 @@ mod tests {
         Ok(())
     }
 
+    // Test dropping many relations at once, like a transaction running drop_chunks
+    #[tokio::test]
+    async fn test_drop_many() -> Result<()> {
+        let (tenant, ctx) = TenantHarness::create("test_drop_many")?.load().await;
+        let tline = tenant
+            .create_test_timeline(TIMELINE_ID, Lsn(8), DEFAULT_PG_VERSION, &ctx)
+            .await?;
+        let mut walingest = init_walingest_test(&tline, &ctx).await?;
+
+        let rel = |relnode, forknum| RelTag {
+            relnode,
+            forknum,
+            ..TESTREL_A
+        };
+        let mut m = tline.begin_modification(Lsn(0x20));
+        for relnode in 2000..2100 {
+            walingest
+                .put_rel_page_image(&mut m, rel(relnode, MAIN_FORKNUM), 0, TEST_IMG("chunk"), &ctx)
+                .await?;
+        }
+        walingest
+            .put_rel_page_image(&mut m, rel(2000, FSM_FORKNUM), 0, TEST_IMG("fsm"), &ctx)
+            .await?;
+        m.commit().await?;
+
+        let mut nodes = (2000..2099)
+            .map(|relnode| RelFileNode {
+                spcnode: TESTREL_A.spcnode,
+                dbnode: TESTREL_A.dbnode,
+                relnode,
+            })
+            .collect::<Vec<_>>();
+        // Not a relation of the timeline
+        nodes.push(RelFileNode {
+            relnode: 3000,
+            ..nodes[0]
+        });
+        let mut m = tline.begin_modification(Lsn(0x30));
+        m.put_rel_drops(&nodes, &ctx).await?;
+        m.commit().await?;
+
+        let rels = tline.list_rels(0, 111, Lsn(0x30), &ctx).await?;
+        assert_eq!(rels, HashSet::from([rel(2099, MAIN_FORKNUM)]));
+        for forknum in [MAIN_FORKNUM, FSM_FORKNUM] {
+            assert_eq!(
+                tline
+                    .get_rel_exists(rel(2000, forknum), Lsn(0x30), false, &ctx)
+                    .await?,
+                false
+            );
+        }
+        // Still there at the earlier LSN
+        assert_eq!(tline.list_rels(0, 111, Lsn(0x20), &ctx).await?.len(), 101);
+
+        Ok(())
+    }
+
+    // Test creating relations in a row with one modification, like the walreceiver ingesting the
+    // chunk creations of a hypertable
+    #[tokio::test]
+    async fn test_create_many() -> Result<()> {
+        let (tenant, ctx) = TenantHarness::create("test_create_many")?.load().await;
+        let tline = tenant
+            .create_test_timeline(TIMELINE_ID, Lsn(8), DEFAULT_PG_VERSION, &ctx)
+            .await?;
+        let mut walingest = init_walingest_test(&tline, &ctx).await?;
+
+        let rel = |dbnode, relnode| RelTag {
+            dbnode,
+            relnode,
+            ..TESTREL_A
+        };
+        let mut m = tline.begin_modification(Lsn(0x20));
+        for (i, relnode) in (2000..2010).enumerate() {
+            m.lsn = Lsn(0x20 + 0x10 * i as u64);
+            walingest
+                .put_rel_creation(&mut m, rel(111, relnode), &ctx)
+                .await?;
+            // The first relation of a database
+            walingest
+                .put_rel_creation(&mut m, rel(222 + i as u32, relnode), &ctx)
+                .await?;
+            m.commit().await?;
+        }
+        assert!(m.put_rel_creation(rel(111, 2000), 0, &ctx).await.is_err());
+
+        for i in 0..10 {
+            let lsn = Lsn(0x20 + 0x10 * i);
+            let expected = (2000..=2000 + i as u32)
+                .map(|relnode| rel(111, relnode))
+                .collect::<HashSet<_>>();
+            assert_eq!(tline.list_rels(0, 111, lsn, &ctx).await?, expected);
+            let rels = tline.list_rels(0, 222 + i as u32, lsn, &ctx).await?;
+            assert_eq!(rels, HashSet::from([rel(222 + i as u32, 2000 + i as u32)]));
+        }
+
+        // Dropping and recreating them goes through the same directories
+        m.lsn = Lsn(0x200);
+        m.put_rel_drop(rel(111, 2000), &ctx).await?;
+        m.drop_dbdir(0, 222, &ctx).await?;
+        m.commit().await?;
+        m.lsn = Lsn(0x210);
+        walingest
+            .put_rel_creation(&mut m, rel(111, 2000), &ctx)
+            .await?;
+        m.commit().await?;
+
+        assert_eq!(tline.list_rels(0, 111, Lsn(0x200), &ctx).await?.len(), 9);
+        assert_eq!(tline.list_rels(0, 111, Lsn(0x210), &ctx).await?.len(), 10);
+        let dbs = tline.list_dbdirs(Lsn(0x210), &ctx).await?;
+        assert!(!dbs.contains_key(&(0, 222)));
+        assert!(dbs.contains_key(&(0, 223)));
+
+        Ok(())
+    }
+
     // Test what happens if we truncated a relation
     // so that one of its segments was dropped
     // and then extended it again within the same layer.
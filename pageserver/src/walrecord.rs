@@ -2,7 +2,7 @@
 //! Functions for parsing WAL records.
 //!
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Result};
 use bytes::{Buf, Bytes};
 use postgres_ffi::pg_constants;
 use postgres_ffi::BLCKSZ;
@@ -87,8 +87,9 @@ pub struct DecodedBkpBlock {
     pub bimg_info: u8,
 
     /* Buffer holding the rmgr-specific data associated with this block */
-    has_data: bool,
-    data_len: u16,
+    pub has_data: bool,
+    pub data_offset: u32,
+    pub data_len: u16,
 }
 
 impl DecodedBkpBlock {
@@ -300,7 +301,7 @@ impl XlHeapUpdate {
             old_offnum: buf.get_u16_le(),
             old_infobits_set: buf.get_u8(),
             flags: buf.get_u8(),
-            t_cid: buf.get_u32(),
+            t_cid: buf.get_u32_le(),
             new_xmax: buf.get_u32_le(),
             new_offnum: buf.get_u16_le(),
         }
@@ -552,44 +553,51 @@ pub fn decode_wal_record(
         xlogrec.xl_info
     );
 
-    let remaining: usize = xlogrec.xl_tot_len as usize - XLOG_SIZE_OF_XLOG_RECORD;
-
-    if buf.remaining() != remaining {
-        //TODO error
-    }
+    ensure!(
+        xlogrec.xl_tot_len as usize >= XLOG_SIZE_OF_XLOG_RECORD
+            && xlogrec.xl_tot_len as usize == record.len(),
+        "invalid record length {} for a record of {} bytes",
+        xlogrec.xl_tot_len,
+        record.len()
+    );
 
     let mut max_block_id = 0;
-    let mut blocks_total_len: u32 = 0;
+    // The lengths are summed as usize, many headers of a bogus record could overflow a u32
+    let mut blocks_total_len: usize = 0;
     let mut main_data_len = 0;
-    let mut datatotal: u32 = 0;
+    let mut datatotal: usize = 0;
     decoded.blocks.clear();
 
     // 2. Decode the headers.
     // XLogRecordBlockHeaders if any,
     // XLogRecordDataHeader[Short|Long]
-    while buf.remaining() > datatotal as usize {
+    while buf.remaining() > datatotal {
         let block_id = buf.get_u8();
 
         match block_id {
             pg_constants::XLR_BLOCK_ID_DATA_SHORT => {
                 /* XLogRecordDataHeaderShort */
+                ensure_remaining(&buf, 1, "XLogRecordDataHeaderShort")?;
                 main_data_len = buf.get_u8() as u32;
-                datatotal += main_data_len;
+                datatotal += main_data_len as usize;
             }
 
             pg_constants::XLR_BLOCK_ID_DATA_LONG => {
                 /* XLogRecordDataHeaderLong */
+                ensure_remaining(&buf, 4, "XLogRecordDataHeaderLong")?;
                 main_data_len = buf.get_u32_le();
-                datatotal += main_data_len;
+                datatotal += main_data_len as usize;
             }
 
             pg_constants::XLR_BLOCK_ID_ORIGIN => {
                 // RepOriginId is uint16
+                ensure_remaining(&buf, 2, "RepOriginId")?;
                 buf.advance(2);
             }
 
             pg_constants::XLR_BLOCK_ID_TOPLEVEL_XID => {
                 // TransactionId is uint32
+                ensure_remaining(&buf, 4, "TransactionId")?;
                 buf.advance(4);
             }
 
@@ -608,6 +616,7 @@ pub fn decode_wal_record(
                 }
                 max_block_id = block_id;
 
+                ensure_remaining(&buf, 3, "XLogRecordBlockHeader")?;
                 let fork_flags: u8 = buf.get_u8();
                 blk.forknum = fork_flags & pg_constants::BKPBLOCK_FORK_MASK;
                 blk.flags = fork_flags;
@@ -618,19 +627,19 @@ pub fn decode_wal_record(
 
                 /* TODO cross-check that the HAS_DATA flag is set iff data_length > 0 */
 
-                datatotal += blk.data_len as u32;
-                blocks_total_len += blk.data_len as u32;
+                datatotal += blk.data_len as usize;
+                blocks_total_len += blk.data_len as usize;
 
                 if blk.has_image {
+                    ensure_remaining(&buf, 5, "XLogRecordBlockImageHeader")?;
                     blk.bimg_len = buf.get_u16_le();
                     blk.hole_offset = buf.get_u16_le();
                     blk.bimg_info = buf.get_u8();
 
-                    blk.apply_image = if pg_version == 14 {
-                        (blk.bimg_info & postgres_ffi::v14::bindings::BKPIMAGE_APPLY) != 0
-                    } else {
-                        assert_eq!(pg_version, 15);
-                        (blk.bimg_info & postgres_ffi::v15::bindings::BKPIMAGE_APPLY) != 0
+                    blk.apply_image = match pg_version {
+                        14 => (blk.bimg_info & postgres_ffi::v14::bindings::BKPIMAGE_APPLY) != 0,
+                        15 => (blk.bimg_info & postgres_ffi::v15::bindings::BKPIMAGE_APPLY) != 0,
+                        _ => bail!("unknown postgres version {pg_version}"),
                     };
 
                     let blk_img_is_compressed =
@@ -642,15 +651,21 @@ pub fn decode_wal_record(
 
                     if blk_img_is_compressed {
                         if blk.bimg_info & pg_constants::BKPIMAGE_HAS_HOLE != 0 {
+                            ensure_remaining(&buf, 2, "XLogRecordBlockCompressHeader")?;
                             blk.hole_length = buf.get_u16_le();
                         } else {
                             blk.hole_length = 0;
                         }
                     } else {
+                        ensure!(
+                            blk.bimg_len <= BLCKSZ,
+                            "invalid block image length {}",
+                            blk.bimg_len
+                        );
                         blk.hole_length = BLCKSZ - blk.bimg_len;
                     }
-                    datatotal += blk.bimg_len as u32;
-                    blocks_total_len += blk.bimg_len as u32;
+                    datatotal += blk.bimg_len as usize;
+                    blocks_total_len += blk.bimg_len as usize;
 
                     /*
                      * cross-check that hole_offset > 0, hole_length > 0 and
@@ -723,6 +738,7 @@ pub fn decode_wal_record(
                     }
                 }
                 if fork_flags & pg_constants::BKPBLOCK_SAME_REL == 0 {
+                    ensure_remaining(&buf, 12, "RelFileNode")?;
                     rnode_spcnode = buf.get_u32_le();
                     rnode_dbnode = buf.get_u32_le();
                     rnode_relnode = buf.get_u32_le();
@@ -740,6 +756,7 @@ pub fn decode_wal_record(
                 blk.rnode_dbnode = rnode_dbnode;
                 blk.rnode_relnode = rnode_relnode;
 
+                ensure_remaining(&buf, 4, "BlockNumber")?;
                 blk.blkno = buf.get_u32_le();
                 trace!(
                     "this record affects {}/{}/{} blk {}",
@@ -752,9 +769,7 @@ pub fn decode_wal_record(
                 decoded.blocks.push(blk);
             }
 
-            _ => {
-                // TODO: invalid block_id
-            }
+            _ => bail!("invalid block_id {block_id}"),
         }
     }
 
@@ -766,19 +781,27 @@ pub fn decode_wal_record(
             ptr += blk.bimg_len as usize;
         }
         if blk.has_data {
+            blk.data_offset = ptr as u32;
             ptr += blk.data_len as usize;
         }
     }
     // We don't need them, so just skip blocks_total_len bytes
-    buf.advance(blocks_total_len as usize);
-    assert_eq!(ptr, record.len() - buf.remaining());
-
-    let main_data_offset = (xlogrec.xl_tot_len - main_data_len) as usize;
+    ensure_remaining(&buf, blocks_total_len, "block data")?;
+    buf.advance(blocks_total_len);
+    ensure!(
+        ptr == record.len() - buf.remaining(),
+        "block data lengths don't match the block flags"
+    );
 
     // 4. Decode main_data
     if main_data_len > 0 {
-        assert_eq!(buf.remaining(), main_data_len as usize);
+        ensure!(
+            buf.remaining() == main_data_len as usize,
+            "invalid main data length {main_data_len}, {} bytes left",
+            buf.remaining()
+        );
     }
+    let main_data_offset = (xlogrec.xl_tot_len - main_data_len) as usize;
 
     decoded.xl_xid = xlogrec.xl_xid;
     decoded.xl_info = xlogrec.xl_info;
@@ -789,6 +812,16 @@ pub fn decode_wal_record(
     Ok(())
 }
 
+/// Bail out of decoding if fewer than `len` bytes of the record are left for `what`.
+fn ensure_remaining(buf: &Bytes, len: usize, what: &str) -> Result<()> {
+    ensure!(
+        buf.remaining() >= len,
+        "record too short for {what}: {} bytes left, {len} needed",
+        buf.remaining()
+    );
+    Ok(())
+}
+
 ///
 /// Build a human-readable string to describe a WAL record
 ///
@@ -11,42 +11,81 @@
 //! postgres process happens via stdin/stdout
 //!
 //! See pgxn/neon_walredo/walredoproc.c for the other side of
-//! this communication.
+//! this communication, and the `protocol` module for its versions.
 //!
 //! The Postgres process is assumed to be secure against malicious WAL
 //! records. It achieves it by dropping privileges before replaying
 //! any WAL records, so that even if an attacker hijacks the Postgres
 //! process, he cannot escape out of it.
 //!
+//! The heap inserts and deletes that TimescaleDB compression is made of can also be redone
+//! without the postgres process, see the `heap` module, and so can the most common records of
+//! other types, see the `btree` and `fpi` modules. The config enables each record type. With
+//! the `fuzzing` feature, the `fuzzing` module exposes the redo of these records and the encoding
+//! of the requests to the postgres process to the fuzz targets in `pageserver/fuzz`.
+//!
+//! The memory and CPU time of the postgres process are limited by the tenant config, see the
+//! `limits` module, and its CPU time is counted for the tenant, see the `cpu` module. What
+//! happens when the process fails, or keeps failing, is up to the `wal_redo_policy` setting,
+//! see the `policy` module.
+//!
+//! Each request is redone in a `wal_redo` span, under the span of the caller, e.g. the
+//! getpage@lsn request that needs the page, and each batch of records in a child span of its
+//! own. The writes to the postgres process and the reads from it run on the threads of the
+//! process, see the `pool` module, which enter the span of the request there, while the request
+//! awaits them. Once done, the time the request
+//! waited for the process and the time spent on its records are recorded in the `wal_redo`
+//! span, as `wait_us` and `execution_us`, so that the traces of a getpage@lsn request show
+//! where it went. With debug logging, the waits and the exchanges with the process are in
+//! child spans too, `wal_redo_wait` and `apply_wal_records`.
+//!
+mod btree;
+mod cpu;
+mod fpi;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+mod gate;
+mod heap;
+mod limits;
+mod native;
+mod policy;
+mod pool;
+mod protocol;
+mod stderr;
+
 use byteorder::{ByteOrder, LittleEndian};
 use bytes::{BufMut, Bytes, BytesMut};
 use nix::poll::*;
 use serde::Serialize;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::io::prelude::*;
 use std::io::{Error, ErrorKind};
 use std::ops::{Deref, DerefMut};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::prelude::CommandExt;
 use std::process::Stdio;
-use std::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
-use std::sync::{Mutex, MutexGuard};
+use std::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::Duration;
 use std::time::Instant;
 use std::{fs, io};
 use tracing::*;
 use utils::crashsafe::path_with_suffix_extension;
+use utils::error_rate_limited;
 use utils::{bin_ser::BeSer, id::TenantId, lsn::Lsn, nonblock::set_nonblock};
 
 use crate::metrics::{
-    WAL_REDO_BYTES_HISTOGRAM, WAL_REDO_RECORDS_HISTOGRAM, WAL_REDO_RECORD_COUNTER, WAL_REDO_TIME,
-    WAL_REDO_WAIT_TIME,
+    observe_traced, WAL_REDO_BYTES_HISTOGRAM, WAL_REDO_CPU_SECONDS_PER_TENANT,
+    WAL_REDO_NATIVE_RECORD_COUNTER, WAL_REDO_PROCESS_FAILURES, WAL_REDO_RECORDS_HISTOGRAM,
+    WAL_REDO_RECORD_COUNTER, WAL_REDO_TIME, WAL_REDO_WAIT_TIME,
 };
 use crate::pgdatadir_mapping::{key_to_rel_block, key_to_slru_block};
 use crate::repository::Key;
 use crate::task_mgr::BACKGROUND_RUNTIME;
 use crate::walrecord::NeonWalRecord;
 use crate::{config::PageServerConf, TEMP_FILE_SUFFIX};
+use pageserver_api::models::WalRedoProcessInfo;
 use pageserver_api::reltag::{RelTag, SlruKind};
 use postgres_ffi::pg_constants;
 use postgres_ffi::relfile_utils::VISIBILITYMAP_FORKNUM;
@@ -54,7 +93,18 @@ use postgres_ffi::v14::nonrelfile_utils::{
     mx_offset_to_flags_bitshift, mx_offset_to_flags_offset, mx_offset_to_member_offset,
     transaction_id_set_status,
 };
-use postgres_ffi::BLCKSZ;
+use postgres_ffi::{XLogRecord, BLCKSZ};
+
+use cpu::CpuTime;
+use gate::RequestGate;
+use limits::SetWalRedoLimits;
+pub use limits::WalRedoLimits;
+use policy::FailureTracker;
+pub use policy::WalRedoPolicy;
+use pool::RedoThreadPool;
+use protocol::{ProtocolVersion, ResponseReader};
+use stderr::StderrLog;
+pub use stderr::WalRedoStderrConfig;
 
 ///
 /// `RelTag` + block number (`blknum`) gives us a unique id of the page in the cluster.
@@ -73,20 +123,85 @@ pub struct BufferTag {
 ///
 /// Callers use the WAL redo manager through this abstract interface,
 /// which makes it easy to mock it in tests.
+#[async_trait::async_trait]
 pub trait WalRedoManager: Send + Sync {
     /// Apply some WAL records.
     ///
     /// The caller passes an old page image, and WAL records that should be
     /// applied over it. The return value is a new page image, after applying
-    /// the reords.
-    fn request_redo(
+    /// the reords. The time the request took is added to `timings`.
+    async fn request_redo(
         &self,
         key: Key,
         lsn: Lsn,
         base_img: Option<(Lsn, Bytes)>,
         records: Vec<(Lsn, NeonWalRecord)>,
         pg_version: u32,
+        timings: &mut RedoTimings,
     ) -> Result<Bytes, WalRedoError>;
+
+    /// Apply the WAL records of several pages, like [`Self::request_redo`] for each of them.
+    ///
+    /// The results are in the order of the requests. The time of the whole batch is added to
+    /// `timings`.
+    async fn request_redo_batch(
+        &self,
+        requests: Vec<RedoRequest>,
+        pg_version: u32,
+        timings: &mut RedoTimings,
+    ) -> Vec<Result<Bytes, WalRedoError>> {
+        let mut results = Vec::with_capacity(requests.len());
+        for request in requests {
+            let RedoRequest {
+                key,
+                lsn,
+                base_img,
+                records,
+            } = request;
+            results.push(
+                self.request_redo(key, lsn, base_img, records, pg_version, timings)
+                    .await,
+            );
+        }
+        results
+    }
+
+    /// Follow a change of the limits of the WAL redo process in the tenant config.
+    fn set_limits(&self, _limits: WalRedoLimits) {}
+
+    /// Check that the WAL redo of Postgres `pg_version` works, see
+    /// [`PostgresRedoManager::ping`].
+    async fn ping(&self, _pg_version: u32) -> Result<(), WalRedoError> {
+        Ok(())
+    }
+
+    /// The status of the WAL redo processes, see [`PostgresRedoManager::status`].
+    fn status(&self) -> Vec<WalRedoProcessInfo> {
+        Vec::new()
+    }
+
+    /// Stop the WAL redo processes when the tenant is detached, see
+    /// [`PostgresRedoManager::shutdown`].
+    fn shutdown(&self) {}
+}
+
+/// A page to reconstruct in [`WalRedoManager::request_redo_batch`], with the arguments of
+/// [`WalRedoManager::request_redo`].
+#[derive(Debug, Clone)]
+pub struct RedoRequest {
+    pub key: Key,
+    pub lsn: Lsn,
+    pub base_img: Option<(Lsn, Bytes)>,
+    pub records: Vec<(Lsn, NeonWalRecord)>,
+}
+
+/// Where the time of WAL redo requests went.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RedoTimings {
+    /// Waiting for access to the WAL redo process, including launching it.
+    pub wait: Duration,
+    /// Applying the records, in the WAL redo process or in neon code.
+    pub execution: Duration,
 }
 
 struct ProcessInput {
@@ -99,31 +214,146 @@ struct ProcessInput {
 
 struct ProcessOutput {
     stdout: ChildStdout,
-    pending_responses: VecDeque<Option<Bytes>>,
+    protocol: ProtocolVersion,
+    /// The pages, or the errors reported by the process, see `protocol`.
+    pending_responses: VecDeque<Option<Result<Bytes, Error>>>,
     n_processed_responses: usize,
 }
 
+/// The postgres process of one Postgres version, launched on first use.
+struct WalRedoProcess {
+    /// The threads that launch the process and talk to it, see `pool`.
+    threads: RedoThreadPool,
+    stdout: Mutex<Option<ProcessOutput>>,
+    stdin: Mutex<Option<ProcessInput>>,
+    stderr: Mutex<Option<ChildStderr>>,
+
+    /// The process reported being out of memory since it was launched.
+    out_of_memory: AtomicBool,
+
+    /// Kept apart from the pipes, which the requests hold, see [`WalRedoProcess::info`].
+    history: Mutex<ProcessHistory>,
+    /// Kept across the launches, see `stderr`.
+    stderr_log: Mutex<StderrLog>,
+}
+
+/// The launches and the kills of the process of a Postgres version.
+#[derive(Debug, Default)]
+struct ProcessHistory {
+    /// The pid of the running process, and when it was launched.
+    launched: Option<(u32, Instant)>,
+    /// The processes killed after a failed request.
+    restarts: u64,
+    last_restart_reason: Option<String>,
+}
+
+impl ProcessHistory {
+    /// The process was killed, after `failure` if it failed.
+    fn killed(&mut self, failure: Option<&WalRedoError>) {
+        self.launched = None;
+        if let Some(failure) = failure {
+            self.restarts += 1;
+            self.last_restart_reason = Some(failure.to_string());
+        }
+    }
+}
+
+impl WalRedoProcess {
+    fn new(conf: &PageServerConf, pg_version: u32) -> Self {
+        WalRedoProcess {
+            threads: RedoThreadPool::new(format!("walredo-pg{pg_version}"), conf.wal_redo_threads),
+            stdout: Mutex::new(None),
+            stdin: Mutex::new(None),
+            stderr: Mutex::new(None),
+            out_of_memory: AtomicBool::new(false),
+            history: Mutex::default(),
+            stderr_log: Mutex::new(StderrLog::new(&conf.wal_redo_stderr)),
+        }
+    }
+
+    /// Log what the process wrote to its stderr.
+    fn forward_stderr(&self, buf: &[u8]) {
+        if self.stderr_log.lock().unwrap().forward(buf) {
+            self.out_of_memory.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Log the last line of a killed process, if it didn't end it.
+    fn flush_stderr(&self) {
+        if self.stderr_log.lock().unwrap().flush() {
+            self.out_of_memory.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn info(&self, pg_version: u32) -> WalRedoProcessInfo {
+        let history = self.history.lock().unwrap();
+        // Without waiting for the requests that hold the pipes, a wedged process holds them
+        // until its timeout
+        let n_requests = match self.stdin.try_lock() {
+            Ok(input) => input.as_ref().map(|input| input.n_requests as u64),
+            Err(_) => None,
+        };
+        let pending_responses = match self.stdout.try_lock() {
+            Ok(output) => output
+                .as_ref()
+                .map(|output| output.pending_responses.len() as u64),
+            Err(_) => None,
+        };
+        WalRedoProcessInfo {
+            pg_version,
+            pid: history.launched.map(|(pid, _)| pid),
+            uptime_secs: history
+                .launched
+                .map(|(_, launched_at)| launched_at.elapsed().as_secs()),
+            n_requests,
+            pending_responses,
+            restarts: history.restarts,
+            last_restart_reason: history.last_restart_reason.clone(),
+            stderr_tail: self.stderr_log.lock().unwrap().tail(),
+        }
+    }
+}
+
 ///
 /// This is the real implementation that uses a Postgres process to
-/// perform WAL replay. Only one thread can use the process at a time,
-/// that is controlled by the Mutex. In the future, we might want to
-/// launch a pool of processes to allow concurrent replay of multiple
-/// records.
+/// perform WAL replay. The requests of concurrent threads are pipelined
+/// to the process: a thread holds the `stdin` Mutex only to write its
+/// request, and then waits for its response under the `stdout` Mutex, so
+/// that the requests of the others are written back-to-back meanwhile.
+/// The responses come in the order of the requests, and are matched to
+/// them by request number, see `apply_wal_records`. In the future, we
+/// might want to launch a pool of processes to allow concurrent replay of
+/// multiple records.
+///
+/// The threads that talk to a process are not those of the callers, which
+/// await the threads of the process instead, see `pool` and
+/// [`PostgresRedoManager::exchange`].
+///
+/// The timelines of a tenant can be of different Postgres versions, during a
+/// major version upgrade, so there is a process for each version, see
+/// [`PostgresRedoManager::process`].
 ///
 pub struct PostgresRedoManager {
     tenant_id: TenantId,
     conf: &'static PageServerConf,
 
-    stdout: Mutex<Option<ProcessOutput>>,
-    stdin: Mutex<Option<ProcessInput>>,
-    stderr: Mutex<Option<ChildStderr>>,
+    /// By Postgres version.
+    processes: Mutex<HashMap<u32, Arc<WalRedoProcess>>>,
+
+    /// The limits of the next process launched, see `limits`.
+    limits: Mutex<WalRedoLimits>,
+    /// The failed requests, and the quarantine of the tenant, see `policy`.
+    failures: Mutex<FailureTracker>,
+    /// The requests in flight, see `gate`.
+    gate: RequestGate,
 }
 
 /// Can this request be served by neon redo functions
 /// or we need to pass it to wal-redo postgres process?
 fn can_apply_in_neon(rec: &NeonWalRecord) -> bool {
-    // Currently, we don't have bespoken Rust code to replay any
-    // Postgres WAL records. But everything else is handled in neon.
+    // Postgres WAL records are replayed by the postgres process, except for
+    // the records of `PostgresRedoManager::can_redo_natively`. Everything else
+    // is handled in neon.
     #[allow(clippy::match_like_matches_macro)]
     match rec {
         NeonWalRecord::Postgres {
@@ -134,6 +364,49 @@ fn can_apply_in_neon(rec: &NeonWalRecord) -> bool {
     }
 }
 
+/// The resource manager of the Postgres record `rec`.
+fn postgres_rmid(rec: &Bytes) -> Option<u8> {
+    let header = XLogRecord::from_bytes(&mut rec.clone()).ok()?;
+    Some(header.xl_rmid)
+}
+
+/// Check that a neon `record` applied to the page of `key` modifies that page: that the
+/// `field` of the page it modifies, `expected`, is that of the page of `key`, `actual`.
+fn check_page(
+    key: Key,
+    record: &'static str,
+    field: &'static str,
+    expected: u32,
+    actual: u32,
+) -> Result<(), WalRedoError> {
+    if expected != actual {
+        return Err(WalRedoError::Corrupted {
+            key,
+            record,
+            field,
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Check that a neon `record` of the `expected` SLRU is applied to a page of it.
+fn check_slru_kind(
+    key: Key,
+    record: &'static str,
+    expected: SlruKind,
+    actual: SlruKind,
+) -> Result<(), WalRedoError> {
+    // The SLRUs are numbered as in the keys of their pages
+    let number = |kind| match kind {
+        SlruKind::Clog => 0,
+        SlruKind::MultiXactMembers => 1,
+        SlruKind::MultiXactOffsets => 2,
+    };
+    check_page(key, record, "slru", number(expected), number(actual))
+}
+
 /// An error happened in WAL redo
 #[derive(Debug, thiserror::Error)]
 pub enum WalRedoError {
@@ -146,25 +419,360 @@ pub enum WalRedoError {
     InvalidRequest,
     #[error("cannot perform WAL redo for this record")]
     InvalidRecord,
+    /// A neon WAL record is stored under the key of another page than the one it modifies.
+    /// `expected` is the `field` of the page that the record modifies, `actual` that of the
+    /// page of `key`.
+    #[error("{record} record with unexpected key {key}: {field} {actual}, expected {expected}")]
+    Corrupted {
+        key: Key,
+        record: &'static str,
+        field: &'static str,
+        expected: u32,
+        actual: u32,
+    },
+    #[error("WAL redo process exceeded its memory limit")]
+    MemoryLimitExceeded,
+    #[error("WAL redo process exceeded its CPU time limit")]
+    CpuTimeLimitExceeded,
+    #[error("WAL redo is quarantined after repeated failures")]
+    Quarantined,
+    #[error("WAL redo is shutting down")]
+    ShuttingDown,
 }
 
 ///
 /// Public interface of WAL redo manager
 ///
+#[async_trait::async_trait]
 impl WalRedoManager for PostgresRedoManager {
     ///
     /// Request the WAL redo manager to apply some WAL records
     ///
     /// The WAL redo is handled by a separate thread, so this just sends a request
-    /// to the thread and waits for response.
+    /// to the thread and awaits the response.
+    ///
+    async fn request_redo(
+        &self,
+        key: Key,
+        lsn: Lsn,
+        base_img: Option<(Lsn, Bytes)>,
+        records: Vec<(Lsn, NeonWalRecord)>,
+        pg_version: u32,
+        timings: &mut RedoTimings,
+    ) -> Result<Bytes, WalRedoError> {
+        let _admitted = self.gate.enter()?;
+        let span = request_span(1);
+        let before = *timings;
+        let result = self
+            .redo(key, lsn, base_img, records, pg_version, timings)
+            .instrument(span.clone())
+            .await;
+        record_timings(&span, before, timings);
+        result
+    }
+
+    /// The pages that need nothing but the postgres process are sent to it in one write, and
+    /// their responses collected together, rather than taking the locks of the process and
+    /// waiting for it once per page. The other pages are redone one by one, and so are the
+    /// batched ones if the batch fails, with the retries of a single request.
+    async fn request_redo_batch(
+        &self,
+        requests: Vec<RedoRequest>,
+        pg_version: u32,
+        timings: &mut RedoTimings,
+    ) -> Vec<Result<Bytes, WalRedoError>> {
+        let Ok(_admitted) = self.gate.enter() else {
+            return requests
+                .iter()
+                .map(|_| Err(WalRedoError::ShuttingDown))
+                .collect();
+        };
+        let span = request_span(requests.len());
+        let before = *timings;
+        let results = self
+            .redo_batch(requests, pg_version, timings)
+            .instrument(span.clone())
+            .await;
+        record_timings(&span, before, timings);
+        results
+    }
+
+    fn set_limits(&self, limits: WalRedoLimits) {
+        *self.limits.lock().unwrap() = limits;
+    }
+
+    async fn ping(&self, pg_version: u32) -> Result<(), WalRedoError> {
+        PostgresRedoManager::ping(self, pg_version).await
+    }
+
+    fn status(&self) -> Vec<WalRedoProcessInfo> {
+        PostgresRedoManager::status(self)
+    }
+
+    fn shutdown(&self) {
+        PostgresRedoManager::shutdown(self);
+    }
+}
+
+/// The `wal_redo` span of a request for `pages` pages, under the span of the caller.
+fn request_span(pages: usize) -> Span {
+    info_span!(
+        "wal_redo",
+        pages,
+        wait_us = field::Empty,
+        execution_us = field::Empty
+    )
+}
+
+/// Record in the `span` of a request the part of `timings` that it accounts for, what was added
+/// to them since they were `before`.
+fn record_timings(span: &Span, before: RedoTimings, timings: &RedoTimings) {
+    span.record("wait_us", (timings.wait - before.wait).as_micros() as u64);
+    span.record("execution_us", (timings.execution - before.execution).as_micros() as u64);
+}
+
+impl PostgresRedoManager {
+    ///
+    /// Create a new PostgresRedoManager.
+    ///
+    pub fn new(conf: &'static PageServerConf, tenant_id: TenantId) -> PostgresRedoManager {
+        // Exported from the start, like the other metrics of the tenant, see `cpu`
+        let _ = WAL_REDO_CPU_SECONDS_PER_TENANT.with_label_values(&[&tenant_id.to_string()]);
+
+        // The actual process is launched lazily, on first request.
+        PostgresRedoManager {
+            tenant_id,
+            conf,
+            processes: Mutex::new(HashMap::new()),
+            limits: Mutex::new(WalRedoLimits::default()),
+            failures: Mutex::new(FailureTracker::default()),
+            gate: RequestGate::default(),
+        }
+    }
+
+    /// Check that the WAL redo process works: that the postgres binary of `pg_version` starts
+    /// and enters its seccomp sandbox, and that a page pushed to it comes back the same.
+    ///
+    /// The process is launched if it isn't running, and stopped again afterwards if no other
+    /// request used it meanwhile, so that the check doesn't keep a process per tenant around. A
+    /// process that fails the check is stopped, like after a failed request.
+    pub async fn ping(&self, pg_version: u32) -> Result<(), WalRedoError> {
+        let _admitted = self.gate.enter()?;
+        self.failures.lock().unwrap().check(Instant::now())?;
+
+        let process = self.process(pg_version);
+        let tag = BufferTag {
+            rel: RelTag {
+                forknum: 0,
+                spcnode: pg_constants::DEFAULTTABLESPACE_OID,
+                dbnode: 0,
+                relnode: 0,
+            },
+            blknum: 0,
+        };
+        let mut page = vec![0; BLCKSZ as usize];
+        native::page_init(&mut page);
+        let page = Bytes::from(page);
+        let pages = [(tag, Some(&page), &[][..])];
+        let exchange = self
+            .exchange(&process, &pages, self.conf.wal_redo_timeout, pg_version)
+            .await?;
+        let launched_pid = exchange.launched_pid;
+        let result = match exchange.pages {
+            Ok(imgs) if imgs == [page.clone()] => Ok(()),
+            Ok(_) => Err(WalRedoError::IoError(Error::new(
+                ErrorKind::InvalidData,
+                "WAL redo process returned another page than the one pushed to it",
+            ))),
+            Err(e) => Err(WalRedoError::IoError(e)),
+        };
+
+        let mut proc = process.stdin.lock().unwrap();
+        let unused = proc.as_ref().map_or(false, |input| {
+            Some(input.child.id()) == launched_pid && input.n_requests == 1
+        });
+        if result.is_err() || unused {
+            if let Some(input) = proc.take() {
+                input.child.kill_and_wait();
+                process.flush_stderr();
+                let mut history = process.history.lock().unwrap();
+                history.killed(result.as_ref().err());
+            }
+        }
+        result
+    }
+
+    /// The status of the processes of the tenant, by Postgres version, for debugging: what is
+    /// running, how busy it is, and why it was last restarted.
+    pub fn status(&self) -> Vec<WalRedoProcessInfo> {
+        let processes = self.processes.lock().unwrap();
+        let mut status = processes
+            .iter()
+            .map(|(pg_version, process)| process.info(*pg_version))
+            .collect::<Vec<_>>();
+        status.sort_by_key(|info| info.pg_version);
+        status
+    }
+
+    /// Stop the processes when the tenant is detached: fail the next requests with
+    /// [`WalRedoError::ShuttingDown`], wait up to `wal_redo_timeout` for the requests in flight,
+    /// and kill the processes. Returns whether the requests in flight were done by then, see
+    /// `gate`.
+    pub fn shutdown(&self) -> bool {
+        let in_flight = self.gate.close(self.conf.wal_redo_timeout);
+        if in_flight > 0 {
+            warn!("stopping the WAL redo processes under {in_flight} requests in flight");
+        }
+        let processes: Vec<_> = self.processes.lock().unwrap().values().cloned().collect();
+        for process in processes {
+            let input = process.stdin.lock().unwrap().take();
+            if let Some(input) = input {
+                input.child.kill_and_wait();
+                process.flush_stderr();
+                process.history.lock().unwrap().killed(None);
+            }
+        }
+        in_flight == 0
+    }
+
+    /// Launch process pre-emptively. Should not be needed except for benchmarking.
+    pub async fn launch_process(&self, pg_version: u32) -> anyhow::Result<()> {
+        let _admitted = self.gate.enter()?;
+        let process = self.process(pg_version);
+        let context = self.context();
+        let job_process = Arc::clone(&process);
+        process
+            .threads
+            .run(move || -> io::Result<()> {
+                let mut proc = job_process.stdin.lock().unwrap();
+                if proc.is_none() {
+                    context.launch(&job_process, &mut proc, pg_version)?;
+                }
+                Ok(())
+            })
+            .await??;
+        Ok(())
+    }
+
+    /// The process of Postgres `pg_version`. A process is kept for each version that the
+    /// timelines of the tenant need, rather than restarted each time the version changes.
+    fn process(&self, pg_version: u32) -> Arc<WalRedoProcess> {
+        let mut processes = self.processes.lock().unwrap();
+        let process = processes
+            .entry(pg_version)
+            .or_insert_with(|| Arc::new(WalRedoProcess::new(self.conf, pg_version)));
+        Arc::clone(process)
+    }
+
+    /// What the threads of the processes need from the manager.
+    fn context(&self) -> ProcessContext {
+        ProcessContext {
+            conf: self.conf,
+            tenant_id: self.tenant_id,
+            limits: *self.limits.lock().unwrap(),
+        }
+    }
+
+    /// Send the records of `pages` to `process`, launching it if it isn't running, and read
+    /// the new page images, on a thread of the process, see `pool`.
     ///
-    fn request_redo(
+    /// Fails without sending anything if the process can't be launched, or the records can't
+    /// be sent to it. The failure of the process is in [`Exchange::pages`].
+    async fn exchange(
+        &self,
+        process: &Arc<WalRedoProcess>,
+        pages: &[(BufferTag, Option<&Bytes>, &[(Lsn, NeonWalRecord)])],
+        wal_redo_timeout: Duration,
+        pg_version: u32,
+    ) -> Result<Exchange, Error> {
+        let request = build_redo_request(pages)?;
+        let n_pages = pages.len();
+        let context = self.context();
+        let job_process = Arc::clone(process);
+        process
+            .threads
+            .run(move || -> Result<Exchange, Error> {
+                let process = job_process;
+                let mut input =
+                    debug_span!("wal_redo_wait").in_scope(|| process.stdin.lock().unwrap());
+                let lock_time = Instant::now();
+                let launched_pid = if input.is_none() {
+                    context.launch(&process, &mut input, pg_version)?;
+                    input.as_ref().map(|input| input.child.id())
+                } else {
+                    None
+                };
+                let pages =
+                    context.apply_wal_records(&process, input, &request, n_pages, wal_redo_timeout);
+                Ok(Exchange {
+                    lock_time,
+                    launched_pid,
+                    pages,
+                })
+            })
+            .await?
+    }
+
+    /// [`WalRedoManager::request_redo_batch`], for requests let in by the gate.
+    async fn redo_batch(
+        &self,
+        requests: Vec<RedoRequest>,
+        pg_version: u32,
+        timings: &mut RedoTimings,
+    ) -> Vec<Result<Bytes, WalRedoError>> {
+        let mut results: Vec<Option<Result<Bytes, WalRedoError>>> =
+            (0..requests.len()).map(|_| None).collect();
+        let (batched, mut one_by_one): (Vec<_>, Vec<_>) = requests
+            .into_iter()
+            .enumerate()
+            .partition(|(_, request)| self.needs_only_postgres(request, pg_version));
+
+        if batched.len() > 1 {
+            match self
+                .apply_pages_postgres(&batched, pg_version, timings)
+                .await
+            {
+                Ok(pages) => {
+                    for ((i, _), page) in batched.iter().zip(pages) {
+                        results[*i] = Some(Ok(page));
+                    }
+                }
+                Err(e) => {
+                    debug!("redoing {} pages one by one after: {e}", batched.len());
+                    one_by_one.extend(batched);
+                }
+            }
+        } else {
+            one_by_one.extend(batched);
+        }
+
+        for (i, request) in one_by_one {
+            let RedoRequest {
+                key,
+                lsn,
+                base_img,
+                records,
+            } = request;
+            let result = self
+                .redo(key, lsn, base_img, records, pg_version, timings)
+                .await;
+            results[i] = Some(result);
+        }
+        results
+            .into_iter()
+            .map(|result| result.expect("every request is redone"))
+            .collect()
+    }
+
+    /// [`WalRedoManager::request_redo`], for a request let in by the gate.
+    async fn redo(
         &self,
         key: Key,
         lsn: Lsn,
         base_img: Option<(Lsn, Bytes)>,
         records: Vec<(Lsn, NeonWalRecord)>,
         pg_version: u32,
+        timings: &mut RedoTimings,
     ) -> Result<Bytes, WalRedoError> {
         if records.is_empty() {
             error!("invalid WAL redo request with no records");
@@ -173,14 +781,24 @@ impl WalRedoManager for PostgresRedoManager {
 
         let base_img_lsn = base_img.as_ref().map(|p| p.0).unwrap_or(Lsn::INVALID);
         let mut img = base_img.map(|p| p.1);
-        let mut batch_neon = can_apply_in_neon(&records[0].1);
-        let mut batch_start = 0;
-        for (i, record) in records.iter().enumerate().skip(1) {
-            let rec_neon = can_apply_in_neon(&record.1);
 
+        // The Postgres records are only redone in neon after the last record that needs the
+        // postgres process, so that they never split its records into more round trips.
+        let mut in_neon = vec![false; records.len()];
+        let mut native = true;
+        for (i, (_, rec)) in records.iter().enumerate().rev() {
+            in_neon[i] =
+                can_apply_in_neon(rec) || (native && self.can_redo_natively(key, rec, pg_version));
+            native &= in_neon[i];
+        }
+
+        let mut batch_neon = in_neon[0];
+        let mut batch_start = 0;
+        for (i, &rec_neon) in in_neon.iter().enumerate().skip(1) {
             if rec_neon != batch_neon {
                 let result = if batch_neon {
-                    self.apply_batch_neon(key, lsn, img, &records[batch_start..i])
+                    let batch = &records[batch_start..i];
+                    self.apply_batch_neon(key, lsn, img, batch, pg_version, timings)
                 } else {
                     self.apply_batch_postgres(
                         key,
@@ -190,7 +808,9 @@ impl WalRedoManager for PostgresRedoManager {
                         &records[batch_start..i],
                         self.conf.wal_redo_timeout,
                         pg_version,
+                        timings,
                     )
+                    .await
                 };
                 img = Some(result?);
 
@@ -200,7 +820,8 @@ impl WalRedoManager for PostgresRedoManager {
         }
         // last batch
         if batch_neon {
-            self.apply_batch_neon(key, lsn, img, &records[batch_start..])
+            let batch = &records[batch_start..];
+            self.apply_batch_neon(key, lsn, img, batch, pg_version, timings)
         } else {
             self.apply_batch_postgres(
                 key,
@@ -210,40 +831,18 @@ impl WalRedoManager for PostgresRedoManager {
                 &records[batch_start..],
                 self.conf.wal_redo_timeout,
                 pg_version,
+                timings,
             )
+            .await
         }
     }
-}
-
-impl PostgresRedoManager {
-    ///
-    /// Create a new PostgresRedoManager.
-    ///
-    pub fn new(conf: &'static PageServerConf, tenant_id: TenantId) -> PostgresRedoManager {
-        // The actual process is launched lazily, on first request.
-        PostgresRedoManager {
-            tenant_id,
-            conf,
-            stdin: Mutex::new(None),
-            stdout: Mutex::new(None),
-            stderr: Mutex::new(None),
-        }
-    }
-
-    /// Launch process pre-emptively. Should not be needed except for benchmarking.
-    pub fn launch_process(&self, pg_version: u32) -> anyhow::Result<()> {
-        let mut proc = self.stdin.lock().unwrap();
-        if proc.is_none() {
-            self.launch(&mut proc, pg_version)?;
-        }
-        Ok(())
-    }
 
     ///
     /// Process one request for WAL redo using wal-redo postgres
     ///
     #[allow(clippy::too_many_arguments)]
-    fn apply_batch_postgres(
+    #[instrument(level = "debug", skip_all, fields(%key, %lsn, %base_img_lsn, records = records.len()))]
+    async fn apply_batch_postgres(
         &self,
         key: Key,
         lsn: Lsn,
@@ -252,25 +851,38 @@ impl PostgresRedoManager {
         records: &[(Lsn, NeonWalRecord)],
         wal_redo_timeout: Duration,
         pg_version: u32,
+        timings: &mut RedoTimings,
     ) -> Result<Bytes, WalRedoError> {
         let (rel, blknum) = key_to_rel_block(key).or(Err(WalRedoError::InvalidRecord))?;
-        const MAX_RETRY_ATTEMPTS: u32 = 1;
+        let policy = &self.conf.wal_redo_policy;
+        let wal_redo_timeout = policy.timeout(wal_redo_timeout, records.len());
         let start_time = Instant::now();
+        self.failures.lock().unwrap().check(start_time)?;
+        let process = self.process(pg_version);
         let mut n_attempts = 0u32;
         loop {
-            let mut proc = self.stdin.lock().unwrap();
-            let lock_time = Instant::now();
-
-            // launch the WAL redo process on first use
-            if proc.is_none() {
-                self.launch(&mut proc, pg_version)?;
-            }
-            WAL_REDO_WAIT_TIME.observe(lock_time.duration_since(start_time).as_secs_f64());
-
-            // Relational WAL records are applied using wal-redo-postgres
+            // Relational WAL records are applied using wal-redo-postgres, launched on first use
             let buf_tag = BufferTag { rel, blknum };
-            let result = self
-                .apply_wal_records(proc, buf_tag, &base_img, records, wal_redo_timeout)
+            let pages = [(buf_tag, base_img.as_ref(), records)];
+            let exchange = self
+                .exchange(&process, &pages, wal_redo_timeout, pg_version)
+                .await;
+            let exchange = match exchange {
+                Ok(exchange) => exchange,
+                Err(e) => {
+                    let mut failures = self.failures.lock().unwrap();
+                    failures.finished(false, policy, Instant::now());
+                    return Err(e.into());
+                }
+            };
+            let lock_time = exchange.lock_time;
+            let wait_time = lock_time.duration_since(start_time);
+            observe_traced(&WAL_REDO_WAIT_TIME, wait_time.as_secs_f64());
+            timings.wait += wait_time;
+
+            let mut result = exchange
+                .pages
+                .map(|mut imgs| imgs.pop().expect("a page for each request"))
                 .map_err(WalRedoError::IoError);
 
             let end_time = Instant::now();
@@ -285,7 +897,8 @@ impl PostgresRedoManager {
                     }
             });
 
-            WAL_REDO_TIME.observe(duration.as_secs_f64());
+            observe_traced(&WAL_REDO_TIME, duration.as_secs_f64());
+            timings.execution += duration;
             WAL_REDO_RECORDS_HISTOGRAM.observe(len as f64);
             WAL_REDO_BYTES_HISTOGRAM.observe(nbytes as f64);
 
@@ -300,48 +913,194 @@ impl PostgresRedoManager {
             // If something went wrong, don't try to reuse the process. Kill it, and
             // next request will launch a new one.
             if result.is_err() {
-                error!(
-                "error applying {} WAL records {}..{} ({} bytes) to base image with LSN {} to reconstruct page image at LSN {}",
-                records.len(),
-				records.first().map(|p| p.0).unwrap_or(Lsn(0)),
-				records.last().map(|p| p.0).unwrap_or(Lsn(0)),
-                nbytes,
-				base_img_lsn,
-                lsn
-            );
-                // self.stdin only holds stdin & stderr as_raw_fd().
+                // A broken walredo binary or a corrupt record that many reads hit fails every
+                // request, don't flood the log with it.
+                error_rate_limited!(
+                    WAL_REDO_PROCESS_FAILURES,
+                    "error applying {} WAL records {}..{} ({} bytes) to base image with LSN {} to reconstruct page image at LSN {}",
+                    records.len(),
+                    records.first().map(|p| p.0).unwrap_or(Lsn(0)),
+                    records.last().map(|p| p.0).unwrap_or(Lsn(0)),
+                    nbytes,
+                    base_img_lsn,
+                    lsn
+                );
+                // process.stdin only holds stdin & stderr as_raw_fd().
                 // Dropping it as part of take() doesn't close them.
                 // The owning objects (ChildStdout and ChildStderr) are stored in
-                // self.stdout and self.stderr, respsectively.
+                // process.stdout and process.stderr, respsectively.
                 // We intentionally keep them open here to avoid a race between
                 // currently running `apply_wal_records()` and a `launch()` call
                 // after we return here.
                 // The currently running `apply_wal_records()` must not read from
                 // the newly launched process.
-                // By keeping self.stdout and self.stderr open here, `launch()` will
+                // By keeping process.stdout and process.stderr open here, `launch()` will
                 // get other file descriptors for the new child's stdout and stderr,
                 // and hence the current `apply_wal_records()` calls will observe
                 //  `output.stdout.as_raw_fd() != stdout_fd` .
-                if let Some(proc) = self.stdin.lock().unwrap().take() {
-                    proc.child.kill_and_wait();
+                if let Err(e) = &result {
+                    if let Some(exceeded) = self.kill_failed(&process, e) {
+                        result = Err(exceeded);
+                    }
+                }
+                if let Err(e) = &result {
+                    policy::count_failure(e);
                 }
             }
             n_attempts += 1;
-            if n_attempts > MAX_RETRY_ATTEMPTS || result.is_ok() {
+            if result.is_ok() || policy.gives_up(n_attempts) {
+                let mut failures = self.failures.lock().unwrap();
+                failures.finished(result.is_ok(), policy, Instant::now());
                 return result;
             }
         }
     }
 
+    /// Apply the records of several pages in one write to the postgres process, see
+    /// [`WalRedoManager::request_redo_batch`]. The requests need nothing but the process, see
+    /// [`Self::needs_only_postgres`].
+    ///
+    /// A failed batch kills the process, without retrying it or counting it towards the
+    /// quarantine of the tenant: the pages are redone one by one then.
+    #[instrument(level = "debug", skip_all, fields(pages = requests.len()))]
+    async fn apply_pages_postgres(
+        &self,
+        requests: &[(usize, RedoRequest)],
+        pg_version: u32,
+        timings: &mut RedoTimings,
+    ) -> Result<Vec<Bytes>, WalRedoError> {
+        let pages = requests
+            .iter()
+            .map(|(_, request)| {
+                let (rel, blknum) =
+                    key_to_rel_block(request.key).or(Err(WalRedoError::InvalidRecord))?;
+                let base_img = request.base_img.as_ref().map(|(_, img)| img);
+                Ok((BufferTag { rel, blknum }, base_img, request.records.as_slice()))
+            })
+            .collect::<Result<Vec<_>, WalRedoError>>()?;
+        let n_records = pages.iter().map(|(_, _, records)| records.len()).sum();
+        let policy = &self.conf.wal_redo_policy;
+        let wal_redo_timeout = policy.timeout(self.conf.wal_redo_timeout, n_records);
+
+        let start_time = Instant::now();
+        self.failures.lock().unwrap().check(start_time)?;
+        let process = self.process(pg_version);
+        let exchange = self
+            .exchange(&process, &pages, wal_redo_timeout, pg_version)
+            .await?;
+        let lock_time = exchange.lock_time;
+        let wait_time = lock_time.duration_since(start_time);
+        observe_traced(&WAL_REDO_WAIT_TIME, wait_time.as_secs_f64());
+        timings.wait += wait_time;
+
+        let result = exchange.pages.map_err(WalRedoError::IoError);
+
+        let duration = lock_time.elapsed();
+        observe_traced(&WAL_REDO_TIME, duration.as_secs_f64());
+        timings.execution += duration;
+        let mut nbytes = 0;
+        for (_, _, records) in &pages {
+            let page_nbytes: usize = records
+                .iter()
+                .map(|(_, rec)| match rec {
+                    NeonWalRecord::Postgres { rec, .. } => rec.len(),
+                    _ => unreachable!("Only PostgreSQL records are accepted in this batch"),
+                })
+                .sum();
+            WAL_REDO_RECORDS_HISTOGRAM.observe(records.len() as f64);
+            WAL_REDO_BYTES_HISTOGRAM.observe(page_nbytes as f64);
+            nbytes += page_nbytes;
+        }
+        debug!(
+            "postgres applied {n_records} WAL records ({nbytes} bytes) in {} us to reconstruct {} pages",
+            duration.as_micros(),
+            pages.len()
+        );
+
+        match result {
+            Ok(imgs) => {
+                let mut failures = self.failures.lock().unwrap();
+                failures.finished(true, policy, Instant::now());
+                Ok(imgs)
+            }
+            Err(e) => {
+                error_rate_limited!(
+                    WAL_REDO_PROCESS_FAILURES,
+                    "error applying {n_records} WAL records ({nbytes} bytes) to reconstruct {} pages in one batch: {e}",
+                    pages.len()
+                );
+                // See apply_batch_postgres about keeping stdout and stderr open
+                let e = self.kill_failed(&process, &e).unwrap_or(e);
+                policy::count_failure(&e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Can the request be batched with others in [`Self::apply_pages_postgres`], are all its
+    /// records redone by the postgres process?
+    ///
+    /// Like in `request_redo`, the records are only redone natively after the last one that
+    /// needs the process, so none of them are if the last one isn't.
+    fn needs_only_postgres(&self, request: &RedoRequest, pg_version: u32) -> bool {
+        let Some((_, last)) = request.records.last() else {
+            return false;
+        };
+        key_to_rel_block(request.key).is_ok()
+            && request.records.iter().all(|(_, rec)| !can_apply_in_neon(rec))
+            && !self.can_redo_natively(request.key, last, pg_version)
+    }
+
+    /// Kill the process after a request failed with `failure`, so that the next one launches a
+    /// new process. Returns the limit of the tenant config that the process exceeded, if any.
+    fn kill_failed(
+        &self,
+        process: &WalRedoProcess,
+        failure: &WalRedoError,
+    ) -> Option<WalRedoError> {
+        let proc = process.stdin.lock().unwrap().take()?;
+        let exit_status = proc.child.kill_and_wait();
+        process.flush_stderr();
+        let out_of_memory = process.out_of_memory.swap(false, Ordering::Relaxed);
+        let limits = *self.limits.lock().unwrap();
+        let exceeded = limits.exceeded(out_of_memory, exit_status);
+        let mut history = process.history.lock().unwrap();
+        history.killed(Some(exceeded.as_ref().unwrap_or(failure)));
+        exceeded
+    }
+
+    /// Can the Postgres record `rec` be redone on the page of `key` without the postgres
+    /// process, by the native redo of its record type, if the config enables it?
+    fn can_redo_natively(&self, key: Key, rec: &NeonWalRecord, pg_version: u32) -> bool {
+        let NeonWalRecord::Postgres { rec, .. } = rec else {
+            return false;
+        };
+        match postgres_rmid(rec) {
+            Some(pg_constants::RM_HEAP_ID) => {
+                self.conf.wal_redo_native_heap && heap::can_redo(key, rec, pg_version)
+            }
+            Some(pg_constants::RM_BTREE_ID) => {
+                self.conf.wal_redo_native_btree && btree::can_redo(key, rec, pg_version)
+            }
+            Some(pg_constants::RM_XLOG_ID) => {
+                self.conf.wal_redo_native_fpi && fpi::can_redo(key, rec, pg_version)
+            }
+            _ => false,
+        }
+    }
+
     ///
     /// Process a batch of WAL records using bespoken Neon code.
     ///
+    #[instrument(level = "debug", skip_all, fields(%key, %lsn, records = records.len()))]
     fn apply_batch_neon(
         &self,
         key: Key,
         lsn: Lsn,
         base_img: Option<Bytes>,
         records: &[(Lsn, NeonWalRecord)],
+        pg_version: u32,
+        timings: &mut RedoTimings,
     ) -> Result<Bytes, WalRedoError> {
         let start_time = Instant::now();
 
@@ -349,20 +1108,22 @@ impl PostgresRedoManager {
         if let Some(fpi) = base_img {
             // If full-page image is provided, then use it...
             page.extend_from_slice(&fpi[..]);
-        } else {
-            // All the current WAL record types that we can handle require a base image.
+        } else if !matches!(records[0].1, NeonWalRecord::Postgres { .. }) {
+            // The Postgres records start from a zeroed page without one, like in the postgres
+            // process. All the other WAL record types that we can handle require a base image.
             error!("invalid neon WAL redo request with no base image");
             return Err(WalRedoError::InvalidRequest);
         }
 
         // Apply all the WAL records in the batch
         for (record_lsn, record) in records.iter() {
-            self.apply_record_neon(key, &mut page, *record_lsn, record)?;
+            self.apply_record_neon(key, &mut page, *record_lsn, record, pg_version)?;
         }
         // Success!
         let end_time = Instant::now();
         let duration = end_time.duration_since(start_time);
-        WAL_REDO_TIME.observe(duration.as_secs_f64());
+        observe_traced(&WAL_REDO_TIME, duration.as_secs_f64());
+        timings.execution += duration;
 
         debug!(
             "neon applied {} WAL records in {} ms to reconstruct page image at LSN {}",
@@ -378,16 +1139,22 @@ impl PostgresRedoManager {
         &self,
         key: Key,
         page: &mut BytesMut,
-        _record_lsn: Lsn,
+        record_lsn: Lsn,
         record: &NeonWalRecord,
+        pg_version: u32,
     ) -> Result<(), WalRedoError> {
         match record {
-            NeonWalRecord::Postgres {
-                will_init: _,
-                rec: _,
-            } => {
-                error!("tried to pass postgres wal record to neon WAL redo");
-                return Err(WalRedoError::InvalidRequest);
+            NeonWalRecord::Postgres { will_init: _, rec } => {
+                match postgres_rmid(rec) {
+                    Some(pg_constants::RM_BTREE_ID) => {
+                        btree::redo(key, page, record_lsn, rec, pg_version)?
+                    }
+                    Some(pg_constants::RM_XLOG_ID) => {
+                        fpi::redo(key, page, record_lsn, rec, pg_version)?
+                    }
+                    _ => heap::redo(key, page, record_lsn, rec, pg_version)?,
+                }
+                WAL_REDO_NATIVE_RECORD_COUNTER.inc();
             }
             NeonWalRecord::ClearVisibilityMapFlags {
                 new_heap_blkno,
@@ -396,11 +1163,13 @@ impl PostgresRedoManager {
             } => {
                 // sanity check that this is modifying the correct relation
                 let (rel, blknum) = key_to_rel_block(key).or(Err(WalRedoError::InvalidRecord))?;
-                assert!(
-                    rel.forknum == VISIBILITYMAP_FORKNUM,
-                    "ClearVisibilityMapFlags record on unexpected rel {}",
-                    rel
-                );
+                check_page(
+                    key,
+                    "ClearVisibilityMapFlags",
+                    "forknum",
+                    VISIBILITYMAP_FORKNUM as u32,
+                    rel.forknum as u32,
+                )?;
                 if let Some(heap_blkno) = *new_heap_blkno {
                     // Calculate the VM block and offset that corresponds to the heap block.
                     let map_block = pg_constants::HEAPBLK_TO_MAPBLOCK(heap_blkno);
@@ -408,7 +1177,7 @@ impl PostgresRedoManager {
                     let map_offset = pg_constants::HEAPBLK_TO_OFFSET(heap_blkno);
 
                     // Check that we're modifying the correct VM block.
-                    assert!(map_block == blknum);
+                    check_page(key, "ClearVisibilityMapFlags", "blknum", map_block, blknum)?;
 
                     // equivalent to PageGetContents(page)
                     let map = &mut page[pg_constants::MAXALIGN_SIZE_OF_PAGE_HEADER_DATA..];
@@ -422,7 +1191,7 @@ impl PostgresRedoManager {
                     let map_byte = pg_constants::HEAPBLK_TO_MAPBYTE(heap_blkno);
                     let map_offset = pg_constants::HEAPBLK_TO_OFFSET(heap_blkno);
 
-                    assert!(map_block == blknum);
+                    check_page(key, "ClearVisibilityMapFlags", "blknum", map_block, blknum)?;
 
                     let map = &mut page[pg_constants::MAXALIGN_SIZE_OF_PAGE_HEADER_DATA..];
 
@@ -434,30 +1203,15 @@ impl PostgresRedoManager {
             NeonWalRecord::ClogSetCommitted { xids, timestamp } => {
                 let (slru_kind, segno, blknum) =
                     key_to_slru_block(key).or(Err(WalRedoError::InvalidRecord))?;
-                assert_eq!(
-                    slru_kind,
-                    SlruKind::Clog,
-                    "ClogSetCommitted record with unexpected key {}",
-                    key
-                );
+                check_slru_kind(key, "ClogSetCommitted", SlruKind::Clog, slru_kind)?;
                 for &xid in xids {
                     let pageno = xid / pg_constants::CLOG_XACTS_PER_PAGE;
                     let expected_segno = pageno / pg_constants::SLRU_PAGES_PER_SEGMENT;
                     let expected_blknum = pageno % pg_constants::SLRU_PAGES_PER_SEGMENT;
 
                     // Check that we're modifying the correct CLOG block.
-                    assert!(
-                        segno == expected_segno,
-                        "ClogSetCommitted record for XID {} with unexpected key {}",
-                        xid,
-                        key
-                    );
-                    assert!(
-                        blknum == expected_blknum,
-                        "ClogSetCommitted record for XID {} with unexpected key {}",
-                        xid,
-                        key
-                    );
+                    check_page(key, "ClogSetCommitted", "segno", expected_segno, segno)?;
+                    check_page(key, "ClogSetCommitted", "blknum", expected_blknum, blknum)?;
 
                     transaction_id_set_status(
                         xid,
@@ -484,30 +1238,15 @@ impl PostgresRedoManager {
             NeonWalRecord::ClogSetAborted { xids } => {
                 let (slru_kind, segno, blknum) =
                     key_to_slru_block(key).or(Err(WalRedoError::InvalidRecord))?;
-                assert_eq!(
-                    slru_kind,
-                    SlruKind::Clog,
-                    "ClogSetAborted record with unexpected key {}",
-                    key
-                );
+                check_slru_kind(key, "ClogSetAborted", SlruKind::Clog, slru_kind)?;
                 for &xid in xids {
                     let pageno = xid / pg_constants::CLOG_XACTS_PER_PAGE;
                     let expected_segno = pageno / pg_constants::SLRU_PAGES_PER_SEGMENT;
                     let expected_blknum = pageno % pg_constants::SLRU_PAGES_PER_SEGMENT;
 
                     // Check that we're modifying the correct CLOG block.
-                    assert!(
-                        segno == expected_segno,
-                        "ClogSetAborted record for XID {} with unexpected key {}",
-                        xid,
-                        key
-                    );
-                    assert!(
-                        blknum == expected_blknum,
-                        "ClogSetAborted record for XID {} with unexpected key {}",
-                        xid,
-                        key
-                    );
+                    check_page(key, "ClogSetAborted", "segno", expected_segno, segno)?;
+                    check_page(key, "ClogSetAborted", "blknum", expected_blknum, blknum)?;
 
                     transaction_id_set_status(xid, pg_constants::TRANSACTION_STATUS_ABORTED, page);
                 }
@@ -515,12 +1254,12 @@ impl PostgresRedoManager {
             NeonWalRecord::MultixactOffsetCreate { mid, moff } => {
                 let (slru_kind, segno, blknum) =
                     key_to_slru_block(key).or(Err(WalRedoError::InvalidRecord))?;
-                assert_eq!(
-                    slru_kind,
+                check_slru_kind(
+                    key,
+                    "MultixactOffsetCreate",
                     SlruKind::MultiXactOffsets,
-                    "MultixactOffsetCreate record with unexpected key {}",
-                    key
-                );
+                    slru_kind,
+                )?;
                 // Compute the block and offset to modify.
                 // See RecordNewMultiXact in PostgreSQL sources.
                 let pageno = mid / pg_constants::MULTIXACT_OFFSETS_PER_PAGE as u32;
@@ -530,30 +1269,20 @@ impl PostgresRedoManager {
                 // Check that we're modifying the correct multixact-offsets block.
                 let expected_segno = pageno / pg_constants::SLRU_PAGES_PER_SEGMENT;
                 let expected_blknum = pageno % pg_constants::SLRU_PAGES_PER_SEGMENT;
-                assert!(
-                    segno == expected_segno,
-                    "MultiXactOffsetsCreate record for multi-xid {} with unexpected key {}",
-                    mid,
-                    key
-                );
-                assert!(
-                    blknum == expected_blknum,
-                    "MultiXactOffsetsCreate record for multi-xid {} with unexpected key {}",
-                    mid,
-                    key
-                );
+                check_page(key, "MultixactOffsetCreate", "segno", expected_segno, segno)?;
+                check_page(key, "MultixactOffsetCreate", "blknum", expected_blknum, blknum)?;
 
                 LittleEndian::write_u32(&mut page[offset..offset + 4], *moff);
             }
             NeonWalRecord::MultixactMembersCreate { moff, members } => {
                 let (slru_kind, segno, blknum) =
                     key_to_slru_block(key).or(Err(WalRedoError::InvalidRecord))?;
-                assert_eq!(
-                    slru_kind,
+                check_slru_kind(
+                    key,
+                    "MultixactMembersCreate",
                     SlruKind::MultiXactMembers,
-                    "MultixactMembersCreate record with unexpected key {}",
-                    key
-                );
+                    slru_kind,
+                )?;
                 for (i, member) in members.iter().enumerate() {
                     let offset = moff + i as u32;
 
@@ -567,18 +1296,8 @@ impl PostgresRedoManager {
                     // Check that we're modifying the correct multixact-members block.
                     let expected_segno = pageno / pg_constants::SLRU_PAGES_PER_SEGMENT;
                     let expected_blknum = pageno % pg_constants::SLRU_PAGES_PER_SEGMENT;
-                    assert!(
-                        segno == expected_segno,
-                        "MultiXactMembersCreate record for offset {} with unexpected key {}",
-                        moff,
-                        key
-                    );
-                    assert!(
-                        blknum == expected_blknum,
-                        "MultiXactMembersCreate record for offset {} with unexpected key {}",
-                        moff,
-                        key
-                    );
+                    check_page(key, "MultixactMembersCreate", "segno", expected_segno, segno)?;
+                    check_page(key, "MultixactMembersCreate", "blknum", expected_blknum, blknum)?;
 
                     let mut flagsval = LittleEndian::read_u32(&page[flagsoff..flagsoff + 4]);
                     flagsval &= !(((1 << pg_constants::MXACT_MEMBER_BITS_PER_XACT) - 1) << bshift);
@@ -627,13 +1346,35 @@ impl<C: CommandExt> CloseFileDescriptors for C {
     }
 }
 
-impl PostgresRedoManager {
+/// What the threads of a process need to launch it and talk to it, see
+/// [`PostgresRedoManager::exchange`].
+#[derive(Clone, Copy)]
+struct ProcessContext {
+    conf: &'static PageServerConf,
+    tenant_id: TenantId,
+    /// The limits of the tenant config when the request was made.
+    limits: WalRedoLimits,
+}
+
+/// What happened to a request sent to the process by [`PostgresRedoManager::exchange`].
+struct Exchange {
+    /// When the request got the process, after waiting for a thread of the process and for the
+    /// requests of the others to be written.
+    lock_time: Instant,
+    /// The process launched for the request, if any.
+    launched_pid: Option<u32>,
+    /// The new page images, or the failure of the process.
+    pages: Result<Vec<Bytes>, Error>,
+}
+
+impl ProcessContext {
     //
     // Start postgres binary in special WAL redo mode.
     //
     #[instrument(skip_all,fields(tenant_id=%self.tenant_id, pg_version=pg_version))]
     fn launch(
         &self,
+        process: &WalRedoProcess,
         input: &mut MutexGuard<Option<ProcessInput>>,
         pg_version: u32,
     ) -> Result<(), Error> {
@@ -669,6 +1410,7 @@ impl PostgresRedoManager {
         // Start postgres itself
         let child = Command::new(pg_bin_dir_path.join("postgres"))
             .arg("--wal-redo")
+            .arg(protocol::PROTOCOL_V2_ARG)
             .stdin(Stdio::piped())
             .stderr(Stdio::piped())
             .stdout(Stdio::piped())
@@ -685,6 +1427,7 @@ impl PostgresRedoManager {
             // as close-on-exec by default, but that's not enough, since we use
             // libraries that directly call libc open without setting that flag.
             .close_fds()
+            .set_limits(self.limits)
             .spawn_no_leak_child(self.tenant_id)
             .map_err(|e| {
                 Error::new(
@@ -698,8 +1441,8 @@ impl PostgresRedoManager {
             child.kill_and_wait();
         });
 
-        let stdin = child.stdin.take().unwrap();
-        let stdout = child.stdout.take().unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let mut stdout = child.stdout.take().unwrap();
         let stderr = child.stderr.take().unwrap();
 
         macro_rules! set_nonblock_or_log_err {
@@ -715,70 +1458,49 @@ impl PostgresRedoManager {
         set_nonblock_or_log_err!(stdout)?;
         set_nonblock_or_log_err!(stderr)?;
 
+        let protocol = protocol::negotiate(&mut stdin, &mut stdout, self.conf.wal_redo_timeout)
+            .map_err(|e| {
+                Error::new(e.kind(), format!("WAL redo protocol negotiation failed: {e}"))
+            })?;
+        info!(pid = child.id(), "launched WAL redo process speaking protocol {protocol:?}");
+        process.history.lock().unwrap().launched = Some((child.id(), Instant::now()));
+
         // all fallible operations post-spawn are complete, so get rid of the guard
         let child = scopeguard::ScopeGuard::into_inner(child);
 
-        **input = Some(ProcessInput {
-            child,
-            stdout_fd: stdout.as_raw_fd(),
-            stderr_fd: stderr.as_raw_fd(),
-            stdin,
-            n_requests: 0,
-        });
-
-        *self.stdout.lock().unwrap() = Some(ProcessOutput {
-            stdout,
-            pending_responses: VecDeque::new(),
-            n_processed_responses: 0,
-        });
-        *self.stderr.lock().unwrap() = Some(stderr);
-
-        Ok(())
-    }
-
-    // Apply given WAL records ('records') over an old page image. Returns
-    // new page image.
-    //
-    #[instrument(skip_all, fields(tenant_id=%self.tenant_id, pid=%input.as_ref().unwrap().child.id()))]
-    fn apply_wal_records(
-        &self,
-        mut input: MutexGuard<Option<ProcessInput>>,
-        tag: BufferTag,
-        base_img: &Option<Bytes>,
-        records: &[(Lsn, NeonWalRecord)],
-        wal_redo_timeout: Duration,
-    ) -> Result<Bytes, std::io::Error> {
-        // Serialize all the messages to send the WAL redo process first.
-        //
-        // This could be problematic if there are millions of records to replay,
-        // but in practice the number of records is usually so small that it doesn't
-        // matter, and it's better to keep this code simple.
-        //
-        // Most requests start with a before-image with BLCKSZ bytes, followed by
-        // by some other WAL records. Start with a buffer that can hold that
-        // comfortably.
-        let mut writebuf: Vec<u8> = Vec::with_capacity((BLCKSZ as usize) * 3);
-        build_begin_redo_for_block_msg(tag, &mut writebuf);
-        if let Some(img) = base_img {
-            build_push_page_msg(tag, img, &mut writebuf);
-        }
-        for (lsn, rec) in records.iter() {
-            if let NeonWalRecord::Postgres {
-                will_init: _,
-                rec: postgres_rec,
-            } = rec
-            {
-                build_apply_record_msg(*lsn, postgres_rec, &mut writebuf);
-            } else {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    "tried to pass neon wal record to postgres WAL redo",
-                ));
-            }
-        }
-        build_get_page_msg(tag, &mut writebuf);
-        WAL_REDO_RECORD_COUNTER.inc_by(records.len() as u64);
-
+        **input = Some(ProcessInput {
+            child,
+            stdout_fd: stdout.as_raw_fd(),
+            stderr_fd: stderr.as_raw_fd(),
+            stdin,
+            n_requests: 0,
+        });
+
+        *process.stdout.lock().unwrap() = Some(ProcessOutput {
+            stdout,
+            protocol,
+            pending_responses: VecDeque::new(),
+            n_processed_responses: 0,
+        });
+        *process.stderr.lock().unwrap() = Some(stderr);
+        process.out_of_memory.store(false, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+
+    // Send the messages of 'n_pages' pages, built by build_redo_request(), to the
+    // process. Returns the new page images, in the same order.
+    //
+    #[instrument(skip_all, fields(tenant_id=%self.tenant_id, pid=%input.as_ref().unwrap().child.id()))]
+    fn apply_wal_records(
+        &self,
+        process: &WalRedoProcess,
+        mut input: MutexGuard<Option<ProcessInput>>,
+        writebuf: &[u8],
+        n_pages: usize,
+        wal_redo_timeout: Duration,
+    ) -> Result<Vec<Bytes>, std::io::Error> {
         let proc = input.as_mut().unwrap();
         let mut nwrite = 0usize;
         let stdout_fd = proc.stdout_fd;
@@ -802,24 +1524,20 @@ impl PostgresRedoManager {
             }?;
 
             if n == 0 {
-                return Err(Error::new(ErrorKind::Other, "WAL redo timed out"));
+                return Err(Error::new(ErrorKind::TimedOut, "WAL redo timed out"));
             }
 
             // If we have some messages in stderr, forward them to the log.
             let err_revents = pollfds[1].revents().unwrap();
             if err_revents & (PollFlags::POLLERR | PollFlags::POLLIN) != PollFlags::empty() {
                 let mut errbuf: [u8; 16384] = [0; 16384];
-                let mut stderr_guard = self.stderr.lock().unwrap();
+                let mut stderr_guard = process.stderr.lock().unwrap();
                 let stderr = stderr_guard.as_mut().unwrap();
                 let len = stderr.read(&mut errbuf)?;
 
-                // The message might not be split correctly into lines here. But this is
-                // good enough, the important thing is to get the message to the log.
+                // The lines cut by the read are put together by `stderr_log`
                 if len > 0 {
-                    error!(
-                        "wal-redo-postgres: {}",
-                        String::from_utf8_lossy(&errbuf[0..len])
-                    );
+                    process.forward_stderr(&errbuf[0..len]);
 
                     // To make sure we capture all log from the process if it fails, keep
                     // reading from the stderr, before checking the stdout.
@@ -844,8 +1562,11 @@ impl PostgresRedoManager {
                 ));
             }
         }
-        let request_no = proc.n_requests;
-        proc.n_requests += 1;
+        // The pages of a batch are consecutive requests, with a response each.
+        let first_request_no = proc.n_requests;
+        proc.n_requests += n_pages;
+        let last_request_no = proc.n_requests - 1;
+        proc.child.sample_cpu_time();
         drop(input);
 
         // To improve walredo performance we separate sending requests and receiving
@@ -860,7 +1581,7 @@ impl PostgresRedoManager {
         // pending responses ring buffer and truncate all empty elements from the front,
         // advancing processed responses number.
 
-        let mut output_guard = self.stdout.lock().unwrap();
+        let mut output_guard = process.stdout.lock().unwrap();
         let output = output_guard.as_mut().unwrap();
         if output.stdout.as_raw_fd() != stdout_fd {
             // If stdout file descriptor is changed then it means that walredo process is crashed and restarted.
@@ -878,12 +1599,11 @@ impl PostgresRedoManager {
             ));
         }
         let n_processed_responses = output.n_processed_responses;
-        while n_processed_responses + output.pending_responses.len() <= request_no {
-            // We expect the WAL redo process to respond with an 8k page image. We read it
-            // into this buffer.
-            let mut resultbuf = vec![0; BLCKSZ.into()];
-            let mut nresult: usize = 0; // # of bytes read into 'resultbuf' so far
-            while nresult < BLCKSZ.into() {
+        while n_processed_responses + output.pending_responses.len() <= last_request_no {
+            // We expect the WAL redo process to respond with an 8k page image, framed or not
+            // depending on the protocol, or with an error.
+            let mut response = ResponseReader::new(output.protocol);
+            while let Some(buf) = response.unfilled() {
                 // We do two things simultaneously: reading response from stdout
                 // and forward any logging information that the child writes to its stderr to the page server's log.
                 let n = loop {
@@ -894,24 +1614,20 @@ impl PostgresRedoManager {
                 }?;
 
                 if n == 0 {
-                    return Err(Error::new(ErrorKind::Other, "WAL redo timed out"));
+                    return Err(Error::new(ErrorKind::TimedOut, "WAL redo timed out"));
                 }
 
                 // If we have some messages in stderr, forward them to the log.
                 let err_revents = pollfds[1].revents().unwrap();
                 if err_revents & (PollFlags::POLLERR | PollFlags::POLLIN) != PollFlags::empty() {
                     let mut errbuf: [u8; 16384] = [0; 16384];
-                    let mut stderr_guard = self.stderr.lock().unwrap();
+                    let mut stderr_guard = process.stderr.lock().unwrap();
                     let stderr = stderr_guard.as_mut().unwrap();
                     let len = stderr.read(&mut errbuf)?;
 
-                    // The message might not be split correctly into lines here. But this is
-                    // good enough, the important thing is to get the message to the log.
+                    // The lines cut by the read are put together by `stderr_log`
                     if len > 0 {
-                        error!(
-                            "wal-redo-postgres: {}",
-                            String::from_utf8_lossy(&errbuf[0..len])
-                        );
+                        process.forward_stderr(&errbuf[0..len]);
 
                         // To make sure we capture all log from the process if it fails, keep
                         // reading from the stderr, before checking the stdout.
@@ -927,7 +1643,8 @@ impl PostgresRedoManager {
                 // If we have some data in stdout, read it to the result buffer.
                 let out_revents = pollfds[2].revents().unwrap();
                 if out_revents & (PollFlags::POLLERR | PollFlags::POLLIN) != PollFlags::empty() {
-                    nresult += output.stdout.read(&mut resultbuf[nresult..])?;
+                    let n = output.stdout.read(buf)?;
+                    response.filled(n)?;
                 } else if out_revents.contains(PollFlags::POLLHUP) {
                     return Err(Error::new(
                         ErrorKind::BrokenPipe,
@@ -935,9 +1652,13 @@ impl PostgresRedoManager {
                     ));
                 }
             }
-            output
-                .pending_responses
-                .push_back(Some(Bytes::from(resultbuf)));
+            let response = response.finish();
+            if let Err(e) = &response {
+                if protocol::is_out_of_memory(e) {
+                    process.out_of_memory.store(true, Ordering::Relaxed);
+                }
+            }
+            output.pending_responses.push_back(Some(response));
         }
         // Replace our request's response with None in `pending_responses`.
         // Then make space in the ring buffer by clearing out any seqence of contiguous
@@ -971,9 +1692,13 @@ impl PostgresRedoManager {
         // T2: does the while loop below
         // pending_responses now looks like this: Front Back
         // n_processed_responses now has value 25
-        let res = output.pending_responses[request_no - n_processed_responses]
-            .take()
-            .expect("we own this request_no, nobody else is supposed to take it");
+        let responses: Vec<_> = (first_request_no..=last_request_no)
+            .map(|request_no| {
+                output.pending_responses[request_no - n_processed_responses]
+                    .take()
+                    .expect("we own this request_no, nobody else is supposed to take it")
+            })
+            .collect();
         while let Some(front) = output.pending_responses.front() {
             if front.is_none() {
                 output.pending_responses.pop_front();
@@ -982,7 +1707,7 @@ impl PostgresRedoManager {
                 break;
             }
         }
-        Ok(res)
+        responses.into_iter().collect()
     }
 }
 
@@ -990,42 +1715,49 @@ impl PostgresRedoManager {
 /// will be killed and waited-for by this process before being dropped.
 struct NoLeakChild {
     tenant_id: TenantId,
-    child: Option<Child>,
+    /// The child, and its CPU time, counted until it is waited for.
+    child: Option<(Child, CpuTime)>,
 }
 
 impl Deref for NoLeakChild {
     type Target = Child;
 
     fn deref(&self) -> &Self::Target {
-        self.child.as_ref().expect("must not use from drop")
+        &self.child.as_ref().expect("must not use from drop").0
     }
 }
 
 impl DerefMut for NoLeakChild {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        self.child.as_mut().expect("must not use from drop")
+        &mut self.child.as_mut().expect("must not use from drop").0
     }
 }
 
 impl NoLeakChild {
     fn spawn(tenant_id: TenantId, command: &mut Command) -> io::Result<Self> {
         let child = command.spawn()?;
+        let cpu_time = CpuTime::new(&tenant_id, child.id());
         Ok(NoLeakChild {
             tenant_id,
-            child: Some(child),
+            child: Some((child, cpu_time)),
         })
     }
 
-    fn kill_and_wait(mut self) {
-        let child = match self.child.take() {
-            Some(child) => child,
-            None => return,
-        };
-        Self::kill_and_wait_impl(child);
+    /// Count the CPU time of the child, every so often.
+    fn sample_cpu_time(&mut self) {
+        if let Some((_, cpu_time)) = &mut self.child {
+            cpu_time.sample_periodically();
+        }
+    }
+
+    /// Returns how the process exited, `None` if that's unknown.
+    fn kill_and_wait(mut self) -> Option<ExitStatus> {
+        let (child, cpu_time) = self.child.take()?;
+        Self::kill_and_wait_impl(child, cpu_time)
     }
 
     #[instrument(skip_all, fields(pid=child.id()))]
-    fn kill_and_wait_impl(mut child: Child) {
+    fn kill_and_wait_impl(mut child: Child, mut cpu_time: CpuTime) -> Option<ExitStatus> {
         let res = child.kill();
         if let Err(e) = res {
             // This branch is very unlikely because:
@@ -1042,13 +1774,17 @@ impl NoLeakChild {
             // with the wait().
             error!(error = %e, "failed to SIGKILL; subsequent wait() might fail or wait for wrong process");
         }
+        // Until it is waited for, the killed process keeps its CPU time for us to read
+        cpu_time.sample();
 
         match child.wait() {
             Ok(exit_status) => {
                 info!(exit_status = %exit_status, "wait successful");
+                Some(exit_status)
             }
             Err(e) => {
                 error!(error = %e, "wait error; might leak the child process; it will show as zombie (defunct)");
+                None
             }
         }
     }
@@ -1056,7 +1792,7 @@ impl NoLeakChild {
 
 impl Drop for NoLeakChild {
     fn drop(&mut self) {
-        let child = match self.child.take() {
+        let (child, cpu_time) = match self.child.take() {
             Some(child) => child,
             None => return,
         };
@@ -1070,7 +1806,7 @@ impl Drop for NoLeakChild {
                 // This thread here is going to outlive of our dropper.
                 let span = tracing::info_span!("walredo", %tenant_id);
                 let _entered = span.enter();
-                Self::kill_and_wait_impl(child);
+                Self::kill_and_wait_impl(child, cpu_time);
             })
             .await
         });
@@ -1091,6 +1827,44 @@ impl NoLeakChildCommandExt for Command {
 // process. See pgxn/neon_walredo/walredoproc.c for
 // explanation of the protocol.
 
+/// The messages of a request for each of `pages`: its old page image, if any, its WAL records,
+/// and the new page image to send back.
+fn build_redo_request(
+    pages: &[(BufferTag, Option<&Bytes>, &[(Lsn, NeonWalRecord)])],
+) -> Result<Vec<u8>, Error> {
+    // This could be problematic if there are millions of records to replay,
+    // but in practice the number of records is usually so small that it doesn't
+    // matter, and it's better to keep this code simple.
+    //
+    // Most requests start with a before-image with BLCKSZ bytes, followed by
+    // by some other WAL records. Start with a buffer that can hold that
+    // comfortably.
+    let mut writebuf: Vec<u8> = Vec::with_capacity((BLCKSZ as usize) * 3 * pages.len());
+    for &(tag, base_img, records) in pages {
+        build_begin_redo_for_block_msg(tag, &mut writebuf);
+        if let Some(img) = base_img {
+            build_push_page_msg(tag, img, &mut writebuf);
+        }
+        for (lsn, rec) in records.iter() {
+            if let NeonWalRecord::Postgres {
+                will_init: _,
+                rec: postgres_rec,
+            } = rec
+            {
+                build_apply_record_msg(*lsn, postgres_rec, &mut writebuf);
+            } else {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    "tried to pass neon wal record to postgres WAL redo",
+                ));
+            }
+        }
+        build_get_page_msg(tag, &mut writebuf);
+        WAL_REDO_RECORD_COUNTER.inc_by(records.len() as u64);
+    }
+    Ok(writebuf)
+}
+
 fn build_begin_redo_for_block_msg(tag: BufferTag, buf: &mut Vec<u8>) {
     let len = 4 + 1 + 4 * 4;
 
@@ -1133,19 +1907,24 @@ fn build_get_page_msg(tag: BufferTag, buf: &mut Vec<u8>) {
 
 #[cfg(test)]
 mod tests {
-    use super::{PostgresRedoManager, WalRedoManager};
+    use super::{PostgresRedoManager, RedoRequest, RedoTimings, WalRedoError, WalRedoManager};
+    use crate::metrics::WAL_REDO_NATIVE_RECORD_COUNTER;
+    use crate::redo_fixture::{RedoFixture, FIXTURE_EXTENSION, PAGE_EXTENSION};
     use crate::repository::Key;
     use crate::{config::PageServerConf, walrecord::NeonWalRecord};
-    use bytes::Bytes;
+    use bytes::{BufMut, Bytes, BytesMut};
+    use postgres_ffi::{pg_constants, XLogRecord, BLCKSZ, XLOG_SIZE_OF_XLOG_RECORD};
+    use std::ffi::OsStr;
     use std::str::FromStr;
     use utils::{id::TenantId, lsn::Lsn};
 
-    #[test]
-    fn short_v14_redo() {
+    #[tokio::test]
+    async fn short_v14_redo() {
         let expected = std::fs::read("fixtures/short_v14_redo.page").unwrap();
 
         let h = RedoHarness::new().unwrap();
 
+        let mut timings = RedoTimings::default();
         let page = h
             .manager
             .request_redo(
@@ -1161,14 +1940,17 @@ mod tests {
                 None,
                 short_records(),
                 14,
+                &mut timings,
             )
+            .await
             .unwrap();
 
         assert_eq!(&expected, &*page);
+        assert!(timings.execution > std::time::Duration::ZERO);
     }
 
-    #[test]
-    fn short_v14_fails_for_wrong_key_but_returns_zero_page() {
+    #[tokio::test]
+    async fn short_v14_fails_for_wrong_key_but_returns_zero_page() {
         let h = RedoHarness::new().unwrap();
 
         let page = h
@@ -1187,7 +1969,9 @@ mod tests {
                 None,
                 short_records(),
                 14,
+                &mut RedoTimings::default(),
             )
+            .await
             .unwrap();
 
         // TODO: there will be some stderr printout, which is forwarded to tracing that could
@@ -1195,6 +1979,570 @@ mod tests {
         assert_eq!(page, crate::ZERO_PAGE);
     }
 
+    /// A neon record under the key of another page fails the request, and only the request.
+    #[tokio::test]
+    async fn neon_record_for_another_page() {
+        let h = RedoHarness::new().unwrap();
+        // The first CLOG page
+        let key = Key {
+            field1: 0x01,
+            field2: 0x00,
+            field3: 1,
+            field4: 0,
+            field5: 0,
+            field6: 0,
+        };
+        let h = &h;
+        let redo = move |record: NeonWalRecord| async move {
+            h.manager
+                .request_redo(
+                    key,
+                    Lsn(0x20),
+                    Some((Lsn(0x10), crate::ZERO_PAGE.clone())),
+                    vec![(Lsn(0x20), record)],
+                    14,
+                    &mut RedoTimings::default(),
+                )
+                .await
+        };
+
+        // An XID on the second page of the second segment
+        let xid = pg_constants::CLOG_XACTS_PER_PAGE * (pg_constants::SLRU_PAGES_PER_SEGMENT + 1);
+        let res = redo(NeonWalRecord::ClogSetAborted { xids: vec![1, xid] }).await;
+        assert!(
+            matches!(
+                res,
+                Err(WalRedoError::Corrupted {
+                    record: "ClogSetAborted",
+                    field: "segno",
+                    expected: 1,
+                    actual: 0,
+                    ..
+                })
+            ),
+            "{res:?}"
+        );
+
+        let res = redo(NeonWalRecord::MultixactOffsetCreate { mid: 1, moff: 1 }).await;
+        assert!(
+            matches!(
+                res,
+                Err(WalRedoError::Corrupted {
+                    record: "MultixactOffsetCreate",
+                    field: "slru",
+                    expected: 2,
+                    actual: 0,
+                    ..
+                })
+            ),
+            "{res:?}"
+        );
+
+        let res = redo(NeonWalRecord::ClogSetAborted { xids: vec![1] }).await;
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[tokio::test]
+    async fn short_v14_batch_redo() {
+        let expected = std::fs::read("fixtures/short_v14_redo.page").unwrap();
+
+        let h = RedoHarness::new().unwrap();
+        h.manager.launch_process(14).await.unwrap();
+
+        let request = |field3: u32, records: Vec<(Lsn, NeonWalRecord)>| RedoRequest {
+            key: Key {
+                field1: 0,
+                field2: 1663,
+                field3,
+                field4: 1259,
+                field5: 0,
+                field6: 0,
+            },
+            lsn: Lsn::from_str("0/16E2408").unwrap(),
+            base_img: None,
+            records,
+        };
+        // The wrong key gets a zero page, a request without records fails on its own
+        let requests = vec![
+            request(13010, short_records()),
+            request(13130, short_records()),
+            request(13010, Vec::new()),
+            request(13010, short_records()),
+        ];
+        let mut timings = RedoTimings::default();
+        let results = h
+            .manager
+            .request_redo_batch(requests, 14, &mut timings)
+            .await;
+
+        assert_eq!(results.len(), 4);
+        let mut results = results.into_iter();
+        assert_eq!(&expected, &*results.next().unwrap().unwrap());
+        assert_eq!(results.next().unwrap().unwrap(), crate::ZERO_PAGE);
+        assert!(matches!(results.next().unwrap(), Err(WalRedoError::InvalidRequest)));
+        assert_eq!(&expected, &*results.next().unwrap().unwrap());
+        assert!(timings.execution > std::time::Duration::ZERO);
+
+        // Only the pages with records went to the process
+        let process = h.manager.process(14);
+        let input = process.stdin.lock().unwrap();
+        assert_eq!(input.as_ref().unwrap().n_requests, 3);
+    }
+
+    /// A tenant whose WAL redo process keeps failing is quarantined: its requests fail without
+    /// launching the process.
+    #[tokio::test]
+    async fn quarantine_after_repeated_failures() {
+        let h = RedoHarness::with_conf(|conf| {
+            conf.pg_distrib_dir = conf.workdir.join("no-such-pg-distrib");
+            conf.wal_redo_policy.quarantine_threshold = 2;
+        })
+        .unwrap();
+        let h = &h;
+        let redo = move || async move {
+            h.manager
+                .request_redo(
+                    Key {
+                        field1: 0,
+                        field2: 1663,
+                        field3: 13010,
+                        field4: 1259,
+                        field5: 0,
+                        field6: 0,
+                    },
+                    Lsn::from_str("0/16E2408").unwrap(),
+                    None,
+                    short_records(),
+                    14,
+                    &mut RedoTimings::default(),
+                )
+                .await
+        };
+
+        assert!(matches!(redo().await, Err(WalRedoError::IoError(_))));
+        assert!(matches!(redo().await, Err(WalRedoError::IoError(_))));
+        assert!(matches!(redo().await, Err(WalRedoError::Quarantined)));
+    }
+
+    #[tokio::test]
+    async fn ping() {
+        let h = RedoHarness::new().unwrap();
+        // The process launched for the ping doesn't stay around
+        h.manager.ping(14).await.unwrap();
+        assert!(h.manager.process(14).stdin.lock().unwrap().is_none());
+
+        // A running process is pinged in place
+        h.manager.launch_process(14).await.unwrap();
+        h.manager.ping(14).await.unwrap();
+        assert!(h.manager.process(14).stdin.lock().unwrap().is_some());
+
+        let h = RedoHarness::with_conf(|conf| {
+            conf.pg_distrib_dir = conf.workdir.join("no-such-pg-distrib");
+        })
+        .unwrap();
+        assert!(matches!(h.manager.ping(14).await, Err(WalRedoError::IoError(_))));
+    }
+
+    #[tokio::test]
+    async fn status() {
+        let h = RedoHarness::new().unwrap();
+        assert!(h.manager.status().is_empty());
+
+        h.manager.launch_process(14).await.unwrap();
+        let process = h.manager.process(14);
+        let pid = process.stdin.lock().unwrap().as_ref().unwrap().child.id();
+        let statuses = h.manager.status();
+        let [status] = &statuses[..] else {
+            panic!("expected the status of a process");
+        };
+        assert_eq!(status.pg_version, 14);
+        assert_eq!(status.pid, Some(pid));
+        assert!(status.uptime_secs.is_some());
+        assert_eq!(status.n_requests, Some(0));
+        assert_eq!(status.pending_responses, Some(0));
+        assert_eq!(status.restarts, 0);
+
+        // The failure that killed the process is kept after it
+        h.manager.kill_failed(&process, &WalRedoError::InvalidRecord);
+        let statuses = h.manager.status();
+        let [status] = &statuses[..] else {
+            panic!("expected the status of a process");
+        };
+        assert_eq!(status.pid, None);
+        assert_eq!(status.n_requests, None);
+        assert_eq!(status.restarts, 1);
+        assert_eq!(
+            status.last_restart_reason.as_deref(),
+            Some("cannot perform WAL redo for this record")
+        );
+    }
+
+    #[tokio::test]
+    async fn shutdown() {
+        let h = RedoHarness::new().unwrap();
+        h.manager.launch_process(14).await.unwrap();
+        assert!(h.manager.shutdown());
+
+        // The process is gone, without counting as a restart, and no request starts another
+        let statuses = h.manager.status();
+        let [status] = &statuses[..] else {
+            panic!("expected the status of a process");
+        };
+        assert_eq!(status.pid, None);
+        assert_eq!(status.restarts, 0);
+        let mut timings = RedoTimings::default();
+        let key = Key::from_hex("000000067F00032CE5000000000000000001").unwrap();
+        let res = h
+            .manager
+            .request_redo(
+                key,
+                Lsn::from_str("0/16E2408").unwrap(),
+                None,
+                short_records(),
+                14,
+                &mut timings,
+            )
+            .await;
+        assert!(matches!(res, Err(WalRedoError::ShuttingDown)));
+        assert!(matches!(h.manager.ping(14).await, Err(WalRedoError::ShuttingDown)));
+        assert!(h.manager.process(14).stdin.lock().unwrap().is_none());
+    }
+
+    /// The timelines of different Postgres versions get a process each, which stays around when
+    /// the requests alternate between them.
+    #[tokio::test]
+    async fn process_per_pg_version() {
+        let expected = std::fs::read("fixtures/short_v14_redo.page").unwrap();
+
+        let h = RedoHarness::new().unwrap();
+        h.manager.launch_process(14).await.unwrap();
+        h.manager.launch_process(15).await.unwrap();
+        let pid = |pg_version| {
+            let process = h.manager.process(pg_version);
+            let input = process.stdin.lock().unwrap();
+            input.as_ref().unwrap().child.id()
+        };
+        let (pid_v14, pid_v15) = (pid(14), pid(15));
+        assert_ne!(pid_v14, pid_v15);
+
+        let page = h
+            .manager
+            .request_redo(
+                Key {
+                    field1: 0,
+                    field2: 1663,
+                    field3: 13010,
+                    field4: 1259,
+                    field5: 0,
+                    field6: 0,
+                },
+                Lsn::from_str("0/16E2408").unwrap(),
+                None,
+                short_records(),
+                14,
+                &mut RedoTimings::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(&expected, &*page);
+        h.manager.ping(15).await.unwrap();
+        assert_eq!((pid(14), pid(15)), (pid_v14, pid_v15));
+    }
+
+    /// The concurrent requests are pipelined to the same process, and each gets the response
+    /// to its own request.
+    #[tokio::test]
+    async fn short_v14_pipelined_redo() {
+        let expected = std::fs::read("fixtures/short_v14_redo.page").unwrap();
+
+        let h = RedoHarness::new().unwrap();
+        h.manager.launch_process(14).await.unwrap();
+        let pid = h.manager.process(14).stdin.lock().unwrap().as_ref().unwrap().child.id();
+
+        let requesters = (0..8).map(|i| {
+            let h = &h;
+            async move {
+                // Every other request is for the wrong key, which gets a zero page
+                let field3 = if i % 2 == 0 { 13010 } else { 13130 };
+                let key = Key {
+                    field1: 0,
+                    field2: 1663,
+                    field3,
+                    field4: 1259,
+                    field5: 0,
+                    field6: 0,
+                };
+                let lsn = Lsn::from_str("0/16E2408").unwrap();
+                let mut pages = Vec::new();
+                for _ in 0..16 {
+                    let page = h
+                        .manager
+                        .request_redo(
+                            key,
+                            lsn,
+                            None,
+                            short_records(),
+                            14,
+                            &mut RedoTimings::default(),
+                        )
+                        .await
+                        .unwrap();
+                    pages.push(page);
+                }
+                pages
+            }
+        });
+        let pages = futures::future::join_all(requesters).await;
+        for (i, pages) in pages.into_iter().enumerate() {
+            for page in pages {
+                if i % 2 == 0 {
+                    assert_eq!(&expected, &*page);
+                } else {
+                    assert_eq!(page, crate::ZERO_PAGE);
+                }
+            }
+        }
+
+        // All the requests went to the same process
+        let process = h.manager.process(14);
+        let input = process.stdin.lock().unwrap();
+        let input = input.as_ref().unwrap();
+        assert_eq!(input.child.id(), pid);
+        assert_eq!(input.n_requests, 8 * 16);
+    }
+
+    #[tokio::test]
+    async fn short_v14_fixture() {
+        let expected = std::fs::read("fixtures/short_v14_redo.page").unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("short_v14.redo");
+        let fixture = RedoFixture {
+            key: Key {
+                field1: 0,
+                field2: 1663,
+                field3: 13010,
+                field4: 1259,
+                field5: 0,
+                field6: 0,
+            },
+            lsn: Lsn::from_str("0/16E2408").unwrap(),
+            pg_version: 14,
+            base_img: None,
+            records: short_records(),
+        };
+        fixture.write(&path).unwrap();
+        let fixture_read = RedoFixture::read(&path).unwrap();
+        assert_eq!(fixture_read, fixture);
+
+        let h = RedoHarness::new().unwrap();
+        assert_eq!(&expected, &*h.redo_fixture(&fixture_read).await);
+    }
+
+    /// The fixtures captured with `pageserver capture-redo-fixture` redo to the page captured
+    /// with them, with the records redone natively or not.
+    #[tokio::test]
+    async fn captured_fixtures() {
+        let h = RedoHarness::new().unwrap();
+        let native = RedoHarness::native().unwrap();
+        for entry in std::fs::read_dir("fixtures").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some(OsStr::new(FIXTURE_EXTENSION)) {
+                continue;
+            }
+            let fixture = RedoFixture::read(&path).unwrap();
+            let expected = std::fs::read(path.with_extension(PAGE_EXTENSION)).unwrap();
+            let page = h.redo_fixture(&fixture).await;
+            assert!(expected == page, "{} redoes to another page", path.display());
+            let page = native.redo_fixture(&fixture).await;
+            assert!(expected == page, "{} redoes natively to another page", path.display());
+        }
+    }
+
+    /// Heap inserts and deletes redone natively give the same pages as the postgres process.
+    #[tokio::test]
+    async fn native_heap_redo() {
+        let native = RedoHarness::native_heap().unwrap();
+        let native_before = WAL_REDO_NATIVE_RECORD_COUNTER.get();
+        assert_same_redo(&native, heap_records()).await;
+        assert!(WAL_REDO_NATIVE_RECORD_COUNTER.get() > native_before);
+    }
+
+    /// HOT updates redone natively give the same pages as the postgres process, with the new
+    /// tuples sharing a prefix or a suffix with the old ones or not.
+    #[tokio::test]
+    async fn native_hot_update_redo() {
+        let native = RedoHarness::native_heap().unwrap();
+        let records = vec![
+            heap_insert(1000, 1, true, 0, &[[1; 40], [2; 40], [3; 40]].concat()),
+            heap_insert(1000, 2, false, 0, &[4; 8]),
+            heap_hot_update(1001, 1, 3, 40, 40, 0, &[5; 20]),
+            heap_hot_update(1002, 3, 4, 0, 10, 0, &[6; 30]),
+            heap_hot_update(1002, 4, 5, 30, 0, 0, &[7; 3]),
+            heap_hot_update(
+                1003,
+                2,
+                6,
+                0,
+                0,
+                pg_constants::XLH_UPDATE_OLD_ALL_VISIBLE_CLEARED,
+                &[8; 16],
+            ),
+        ];
+        assert_same_redo(&native, lsns(records)).await;
+    }
+
+    /// Heap records on a page whose pointers are corrupted fail the request.
+    #[tokio::test]
+    async fn native_heap_redo_on_corrupted_page() {
+        let native = RedoHarness::native_heap().unwrap();
+        let insert = heap_insert(1000, 1, true, 0, &[1; 8]);
+        let records = lsns(vec![insert.clone()]);
+        let page = native
+            .redo_fixture(&RedoFixture {
+                key: heap_key(),
+                lsn: records[0].0,
+                pg_version: 14,
+                base_img: None,
+                records,
+            })
+            .await;
+        let mut page = BytesMut::from(&page[..]);
+        // pd_lower past the end of the page
+        page[12..14].copy_from_slice(&u16::MAX.to_le_bytes());
+        let page = page.freeze();
+
+        for record in [
+            heap_insert(1001, 2, false, 0, &[2; 8]),
+            heap_delete(1001, 1, 0, 0),
+            heap_hot_update(1001, 1, 2, 0, 0, 0, &[3; 8]),
+        ] {
+            let records = lsns(vec![insert.clone(), record]);
+            let res = native
+                .manager
+                .request_redo(
+                    heap_key(),
+                    records[1].0,
+                    Some((records[0].0, page.clone())),
+                    records[1..].to_vec(),
+                    14,
+                    &mut RedoTimings::default(),
+                )
+                .await;
+            assert!(matches!(res, Err(WalRedoError::InvalidRecord)), "{res:?}");
+        }
+    }
+
+    /// Btree leaf inserts on a page of a new index, and the full-page images of the page,
+    /// redone natively give the same pages as the postgres process.
+    #[tokio::test]
+    async fn native_btree_and_fpi_redo() {
+        let h = RedoHarness::new().unwrap();
+        let native = RedoHarness::native().unwrap();
+        let fpi = |info: u8, image: &[u8]| {
+            heap_record(pg_constants::RM_XLOG_ID, info, 0, Some(image), &[], &[])
+        };
+        let mut records = vec![
+            fpi(pg_constants::XLOG_FPI, &btree_leaf_page()),
+            btree_insert(1, &[1; 16]),
+            btree_insert(2, &[2; 24]),
+            // Before the existing items
+            btree_insert(1, &[3; 16]),
+            btree_insert(2, &[4; 13]),
+        ];
+        let image = h
+            .redo_fixture(&RedoFixture {
+                key: heap_key(),
+                lsn: Lsn(0x0100_0000 + 0x100 * (records.len() - 1) as u64),
+                pg_version: 14,
+                base_img: None,
+                records: lsns(records.clone()),
+            })
+            .await;
+        records.push(fpi(pg_constants::XLOG_FPI_FOR_HINT, &image));
+        records.push(btree_insert(5, &[5; 16]));
+
+        let native_before = WAL_REDO_NATIVE_RECORD_COUNTER.get();
+        assert_same_redo(&native, lsns(records)).await;
+        assert!(WAL_REDO_NATIVE_RECORD_COUNTER.get() > native_before);
+    }
+
+    /// Redo the prefixes of `records` with `native`, and with the postgres process, on top of
+    /// base images or not, and compare the pages.
+    async fn assert_same_redo(native: &RedoHarness, records: Vec<(Lsn, NeonWalRecord)>) {
+        let h = RedoHarness::new().unwrap();
+        for end in 1..=records.len() {
+            let fixture = RedoFixture {
+                key: heap_key(),
+                lsn: records[end - 1].0,
+                pg_version: 14,
+                base_img: None,
+                records: records[..end].to_vec(),
+            };
+            let expected = h.redo_fixture(&fixture).await;
+            assert!(
+                expected == native.redo_fixture(&fixture).await,
+                "redo of {end} records differs"
+            );
+
+            // On top of a base image, including a record that the image has already
+            for start in 2..end {
+                let base_img = h
+                    .redo_fixture(&RedoFixture {
+                        lsn: records[start - 1].0,
+                        records: records[..start].to_vec(),
+                        ..fixture.clone()
+                    })
+                    .await;
+                let fixture = RedoFixture {
+                    base_img: Some((records[start - 1].0, base_img)),
+                    records: records[start - 1..end].to_vec(),
+                    ..fixture.clone()
+                };
+                assert!(
+                    h.redo_fixture(&fixture).await == native.redo_fixture(&fixture).await,
+                    "redo of records {start}..{end} on a base image differs"
+                );
+            }
+        }
+    }
+
+    /// Heap records before a record that needs the postgres process are left to it.
+    #[tokio::test]
+    async fn native_heap_redo_after_postgres_records() {
+        let h = RedoHarness::new().unwrap();
+        let native = RedoHarness::native_heap().unwrap();
+        let mut records = heap_records();
+
+        let image = h
+            .redo_fixture(&RedoFixture {
+                key: heap_key(),
+                lsn: records[2].0,
+                pg_version: 14,
+                base_img: None,
+                records: records[..3].to_vec(),
+            })
+            .await;
+        let fpi = heap_record(
+            pg_constants::RM_XLOG_ID,
+            pg_constants::XLOG_FPI,
+            0,
+            Some(&image[..]),
+            &[],
+            &[],
+        );
+        records.insert(3, (Lsn(records[2].0 .0 + 0x80), fpi));
+
+        let fixture = RedoFixture {
+            key: heap_key(),
+            lsn: records.last().unwrap().0,
+            pg_version: 14,
+            base_img: None,
+            records,
+        };
+        assert!(h.redo_fixture(&fixture).await == native.redo_fixture(&fixture).await);
+    }
+
     #[allow(clippy::octal_escapes)]
     fn short_records() -> Vec<(Lsn, NeonWalRecord)> {
         vec![
@@ -1215,6 +2563,240 @@ mod tests {
         ]
     }
 
+    fn heap_key() -> Key {
+        Key {
+            field1: 0,
+            field2: 1663,
+            field3: 16384,
+            field4: 16385,
+            field5: 0,
+            field6: 7,
+        }
+    }
+
+    /// A WAL record of `rmid` on the block of `heap_key`, see XLogRecordAssemble.
+    fn heap_record(
+        rmid: u8,
+        info: u8,
+        xid: u32,
+        image: Option<&[u8]>,
+        block_data: &[u8],
+        main_data: &[u8],
+    ) -> NeonWalRecord {
+        let key = heap_key();
+        let mut fork_flags = key.field5;
+        if image.is_some() {
+            fork_flags |= pg_constants::BKPBLOCK_HAS_IMAGE;
+        }
+        if !block_data.is_empty() {
+            fork_flags |= pg_constants::BKPBLOCK_HAS_DATA;
+        }
+        if rmid == pg_constants::RM_HEAP_ID && info & pg_constants::XLOG_HEAP_INIT_PAGE != 0 {
+            fork_flags |= pg_constants::BKPBLOCK_WILL_INIT;
+        }
+
+        // The hole between pd_lower and pd_upper of the image is left out, like for the
+        // buffers registered with REGBUF_STANDARD.
+        let image = image.map(|image| {
+            let lower = u16::from_le_bytes([image[12], image[13]]) as usize;
+            let upper = u16::from_le_bytes([image[14], image[15]]) as usize;
+            if lower >= pg_constants::SIZE_OF_PAGE_HEADER as usize
+                && lower < upper
+                && upper <= image.len()
+            {
+                (lower, [&image[..lower], &image[upper..]].concat())
+            } else {
+                (0, image.to_vec())
+            }
+        });
+
+        let mut body = BytesMut::new();
+        body.put_u8(0);
+        body.put_u8(fork_flags);
+        body.put_u16_le(block_data.len() as u16);
+        if let Some((hole_offset, image)) = &image {
+            let mut bimg_info = postgres_ffi::v14::bindings::BKPIMAGE_APPLY;
+            if image.len() < BLCKSZ as usize {
+                bimg_info |= pg_constants::BKPIMAGE_HAS_HOLE;
+            }
+            body.put_u16_le(image.len() as u16);
+            body.put_u16_le(*hole_offset as u16);
+            body.put_u8(bimg_info);
+        }
+        body.put_u32_le(key.field2);
+        body.put_u32_le(key.field3);
+        body.put_u32_le(key.field4);
+        body.put_u32_le(key.field6);
+        if !main_data.is_empty() {
+            body.put_u8(pg_constants::XLR_BLOCK_ID_DATA_SHORT);
+            body.put_u8(main_data.len() as u8);
+        }
+        if let Some((_, image)) = &image {
+            body.put_slice(image);
+        }
+        body.put_slice(block_data);
+        body.put_slice(main_data);
+
+        let mut header = XLogRecord {
+            xl_tot_len: (XLOG_SIZE_OF_XLOG_RECORD + body.len()) as u32,
+            xl_xid: xid,
+            xl_prev: 0,
+            xl_info: info,
+            xl_rmid: rmid,
+            __bindgen_padding_0: [0u8; 2usize],
+            xl_crc: 0,
+        };
+        let crc_offs = postgres_ffi::v14::xlog_utils::XLOG_RECORD_CRC_OFFS;
+        let crc = crc32c::crc32c_append(0, &body);
+        header.xl_crc = crc32c::crc32c_append(crc, &header.encode().unwrap()[..crc_offs]);
+
+        let mut rec = BytesMut::new();
+        rec.put_slice(&header.encode().unwrap());
+        rec.put_slice(&body);
+        NeonWalRecord::Postgres {
+            will_init: fork_flags & pg_constants::BKPBLOCK_WILL_INIT != 0 || image.is_some(),
+            rec: rec.freeze(),
+        }
+    }
+
+    /// An insert of a tuple with one attribute, `value`, at `offnum`.
+    fn heap_insert(
+        xid: u32,
+        offnum: u16,
+        init_page: bool,
+        flags: u8,
+        value: &[u8],
+    ) -> NeonWalRecord {
+        let mut main_data = offnum.to_le_bytes().to_vec();
+        main_data.push(flags);
+
+        // xl_heap_header, and the tuple after its header
+        let mut block_data = Vec::new();
+        block_data.extend_from_slice(&1u16.to_le_bytes());
+        let infomask = pg_constants::HEAP_XMAX_INVALID | pg_constants::HEAP_COMBOCID;
+        block_data.extend_from_slice(&infomask.to_le_bytes());
+        block_data.extend_from_slice(&3u32.to_le_bytes());
+        block_data.push(24);
+        block_data.push(0);
+        block_data.extend_from_slice(value);
+
+        let mut info = pg_constants::XLOG_HEAP_INSERT;
+        if init_page {
+            info |= pg_constants::XLOG_HEAP_INIT_PAGE;
+        }
+        heap_record(pg_constants::RM_HEAP_ID, info, xid, None, &block_data, &main_data)
+    }
+
+    /// A delete of the tuple at `offnum`.
+    fn heap_delete(xid: u32, offnum: u16, infobits_set: u8, flags: u8) -> NeonWalRecord {
+        let mut main_data = Vec::new();
+        main_data.extend_from_slice(&xid.to_le_bytes());
+        main_data.extend_from_slice(&offnum.to_le_bytes());
+        main_data.extend_from_slice(&0u16.to_le_bytes());
+        main_data.extend_from_slice(&5u32.to_le_bytes());
+        main_data.push(infobits_set);
+        main_data.push(flags);
+        let info = pg_constants::XLOG_HEAP_DELETE;
+        heap_record(pg_constants::RM_HEAP_ID, info, xid, None, &[], &main_data)
+    }
+
+    /// A HOT update of the tuple at `old_offnum` into one at `new_offnum`, whose attribute is
+    /// `value` between the first `prefix_len` and the last `suffix_len` bytes of the old one.
+    fn heap_hot_update(
+        xid: u32,
+        old_offnum: u16,
+        new_offnum: u16,
+        prefix_len: u16,
+        suffix_len: u16,
+        flags: u8,
+        value: &[u8],
+    ) -> NeonWalRecord {
+        let mut flags = flags;
+        let mut block_data = Vec::new();
+        if prefix_len > 0 {
+            flags |= pg_constants::XLH_UPDATE_PREFIX_FROM_OLD;
+            block_data.extend_from_slice(&prefix_len.to_le_bytes());
+        }
+        if suffix_len > 0 {
+            flags |= pg_constants::XLH_UPDATE_SUFFIX_FROM_OLD;
+            block_data.extend_from_slice(&suffix_len.to_le_bytes());
+        }
+        // xl_heap_header, and the tuple after its header
+        block_data.extend_from_slice(&1u16.to_le_bytes());
+        block_data.extend_from_slice(&pg_constants::HEAP_XMAX_INVALID.to_le_bytes());
+        block_data.extend_from_slice(&4u32.to_le_bytes());
+        block_data.push(24);
+        block_data.push(0);
+        block_data.extend_from_slice(value);
+
+        let mut main_data = Vec::new();
+        main_data.extend_from_slice(&xid.to_le_bytes());
+        main_data.extend_from_slice(&old_offnum.to_le_bytes());
+        main_data.push(pg_constants::XLHL_KEYS_UPDATED);
+        main_data.push(flags);
+        main_data.extend_from_slice(&6u32.to_le_bytes());
+        main_data.extend_from_slice(&0u32.to_le_bytes());
+        main_data.extend_from_slice(&new_offnum.to_le_bytes());
+        let info = pg_constants::XLOG_HEAP_HOT_UPDATE;
+        heap_record(pg_constants::RM_HEAP_ID, info, xid, None, &block_data, &main_data)
+    }
+
+    /// An empty leaf page of a btree index, see _bt_pageinit.
+    fn btree_leaf_page() -> Vec<u8> {
+        let mut page = vec![0u8; BLCKSZ as usize];
+        let special = BLCKSZ - 16;
+        page[12..14].copy_from_slice(&pg_constants::SIZE_OF_PAGE_HEADER.to_le_bytes());
+        page[14..16].copy_from_slice(&special.to_le_bytes());
+        page[16..18].copy_from_slice(&special.to_le_bytes());
+        let pagesize_version = BLCKSZ | pg_constants::PG_PAGE_LAYOUT_VERSION;
+        page[18..20].copy_from_slice(&pagesize_version.to_le_bytes());
+        // BTPageOpaqueData, with btpo_flags = BTP_LEAF
+        page[special as usize + 12] = 1;
+        page
+    }
+
+    /// A btree leaf insert of the index tuple `item` at `offnum`.
+    fn btree_insert(offnum: u16, item: &[u8]) -> NeonWalRecord {
+        let info = pg_constants::XLOG_BTREE_INSERT_LEAF;
+        let main_data = offnum.to_le_bytes();
+        heap_record(pg_constants::RM_BTREE_ID, info, 1000, None, item, &main_data)
+    }
+
+    /// The records at consecutive LSNs.
+    fn lsns(records: Vec<NeonWalRecord>) -> Vec<(Lsn, NeonWalRecord)> {
+        records
+            .into_iter()
+            .enumerate()
+            .map(|(i, rec)| (Lsn(0x0100_0000 + 0x100 * i as u64), rec))
+            .collect()
+    }
+
+    /// Inserts and deletes on a heap page, like those of a chunk being compressed.
+    fn heap_records() -> Vec<(Lsn, NeonWalRecord)> {
+        let records = vec![
+            heap_insert(1000, 1, true, 0, &1u32.to_le_bytes()),
+            heap_insert(1000, 2, false, 0, &2u32.to_le_bytes()),
+            // A compressed value
+            heap_insert(1001, 3, false, 0, &[0xAB; 1999]),
+            heap_delete(
+                1002,
+                2,
+                pg_constants::XLHL_KEYS_UPDATED,
+                pg_constants::XLH_DELETE_ALL_VISIBLE_CLEARED,
+            ),
+            heap_delete(
+                1001,
+                1,
+                pg_constants::XLHL_XMAX_EXCL_LOCK | pg_constants::XLHL_XMAX_KEYSHR_LOCK,
+                pg_constants::XLH_DELETE_IS_SUPER,
+            ),
+            heap_insert(1003, 4, false, pg_constants::XLH_INSERT_ALL_FROZEN_SET, &[7; 13]),
+            heap_delete(1003, 3, 0, pg_constants::XLH_DELETE_IS_PARTITION_MOVE),
+            heap_insert(1004, 5, false, pg_constants::XLH_INSERT_ALL_VISIBLE_CLEARED, &[]),
+        ];
+        lsns(records)
+    }
+
     struct RedoHarness {
         // underscored because unused, except for removal at drop
         _repo_dir: tempfile::TempDir,
@@ -1223,8 +2805,31 @@ mod tests {
 
     impl RedoHarness {
         fn new() -> anyhow::Result<Self> {
+            Self::with_native(false, false, false)
+        }
+
+        /// A harness redoing the heap records it can without the postgres process.
+        fn native_heap() -> anyhow::Result<Self> {
+            Self::with_native(true, false, false)
+        }
+
+        /// A harness redoing all the records it can without the postgres process.
+        fn native() -> anyhow::Result<Self> {
+            Self::with_native(true, true, true)
+        }
+
+        fn with_native(heap: bool, btree: bool, fpi: bool) -> anyhow::Result<Self> {
+            Self::with_conf(|conf| {
+                conf.wal_redo_native_heap = heap;
+                conf.wal_redo_native_btree = btree;
+                conf.wal_redo_native_fpi = fpi;
+            })
+        }
+
+        fn with_conf(f: impl FnOnce(&mut PageServerConf)) -> anyhow::Result<Self> {
             let repo_dir = tempfile::tempdir()?;
-            let conf = PageServerConf::dummy_conf(repo_dir.path().to_path_buf());
+            let mut conf = PageServerConf::dummy_conf(repo_dir.path().to_path_buf());
+            f(&mut conf);
             let conf = Box::leak(Box::new(conf));
             let tenant_id = TenantId::generate();
 
@@ -1235,5 +2840,19 @@ mod tests {
                 manager,
             })
         }
+
+        async fn redo_fixture(&self, fixture: &RedoFixture) -> Bytes {
+            self.manager
+                .request_redo(
+                    fixture.key,
+                    fixture.lsn,
+                    fixture.base_img.clone(),
+                    fixture.records.clone(),
+                    fixture.pg_version,
+                    &mut RedoTimings::default(),
+                )
+                .await
+                .unwrap()
+        }
     }
 }
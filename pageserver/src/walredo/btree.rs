@@ -0,0 +1,87 @@
+//!
+//! Redo of btree leaf inserts without the WAL redo process.
+//!
+//! Every row inserted into an indexed table inserts a tuple into a leaf page of each of its
+//! btree indexes. The leaf inserts that carry no full-page image are applied here, with the same
+//! effects as btree_xlog_insert: the index tuple is added at its offset, and the items after it
+//! move up by one.
+//!
+//! Everything else, inserts into posting lists or internal pages, splits, deletes, vacuum, is
+//! left to the WAL redo process.
+//!
+use bytes::{Buf, Bytes, BytesMut};
+use postgres_ffi::{page_set_lsn, pg_constants, OffsetNumber};
+use tracing::*;
+use utils::lsn::Lsn;
+
+use super::native::{decode_record, page_add_index_item, page_needs_redo};
+use super::WalRedoError;
+use crate::pgdatadir_mapping::key_to_rel_block;
+use crate::repository::Key;
+
+/// Size of xl_btree_insert.
+const SIZE_OF_BTREE_INSERT: usize = 2;
+
+/// A btree leaf insert that can be redone here.
+struct LeafInsert {
+    offnum: OffsetNumber,
+    /// The index tuple.
+    item: Bytes,
+}
+
+impl LeafInsert {
+    /// Decode `rec`, if it is a btree leaf insert that can be redone here on the page of `key`.
+    fn decode(rec: &Bytes, key: Key, pg_version: u32) -> Option<Self> {
+        let (rel, blkno) = key_to_rel_block(key).ok()?;
+        let (decoded, _) = decode_record(rec, rel, blkno, pg_version)?;
+        let [blk] = decoded.blocks.as_slice() else {
+            return None;
+        };
+        let info = decoded.xl_info & pg_constants::XLR_RMGR_INFO_MASK;
+        if decoded.xl_rmid != pg_constants::RM_BTREE_ID
+            || info != pg_constants::XLOG_BTREE_INSERT_LEAF
+            || blk.has_image
+            || !blk.has_data
+        {
+            return None;
+        }
+
+        let mut main_data = decoded.record.slice(decoded.main_data_offset..);
+        if main_data.len() < SIZE_OF_BTREE_INSERT {
+            return None;
+        }
+        let data_offset = blk.data_offset as usize;
+        let data_len = blk.data_len as usize;
+        Some(LeafInsert {
+            offnum: main_data.get_u16_le(),
+            item: decoded.record.slice(data_offset..data_offset + data_len),
+        })
+    }
+}
+
+/// Can the Postgres record `rec` be redone on the page of `key` by [`redo`]?
+pub(super) fn can_redo(key: Key, rec: &Bytes, pg_version: u32) -> bool {
+    LeafInsert::decode(rec, key, pg_version).is_some()
+}
+
+/// Redo the Postgres record `rec`, which ends at `lsn`, on `page` of `key`.
+///
+/// The page is empty if there is no base image.
+pub(super) fn redo(
+    key: Key,
+    page: &mut BytesMut,
+    lsn: Lsn,
+    rec: &Bytes,
+    pg_version: u32,
+) -> Result<(), WalRedoError> {
+    let Some(insert) = LeafInsert::decode(rec, key, pg_version) else {
+        error!("tried to pass unsupported postgres wal record to neon WAL redo");
+        return Err(WalRedoError::InvalidRequest);
+    };
+    if !page_needs_redo(page, lsn)? {
+        return Ok(());
+    }
+    page_add_index_item(page, &insert.item, insert.offnum)?;
+    page_set_lsn(page, lsn);
+    Ok(())
+}
@@ -0,0 +1,119 @@
+//! The CPU time spent by the WAL redo processes of each tenant, counted in
+//! `pageserver_tenant_wal_redo_cpu_seconds_total`.
+//!
+//! The CPU time of the pageserver doesn't include that of its children, and getrusage(2) only
+//! reports the children that were waited for, of all the tenants together. Instead, the user
+//! and system time of each process is read from its `/proc/<pid>/stat`: after a request, at
+//! most once every [`SAMPLE_INTERVAL`], and a last time when the process is killed, before it
+//! is waited for. The time spent since the previous sample is added to the counter of the
+//! tenant.
+//!
+//! Without `/proc`, outside of Linux, nothing is counted.
+
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
+
+use metrics::Counter;
+use nix::unistd::{sysconf, SysconfVar};
+use once_cell::sync::Lazy;
+use tracing::debug;
+use utils::id::TenantId;
+
+use crate::metrics::WAL_REDO_CPU_SECONDS_PER_TENANT;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The clock ticks per second of the times in `/proc/<pid>/stat`.
+static CLOCK_TICKS: Lazy<u64> = Lazy::new(|| match sysconf(SysconfVar::CLK_TCK) {
+    Ok(Some(ticks)) if ticks > 0 => ticks as u64,
+    _ => 100,
+});
+
+/// The CPU time of a WAL redo process, counted for its tenant.
+pub(super) struct CpuTime {
+    pid: u32,
+    /// The CPU time of the process at the last sample.
+    last: Duration,
+    sampled_at: Instant,
+    seconds: Counter,
+}
+
+impl CpuTime {
+    pub(super) fn new(tenant_id: &TenantId, pid: u32) -> Self {
+        CpuTime {
+            pid,
+            last: Duration::ZERO,
+            sampled_at: Instant::now(),
+            seconds: WAL_REDO_CPU_SECONDS_PER_TENANT.with_label_values(&[&tenant_id.to_string()]),
+        }
+    }
+
+    /// Count the CPU time spent since the last sample, if that was long enough ago.
+    pub(super) fn sample_periodically(&mut self) {
+        if self.sampled_at.elapsed() >= SAMPLE_INTERVAL {
+            self.sample();
+        }
+    }
+
+    /// Count the CPU time spent since the last sample. The process must not have been waited
+    /// for, its pid could be another process's by then.
+    pub(super) fn sample(&mut self) {
+        self.sampled_at = Instant::now();
+        match read_cpu_time(self.pid) {
+            Ok(cpu_time) if cpu_time > self.last => {
+                self.seconds.inc_by((cpu_time - self.last).as_secs_f64());
+                self.last = cpu_time;
+            }
+            Ok(_) => {}
+            Err(e) => debug!(
+                pid = self.pid,
+                "failed to read the CPU time of the WAL redo process: {e}"
+            ),
+        }
+    }
+}
+
+/// The CPU time that process `pid` has spent so far.
+fn read_cpu_time(pid: u32) -> io::Result<Duration> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat"))?;
+    parse_cpu_time(&stat, *CLOCK_TICKS).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected /proc/{pid}/stat: {stat}"),
+        )
+    })
+}
+
+/// The sum of the utime and stime fields of a `/proc/<pid>/stat`, see proc(5).
+fn parse_cpu_time(stat: &str, clock_ticks: u64) -> Option<Duration> {
+    // The comm field, the second one, is in parentheses, and may contain anything
+    let (_, fields) = stat.rsplit_once(')')?;
+    // utime and stime are the 14th and 15th fields, the 12th and 13th after comm
+    let mut fields = fields.split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    let ticks = utime + stime;
+    Some(
+        Duration::from_secs(ticks / clock_ticks)
+            + Duration::from_secs(ticks % clock_ticks) / clock_ticks as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let stat = "4242 (postgres) x) S 1 4242 4242 0 -1 4194560 1137 0 0 0 253 47 0 0 20 0 \
+                    1 0 3525 221593600 3011 18446744073709551615 1 1 0 0 0 0 0 4096 0 0 0 0 \
+                    17 3 0 0 0 0 0";
+        assert_eq!(parse_cpu_time(stat, 100), Some(Duration::from_secs(3)));
+        assert_eq!(parse_cpu_time(stat, 64), Some(Duration::from_secs_f64(300.0 / 64.0)));
+        assert_eq!(parse_cpu_time("4242 (postgres) S 1 4242", 100), None);
+
+        #[cfg(target_os = "linux")]
+        assert!(read_cpu_time(std::process::id()).is_ok());
+    }
+}
@@ -0,0 +1,90 @@
+//!
+//! Redo of full-page images without the WAL redo process.
+//!
+//! XLOG_FPI records log pages whole, like those of a new index built by CREATE INDEX, and
+//! XLOG_FPI_FOR_HINT records the first change of a hint bit on a page after a checkpoint. They
+//! carry nothing but the images of their pages, which are restored here, like RestoreBlockImage
+//! does, when they are not compressed.
+//!
+//! The compressed images, and the images in the records of other types, are left to the WAL redo
+//! process.
+//!
+use bytes::{Bytes, BytesMut};
+use postgres_ffi::{bkpimage_is_compressed, page_is_new, page_set_lsn, pg_constants, BLCKSZ};
+use tracing::*;
+use utils::lsn::Lsn;
+
+use super::native::decode_record;
+use super::WalRedoError;
+use crate::pgdatadir_mapping::key_to_rel_block;
+use crate::repository::Key;
+
+/// The image of a page, without its hole of zeros.
+struct PageImage {
+    image: Bytes,
+    hole_offset: usize,
+    hole_length: usize,
+}
+
+impl PageImage {
+    /// Decode `rec`, if it is a full-page image record of the page of `key` that can be restored
+    /// here.
+    fn decode(rec: &Bytes, key: Key, pg_version: u32) -> Option<Self> {
+        let (rel, blkno) = key_to_rel_block(key).ok()?;
+        let (decoded, block) = decode_record(rec, rel, blkno, pg_version)?;
+        let blk = &decoded.blocks[block];
+        let info = decoded.xl_info & pg_constants::XLR_RMGR_INFO_MASK;
+        if decoded.xl_rmid != pg_constants::RM_XLOG_ID
+            || !matches!(info, pg_constants::XLOG_FPI | pg_constants::XLOG_FPI_FOR_HINT)
+            || !blk.apply_image
+            || bkpimage_is_compressed(blk.bimg_info, pg_version).ok()?
+        {
+            return None;
+        }
+
+        let bimg_offset = blk.bimg_offset as usize;
+        let image = decoded
+            .record
+            .slice(bimg_offset..bimg_offset + blk.bimg_len as usize);
+        let hole_offset = blk.hole_offset as usize;
+        let hole_length = blk.hole_length as usize;
+        if hole_offset > image.len() || image.len() + hole_length != BLCKSZ as usize {
+            return None;
+        }
+        Some(PageImage {
+            image,
+            hole_offset,
+            hole_length,
+        })
+    }
+}
+
+/// Can the Postgres record `rec` be redone on the page of `key` by [`redo`]?
+pub(super) fn can_redo(key: Key, rec: &Bytes, pg_version: u32) -> bool {
+    PageImage::decode(rec, key, pg_version).is_some()
+}
+
+/// Redo the Postgres record `rec`, which ends at `lsn`, on `page` of `key`: restore its image.
+pub(super) fn redo(
+    key: Key,
+    page: &mut BytesMut,
+    lsn: Lsn,
+    rec: &Bytes,
+    pg_version: u32,
+) -> Result<(), WalRedoError> {
+    let Some(image) = PageImage::decode(rec, key, pg_version) else {
+        error!("tried to pass unsupported postgres wal record to neon WAL redo");
+        return Err(WalRedoError::InvalidRequest);
+    };
+
+    page.clear();
+    page.extend_from_slice(&image.image[..image.hole_offset]);
+    page.resize(image.hole_offset + image.hole_length, 0);
+    page.extend_from_slice(&image.image[image.hole_offset..]);
+
+    // See XLogReadBufferForRedoExtended, the LSN of an uninitialized page is left alone
+    if !page_is_new(page) {
+        page_set_lsn(page, lsn);
+    }
+    Ok(())
+}
@@ -0,0 +1,414 @@
+//! Entry points of the fuzz targets in `pageserver/fuzz`, behind the `fuzzing` feature.
+//!
+//! The records redone in neon do their own arithmetic on the page buffer, and the requests to
+//! the WAL redo process are encoded by hand. [`redo_neon`] and [`encode_request`] run arbitrary
+//! records through them, and panic if what comes out is off: a page of another size, or a
+//! request that isn't made of the messages of its pages.
+//!
+//! The neon records that the pageserver stores under a key always belong to the page of the
+//! key, which their redo checks. [`redo_neon`] moves the records onto the page it is given, and
+//! leaves out those of another kind of page, so that the fuzzer gets past these checks.
+
+use std::io;
+use std::path::PathBuf;
+
+use bytes::{Buf, Bytes};
+use once_cell::sync::Lazy;
+use pageserver_api::reltag::{RelTag, SlruKind};
+use postgres_ffi::relfile_utils::VISIBILITYMAP_FORKNUM;
+use postgres_ffi::{pg_constants, BlockNumber, BLCKSZ};
+use utils::bin_ser::BeSer;
+use utils::id::TenantId;
+use utils::lsn::Lsn;
+
+use super::{
+    build_redo_request, can_apply_in_neon, BufferTag, PostgresRedoManager, RedoTimings,
+    WalRedoError,
+};
+use crate::config::PageServerConf;
+use crate::pgdatadir_mapping::{rel_block_to_key, slru_block_to_key};
+use crate::repository::Key;
+use crate::walrecord::NeonWalRecord;
+
+/// The size of a serialized [`BufferTag`].
+const TAG_LEN: usize = 4 * 4 + 1;
+
+/// The page that [`redo_neon`] applies the records to.
+#[derive(Debug, Clone, Copy)]
+pub enum FuzzPage {
+    /// A block of a relation.
+    Rel { rel: RelTag, blkno: BlockNumber },
+    /// A page of an SLRU, by its number in the SLRU.
+    Slru { kind: SlruKind, pageno: u32 },
+}
+
+static MANAGER: Lazy<PostgresRedoManager> = Lazy::new(|| {
+    // Nothing is read from the repository, and the postgres process is never launched
+    let conf = PageServerConf::dummy_conf(PathBuf::from("fuzz_repo"));
+    PostgresRedoManager::new(Box::leak(Box::new(conf)), TenantId::generate())
+});
+
+/// Redo `records` in neon over `base_img`, cut or zero-padded to a page, as the records of
+/// `page`. The Postgres records are redone by the `btree`, `fpi` and `heap` modules, and fail
+/// with [`WalRedoError::InvalidRequest`] if none of them can, or if they don't decode.
+///
+/// Panics if the new page isn't a page, or a CLOG page with its commit timestamp.
+pub fn redo_neon(
+    page: FuzzPage,
+    base_img: Option<&[u8]>,
+    records: Vec<(Lsn, NeonWalRecord)>,
+    pg_version: u32,
+) -> Result<Bytes, WalRedoError> {
+    let page = page.clamped();
+    let records = records
+        .into_iter()
+        .filter_map(|(lsn, rec)| Some((lsn, page.rebase(rec)?)))
+        .collect::<Vec<_>>();
+    let Some(&(lsn, _)) = records.last() else {
+        return Err(WalRedoError::InvalidRequest);
+    };
+    let base_img = base_img.map(page_image);
+
+    let mut timings = RedoTimings::default();
+    let new_page =
+        MANAGER.apply_batch_neon(page.key(), lsn, base_img, &records, pg_version, &mut timings)?;
+    let clog = matches!(
+        page,
+        FuzzPage::Slru {
+            kind: SlruKind::Clog,
+            ..
+        }
+    );
+    assert!(
+        new_page.len() == BLCKSZ as usize || (clog && new_page.len() == BLCKSZ as usize + 8),
+        "redo of {page:?} returned {} bytes",
+        new_page.len()
+    );
+    Ok(new_page)
+}
+
+/// Encode the request for `pages` to the WAL redo process, with their base images cut or
+/// zero-padded to a page, and read its messages back.
+///
+/// Panics if the request isn't made of the messages of the pages, one after the other, or if a
+/// neon record gets into it.
+pub fn encode_request(
+    pages: &[(BufferTag, Option<&[u8]>, Vec<(Lsn, NeonWalRecord)>)],
+) -> io::Result<Vec<u8>> {
+    let images = pages
+        .iter()
+        .map(|(_, base_img, _)| base_img.map(page_image))
+        .collect::<Vec<_>>();
+    let pages = pages
+        .iter()
+        .zip(&images)
+        .map(|((tag, _, records), base_img)| (*tag, base_img.as_ref(), &records[..]))
+        .collect::<Vec<_>>();
+    let neon_records = pages
+        .iter()
+        .flat_map(|(_, _, records)| records.iter())
+        .any(|(_, rec)| can_apply_in_neon(rec));
+
+    let request = match build_redo_request(&pages) {
+        Ok(request) => request,
+        Err(e) => {
+            assert!(neon_records, "request without neon records failed: {e}");
+            return Err(e);
+        }
+    };
+    assert!(!neon_records, "request with neon records was encoded");
+
+    let mut buf = Bytes::from(request.clone());
+    for (tag, base_img, records) in pages {
+        let tag_bytes = tag.ser().unwrap();
+        assert_eq!(read_message(&mut buf, b'B', TAG_LEN), tag_bytes);
+        if let Some(img) = base_img {
+            let mut payload = read_message(&mut buf, b'P', TAG_LEN + BLCKSZ as usize);
+            assert_eq!(payload.split_to(TAG_LEN), tag_bytes);
+            assert_eq!(payload, img);
+        }
+        for (lsn, rec) in records {
+            let NeonWalRecord::Postgres { rec, .. } = rec else {
+                unreachable!("checked above");
+            };
+            let mut payload = read_message(&mut buf, b'A', 8 + rec.len());
+            assert_eq!(payload.get_u64(), lsn.0);
+            assert_eq!(payload, rec);
+        }
+        assert_eq!(read_message(&mut buf, b'G', TAG_LEN), tag_bytes);
+    }
+    assert!(buf.is_empty(), "{} bytes after the last message", buf.len());
+    Ok(request)
+}
+
+/// Take a message of type `msgtype`, with a payload of `payload_len` bytes, off `buf`, and
+/// return its payload.
+fn read_message(buf: &mut Bytes, msgtype: u8, payload_len: usize) -> Bytes {
+    let msg = msgtype as char;
+    assert!(buf.len() >= 5, "message {msg} cut short");
+    assert_eq!(buf.get_u8() as char, msg);
+    assert_eq!(buf.get_u32() as usize, 4 + payload_len, "length of message {msg}");
+    assert!(buf.len() >= payload_len, "payload of message {msg} cut short");
+    buf.split_to(payload_len)
+}
+
+/// `bytes` cut or zero-padded to a page.
+fn page_image(bytes: &[u8]) -> Bytes {
+    let mut page = bytes[..bytes.len().min(BLCKSZ as usize)].to_vec();
+    page.resize(BLCKSZ as usize, 0);
+    Bytes::from(page)
+}
+
+/// The entries of an SLRU page of `kind`.
+fn entries_per_page(kind: SlruKind) -> u32 {
+    match kind {
+        SlruKind::Clog => pg_constants::CLOG_XACTS_PER_PAGE,
+        SlruKind::MultiXactMembers => pg_constants::MULTIXACT_MEMBERS_PER_PAGE as u32,
+        SlruKind::MultiXactOffsets => pg_constants::MULTIXACT_OFFSETS_PER_PAGE as u32,
+    }
+}
+
+impl FuzzPage {
+    /// The page, with the number of an SLRU page wrapped around to that of a page whose
+    /// entries are all numbered in 32 bits.
+    fn clamped(self) -> Self {
+        match self {
+            FuzzPage::Slru { kind, pageno } => FuzzPage::Slru {
+                kind,
+                pageno: pageno % (u32::MAX / entries_per_page(kind)),
+            },
+            rel => rel,
+        }
+    }
+
+    fn key(&self) -> Key {
+        match *self {
+            FuzzPage::Rel { rel, blkno } => rel_block_to_key(rel, blkno),
+            FuzzPage::Slru { kind, pageno } => slru_block_to_key(
+                kind,
+                pageno / pg_constants::SLRU_PAGES_PER_SEGMENT,
+                pageno % pg_constants::SLRU_PAGES_PER_SEGMENT,
+            ),
+        }
+    }
+
+    /// The entry `n` of the page, wrapped around, for an SLRU page.
+    fn entry(&self, n: u32) -> u32 {
+        let FuzzPage::Slru { kind, pageno } = *self else {
+            unreachable!("entry of a relation block");
+        };
+        let per_page = entries_per_page(kind);
+        pageno * per_page + n % per_page
+    }
+
+    /// `rec` moved onto this page, or `None` if it is for another kind of page. The Postgres
+    /// records are left as they are.
+    fn rebase(&self, rec: NeonWalRecord) -> Option<NeonWalRecord> {
+        let rec = match (*self, rec) {
+            (_, rec @ NeonWalRecord::Postgres { .. }) => rec,
+            (
+                FuzzPage::Rel { rel, blkno },
+                NeonWalRecord::ClearVisibilityMapFlags {
+                    new_heap_blkno,
+                    old_heap_blkno,
+                    flags,
+                },
+            ) if rel.forknum == VISIBILITYMAP_FORKNUM => {
+                // The heap block is left out if its VM page can't exist
+                let per_page = pg_constants::HEAPBLOCKS_PER_PAGE;
+                let on_page = |heap_blkno: u32| {
+                    blkno
+                        .checked_mul(per_page)?
+                        .checked_add(heap_blkno % per_page)
+                };
+                NeonWalRecord::ClearVisibilityMapFlags {
+                    new_heap_blkno: new_heap_blkno.and_then(on_page),
+                    old_heap_blkno: old_heap_blkno.and_then(on_page),
+                    flags,
+                }
+            }
+            (
+                FuzzPage::Slru {
+                    kind: SlruKind::Clog,
+                    ..
+                },
+                NeonWalRecord::ClogSetCommitted { xids, timestamp },
+            ) => NeonWalRecord::ClogSetCommitted {
+                xids: xids.into_iter().map(|xid| self.entry(xid)).collect(),
+                timestamp,
+            },
+            (
+                FuzzPage::Slru {
+                    kind: SlruKind::Clog,
+                    ..
+                },
+                NeonWalRecord::ClogSetAborted { xids },
+            ) => NeonWalRecord::ClogSetAborted {
+                xids: xids.into_iter().map(|xid| self.entry(xid)).collect(),
+            },
+            (
+                FuzzPage::Slru {
+                    kind: SlruKind::MultiXactOffsets,
+                    ..
+                },
+                NeonWalRecord::MultixactOffsetCreate { mid, moff },
+            ) => NeonWalRecord::MultixactOffsetCreate {
+                mid: self.entry(mid),
+                moff,
+            },
+            (
+                FuzzPage::Slru {
+                    kind: SlruKind::MultiXactMembers,
+                    ..
+                },
+                NeonWalRecord::MultixactMembersCreate { moff, mut members },
+            ) => {
+                // Like the records that the WAL ingest splits by page
+                let moff = self.entry(moff);
+                let per_page = entries_per_page(SlruKind::MultiXactMembers);
+                members.truncate((per_page - moff % per_page) as usize);
+                NeonWalRecord::MultixactMembersCreate { moff, members }
+            }
+            _ => return None,
+        };
+        Some(rec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redo_neon_moves_records_onto_page() {
+        // The XIDs of other CLOG pages are set on the page given
+        let page = FuzzPage::Slru {
+            kind: SlruKind::Clog,
+            pageno: 3,
+        };
+        let records = vec![
+            (
+                Lsn(0x10),
+                NeonWalRecord::ClogSetAborted {
+                    xids: vec![1, u32::MAX],
+                },
+            ),
+            (
+                Lsn(0x20),
+                NeonWalRecord::MultixactOffsetCreate { mid: 1, moff: 1 },
+            ),
+        ];
+        let new_page = redo_neon(page, Some(&[]), records, 14).unwrap();
+        assert_eq!(new_page.len(), BLCKSZ as usize);
+        assert_eq!(new_page[0], pg_constants::TRANSACTION_STATUS_ABORTED << 2);
+        assert_eq!(new_page[8191], pg_constants::TRANSACTION_STATUS_ABORTED << 6);
+
+        // A multixact record has nothing to do on a CLOG page
+        let records = vec![(
+            Lsn(0x10),
+            NeonWalRecord::MultixactOffsetCreate { mid: 1, moff: 1 },
+        )];
+        assert!(matches!(
+            redo_neon(page, Some(&[]), records, 14),
+            Err(WalRedoError::InvalidRequest)
+        ));
+    }
+
+    #[test]
+    fn encode_request_reads_back() {
+        let tag = BufferTag {
+            rel: RelTag {
+                forknum: 0,
+                spcnode: 1663,
+                dbnode: 16384,
+                relnode: 16385,
+            },
+            blknum: 7,
+        };
+        let records = vec![
+            (
+                Lsn(0x10),
+                NeonWalRecord::Postgres {
+                    will_init: false,
+                    rec: Bytes::from_static(b"record"),
+                },
+            ),
+            (
+                Lsn(0x20),
+                NeonWalRecord::Postgres {
+                    will_init: false,
+                    rec: Bytes::new(),
+                },
+            ),
+        ];
+        let request = encode_request(&[
+            (tag, Some(&b"short"[..]), records.clone()),
+            (tag, None, records),
+        ])
+        .unwrap();
+        let messages = (5 + TAG_LEN) * 2 + 5 + TAG_LEN + BLCKSZ as usize + (5 + 8) * 2 + 6;
+        assert_eq!(request.len(), messages * 2 - (5 + TAG_LEN + BLCKSZ as usize));
+
+        let neon = vec![(Lsn(0x10), NeonWalRecord::ClogSetAborted { xids: vec![1] })];
+        assert!(encode_request(&[(tag, None, neon)]).is_err());
+    }
+
+    #[test]
+    fn redo_neon_malformed_record() {
+        use bytes::{BufMut, BytesMut};
+        use postgres_ffi::{XLogRecord, XLOG_SIZE_OF_XLOG_RECORD};
+
+        let page = FuzzPage::Rel {
+            rel: RelTag {
+                forknum: 0,
+                spcnode: 1663,
+                dbnode: 16384,
+                relnode: 16385,
+            },
+            blkno: 0,
+        };
+        let record = |xl_tot_len: usize, body: &[u8]| {
+            let header = XLogRecord {
+                xl_tot_len: xl_tot_len as u32,
+                xl_xid: 0,
+                xl_prev: 0,
+                xl_info: pg_constants::XLOG_HEAP_INSERT,
+                xl_rmid: pg_constants::RM_HEAP_ID,
+                __bindgen_padding_0: [0u8; 2usize],
+                xl_crc: 0,
+            };
+            let mut rec = BytesMut::new();
+            rec.put_slice(&header.encode().unwrap());
+            rec.put_slice(body);
+            vec![(
+                Lsn(0x10),
+                NeonWalRecord::Postgres {
+                    will_init: false,
+                    rec: rec.freeze(),
+                },
+            )]
+        };
+        let len = |body: &[u8]| XLOG_SIZE_OF_XLOG_RECORD + body.len();
+
+        // Each of these used to panic in the decoding of the record: a length shorter than
+        // the header, a block header cut short, a block image longer than a page, and block
+        // data without the flag for it
+        let truncated = [0, pg_constants::BKPBLOCK_HAS_DATA];
+        let image = [0, pg_constants::BKPBLOCK_HAS_IMAGE, 0, 0, 0xff, 0xff, 0, 0, 0];
+        let data = [0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff];
+        let inputs = [
+            record(4, &[]),
+            record(len(&truncated), &truncated),
+            record(len(&image), &image),
+            record(len(&data), &data),
+        ];
+        for records in inputs {
+            for pg_version in [14, 15] {
+                assert!(matches!(
+                    redo_neon(page, Some(&[]), records.clone(), pg_version),
+                    Err(WalRedoError::InvalidRequest)
+                ));
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! The requests that are using the WAL redo manager of a tenant, so that its processes are only
+//! stopped after them when the tenant is detached, see [`super::PostgresRedoManager::shutdown`].
+//!
+//! A process killed under a request fails it with a broken pipe, logged as a failure of the
+//! process. Closing the gate fails the next requests with [`WalRedoError::ShuttingDown`]
+//! instead, without touching the processes, and waits for the requests that got in before.
+
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use super::WalRedoError;
+
+#[derive(Default)]
+pub(super) struct RequestGate {
+    state: Mutex<GateState>,
+    /// Notified when the last request in flight leaves.
+    drained: Condvar,
+}
+
+#[derive(Default)]
+struct GateState {
+    closed: bool,
+    in_flight: usize,
+}
+
+/// A request in flight, until dropped.
+pub(super) struct Admitted<'a> {
+    gate: &'a RequestGate,
+}
+
+impl RequestGate {
+    /// Let a request in, unless the gate is closed.
+    pub(super) fn enter(&self) -> Result<Admitted<'_>, WalRedoError> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(WalRedoError::ShuttingDown);
+        }
+        state.in_flight += 1;
+        Ok(Admitted { gate: self })
+    }
+
+    /// Let no more requests in, and wait up to `timeout` for those in flight. Returns the number
+    /// of requests still in flight after that.
+    pub(super) fn close(&self, timeout: Duration) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        let (state, _) = self
+            .drained
+            .wait_timeout_while(state, timeout, |state| state.in_flight > 0)
+            .unwrap();
+        state.in_flight
+    }
+}
+
+impl Drop for Admitted<'_> {
+    fn drop(&mut self) {
+        let mut state = self.gate.state.lock().unwrap();
+        state.in_flight -= 1;
+        if state.in_flight == 0 {
+            self.gate.drained.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn close_waits_for_requests_in_flight() {
+        let gate = RequestGate::default();
+        assert_eq!(gate.close(Duration::ZERO), 0);
+        assert!(matches!(gate.enter(), Err(WalRedoError::ShuttingDown)));
+
+        let gate = RequestGate::default();
+        let first = gate.enter().unwrap();
+        let second = gate.enter().unwrap();
+        drop(first);
+        assert_eq!(gate.close(Duration::from_millis(10)), 1);
+        assert!(gate.enter().is_err());
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                drop(second);
+            });
+            assert_eq!(gate.close(Duration::from_secs(60)), 0);
+        });
+    }
+}
@@ -0,0 +1,418 @@
+//!
+//! Redo of heap inserts, deletes and HOT updates without the WAL redo process.
+//!
+//! Compressing a TimescaleDB chunk inserts the compressed rows into the compressed chunk, and
+//! their large values into its toast table, and decompressing it deletes them again. The pages
+//! of these relations are rebuilt from long runs of heap inserts and deletes, and passing them
+//! to the WAL redo process costs a lot more than applying them. The records that only touch the
+//! page being reconstructed and carry no full-page image are applied here instead, with the same
+//! effects as heap_xlog_insert, heap_xlog_delete and heap_xlog_update of our Postgres, whose
+//! records carry the t_cid of the tuple. HOT updates, the most common updates, always keep the
+//! new tuple on the page of the old one.
+//!
+//! Everything else, multi-inserts, other updates, pruning, full-page images, is left to the WAL
+//! redo process.
+//!
+use byteorder::{ByteOrder, LittleEndian};
+use bytes::{Buf, Bytes, BytesMut};
+use postgres_ffi::pg_constants;
+use postgres_ffi::{page_set_lsn, BlockNumber, OffsetNumber, TransactionId, BLCKSZ};
+use tracing::*;
+use utils::lsn::Lsn;
+
+use super::native::{
+    decode_record, page_add_heap_item, page_get_flags, page_get_item_id,
+    page_get_max_offset_number, page_init, page_needs_redo, page_set_flags, page_set_prunable,
+};
+use super::WalRedoError;
+use crate::pgdatadir_mapping::key_to_rel_block;
+use crate::repository::Key;
+use crate::walrecord::{XlHeapDelete, XlHeapInsert, XlHeapUpdate};
+
+/// Size of xl_heap_insert.
+const SIZE_OF_HEAP_INSERT: usize = 3;
+/// Size of xl_heap_delete, with its t_cid.
+const SIZE_OF_HEAP_DELETE: usize = 14;
+/// Size of xl_heap_update, with its t_cid.
+const SIZE_OF_HEAP_UPDATE: usize = 18;
+/// Size of xl_heap_header, with its t_cid.
+const SIZE_OF_HEAP_HEADER: usize = 9;
+
+// Offsets of the fields of HeapTupleHeaderData
+const T_XMIN: usize = 0;
+const T_XMAX: usize = 4;
+const T_CID: usize = 8;
+const T_CTID: usize = 12;
+const T_INFOMASK2: usize = 18;
+const T_INFOMASK: usize = 20;
+const T_HOFF: usize = 22;
+
+// See MovedPartitionsBlockNumber and MovedPartitionsOffsetNumber in htup_details.h
+const MOVED_PARTITIONS_BLOCK_NUMBER: BlockNumber = 0xFFFFFFFF;
+const MOVED_PARTITIONS_OFFSET_NUMBER: OffsetNumber = 0xFFFD;
+
+/// The fixed part of the tuple header that is in the WAL record of an insert.
+#[derive(Debug)]
+struct XlHeapHeader {
+    t_infomask2: u16,
+    t_infomask: u16,
+    t_cid: u32,
+    t_hoff: u8,
+}
+
+impl XlHeapHeader {
+    fn decode(buf: &mut Bytes) -> XlHeapHeader {
+        XlHeapHeader {
+            t_infomask2: buf.get_u16_le(),
+            t_infomask: buf.get_u16_le(),
+            t_cid: buf.get_u32_le(),
+            t_hoff: buf.get_u8(),
+        }
+    }
+}
+
+enum HeapOp {
+    Insert {
+        init_page: bool,
+        xlrec: XlHeapInsert,
+        xlhdr: XlHeapHeader,
+        /// The tuple, after its header.
+        data: Bytes,
+    },
+    Delete(XlHeapDelete),
+    HotUpdate(HotUpdate),
+}
+
+struct HotUpdate {
+    xlrec: XlHeapUpdate,
+    /// The lengths of the prefix and the suffix of the old tuple that the new one shares.
+    prefix_len: usize,
+    suffix_len: usize,
+    xlhdr: XlHeapHeader,
+    /// The rest of the new tuple, after its header.
+    data: Bytes,
+}
+
+/// A heap record that can be redone here.
+struct HeapRecord {
+    xid: TransactionId,
+    op: HeapOp,
+}
+
+impl HeapRecord {
+    /// Decode `rec`, if it is a heap record that can be redone here on the page of `key`.
+    fn decode(rec: &Bytes, key: Key, pg_version: u32) -> Option<Self> {
+        let (rel, blkno) = key_to_rel_block(key).ok()?;
+        let (decoded, _) = decode_record(rec, rel, blkno, pg_version)?;
+        let [blk] = decoded.blocks.as_slice() else {
+            return None;
+        };
+        if blk.has_image {
+            return None;
+        }
+
+        let mut main_data = decoded.record.slice(decoded.main_data_offset..);
+        let init_page = decoded.xl_info & pg_constants::XLOG_HEAP_INIT_PAGE != 0;
+        let op = match (decoded.xl_rmid, decoded.xl_info & pg_constants::XLOG_HEAP_OPMASK) {
+            (pg_constants::RM_HEAP_ID, pg_constants::XLOG_HEAP_INSERT) => {
+                let data_len = blk.data_len as usize;
+                if main_data.len() != SIZE_OF_HEAP_INSERT
+                    || !blk.has_data
+                    || data_len <= SIZE_OF_HEAP_HEADER
+                {
+                    return None;
+                }
+                let data_offset = blk.data_offset as usize;
+                let mut data = decoded.record.slice(data_offset..data_offset + data_len);
+                HeapOp::Insert {
+                    init_page,
+                    xlrec: XlHeapInsert::decode(&mut main_data),
+                    xlhdr: XlHeapHeader::decode(&mut data),
+                    data,
+                }
+            }
+            // The old tuple of logical decoding may follow xl_heap_delete, redo ignores it
+            (pg_constants::RM_HEAP_ID, pg_constants::XLOG_HEAP_DELETE) => {
+                if init_page || main_data.len() < SIZE_OF_HEAP_DELETE {
+                    return None;
+                }
+                HeapOp::Delete(XlHeapDelete::decode(&mut main_data))
+            }
+            // The old tuple of logical decoding may follow xl_heap_update, redo ignores it
+            (pg_constants::RM_HEAP_ID, pg_constants::XLOG_HEAP_HOT_UPDATE) => {
+                if init_page || main_data.len() < SIZE_OF_HEAP_UPDATE || !blk.has_data {
+                    return None;
+                }
+                let xlrec = XlHeapUpdate::decode(&mut main_data);
+                let data_offset = blk.data_offset as usize;
+                let data_len = blk.data_len as usize;
+                let mut data = decoded.record.slice(data_offset..data_offset + data_len);
+                let mut get_len = |flag: u8| match xlrec.flags & flag {
+                    0 => Some(0),
+                    _ if data.len() >= 2 => Some(data.get_u16_le() as usize),
+                    _ => None,
+                };
+                let prefix_len = get_len(pg_constants::XLH_UPDATE_PREFIX_FROM_OLD)?;
+                let suffix_len = get_len(pg_constants::XLH_UPDATE_SUFFIX_FROM_OLD)?;
+                if data.len() < SIZE_OF_HEAP_HEADER {
+                    return None;
+                }
+                HeapOp::HotUpdate(HotUpdate {
+                    xlrec,
+                    prefix_len,
+                    suffix_len,
+                    xlhdr: XlHeapHeader::decode(&mut data),
+                    data,
+                })
+            }
+            _ => return None,
+        };
+        Some(HeapRecord {
+            xid: decoded.xl_xid,
+            op,
+        })
+    }
+}
+
+/// Can the Postgres record `rec` be redone on the page of `key` by [`redo`]?
+pub(super) fn can_redo(key: Key, rec: &Bytes, pg_version: u32) -> bool {
+    HeapRecord::decode(rec, key, pg_version).is_some()
+}
+
+/// Redo the Postgres record `rec`, which ends at `lsn`, on `page` of `key`.
+///
+/// The page is empty if there is no base image.
+pub(super) fn redo(
+    key: Key,
+    page: &mut BytesMut,
+    lsn: Lsn,
+    rec: &Bytes,
+    pg_version: u32,
+) -> Result<(), WalRedoError> {
+    let (_, blkno) = key_to_rel_block(key).or(Err(WalRedoError::InvalidRecord))?;
+    let Some(record) = HeapRecord::decode(rec, key, pg_version) else {
+        error!("tried to pass unsupported postgres wal record to neon WAL redo");
+        return Err(WalRedoError::InvalidRequest);
+    };
+
+    if matches!(record.op, HeapOp::Insert { init_page: true, .. }) {
+        page.clear();
+        page.resize(BLCKSZ as usize, 0);
+        page_init(page);
+    } else if !page_needs_redo(page, lsn)? {
+        return Ok(());
+    }
+
+    match record.op {
+        HeapOp::Insert {
+            xlrec, xlhdr, data, ..
+        } => redo_insert(page, blkno, record.xid, &xlrec, &xlhdr, &data)?,
+        HeapOp::Delete(xlrec) => redo_delete(page, blkno, record.xid, &xlrec)?,
+        HeapOp::HotUpdate(update) => redo_hot_update(page, blkno, record.xid, &update)?,
+    }
+    page_set_lsn(page, lsn);
+    Ok(())
+}
+
+/// See heap_xlog_insert
+fn redo_insert(
+    page: &mut [u8],
+    blkno: BlockNumber,
+    xid: TransactionId,
+    xlrec: &XlHeapInsert,
+    xlhdr: &XlHeapHeader,
+    data: &[u8],
+) -> Result<(), WalRedoError> {
+    if page_get_max_offset_number(page)? + 1 < xlrec.offnum {
+        error!("invalid max offset number");
+        return Err(WalRedoError::InvalidRecord);
+    }
+
+    let mut tuple = vec![0u8; pg_constants::SIZEOF_HEAP_TUPLE_HEADER + data.len()];
+    LittleEndian::write_u32(&mut tuple[T_XMIN..], xid);
+    LittleEndian::write_u32(&mut tuple[T_CID..], xlhdr.t_cid);
+    set_item_pointer(&mut tuple[T_CTID..], blkno, xlrec.offnum);
+    LittleEndian::write_u16(&mut tuple[T_INFOMASK2..], xlhdr.t_infomask2);
+    LittleEndian::write_u16(
+        &mut tuple[T_INFOMASK..],
+        xlhdr.t_infomask & !pg_constants::HEAP_COMBOCID,
+    );
+    tuple[T_HOFF] = xlhdr.t_hoff;
+    tuple[pg_constants::SIZEOF_HEAP_TUPLE_HEADER..].copy_from_slice(data);
+    page_add_heap_item(page, &tuple, xlrec.offnum)?;
+
+    if xlrec.flags & pg_constants::XLH_INSERT_ALL_VISIBLE_CLEARED != 0 {
+        page_set_flags(page, page_get_flags(page) & !pg_constants::PD_ALL_VISIBLE);
+    }
+    // XLH_INSERT_ALL_FROZEN_SET implies that all tuples are visible
+    if xlrec.flags & pg_constants::XLH_INSERT_ALL_FROZEN_SET != 0 {
+        page_set_flags(page, page_get_flags(page) | pg_constants::PD_ALL_VISIBLE);
+    }
+    Ok(())
+}
+
+/// See heap_xlog_delete
+fn redo_delete(
+    page: &mut [u8],
+    blkno: BlockNumber,
+    xid: TransactionId,
+    xlrec: &XlHeapDelete,
+) -> Result<(), WalRedoError> {
+    let offnum = xlrec.offnum;
+    let tuple = page_get_tuple(page, offnum)?;
+
+    let mut infomask = LittleEndian::read_u16(&tuple[T_INFOMASK..]);
+    let mut infomask2 = LittleEndian::read_u16(&tuple[T_INFOMASK2..]);
+    infomask &= !(pg_constants::HEAP_XMAX_BITS | pg_constants::HEAP_MOVED);
+    infomask2 &= !(pg_constants::HEAP_KEYS_UPDATED | pg_constants::HEAP_HOT_UPDATED);
+    fix_infomask_from_infobits(xlrec.infobits_set, &mut infomask, &mut infomask2);
+    if xlrec.flags & pg_constants::XLH_DELETE_IS_SUPER == 0 {
+        LittleEndian::write_u32(&mut tuple[T_XMAX..], xlrec.xmax);
+    } else {
+        LittleEndian::write_u32(&mut tuple[T_XMIN..], 0);
+    }
+    LittleEndian::write_u32(&mut tuple[T_CID..], xlrec.t_cid);
+    infomask &= !pg_constants::HEAP_COMBOCID;
+    LittleEndian::write_u16(&mut tuple[T_INFOMASK..], infomask);
+    LittleEndian::write_u16(&mut tuple[T_INFOMASK2..], infomask2);
+
+    // Make sure t_ctid is set correctly
+    if xlrec.flags & pg_constants::XLH_DELETE_IS_PARTITION_MOVE != 0 {
+        set_item_pointer(
+            &mut tuple[T_CTID..],
+            MOVED_PARTITIONS_BLOCK_NUMBER,
+            MOVED_PARTITIONS_OFFSET_NUMBER,
+        );
+    } else {
+        set_item_pointer(&mut tuple[T_CTID..], blkno, offnum);
+    }
+
+    page_set_prunable(page, xid);
+    if xlrec.flags & pg_constants::XLH_DELETE_ALL_VISIBLE_CLEARED != 0 {
+        page_set_flags(page, page_get_flags(page) & !pg_constants::PD_ALL_VISIBLE);
+    }
+    Ok(())
+}
+
+/// See heap_xlog_update, for a HOT update: the old and the new tuple are on the same page.
+fn redo_hot_update(
+    page: &mut [u8],
+    blkno: BlockNumber,
+    xid: TransactionId,
+    update: &HotUpdate,
+) -> Result<(), WalRedoError> {
+    let HotUpdate {
+        xlrec, xlhdr, data, ..
+    } = update;
+    let (prefix_len, suffix_len) = (update.prefix_len, update.suffix_len);
+    let old_tuple = page_get_tuple(page, xlrec.old_offnum)?;
+
+    let mut infomask = LittleEndian::read_u16(&old_tuple[T_INFOMASK..]);
+    let mut infomask2 = LittleEndian::read_u16(&old_tuple[T_INFOMASK2..]);
+    infomask &= !(pg_constants::HEAP_XMAX_BITS | pg_constants::HEAP_MOVED);
+    infomask2 &= !pg_constants::HEAP_KEYS_UPDATED;
+    infomask2 |= pg_constants::HEAP_HOT_UPDATED;
+    fix_infomask_from_infobits(xlrec.old_infobits_set, &mut infomask, &mut infomask2);
+    LittleEndian::write_u32(&mut old_tuple[T_XMAX..], xlrec.old_xmax);
+    LittleEndian::write_u32(&mut old_tuple[T_CID..], xlrec.t_cid);
+    infomask &= !pg_constants::HEAP_COMBOCID;
+    LittleEndian::write_u16(&mut old_tuple[T_INFOMASK..], infomask);
+    LittleEndian::write_u16(&mut old_tuple[T_INFOMASK2..], infomask2);
+    // Set forward chain link in t_ctid
+    set_item_pointer(&mut old_tuple[T_CTID..], blkno, xlrec.new_offnum);
+
+    // Reconstruct the new tuple using the prefix and/or suffix from the old tuple, and the
+    // data stored in the WAL record.
+    let old_data = &old_tuple[old_tuple[T_HOFF] as usize..];
+    let bitmap_len =
+        (xlhdr.t_hoff as usize).saturating_sub(pg_constants::SIZEOF_HEAP_TUPLE_HEADER);
+    if prefix_len + suffix_len > old_data.len() || (prefix_len > 0 && bitmap_len > data.len()) {
+        error!("invalid prefix or suffix of the old tuple");
+        return Err(WalRedoError::InvalidRecord);
+    }
+    let new_len = pg_constants::SIZEOF_HEAP_TUPLE_HEADER + data.len() + prefix_len + suffix_len;
+    let mut tuple = vec![0u8; pg_constants::SIZEOF_HEAP_TUPLE_HEADER];
+    tuple.reserve(new_len - tuple.len());
+    if prefix_len > 0 {
+        // The bitmap [+ padding] [+ oid] comes first in the record
+        tuple.extend_from_slice(&data[..bitmap_len]);
+        tuple.extend_from_slice(&old_data[..prefix_len]);
+        tuple.extend_from_slice(&data[bitmap_len..]);
+    } else {
+        tuple.extend_from_slice(&data[..]);
+    }
+    tuple.extend_from_slice(&old_data[old_data.len() - suffix_len..]);
+
+    LittleEndian::write_u32(&mut tuple[T_XMIN..], xid);
+    LittleEndian::write_u32(&mut tuple[T_XMAX..], xlrec.new_xmax);
+    LittleEndian::write_u32(&mut tuple[T_CID..], xlhdr.t_cid);
+    set_item_pointer(&mut tuple[T_CTID..], blkno, xlrec.new_offnum);
+    LittleEndian::write_u16(&mut tuple[T_INFOMASK2..], xlhdr.t_infomask2);
+    LittleEndian::write_u16(
+        &mut tuple[T_INFOMASK..],
+        xlhdr.t_infomask & !pg_constants::HEAP_COMBOCID,
+    );
+    tuple[T_HOFF] = xlhdr.t_hoff;
+    page_add_heap_item(page, &tuple, xlrec.new_offnum)?;
+
+    page_set_prunable(page, xid);
+    let all_visible_cleared = pg_constants::XLH_UPDATE_OLD_ALL_VISIBLE_CLEARED
+        | pg_constants::XLH_UPDATE_NEW_ALL_VISIBLE_CLEARED;
+    if xlrec.flags & all_visible_cleared != 0 {
+        page_set_flags(page, page_get_flags(page) & !pg_constants::PD_ALL_VISIBLE);
+    }
+    Ok(())
+}
+
+/// The normal tuple at `offnum`.
+fn page_get_tuple(page: &mut [u8], offnum: OffsetNumber) -> Result<&mut [u8], WalRedoError> {
+    let (lp_off, lp_flags, lp_len) = page_get_item_id(page, offnum)?;
+    if lp_flags != pg_constants::LP_NORMAL
+        || lp_len < pg_constants::SIZEOF_HEAP_TUPLE_HEADER
+        || lp_off + lp_len > BLCKSZ as usize
+    {
+        error!("invalid lp");
+        return Err(WalRedoError::InvalidRecord);
+    }
+    let tuple = &mut page[lp_off..lp_off + lp_len];
+    if (tuple[T_HOFF] as usize) < pg_constants::SIZEOF_HEAP_TUPLE_HEADER
+        || tuple[T_HOFF] as usize > lp_len
+    {
+        error!("invalid tuple header");
+        return Err(WalRedoError::InvalidRecord);
+    }
+    Ok(tuple)
+}
+
+/// See fix_infomask_from_infobits in heapam.c
+fn fix_infomask_from_infobits(infobits: u8, infomask: &mut u16, infomask2: &mut u16) {
+    *infomask &= !(pg_constants::HEAP_XMAX_IS_MULTI
+        | pg_constants::HEAP_XMAX_LOCK_ONLY
+        | pg_constants::HEAP_XMAX_KEYSHR_LOCK
+        | pg_constants::HEAP_XMAX_EXCL_LOCK);
+    *infomask2 &= !pg_constants::HEAP_KEYS_UPDATED;
+
+    if infobits & pg_constants::XLHL_XMAX_IS_MULTI != 0 {
+        *infomask |= pg_constants::HEAP_XMAX_IS_MULTI;
+    }
+    if infobits & pg_constants::XLHL_XMAX_LOCK_ONLY != 0 {
+        *infomask |= pg_constants::HEAP_XMAX_LOCK_ONLY;
+    }
+    if infobits & pg_constants::XLHL_XMAX_EXCL_LOCK != 0 {
+        *infomask |= pg_constants::HEAP_XMAX_EXCL_LOCK;
+    }
+    // note HEAP_XMAX_SHR_LOCK isn't considered here
+    if infobits & pg_constants::XLHL_XMAX_KEYSHR_LOCK != 0 {
+        *infomask |= pg_constants::HEAP_XMAX_KEYSHR_LOCK;
+    }
+    if infobits & pg_constants::XLHL_KEYS_UPDATED != 0 {
+        *infomask2 |= pg_constants::HEAP_KEYS_UPDATED;
+    }
+}
+
+/// See ItemPointerSet
+fn set_item_pointer(buf: &mut [u8], blkno: BlockNumber, offnum: OffsetNumber) {
+    LittleEndian::write_u16(&mut buf[0..], (blkno >> 16) as u16);
+    LittleEndian::write_u16(&mut buf[2..], blkno as u16);
+    LittleEndian::write_u16(&mut buf[4..], offnum);
+}
@@ -0,0 +1,131 @@
+//! Resource limits of the WAL redo process of a tenant.
+//!
+//! The WAL redo process replays the WAL of the tenant, which may be malicious or pathological:
+//! nothing but the memory of the host bounds how much the process allocates. The tenant config
+//! limits it with rlimits, set when the process is launched:
+//!
+//! - `walredo_memory_limit`, in bytes, limits the address space of the process. An allocation
+//!   over it fails, and the process exits with an "out of memory" error,
+//! - `walredo_cpu_time_limit` limits the CPU time the process spends over its lifetime. Once
+//!   spent, the process is killed with `SIGXCPU`.
+//!
+//! The failed request is retried with a new process once, like any other failure: a process
+//! killed for its CPU time is replaced by one with the whole budget. The requests that still
+//! fail return [`WalRedoError::MemoryLimitExceeded`] or [`WalRedoError::CpuTimeLimitExceeded`],
+//! counted in the `pageserver_wal_redo_limit_violations_total` metric.
+//!
+//! A change of the limits applies to the next process of the tenant, the running one keeps the
+//! limits it was launched with.
+
+use std::os::unix::prelude::{CommandExt, ExitStatusExt};
+use std::process::{Command, ExitStatus};
+use std::time::Duration;
+
+use nix::sys::resource::{setrlimit, Resource};
+use nix::sys::signal::Signal;
+use tracing::warn;
+
+use super::WalRedoError;
+use crate::metrics::WAL_REDO_LIMIT_VIOLATIONS;
+
+/// The limits of the WAL redo process of a tenant, from its config. `None` for no limit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WalRedoLimits {
+    /// In bytes.
+    pub memory: Option<u64>,
+    pub cpu_time: Option<Duration>,
+}
+
+impl WalRedoLimits {
+    /// Which limit made the process fail, if any, given whether it reported being out of memory,
+    /// and how it exited.
+    pub(super) fn exceeded(
+        &self,
+        out_of_memory: bool,
+        exit_status: Option<ExitStatus>,
+    ) -> Option<WalRedoError> {
+        let killed_for_cpu_time = exit_status
+            .and_then(|exit_status| exit_status.signal())
+            .map_or(false, |signal| signal == Signal::SIGXCPU as i32);
+        let (limit, error) = if self.memory.is_some() && out_of_memory {
+            ("memory", WalRedoError::MemoryLimitExceeded)
+        } else if self.cpu_time.is_some() && killed_for_cpu_time {
+            ("cpu_time", WalRedoError::CpuTimeLimitExceeded)
+        } else {
+            return None;
+        };
+        warn!("WAL redo process exceeded its {limit} limit: {self:?}");
+        WAL_REDO_LIMIT_VIOLATIONS.with_label_values(&[limit]).inc();
+        Some(error)
+    }
+}
+
+/// Whether a message of the WAL redo process reports an allocation failure.
+pub(super) fn is_out_of_memory(message: &str) -> bool {
+    message.contains("out of memory")
+}
+
+///
+/// Command with the limits of the WAL redo process
+///
+pub(super) trait SetWalRedoLimits: CommandExt {
+    ///
+    /// Set the rlimits of the child process
+    ///
+    fn set_limits(&mut self, limits: WalRedoLimits) -> &mut Command;
+}
+
+impl SetWalRedoLimits for Command {
+    fn set_limits(&mut self, limits: WalRedoLimits) -> &mut Command {
+        if limits == WalRedoLimits::default() {
+            return self;
+        }
+        unsafe {
+            self.pre_exec(move || {
+                // SAFETY: setrlimit is a system call, async-signal-safe like the rest of the
+                // code executed inside pre_exec must be, see `close_fds`. The limits are
+                // computed before the fork.
+                if let Some(memory) = limits.memory {
+                    setrlimit(Resource::RLIMIT_AS, memory, memory)?;
+                }
+                if let Some(cpu_time) = limits.cpu_time {
+                    // SIGXCPU at the soft limit, SIGKILL a second later if it's ignored
+                    let seconds = cpu_time.as_secs().max(1);
+                    setrlimit(Resource::RLIMIT_CPU, seconds, seconds + 1)?;
+                }
+                Ok(())
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exceeded_limits() {
+        let exit_status = |signal: Signal| Some(ExitStatus::from_raw(signal as i32));
+        let limits = WalRedoLimits {
+            memory: Some(1 << 30),
+            cpu_time: Some(Duration::from_secs(3600)),
+        };
+        assert!(matches!(
+            limits.exceeded(true, None),
+            Some(WalRedoError::MemoryLimitExceeded)
+        ));
+        assert!(matches!(
+            limits.exceeded(false, exit_status(Signal::SIGXCPU)),
+            Some(WalRedoError::CpuTimeLimitExceeded)
+        ));
+        // Killed by us after some other failure
+        assert!(limits.exceeded(false, exit_status(Signal::SIGKILL)).is_none());
+        assert!(limits.exceeded(false, None).is_none());
+
+        // Without limits, the failures are not theirs
+        let no_limits = WalRedoLimits::default();
+        assert!(no_limits.exceeded(true, exit_status(Signal::SIGXCPU)).is_none());
+
+        assert!(is_out_of_memory("ERROR:  out of memory\nDETAIL:  Failed on request of size"));
+    }
+}
@@ -0,0 +1,214 @@
+//!
+//! What the native redo of the `heap`, `btree` and `fpi` records shares: finding the page being
+//! reconstructed in a Postgres record, and the page layout of bufpage.c.
+//!
+use byteorder::{ByteOrder, LittleEndian};
+use bytes::{Bytes, BytesMut};
+use pageserver_api::reltag::RelTag;
+use postgres_ffi::pg_constants;
+use postgres_ffi::{page_get_lsn, page_is_new, transaction_id_precedes};
+use postgres_ffi::{BlockNumber, OffsetNumber, TransactionId, BLCKSZ};
+use tracing::*;
+use utils::lsn::Lsn;
+
+use super::WalRedoError;
+use crate::walrecord::{decode_wal_record, DecodedWALRecord};
+
+/// Size of ItemIdData.
+const SIZE_OF_ITEM_ID: usize = 4;
+
+// Offsets of the fields of PageHeaderData
+const PD_FLAGS: usize = 10;
+const PD_LOWER: usize = 12;
+const PD_UPPER: usize = 14;
+const PD_SPECIAL: usize = 16;
+const PD_PAGESIZE_VERSION: usize = 18;
+const PD_PRUNE_XID: usize = 20;
+
+/// Decode the Postgres record `rec`, and find the block of page `blkno` of `rel` in it.
+///
+/// Returns the decoded record and the index of the block in its blocks.
+pub(super) fn decode_record(
+    rec: &Bytes,
+    rel: RelTag,
+    blkno: BlockNumber,
+    pg_version: u32,
+) -> Option<(DecodedWALRecord, usize)> {
+    if !matches!(pg_version, 14 | 15) {
+        return None;
+    }
+    let mut decoded = DecodedWALRecord::default();
+    decode_wal_record(rec.clone(), &mut decoded, pg_version).ok()?;
+
+    let block = decoded.blocks.iter().position(|blk| {
+        let blk_rel = RelTag {
+            spcnode: blk.rnode_spcnode,
+            dbnode: blk.rnode_dbnode,
+            relnode: blk.rnode_relnode,
+            forknum: blk.forknum,
+        };
+        blk_rel == rel && blk.blkno == blkno
+    })?;
+    Some((decoded, block))
+}
+
+/// Does the record ending at `lsn` need to be redone on `page`?
+///
+/// See XLogReadBufferForRedo, there is nothing to redo on a page that is not initialized, or
+/// that has the record already. The page is empty if there is no base image, it starts zeroed
+/// then, like in the postgres process.
+pub(super) fn page_needs_redo(page: &mut BytesMut, lsn: Lsn) -> Result<bool, WalRedoError> {
+    if page.is_empty() {
+        page.resize(BLCKSZ as usize, 0);
+    }
+    if page.len() != BLCKSZ as usize {
+        error!("invalid page of {} bytes for WAL record", page.len());
+        return Err(WalRedoError::InvalidRecord);
+    }
+    Ok(!page_is_new(page) && lsn > page_get_lsn(page))
+}
+
+/// See PageInit, with no special space
+pub(super) fn page_init(page: &mut [u8]) {
+    page.fill(0);
+    LittleEndian::write_u16(&mut page[PD_LOWER..], pg_constants::SIZE_OF_PAGE_HEADER);
+    LittleEndian::write_u16(&mut page[PD_UPPER..], BLCKSZ);
+    LittleEndian::write_u16(&mut page[PD_SPECIAL..], BLCKSZ);
+    LittleEndian::write_u16(
+        &mut page[PD_PAGESIZE_VERSION..],
+        BLCKSZ | pg_constants::PG_PAGE_LAYOUT_VERSION,
+    );
+}
+
+/// See PageAddItem, called with overwrite and is_heap
+pub(super) fn page_add_heap_item(
+    page: &mut [u8],
+    item: &[u8],
+    offnum: OffsetNumber,
+) -> Result<(), WalRedoError> {
+    page_add_item(page, item, offnum, true)
+}
+
+/// See PageAddItem, called with neither overwrite nor is_heap: the items from `offnum` on move
+/// up by one.
+pub(super) fn page_add_index_item(
+    page: &mut [u8],
+    item: &[u8],
+    offnum: OffsetNumber,
+) -> Result<(), WalRedoError> {
+    page_add_item(page, item, offnum, false)
+}
+
+fn page_add_item(
+    page: &mut [u8],
+    item: &[u8],
+    offnum: OffsetNumber,
+    is_heap: bool,
+) -> Result<(), WalRedoError> {
+    let (lower, upper, _) = page_get_pointers(page)?;
+
+    let limit = page_get_max_offset_number(page)? + 1;
+    if offnum == 0
+        || offnum > limit
+        || (is_heap && offnum > pg_constants::MAX_HEAP_TUPLES_PER_PAGE)
+    {
+        error!("specified item offset {offnum} is invalid, the page has {} items", limit - 1);
+        return Err(WalRedoError::InvalidRecord);
+    }
+    if is_heap && offnum < limit {
+        let (_, lp_flags, lp_len) = page_get_item_id(page, offnum)?;
+        if lp_flags != pg_constants::LP_UNUSED || lp_len != 0 {
+            error!("will not overwrite a used ItemId");
+            return Err(WalRedoError::InvalidRecord);
+        }
+    }
+    let shuffle = !is_heap && offnum < limit;
+
+    let lower = if offnum == limit || shuffle {
+        lower + SIZE_OF_ITEM_ID
+    } else {
+        lower
+    };
+    let aligned_size = (item.len() + 7) & !7;
+    if lower + aligned_size > upper {
+        error!("failed to add tuple of {} bytes", item.len());
+        return Err(WalRedoError::InvalidRecord);
+    }
+    let upper = upper - aligned_size;
+
+    if shuffle {
+        let item_ids = item_id_offset(offnum)..item_id_offset(limit);
+        page.copy_within(item_ids, item_id_offset(offnum + 1));
+    }
+    page_set_item_id(page, offnum, upper, item.len());
+    page[upper..upper + item.len()].copy_from_slice(item);
+    LittleEndian::write_u16(&mut page[PD_LOWER..], lower as u16);
+    LittleEndian::write_u16(&mut page[PD_UPPER..], upper as u16);
+    Ok(())
+}
+
+/// The pd_lower, pd_upper and pd_special of the page, checked like in PageAddItem, so that the
+/// item ids up to pd_lower are in the page.
+fn page_get_pointers(page: &[u8]) -> Result<(usize, usize, usize), WalRedoError> {
+    let lower = LittleEndian::read_u16(&page[PD_LOWER..]) as usize;
+    let upper = LittleEndian::read_u16(&page[PD_UPPER..]) as usize;
+    let special = LittleEndian::read_u16(&page[PD_SPECIAL..]) as usize;
+    if lower < pg_constants::SIZE_OF_PAGE_HEADER as usize
+        || lower > upper
+        || upper > special
+        || special > BLCKSZ as usize
+    {
+        error!("corrupted page pointers: lower = {lower}, upper = {upper}, special = {special}");
+        return Err(WalRedoError::InvalidRecord);
+    }
+    Ok((lower, upper, special))
+}
+
+/// See PageGetMaxOffsetNumber. Fails if the page pointers are corrupted.
+pub(super) fn page_get_max_offset_number(page: &[u8]) -> Result<OffsetNumber, WalRedoError> {
+    let (lower, _, _) = page_get_pointers(page)?;
+    Ok(((lower - pg_constants::SIZE_OF_PAGE_HEADER as usize) / SIZE_OF_ITEM_ID) as OffsetNumber)
+}
+
+pub(super) fn page_get_flags(page: &[u8]) -> u16 {
+    LittleEndian::read_u16(&page[PD_FLAGS..])
+}
+
+pub(super) fn page_set_flags(page: &mut [u8], flags: u16) {
+    LittleEndian::write_u16(&mut page[PD_FLAGS..], flags);
+}
+
+/// Mark the page as a candidate for pruning, see PageSetPrunable
+pub(super) fn page_set_prunable(page: &mut [u8], xid: TransactionId) {
+    let prune_xid = LittleEndian::read_u32(&page[PD_PRUNE_XID..]);
+    if prune_xid == 0 || transaction_id_precedes(xid, prune_xid) {
+        LittleEndian::write_u32(&mut page[PD_PRUNE_XID..], xid);
+    }
+}
+
+fn item_id_offset(offnum: OffsetNumber) -> usize {
+    pg_constants::SIZE_OF_PAGE_HEADER as usize + (offnum as usize - 1) * SIZE_OF_ITEM_ID
+}
+
+/// The lp_off, lp_flags and lp_len of the item at `offnum`. Fails if there is no such item, or
+/// if the page pointers are corrupted.
+pub(super) fn page_get_item_id(
+    page: &[u8],
+    offnum: OffsetNumber,
+) -> Result<(usize, u32, usize), WalRedoError> {
+    if offnum == 0 || offnum > page_get_max_offset_number(page)? {
+        error!("invalid lp");
+        return Err(WalRedoError::InvalidRecord);
+    }
+    let item_id = LittleEndian::read_u32(&page[item_id_offset(offnum)..]);
+    let lp_off = item_id & 0x7FFF;
+    let lp_flags = (item_id >> 15) & 0x03;
+    let lp_len = item_id >> 17;
+    Ok((lp_off as usize, lp_flags, lp_len as usize))
+}
+
+/// See ItemIdSetNormal
+fn page_set_item_id(page: &mut [u8], offnum: OffsetNumber, lp_off: usize, lp_len: usize) {
+    let item_id = lp_off as u32 | pg_constants::LP_NORMAL << 15 | (lp_len as u32) << 17;
+    LittleEndian::write_u32(&mut page[item_id_offset(offnum)..], item_id);
+}
@@ -0,0 +1,189 @@
+//! What the WAL redo manager of a tenant does when the WAL redo process fails.
+//!
+//! The `wal_redo_policy` setting of the pageserver config, for example:
+//!
+//! ```toml
+//! [wal_redo_policy]
+//! timeout_per_record = '10ms'
+//! max_timeout = '5min'
+//! max_attempts = 3
+//! quarantine_threshold = 10
+//! quarantine_period = '1min'
+//! ```
+//!
+//! A batch of records sent to the process times out after `wal_redo_timeout`, plus
+//! `timeout_per_record` for each record of the batch, up to `max_timeout`. A failed attempt
+//! kills the process, and the next attempt launches a new one, up to `max_attempts` attempts.
+//! The timeouts and the crashes of the process are counted apart, in
+//! `pageserver_wal_redo_timeouts_total` and `pageserver_wal_redo_crashes_total`.
+//!
+//! After `quarantine_threshold` requests in a row have failed all their attempts, the tenant is
+//! quarantined: for `quarantine_period`, its requests that need the process fail with
+//! [`WalRedoError::Quarantined`] without launching it. The first request after that tries again,
+//! its failure extends the quarantine, its success ends it.
+//!
+//! By default a request is retried once, with the same timeout whatever its size, and the
+//! tenants are never quarantined.
+
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use super::WalRedoError;
+use crate::metrics::{WAL_REDO_CRASHES, WAL_REDO_QUARANTINES, WAL_REDO_TIMEOUTS};
+
+/// The `wal_redo_policy` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WalRedoPolicy {
+    /// Added to `wal_redo_timeout` for each record of a batch.
+    #[serde(with = "humantime_serde")]
+    pub timeout_per_record: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max_timeout: Option<Duration>,
+    /// Attempts of a request, including the first one.
+    pub max_attempts: u32,
+    /// Requests failed in a row before the tenant is quarantined. 0 never quarantines it.
+    pub quarantine_threshold: u32,
+    #[serde(with = "humantime_serde")]
+    pub quarantine_period: Duration,
+}
+
+impl Default for WalRedoPolicy {
+    fn default() -> Self {
+        WalRedoPolicy {
+            timeout_per_record: Duration::ZERO,
+            max_timeout: None,
+            max_attempts: 2,
+            quarantine_threshold: 0,
+            quarantine_period: Duration::from_secs(60),
+        }
+    }
+}
+
+impl WalRedoPolicy {
+    /// The timeout of a batch of `n_records` records.
+    pub(super) fn timeout(&self, wal_redo_timeout: Duration, n_records: usize) -> Duration {
+        let n_records = u32::try_from(n_records).unwrap_or(u32::MAX);
+        let timeout =
+            wal_redo_timeout.saturating_add(self.timeout_per_record.saturating_mul(n_records));
+        self.max_timeout.map_or(timeout, |max_timeout| timeout.min(max_timeout))
+    }
+
+    /// Whether to give up after the failure of the attempt number `n_attempts`, from 1.
+    pub(super) fn gives_up(&self, n_attempts: u32) -> bool {
+        n_attempts >= self.max_attempts
+    }
+}
+
+/// Count the failed attempt by its cause. The failures of the limits of the tenant config are
+/// counted by [`super::limits`].
+pub(super) fn count_failure(error: &WalRedoError) {
+    match error {
+        WalRedoError::IoError(e) if e.kind() == ErrorKind::TimedOut => WAL_REDO_TIMEOUTS.inc(),
+        WalRedoError::IoError(_) => WAL_REDO_CRASHES.inc(),
+        _ => {}
+    }
+}
+
+/// The requests of a tenant failed in a row, and its quarantine.
+#[derive(Debug, Default)]
+pub(super) struct FailureTracker {
+    consecutive_failures: u32,
+    quarantined_until: Option<Instant>,
+}
+
+impl FailureTracker {
+    /// Fail a request that needs the process if the tenant is quarantined.
+    pub(super) fn check(&self, now: Instant) -> Result<(), WalRedoError> {
+        match self.quarantined_until {
+            Some(until) if now < until => Err(WalRedoError::Quarantined),
+            _ => Ok(()),
+        }
+    }
+
+    /// Follow the outcome of a request, after all its attempts.
+    pub(super) fn finished(&mut self, succeeded: bool, policy: &WalRedoPolicy, now: Instant) {
+        if succeeded {
+            if self.quarantined_until.take().is_some() {
+                info!("WAL redo quarantine ended");
+            }
+            self.consecutive_failures = 0;
+            return;
+        }
+
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        if policy.quarantine_threshold != 0
+            && self.consecutive_failures >= policy.quarantine_threshold
+        {
+            warn!(
+                "WAL redo quarantined for {:?} after {} failed requests in a row",
+                policy.quarantine_period, self.consecutive_failures
+            );
+            WAL_REDO_QUARANTINES.inc();
+            self.quarantined_until = Some(now + policy.quarantine_period);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_timeout() {
+        let base = Duration::from_secs(60);
+        assert_eq!(WalRedoPolicy::default().timeout(base, 1000), base);
+
+        let policy = WalRedoPolicy {
+            timeout_per_record: Duration::from_millis(10),
+            max_timeout: Some(Duration::from_secs(120)),
+            ..WalRedoPolicy::default()
+        };
+        assert_eq!(policy.timeout(base, 1000), Duration::from_secs(70));
+        assert_eq!(policy.timeout(base, 100_000), Duration::from_secs(120));
+        assert_eq!(policy.timeout(base, usize::MAX), Duration::from_secs(120));
+
+        assert!(!WalRedoPolicy::default().gives_up(1));
+        assert!(WalRedoPolicy::default().gives_up(2));
+    }
+
+    #[test]
+    fn quarantine() {
+        let policy = WalRedoPolicy {
+            quarantine_threshold: 3,
+            quarantine_period: Duration::from_secs(10),
+            ..WalRedoPolicy::default()
+        };
+        let now = Instant::now();
+        let mut tracker = FailureTracker::default();
+
+        // A success resets the count
+        tracker.finished(false, &policy, now);
+        tracker.finished(false, &policy, now);
+        tracker.finished(true, &policy, now);
+        tracker.finished(false, &policy, now);
+        tracker.finished(false, &policy, now);
+        assert!(tracker.check(now).is_ok());
+
+        tracker.finished(false, &policy, now);
+        assert!(matches!(tracker.check(now), Err(WalRedoError::Quarantined)));
+        let later = now + Duration::from_secs(10);
+        assert!(tracker.check(later).is_ok());
+
+        // The next failure extends the quarantine, a success ends it
+        tracker.finished(false, &policy, later);
+        assert!(tracker.check(later + Duration::from_secs(9)).is_err());
+        tracker.finished(true, &policy, later);
+        assert!(tracker.check(later).is_ok());
+
+        // Never quarantined by default
+        let mut tracker = FailureTracker::default();
+        for _ in 0..100 {
+            tracker.finished(false, &WalRedoPolicy::default(), now);
+        }
+        assert!(tracker.check(now).is_ok());
+    }
+}
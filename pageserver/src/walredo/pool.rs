@@ -0,0 +1,216 @@
+//! The threads that talk to a WAL redo process.
+//!
+//! Launching a process, writing the requests to its stdin and reading the pages from its stdout
+//! blocks in poll(2), read and write for as long as the process takes, up to the WAL redo
+//! timeout. Rather than doing that on the thread that needs the redo, often a tokio worker
+//! thread, the exchange with the process is sent to one of the threads of the process, and the
+//! caller awaits its result on a oneshot channel.
+//!
+//! Each process has threads of its own, so that a slow or wedged process only holds up the
+//! requests of its tenant. The requests of concurrent callers are still pipelined to the
+//! process, see [`super::PostgresRedoManager`]: a thread writes its request and waits for the
+//! response while the others write theirs. The threads are started when the requests need
+//! them, up to `wal_redo_threads`, and exit after [`IDLE_TIMEOUT`] without a request, so that
+//! idle tenants take none.
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+use tracing::{warn, Span};
+
+/// How long a thread waits for a job before it exits.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+pub(super) struct RedoThreadPool {
+    /// The name of the threads.
+    name: String,
+    max_threads: usize,
+    idle_timeout: Duration,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<PoolState>,
+    /// Notified when a job is queued, or when the pool is dropped.
+    queued: Condvar,
+}
+
+#[derive(Default)]
+struct PoolState {
+    jobs: VecDeque<Job>,
+    threads: usize,
+    /// The threads waiting for a job.
+    idle: usize,
+    /// The pool was dropped: the threads exit once the jobs are done.
+    closed: bool,
+}
+
+impl RedoThreadPool {
+    /// A pool of up to `max_threads` threads, at least one, named `name`. No thread is started
+    /// before the first job.
+    pub(super) fn new(name: String, max_threads: usize) -> Self {
+        Self::with_idle_timeout(name, max_threads, IDLE_TIMEOUT)
+    }
+
+    fn with_idle_timeout(name: String, max_threads: usize, idle_timeout: Duration) -> Self {
+        RedoThreadPool {
+            name,
+            max_threads: max_threads.max(1),
+            idle_timeout,
+            shared: Arc::new(Shared {
+                state: Mutex::default(),
+                queued: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Run `job` on one of the threads, in the span of the caller, and await its result. A
+    /// panic of the job is resumed in the caller, and doesn't take the thread down.
+    ///
+    /// The job runs to completion even if the caller stops awaiting it. Fails without running
+    /// it if no thread is running and none can be started.
+    pub(super) async fn run<R, F>(&self, job: F) -> io::Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce() -> R + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel::<Result<R, Box<dyn Any + Send>>>();
+        let span = Span::current();
+        let job: Job = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| span.in_scope(job)));
+            // The caller awaits it, unless it was cancelled
+            let _ = sender.send(result);
+        });
+        self.submit(job)?;
+        match receiver.await.expect("WAL redo jobs always send a result") {
+            Ok(result) => Ok(result),
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    }
+
+    /// Queue `job`, and start a thread for it if the running ones are all busy.
+    fn submit(&self, job: Job) -> io::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.jobs.push_back(job);
+        if state.jobs.len() > state.idle && state.threads < self.max_threads {
+            let shared = Arc::clone(&self.shared);
+            let idle_timeout = self.idle_timeout;
+            let spawned = thread::Builder::new()
+                .name(self.name.clone())
+                .spawn(move || shared.work(idle_timeout));
+            match spawned {
+                Ok(_) => state.threads += 1,
+                Err(e) if state.threads == 0 => {
+                    state.jobs.pop_back();
+                    return Err(e);
+                }
+                // The running threads get to the job later
+                Err(e) => warn!("failed to start a WAL redo thread: {e}"),
+            }
+        }
+        self.shared.queued.notify_one();
+        Ok(())
+    }
+}
+
+impl Drop for RedoThreadPool {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.queued.notify_all();
+    }
+}
+
+impl Shared {
+    /// The loop of a thread: run the jobs, until none comes for `idle_timeout`, or the pool is
+    /// dropped.
+    fn work(&self, idle_timeout: Duration) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                // The lock is released while running the job
+                drop(state);
+                job();
+                state = self.state.lock().unwrap();
+                continue;
+            }
+            if state.closed {
+                break;
+            }
+            state.idle += 1;
+            let (guard, wait) = self.queued.wait_timeout(state, idle_timeout).unwrap();
+            state = guard;
+            state.idle -= 1;
+            if wait.timed_out() && state.jobs.is_empty() {
+                break;
+            }
+        }
+        state.threads -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use futures::FutureExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn run() {
+        let pool = RedoThreadPool::new("walredo-test".to_string(), 2);
+        let caller = thread::current().id();
+        let (result, thread_id) = pool
+            .run(|| (1 + 1, thread::current().id()))
+            .await
+            .unwrap();
+        assert_eq!(result, 2);
+        assert_ne!(thread_id, caller);
+
+        // The jobs of concurrent callers run on all the threads, and no more
+        let jobs = (0..8).map(|_| {
+            pool.run(|| {
+                thread::sleep(Duration::from_millis(50));
+                thread::current().id()
+            })
+        });
+        let threads = futures::future::join_all(jobs)
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<HashSet<_>>();
+        assert_eq!(threads.len(), 2);
+
+        // A panic reaches the caller, the threads carry on
+        let panicked = AssertUnwindSafe(pool.run(|| panic!("job failed")))
+            .catch_unwind()
+            .await;
+        assert!(panicked.is_err());
+        assert_eq!(pool.run(|| 3).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn idle_threads_exit() {
+        let pool =
+            RedoThreadPool::with_idle_timeout("walredo-test".to_string(), 2, Duration::ZERO);
+        assert_eq!(pool.run(|| 1).await.unwrap(), 1);
+        for _ in 0..100 {
+            if pool.shared.state.lock().unwrap().threads == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(pool.shared.state.lock().unwrap().threads, 0);
+
+        // A thread is started again for the next job
+        assert_eq!(pool.run(|| 2).await.unwrap(), 2);
+    }
+}
@@ -0,0 +1,288 @@
+//! Versions of the protocol spoken with the WAL redo process.
+//!
+//! In version 1, the process answers a GetPage ('G') message with the bare page, and reports
+//! nothing else: a failed request is only seen as the process closing its pipes, with the error
+//! in its stderr.
+//!
+//! In version 2, each response is framed like the messages sent to the process, with a message
+//! type and a length, and the process reports the error that it exits with:
+//!
+//! ```text
+//! char   msgtype;  // 'P' for a page, 'E' for an error
+//! int32  length;   // including 'length', excluding 'msgtype', in network byte order
+//! <payload>        // 'P': the page, 'E': the SQLSTATE (5 chars) and the error message
+//! ```
+//!
+//! The process is launched with [`PROTOCOL_V2_ARG`], which the binaries that only speak
+//! version 1 ignore. Then [`negotiate`] sends it a probe, a zero page to return: the first byte
+//! of its response is 0 in version 1, and 'P' (or 'E') in version 2.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::process::{ChildStdin, ChildStdout};
+use std::time::Duration;
+
+use bytes::Bytes;
+use nix::poll::{PollFd, PollFlags};
+use pageserver_api::reltag::RelTag;
+use postgres_ffi::{pg_constants, BLCKSZ};
+
+use super::{build_begin_redo_for_block_msg, build_get_page_msg, build_push_page_msg, BufferTag};
+
+/// Asks the process to speak version 2 of the protocol.
+pub(super) const PROTOCOL_V2_ARG: &str = "--wal-redo-protocol=2";
+
+/// The msgtype and the length of a version 2 response.
+const RESPONSE_HEADER_LEN: usize = 5;
+
+const ERRCODE_OUT_OF_MEMORY: &str = "53200";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ProtocolVersion {
+    V1,
+    V2,
+}
+
+/// An error that the WAL redo process reported in a version 2 response.
+#[derive(Debug, thiserror::Error)]
+#[error("WAL redo process failed with SQLSTATE {sqlstate}: {message}")]
+pub(super) struct ProcessError {
+    pub sqlstate: String,
+    pub message: String,
+}
+
+/// Whether `error` is an allocation failure reported by the WAL redo process.
+pub(super) fn is_out_of_memory(error: &io::Error) -> bool {
+    error
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<ProcessError>())
+        .map_or(false, |e| e.sqlstate == ERRCODE_OUT_OF_MEMORY)
+}
+
+/// Reads a response of the WAL redo process from its non-blocking stdout, as it comes.
+pub(super) struct ResponseReader {
+    protocol: ProtocolVersion,
+    buf: Vec<u8>,
+    nread: usize,
+    /// The length of the payload is known.
+    header_read: bool,
+}
+
+impl ResponseReader {
+    pub(super) fn new(protocol: ProtocolVersion) -> Self {
+        let len = match protocol {
+            ProtocolVersion::V1 => BLCKSZ as usize,
+            ProtocolVersion::V2 => RESPONSE_HEADER_LEN,
+        };
+        ResponseReader {
+            protocol,
+            buf: vec![0; len],
+            nread: 0,
+            header_read: protocol == ProtocolVersion::V1,
+        }
+    }
+
+    /// Where to read the rest of the response, `None` once it's complete.
+    pub(super) fn unfilled(&mut self) -> Option<&mut [u8]> {
+        if self.nread < self.buf.len() {
+            Some(&mut self.buf[self.nread..])
+        } else {
+            None
+        }
+    }
+
+    /// `n` bytes were read into [`Self::unfilled`].
+    pub(super) fn filled(&mut self, n: usize) -> io::Result<()> {
+        self.nread += n;
+        if !self.header_read && self.nread == self.buf.len() {
+            self.header_read = true;
+            let len = u32::from_be_bytes(self.buf[1..5].try_into().unwrap()) as usize;
+            // Nothing but a page or an error message comes back, both well under a page
+            if !(4..=4 + BLCKSZ as usize).contains(&len) {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid WAL redo response length {len}"),
+                ));
+            }
+            self.buf.resize(RESPONSE_HEADER_LEN + len - 4, 0);
+        }
+        Ok(())
+    }
+
+    /// The page in the complete response, or the error that it reports.
+    pub(super) fn finish(self) -> io::Result<Bytes> {
+        if self.protocol == ProtocolVersion::V1 {
+            return Ok(Bytes::from(self.buf));
+        }
+        let payload = &self.buf[RESPONSE_HEADER_LEN..];
+        match self.buf[0] {
+            b'P' if payload.len() == BLCKSZ as usize => {
+                Ok(Bytes::from(self.buf).slice(RESPONSE_HEADER_LEN..))
+            }
+            b'E' if payload.len() >= 5 => Err(io::Error::new(
+                ErrorKind::Other,
+                ProcessError {
+                    sqlstate: String::from_utf8_lossy(&payload[..5]).into_owned(),
+                    message: String::from_utf8_lossy(&payload[5..]).into_owned(),
+                },
+            )),
+            msgtype => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "invalid WAL redo response of type {msgtype:#x} and {} bytes",
+                    payload.len()
+                ),
+            )),
+        }
+    }
+}
+
+/// Find out the version of the protocol that the process launched with [`PROTOCOL_V2_ARG`]
+/// speaks, see the module doc.
+pub(super) fn negotiate(
+    stdin: &mut ChildStdin,
+    stdout: &mut ChildStdout,
+    timeout: Duration,
+) -> io::Result<ProtocolVersion> {
+    let tag = BufferTag {
+        rel: RelTag {
+            forknum: 0,
+            spcnode: pg_constants::DEFAULTTABLESPACE_OID,
+            dbnode: 0,
+            relnode: 0,
+        },
+        blknum: 0,
+    };
+    let mut probe = Vec::with_capacity(BLCKSZ as usize * 2);
+    build_begin_redo_for_block_msg(tag, &mut probe);
+    build_push_page_msg(tag, &crate::ZERO_PAGE, &mut probe);
+    build_get_page_msg(tag, &mut probe);
+    write_all(stdin, &probe, timeout)?;
+
+    let mut msgtype = [0u8];
+    read_exact(stdout, &mut msgtype, timeout)?;
+    let protocol = match msgtype[0] {
+        0 => ProtocolVersion::V1,
+        // Including the error of a process that failed to start
+        b'P' | b'E' => ProtocolVersion::V2,
+        _ => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "invalid WAL redo response to the protocol probe",
+            ))
+        }
+    };
+    let mut response = ResponseReader::new(protocol);
+    response.unfilled().unwrap()[0] = msgtype[0];
+    response.filled(1)?;
+    while let Some(buf) = response.unfilled() {
+        let n = buf.len();
+        read_exact(stdout, buf, timeout)?;
+        response.filled(n)?;
+    }
+    if response.finish()? != crate::ZERO_PAGE {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "WAL redo process returned another page than the protocol probe",
+        ));
+    }
+    Ok(protocol)
+}
+
+/// Wait for `events` on `fd`, for up to `timeout`.
+fn wait(fd: RawFd, events: PollFlags, timeout: Duration) -> io::Result<()> {
+    let mut pollfds = [PollFd::new(fd, events)];
+    let n = loop {
+        match nix::poll::poll(&mut pollfds, timeout.as_millis() as i32) {
+            Err(e) if e == nix::errno::Errno::EINTR => continue,
+            res => break res,
+        }
+    }?;
+    if n == 0 {
+        return Err(io::Error::new(ErrorKind::TimedOut, "WAL redo protocol probe timed out"));
+    }
+    Ok(())
+}
+
+fn write_all(stdin: &mut ChildStdin, buf: &[u8], timeout: Duration) -> io::Result<()> {
+    let mut nwrite = 0;
+    while nwrite < buf.len() {
+        wait(stdin.as_raw_fd(), PollFlags::POLLOUT, timeout)?;
+        match stdin.write(&buf[nwrite..]) {
+            Ok(n) => nwrite += n,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn read_exact(stdout: &mut ChildStdout, buf: &mut [u8], timeout: Duration) -> io::Result<()> {
+    let mut nread = 0;
+    while nread < buf.len() {
+        wait(stdout.as_raw_fd(), PollFlags::POLLIN, timeout)?;
+        match stdout.read(&mut buf[nread..]) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "WAL redo process closed its stdout during the protocol probe",
+                ))
+            }
+            Ok(n) => nread += n,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(protocol: ProtocolVersion, response: &[u8]) -> io::Result<Bytes> {
+        let mut reader = ResponseReader::new(protocol);
+        let mut response = response;
+        // In chunks of 1000 bytes, like reads of a pipe would
+        while let Some(buf) = reader.unfilled() {
+            let n = buf.len().min(1000).min(response.len());
+            assert!(n > 0, "incomplete response");
+            buf[..n].copy_from_slice(&response[..n]);
+            response = &response[n..];
+            reader.filled(n)?;
+        }
+        assert!(response.is_empty(), "response longer than its header says");
+        reader.finish()
+    }
+
+    fn frame(msgtype: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![msgtype];
+        frame.extend_from_slice(&(4 + payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn responses() {
+        let page = vec![7u8; BLCKSZ as usize];
+        assert_eq!(read(ProtocolVersion::V1, &page).unwrap(), page);
+        assert_eq!(read(ProtocolVersion::V2, &frame(b'P', &page)).unwrap(), page);
+
+        let error = read(ProtocolVersion::V2, &frame(b'E', b"53200out of memory")).unwrap_err();
+        assert!(is_out_of_memory(&error));
+        assert_eq!(
+            error.to_string(),
+            "WAL redo process failed with SQLSTATE 53200: out of memory"
+        );
+        let error = read(ProtocolVersion::V2, &frame(b'E', b"XX000could not read")).unwrap_err();
+        assert!(!is_out_of_memory(&error));
+
+        // A page of the wrong size, an unknown type, a length over a page
+        assert!(read(ProtocolVersion::V2, &frame(b'P', &page[1..])).is_err());
+        assert!(read(ProtocolVersion::V2, &frame(b'X', &page)).is_err());
+        let mut reader = ResponseReader::new(ProtocolVersion::V2);
+        let header = [b'P', 0, 1, 0, 0];
+        reader.unfilled().unwrap().copy_from_slice(&header);
+        assert!(reader.filled(header.len()).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+//! What the WAL redo process writes to its stderr.
+//!
+//! The `wal_redo_stderr` setting of the pageserver config, for example:
+//!
+//! ```toml
+//! [wal_redo_stderr]
+//! max_lines_per_minute = 60
+//! tail_lines = 20
+//! max_line_length = 4096
+//! ```
+//!
+//! The output of the process is split into lines, however the reads from the pipe cut it, and
+//! each line is logged at ERROR level. A line longer than `max_line_length` bytes is logged in
+//! pieces. At most `max_lines_per_minute` lines of a process are logged each minute, the next
+//! log line reports how many were suppressed, and they are counted in
+//! `pageserver_wal_redo_stderr_suppressed_lines_total`. A process that crashes in a loop
+//! shares the limit with the processes that replace it, so that it can't flood the log.
+//!
+//! The last `tail_lines` lines, logged or not, are kept for the status of the process in the
+//! management API.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use serde::Deserialize;
+use tracing::error;
+use utils::rate_limit::RateLimitedLog;
+
+use super::limits;
+use crate::metrics::WAL_REDO_STDERR_SUPPRESSED_LINES;
+
+/// The `wal_redo_stderr` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WalRedoStderrConfig {
+    /// Lines logged per minute. 0 logs them all.
+    pub max_lines_per_minute: u32,
+    /// Lines kept for the status of the process.
+    pub tail_lines: usize,
+    /// Bytes of a line logged at once.
+    pub max_line_length: usize,
+}
+
+impl Default for WalRedoStderrConfig {
+    fn default() -> Self {
+        WalRedoStderrConfig {
+            max_lines_per_minute: 60,
+            tail_lines: 20,
+            max_line_length: 4096,
+        }
+    }
+}
+
+/// The stderr of the processes of a Postgres version, one after the other.
+pub(super) struct StderrLog {
+    config: WalRedoStderrConfig,
+    /// The output after the last newline.
+    partial: Vec<u8>,
+    rate_limit: RateLimitedLog,
+    tail: VecDeque<String>,
+}
+
+impl StderrLog {
+    pub(super) fn new(config: &WalRedoStderrConfig) -> Self {
+        StderrLog {
+            config: *config,
+            partial: Vec::new(),
+            rate_limit: RateLimitedLog::new(config.max_lines_per_minute, Duration::from_secs(60)),
+            tail: VecDeque::with_capacity(config.tail_lines),
+        }
+    }
+
+    /// Log the lines completed by `buf`, read from the stderr of the process. Returns whether
+    /// one of them reports that the process ran out of memory.
+    pub(super) fn forward(&mut self, buf: &[u8]) -> bool {
+        let max_line_length = self.config.max_line_length.max(1);
+        let mut out_of_memory = false;
+        for chunk in buf.split_inclusive(|b| *b == b'\n') {
+            let (text, complete) = match chunk.split_last() {
+                Some((b'\n', text)) => (text, true),
+                _ => (chunk, false),
+            };
+            self.partial.extend_from_slice(text);
+            while self.partial.len() > max_line_length {
+                let rest = self.partial.split_off(max_line_length);
+                out_of_memory |= self.end_line();
+                self.partial = rest;
+            }
+            if complete {
+                out_of_memory |= self.end_line();
+            }
+        }
+        out_of_memory
+    }
+
+    /// Log the output after the last newline, when the process is gone.
+    pub(super) fn flush(&mut self) -> bool {
+        !self.partial.is_empty() && self.end_line()
+    }
+
+    /// The last lines, oldest first.
+    pub(super) fn tail(&self) -> Vec<String> {
+        self.tail.iter().cloned().collect()
+    }
+
+    fn end_line(&mut self) -> bool {
+        let line = String::from_utf8_lossy(&self.partial)
+            .trim_end_matches('\r')
+            .to_string();
+        self.partial.clear();
+
+        let allowed = if self.config.max_lines_per_minute == 0 {
+            Some(0)
+        } else {
+            self.rate_limit.check()
+        };
+        match allowed {
+            Some(suppressed) => error!(suppressed, "wal-redo-postgres: {line}"),
+            None => WAL_REDO_STDERR_SUPPRESSED_LINES.inc(),
+        }
+
+        let out_of_memory = limits::is_out_of_memory(&line);
+        if self.config.tail_lines > 0 {
+            if self.tail.len() == self.config.tail_lines {
+                self.tail.pop_front();
+            }
+            self.tail.push_back(line);
+        }
+        out_of_memory
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        let config = WalRedoStderrConfig {
+            tail_lines: 3,
+            max_line_length: 16,
+            ..WalRedoStderrConfig::default()
+        };
+        let mut log = StderrLog::new(&config);
+
+        // The lines are put together across the reads
+        assert!(!log.forward(b"LOG:  a\nLOG:"));
+        assert!(!log.forward(b"  b\r\n"));
+        assert_eq!(log.tail(), ["LOG:  a", "LOG:  b"]);
+
+        // Reported out of memory even when cut in two reads
+        assert!(!log.forward(b"out of "));
+        assert!(log.forward(b"memory\n"));
+
+        // A line too long is cut, the older lines fall out of the tail
+        assert!(!log.forward(b"0123456789abcdef01"));
+        assert_eq!(log.tail(), ["LOG:  b", "out of memory", "0123456789abcdef"]);
+        assert!(!log.flush());
+        assert!(!log.flush());
+        assert_eq!(log.tail(), ["out of memory", "0123456789abcdef", "01"]);
+    }
+
+    #[test]
+    fn rate_limit() {
+        let suppressed = || WAL_REDO_STDERR_SUPPRESSED_LINES.get();
+        let config = WalRedoStderrConfig {
+            max_lines_per_minute: 2,
+            tail_lines: 0,
+            ..WalRedoStderrConfig::default()
+        };
+        let mut log = StderrLog::new(&config);
+        let before = suppressed();
+        log.forward(b"1\n2\n3\n4\n");
+        assert!(suppressed() >= before + 2);
+        assert!(log.tail().is_empty());
+
+        let config = WalRedoStderrConfig {
+            max_lines_per_minute: 0,
+            ..WalRedoStderrConfig::default()
+        };
+        let mut log = StderrLog::new(&config);
+        log.forward(b"1\n2\n3\n4\n");
+        assert_eq!(log.tail(), ["1", "2", "3", "4"]);
+    }
+}
@@ -25,8 +25,14 @@
  * ApplyRecord ('A'): Apply a WAL record (in the payload)
  * GetPage ('G'): Return a page image from buffer cache.
  *
- * Currently, you only get a response to GetPage requests; the response is
- * simply a 8k page, without any headers. Errors are logged to stderr.
+ * You only get a response to GetPage requests. In version 1 of the protocol,
+ * the response is simply a 8k page, without any headers, and errors are only
+ * logged to stderr. In version 2, requested with the --wal-redo-protocol=2
+ * argument, the responses have the same format as the messages above:
+ *
+ * Page ('P'): The 8k page
+ * Error ('E'): SQLSTATE (5 chars), then the error message. Sent for the
+ *   error that the process exits with, instead of any other response.
  *
  * FIXME:
  * - this currently requires a valid PGDATA, and creates a lock file there
@@ -127,10 +133,17 @@ static void apply_error_callback(void *arg);
 static bool redo_block_filter(XLogReaderState *record, uint8 block_id);
 static void GetPage(StringInfo input_message);
 static ssize_t buffered_read(void *buf, size_t count);
+static bool write_stdout(const char *buf, size_t len);
+static void walredo_emit_log_hook(ErrorData *edata);
 static void CreateFakeSharedMemoryAndSemaphores();
 
 static BufferTag target_redo_tag;
 
+/* Version of the protocol spoken with the pageserver */
+static int	protocol_version = 1;
+static emit_log_hook_type prev_emit_log_hook = NULL;
+static bool error_response_sent = false;
+
 static XLogReaderState *reader_state;
 
 #define TRACE DEBUG5
@@ -198,6 +211,15 @@ WalRedoMain(int argc, char *argv[])
 
 	am_wal_redo_postgres = true;
 
+	for (int i = 1; i < argc; i++)
+		if (strcmp(argv[i], "--wal-redo-protocol=2") == 0)
+			protocol_version = 2;
+	if (protocol_version >= 2)
+	{
+		prev_emit_log_hook = emit_log_hook;
+		emit_log_hook = walredo_emit_log_hook;
+	}
+
 	/*
 	 * WAL redo does not need a large number of buffers. And speed of
 	 * DropRelFileNodeAllLocalBuffers() is proportional to the number of
@@ -919,7 +941,6 @@ GetPage(StringInfo input_message)
 	BlockNumber blknum;
 	Buffer		buf;
 	Page		page;
-	int			tot_written;
 
 	/*
 	 * message format:
@@ -943,22 +964,23 @@ GetPage(StringInfo input_message)
 	page = BufferGetPage(buf);
 	/* single thread, so don't bother locking the page */
 
-	/* Response: Page content */
-	tot_written = 0;
-	do {
-		ssize_t		rc;
+	/* Response: Page content, after a header in version 2 of the protocol */
+	if (protocol_version >= 2)
+	{
+		char		hdr[1 + sizeof(int32)];
+		int32		len = pg_hton32(sizeof(int32) + BLCKSZ);
 
-		rc = write(STDOUT_FILENO, &page[tot_written], BLCKSZ - tot_written);
-		if (rc < 0) {
-			/* If interrupted by signal, just retry */
-			if (errno == EINTR)
-				continue;
+		hdr[0] = 'P';
+		memcpy(&hdr[1], &len, sizeof(int32));
+		if (!write_stdout(hdr, sizeof(hdr)))
 			ereport(ERROR,
 					(errcode_for_file_access(),
 					 errmsg("could not write to stdout: %m")));
-		}
-		tot_written += rc;
-	} while (tot_written < BLCKSZ);
+	}
+	if (!write_stdout(page, BLCKSZ))
+		ereport(ERROR,
+				(errcode_for_file_access(),
+				 errmsg("could not write to stdout: %m")));
 
 	ReleaseBuffer(buf);
 	DropRelFileNodeAllLocalBuffers(rnode);
@@ -1023,3 +1045,62 @@ buffered_read(void *buf, size_t count)
 
 	return (dst - (char *) buf);
 }
+
+/*
+ * Write all of 'buf' to stdout.
+ *
+ * Returns false on error, with errno set. Doesn't ereport(), so that it can be
+ * used while reporting an error.
+ */
+static bool
+write_stdout(const char *buf, size_t len)
+{
+	size_t		tot_written = 0;
+
+	while (tot_written < len)
+	{
+		ssize_t		rc;
+
+		rc = write(STDOUT_FILENO, &buf[tot_written], len - tot_written);
+		if (rc < 0)
+		{
+			/* If interrupted by signal, just retry */
+			if (errno == EINTR)
+				continue;
+			return false;
+		}
+		tot_written += rc;
+	}
+	return true;
+}
+
+/*
+ * Send an Error response for the error that the process exits with, in
+ * version 2 of the protocol.
+ *
+ * There is no error recovery in the main loop: every ERROR is promoted to
+ * FATAL, so the first error reported is the last one.
+ */
+static void
+walredo_emit_log_hook(ErrorData *edata)
+{
+	if (prev_emit_log_hook)
+		prev_emit_log_hook(edata);
+
+	if (edata->elevel >= ERROR && !error_response_sent)
+	{
+		const char *sqlstate = unpack_sql_state(edata->sqlerrcode);
+		const char *message = edata->message ? edata->message : "";
+		/* The pageserver accepts no response longer than a page */
+		size_t		msglen = Min(strlen(message), BLCKSZ - 5);
+		char		hdr[1 + sizeof(int32)];
+		int32		len = pg_hton32(sizeof(int32) + 5 + msglen);
+
+		error_response_sent = true;
+		hdr[0] = 'E';
+		memcpy(&hdr[1], &len, sizeof(int32));
+		/* Nothing to do about a failure here, the pageserver sees the exit anyway */
+		if (write_stdout(hdr, sizeof(hdr)) && write_stdout(sqlstate, 5))
+			(void) write_stdout(message, msglen);
+	}
+}
@@ -1,9 +1,12 @@
+use futures::future::poll_fn;
 use parking_lot::Mutex;
 use pq_proto::StartupMessageParams;
 use std::fmt;
-use std::ops::ControlFlow;
+use std::ops::{ControlFlow, Deref};
 use std::{collections::HashMap, sync::Arc};
+use tokio::sync::mpsc;
 use tokio::time;
+use tokio_postgres::{AsyncMessage, Notification};
 
 use crate::config;
 use crate::{auth, console};
@@ -17,9 +20,12 @@ use crate::proxy::{
 
 use tracing::error;
 use tracing::info;
+use tracing::warn;
 
 pub const APP_NAME: &str = "sql_over_http";
 const MAX_CONNS_PER_ENDPOINT: usize = 20;
+// Notifications received on a connection and not taken yet, beyond which they are dropped.
+const MAX_PENDING_NOTIFICATIONS: usize = 1024;
 
 #[derive(Debug)]
 pub struct ConnInfo {
@@ -43,8 +49,38 @@ impl fmt::Display for ConnInfo {
     }
 }
 
+/// A connection to the compute, along with the asynchronous notifications received on it.
+pub struct Client {
+    inner: tokio_postgres::Client,
+    notifications: mpsc::Receiver<Notification>,
+}
+
+impl Client {
+    /// Take the notifications received so far.
+    pub fn take_notifications(&mut self) -> Vec<Notification> {
+        let mut notifications = Vec::new();
+        while let Ok(notification) = self.notifications.try_recv() {
+            notifications.push(notification);
+        }
+        notifications
+    }
+
+    /// Wait for the next notification. Returns `None` once the connection is closed.
+    pub async fn next_notification(&mut self) -> Option<Notification> {
+        self.notifications.recv().await
+    }
+}
+
+impl Deref for Client {
+    type Target = tokio_postgres::Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
 struct ConnPoolEntry {
-    conn: tokio_postgres::Client,
+    conn: Client,
     _last_access: std::time::Instant,
 }
 
@@ -84,8 +120,8 @@ impl GlobalConnPool {
         &self,
         conn_info: &ConnInfo,
         force_new: bool,
-    ) -> anyhow::Result<tokio_postgres::Client> {
-        let mut client: Option<tokio_postgres::Client> = None;
+    ) -> anyhow::Result<Client> {
+        let mut client: Option<Client> = None;
 
         if !force_new {
             let pool = self.get_endpoint_pool(&conn_info.hostname).await;
@@ -102,12 +138,17 @@ impl GlobalConnPool {
         }
 
         // ok return cached connection if found and establish a new one otherwise
-        if let Some(client) = client {
+        if let Some(mut client) = client {
             if client.is_closed() {
                 info!("pool: cached connection '{conn_info}' is closed, opening a new one");
                 connect_to_compute(self.proxy_config, conn_info).await
             } else {
                 info!("pool: reusing connection '{conn_info}'");
+                // Whatever was received while the connection sat in the pool is not for us.
+                let stale = client.take_notifications().len();
+                if stale > 0 {
+                    info!("pool: dropping {stale} stale notifications");
+                }
                 Ok(client)
             }
         } else {
@@ -119,7 +160,7 @@ impl GlobalConnPool {
     pub async fn put(
         &self,
         conn_info: &ConnInfo,
-        client: tokio_postgres::Client,
+        client: Client,
     ) -> anyhow::Result<()> {
         let pool = self.get_endpoint_pool(&conn_info.hostname).await;
 
@@ -194,7 +235,7 @@ impl GlobalConnPool {
 async fn connect_to_compute(
     config: &config::ProxyConfig,
     conn_info: &ConnInfo,
-) -> anyhow::Result<tokio_postgres::Client> {
+) -> anyhow::Result<Client> {
     let tls = config.tls_config.as_ref();
     let common_names = tls.and_then(|tls| tls.common_names.clone());
 
@@ -286,10 +327,10 @@ fn can_retry_error(err: &tokio_postgres::Error, num_retries: u32) -> bool {
 async fn connect_to_compute_once(
     node_info: &console::CachedNodeInfo,
     conn_info: &ConnInfo,
-) -> Result<tokio_postgres::Client, tokio_postgres::Error> {
+) -> Result<Client, tokio_postgres::Error> {
     let mut config = (*node_info.config).clone();
 
-    let (client, connection) = config
+    let (client, mut connection) = config
         .user(&conn_info.username)
         .password(&conn_info.password)
         .dbname(&conn_info.dbname)
@@ -297,11 +338,27 @@ async fn connect_to_compute_once(
         .connect(tokio_postgres::NoTls)
         .await?;
 
+    // Drive the connection, keeping the notifications instead of dropping them.
+    let (tx, notifications) = mpsc::channel(MAX_PENDING_NOTIFICATIONS);
     tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            error!("connection error: {}", e);
+        while let Some(message) = poll_fn(|cx| connection.poll_message(cx)).await {
+            match message {
+                Ok(AsyncMessage::Notification(notification)) => {
+                    if let Err(mpsc::error::TrySendError::Full(_)) = tx.try_send(notification) {
+                        warn!("too many pending notifications, dropping one");
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    error!("connection error: {}", e);
+                    break;
+                }
+            }
         }
     });
 
-    Ok(client)
+    Ok(Client {
+        inner: client,
+        notifications,
+    })
 }
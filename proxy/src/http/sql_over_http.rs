@@ -1,17 +1,23 @@
 use std::sync::Arc;
+use std::time::Duration;
 
+use bytes::Bytes;
 use futures::pin_mut;
 use futures::StreamExt;
 use hyper::body::HttpBody;
+use hyper::header;
 use hyper::http::HeaderName;
 use hyper::http::HeaderValue;
-use hyper::{Body, HeaderMap, Request};
+use hyper::{Body, HeaderMap, Request, Response, StatusCode};
 use serde_json::json;
 use serde_json::Map;
 use serde_json::Value;
 use tokio_postgres::types::Kind;
 use tokio_postgres::types::Type;
+use tokio_postgres::Notification;
 use tokio_postgres::Row;
+use tracing::info;
+use tracing::Instrument;
 use url::Url;
 
 use super::conn_pool::ConnInfo;
@@ -23,14 +29,29 @@ struct QueryData {
     params: Vec<serde_json::Value>,
 }
 
+#[derive(serde::Deserialize)]
+struct ListenData {
+    channels: Vec<String>,
+    // How long to wait for a notification, when long-polling.
+    #[serde(default = "default_listen_timeout_ms")]
+    timeout_ms: u64,
+}
+
+fn default_listen_timeout_ms() -> u64 {
+    30_000
+}
+
 pub const MAX_RESPONSE_SIZE: usize = 1024 * 1024; // 1 MB
 const MAX_REQUEST_SIZE: u64 = 1024 * 1024; // 1 MB
+const MAX_LISTEN_TIMEOUT: Duration = Duration::from_secs(60);
+const EVENT_STREAM_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
 
 static RAW_TEXT_OUTPUT: HeaderName = HeaderName::from_static("neon-raw-text-output");
 static ARRAY_MODE: HeaderName = HeaderName::from_static("neon-array-mode");
 static ALLOW_POOL: HeaderName = HeaderName::from_static("neon-pool-opt-in");
 
 static HEADER_VALUE_TRUE: HeaderValue = HeaderValue::from_static("true");
+static EVENT_STREAM: HeaderValue = HeaderValue::from_static("text/event-stream");
 
 //
 // Convert json non-string types to strings, so that they can be passed to Postgres
@@ -177,28 +198,17 @@ pub async fn handle(
     // Allow connection pooling only if explicitly requested
     let allow_pool = headers.get(&ALLOW_POOL) == Some(&HEADER_VALUE_TRUE);
 
-    let request_content_length = match request.body().size_hint().upper() {
-        Some(v) => v,
-        None => MAX_REQUEST_SIZE + 1,
-    };
-
-    if request_content_length > MAX_REQUEST_SIZE {
-        return Err(anyhow::anyhow!(
-            "request is too large (max {MAX_REQUEST_SIZE} bytes)"
-        ));
-    }
-
     //
     // Read the query and query params from the request body
     //
-    let body = hyper::body::to_bytes(request.into_body()).await?;
+    let body = read_body(request).await?;
     let QueryData { query, params } = serde_json::from_slice(&body)?;
     let query_params = json_to_pg_text(params)?;
 
     //
     // Now execute the query and return the result
     //
-    let client = conn_pool.get(&conn_info, !allow_pool).await?;
+    let mut client = conn_pool.get(&conn_info, !allow_pool).await?;
 
     let row_stream = client.query_raw_txt(query, query_params).await?;
 
@@ -230,6 +240,14 @@ pub async fn handle(
     }
     .and_then(|s| s.parse::<i64>().ok());
 
+    // Notifications sent to the connection while the query ran, e.g. by a NOTIFY of
+    // the query itself when the connection is listening.
+    let notifications = client
+        .take_notifications()
+        .iter()
+        .map(notification_to_json)
+        .collect::<Vec<_>>();
+
     let fields = if !rows.is_empty() {
         rows[0]
             .columns()
@@ -256,7 +274,9 @@ pub async fn handle(
         .map(|row| pg_text_row_to_json(row, raw_output, array_mode))
         .collect::<Result<Vec<_>, _>>()?;
 
-    if allow_pool {
+    // A listening connection would get the notifications of its channels delivered
+    // to whichever request picks it up next, so it is not returned to the pool.
+    if allow_pool && command_tag_name != "LISTEN" {
         // return connection to the pool
         tokio::task::spawn(async move {
             let _ = conn_pool.put(&conn_info, client).await;
@@ -264,13 +284,132 @@ pub async fn handle(
     }
 
     // resulting JSON format is based on the format of node-postgres result
-    Ok(json!({
+    let mut result = json!({
         "command": command_tag_name,
         "rowCount": command_tag_count,
         "rows": rows,
         "fields": fields,
         "rowAsArray": array_mode,
-    }))
+    });
+    if !notifications.is_empty() {
+        result["notifications"] = Value::Array(notifications);
+    }
+    Ok(result)
+}
+
+//
+// Listen on the channels of the request and deliver their notifications: as a
+// server-sent event stream if the client accepts one, otherwise by long-polling,
+// responding with the notifications once the first arrives or the timeout passes.
+// Notifications sent between two polls are missed, the event stream is for
+// continuous delivery.
+//
+pub async fn handle_listen(
+    request: Request<Body>,
+    sni_hostname: Option<String>,
+    conn_pool: Arc<GlobalConnPool>,
+) -> anyhow::Result<Response<Body>> {
+    let headers = request.headers();
+    let conn_info = get_conn_info(headers, sni_hostname)?;
+    let event_stream = headers.get(header::ACCEPT) == Some(&EVENT_STREAM);
+
+    let body = read_body(request).await?;
+    let ListenData {
+        channels,
+        timeout_ms,
+    } = serde_json::from_slice(&body)?;
+    if channels.is_empty() {
+        return Err(anyhow::anyhow!("no channels to listen on"));
+    }
+
+    // The connection stays subscribed to the channels, so it is never pooled.
+    let mut client = conn_pool.get(&conn_info, true).await?;
+    for channel in &channels {
+        let listen = format!("LISTEN \"{}\"", channel.replace('"', "\"\""));
+        client.batch_execute(&listen).await?;
+    }
+
+    if event_stream {
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(
+            async move {
+                let mut keepalive = tokio::time::interval(EVENT_STREAM_KEEPALIVE_INTERVAL);
+                loop {
+                    let event = tokio::select! {
+                        notification = client.next_notification() => match notification {
+                            Some(n) => notification_event(&n),
+                            None => break,
+                        },
+                        _ = keepalive.tick() => ":\n\n".to_string(),
+                    };
+                    if sender.send_data(Bytes::from(event)).await.is_err() {
+                        // the client went away
+                        break;
+                    }
+                }
+                info!("notification stream closed");
+            }
+            .in_current_span(),
+        );
+
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, EVENT_STREAM.clone())
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(body)?);
+    }
+
+    let timeout = Duration::from_millis(timeout_ms).min(MAX_LISTEN_TIMEOUT);
+    let mut notifications = Vec::new();
+    match tokio::time::timeout(timeout, client.next_notification()).await {
+        Ok(Some(first)) => {
+            notifications.push(first);
+            notifications.extend(client.take_notifications());
+        }
+        Ok(None) => return Err(anyhow::anyhow!("connection to compute closed")),
+        Err(_elapsed) => {}
+    }
+    let notifications = notifications
+        .iter()
+        .map(notification_to_json)
+        .collect::<Vec<_>>();
+
+    let body = serde_json::to_vec(&json!({ "notifications": notifications }))?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))?)
+}
+
+async fn read_body(request: Request<Body>) -> anyhow::Result<Bytes> {
+    let request_content_length = match request.body().size_hint().upper() {
+        Some(v) => v,
+        None => MAX_REQUEST_SIZE + 1,
+    };
+
+    if request_content_length > MAX_REQUEST_SIZE {
+        return Err(anyhow::anyhow!(
+            "request is too large (max {MAX_REQUEST_SIZE} bytes)"
+        ));
+    }
+
+    Ok(hyper::body::to_bytes(request.into_body()).await?)
+}
+
+fn notification_event(notification: &Notification) -> String {
+    let data = notification_to_json(notification);
+    format!("event: notification\ndata: {data}\n\n")
+}
+
+//
+// Convert a notification to JSON, in the format of node-postgres notification events
+//
+fn notification_to_json(notification: &Notification) -> Value {
+    json!({
+        "processId": notification.process_id(),
+        "channel": notification.channel(),
+        "payload": notification.payload(),
+    })
 }
 
 //
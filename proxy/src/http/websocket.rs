@@ -196,36 +196,44 @@ async fn ws_handler(
         let result = sql_over_http::handle(request, sni_hostname, conn_pool)
             .instrument(info_span!("sql-over-http"))
             .await;
-        let status_code = match result {
-            Ok(_) => StatusCode::OK,
-            Err(_) => StatusCode::BAD_REQUEST,
+        let (status_code, json) = match result {
+            Ok(r) => (StatusCode::OK, r),
+            Err(e) => (StatusCode::BAD_REQUEST, error_json(e)),
         };
-        let json = match result {
-            Ok(r) => r,
-            Err(e) => {
-                let message = format!("{:?}", e);
-                let code = match e.downcast_ref::<tokio_postgres::Error>() {
-                    Some(e) => match e.code() {
-                        Some(e) => serde_json::to_value(e.code()).unwrap(),
-                        None => Value::Null,
-                    },
-                    None => Value::Null,
-                };
-                json!({ "message": message, "code": code })
-            }
-        };
-        json_response(status_code, json).map(|mut r| {
-            r.headers_mut().insert(
-                "Access-Control-Allow-Origin",
-                hyper::http::HeaderValue::from_static("*"),
-            );
-            r
-        })
+        json_response(status_code, json).map(allow_any_origin)
+    } else if request.uri().path() == "/sql/listen" && request.method() == Method::POST {
+        let result = sql_over_http::handle_listen(request, sni_hostname, conn_pool)
+            .instrument(info_span!("sql-over-http-listen"))
+            .await;
+        match result {
+            Ok(r) => Ok(allow_any_origin(r)),
+            Err(e) => json_response(StatusCode::BAD_REQUEST, error_json(e)).map(allow_any_origin),
+        }
     } else {
         json_response(StatusCode::BAD_REQUEST, "query is not supported")
     }
 }
 
+fn error_json(e: anyhow::Error) -> Value {
+    let message = format!("{:?}", e);
+    let code = match e.downcast_ref::<tokio_postgres::Error>() {
+        Some(e) => match e.code() {
+            Some(e) => serde_json::to_value(e.code()).unwrap(),
+            None => Value::Null,
+        },
+        None => Value::Null,
+    };
+    json!({ "message": message, "code": code })
+}
+
+fn allow_any_origin(mut r: Response<Body>) -> Response<Body> {
+    r.headers_mut().insert(
+        "Access-Control-Allow-Origin",
+        hyper::http::HeaderValue::from_static("*"),
+    );
+    r
+}
+
 pub async fn task_main(
     config: &'static ProxyConfig,
     ws_listener: TcpListener,
@@ -47,6 +47,9 @@ PAGESERVER_PER_TENANT_REMOTE_TIMELINE_CLIENT_METRICS: Tuple[str, ...] = (
     "pageserver_remote_physical_size",
     "pageserver_remote_timeline_client_bytes_started_total",
     "pageserver_remote_timeline_client_bytes_finished_total",
+    "pageserver_remote_upload_queue_depth",
+    "pageserver_remote_upload_queue_oldest_op_timestamp_seconds",
+    "pageserver_remote_last_index_upload_timestamp_seconds",
 )
 
 PAGESERVER_GLOBAL_METRICS: Tuple[str, ...] = (
@@ -61,12 +64,15 @@ PAGESERVER_GLOBAL_METRICS: Tuple[str, ...] = (
     "pageserver_materialized_cache_hits_direct_total",
     "pageserver_page_cache_read_hits_total",
     "pageserver_page_cache_read_accesses_total",
+    "pageserver_page_cache_read_misses_total",
+    "pageserver_page_cache_evictions_total",
     "pageserver_page_cache_size_current_bytes",
     "pageserver_page_cache_size_max_bytes",
     "pageserver_getpage_reconstruct_seconds_bucket",
     "pageserver_getpage_reconstruct_seconds_count",
     "pageserver_getpage_reconstruct_seconds_sum",
     *[f"pageserver_basebackup_query_seconds_{x}" for x in ["bucket", "count", "sum"]],
+    "pageserver_background_job_failures_total",
 )
 
 PAGESERVER_PER_TENANT_METRICS: Tuple[str, ...] = (
@@ -96,5 +102,13 @@ PAGESERVER_PER_TENANT_METRICS: Tuple[str, ...] = (
     "pageserver_tenant_states_count",
     "pageserver_evictions_total",
     "pageserver_evictions_with_low_residence_duration_total",
+    "pageserver_wal_ingest_database_bytes_total",
+    "pageserver_wal_ingest_database_records_total",
+    "pageserver_tenant_wal_redo_seconds_sum_total",
+    "pageserver_tenant_wal_redo_seconds_count_total",
+    "pageserver_tenant_wal_redo_wait_seconds_sum_total",
+    "pageserver_tenant_wal_redo_records_total",
+    "pageserver_tenant_wal_redo_bytes_total",
+    "pageserver_tenant_wal_redo_cpu_seconds_total",
     *PAGESERVER_PER_TENANT_REMOTE_TIMELINE_CLIENT_METRICS,
 )
@@ -16,9 +16,11 @@ from fixtures.utils import Fn
 
 
 class PageserverApiException(Exception):
-    def __init__(self, message, status_code: int):
+    def __init__(self, message, status_code: int, code: Optional[str] = None):
         super().__init__(message)
         self.status_code = status_code
+        # Machine-readable error code from the error body, see ErrorCode in the pageserver.
+        self.code = code
 
 
 class TimelineCreate406(PageserverApiException):
@@ -112,6 +114,25 @@ class TenantConfig:
         )
 
 
+def list_query_params(
+    limit: Optional[int],
+    cursor: Optional[Any],
+    state: Optional[List[str]],
+    detail: Optional[str],
+) -> Dict[str, str]:
+    """Pagination and filtering parameters of the tenant and timeline list endpoints."""
+    params = {}
+    if limit is not None:
+        params["limit"] = str(limit)
+    if cursor is not None:
+        params["cursor"] = str(cursor)
+    if state is not None:
+        params["state"] = ",".join(state)
+    if detail is not None:
+        params["detail"] = detail
+    return params
+
+
 class PageserverHttpClient(requests.Session):
     def __init__(self, port: int, is_testing_enabled_or_skip: Fn, auth_token: Optional[str] = None):
         super().__init__()
@@ -131,14 +152,31 @@ class PageserverHttpClient(requests.Session):
             res.raise_for_status()
         except requests.RequestException as e:
             try:
-                msg = res.json()["msg"]
+                body = res.json()
+                msg = body["msg"]
+                code = body.get("code")
             except:  # noqa: E722
                 msg = ""
-            raise PageserverApiException(msg, res.status_code) from e
+                code = None
+            raise PageserverApiException(msg, res.status_code, code) from e
 
     def check_status(self):
         self.get(f"http://localhost:{self.port}/v1/status").raise_for_status()
 
+    def build_info(self) -> Dict[str, Any]:
+        res = self.get(f"http://localhost:{self.port}/v1/status/build")
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def openapi(self) -> Dict[str, Any]:
+        res = self.get(f"http://localhost:{self.port}/v1/openapi.json")
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
     def configure_failpoints(self, config_strings: Tuple[str, str] | List[Tuple[str, str]]):
         self.is_testing_enabled_or_skip()
 
@@ -159,8 +197,51 @@ class PageserverHttpClient(requests.Session):
         assert res_json is None
         return res_json
 
-    def tenant_list(self) -> List[Dict[Any, Any]]:
-        res = self.get(f"http://localhost:{self.port}/v1/tenant")
+    def list_failpoints(self) -> Dict[str, str]:
+        self.is_testing_enabled_or_skip()
+
+        res = self.get(f"http://localhost:{self.port}/v1/failpoints")
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, list)
+        return {fp["name"]: fp["actions"] for fp in res_json}
+
+    def remove_failpoint(self, name: str):
+        self.is_testing_enabled_or_skip()
+
+        log.info(f"Requesting failpoint removal: {name}")
+        res = self.delete(f"http://localhost:{self.port}/v1/failpoints/{name}")
+        self.verbose_error(res)
+
+    def configure_remote_storage_faults(self, faults: List[Dict[str, Any]]):
+        """
+        Replace the faults injected into the remote storage operations, each a dict with the
+        `op` kind and optionally `path_contains`, `latency_ms`, `errors` and `partial`.
+        """
+        self.is_testing_enabled_or_skip()
+
+        log.info(f"Requesting remote storage faults: {faults}")
+        res = self.put(f"http://localhost:{self.port}/v1/remote_storage_faults", json=faults)
+        self.verbose_error(res)
+
+    def list_remote_storage_faults(self) -> List[Dict[str, Any]]:
+        self.is_testing_enabled_or_skip()
+
+        res = self.get(f"http://localhost:{self.port}/v1/remote_storage_faults")
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, list)
+        return res_json
+
+    def tenant_list(
+        self,
+        limit: Optional[int] = None,
+        cursor: Optional[TenantId] = None,
+        state: Optional[List[str]] = None,
+        detail: Optional[str] = None,
+    ) -> List[Dict[Any, Any]]:
+        params = list_query_params(limit, cursor, state, detail)
+        res = self.get(f"http://localhost:{self.port}/v1/tenant", params=params)
         self.verbose_error(res)
         res_json = res.json()
         assert isinstance(res_json, list)
@@ -186,16 +267,28 @@ class PageserverHttpClient(requests.Session):
         return TenantId(new_tenant_id)
 
     def tenant_attach(
-        self, tenant_id: TenantId, config: None | Dict[str, Any] = None, config_null: bool = False
+        self,
+        tenant_id: TenantId,
+        config: None | Dict[str, Any] = None,
+        config_null: bool = False,
+        generation: Optional[int] = None,
+        import_source: Optional[Dict[str, Any]] = None,
     ):
         if config_null:
             assert config is None
+            assert generation is None
+            assert import_source is None
             body = "null"
         else:
             # null-config is prohibited by the API
             if config is None:
                 config = {}
-            body = json.dumps({"config": config})
+            request: Dict[str, Any] = {"config": config}
+            if generation is not None:
+                request["generation"] = generation
+            if import_source is not None:
+                request["import"] = import_source
+            body = json.dumps(request)
         res = self.post(
             f"http://localhost:{self.port}/v1/tenant/{tenant_id}/attach",
             data=body,
@@ -253,6 +346,35 @@ class PageserverHttpClient(requests.Session):
                 del current[key]
         self.set_tenant_config(tenant_id, current)
 
+    def tenant_upload_throttle(self, tenant_id: TenantId) -> Dict[str, Any]:
+        res = self.get(f"http://localhost:{self.port}/v1/tenant/{tenant_id}/upload_throttle")
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def set_tenant_upload_throttle(
+        self,
+        tenant_id: TenantId,
+        bytes_per_second: Optional[int] = None,
+        ops_per_second: Optional[int] = None,
+    ) -> Dict[str, Any]:
+        res = self.put(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/upload_throttle",
+            json={"bytes_per_second": bytes_per_second, "ops_per_second": ops_per_second},
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def tenant_wal_redo_status(self, tenant_id: TenantId) -> List[Dict[str, Any]]:
+        res = self.get(f"http://localhost:{self.port}/v1/tenant/{tenant_id}/wal_redo")
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, list)
+        return res_json
+
     def tenant_size(self, tenant_id: TenantId) -> int:
         return self.tenant_size_and_modelinputs(tenant_id)[0]
 
@@ -286,8 +408,12 @@ class PageserverHttpClient(requests.Session):
         tenant_id: TenantId,
         include_non_incremental_logical_size: bool = False,
         include_timeline_dir_layer_file_size_sum: bool = False,
+        limit: Optional[int] = None,
+        cursor: Optional[TimelineId] = None,
+        state: Optional[List[str]] = None,
+        detail: Optional[str] = None,
     ) -> List[Dict[str, Any]]:
-        params = {}
+        params = list_query_params(limit, cursor, state, detail)
         if include_non_incremental_logical_size:
             params["include-non-incremental-logical-size"] = "true"
         if include_timeline_dir_layer_file_size_sum:
@@ -338,6 +464,7 @@ class PageserverHttpClient(requests.Session):
         timeline_id: TimelineId,
         include_non_incremental_logical_size: bool = False,
         include_timeline_dir_layer_file_size_sum: bool = False,
+        check_remote_layer: Optional[str] = None,
         **kwargs,
     ) -> Dict[Any, Any]:
         params = {}
@@ -345,6 +472,8 @@ class PageserverHttpClient(requests.Session):
             params["include-non-incremental-logical-size"] = "true"
         if include_timeline_dir_layer_file_size_sum:
             params["include-timeline-dir-layer-file-size-sum"] = "true"
+        if check_remote_layer is not None:
+            params["check-remote-layer"] = check_remote_layer
 
         res = self.get(
             f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}",
@@ -413,6 +542,21 @@ class PageserverHttpClient(requests.Session):
         res_json = res.json()
         return res_json
 
+    def timeline_relation_sizes(
+        self, tenant_id: TenantId, timeline_id: TimelineId, lsn: Optional[Lsn] = None
+    ) -> Dict[str, Any]:
+        params = {}
+        if lsn is not None:
+            params["lsn"] = str(lsn)
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/relation_sizes",
+            params=params,
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
     def timeline_checkpoint(self, tenant_id: TenantId, timeline_id: TimelineId):
         self.is_testing_enabled_or_skip()
 
@@ -425,6 +569,81 @@ class PageserverHttpClient(requests.Session):
         res_json = res.json()
         assert res_json is None
 
+    def timeline_scrub_remote(self, tenant_id: TenantId, timeline_id: TimelineId) -> dict[str, Any]:
+        log.info(f"Requesting remote scrub: tenant {tenant_id}, timeline {timeline_id}")
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/scrub_remote"
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_upload_queue(self, tenant_id: TenantId, timeline_id: TimelineId) -> dict[str, Any]:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/upload_queue"
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_cancel_upload_task(
+        self, tenant_id: TenantId, timeline_id: TimelineId, task_id: int
+    ):
+        res = self.delete(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/upload_queue/{task_id}"
+        )
+        self.verbose_error(res)
+
+    def timeline_remote_consistency(
+        self, tenant_id: TenantId, timeline_id: TimelineId
+    ) -> dict[str, Any]:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/remote_consistency"
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_copy_remote(
+        self, tenant_id: TenantId, timeline_id: TimelineId, remote_storage: dict[str, Any]
+    ) -> dict[str, Any]:
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/copy_remote",
+            json={"remote_storage": remote_storage},
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_export(
+        self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        lsn: Optional[Lsn] = None,
+        remote_storage: Optional[dict[str, Any]] = None,
+        prefix: Optional[str] = None,
+        gzip: bool = False,
+    ) -> dict[str, Any]:
+        body: dict[str, Any] = {"gzip": gzip}
+        if lsn is not None:
+            body["lsn"] = str(lsn)
+        if remote_storage is not None:
+            body["remote_storage"] = remote_storage
+        if prefix is not None:
+            body["prefix"] = prefix
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/export",
+            json=body,
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
     def timeline_spawn_download_remote_layers(
         self,
         tenant_id: TenantId,
@@ -490,6 +709,18 @@ class PageserverHttpClient(requests.Session):
                 assert completed["successful_download_count"] > 0
             return completed
 
+    def timeline_prefetch_layers(
+        self, tenant_id: TenantId, timeline_id: TimelineId, **body: Any
+    ) -> dict[str, Any]:
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/prefetch_layers",
+            json=body,
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
     def get_metrics_str(self) -> str:
         """You probably want to use get_metrics() instead."""
         res = self.get(f"http://localhost:{self.port}/metrics")
@@ -0,0 +1,62 @@
+from fixtures.neon_fixtures import (
+    LocalFsStorage,
+    NeonEnvBuilder,
+    RemoteStorageKind,
+    wait_for_last_flush_lsn,
+)
+from fixtures.pageserver.utils import (
+    wait_for_upload_queue_empty,
+    wait_until_tenant_active,
+    wait_until_tenant_state,
+)
+from fixtures.utils import wait_until
+
+
+# An attachment with a generation writes the index files under it, and an attachment with an
+# earlier generation than that of the index files can't load the tenant.
+def test_attach_generation(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_attach_generation",
+    )
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 1000) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+    endpoint.stop()
+
+    assert isinstance(env.remote_storage, LocalFsStorage)
+    remote_timeline_dir = (
+        env.remote_storage.root / "tenants" / str(tenant_id) / "timelines" / str(timeline_id)
+    )
+
+    # The attachment writes the index of its generation right away
+    client.tenant_detach(tenant_id)
+    client.tenant_attach(tenant_id, generation=2)
+    wait_until_tenant_active(client, tenant_id)
+
+    def index_of_generation_written():
+        assert (remote_timeline_dir / "index_part.json-00000002").exists()
+
+    wait_until(20, 0.5, index_of_generation_written)
+
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM t") == [(1000,)]
+    endpoint.stop()
+
+    # An attachment of an earlier generation can't load the timeline, nor write its index
+    env.pageserver.allowed_errors.extend(
+        [
+            ".*attach failed, setting tenant state to Broken.*",
+            f".*Tenant {tenant_id} will not become active.*",
+        ]
+    )
+    client.tenant_detach(tenant_id)
+    client.tenant_attach(tenant_id, generation=1)
+    wait_until_tenant_state(client, tenant_id, "Broken", 10)
+    assert not (remote_timeline_dir / "index_part.json-00000001").exists()
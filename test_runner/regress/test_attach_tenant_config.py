@@ -157,6 +157,7 @@ def test_fully_custom_config(positive_env: NeonEnv):
         "compaction_target_size": 1048576,
         "checkpoint_distance": 10000,
         "checkpoint_timeout": "13m",
+        "deletion_dry_run": True,
         "eviction_policy": {
             "kind": "LayerAccessThreshold",
             "period": "20s",
@@ -169,10 +170,20 @@ def test_fully_custom_config(positive_env: NeonEnv):
         "image_creation_threshold": 7,
         "pitr_interval": "1m",
         "lagging_wal_timeout": "23m",
+        "max_inprogress_uploads": 4,
         "max_lsn_wal_lag": 230000,
         "min_resident_size_override": 23,
+        "prefetch_distance": 32,
+        "prefetch_max_inflight": 2,
+        "tiering_policy": {
+            "kind": "AgeThreshold",
+            "period": "1h",
+            "threshold": "30days",
+        },
         "trace_read_requests": True,
         "walreceiver_connect_timeout": "13m",
+        "walredo_cpu_time_limit": "1h",
+        "walredo_memory_limit": 1024 * 1024 * 1024,
     }
 
     ps_http = env.pageserver.http_client()
@@ -1,5 +1,5 @@
 from fixtures.metrics import parse_metrics
-from fixtures.neon_fixtures import NeonEnvBuilder, NeonProxy
+from fixtures.neon_fixtures import NeonEnv, NeonEnvBuilder, NeonProxy
 
 
 def test_build_info_metric(neon_env_builder: NeonEnvBuilder, link_proxy: NeonProxy):
@@ -17,3 +17,18 @@ def test_build_info_metric(neon_env_builder: NeonEnvBuilder, link_proxy: NeonPro
 
         assert "revision" in sample.labels
         assert len(sample.labels["revision"]) > 0
+
+
+def test_pageserver_build_info(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    client = env.pageserver.http_client()
+
+    build_info = client.build_info()
+    assert build_info["git_version"].startswith("git")
+    assert build_info["rustc_version"].startswith("rustc")
+    assert build_info["build_time"] > 0
+    assert env.pg_version in [str(v) for v in build_info["pg_versions"]]
+
+    sample = parse_metrics(client.get_metrics_str()).query_one("pageserver_build_info")
+    assert sample.labels["revision"] == build_info["git_version"]
+    assert sample.labels["rustc_version"] == build_info["rustc_version"]
@@ -0,0 +1,37 @@
+from fixtures.neon_fixtures import NeonEnv, wait_for_last_flush_lsn
+
+
+# TimescaleDB creates the chunks of a hypertable at a high rate, like an insert of rows spread
+# over a wide time range does in one transaction.
+def test_bulk_relation_create(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    client = env.pageserver.http_client()
+    endpoint = env.endpoints.create_start("main")
+
+    chunks = [f"_hyper_1_{i}_chunk" for i in range(500)]
+    endpoint.safe_psql(
+        "DO $$ BEGIN FOR i IN 0..499 LOOP"
+        " EXECUTE format('CREATE TABLE _hyper_1_%s_chunk (t timestamptz, v int)', i);"
+        " EXECUTE format('INSERT INTO _hyper_1_%s_chunk VALUES (now(), %s)', i, i);"
+        " END LOOP; END $$"
+    )
+    dbnode = endpoint.safe_psql("SELECT oid FROM pg_database WHERE datname = current_database()")
+    dbnode = dbnode[0][0]
+    relnodes = endpoint.safe_psql(
+        "SELECT relfilenode FROM pg_class WHERE relname LIKE '\\_hyper\\_%\\_chunk'"
+    )
+    assert len(relnodes) == len(chunks)
+    lsn = wait_for_last_flush_lsn(env, endpoint, env.initial_tenant, env.initial_timeline)
+
+    sizes = client.timeline_relation_sizes(env.initial_tenant, env.initial_timeline, lsn)
+    (database,) = [db for db in sizes["databases"] if db["dbnode"] == dbnode]
+    created = {rel["relnode"] for rel in database["relations"]} & {r[0] for r in relnodes}
+    assert len(created) == len(chunks)
+
+    # A restarted compute sees the same.
+    endpoint.stop()
+    endpoint.start()
+    rows = endpoint.safe_psql(
+        f"SELECT sum(v) FROM ({' UNION ALL '.join(f'SELECT v FROM {c}' for c in chunks)}) s"
+    )
+    assert rows[0][0] == sum(range(len(chunks)))
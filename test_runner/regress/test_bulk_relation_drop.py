@@ -0,0 +1,37 @@
+from fixtures.neon_fixtures import NeonEnv, wait_for_last_flush_lsn
+
+
+# A retention policy of TimescaleDB drops many chunks in one transaction.
+def test_bulk_relation_drop(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    client = env.pageserver.http_client()
+    endpoint = env.endpoints.create_start("main")
+
+    chunks = [f"_hyper_1_{i}_chunk" for i in range(200)]
+    endpoint.safe_psql_many(
+        [f"CREATE TABLE {chunk} AS SELECT g FROM generate_series(1, 100) g" for chunk in chunks]
+        + ["CREATE TABLE kept AS SELECT g FROM generate_series(1, 100) g"]
+    )
+    dbnode = endpoint.safe_psql("SELECT oid FROM pg_database WHERE datname = current_database()")
+    dbnode = dbnode[0][0]
+    relnodes = endpoint.safe_psql(
+        "SELECT relfilenode FROM pg_class WHERE relname LIKE '\\_hyper\\_%' OR relname = 'kept'"
+    )
+    assert len(relnodes) == len(chunks) + 1
+
+    endpoint.safe_psql(f"DROP TABLE {', '.join(chunks)}")
+    lsn = wait_for_last_flush_lsn(env, endpoint, env.initial_tenant, env.initial_timeline)
+
+    sizes = client.timeline_relation_sizes(env.initial_tenant, env.initial_timeline, lsn)
+    (database,) = [db for db in sizes["databases"] if db["dbnode"] == dbnode]
+    remaining = {rel["relnode"] for rel in database["relations"]} & {r[0] for r in relnodes}
+    assert [rel["name"] for rel in database["relations"] if rel["relnode"] in remaining] == ["kept"]
+
+    # A restarted compute sees the same.
+    endpoint.stop()
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM kept")[0][0] == 100
+    chunks_left = endpoint.safe_psql(
+        "SELECT count(*) FROM pg_class WHERE relname LIKE '\\_hyper\\_%'"
+    )
+    assert chunks_left[0][0] == 0
@@ -0,0 +1,57 @@
+import json
+
+from fixtures.neon_fixtures import (
+    LocalFsStorage,
+    NeonEnvBuilder,
+    RemoteStorageKind,
+    wait_for_last_flush_lsn,
+)
+from fixtures.pageserver.utils import wait_for_upload_queue_empty, wait_until_tenant_active
+
+
+# With remote_index_segment_layers, the index of a timeline with more layers is written as a
+# manifest and segments, and read back the same on attach.
+def test_chunked_index(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_chunked_index",
+    )
+    neon_env_builder.pageserver_config_override = "remote_index_segment_layers=2"
+    env = neon_env_builder.init_start(
+        initial_tenant_conf={
+            "gc_period": "0s",
+            "compaction_period": "0s",
+        }
+    )
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE foo (id int)")
+    for i in range(5):
+        start, end = i * 1000 + 1, (i + 1) * 1000
+        endpoint.safe_psql(f"INSERT INTO foo SELECT g FROM generate_series({start}, {end}) g")
+        wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+        client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+    endpoint.stop()
+
+    assert isinstance(env.remote_storage, LocalFsStorage)
+    remote_timeline_dir = (
+        env.remote_storage.root / "tenants" / str(tenant_id) / "timelines" / str(timeline_id)
+    )
+    manifest = json.loads((remote_timeline_dir / "index_part.json").read_text())
+    assert "timeline_layers" not in manifest
+    assert len(manifest["segments"]) > 0
+    assert len(manifest["tail"]) < 2
+    segment_files = [
+        p for p in remote_timeline_dir.iterdir() if p.name.startswith("index_segment-")
+    ]
+    assert len(segment_files) == len(manifest["segments"])
+
+    client.tenant_detach(tenant_id)
+    client.tenant_attach(tenant_id)
+    wait_until_tenant_active(client, tenant_id)
+
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*), sum(id) FROM foo") == [(5000, 5000 * 5001 // 2)]
@@ -0,0 +1,55 @@
+import pytest
+import toml
+from fixtures.neon_fixtures import NeonEnvBuilder
+from fixtures.pageserver.http import PageserverApiException
+from fixtures.types import TimelineId
+from fixtures.utils import wait_until
+
+
+def test_disk_space_monitor_refuses_timeline_creation(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    env.pageserver.allowed_errors.append(".*disk usage is critical.*")
+    env.pageserver.stop()
+
+    blocksize = 8192
+    total_blocks = 2**20
+    monitor_config = {
+        "period": "1s",
+        "critical_usage_pct": 100,
+        # More than the whole mocked filesystem, so usage is critical from the start.
+        "critical_avail_bytes": blocksize * total_blocks * 2,
+        "actions": ["refuse_timeline_creation"],
+        "mock_statvfs": {
+            "type": "Success",
+            "blocksize": blocksize,
+            "total_blocks": total_blocks,
+            # Only count layer files towards used bytes in the mock_statvfs.
+            "name_filter": ".*__.*",
+        },
+    }
+    enc = toml.TomlEncoder()
+    env.pageserver.start(
+        overrides=(
+            "--pageserver-config-override=disk_space_monitor="
+            + enc.dump_inline_table(monitor_config).replace("\n", " "),
+            "--pageserver-config-override=background_task_maximum_delay='0s'",
+        ),
+    )
+
+    client = env.pageserver.http_client()
+
+    def critical():
+        assert client.get_metric_value("pageserver_disk_space_critical") == 1
+
+    wait_until(10, 1, critical)
+    assert env.pageserver.log_contains(".*disk usage is critical.*")
+
+    with pytest.raises(PageserverApiException, match="disk usage is critical") as exc:
+        client.timeline_create(
+            env.pg_version,
+            env.initial_tenant,
+            TimelineId.generate(),
+            ancestor_timeline_id=env.initial_timeline,
+        )
+    assert exc.value.status_code == 503
+    assert exc.value.code == "disk_space_critical"
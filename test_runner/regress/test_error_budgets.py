@@ -0,0 +1,33 @@
+import pytest
+from fixtures.neon_fixtures import NeonEnvBuilder
+from fixtures.pageserver.http import PageserverApiException
+from fixtures.types import TenantId
+
+
+def test_error_budgets(neon_env_builder: NeonEnvBuilder):
+    # The HTTP API logs every failed request as an error.
+    neon_env_builder.pageserver_config_override = (
+        "error_budgets=[{ target = 'utils::http', max_errors = 2, window = '1h' }]"
+    )
+    env = neon_env_builder.init_start()
+    env.pageserver.allowed_errors.append(".*Error processing HTTP request: NotFound.*")
+    env.pageserver.allowed_errors.append(".*error budget exceeded.*")
+    client = env.pageserver.http_client()
+
+    def exceeded():
+        return client.get_metric_value(
+            "libmetrics_tracing_error_budget_exceeded_total", {"target": "utils::http"}
+        )
+
+    for _ in range(2):
+        with pytest.raises(PageserverApiException):
+            client.tenant_status(TenantId.generate())
+    assert not exceeded()
+    assert not env.pageserver.log_contains("error budget exceeded")
+
+    # Only the first error over the budget is reported within the window.
+    for _ in range(3):
+        with pytest.raises(PageserverApiException):
+            client.tenant_status(TenantId.generate())
+    assert exceeded() == 1
+    assert env.pageserver.log_contains("error budget exceeded")
@@ -1,13 +1,18 @@
 import os
 from pathlib import Path
 
+import pytest
 from fixtures.log_helper import log
 from fixtures.neon_fixtures import (
+    LocalFsStorage,
     NeonEnvBuilder,
     PgBin,
     PortDistributor,
+    RemoteStorageKind,
     VanillaPostgres,
+    wait_for_last_flush_lsn,
 )
+from fixtures.pageserver.http import PageserverApiException
 from fixtures.types import Lsn, TimelineId
 from fixtures.utils import query_scalar, subprocess_capture
 
@@ -73,3 +78,65 @@ def test_fullbackup(
         vanilla_pg.start()
         num_rows_found = vanilla_pg.safe_psql("select count(*) from tbl;", user="cloud_admin")[0][0]
         assert num_rows == num_rows_found
+
+
+# Ensure that regular postgres can start from a timeline exported to remote storage
+def test_timeline_export(
+    neon_env_builder: NeonEnvBuilder,
+    pg_bin: PgBin,
+    port_distributor: PortDistributor,
+    pg_distrib_dir: Path,
+):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_timeline_export",
+    )
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+    assert isinstance(env.remote_storage, LocalFsStorage)
+    tenant_id = env.initial_tenant
+
+    endpoint = env.endpoints.create_start("main")
+    timeline_id = env.initial_timeline
+    endpoint.safe_psql(f"CREATE TABLE tbl AS SELECT g AS id FROM generate_series(1, {num_rows}) g")
+    lsn = Lsn(endpoint.safe_psql("SELECT pg_current_wal_insert_lsn()")[0][0])
+    # Rows inserted after the LSN are not in the export
+    endpoint.safe_psql("INSERT INTO tbl SELECT g FROM generate_series(1, 10) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    endpoint.stop()
+
+    with pytest.raises(PageserverApiException, match="prefix must be a relative path"):
+        client.timeline_export(tenant_id, timeline_id, lsn=lsn, prefix="../escape")
+
+    export = client.timeline_export(tenant_id, timeline_id, lsn=lsn, prefix="nightly", gzip=True)
+    assert Lsn(export["lsn"]) == lsn
+    assert export["remote_path"].startswith(f"tenants/{tenant_id}/exports/nightly/{timeline_id}-")
+    tarball = env.remote_storage.root / export["remote_path"]
+    assert tarball.stat().st_size == export["size"]
+    # The temporary file is removed once uploaded
+    timeline_dir = env.timeline_dir(tenant_id, timeline_id)
+    assert not [f for f in os.listdir(timeline_dir) if f.startswith("export-")]
+
+    # To a remote storage of the caller's choice
+    destination = env.repo_dir / "export_destination"
+    destination.mkdir()
+    export = client.timeline_export(
+        tenant_id, timeline_id, lsn=lsn, remote_storage={"local_path": str(destination)}
+    )
+    assert (destination / export["remote_path"]).exists()
+
+    restored_dir_path = env.repo_dir / "restored_datadir"
+    os.mkdir(restored_dir_path, 0o750)
+    subprocess_capture(env.repo_dir, ["tar", "-xzf", str(tarball), "-C", str(restored_dir_path)])
+
+    # Like the fullbackup, the export has the neon specific pg_control and first WAL segment
+    psql_env = {"LD_LIBRARY_PATH": str(pg_distrib_dir / "lib")}
+    pg_resetwal_path = os.path.join(pg_bin.pg_bin_path, "pg_resetwal")
+    pg_bin.run_capture([pg_resetwal_path, "-D", str(restored_dir_path)], env=psql_env)
+
+    port = port_distributor.get_port()
+    with VanillaPostgres(restored_dir_path, pg_bin, port, init=False) as vanilla_pg:
+        vanilla_pg.configure([f"port={port}"])
+        vanilla_pg.start()
+        num_rows_found = vanilla_pg.safe_psql("select count(*) from tbl;", user="cloud_admin")[0][0]
+        assert num_rows == num_rows_found
@@ -0,0 +1,35 @@
+from fixtures.neon_fixtures import NeonEnvBuilder
+
+
+def test_getpage_timing(neon_env_builder: NeonEnvBuilder):
+    # Sample every request.
+    neon_env_builder.pageserver_config_override = "getpage_timing={sample_one_in = 1, trace = true}"
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 10000) g")
+    # Restart the endpoint, so that the pages are read from the pageserver.
+    endpoint.stop()
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM t")[0][0] == 10000
+
+    metrics = client.get_metrics()
+    counts = {
+        stage: metrics.query_one("pageserver_getpage_stage_seconds_count", {"stage": stage}).value
+        for stage in [
+            "layer_map_lookup",
+            "ondemand_download_wait",
+            "redo_wait",
+            "redo_execution",
+            "page_cache",
+        ]
+    }
+    # Every sampled request is recorded in all the stages, also those it skipped.
+    assert len(set(counts.values())) == 1
+    assert counts["layer_map_lookup"] > 0
+    # The table was written after the initial image layer, so its pages needed WAL redo.
+    redo = metrics.query_one("pageserver_getpage_stage_seconds_sum", {"stage": "redo_execution"})
+    assert redo.value > 0
+
+    assert env.pageserver.log_contains("sampled getpage request of")
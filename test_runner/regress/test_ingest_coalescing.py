@@ -0,0 +1,57 @@
+import time
+
+from fixtures.neon_fixtures import NeonEnvBuilder, wait_for_last_flush_lsn
+from fixtures.utils import wait_until
+
+
+def test_ingest_coalescing(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.pageserver_config_override = (
+        "ingest_coalescing={ max_rel_blocks = 16, max_delay = '1h' }"
+    )
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+
+    tenant_id, timeline_id = env.neon_cli.create_tenant(
+        conf={"checkpoint_timeout": "1 s", "compaction_period": "0s"}
+    )
+    endpoint = env.endpoints.create_start(
+        "main", tenant_id=tenant_id, config_lines=["autovacuum = off"]
+    )
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE invalidations (lowest bigint, greatest bigint)",
+            "CREATE TABLE big AS SELECT g FROM generate_series(1, 10000) g",
+        ]
+    )
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+    # Writing the big table was not coalesced, so the open layer is frozen on timeout.
+    def open_layer_flushed():
+        assert client.layer_map_info(tenant_id, timeline_id).in_memory_layers == []
+
+    wait_until(20, 0.5, open_layer_flushed)
+    layers = len(client.layer_map_info(tenant_id, timeline_id).historic_layers)
+
+    # Churn on the small table, for several checkpoint timeouts.
+    for i in range(25):
+        endpoint.safe_psql_many(
+            [
+                f"INSERT INTO invalidations VALUES ({i}, {i + 1})",
+                "DELETE FROM invalidations WHERE lowest < greatest",
+            ]
+        )
+        time.sleep(0.2)
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+    layer_map = client.layer_map_info(tenant_id, timeline_id)
+    assert len(layer_map.historic_layers) == layers
+    assert len(layer_map.in_memory_layers) == 1
+    assert client.get_metric_value("pageserver_ingest_deferred_freezes_total") >= 1
+
+    # A write to a big relation ends the coalescing.
+    endpoint.safe_psql("INSERT INTO big SELECT g FROM generate_series(1, 10000) g")
+
+    def new_layer():
+        assert len(client.layer_map_info(tenant_id, timeline_id).historic_layers) > layers
+
+    wait_until(20, 0.5, new_layer)
@@ -0,0 +1,58 @@
+import json
+
+from fixtures.neon_fixtures import (
+    LocalFsStorage,
+    NeonEnvBuilder,
+    RemoteStorageKind,
+    wait_for_last_flush_lsn,
+)
+from fixtures.pageserver.utils import wait_for_upload_queue_empty
+
+
+def is_delta_layer(name: str) -> bool:
+    return "-" in name.split("__")[1]
+
+
+# The index records how each layer file was created: the image layers of the initial import, and
+# the L0 delta layers flushed since.
+def test_layer_creation(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_layer_creation",
+    )
+    env = neon_env_builder.init_start(
+        initial_tenant_conf={
+            "gc_period": "0s",
+            "compaction_period": "0s",
+        }
+    )
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE foo AS SELECT g AS id FROM generate_series(1, 10000) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+    endpoint.stop()
+
+    assert isinstance(env.remote_storage, LocalFsStorage)
+    remote_timeline_dir = (
+        env.remote_storage.root / "tenants" / str(tenant_id) / "timelines" / str(timeline_id)
+    )
+    index_part = json.loads((remote_timeline_dir / "index_part.json").read_text())
+    assert index_part["version"] >= 7
+
+    sources = set()
+    for name, layer_metadata in index_part["layer_metadata"].items():
+        creation = layer_metadata["creation"]
+        assert isinstance(creation["node_id"], int)
+        assert "created_at" in creation
+        if is_delta_layer(name):
+            assert creation["source"] == "flush"
+            assert creation["compaction_level"] == 0
+        else:
+            assert creation["source"] == "import"
+            assert creation["compaction_level"] == 1
+        sources.add(creation["source"])
+    assert sources == {"import", "flush"}
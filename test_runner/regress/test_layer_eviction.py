@@ -3,11 +3,17 @@ import time
 import pytest
 from fixtures.log_helper import log
 from fixtures.neon_fixtures import (
+    LocalFsStorage,
     NeonEnvBuilder,
     RemoteStorageKind,
     wait_for_last_flush_lsn,
 )
-from fixtures.pageserver.utils import wait_for_last_record_lsn, wait_for_upload
+from fixtures.pageserver.http import PageserverApiException
+from fixtures.pageserver.utils import (
+    wait_for_last_record_lsn,
+    wait_for_upload,
+    wait_for_upload_queue_empty,
+)
 from fixtures.types import Lsn, TenantId, TimelineId
 from fixtures.utils import query_scalar
 
@@ -154,6 +160,47 @@ def test_basic_eviction(
     ), "Should have the same layer map after redownloading the evicted layers"
 
 
+# The timeline detail API checks that a layer file is in the remote storage before it's evicted.
+def test_check_remote_layer(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_check_remote_layer",
+    )
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+    assert isinstance(env.remote_storage, LocalFsStorage)
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("CREATE TABLE foo AS SELECT g AS id FROM generate_series(1, 10000) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+    endpoint.stop()
+
+    detail = client.timeline_detail(tenant_id, timeline_id)
+    assert detail["remote_layer_exists"] is None
+
+    layer = client.layer_map_info(tenant_id, timeline_id).historic_layers[0]
+    detail = client.timeline_detail(
+        tenant_id, timeline_id, check_remote_layer=layer.layer_file_name
+    )
+    assert detail["remote_layer_exists"] is True
+
+    # A layer file lost from the remote storage must not be evicted
+    remote_timeline_dir = (
+        env.remote_storage.root / "tenants" / str(tenant_id) / "timelines" / str(timeline_id)
+    )
+    (remote_timeline_dir / layer.layer_file_name).unlink()
+    detail = client.timeline_detail(
+        tenant_id, timeline_id, check_remote_layer=layer.layer_file_name
+    )
+    assert detail["remote_layer_exists"] is False
+
+    with pytest.raises(PageserverApiException, match="invalid check-remote-layer"):
+        client.timeline_detail(tenant_id, timeline_id, check_remote_layer="not-a-layer")
+
+
 def test_gc_of_remote_layers(neon_env_builder: NeonEnvBuilder):
     neon_env_builder.enable_remote_storage(
         remote_storage_kind=RemoteStorageKind.LOCAL_FS,
@@ -0,0 +1,33 @@
+from fixtures.neon_fixtures import NeonEnvBuilder
+from fixtures.utils import wait_until
+
+
+def test_layer_watermarks(neon_env_builder: NeonEnvBuilder):
+    # Every timeline with data has at least one layer, and is far from a TiB.
+    neon_env_builder.pageserver_config_override = (
+        "layer_watermarks={ layer_count = { warning = 1, critical = 1000000 }, "
+        f"local_size_bytes = {{ warning = {1024 ** 4}, critical = {2 * 1024 ** 4} }} }}"
+    )
+    env = neon_env_builder.init_start()
+    env.pageserver.allowed_errors.append(".*timeline layer_count crossed the warning watermark.*")
+    client = env.pageserver.http_client()
+
+    # The watermarks are checked after each compaction iteration.
+    tenant_id, timeline_id = env.neon_cli.create_tenant(conf={"compaction_period": "1 s"})
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 10000) g")
+    endpoint.stop()
+    client.timeline_checkpoint(tenant_id, timeline_id)
+
+    def level(resource: str):
+        return client.get_metric_value(
+            "pageserver_timeline_watermark_level",
+            {"tenant_id": str(tenant_id), "timeline_id": str(timeline_id), "resource": resource},
+        )
+
+    def warning():
+        assert level("layer_count") == 1
+
+    wait_until(20, 0.5, warning)
+    assert level("local_size") == 0
+    assert env.pageserver.log_contains("timeline layer_count crossed the warning watermark")
@@ -1,13 +1,15 @@
+import json
 import subprocess
 from pathlib import Path
 from typing import Optional
 
+import pytest
 from fixtures.neon_fixtures import (
     DEFAULT_BRANCH_NAME,
     NeonEnv,
     NeonEnvBuilder,
 )
-from fixtures.pageserver.http import PageserverHttpClient
+from fixtures.pageserver.http import PageserverApiException, PageserverHttpClient
 from fixtures.pg_version import PgVersion
 from fixtures.types import Lsn, TenantId, TimelineId
 from fixtures.utils import wait_until
@@ -62,6 +64,49 @@ def test_pageserver_init_node_id(
     assert "has node id already, it cannot be overridden" in bad_update.stderr
 
 
+def test_pageserver_check_config(neon_simple_env: NeonEnv, neon_binpath: Path):
+    env = neon_simple_env
+    pageserver_bin = neon_binpath / "pageserver"
+
+    def check_config(*overrides: str):
+        args = [a for o in overrides for a in ("-c", o)]
+        res = subprocess.run(
+            [str(pageserver_bin), "-D", str(env.repo_dir), "check-config", *args],
+            check=False,
+            universal_newlines=True,
+            stdout=subprocess.PIPE,
+            stderr=subprocess.PIPE,
+        )
+        return res.returncode, json.loads(res.stdout)
+
+    returncode, report = check_config()
+    errors = [d for d in report["diagnostics"] if d["severity"] == "error"]
+    assert returncode == 0 and report["ok"] and errors == [], report
+
+    # Conflicting settings don't stop the running pageserver from being checked,
+    # and the config file stays untouched.
+    config_before = (env.repo_dir / "pageserver.toml").read_text()
+    returncode, report = check_config(
+        f"listen_http_addr='localhost:{env.pageserver.service_port.pg}'",
+        "pg_distrib_dir='/nonexistent'",
+    )
+    assert returncode == 1
+    assert not report["ok"]
+    checks = {d["check"] for d in report["diagnostics"] if d["severity"] == "error"}
+    assert checks == {"overlapping_settings", "pg_binaries"}, report
+    assert (env.repo_dir / "pageserver.toml").read_text() == config_before
+
+    returncode, report = check_config("no_such_option=1")
+    assert returncode == 1
+    assert report["diagnostics"] == [
+        {
+            "check": "parse",
+            "severity": "error",
+            "message": "unrecognized pageserver option 'no_such_option'",
+        }
+    ]
+
+
 def check_client(pg_version: PgVersion, client: PageserverHttpClient, initial_tenant: TenantId):
     client.check_status()
 
@@ -121,6 +166,34 @@ def test_pageserver_http_get_wal_receiver_not_found(neon_simple_env: NeonEnv):
         ), "Should not be able to connect to WAL streaming without PG compute node running"
 
 
+def test_pageserver_http_error_codes(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    env.pageserver.allowed_errors.append(".*NotFound: tenant.*")
+    env.pageserver.allowed_errors.append(".*NotFound: Timeline .* was not found.*")
+
+    with env.pageserver.http_client() as client:
+        missing_tenant = TenantId.generate()
+        with pytest.raises(PageserverApiException) as exc:
+            client.tenant_status(missing_tenant)
+        assert exc.value.status_code == 404
+        assert exc.value.code == "tenant_not_found"
+
+        with pytest.raises(PageserverApiException) as exc:
+            client.timeline_detail(env.initial_tenant, TimelineId.generate())
+        assert exc.value.status_code == 404
+        assert exc.value.code == "timeline_not_found"
+
+        res = client.get(
+            f"http://localhost:{client.port}/v1/tenant/{missing_tenant}",
+            headers={"x-request-id": "error-codes-test"},
+        )
+        assert res.status_code == 404
+        body = res.json()
+        assert body["code"] == "tenant_not_found"
+        assert body["request_id"] == "error-codes-test"
+        assert body["retryable"] is False
+
+
 def expect_updated_msg_lsn(
     client: PageserverHttpClient,
     tenant_id: TenantId,
@@ -147,6 +220,88 @@ def expect_updated_msg_lsn(
     return last_msg_lsn
 
 
+def test_pageserver_openapi_json(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    client = env.pageserver.http_client()
+
+    doc = client.openapi()
+    assert doc["openapi"].startswith("3.")
+
+    timeline_list = doc["paths"]["/v1/tenant/{tenant_id}/timeline"]["get"]
+    assert [p["name"] for p in timeline_list["parameters"]] == [
+        "tenant_id",
+        "include-non-incremental-logical-size",
+        "limit",
+        "cursor",
+        "state",
+        "detail",
+    ]
+    schema = timeline_list["responses"]["200"]["content"]["application/json"]["schema"]
+    assert schema == {"type": "array", "items": {"$ref": "#/components/schemas/TimelineInfo"}}
+
+    # The described fields are the ones the handlers return.
+    schemas = doc["components"]["schemas"]
+    timeline = client.timeline_detail(env.initial_tenant, env.initial_timeline)
+    assert set(timeline.keys()) == set(schemas["TimelineInfo"]["properties"].keys())
+    tenant = client.tenant_status(env.initial_tenant)
+    assert set(tenant.keys()) == set(schemas["TenantInfo"]["properties"].keys())
+    build_info = client.build_info()
+    assert set(build_info.keys()) == set(schemas["BuildInfoResponse"]["properties"].keys())
+
+
+def test_pageserver_list_pagination(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    client = env.pageserver.http_client()
+
+    for _ in range(4):
+        env.neon_cli.create_tenant()
+
+    all_tenants = client.tenant_list()
+    tenant_ids = [t["id"] for t in all_tenants]
+    assert len(tenant_ids) >= 5
+    assert tenant_ids == sorted(tenant_ids)
+
+    # Page through the tenants, two at a time.
+    pages = []
+    cursor = None
+    while True:
+        page = client.tenant_list(limit=2, cursor=cursor)
+        if not page:
+            break
+        assert len(page) <= 2
+        pages.append(page)
+        cursor = page[-1]["id"]
+    assert [t["id"] for page in pages for t in page] == tenant_ids
+
+    active = client.tenant_list(state=["Active"])
+    assert [t["id"] for t in active] == tenant_ids
+    assert client.tenant_list(state=["Broken", "Attaching"]) == []
+
+    assert all(t.get("current_physical_size") is None for t in all_tenants)
+    full = client.tenant_list(limit=1, detail="full")
+    assert full[0]["current_physical_size"] is not None
+
+    for bad_params in ({"state": "Sleeping"}, {"limit": "0"}, {"detail": "everything"}):
+        with pytest.raises(PageserverApiException) as exc:
+            res = client.get(f"http://localhost:{client.port}/v1/tenant", params=bad_params)
+            client.verbose_error(res)
+        assert exc.value.status_code == 400
+
+    # The timeline list takes the same parameters.
+    timelines = [env.initial_timeline]
+    for name in ["branch1", "branch2"]:
+        timelines.append(env.neon_cli.create_branch(name, tenant_id=env.initial_tenant))
+    timeline_ids = sorted(str(t) for t in timelines)
+
+    page = client.timeline_list(env.initial_tenant, limit=2)
+    assert [t["timeline_id"] for t in page] == timeline_ids[:2]
+    page = client.timeline_list(env.initial_tenant, cursor=TimelineId(timeline_ids[1]))
+    assert [t["timeline_id"] for t in page] == timeline_ids[2:]
+    page = client.timeline_list(env.initial_tenant, state=["Active"], detail="full")
+    assert [t["timeline_id"] for t in page] == timeline_ids
+    assert all(t["current_logical_size_non_incremental"] is not None for t in page)
+
+
 # Test the WAL-receiver related fields in the response to `timeline_details` API call
 #
 # These fields used to be returned by a separate API call, but they're part of
@@ -190,3 +345,24 @@ def test_pageserver_http_api_client_auth_enabled(neon_env_builder: NeonEnvBuilde
 
     with env.pageserver.http_client(auth_token=pageserver_token) as client:
         check_client(env.pg_version, client, env.initial_tenant)
+
+
+def test_pageserver_failpoints_at_runtime(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    with env.pageserver.http_client() as client:
+        client.configure_failpoints(
+            [("before-delete-layer", "return"), ("persist_deleted_index_part", "sleep(100)")]
+        )
+        failpoints = client.list_failpoints()
+        assert failpoints["before-delete-layer"] == "return"
+        assert failpoints["persist_deleted_index_part"] == "sleep(100)"
+
+        client.remove_failpoint("before-delete-layer")
+        failpoints = client.list_failpoints()
+        assert "before-delete-layer" not in failpoints
+        assert "persist_deleted_index_part" in failpoints
+
+        with pytest.raises(PageserverApiException, match="is not configured"):
+            client.remove_failpoint("before-delete-layer")
+
+        client.remove_failpoint("persist_deleted_index_part")
@@ -125,3 +125,35 @@ def test_pageserver_chaos(neon_env_builder: NeonEnvBuilder):
         # Check that all the updates are visible
         num_updates = endpoint.safe_psql("SELECT sum(updates) FROM foo")[0][0]
         assert num_updates == i * 100000
+
+
+# Test that a graceful shutdown goes through all of its phases, in order.
+def test_pageserver_graceful_shutdown_phases(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("CREATE TABLE foo AS SELECT generate_series(1, 10000) AS x")
+    endpoint.stop()
+
+    env.pageserver.stop()
+
+    phases = [
+        "stop_accepting_requests",
+        "stop_wal_receivers",
+        "flush_in_memory_layers",
+        "drain_upload_queues",
+        "stop_tasks",
+        "stop_runtimes",
+    ]
+    for phase in phases:
+        assert env.pageserver.log_contains(f".*shutdown phase {phase} finished.*")
+    assert env.pageserver.log_contains(".*Shut down successfully completed.*")
+
+    # The phases are logged in order.
+    log_file = env.repo_dir / "pageserver.log"
+    finished = [
+        line.split("shutdown phase ")[1].split(" ")[0]
+        for line in log_file.read_text().splitlines()
+        if "shutdown phase" in line and "finished" in line
+    ]
+    assert finished[-len(phases) :] == phases
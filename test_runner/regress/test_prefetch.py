@@ -0,0 +1,57 @@
+import pytest
+from fixtures.neon_fixtures import (
+    NeonEnvBuilder,
+    RemoteStorageKind,
+    wait_for_last_flush_lsn,
+)
+from fixtures.pageserver.utils import wait_for_upload
+
+
+# A sequential scan of a relation whose layers are all evicted gets them downloaded ahead of it.
+@pytest.mark.parametrize("remote_storage_kind", [RemoteStorageKind.LOCAL_FS])
+def test_prefetch(neon_env_builder: NeonEnvBuilder, remote_storage_kind: RemoteStorageKind):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=remote_storage_kind,
+        test_name="test_prefetch",
+    )
+    env = neon_env_builder.init_start(
+        initial_tenant_conf={
+            # disable gc and compaction background loops because they perform on-demand downloads
+            "gc_period": "0s",
+            "compaction_period": "0s",
+            "prefetch_distance": "64",
+        }
+    )
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+    endpoint = env.endpoints.create_start("main", config_lines=["shared_buffers = 1MB"])
+
+    # Two chunks of a hypertable, scanned one after the other.
+    endpoint.safe_psql_many(
+        [
+            f"CREATE TABLE _hyper_1_{i}_chunk AS SELECT g, repeat('x', 100) AS note"
+            " FROM generate_series(1, 100000) g"
+            for i in (1, 2)
+        ]
+    )
+    lsn = wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload(client, tenant_id, timeline_id, lsn)
+
+    endpoint.stop()
+    client.evict_all_layers(tenant_id, timeline_id)
+    endpoint.start()
+
+    rows = endpoint.safe_psql(
+        "SELECT count(*) FROM (SELECT * FROM _hyper_1_1_chunk"
+        " UNION ALL SELECT * FROM _hyper_1_2_chunk) s"
+    )
+    assert rows[0][0] == 200000
+    assert client.get_metric_value("pageserver_prefetch_pages_total") > 0
+
+    # Without any prefetches allowed in flight, none are started.
+    client.set_tenant_config(tenant_id, {"prefetch_distance": 64, "prefetch_max_inflight": 0})
+    endpoint.stop()
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM _hyper_1_1_chunk")[0][0] == 100000
+    assert client.get_metric_value("pageserver_prefetch_skipped_total") > 0
@@ -0,0 +1,76 @@
+from fixtures.neon_fixtures import (
+    NeonEnvBuilder,
+    RemoteStorageKind,
+    wait_for_last_flush_lsn,
+)
+from fixtures.pageserver.utils import wait_for_upload_queue_empty
+
+
+# After all the layers are evicted, the prefetch downloads those that the reads at the latest
+# LSN need, and with an LSN range, all the layers in it.
+def test_prefetch_layers(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_prefetch_layers",
+    )
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+
+    tenant_id, timeline_id = env.neon_cli.create_tenant(
+        conf={
+            "checkpoint_distance": f"{128 * 1024}",
+            "compaction_threshold": "1",
+            "compaction_target_size": f"{128 * 1024}",
+            # We invoke compaction manually
+            "gc_period": "0s",
+            "compaction_period": "0s",
+            "image_creation_threshold": "1",
+        }
+    )
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE foo (id INTEGER PRIMARY KEY, val text)")
+    for data in ["a", "b"]:
+        endpoint.safe_psql(
+            f"""
+            INSERT INTO foo (id, val)
+            SELECT g, '{data}'
+            FROM generate_series(1, 10000) g
+            ON CONFLICT (id) DO UPDATE
+            SET val = EXCLUDED.val
+            """
+        )
+        wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+        client.timeline_checkpoint(tenant_id, timeline_id)
+        client.timeline_compact(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+    endpoint.stop()
+
+    def remote_layers():
+        layer_map = client.layer_map_info(tenant_id, timeline_id)
+        return [layer for layer in layer_map.historic_layers if layer.remote]
+
+    client.evict_all_layers(tenant_id, timeline_id)
+    total_layers = len(remote_layers())
+    assert total_layers > 0
+
+    result = client.timeline_prefetch_layers(tenant_id, timeline_id)
+    assert result["downloaded_layers"] > 0
+    assert result["downloaded_bytes"] > 0
+    assert result["failed_layers"] == 0
+    assert len(remote_layers()) == total_layers - result["downloaded_layers"]
+
+    # The prefetched layers are resident now
+    again = client.timeline_prefetch_layers(tenant_id, timeline_id)
+    assert again["downloaded_layers"] == 0
+    assert again["resident_layers"] == result["downloaded_layers"]
+
+    # The whole LSN range covers all the layers
+    client.timeline_prefetch_layers(
+        tenant_id, timeline_id, lsn_start="0/0", max_concurrent_downloads=2
+    )
+    assert remote_layers() == []
+
+    # Reads don't download anything
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM foo WHERE val = 'b'") == [(10000,)]
+    assert remote_layers() == []
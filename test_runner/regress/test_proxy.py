@@ -1,5 +1,7 @@
 import json
 import subprocess
+import time
+from concurrent.futures import ThreadPoolExecutor
 from typing import Any, List
 
 import psycopg2
@@ -258,3 +260,87 @@ def test_sql_over_http_output_options(static_proxy: NeonProxy):
 
     rows = q("select 1 as n, 'a' as s, '{1,2,3}'::int4[] as arr", True, True)["rows"]
     assert rows == [["1", "a", "{1,2,3}"]]
+
+
+def test_sql_over_http_listen(static_proxy: NeonProxy):
+    static_proxy.safe_psql("create role http3 with login password 'http3' superuser")
+    connstr = f"postgresql://http3:http3@{static_proxy.domain}:{static_proxy.proxy_port}/postgres"
+    url = f"https://{static_proxy.domain}:{static_proxy.external_http_port}/sql/listen"
+    verify = str(static_proxy.test_output_dir / "proxy.crt")
+
+    def listen(channels: List[str], timeout_ms: int) -> Any:
+        response = requests.post(
+            url,
+            data=json.dumps({"channels": channels, "timeout_ms": timeout_ms}),
+            headers={"Content-Type": "application/json", "Neon-Connection-String": connstr},
+            verify=verify,
+        )
+        assert response.status_code == 200
+        return response.json()
+
+    # Long-polling, without a notification until the timeout.
+    assert listen(["alerts"], 100) == {"notifications": []}
+
+    # Long-polling, notified while waiting.
+    with ThreadPoolExecutor(max_workers=1) as executor:
+        poll = executor.submit(listen, ["alerts", "jobs"], 20000)
+        while not poll.done():
+            static_proxy.safe_psql("NOTIFY jobs, 'job 1000 finished'")
+            time.sleep(0.5)
+        notifications = poll.result()["notifications"]
+    assert len(notifications) >= 1
+    assert notifications[0]["channel"] == "jobs"
+    assert notifications[0]["payload"] == "job 1000 finished"
+
+    # As an event stream, subscribed once the response starts.
+    with requests.post(
+        url,
+        data=json.dumps({"channels": ["alerts"]}),
+        headers={
+            "Content-Type": "application/json",
+            "Neon-Connection-String": connstr,
+            "Accept": "text/event-stream",
+        },
+        verify=verify,
+        stream=True,
+    ) as response:
+        assert response.status_code == 200
+        assert response.headers["Content-Type"] == "text/event-stream"
+        static_proxy.safe_psql("NOTIFY alerts, 'disk full'")
+        static_proxy.safe_psql("NOTIFY alerts, 'disk still full'")
+        events = []
+        for line in response.iter_lines(decode_unicode=True):
+            if line.startswith("data: "):
+                events.append(json.loads(line[len("data: ") :]))
+            if len(events) == 2:
+                break
+    assert [(e["channel"], e["payload"]) for e in events] == [
+        ("alerts", "disk full"),
+        ("alerts", "disk still full"),
+    ]
+
+
+def test_sql_over_http_notifications(static_proxy: NeonProxy):
+    static_proxy.safe_psql("create role http4 with login password 'http4' superuser")
+
+    def q(sql: str) -> Any:
+        connstr = (
+            f"postgresql://http4:http4@{static_proxy.domain}:{static_proxy.proxy_port}/postgres"
+        )
+        response = requests.post(
+            f"https://{static_proxy.domain}:{static_proxy.external_http_port}/sql",
+            data=json.dumps({"query": sql, "params": []}),
+            headers={
+                "Content-Type": "application/sql",
+                "Neon-Connection-String": connstr,
+                "Neon-Pool-Opt-In": "true",
+            },
+            verify=str(static_proxy.test_output_dir / "proxy.crt"),
+        )
+        assert response.status_code == 200
+        return response.json()
+
+    # A listening connection is not pooled, so its notifications don't leak to later requests.
+    assert q("LISTEN alerts")["command"] == "LISTEN"
+    static_proxy.safe_psql("NOTIFY alerts, 'disk full'")
+    assert "notifications" not in q("SELECT 1")
@@ -0,0 +1,94 @@
+from fixtures.neon_fixtures import NeonEnv, wait_for_last_flush_lsn
+
+# The catalog table of TimescaleDB, with the columns of the 2.x versions.
+HYPERTABLE_CATALOG = """
+CREATE TABLE _timescaledb_catalog.hypertable (
+    id serial NOT NULL,
+    schema_name name NOT NULL,
+    table_name name NOT NULL,
+    associated_schema_name name NOT NULL,
+    associated_table_prefix name NOT NULL,
+    num_dimensions smallint NOT NULL,
+    chunk_sizing_func_schema name NOT NULL,
+    chunk_sizing_func_name name NOT NULL,
+    chunk_target_size int8 NOT NULL,
+    compression_state smallint NOT NULL DEFAULT 0,
+    compressed_hypertable_id integer,
+    replication_factor smallint NULL
+)
+"""
+
+
+def test_relation_sizes(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    client = env.pageserver.http_client()
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 10000) g")
+    lsn_before = wait_for_last_flush_lsn(env, endpoint, env.initial_tenant, env.initial_timeline)
+
+    # Lay out the storage like TimescaleDB does, without the extension: hypertable 1 with two
+    # chunks, and its internal hypertable 2 with one compressed chunk.
+    endpoint.safe_psql_many(
+        [
+            "CREATE SCHEMA _timescaledb_catalog",
+            "CREATE SCHEMA _timescaledb_internal",
+            HYPERTABLE_CATALOG,
+            """INSERT INTO _timescaledb_catalog.hypertable VALUES
+                (1, 'public', 'conditions', '_timescaledb_internal', '_hyper_1', 1,
+                 '_timescaledb_internal', 'calculate_chunk_interval', 0, 1, 2, NULL),
+                (2, '_timescaledb_internal', '_compressed_hypertable_2', '_timescaledb_internal',
+                 '_hyper_2', 0, '_timescaledb_internal', 'calculate_chunk_interval', 0, 2, NULL,
+                 NULL)""",
+            "CREATE TABLE conditions (time timestamptz, note text)",
+            """CREATE TABLE _timescaledb_internal._hyper_1_1_chunk AS
+                SELECT now() AS time, repeat('x', g) AS note FROM generate_series(1, 1000) g""",
+            """CREATE INDEX _hyper_1_1_chunk_conditions_time_idx
+                ON _timescaledb_internal._hyper_1_1_chunk (time)""",
+            """CREATE TABLE _timescaledb_internal._hyper_1_2_chunk AS
+                SELECT now() AS time, 'y' AS note FROM generate_series(1, 1000) g""",
+            """CREATE TABLE _timescaledb_internal.compress_hyper_2_3_chunk AS
+                SELECT 'z' AS data FROM generate_series(1, 1000) g""",
+        ]
+    )
+    dbnode = endpoint.safe_psql("SELECT oid FROM pg_database WHERE datname = current_database()")
+    dbnode = dbnode[0][0]
+    toast_name = endpoint.safe_psql(
+        "SELECT 'pg_toast_' || '_timescaledb_internal._hyper_1_1_chunk'::regclass::oid"
+    )[0][0]
+    t_size, chunks_size, compressed_size = endpoint.safe_psql(
+        """SELECT pg_relation_size('t'),
+            pg_relation_size('_timescaledb_internal._hyper_1_1_chunk')
+                + pg_relation_size('_timescaledb_internal._hyper_1_2_chunk'),
+            pg_relation_size('_timescaledb_internal.compress_hyper_2_3_chunk')"""
+    )[0]
+    lsn = wait_for_last_flush_lsn(env, endpoint, env.initial_tenant, env.initial_timeline)
+
+    sizes = client.timeline_relation_sizes(env.initial_tenant, env.initial_timeline, lsn)
+    assert sizes["lsn"] == str(lsn)
+    (database,) = [db for db in sizes["databases"] if db["dbnode"] == dbnode]
+    relations = {(rel["schema"], rel["name"]): rel for rel in database["relations"]}
+
+    # The sizes are of all the forks, so at least the size of the main fork.
+    assert relations[("public", "t")]["size_bytes"] >= t_size
+    assert ("pg_catalog", "pg_class") in relations
+    # The chunks are not listed one by one, nor their indexes and TOAST relations.
+    for schema, name in relations:
+        assert schema != "_timescaledb_internal", name
+        assert name != toast_name
+
+    (hypertable,) = database["hypertables"]
+    assert hypertable["id"] == 1
+    assert hypertable["schema"] == "public"
+    assert hypertable["name"] == "conditions"
+    assert hypertable["chunks"] == 2
+    assert hypertable["compressed_size_bytes"] >= compressed_size
+    assert hypertable["size_bytes"] >= chunks_size + hypertable["compressed_size_bytes"]
+
+    # At an earlier LSN, before the chunks were created.
+    sizes = client.timeline_relation_sizes(env.initial_tenant, env.initial_timeline, lsn_before)
+    assert sizes["lsn"] == str(lsn_before)
+    (database,) = [db for db in sizes["databases"] if db["dbnode"] == dbnode]
+    assert database["hypertables"] == []
+    relations = {(rel["schema"], rel["name"]) for rel in database["relations"]}
+    assert ("public", "t") in relations
+    assert ("public", "conditions") not in relations
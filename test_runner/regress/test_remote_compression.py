@@ -0,0 +1,73 @@
+import json
+
+from fixtures.neon_fixtures import (
+    LocalFsStorage,
+    NeonEnvBuilder,
+    RemoteStorageKind,
+    wait_for_last_flush_lsn,
+)
+from fixtures.pageserver.utils import wait_for_upload_queue_empty
+
+
+# With compression, the layer files are smaller in the remote storage, and read back the same
+# once evicted and downloaded again.
+def test_remote_compression(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_remote_compression",
+    )
+    neon_env_builder.pageserver_config_override = (
+        "remote_compression={ algorithm = 'zstd', level = 3 }"
+    )
+    env = neon_env_builder.init_start(
+        initial_tenant_conf={
+            # don't download the evicted layers in the background
+            "gc_period": "0s",
+            "compaction_period": "0s",
+        }
+    )
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE notes (g int, note text)",
+            "INSERT INTO notes SELECT g, repeat('abc', 40) FROM generate_series(1, 10000) g",
+        ]
+    )
+    expected = endpoint.safe_psql("SELECT count(*), sum(g), sum(length(note)) FROM notes")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+
+    assert isinstance(env.remote_storage, LocalFsStorage)
+    remote_timeline_dir = (
+        env.remote_storage.root / "tenants" / str(tenant_id) / "timelines" / str(timeline_id)
+    )
+    index_part = json.loads((remote_timeline_dir / "index_part.json").read_text())
+    compressed = {
+        name: layer_metadata
+        for name, layer_metadata in index_part["layer_metadata"].items()
+        if "compression" in layer_metadata
+    }
+    assert len(compressed) > 0
+    for name, layer_metadata in compressed.items():
+        compression = layer_metadata["compression"]
+        assert compression["algorithm"] == "zstd"
+        remote_size = (remote_timeline_dir / name).stat().st_size
+        assert remote_size == compression["compressed_size"]
+        assert remote_size < layer_metadata["file_size"]
+
+    # Read the data back from the downloaded layers
+    endpoint.stop()
+    client.evict_all_layers(tenant_id, timeline_id)
+    endpoint.start()
+    rows = endpoint.safe_psql("SELECT count(*), sum(g), sum(length(note)) FROM notes")
+    assert rows == expected
+
+    timeline_dir = env.timeline_dir(tenant_id, timeline_id)
+    for name, layer_metadata in compressed.items():
+        local_layer = timeline_dir / name
+        if local_layer.exists():
+            assert local_layer.stat().st_size == layer_metadata["file_size"]
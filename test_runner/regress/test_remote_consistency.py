@@ -0,0 +1,53 @@
+from fixtures.neon_fixtures import (
+    LocalFsStorage,
+    NeonEnvBuilder,
+    RemoteStorageKind,
+    wait_for_last_flush_lsn,
+)
+from fixtures.pageserver.utils import wait_for_upload_queue_empty
+
+
+# The consistency check reports the layer files that the index references but that are missing
+# from the remote storage, or have another size there.
+def test_remote_consistency(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_remote_consistency",
+    )
+    env = neon_env_builder.init_start()
+    env.pageserver.allowed_errors.append(".*remote storage is inconsistent with the index.*")
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 1000) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+    endpoint.stop()
+
+    report = client.timeline_remote_consistency(tenant_id, timeline_id)
+    assert report["checked_layers"] > 0
+    assert report["missing_layers"] == []
+    assert report["mismatched_layers"] == []
+
+    assert isinstance(env.remote_storage, LocalFsStorage)
+    remote_timeline_dir = (
+        env.remote_storage.root / "tenants" / str(tenant_id) / "timelines" / str(timeline_id)
+    )
+    layers = sorted(p for p in remote_timeline_dir.iterdir() if "__" in p.name)
+    assert len(layers) >= 2
+
+    # Lose a layer, and truncate another one
+    layers[0].unlink()
+    size = layers[1].stat().st_size
+    with open(layers[1], "r+b") as f:
+        f.truncate(size // 2)
+
+    report = client.timeline_remote_consistency(tenant_id, timeline_id)
+    assert report["missing_layers"] == [layers[0].name]
+    assert len(report["mismatched_layers"]) == 1
+    mismatch = report["mismatched_layers"][0]
+    assert mismatch["layer_file_name"] == layers[1].name
+    assert mismatch["expected_size"] == size
+    assert mismatch["remote_size"] == size // 2
@@ -0,0 +1,84 @@
+from fixtures.neon_fixtures import (
+    LocalFsStorage,
+    NeonEnvBuilder,
+    RemoteStorageKind,
+    wait_for_last_flush_lsn,
+)
+from fixtures.pageserver.utils import wait_for_upload_queue_empty, wait_until_tenant_active
+from fixtures.utils import wait_until
+
+
+# With a grace period, the layer files removed by compaction and GC stay in the remote storage
+# until the grace period has passed, also across a restart.
+def test_remote_deletion_grace_period(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_remote_deletion_grace_period",
+    )
+    neon_env_builder.pageserver_config_override = "remote_deletion_grace_period='10s'"
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+
+    tenant_id, timeline_id = env.neon_cli.create_tenant(
+        conf={
+            "checkpoint_distance": f"{128 * 1024}",
+            "compaction_threshold": "1",
+            "compaction_target_size": f"{128 * 1024}",
+            "pitr_interval": "0s",
+            # We invoke compaction and GC manually
+            "gc_period": "0s",
+            "compaction_period": "0s",
+            "image_creation_threshold": "1",
+        }
+    )
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE foo (id INTEGER PRIMARY KEY, val text)")
+
+    for data in ["a", "b"]:
+        endpoint.safe_psql_many(
+            [
+                f"""
+                INSERT INTO foo (id, val)
+                SELECT g, '{data}'
+                FROM generate_series(1, 10000) g
+                ON CONFLICT (id) DO UPDATE
+                SET val = EXCLUDED.val
+                """,
+                "VACUUM foo",
+            ]
+        )
+        wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+        client.timeline_checkpoint(tenant_id, timeline_id)
+        client.timeline_compact(tenant_id, timeline_id)
+    gc_result = client.timeline_gc(tenant_id, timeline_id, 0)
+    assert gc_result["layers_removed"] > 0
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+
+    assert isinstance(env.remote_storage, LocalFsStorage)
+    remote_timeline_dir = (
+        env.remote_storage.root / "tenants" / str(tenant_id) / "timelines" / str(timeline_id)
+    )
+
+    def dereferenced_remote_layers():
+        remote_layers = {p.name for p in remote_timeline_dir.iterdir() if "__" in p.name}
+        layer_map = client.layer_map_info(tenant_id, timeline_id)
+        return remote_layers - {layer.layer_file_name for layer in layer_map.historic_layers}
+
+    # The removed layers are still in the remote storage
+    deferred = dereferenced_remote_layers()
+    assert len(deferred) > 0
+
+    # The deferred deletions are persisted, and executed after the restart
+    endpoint.stop()
+    env.pageserver.stop()
+    env.pageserver.start()
+    wait_until_tenant_active(client, tenant_id)
+
+    def deferred_deletions_executed():
+        assert dereferenced_remote_layers() == set()
+
+    wait_until(40, 1, deferred_deletions_executed)
+    assert env.pageserver.log_contains(".*executed .* deferred deletions.*")
+
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM foo WHERE val = 'b'") == [(10000,)]
@@ -0,0 +1,73 @@
+import json
+import os
+from pathlib import Path
+
+from fixtures.neon_fixtures import (
+    LocalFsStorage,
+    NeonEnvBuilder,
+    RemoteStorageKind,
+    wait_for_last_flush_lsn,
+)
+from fixtures.pageserver.utils import wait_for_upload_queue_empty
+
+MARKER = b"remote-encryption-marker"
+
+
+# With client-side encryption, the layer files are encrypted in the remote storage, and read back
+# the same once evicted and downloaded again.
+def test_remote_encryption_client_side(neon_env_builder: NeonEnvBuilder, test_output_dir: Path):
+    key_file = test_output_dir / "remote_encryption.key"
+    key_file.write_text(os.urandom(32).hex())
+
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_remote_encryption_client_side",
+    )
+    neon_env_builder.pageserver_config_override = (
+        f"remote_encryption={{ mode = 'client_side', key_file = '{key_file}' }}"
+    )
+    env = neon_env_builder.init_start(
+        initial_tenant_conf={
+            # don't download the evicted layers in the background
+            "gc_period": "0s",
+            "compaction_period": "0s",
+        }
+    )
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE notes (g int, note text)",
+            f"INSERT INTO notes SELECT g, '{MARKER.decode()}' FROM generate_series(1, 10000) g",
+        ]
+    )
+    expected = endpoint.safe_psql("SELECT count(*), sum(g) FROM notes WHERE note LIKE 'remote%'")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+
+    assert isinstance(env.remote_storage, LocalFsStorage)
+    remote_timeline_dir = (
+        env.remote_storage.root / "tenants" / str(tenant_id) / "timelines" / str(timeline_id)
+    )
+    index_part = json.loads((remote_timeline_dir / "index_part.json").read_text())
+    assert len(index_part["layer_metadata"]) > 0
+    for layer_metadata in index_part["layer_metadata"].values():
+        assert layer_metadata["encryption"] == "chunked_aes256_gcm"
+        assert "crc32c" in layer_metadata
+
+    timeline_dir = env.timeline_dir(tenant_id, timeline_id)
+    local_layers = [timeline_dir / name for name in index_part["layer_metadata"]]
+    assert any(MARKER in layer.read_bytes() for layer in local_layers)
+    for name in index_part["layer_metadata"]:
+        assert MARKER not in (remote_timeline_dir / name).read_bytes()
+
+    # Read the data back from the downloaded layers
+    endpoint.stop()
+    client.evict_all_layers(tenant_id, timeline_id)
+    endpoint.start()
+    rows = endpoint.safe_psql("SELECT count(*), sum(g) FROM notes WHERE note LIKE 'remote%'")
+    assert rows == expected
+    assert any(MARKER in layer.read_bytes() for layer in local_layers)
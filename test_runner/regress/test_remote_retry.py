@@ -0,0 +1,85 @@
+from fixtures.neon_fixtures import (
+    NeonEnvBuilder,
+    RemoteStorageKind,
+    wait_for_last_flush_lsn,
+)
+from fixtures.pageserver.utils import wait_for_upload_queue_empty, wait_until_tenant_active
+from fixtures.utils import wait_until
+
+
+# With `give_up = 'cancel'`, a layer upload that keeps failing is cancelled after its attempts,
+# along with the index upload waiting for it, and the upload queue goes on.
+def test_upload_gives_up(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_upload_gives_up",
+    )
+    neon_env_builder.pageserver_config_override = (
+        "remote_retry={ upload = { max_attempts = 3, give_up = 'cancel' } }"
+    )
+    env = neon_env_builder.init_start()
+    env.pageserver.allowed_errors.extend(
+        [
+            ".*failed to perform remote task UploadLayer.*",
+            ".*remote task UploadLayer.*still failed after 3 attempts, giving up.*",
+            ".*cancelled the upload of layer.*",
+        ]
+    )
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    client.configure_failpoints(("before-upload-layer", "return"))
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 10000) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+    # The checkpoint waits for the uploads, until they give up
+    client.timeline_checkpoint(tenant_id, timeline_id)
+
+    def layer_uploads_cancelled():
+        info = client.timeline_upload_queue(tenant_id, timeline_id)
+        assert info["state"] == "initialized"
+        assert info["inprogress_tasks"] == []
+        assert info["queued_operations"] == []
+
+    wait_until(20, 0.5, layer_uploads_cancelled)
+    assert env.pageserver.log_contains(".*still failed after 3 attempts, giving up.*")
+
+
+# The faults injected through the management API fail the index uploads, leaving truncated
+# index files behind, and the retries of the upload queue replace them once the faults run out.
+def test_upload_retries_injected_faults(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_upload_retries_injected_faults",
+    )
+    env = neon_env_builder.init_start()
+    env.pageserver.allowed_errors.append(".*failed to perform remote task UploadMetadata.*")
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+
+    client.configure_remote_storage_faults(
+        [
+            {"op": "upload", "path_contains": "index_part.json", "errors": 3, "partial": True},
+            {"op": "upload", "latency_ms": 100, "errors": 0},
+        ]
+    )
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 10000) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+
+    faults = client.list_remote_storage_faults()
+    assert [fault["errors"] for fault in faults] == [0, 0]
+    assert env.pageserver.log_contains(".*injected failure of remote Upload operation.*")
+    client.configure_remote_storage_faults([])
+    endpoint.stop()
+
+    # The index file uploaded last is whole
+    client.tenant_detach(tenant_id)
+    client.tenant_attach(tenant_id)
+    wait_until_tenant_active(client, tenant_id)
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM t") == [(10000,)]
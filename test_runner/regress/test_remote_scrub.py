@@ -0,0 +1,60 @@
+import shutil
+
+from fixtures.neon_fixtures import (
+    LocalFsStorage,
+    NeonEnvBuilder,
+    RemoteStorageKind,
+    wait_for_last_flush_lsn,
+)
+from fixtures.pageserver.utils import wait_for_upload_queue_empty
+
+
+# A layer file in the remote storage that the index doesn't reference is deleted by the scrub,
+# and the layers of the index are left alone.
+def test_remote_scrub(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_remote_scrub",
+    )
+    # Scrub only through the management API
+    neon_env_builder.pageserver_config_override = "remote_scrub={ period = '0s', min_age = '0s' }"
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 1000) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+
+    assert isinstance(env.remote_storage, LocalFsStorage)
+    remote_timeline_dir = (
+        env.remote_storage.root / "tenants" / str(tenant_id) / "timelines" / str(timeline_id)
+    )
+    remote_files = sorted(p.name for p in remote_timeline_dir.iterdir())
+
+    # Nothing to delete yet
+    report = client.timeline_scrub_remote(tenant_id, timeline_id)
+    assert report["deleted_objects"] == 0
+    assert sorted(p.name for p in remote_timeline_dir.iterdir()) == remote_files
+
+    # A layer left behind by a crash before the index upload
+    layer = next(p for p in remote_timeline_dir.iterdir() if "__" in p.name)
+    leaked = (
+        remote_timeline_dir
+        / "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000000000001-0000000000000002"
+    )
+    shutil.copyfile(layer, leaked)
+
+    report = client.timeline_scrub_remote(tenant_id, timeline_id)
+    assert report["deleted_objects"] == 1
+    assert report["deleted_bytes"] == layer.stat().st_size
+    assert not leaked.exists()
+    assert sorted(p.name for p in remote_timeline_dir.iterdir()) == remote_files
+
+    # The layers of the index are still there to read the data back
+    endpoint.stop()
+    client.evict_all_layers(tenant_id, timeline_id)
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM t") == [(1000,)]
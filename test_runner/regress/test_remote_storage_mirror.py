@@ -0,0 +1,51 @@
+from fixtures.neon_fixtures import (
+    LocalFsStorage,
+    NeonEnvBuilder,
+    RemoteStorageKind,
+    wait_for_last_flush_lsn,
+)
+from fixtures.pageserver.utils import wait_for_upload_queue_empty
+from fixtures.utils import wait_until
+
+
+# The layer files and indexes uploaded to the remote storage are copied to the mirror, and the
+# timeline deletion deletes them from the mirror too.
+def test_remote_storage_mirror(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_remote_storage_mirror",
+    )
+    mirror_dir = neon_env_builder.repo_dir / "remote_storage_mirror"
+    neon_env_builder.pageserver_config_override = (
+        f"remote_storage_mirror={{local_path='{mirror_dir}'}}"
+    )
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+    assert isinstance(env.remote_storage, LocalFsStorage)
+
+    tenant_id, timeline_id = env.neon_cli.create_tenant()
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE foo AS SELECT g AS id FROM generate_series(1, 10000) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+    endpoint.stop()
+
+    timeline_path = ("tenants", str(tenant_id), "timelines", str(timeline_id))
+    remote_timeline_dir = env.remote_storage.root.joinpath(*timeline_path)
+    mirror_timeline_dir = mirror_dir.joinpath(*timeline_path)
+
+    def mirrored():
+        remote_files = {p.name: p.read_bytes() for p in remote_timeline_dir.iterdir()}
+        mirror_files = {p.name: p.read_bytes() for p in mirror_timeline_dir.iterdir()}
+        assert "index_part.json" in remote_files
+        assert mirror_files == remote_files
+
+    wait_until(20, 0.5, mirrored)
+
+    client.timeline_delete(tenant_id, timeline_id)
+
+    def mirror_deleted():
+        assert not mirror_timeline_dir.exists() or list(mirror_timeline_dir.iterdir()) == []
+
+    wait_until(20, 0.5, mirror_deleted)
@@ -0,0 +1,178 @@
+import json
+import shutil
+
+import pytest
+from fixtures.neon_fixtures import (
+    LocalFsStorage,
+    NeonEnvBuilder,
+    RemoteStorageKind,
+    wait_for_last_flush_lsn,
+)
+from fixtures.pageserver.http import PageserverApiException
+from fixtures.pageserver.utils import wait_for_upload_queue_empty, wait_until_tenant_active
+from fixtures.types import TenantId, TimelineId
+
+
+# A tenant created with a remote storage of its own uploads there rather than to the remote
+# storage of the pageserver, and is attached from there again.
+def test_tenant_remote_storage(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_tenant_remote_storage",
+    )
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+    assert isinstance(env.remote_storage, LocalFsStorage)
+
+    tenant_storage = env.repo_dir / "tenant_remote_storage"
+    tenant_storage.mkdir()
+    remote_storage = {"local_path": str(tenant_storage)}
+
+    tenant_id = TenantId.generate()
+    client.tenant_create(tenant_id, conf={"remote_storage": remote_storage})
+    timeline_id = env.neon_cli.create_timeline("main", tenant_id=tenant_id)
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE foo AS SELECT g AS id FROM generate_series(1, 10000) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+    endpoint.stop()
+
+    remote_timeline_dir = tenant_storage / "tenants" / str(tenant_id) / "timelines"
+    assert (remote_timeline_dir / str(timeline_id) / "index_part.json").exists()
+    assert not (env.remote_storage.root / "tenants" / str(tenant_id)).exists()
+
+    # Can't be changed after the creation
+    with pytest.raises(PageserverApiException, match="only be set when it is created"):
+        client.set_tenant_config(tenant_id, {"remote_storage": remote_storage})
+
+    client.tenant_detach(tenant_id)
+    client.tenant_attach(tenant_id, config={"remote_storage": remote_storage})
+    wait_until_tenant_active(client, tenant_id)
+
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM foo") == [(10000,)]
+
+
+# A tenant is relocated to a remote storage of its own by copying its timelines there, and
+# attaching it from there.
+def test_tenant_relocation_to_remote_storage(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_tenant_relocation_to_remote_storage",
+    )
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+
+    tenant_id, timeline_id = env.neon_cli.create_tenant()
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE foo AS SELECT g AS id FROM generate_series(1, 10000) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+    endpoint.stop()
+
+    tenant_storage = env.repo_dir / "tenant_remote_storage"
+    tenant_storage.mkdir()
+    remote_storage = {"local_path": str(tenant_storage)}
+
+    report = client.timeline_copy_remote(tenant_id, timeline_id, remote_storage)
+    assert report["copied_layers"] > 0
+    assert report["skipped_layers"] == 0
+    remote_timeline_dir = tenant_storage / "tenants" / str(tenant_id) / "timelines"
+    assert (remote_timeline_dir / str(timeline_id) / "index_part.json").exists()
+
+    # Copying again skips the layer files already there
+    again = client.timeline_copy_remote(tenant_id, timeline_id, remote_storage)
+    assert again["copied_layers"] == 0
+    assert again["skipped_layers"] == report["copied_layers"]
+
+    client.tenant_detach(tenant_id)
+    client.tenant_attach(tenant_id, config={"remote_storage": remote_storage})
+    wait_until_tenant_active(client, tenant_id)
+
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM foo") == [(10000,)]
+
+
+# A tenant attached in import mode copies the timelines of an offline import tool to its remote
+# storage, with an index constructed from their layer files and metadata file.
+def test_tenant_attach_import(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_tenant_attach_import",
+    )
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+    assert isinstance(env.remote_storage, LocalFsStorage)
+
+    tenant_id, timeline_id = env.neon_cli.create_tenant()
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE foo AS SELECT g AS id FROM generate_series(1, 10000) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+    client.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+    endpoint.stop()
+
+    # What the import tool writes: the layer files and the metadata file, without an index
+    import_root = env.repo_dir / "import_source"
+    import_timeline_dir = import_root / "offline" / str(timeline_id)
+    import_timeline_dir.mkdir(parents=True)
+    for file in env.timeline_dir(tenant_id, timeline_id).iterdir():
+        if file.is_file():
+            shutil.copy(file, import_timeline_dir)
+
+    client.tenant_detach(tenant_id)
+    remote_tenant_dir = env.remote_storage.root / "tenants" / str(tenant_id)
+    shutil.rmtree(remote_tenant_dir)
+
+    with pytest.raises(PageserverApiException, match="import prefix must be a relative path"):
+        client.tenant_attach(tenant_id, import_source={"prefix": "../offline"})
+
+    client.tenant_attach(
+        tenant_id,
+        import_source={"remote_storage": {"local_path": str(import_root)}, "prefix": "offline"},
+    )
+    wait_until_tenant_active(client, tenant_id)
+    remote_timeline_dir = remote_tenant_dir / "timelines" / str(timeline_id)
+    assert (remote_timeline_dir / "index_part.json").exists()
+    assert not (env.repo_dir / "tenants" / str(tenant_id) / "import").exists()
+
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM foo") == [(10000,)]
+
+
+# The attach takes the timelines from the timeline manifest written by the previous listing,
+# which has the timelines created since, and skips the ones without an index.
+def test_tenant_attach_timeline_manifest(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_tenant_attach_timeline_manifest",
+    )
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+    assert isinstance(env.remote_storage, LocalFsStorage)
+
+    tenant_id, timeline_id = env.neon_cli.create_tenant()
+    manifest_path = env.remote_storage.root / "tenants" / str(tenant_id) / "timeline_manifest.json"
+    client.tenant_detach(tenant_id)
+    client.tenant_attach(tenant_id)
+    wait_until_tenant_active(client, tenant_id)
+    manifest = json.loads(manifest_path.read_text())
+    assert manifest["timelines"] == [str(timeline_id)]
+
+    branch_id = env.neon_cli.create_branch("branch", "main", tenant_id=tenant_id)
+    manifest = json.loads(manifest_path.read_text())
+    assert set(manifest["timelines"]) == {str(timeline_id), str(branch_id)}
+
+    # A timeline whose creation failed after it was added to the manifest
+    manifest["timelines"].append(str(TimelineId.generate()))
+    manifest_path.write_text(json.dumps(manifest))
+
+    client.tenant_detach(tenant_id)
+    client.tenant_attach(tenant_id)
+    wait_until_tenant_active(client, tenant_id)
+    assert env.pageserver.log_contains("found 3 timelines in the manifest")
+    timelines = {TimelineId(t["timeline_id"]) for t in client.timeline_list(tenant_id)}
+    assert timelines == {timeline_id, branch_id}
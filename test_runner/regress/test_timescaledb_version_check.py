@@ -0,0 +1,47 @@
+from contextlib import closing
+
+import psycopg2
+import pytest
+from fixtures.neon_fixtures import NeonEnv, PgBin, wait_for_last_flush_lsn
+
+
+def test_timescaledb_version_check(neon_simple_env: NeonEnv, pg_bin: PgBin):
+    env = neon_simple_env
+    env.pageserver.allowed_errors.append(
+        ".*has timescaledb 2.10.1 installed, but the compute ships.*"
+    )
+    endpoint = env.endpoints.create_start("main")
+
+    # The catalog of a database with the extension, without the extension at hand.
+    endpoint.safe_psql(
+        """INSERT INTO pg_extension
+            (oid, extname, extowner, extnamespace, extrelocatable, extversion)
+            VALUES (65000, 'timescaledb', 10, 2200, false, '2.10.1')"""
+    )
+    lsn = wait_for_last_flush_lsn(env, endpoint, env.initial_tenant, env.initial_timeline)
+    endpoint.stop()
+
+    basebackup = f"basebackup {env.initial_tenant} {env.initial_timeline} {lsn}"
+    pg_bin.run(
+        [
+            "psql",
+            "--no-psqlrc",
+            env.pageserver.connstr(),
+            "-o",
+            "/dev/null",
+            "-c",
+            f"{basebackup} --gzip --timescaledb-versions=2.10.1,2.11.0",
+        ]
+    )
+
+    with closing(env.pageserver.connect()) as conn:
+        with conn.cursor() as cur:
+            with pytest.raises(psycopg2.Error, match="the compute ships versions 2.11.0, 2.11.1"):
+                cur.execute(f"{basebackup} --timescaledb-versions=2.11.0,2.11.1")
+            with pytest.raises(psycopg2.Error, match="the compute ships no versions of it"):
+                cur.execute(f"{basebackup} --timescaledb-versions=")
+
+    # The test compute doesn't ship TimescaleDB, so it cannot start.
+    with pytest.raises(Exception):
+        endpoint.start()
+    assert env.pageserver.log_contains("has timescaledb 2.10.1 installed, but the compute ships")
@@ -0,0 +1,127 @@
+import threading
+
+import pytest
+from fixtures.neon_fixtures import (
+    NeonEnvBuilder,
+    RemoteStorageKind,
+    wait_for_last_flush_lsn,
+)
+from fixtures.pageserver.http import PageserverApiException
+from fixtures.pageserver.utils import wait_for_upload_queue_empty, wait_until_tenant_active
+from fixtures.utils import wait_until
+
+
+# The upload queue endpoint shows the layer uploads that keep failing, with their retries and
+# last error, and the index upload waiting for them.
+def test_upload_queue_info(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_upload_queue_info",
+    )
+    env = neon_env_builder.init_start()
+    env.pageserver.allowed_errors.append(".*failed to perform remote task UploadLayer.*")
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+
+    info = client.timeline_upload_queue(tenant_id, timeline_id)
+    assert info["state"] == "initialized"
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    client.configure_failpoints(("before-upload-layer", "return"))
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 10000) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+    # The checkpoint waits for the uploads
+    checkpoint_thread = threading.Thread(
+        target=client.timeline_checkpoint, args=(tenant_id, timeline_id)
+    )
+    checkpoint_thread.start()
+
+    def layer_upload_retried():
+        info = client.timeline_upload_queue(tenant_id, timeline_id)
+        layer_uploads = [t for t in info["inprogress_tasks"] if t["kind"] == "upload_layer"]
+        assert len(layer_uploads) > 0
+        for task in layer_uploads:
+            assert task["retries"] > 0
+            assert "failpoint before-upload-layer" in task["last_error"]
+        assert any(op["kind"] == "upload_index" for op in info["queued_operations"])
+        assert info["queued_layer_bytes"] > 0
+
+    wait_until(20, 0.5, layer_upload_retried)
+
+    client.configure_failpoints(("before-upload-layer", "off"))
+    checkpoint_thread.join(60)
+    assert not checkpoint_thread.is_alive()
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+
+    info = client.timeline_upload_queue(tenant_id, timeline_id)
+    assert info["inprogress_tasks"] == []
+    assert info["queued_operations"] == []
+    assert info["queued_layer_bytes"] == 0
+
+
+# Cancelling the layer uploads that keep failing also cancels the index upload waiting for them,
+# which unblocks the checkpoint. The layers are uploaded again after a restart.
+def test_cancel_upload_task(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_cancel_upload_task",
+    )
+    env = neon_env_builder.init_start()
+    env.pageserver.allowed_errors.extend(
+        [
+            ".*failed to perform remote task UploadLayer.*",
+            ".*cancelled the upload of layer.*",
+        ]
+    )
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    client.configure_failpoints(("before-upload-layer", "return"))
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g FROM generate_series(1, 10000) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+    checkpoint_thread = threading.Thread(
+        target=client.timeline_checkpoint, args=(tenant_id, timeline_id)
+    )
+    checkpoint_thread.start()
+
+    def layer_upload_retried():
+        info = client.timeline_upload_queue(tenant_id, timeline_id)
+        layer_uploads = [t for t in info["inprogress_tasks"] if t["kind"] == "upload_layer"]
+        assert len(layer_uploads) > 0
+        assert all(task["retries"] > 0 for task in layer_uploads)
+        assert any(op["kind"] == "upload_index" for op in info["queued_operations"])
+        return layer_uploads
+
+    layer_uploads = wait_until(20, 0.5, layer_upload_retried)
+    for task in layer_uploads:
+        client.timeline_cancel_upload_task(tenant_id, timeline_id, task["task_id"])
+
+    with pytest.raises(PageserverApiException, match="no in-progress upload task") as exc:
+        client.timeline_cancel_upload_task(tenant_id, timeline_id, layer_uploads[0]["task_id"])
+    assert exc.value.status_code == 404
+
+    # Nothing waits for the failing uploads anymore
+    checkpoint_thread.join(60)
+    assert not checkpoint_thread.is_alive()
+    info = client.timeline_upload_queue(tenant_id, timeline_id)
+    assert info["state"] == "initialized"
+    assert not any(task["kind"] == "upload_layer" for task in info["inprogress_tasks"])
+    assert not any(op["kind"] == "upload_index" for op in info["queued_operations"])
+
+    # The layers are still on the local disk, and are uploaded when the timeline is loaded
+    client.configure_failpoints(("before-upload-layer", "off"))
+    endpoint.stop()
+    env.pageserver.stop()
+    env.pageserver.start()
+    wait_until_tenant_active(client, tenant_id)
+    wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+    report = client.timeline_remote_consistency(tenant_id, timeline_id)
+    assert report["missing_layers"] == []
+    assert report["mismatched_layers"] == []
+
+    client.evict_all_layers(tenant_id, timeline_id)
+    endpoint.start()
+    assert endpoint.safe_psql("SELECT count(*) FROM t") == [(10000,)]
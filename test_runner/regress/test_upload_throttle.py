@@ -0,0 +1,59 @@
+import pytest
+from fixtures.neon_fixtures import NeonEnvBuilder, RemoteStorageKind, wait_for_last_flush_lsn
+from fixtures.pageserver.http import PageserverApiException
+from fixtures.pageserver.utils import wait_for_upload_queue_empty
+
+
+# The layer uploads of a tenant are limited by the upload_throttle of the pageserver config, and
+# the limits can be changed at runtime.
+def test_upload_throttle(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.enable_remote_storage(
+        remote_storage_kind=RemoteStorageKind.LOCAL_FS,
+        test_name="test_upload_throttle",
+    )
+    neon_env_builder.pageserver_config_override = (
+        "upload_throttle={ bytes_per_second = 1048576, ops_per_second = 10 }"
+    )
+    env = neon_env_builder.init_start()
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+
+    assert client.tenant_upload_throttle(tenant_id) == {
+        "bytes_per_second": 1048576,
+        "ops_per_second": 10,
+    }
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE conditions (g int, note text)")
+
+    def upload_layers():
+        # Layers of a few megabytes each, the ones after the first wait at a megabyte per second
+        for _ in range(3):
+            endpoint.safe_psql(
+                "INSERT INTO conditions SELECT g, repeat('x', 100) FROM generate_series(1, 20000) g"
+            )
+            wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+            client.timeline_checkpoint(tenant_id, timeline_id)
+        wait_for_upload_queue_empty(client, tenant_id, timeline_id)
+
+    upload_layers()
+    throttled = client.get_metric_value("pageserver_remote_upload_throttled_seconds_total")
+    assert throttled is not None and throttled > 0
+
+    # Without limits, none of the uploads wait
+    assert client.set_tenant_upload_throttle(tenant_id) == {
+        "bytes_per_second": None,
+        "ops_per_second": None,
+    }
+    upload_layers()
+    assert client.get_metric_value("pageserver_remote_upload_throttled_seconds_total") == throttled
+
+    assert client.set_tenant_upload_throttle(tenant_id, ops_per_second=5) == {
+        "bytes_per_second": None,
+        "ops_per_second": 5,
+    }
+    assert client.tenant_upload_throttle(tenant_id)["ops_per_second"] == 5
+
+    env.pageserver.allowed_errors.append(".*Error processing HTTP request: Bad request")
+    with pytest.raises(PageserverApiException):
+        client.set_tenant_upload_throttle(tenant_id, bytes_per_second=0)
@@ -0,0 +1,36 @@
+from fixtures.neon_fixtures import NeonEnv, wait_for_last_flush_lsn
+
+
+def test_wal_ingest_database_metrics(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    client = env.pageserver.http_client()
+    tenant_id, timeline_id = env.initial_tenant, env.initial_timeline
+    endpoint = env.endpoints.create_start("main")
+
+    endpoint.safe_psql("CREATE DATABASE metrics")
+    dboid = endpoint.safe_psql("SELECT oid FROM pg_database WHERE datname = 'metrics'")[0][0]
+    endpoint.safe_psql(
+        "CREATE TABLE conditions AS SELECT g, repeat('x', 100) AS note"
+        " FROM generate_series(1, 10000) g",
+        dbname="metrics",
+    )
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+    def ingested(name: str, database_oid: str) -> float:
+        value = client.get_metric_value(
+            name,
+            {
+                "tenant_id": str(tenant_id),
+                "timeline_id": str(timeline_id),
+                "database_oid": database_oid,
+            },
+        )
+        return value or 0
+
+    # The rows of the table are over a megabyte, all of their WAL is in the new database.
+    assert ingested("pageserver_wal_ingest_database_bytes_total", str(dboid)) > 512 * 1024
+    assert ingested("pageserver_wal_ingest_database_records_total", str(dboid)) > 0
+    # The commit records are of no database.
+    assert ingested("pageserver_wal_ingest_database_records_total", "0") > 0
+    # Fewer databases than are counted on their own.
+    assert ingested("pageserver_wal_ingest_database_records_total", "other") == 0
@@ -1,5 +1,3 @@
-import time
-
 import psutil
 import pytest
 from fixtures.log_helper import log
@@ -22,6 +20,8 @@ def assert_child_processes(pageserver_pid, wal_redo_present=False, defunct_prese
 # to wait and consume the exit code of the WAL redo process, leaving it behind
 # as a zombie process.
 def test_walredo_not_left_behind_on_detach(neon_env_builder: NeonEnvBuilder):
+    # The ping at tenant activation briefly launches a WAL redo process
+    neon_env_builder.pageserver_config_override = "wal_redo_ping_period='0s'"
     env = neon_env_builder.init_start()
     # We intentionally test for a non-existent tenant.
     env.pageserver.allowed_errors.append(".*NotFound: tenant.*")
@@ -83,6 +83,13 @@ def test_walredo_not_left_behind_on_detach(neon_env_builder: NeonEnvBuilder):
     # XXX this is quite brittle as the lifecycle of the WAL redo process is an implementation detail
     assert_child_processes(pagserver_pid, wal_redo_present=True, defunct_present=False)
 
+    # The status of the tenant reports the same process
+    [status] = pageserver_http.tenant_wal_redo_status(tenant_id)
+    log.info(f"WAL redo status: {status}")
+    assert status["pid"] in [child.pid for child in psutil.Process(pagserver_pid).children()]
+    assert status["n_requests"] is None or status["n_requests"] > 0
+    assert status["restarts"] == 0
+
     # Stop the compute before detaching, to avoid errors in the log.
     endpoint.stop()
 
@@ -103,8 +110,5 @@ def test_walredo_not_left_behind_on_detach(neon_env_builder: NeonEnvBuilder):
     # check that nothing is left on disk for deleted tenant
     assert not (env.repo_dir / "tenants" / str(tenant_id)).exists()
 
-    # Pageserver schedules kill+wait of the WAL redo process to the background runtime,
-    # asynchronously to tenant detach. Cut it some slack to complete kill+wait before
-    # checking.
-    time.sleep(1.0)
+    # The WAL redo process is killed and waited for before the detach returns
     assert_child_processes(pagserver_pid, wal_redo_present=False, defunct_present=False)